@@ -0,0 +1,9 @@
+CREATE TABLE address_batch (
+    idempotency_key TEXT PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+ALTER TABLE temporary_email
+    ADD COLUMN batch_key TEXT REFERENCES address_batch (idempotency_key) ON DELETE CASCADE;
+
+CREATE INDEX idx_temporary_email_batch_key ON temporary_email (batch_key);
@@ -0,0 +1,7 @@
+CREATE TABLE blocked_local_part (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    pattern TEXT NOT NULL UNIQUE,
+    is_regex BOOLEAN NOT NULL DEFAULT false,
+    reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+ALTER TABLE temporary_email ADD COLUMN is_honeypot BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE sender_reputation (
+    sender_domain TEXT PRIMARY KEY,
+    honeypot_hits BIGINT NOT NULL DEFAULT 0,
+    last_ip TEXT,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT now()
+);
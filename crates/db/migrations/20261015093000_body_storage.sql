@@ -0,0 +1,5 @@
+-- HTML body and the original message, stored zstd-compressed when is_compressed.
+ALTER TABLE received_email
+    ADD COLUMN body_html BYTEA,
+    ADD COLUMN raw_email BYTEA,
+    ADD COLUMN is_compressed BOOLEAN NOT NULL DEFAULT false;
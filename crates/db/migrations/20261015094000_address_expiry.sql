@@ -0,0 +1,9 @@
+-- Addresses expire 24h after creation (see db::ADDRESS_TTL). The janitor flips
+-- is_active once expires_at passes; reactivation within the grace window flips
+-- it back until the daily purge removes the row.
+ALTER TABLE temporary_email
+    ADD COLUMN expires_at TIMESTAMPTZ NOT NULL DEFAULT now() + interval '24 hours',
+    ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT true;
+
+CREATE INDEX idx_temporary_email_active_expires_at
+    ON temporary_email (expires_at) WHERE is_active;
@@ -0,0 +1,3 @@
+ALTER TABLE received_email
+    ADD COLUMN redacted_at TIMESTAMPTZ,
+    ADD COLUMN redaction_reason TEXT;
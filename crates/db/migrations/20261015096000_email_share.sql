@@ -0,0 +1,9 @@
+CREATE TABLE email_share (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    received_email_id UUID NOT NULL REFERENCES received_email (id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_email_share_received_email_id ON email_share (received_email_id);
@@ -0,0 +1,4 @@
+-- Public mailboxes are readable by anyone who knows the address. Their mail is
+-- kept for a much shorter time (see the janitor's expiry sweep).
+ALTER TABLE temporary_email
+    ADD COLUMN is_public BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,5 @@
+-- Keyed BLAKE2b hash of the mailbox access token. The token itself is only
+-- ever returned once, at creation or rotation. NULL for honeypots, batch
+-- addresses and mailboxes created before tokens existed.
+ALTER TABLE temporary_email
+    ADD COLUMN token_hash BYTEA;
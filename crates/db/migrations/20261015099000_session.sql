@@ -0,0 +1,8 @@
+-- Anonymous sessions. Access tokens are stateless JWTs; only the current
+-- refresh token is tracked here, as a keyed hash, and it is replaced on use.
+CREATE TABLE session (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    refresh_hash BYTEA NOT NULL UNIQUE,
+    refresh_expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,20 @@
+-- Persistent accounts from OIDC login. Anonymous use needs none of this:
+-- user_id stays NULL on sessions and addresses created without logging in.
+CREATE TABLE users (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_login_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (issuer, subject)
+);
+
+ALTER TABLE session
+    ADD COLUMN user_id UUID REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE temporary_email
+    ADD COLUMN user_id UUID REFERENCES users (id) ON DELETE SET NULL;
+
+CREATE INDEX idx_temporary_email_user_id ON temporary_email (user_id)
+    WHERE user_id IS NOT NULL;
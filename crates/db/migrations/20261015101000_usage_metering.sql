@@ -0,0 +1,30 @@
+-- Billable usage per API key. Raw events are rolled up into usage_daily by the
+-- janitor once their day is over and then deleted.
+CREATE TABLE api_key (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    key_hash BYTEA NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    revoked_at TIMESTAMPTZ
+);
+
+ALTER TABLE temporary_email
+    ADD COLUMN api_key_id UUID REFERENCES api_key (id) ON DELETE SET NULL;
+
+CREATE TABLE usage_events (
+    id BIGSERIAL PRIMARY KEY,
+    api_key_id UUID NOT NULL REFERENCES api_key (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL,
+    quantity BIGINT NOT NULL DEFAULT 1,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_usage_events_occurred_at ON usage_events (occurred_at);
+
+CREATE TABLE usage_daily (
+    api_key_id UUID NOT NULL REFERENCES api_key (id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    kind TEXT NOT NULL,
+    quantity BIGINT NOT NULL,
+    PRIMARY KEY (api_key_id, day, kind)
+);
@@ -0,0 +1,10 @@
+-- Branding for the tenant behind an API key. Every column is optional; unset
+-- ones fall back to the deployment defaults.
+CREATE TABLE tenant_settings (
+    api_key_id UUID PRIMARY KEY REFERENCES api_key (id) ON DELETE CASCADE,
+    smtp_banner_domain TEXT,
+    auto_reply_from TEXT,
+    share_page_name TEXT,
+    share_page_logo_url TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,4 @@
+-- Messages sent with the null reverse path (`MAIL FROM:<>`): bounces and
+-- other automatic notifications. Their from_addr is NULL.
+ALTER TABLE received_email
+    ADD COLUMN is_bounce BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,12 @@
+-- MIME parts of a message that are not its text or HTML body.
+CREATE TABLE email_attachment (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    received_email_id UUID NOT NULL REFERENCES received_email (id) ON DELETE CASCADE,
+    filename TEXT,
+    content_type TEXT NOT NULL,
+    size BIGINT NOT NULL,
+    content BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_email_attachment_received_email_id ON email_attachment (received_email_id);
@@ -0,0 +1,16 @@
+-- Messages whose processing timed out or panicked. Kept with their raw bytes
+-- for inspection; `digest` (SHA-256 of the raw message) recognises retries
+-- of the same message so they are not processed again.
+CREATE TABLE poison_message (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    digest BYTEA NOT NULL UNIQUE,
+    peer_ip TEXT,
+    mail_from TEXT,
+    recipients TEXT[] NOT NULL DEFAULT '{}',
+    error TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    size BIGINT NOT NULL,
+    raw_email BYTEA NOT NULL,
+    first_seen_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+-- Every header field of a stored message, as {"lowercased-name": ["value", ...]}
+-- with values unfolded and in arrival order. NULL for mail stored before this
+-- column existed and for redacted messages.
+ALTER TABLE received_email
+    ADD COLUMN headers JSONB;
@@ -0,0 +1,11 @@
+-- Inbound routing per recipient domain. Domains without a row accept mail
+-- for existing addresses only, as before.
+CREATE TABLE mail_domain (
+    domain TEXT PRIMARY KEY,
+    policy TEXT NOT NULL
+        CHECK (policy IN ('registered', 'api_only', 'catch_all', 'webhook')),
+    -- Where `webhook` domains POST every message.
+    webhook_url TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    CHECK ((policy = 'webhook') = (webhook_url IS NOT NULL))
+);
@@ -0,0 +1,7 @@
+-- Credentials for SMTP AUTH, next to any configured in SMTP_AUTH_USERS.
+CREATE TABLE smtp_user (
+    username TEXT PRIMARY KEY,
+    -- Argon2 hash in PHC string format.
+    password_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
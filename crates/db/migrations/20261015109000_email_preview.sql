@@ -0,0 +1,6 @@
+-- PNG screenshots of a message's HTML body, made by the renderer sidecar.
+CREATE TABLE email_preview (
+    received_email_id UUID PRIMARY KEY REFERENCES received_email (id) ON DELETE CASCADE,
+    png BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
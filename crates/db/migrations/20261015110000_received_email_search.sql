@@ -0,0 +1,9 @@
+-- Full-text search over subject and plain-text body. Subject matches rank
+-- higher. Adding a stored column rewrites the table once.
+ALTER TABLE received_email ADD COLUMN search_vector tsvector
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', coalesce(subject, '')), 'A')
+        || setweight(to_tsvector('english', coalesce(body_text, '')), 'B')
+    ) STORED;
+
+CREATE INDEX idx_received_email_search_vector ON received_email USING GIN (search_vector);
@@ -0,0 +1,12 @@
+-- Per-mailbox rules checked against every incoming message; a match is
+-- POSTed to the rule's webhook. Rules go away with their address.
+CREATE TABLE watch_rule (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    temporary_email_id UUID NOT NULL REFERENCES temporary_email (id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('substring', 'regex')),
+    pattern TEXT NOT NULL,
+    webhook_url TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_watch_rule_temporary_email_id ON watch_rule (temporary_email_id);
@@ -0,0 +1,32 @@
+-- Callbacks notified of every message an address receives. Storing a message
+-- queues one `webhook_delivery` per subscription in the same transaction; the
+-- delivery worker sends them and retries failures with backoff.
+CREATE TABLE webhook_subscription (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    temporary_email_id UUID NOT NULL REFERENCES temporary_email (id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    -- HMAC-SHA256 key for the payload signature; only shown on creation.
+    secret TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_webhook_subscription_temporary_email_id
+    ON webhook_subscription (temporary_email_id);
+
+CREATE TABLE webhook_delivery (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    subscription_id UUID NOT NULL REFERENCES webhook_subscription (id) ON DELETE CASCADE,
+    received_email_id UUID NOT NULL REFERENCES received_email (id) ON DELETE CASCADE,
+    attempts INT NOT NULL DEFAULT 0,
+    -- Also pushed forward while a worker holds the delivery.
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    -- Set when the worker gives up.
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_webhook_delivery_due ON webhook_delivery (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
+CREATE INDEX idx_webhook_delivery_received_email_id ON webhook_delivery (received_email_id);
@@ -0,0 +1,7 @@
+-- Serves the per-mailbox timeline (counts by period and by sender) from the
+-- index alone, and every other per-mailbox listing ordered by arrival. It
+-- covers the old single-column index, which is dropped.
+CREATE INDEX idx_received_email_timeline
+    ON received_email (temporary_email_id, received_at) INCLUDE (from_addr);
+
+DROP INDEX idx_received_email_temporary_email_id;
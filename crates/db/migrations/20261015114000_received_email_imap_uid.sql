@@ -0,0 +1,4 @@
+-- IMAP UIDs must only ever grow within a mailbox and never be reused. One
+-- identity column across all mailboxes guarantees that without a per-mailbox
+-- counter; existing rows are numbered as the column is added.
+ALTER TABLE received_email ADD COLUMN imap_uid BIGINT GENERATED ALWAYS AS IDENTITY;
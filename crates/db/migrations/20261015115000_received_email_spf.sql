@@ -0,0 +1,6 @@
+-- Where a message came from and what the sender domain's SPF policy said
+-- about that. Mail stored before the check existed has neither.
+ALTER TABLE received_email
+    ADD COLUMN peer_ip TEXT,
+    ADD COLUMN spf_result TEXT
+        CHECK (spf_result IN ('pass', 'fail', 'softfail', 'neutral', 'none', 'temperror', 'permerror'));
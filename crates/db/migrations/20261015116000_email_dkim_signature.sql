@@ -0,0 +1,11 @@
+-- One row per DKIM-Signature header of a message, in header order, with
+-- the verdict on it.
+CREATE TABLE email_dkim_signature (
+    received_email_id UUID NOT NULL REFERENCES received_email (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    domain TEXT NOT NULL,
+    selector TEXT NOT NULL,
+    result TEXT NOT NULL CHECK (result IN ('pass', 'fail', 'temperror', 'permerror')),
+    reason TEXT,
+    PRIMARY KEY (received_email_id, position)
+);
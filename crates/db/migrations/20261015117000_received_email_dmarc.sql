@@ -0,0 +1,5 @@
+-- Whether SPF or DKIM passed for the From: domain under its DMARC policy.
+-- Mail stored before the check existed has none.
+ALTER TABLE received_email
+    ADD COLUMN dmarc_result TEXT
+        CHECK (dmarc_result IN ('pass', 'fail', 'none', 'temperror', 'permerror'));
@@ -0,0 +1,4 @@
+-- Labels the SMTP server attaches from how a message arrived, e.g.
+-- 'early-talker' for a client that spoke before the greeting.
+ALTER TABLE received_email
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,4 @@
+-- ISO 3166-1 alpha-2 code of the country the SMTP client connected from,
+-- when the server has a GeoIP database.
+ALTER TABLE received_email
+    ADD COLUMN country TEXT;
@@ -0,0 +1,8 @@
+-- The `tag` of mail sent to `local+tag@domain`, which lands in the
+-- mailbox `local@domain`.
+ALTER TABLE received_email
+    ADD COLUMN plus_tag TEXT;
+
+CREATE INDEX idx_received_email_plus_tag
+    ON received_email (temporary_email_id, plus_tag)
+    WHERE plus_tag IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Set when a client opens the message or marks it read, so front-ends can
+-- show which mail is new.
+ALTER TABLE received_email
+    ADD COLUMN is_read BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,18 @@
+-- Senders a mailbox is waiting for, e.g. the domain about to send a one-time
+-- code. Mail from them skips the per-IP message throttle and its webhook
+-- deliveries go out ahead of the queue until `expires_at`.
+CREATE TABLE sender_expectation (
+    temporary_email_id UUID NOT NULL REFERENCES temporary_email (id) ON DELETE CASCADE,
+    -- Lowercased; matches the envelope sender's domain and its subdomains.
+    from_domain TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (temporary_email_id, from_domain)
+);
+
+ALTER TABLE webhook_delivery
+    ADD COLUMN priority BOOLEAN NOT NULL DEFAULT false;
+
+DROP INDEX idx_webhook_delivery_due;
+CREATE INDEX idx_webhook_delivery_due ON webhook_delivery (priority DESC, next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,10 @@
+-- When the SMTP server accepted the first recipient of the message, and
+-- when an API client was first shown it; the difference is how long a
+-- one-time code took to reach whoever was waiting for it.
+ALTER TABLE received_email
+    ADD COLUMN accepted_at TIMESTAMPTZ,
+    ADD COLUMN first_read_at TIMESTAMPTZ;
+
+CREATE INDEX idx_received_email_first_read_at
+    ON received_email (first_read_at)
+    WHERE first_read_at IS NOT NULL;
@@ -0,0 +1,11 @@
+-- Further addresses of a mailbox. Mail to an alias is delivered into the
+-- mailbox it belongs to; the alias is kept as the message's `to_addr`.
+CREATE TABLE address_alias (
+    -- Lowercased, with the domain of the mailbox. Never also a
+    -- `temporary_email.temp_email_addr`.
+    alias_addr TEXT PRIMARY KEY,
+    temporary_email_id UUID NOT NULL REFERENCES temporary_email (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_address_alias_temporary_email ON address_alias (temporary_email_id);
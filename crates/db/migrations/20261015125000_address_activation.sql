@@ -0,0 +1,4 @@
+-- Addresses created ahead of time for a scheduled run. Until activate_at they
+-- reject mail and are not served; they expire 24h after it rather than after
+-- creation. NULL means active from creation.
+ALTER TABLE temporary_email ADD COLUMN activate_at TIMESTAMPTZ;
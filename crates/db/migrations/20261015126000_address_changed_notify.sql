@@ -0,0 +1,27 @@
+-- Announces on the `address_changed` channel, with the address as payload,
+-- whenever a cached lookup of it could go stale: the address or an alias of
+-- it is deleted, or it is (de)activated, expires or is rescheduled. Done in
+-- triggers rather than by the callers so no path that changes an address,
+-- including ad-hoc SQL, is missed. The notification is sent on commit.
+CREATE FUNCTION notify_address_changed() RETURNS trigger AS $$
+BEGIN
+    IF TG_TABLE_NAME = 'address_alias' THEN
+        PERFORM pg_notify('address_changed', OLD.alias_addr);
+    ELSIF TG_OP = 'DELETE' THEN
+        PERFORM pg_notify('address_changed', OLD.temp_email_addr);
+    ELSIF OLD.is_active IS DISTINCT FROM NEW.is_active
+        OR OLD.expires_at IS DISTINCT FROM NEW.expires_at
+        OR OLD.activate_at IS DISTINCT FROM NEW.activate_at THEN
+        PERFORM pg_notify('address_changed', NEW.temp_email_addr);
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER temporary_email_changed
+    AFTER UPDATE OR DELETE ON temporary_email
+    FOR EACH ROW EXECUTE FUNCTION notify_address_changed();
+
+CREATE TRIGGER address_alias_deleted
+    AFTER DELETE ON address_alias
+    FOR EACH ROW EXECUTE FUNCTION notify_address_changed();
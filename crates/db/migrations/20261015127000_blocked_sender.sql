@@ -0,0 +1,15 @@
+-- Senders whose mail is dropped, for one mailbox or, with a NULL
+-- temporary_email_id, for all of them. Checked against the envelope sender
+-- and the From: address of every message.
+CREATE TABLE blocked_sender (
+    temporary_email_id UUID REFERENCES temporary_email (id) ON DELETE CASCADE,
+    -- Lowercased. An address matches only itself; a domain matches addresses
+    -- at it and at its subdomains.
+    sender TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX idx_blocked_sender_mailbox ON blocked_sender (temporary_email_id, sender)
+    WHERE temporary_email_id IS NOT NULL;
+CREATE UNIQUE INDEX idx_blocked_sender_global ON blocked_sender (sender)
+    WHERE temporary_email_id IS NULL;
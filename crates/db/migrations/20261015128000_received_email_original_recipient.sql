@@ -0,0 +1,3 @@
+-- The recipient as the sender addressed it, from the DSN ORCPT parameter of
+-- RCPT TO. Forwarders set it when they rewrite the envelope recipient.
+ALTER TABLE received_email ADD COLUMN original_recipient TEXT;
@@ -0,0 +1,6 @@
+-- Deleted messages go to the trash first: they are hidden from the mailbox
+-- until restored, and purged once deleted_at is older than the grace period.
+ALTER TABLE received_email ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_received_email_trash ON received_email (deleted_at)
+    WHERE deleted_at IS NOT NULL;
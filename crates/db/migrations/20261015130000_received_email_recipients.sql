@@ -0,0 +1,8 @@
+-- Who a message was for, twice over: the RCPT TO addresses that delivered
+-- it to the mailbox, and the addresses its To: and Cc: headers name. They
+-- differ for Bcc, forwards and mailing lists.
+ALTER TABLE received_email
+    ADD COLUMN envelope_recipients TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN header_recipients TEXT[] NOT NULL DEFAULT '{}';
+
+UPDATE received_email SET envelope_recipients = ARRAY[to_addr] WHERE to_addr IS NOT NULL;
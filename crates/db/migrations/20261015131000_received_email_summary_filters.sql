@@ -0,0 +1,14 @@
+-- Indexes for filtering a mailbox's message list by sender, subject and
+-- unread state; date ranges use idx_received_email_timeline. pg_trgm lets
+-- the GIN index serve substring (ILIKE '%…%') matches on the subject.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_received_email_sender
+    ON received_email (temporary_email_id, lower(from_addr), received_at);
+
+CREATE INDEX idx_received_email_subject_trgm
+    ON received_email USING GIN (subject gin_trgm_ops);
+
+CREATE INDEX idx_received_email_unread
+    ON received_email (temporary_email_id, received_at)
+    WHERE NOT is_read AND deleted_at IS NULL;
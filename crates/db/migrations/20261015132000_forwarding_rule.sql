@@ -0,0 +1,29 @@
+-- Real mailboxes an address passes its mail on to. Storing a message queues
+-- one `forward_delivery` per rule in the same transaction; the forwarding
+-- worker hands them to the outbound relays and retries failures with backoff.
+CREATE TABLE forwarding_rule (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    temporary_email_id UUID NOT NULL REFERENCES temporary_email (id) ON DELETE CASCADE,
+    -- Stored lowercased.
+    forward_to TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (temporary_email_id, forward_to)
+);
+
+CREATE TABLE forward_delivery (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    rule_id UUID NOT NULL REFERENCES forwarding_rule (id) ON DELETE CASCADE,
+    received_email_id UUID NOT NULL REFERENCES received_email (id) ON DELETE CASCADE,
+    attempts INT NOT NULL DEFAULT 0,
+    -- Also pushed forward while a worker holds the delivery.
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    -- Set when the worker gives up.
+    failed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX idx_forward_delivery_due ON forward_delivery (next_attempt_at)
+    WHERE delivered_at IS NULL AND failed_at IS NULL;
+CREATE INDEX idx_forward_delivery_received_email_id ON forward_delivery (received_email_id);
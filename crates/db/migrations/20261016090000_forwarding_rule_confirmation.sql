@@ -0,0 +1,3 @@
+-- A forwarding rule stays pending until its target opens the signed link
+-- mailed to it; nothing is forwarded before then.
+ALTER TABLE forwarding_rule ADD COLUMN confirmed_at TIMESTAMPTZ;
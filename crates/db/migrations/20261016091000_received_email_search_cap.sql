@@ -0,0 +1,11 @@
+-- to_tsvector fails once a document's lexemes pass 1 MiB, which a large
+-- body of distinct words reaches well within the SMTP size limit; only the
+-- start of the subject and body is indexed. A generated column's expression
+-- cannot be altered in place, so the column (and its index) is rebuilt.
+ALTER TABLE received_email DROP COLUMN search_vector;
+ALTER TABLE received_email ADD COLUMN search_vector tsvector
+    GENERATED ALWAYS AS (
+        setweight(to_tsvector('english', left(coalesce(subject, ''), 1000)), 'A')
+        || setweight(to_tsvector('english', left(coalesce(body_text, ''), 100000)), 'B')
+    ) STORED;
+CREATE INDEX idx_received_email_search_vector ON received_email USING GIN (search_vector);
@@ -0,0 +1,49 @@
+-- One `address_changed` notification per statement instead of per row, so a
+-- purge or expiry sweep no longer queues one NOTIFY for every address it
+-- touches. The payload lists the changed addresses one per line, or is `*`
+-- when they would not fit under NOTIFY's 8000-byte payload limit and every
+-- cached lookup should be dropped.
+DROP TRIGGER temporary_email_changed ON temporary_email;
+DROP TRIGGER address_alias_deleted ON address_alias;
+
+CREATE OR REPLACE FUNCTION notify_address_changed() RETURNS trigger AS $$
+DECLARE
+    changed text;
+BEGIN
+    IF TG_TABLE_NAME = 'address_alias' THEN
+        SELECT string_agg(DISTINCT alias_addr, E'\n') INTO changed FROM old_rows;
+    ELSIF TG_OP = 'DELETE' THEN
+        SELECT string_agg(DISTINCT temp_email_addr, E'\n') INTO changed FROM old_rows;
+    ELSE
+        SELECT string_agg(DISTINCT n.temp_email_addr, E'\n') INTO changed
+        FROM old_rows o JOIN new_rows n USING (id)
+        WHERE o.is_active IS DISTINCT FROM n.is_active
+           OR o.expires_at IS DISTINCT FROM n.expires_at
+           OR o.activate_at IS DISTINCT FROM n.activate_at;
+    END IF;
+    IF changed IS NULL THEN
+        RETURN NULL;
+    END IF;
+    IF octet_length(changed) >= 8000 THEN
+        changed := '*';
+    END IF;
+    PERFORM pg_notify('address_changed', changed);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Transition tables allow only one event per trigger.
+CREATE TRIGGER temporary_email_updated
+    AFTER UPDATE ON temporary_email
+    REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_address_changed();
+
+CREATE TRIGGER temporary_email_deleted
+    AFTER DELETE ON temporary_email
+    REFERENCING OLD TABLE AS old_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_address_changed();
+
+CREATE TRIGGER address_alias_deleted
+    AFTER DELETE ON address_alias
+    REFERENCING OLD TABLE AS old_rows
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_address_changed();
@@ -0,0 +1,4 @@
+-- Hash of the request an Idempotency-Key was first sent with; the same key
+-- with a different request is refused instead of answered with this batch.
+ALTER TABLE address_batch ADD COLUMN request_hash BYTEA NOT NULL DEFAULT ''::bytea;
+ALTER TABLE address_batch ALTER COLUMN request_hash DROP DEFAULT;
@@ -0,0 +1,68 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A further address delivering into a mailbox.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+#[schema(as = Alias)]
+pub struct AddressAlias {
+    pub alias_addr: String,
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+const ADDRESS_ALIAS_COLUMNS: &str = "alias_addr, temporary_email_id, created_at";
+
+/// Adds `alias_addr` to the mailbox. `None` when it is already an address or
+/// another alias.
+pub async fn insert_address_alias(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    alias_addr: &str,
+) -> Result<Option<AddressAlias>, sqlx::Error> {
+    sqlx::query_as::<_, AddressAlias>(&format!(
+        "INSERT INTO address_alias (alias_addr, temporary_email_id) \
+         SELECT lower($2), $1 \
+         WHERE NOT EXISTS (SELECT 1 FROM temporary_email WHERE temp_email_addr = lower($2)) \
+         ON CONFLICT (alias_addr) DO NOTHING \
+         RETURNING {ADDRESS_ALIAS_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(alias_addr)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Aliases of one mailbox, oldest first.
+pub async fn list_address_aliases(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<AddressAlias>, sqlx::Error> {
+    sqlx::query_as::<_, AddressAlias>(&format!(
+        "SELECT {ADDRESS_ALIAS_COLUMNS} FROM address_alias \
+         WHERE temporary_email_id = $1 ORDER BY created_at, alias_addr"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns false when the mailbox has no such alias.
+pub async fn delete_address_alias(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    alias_addr: &str,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "DELETE FROM address_alias WHERE temporary_email_id = $1 AND alias_addr = lower($2)",
+    )
+    .bind(temporary_email_id)
+    .bind(alias_addr)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0)
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use uuid::Uuid;
+
+const ATTACHMENT_COLUMNS: &str = "id, received_email_id, filename, content_type, size, created_at";
+
+/// Metadata of an attachment; the bytes come from [`fetch_attachment`].
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Attachment {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub received_email_id: Uuid,
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub size: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewAttachment<'a> {
+    pub filename: Option<&'a str>,
+    pub content_type: &'a str,
+    pub content: &'a [u8],
+}
+
+pub async fn insert_attachments(
+    pool: &PgPool,
+    received_email_id: Uuid,
+    attachments: &[NewAttachment<'_>],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    insert_attachment_rows(&mut tx, received_email_id, attachments).await?;
+    tx.commit().await
+}
+
+pub(crate) async fn insert_attachment_rows(
+    conn: &mut PgConnection,
+    received_email_id: Uuid,
+    attachments: &[NewAttachment<'_>],
+) -> Result<(), sqlx::Error> {
+    for a in attachments {
+        sqlx::query(
+            "INSERT INTO email_attachment (received_email_id, filename, content_type, size, content) \
+             VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(received_email_id)
+        .bind(a.filename)
+        .bind(a.content_type)
+        .bind(a.content.len() as i64)
+        .bind(a.content)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+pub async fn list_attachments(
+    pool: &PgPool,
+    received_email_id: Uuid,
+) -> Result<Vec<Attachment>, sqlx::Error> {
+    sqlx::query_as::<_, Attachment>(&format!(
+        "SELECT {ATTACHMENT_COLUMNS} FROM email_attachment \
+         WHERE received_email_id = $1 ORDER BY created_at, id"
+    ))
+    .bind(received_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// An attachment with its bytes, only if it belongs to `received_email_id`.
+pub async fn fetch_attachment(
+    pool: &PgPool,
+    received_email_id: Uuid,
+    id: Uuid,
+) -> Result<Option<(Attachment, Vec<u8>)>, sqlx::Error> {
+    #[derive(FromRow)]
+    struct Row {
+        #[sqlx(flatten)]
+        meta: Attachment,
+        content: Vec<u8>,
+    }
+    Ok(sqlx::query_as::<_, Row>(&format!(
+        "SELECT {ATTACHMENT_COLUMNS}, content FROM email_attachment \
+         WHERE id = $1 AND received_email_id = $2"
+    ))
+    .bind(id)
+    .bind(received_email_id)
+    .fetch_optional(pool)
+    .await?
+    .map(|r| (r.meta, r.content)))
+}
@@ -0,0 +1,82 @@
+use regex::{Regex, RegexBuilder};
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+use crate::models::BlockedLocalPart;
+use crate::repo::list_blocked_local_parts;
+
+/// The `blocked_local_part` rows, ready to match local parts against: exact
+/// entries compare case-insensitively, regex entries must match the whole
+/// local part.
+#[derive(Debug, Default)]
+pub struct LocalPartBlocklist {
+    exact: HashSet<String>,
+    patterns: Vec<Regex>,
+}
+
+impl LocalPartBlocklist {
+    pub fn from_rows(rows: &[BlockedLocalPart]) -> Self {
+        let mut list = Self::default();
+        for row in rows {
+            if !row.is_regex {
+                list.exact.insert(row.pattern.to_ascii_lowercase());
+                continue;
+            }
+            match compile_local_part_pattern(&row.pattern) {
+                Ok(re) => list.patterns.push(re),
+                Err(e) => tracing::warn!(
+                    pattern = %row.pattern,
+                    error = %e,
+                    "skipping invalid blocklist regex"
+                ),
+            }
+        }
+        list
+    }
+
+    pub async fn load(pool: &PgPool) -> Result<Self, sqlx::Error> {
+        Ok(Self::from_rows(&list_blocked_local_parts(pool).await?))
+    }
+
+    pub fn is_blocked(&self, local: &str) -> bool {
+        let local = local.to_ascii_lowercase();
+        self.exact.contains(&local) || self.patterns.iter().any(|re| re.is_match(&local))
+    }
+}
+
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// [`LocalPartBlocklist::load`], reloaded at most once a minute unless
+/// invalidated. The HTTP API and the SMTP server share one per process.
+#[derive(Debug, Default)]
+pub struct BlocklistCache {
+    cached: RwLock<Option<(Instant, Arc<LocalPartBlocklist>)>>,
+}
+
+impl BlocklistCache {
+    pub async fn get(&self, pool: &PgPool) -> Result<Arc<LocalPartBlocklist>, sqlx::Error> {
+        if let Some((loaded_at, list)) = self.cached.read().await.as_ref() {
+            if loaded_at.elapsed() < CACHE_TTL {
+                return Ok(Arc::clone(list));
+            }
+        }
+
+        let list = Arc::new(LocalPartBlocklist::load(pool).await?);
+        *self.cached.write().await = Some((Instant::now(), Arc::clone(&list)));
+        Ok(list)
+    }
+
+    pub async fn invalidate(&self) {
+        *self.cached.write().await = None;
+    }
+}
+
+pub fn compile_local_part_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!("^(?:{pattern})$"))
+        .case_insensitive(true)
+        .size_limit(1 << 20)
+        .build()
+}
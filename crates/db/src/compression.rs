@@ -0,0 +1,98 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How `body_html` / `raw_email` are written. Reads handle either form, so the
+/// setting can change at any time; `compress_stored_bodies` converts old rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyCompression {
+    None,
+    Zstd { level: i32 },
+}
+
+impl Default for BodyCompression {
+    fn default() -> Self {
+        Self::Zstd { level: 3 }
+    }
+}
+
+impl FromStr for BodyCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(Self::None),
+            "zstd" => Ok(Self::default()),
+            other => {
+                let level = other
+                    .strip_prefix("zstd:")
+                    .and_then(|l| l.parse().ok())
+                    .filter(|l| zstd::compression_level_range().contains(l))
+                    .ok_or_else(|| format!("expected none, zstd or zstd:<1-22>, got {s:?}"))?;
+                Ok(Self::Zstd { level })
+            }
+        }
+    }
+}
+
+impl fmt::Display for BodyCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Zstd { level } => write!(f, "zstd:{level}"),
+        }
+    }
+}
+
+pub(crate) struct StoredBodies {
+    pub body_html: Option<Vec<u8>>,
+    pub raw_email: Option<Vec<u8>>,
+    pub is_compressed: bool,
+}
+
+/// Compresses both columns together and keeps the result only if it is
+/// actually smaller; tiny messages usually are not.
+pub(crate) fn encode(
+    body_html: Option<&[u8]>,
+    raw_email: Option<&[u8]>,
+    mode: BodyCompression,
+) -> std::io::Result<StoredBodies> {
+    let plain = StoredBodies {
+        body_html: body_html.map(<[u8]>::to_vec),
+        raw_email: raw_email.map(<[u8]>::to_vec),
+        is_compressed: false,
+    };
+    let plain_len = plain.len();
+
+    let stored = match mode {
+        BodyCompression::None => plain,
+        BodyCompression::Zstd { level } => {
+            let compressed = StoredBodies {
+                body_html: body_html.map(|b| zstd::encode_all(b, level)).transpose()?,
+                raw_email: raw_email.map(|b| zstd::encode_all(b, level)).transpose()?,
+                is_compressed: true,
+            };
+            if compressed.len() < plain_len {
+                compressed
+            } else {
+                plain
+            }
+        }
+    };
+
+    metrics::counter!("body_storage_plain_bytes_total").increment(plain_len as u64);
+    metrics::counter!("body_storage_stored_bytes_total").increment(stored.len() as u64);
+    Ok(stored)
+}
+
+pub(crate) fn decode(bytes: Vec<u8>, is_compressed: bool) -> Result<Vec<u8>, sqlx::Error> {
+    if !is_compressed {
+        return Ok(bytes);
+    }
+    zstd::decode_all(bytes.as_slice()).map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+impl StoredBodies {
+    pub fn len(&self) -> usize {
+        self.body_html.as_ref().map_or(0, Vec::len) + self.raw_email.as_ref().map_or(0, Vec::len)
+    }
+}
@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Verdict on one `DKIM-Signature` header (RFC 6376).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimResult {
+    /// The signature verifies against the published key.
+    Pass,
+    /// The body or headers were changed, or the signature is wrong.
+    Fail,
+    /// The key could not be looked up; a retry might succeed.
+    TempError,
+    /// The signature or the key record is unusable.
+    PermError,
+}
+
+impl DkimResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::TempError => "temperror",
+            Self::PermError => "permerror",
+        }
+    }
+}
+
+impl fmt::Display for DkimResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DkimResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Self::Pass),
+            "fail" => Ok(Self::Fail),
+            "temperror" => Ok(Self::TempError),
+            "permerror" => Ok(Self::PermError),
+            other => Err(format!("unknown DKIM result {other:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for DkimResult {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DkimSignature {
+    #[serde(skip_serializing)]
+    pub received_email_id: Uuid,
+    /// Signing domain (`d=`).
+    pub domain: String,
+    /// `s=`; the key is published at `<selector>._domainkey.<domain>`.
+    pub selector: String,
+    #[sqlx(try_from = "String")]
+    pub result: DkimResult,
+    /// Why the signature did not pass.
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewDkimSignature<'a> {
+    pub domain: &'a str,
+    pub selector: &'a str,
+    pub result: DkimResult,
+    pub reason: Option<&'a str>,
+}
+
+pub(crate) async fn insert_dkim_rows(
+    conn: &mut PgConnection,
+    received_email_id: Uuid,
+    signatures: &[NewDkimSignature<'_>],
+) -> Result<(), sqlx::Error> {
+    for (position, s) in signatures.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO email_dkim_signature \
+             (received_email_id, position, domain, selector, result, reason) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(received_email_id)
+        .bind(position as i32)
+        .bind(s.domain)
+        .bind(s.selector)
+        .bind(s.result.as_str())
+        .bind(s.reason)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// The message's signatures in the order they appear in its header.
+pub async fn list_dkim_signatures(
+    pool: &PgPool,
+    received_email_id: Uuid,
+) -> Result<Vec<DkimSignature>, sqlx::Error> {
+    sqlx::query_as::<_, DkimSignature>(
+        "SELECT received_email_id, domain, selector, result, reason \
+         FROM email_dkim_signature WHERE received_email_id = $1 ORDER BY position",
+    )
+    .bind(received_email_id)
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// How the SMTP server treats recipients at a domain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DomainPolicy {
+    /// Existing live addresses only; domains without a row behave like this.
+    #[default]
+    Registered,
+    /// Existing live addresses that were created with an API key.
+    ApiOnly,
+    /// Any local part; unknown addresses are created on first delivery.
+    CatchAll,
+    /// Any local part; mail is POSTed to the domain's webhook, not stored.
+    Webhook,
+}
+
+impl DomainPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Registered => "registered",
+            Self::ApiOnly => "api_only",
+            Self::CatchAll => "catch_all",
+            Self::Webhook => "webhook",
+        }
+    }
+}
+
+impl fmt::Display for DomainPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DomainPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "registered" => Ok(Self::Registered),
+            "api_only" => Ok(Self::ApiOnly),
+            "catch_all" => Ok(Self::CatchAll),
+            "webhook" => Ok(Self::Webhook),
+            other => Err(format!(
+                "expected registered, api_only, catch_all or webhook, got {other:?}"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for DomainPolicy {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct MailDomain {
+    pub domain: String,
+    #[sqlx(try_from = "String")]
+    pub policy: DomainPolicy,
+    /// Set exactly when `policy` is `webhook`.
+    pub webhook_url: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+const MAIL_DOMAIN_COLUMNS: &str = "domain, policy, webhook_url, updated_at";
+
+/// Sets the routing of `domain` (lowercase), replacing any previous rule.
+pub async fn upsert_mail_domain(
+    pool: &PgPool,
+    domain: &str,
+    policy: DomainPolicy,
+    webhook_url: Option<&str>,
+) -> Result<MailDomain, sqlx::Error> {
+    sqlx::query_as::<_, MailDomain>(&format!(
+        "INSERT INTO mail_domain (domain, policy, webhook_url) VALUES ($1, $2, $3) \
+         ON CONFLICT (domain) DO UPDATE SET \
+             policy = EXCLUDED.policy, \
+             webhook_url = EXCLUDED.webhook_url, \
+             updated_at = now() \
+         RETURNING {MAIL_DOMAIN_COLUMNS}"
+    ))
+    .bind(domain)
+    .bind(policy.as_str())
+    .bind(webhook_url)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn find_mail_domain(
+    pool: &PgPool,
+    domain: &str,
+) -> Result<Option<MailDomain>, sqlx::Error> {
+    sqlx::query_as::<_, MailDomain>(&format!(
+        "SELECT {MAIL_DOMAIN_COLUMNS} FROM mail_domain WHERE domain = lower($1)"
+    ))
+    .bind(domain)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn list_mail_domains(pool: &PgPool) -> Result<Vec<MailDomain>, sqlx::Error> {
+    sqlx::query_as::<_, MailDomain>(&format!(
+        "SELECT {MAIL_DOMAIN_COLUMNS} FROM mail_domain ORDER BY domain"
+    ))
+    .fetch_all(pool)
+    .await
+}
+
+/// Drops the rule, so the domain falls back to `registered`.
+pub async fn delete_mail_domain(pool: &PgPool, domain: &str) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM mail_domain WHERE domain = lower($1)")
+            .bind(domain)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
+
+/// Whether the address was created with an API key (or billed to one).
+pub async fn is_api_created(pool: &PgPool, temporary_email_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM temporary_email WHERE id = $1 AND api_key_id IS NOT NULL)",
+    )
+    .bind(temporary_email_id)
+    .fetch_one(pool)
+    .await
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A sender an address is waiting for, such as the service about to send it
+/// a one-time code. Matches envelope senders at `from_domain` or any of its
+/// subdomains until `expires_at`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SenderExpectation {
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Uuid,
+    pub from_domain: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+const SENDER_EXPECTATION_COLUMNS: &str = "temporary_email_id, from_domain, expires_at, created_at";
+
+/// Expects `from_domain` for `ttl` from now; expecting it again extends the
+/// deadline.
+pub async fn upsert_sender_expectation(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    from_domain: &str,
+    ttl: Duration,
+) -> Result<SenderExpectation, sqlx::Error> {
+    sqlx::query_as::<_, SenderExpectation>(&format!(
+        "INSERT INTO sender_expectation (temporary_email_id, from_domain, expires_at) \
+         VALUES ($1, lower($2), now() + make_interval(secs => $3::float8)) \
+         ON CONFLICT (temporary_email_id, from_domain) \
+         DO UPDATE SET expires_at = EXCLUDED.expires_at \
+         RETURNING {SENDER_EXPECTATION_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(from_domain)
+    .bind(ttl.as_secs_f64())
+    .fetch_one(pool)
+    .await
+}
+
+/// Expectations of one address that have not run out, soonest to expire
+/// first.
+pub async fn list_sender_expectations(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<SenderExpectation>, sqlx::Error> {
+    sqlx::query_as::<_, SenderExpectation>(&format!(
+        "SELECT {SENDER_EXPECTATION_COLUMNS} FROM sender_expectation \
+         WHERE temporary_email_id = $1 AND expires_at > now() \
+         ORDER BY expires_at, from_domain"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Addresses currently expecting mail from `sender_domain`.
+pub async fn find_mailboxes_expecting(
+    pool: &PgPool,
+    sender_domain: &str,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT DISTINCT temporary_email_id FROM sender_expectation \
+         WHERE expires_at > now() \
+           AND (from_domain = lower($1) \
+                OR right(lower($1), length(from_domain) + 1) = '.' || from_domain)",
+    )
+    .bind(sender_domain)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn delete_expired_sender_expectations(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM sender_expectation WHERE expires_at <= now()")
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
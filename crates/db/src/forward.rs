@@ -0,0 +1,208 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A real mailbox that gets a copy of every message an address receives,
+/// once its owner has confirmed it wants them.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ForwardingRule {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Uuid,
+    pub forward_to: String,
+    pub created_at: DateTime<Utc>,
+    /// `None` while the rule is pending.
+    pub confirmed_at: Option<DateTime<Utc>>,
+}
+
+const FORWARDING_RULE_COLUMNS: &str =
+    "id, temporary_email_id, forward_to, created_at, confirmed_at";
+
+/// A queued forward claimed by [`claim_forward_deliveries`], with what is
+/// needed to send it.
+#[derive(Debug, Clone, FromRow)]
+pub struct PendingForward {
+    pub id: Uuid,
+    pub rule_id: Uuid,
+    pub received_email_id: Uuid,
+    /// Attempts before this one.
+    pub attempts: i32,
+    /// The address the message was delivered to; it becomes the sender.
+    pub temp_email_addr: String,
+    pub forward_to: String,
+}
+
+/// `None` when the address already forwards to `forward_to`. The rule starts
+/// out pending.
+pub async fn insert_forwarding_rule(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    forward_to: &str,
+) -> Result<Option<ForwardingRule>, sqlx::Error> {
+    sqlx::query_as::<_, ForwardingRule>(&format!(
+        "INSERT INTO forwarding_rule (temporary_email_id, forward_to) VALUES ($1, lower($2)) \
+         ON CONFLICT DO NOTHING \
+         RETURNING {FORWARDING_RULE_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(forward_to)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Rules of one address, oldest first.
+pub async fn list_forwarding_rules(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<ForwardingRule>, sqlx::Error> {
+    sqlx::query_as::<_, ForwardingRule>(&format!(
+        "SELECT {FORWARDING_RULE_COLUMNS} FROM forwarding_rule \
+         WHERE temporary_email_id = $1 \
+         ORDER BY created_at, id"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Confirms a rule, leaving an earlier confirmation as it was. `None` when
+/// the rule no longer exists.
+pub async fn confirm_forwarding_rule(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<ForwardingRule>, sqlx::Error> {
+    sqlx::query_as::<_, ForwardingRule>(&format!(
+        "UPDATE forwarding_rule SET confirmed_at = coalesce(confirmed_at, now()) \
+         WHERE id = $1 \
+         RETURNING {FORWARDING_RULE_COLUMNS}"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn find_forwarding_rule(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<ForwardingRule>, sqlx::Error> {
+    sqlx::query_as::<_, ForwardingRule>(&format!(
+        "SELECT {FORWARDING_RULE_COLUMNS} FROM forwarding_rule WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Drops the rule and whatever it still had queued.
+pub async fn delete_forwarding_rule(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM forwarding_rule WHERE id = $1 AND temporary_email_id = $2")
+            .bind(id)
+            .bind(temporary_email_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
+
+/// Queues the message for every confirmed rule of its address; called in
+/// the transaction that stores it, so a stored message is never missed.
+/// Bounces are not forwarded: a bounce of a forward would bounce again.
+pub(crate) async fn queue_forward_deliveries(
+    conn: &mut PgConnection,
+    temporary_email_id: Uuid,
+    received_email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO forward_delivery (rule_id, received_email_id) \
+         SELECT r.id, $2 FROM forwarding_rule r \
+         WHERE r.temporary_email_id = $1 \
+           AND r.confirmed_at IS NOT NULL \
+           AND NOT (SELECT is_bounce FROM received_email WHERE id = $2)",
+    )
+    .bind(temporary_email_id)
+    .bind(received_email_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Takes up to `limit` due forwards of confirmed rules, oldest first, and
+/// hides them from other workers for `lease`; a worker that dies
+/// mid-delivery only delays them.
+pub async fn claim_forward_deliveries(
+    pool: &PgPool,
+    limit: i64,
+    lease: Duration,
+) -> Result<Vec<PendingForward>, sqlx::Error> {
+    sqlx::query_as::<_, PendingForward>(
+        "WITH due AS ( \
+             SELECT d.id FROM forward_delivery d \
+             JOIN forwarding_rule r ON r.id = d.rule_id \
+             WHERE d.delivered_at IS NULL AND d.failed_at IS NULL \
+               AND d.next_attempt_at <= now() \
+               AND r.confirmed_at IS NOT NULL \
+             ORDER BY d.next_attempt_at \
+             LIMIT $1 \
+             FOR UPDATE OF d SKIP LOCKED \
+         ), claimed AS ( \
+             UPDATE forward_delivery d \
+             SET next_attempt_at = now() + make_interval(secs => $2::float8) \
+             FROM due WHERE d.id = due.id \
+             RETURNING d.id, d.rule_id, d.received_email_id, d.attempts \
+         ) \
+         SELECT c.id, c.rule_id, c.received_email_id, c.attempts, t.temp_email_addr, \
+                r.forward_to \
+         FROM claimed c \
+         JOIN forwarding_rule r ON r.id = c.rule_id \
+         JOIN temporary_email t ON t.id = r.temporary_email_id",
+    )
+    .bind(limit)
+    .bind(lease.as_secs_f64())
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn complete_forward_delivery(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE forward_delivery \
+         SET attempts = attempts + 1, delivered_at = now(), last_error = NULL \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a failed attempt: the forward is tried again after `retry_in`, or
+/// given up on when that is `None`.
+pub async fn fail_forward_delivery(
+    pool: &PgPool,
+    id: Uuid,
+    error: &str,
+    retry_in: Option<Duration>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE forward_delivery \
+         SET attempts = attempts + 1, \
+             last_error = $2, \
+             next_attempt_at = now() + make_interval(secs => coalesce($3::float8, 0)), \
+             failed_at = CASE WHEN $3::float8 IS NULL THEN now() END \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .bind(retry_in.map(|d| d.as_secs_f64()))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
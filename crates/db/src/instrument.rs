@@ -0,0 +1,34 @@
+//! Database metrics: pool occupancy, read when metrics are scraped, and
+//! query latency labelled with the service that ran the query.
+
+use sqlx::postgres::PgPool;
+use std::future::Future;
+use std::time::Instant;
+
+/// Sets `db_pool_connections{state}` (`in_use` or `idle`) and
+/// `db_pool_max_connections` from the pool as it is now.
+pub fn record_pool_metrics(pool: &PgPool) {
+    let idle = u32::try_from(pool.num_idle()).unwrap_or(u32::MAX);
+    let in_use = pool.size().saturating_sub(idle);
+    metrics::gauge!("db_pool_connections", "state" => "in_use").set(f64::from(in_use));
+    metrics::gauge!("db_pool_connections", "state" => "idle").set(f64::from(idle));
+    metrics::gauge!("db_pool_max_connections").set(f64::from(pool.options().get_max_connections()));
+}
+
+/// Awaits `query`, recording its duration in
+/// `db_query_duration_seconds{service, query}`, and counts a failure to get
+/// a connection in `db_pool_timeouts_total{service}`.
+pub async fn timed<T>(
+    service: &'static str,
+    query: &'static str,
+    fut: impl Future<Output = Result<T, sqlx::Error>>,
+) -> Result<T, sqlx::Error> {
+    let start = Instant::now();
+    let result = fut.await;
+    metrics::histogram!("db_query_duration_seconds", "service" => service, "query" => query)
+        .record(start.elapsed().as_secs_f64());
+    if matches!(result, Err(sqlx::Error::PoolTimedOut)) {
+        metrics::counter!("db_pool_timeouts_total", "service" => service).increment(1);
+    }
+    result
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// How long mail from one sender domain took from the SMTP server accepting
+/// it to an API client first being shown it.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SenderLatency {
+    pub sender_domain: String,
+    pub messages: i64,
+    pub p50_secs: f64,
+    pub p95_secs: f64,
+    pub max_secs: f64,
+}
+
+/// Records that the messages were shown to a client, if they had not been
+/// yet. Returns the seconds each newly read message took since it was
+/// accepted, for those that came over SMTP.
+pub async fn mark_first_read(pool: &PgPool, ids: &[Uuid]) -> Result<Vec<f64>, sqlx::Error> {
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let latencies: Vec<Option<f64>> = sqlx::query_scalar(
+        "UPDATE received_email SET first_read_at = now() \
+         WHERE id = ANY($1) AND first_read_at IS NULL \
+         RETURNING extract(epoch FROM first_read_at - accepted_at)::float8",
+    )
+    .bind(ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(latencies.into_iter().flatten().collect())
+}
+
+/// Latency percentiles per envelope sender domain over mail first read
+/// since `since`, slowest p95 first. Bounces and mail that did not come
+/// over SMTP are left out.
+pub async fn delivery_latency_by_sender(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<SenderLatency>, sqlx::Error> {
+    sqlx::query_as::<_, SenderLatency>(
+        "SELECT sender_domain, \
+                count(*) AS messages, \
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY secs) AS p50_secs, \
+                percentile_cont(0.95) WITHIN GROUP (ORDER BY secs) AS p95_secs, \
+                max(secs) AS max_secs \
+         FROM ( \
+             SELECT lower(substring(from_addr FROM '@([^@]+)$')) AS sender_domain, \
+                    extract(epoch FROM first_read_at - accepted_at)::float8 AS secs \
+             FROM received_email \
+             WHERE first_read_at >= $1 AND accepted_at IS NOT NULL \
+         ) m \
+         WHERE sender_domain IS NOT NULL \
+         GROUP BY sender_domain \
+         ORDER BY p95_secs DESC, sender_domain \
+         LIMIT $2",
+    )
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
@@ -1,13 +1,118 @@
+mod alias;
+mod attachment;
+mod blocklist;
+mod compression;
+mod dkim;
+mod domain;
+mod expectation;
+mod forward;
+mod instrument;
+mod latency;
+mod metering;
 mod models;
+mod poison;
+mod preview;
+mod purge;
+mod quota;
 mod repo;
+mod sender_block;
+mod smtp_user;
+mod summary;
+mod tenant;
+mod timeline;
+mod watch;
+mod webhook;
 
-pub use models::{ReceivedEmail, TemporaryEmail};
+pub use alias::{delete_address_alias, insert_address_alias, list_address_aliases, AddressAlias};
+pub use attachment::{
+    fetch_attachment, insert_attachments, list_attachments, Attachment, NewAttachment,
+};
+pub use blocklist::{compile_local_part_pattern, BlocklistCache, LocalPartBlocklist};
+pub use compression::BodyCompression;
+pub use dkim::{list_dkim_signatures, DkimResult, DkimSignature, NewDkimSignature};
+pub use domain::{
+    delete_mail_domain, find_mail_domain, is_api_created, list_mail_domains, upsert_mail_domain,
+    DomainPolicy, MailDomain,
+};
+pub use expectation::{
+    delete_expired_sender_expectations, find_mailboxes_expecting, list_sender_expectations,
+    upsert_sender_expectation, SenderExpectation,
+};
+pub use forward::{
+    claim_forward_deliveries, complete_forward_delivery, confirm_forwarding_rule,
+    delete_forwarding_rule, fail_forward_delivery, find_forwarding_rule, insert_forwarding_rule,
+    list_forwarding_rules, ForwardingRule, PendingForward,
+};
+pub use instrument::{record_pool_metrics, timed};
+pub use latency::{delivery_latency_by_sender, mark_first_read, SenderLatency};
+pub use metering::{
+    aggregate_usage, attribute_temporary_emails, find_api_key_by_hash, insert_api_key,
+    list_api_keys, list_usage_daily, record_message_usage, record_usage, revoke_api_key, ApiKey,
+    UsageDaily, UsageKind,
+};
+pub use models::{
+    BlockedLocalPart, DmarcResult, EmailShare, NewReceivedEmail, ReceivedEmail, SenderReputation,
+    Session, SpfResult, TemporaryEmail, User,
+};
+pub use poison::{
+    delete_poison_message, fetch_poison_raw, find_poison_message, list_poison_messages,
+    record_poison_message, refuse_known_poison, NewPoisonMessage, PoisonMessage,
+};
+pub use preview::{fetch_email_preview, store_email_preview};
+pub use purge::{purge_all_data, purge_all_data_with, PurgeOptions, PurgeResult, PurgeStrategy};
+pub use quota::{mailbox_usage, MailboxQuota, MailboxUsage, QuotaWarning, QUOTA_WARN_PERCENT};
 pub use repo::{
-    find_temporary_email_by_addr, insert_received_email, insert_temporary_email,
-    list_received_emails, purge_all_data, PurgeResult,
+    claim_temporary_email, compress_stored_bodies, count_unread_emails,
+    deactivate_expired_addresses, delete_blocked_local_part, delete_expired_public_messages,
+    delete_expired_sessions, extend_temporary_email, fetch_batch_request_hash, fetch_email_headers,
+    fetch_mailbox_token_hash, fetch_raw_email, find_email_share, find_received_email,
+    find_received_email_by_id, find_temporary_email_by_addr, find_temporary_email_by_alias,
+    insert_blocked_local_part, insert_email_share, insert_honeypot_email,
+    insert_public_temporary_email, insert_received_email, insert_received_email_for_recipients,
+    insert_scheduled_temporary_email, insert_session, insert_temporary_email,
+    insert_temporary_email_batch, insert_temporary_email_with_token, list_blocked_local_parts,
+    list_honeypot_emails, list_imap_messages, list_received_emails, list_sender_reputation,
+    list_taken_addresses, list_temporary_emails_by_batch, list_temporary_emails_by_owner,
+    list_trashed_emails, merge_temporary_emails, new_mail_payload, parse_address_changed_payload,
+    parse_new_mail_payload, purge_trashed_emails, reactivate_temporary_email, record_honeypot_hit,
+    redact_received_email, replace_mailbox_token_hash, replace_mailbox_token_hashes,
+    restore_received_emails, revoke_email_share, rotate_session_refresh, search_emails_by_address,
+    set_received_email_read, trash_received_emails, upsert_user, BatchKey, CompressionBackfill,
+    ADDRESS_CHANGED_CHANNEL, NEW_MAIL_CHANNEL, REDACTION_NOTICE,
+};
+pub use sender_block::{
+    delete_blocked_sender, find_sender_blocks, insert_blocked_sender, list_blocked_senders,
+    BlockedSender, SenderBlocks,
+};
+pub use smtp_user::{
+    delete_smtp_user, find_smtp_user_password_hash, list_smtp_users, upsert_smtp_user, SmtpUser,
+};
+pub use summary::{
+    list_email_summaries_by_address, EmailSummary, SummaryCursor, SummaryFilter, SummaryPage,
+};
+pub use tenant::{
+    find_tenant_settings, find_tenant_settings_for_address, upsert_tenant_settings, TenantSettings,
+    TenantSettingsUpdate,
+};
+pub use timeline::{
+    mailbox_activity_by_period, mailbox_activity_by_sender, PeriodActivity, SenderActivity,
+    TimelineBucket,
+};
+pub use watch::{
+    delete_watch_rule, insert_watch_rule, list_watch_rules, list_watch_rules_for, WatchKind,
+    WatchRule,
+};
+pub use webhook::{
+    claim_webhook_deliveries, complete_webhook_delivery, delete_webhook_subscription,
+    fail_webhook_delivery, insert_webhook_subscription, list_webhook_subscriptions,
+    PendingWebhookDelivery, WebhookSubscription, WEBHOOK_PRIORITY_CHANNEL,
 };
 
 use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::time::Duration;
+
+/// Lifetime of a new address; matches the `temporary_email.expires_at` default.
+pub const ADDRESS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
 pub async fn connect_pool() -> Result<PgPool, sqlx::Error> {
     let database_url = std::env::var("DATABASE_URL").map_err(|_| {
@@ -22,3 +127,22 @@ pub async fn connect_pool() -> Result<PgPool, sqlx::Error> {
 pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
     sqlx::migrate!("./migrations").run(pool).await
 }
+
+/// Migrations bundled in this binary that the database has not applied yet,
+/// as `(version, description)`. A fresh database reports all of them.
+pub async fn pending_migrations(pool: &PgPool) -> Result<Vec<(i64, String)>, sqlx::Error> {
+    let applied: Vec<i64> =
+        match sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+        {
+            Ok(v) => v,
+            Err(sqlx::Error::Database(e)) if e.code().is_some_and(|c| c == "42P01") => Vec::new(),
+            Err(e) => return Err(e),
+        };
+    Ok(sqlx::migrate!("./migrations")
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(|m| (m.version, m.description.to_string()))
+        .collect())
+}
@@ -0,0 +1,193 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageKind {
+    ApiCall,
+    AddressCreated,
+    MessageStored,
+}
+
+impl UsageKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ApiCall => "api_call",
+            Self::AddressCreated => "address_created",
+            Self::MessageStored => "message_stored",
+        }
+    }
+}
+
+impl fmt::Display for UsageKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UsageDaily {
+    pub api_key_id: Uuid,
+    pub day: NaiveDate,
+    pub kind: String,
+    pub quantity: i64,
+}
+
+pub async fn insert_api_key(
+    pool: &PgPool,
+    name: &str,
+    key_hash: &[u8],
+) -> Result<ApiKey, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        "INSERT INTO api_key (name, key_hash) VALUES ($1, $2) \
+         RETURNING id, name, created_at, revoked_at",
+    )
+    .bind(name)
+    .bind(key_hash)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_api_keys(pool: &PgPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        "SELECT id, name, created_at, revoked_at FROM api_key ORDER BY created_at ASC",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Unrevoked key with this hash.
+pub async fn find_api_key_by_hash(
+    pool: &PgPool,
+    key_hash: &[u8],
+) -> Result<Option<ApiKey>, sqlx::Error> {
+    sqlx::query_as::<_, ApiKey>(
+        "SELECT id, name, created_at, revoked_at FROM api_key \
+         WHERE key_hash = $1 AND revoked_at IS NULL",
+    )
+    .bind(key_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn revoke_api_key(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("UPDATE api_key SET revoked_at = now() WHERE id = $1 AND revoked_at IS NULL")
+            .bind(id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
+
+pub async fn record_usage(
+    pool: &PgPool,
+    api_key_id: Uuid,
+    kind: UsageKind,
+    quantity: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO usage_events (api_key_id, kind, quantity) VALUES ($1, $2, $3)")
+        .bind(api_key_id)
+        .bind(kind.as_str())
+        .bind(quantity)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Bills addresses not yet billed to anyone to `api_key_id`, recording one
+/// `address_created` event for them. Replaying an idempotent batch is free.
+pub async fn attribute_temporary_emails(
+    pool: &PgPool,
+    ids: &[Uuid],
+    api_key_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let billed = sqlx::query(
+        "UPDATE temporary_email SET api_key_id = $2 WHERE id = ANY($1) AND api_key_id IS NULL",
+    )
+    .bind(ids)
+    .bind(api_key_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    if billed > 0 {
+        sqlx::query("INSERT INTO usage_events (api_key_id, kind, quantity) VALUES ($1, $2, $3)")
+            .bind(api_key_id)
+            .bind(UsageKind::AddressCreated.as_str())
+            .bind(billed as i64)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(billed)
+}
+
+/// Records a stored message against the key the address was created with, if
+/// any.
+pub async fn record_message_usage(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO usage_events (api_key_id, kind) \
+         SELECT api_key_id, $2 FROM temporary_email WHERE id = $1 AND api_key_id IS NOT NULL",
+    )
+    .bind(temporary_email_id)
+    .bind(UsageKind::MessageStored.as_str())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Folds raw events from days that have ended (UTC) into `usage_daily` and
+/// deletes them. Safe to run repeatedly. Returns the number of daily rows written.
+pub async fn aggregate_usage(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let folded = sqlx::query(
+        "WITH folded AS ( \
+             DELETE FROM usage_events \
+             WHERE occurred_at < date_trunc('day', now() AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' \
+             RETURNING api_key_id, (occurred_at AT TIME ZONE 'UTC')::date AS day, kind, quantity \
+         ) \
+         INSERT INTO usage_daily (api_key_id, day, kind, quantity) \
+         SELECT api_key_id, day, kind, SUM(quantity) FROM folded GROUP BY api_key_id, day, kind \
+         ON CONFLICT (api_key_id, day, kind) \
+         DO UPDATE SET quantity = usage_daily.quantity + EXCLUDED.quantity",
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    tx.commit().await?;
+    Ok(folded)
+}
+
+/// Daily totals for `from..=to`, optionally for one key.
+pub async fn list_usage_daily(
+    pool: &PgPool,
+    from: NaiveDate,
+    to: NaiveDate,
+    api_key_id: Option<Uuid>,
+) -> Result<Vec<UsageDaily>, sqlx::Error> {
+    sqlx::query_as::<_, UsageDaily>(
+        "SELECT api_key_id, day, kind, quantity FROM usage_daily \
+         WHERE day BETWEEN $1 AND $2 AND ($3::uuid IS NULL OR api_key_id = $3) \
+         ORDER BY day ASC, api_key_id ASC, kind ASC",
+    )
+    .bind(from)
+    .bind(to)
+    .bind(api_key_id)
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct BlockedLocalPart {
+    pub id: Uuid,
+    pub pattern: String,
+    pub is_regex: bool,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
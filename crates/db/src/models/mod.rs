@@ -1,5 +1,15 @@
+mod blocked_local_part;
+mod email_share;
 mod received_email;
+mod sender_reputation;
+mod session;
 mod temporary_email;
+mod user;
 
-pub use received_email::ReceivedEmail;
+pub use blocked_local_part::BlockedLocalPart;
+pub use email_share::EmailShare;
+pub use received_email::{DmarcResult, NewReceivedEmail, ReceivedEmail, SpfResult};
+pub use sender_reputation::SenderReputation;
+pub use session::Session;
 pub use temporary_email::TemporaryEmail;
+pub use user::User;
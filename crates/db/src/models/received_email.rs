@@ -1,9 +1,139 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+use crate::dkim::NewDkimSignature;
+
+/// Outcome of the SPF check (RFC 7208) on the connecting IP and the
+/// `MAIL FROM` domain, or the `HELO` name for bounces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SpfResult {
+    /// The IP is authorized to send for the domain.
+    Pass,
+    /// The domain says the IP must not send for it.
+    Fail,
+    /// The IP is probably not authorized (`~all`).
+    SoftFail,
+    /// The domain makes no assertion about the IP (`?all`).
+    Neutral,
+    /// The domain publishes no SPF record.
+    None,
+    /// DNS could not be queried; a retry might succeed.
+    TempError,
+    /// The domain's record is broken.
+    PermError,
+}
+
+impl SpfResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::SoftFail => "softfail",
+            Self::Neutral => "neutral",
+            Self::None => "none",
+            Self::TempError => "temperror",
+            Self::PermError => "permerror",
+        }
+    }
+}
+
+impl fmt::Display for SpfResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SpfResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Self::Pass),
+            "fail" => Ok(Self::Fail),
+            "softfail" => Ok(Self::SoftFail),
+            "neutral" => Ok(Self::Neutral),
+            "none" => Ok(Self::None),
+            "temperror" => Ok(Self::TempError),
+            "permerror" => Ok(Self::PermError),
+            other => Err(format!("unknown SPF result {other:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for SpfResult {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Outcome of DMARC (RFC 7489): SPF or DKIM passing for a domain aligned
+/// with the `From:` header, under the policy that domain publishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DmarcResult {
+    /// An aligned SPF or DKIM pass.
+    Pass,
+    /// Neither passed for an aligned domain.
+    Fail,
+    /// The `From:` domain publishes no policy, or the message has no
+    /// single `From:` domain.
+    None,
+    /// The policy could not be looked up; a retry might succeed.
+    TempError,
+    /// The published policy is broken.
+    PermError,
+}
+
+impl DmarcResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pass => "pass",
+            Self::Fail => "fail",
+            Self::None => "none",
+            Self::TempError => "temperror",
+            Self::PermError => "permerror",
+        }
+    }
+}
+
+impl fmt::Display for DmarcResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DmarcResult {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(Self::Pass),
+            "fail" => Ok(Self::Fail),
+            "none" => Ok(Self::None),
+            "temperror" => Ok(Self::TempError),
+            "permerror" => Ok(Self::PermError),
+            other => Err(format!("unknown DMARC result {other:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for DmarcResult {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[schema(as = Message)]
 pub struct ReceivedEmail {
     pub id: Uuid,
     #[serde(skip_serializing)]
@@ -12,5 +142,76 @@ pub struct ReceivedEmail {
     pub to_addr: Option<String>,
     pub subject: Option<String>,
     pub body_text: Option<String>,
+    pub body_html: Option<String>,
     pub received_at: DateTime<Utc>,
+    pub redacted_at: Option<DateTime<Utc>>,
+    /// Sent with a null reverse path (`MAIL FROM:<>`), e.g. a bounce.
+    pub is_bounce: bool,
+    /// Address of the SMTP client that delivered the message.
+    pub peer_ip: Option<String>,
+    /// `None` when SPF was not checked.
+    pub spf_result: Option<SpfResult>,
+    /// `None` when DMARC was not checked.
+    pub dmarc_result: Option<DmarcResult>,
+    /// What the SMTP server noticed about the delivery, like `early-talker`.
+    pub tags: Vec<String>,
+    /// Country code of the SMTP client, when GeoIP lookup is configured.
+    pub country: Option<String>,
+    /// `shop` for mail sent to `local+shop@domain`.
+    pub plus_tag: Option<String>,
+    /// The recipient as the sender addressed it, from the DSN `ORCPT`
+    /// parameter; often the address before a forwarder rewrote it.
+    pub original_recipient: Option<String>,
+    /// The `RCPT TO` addresses that delivered this copy, as given: the
+    /// mailbox's own, and `+tag` forms of it named in the same transaction.
+    pub envelope_recipients: Vec<String>,
+    /// Lowercased addresses of the `To:` and `Cc:` headers.
+    pub header_recipients: Vec<String>,
+    pub is_read: bool,
+    /// When the message was moved to the trash.
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl ReceivedEmail {
+    /// None of the envelope recipients is named in `To:` or `Cc:`: the
+    /// mailbox was Bcc'd, or the message came through a forward or a mailing
+    /// list. False when the message has no recipient headers at all.
+    pub fn recipient_mismatch(&self) -> bool {
+        !self.header_recipients.is_empty()
+            && !self.envelope_recipients.iter().any(|rcpt| {
+                self.header_recipients
+                    .iter()
+                    .any(|h| h.eq_ignore_ascii_case(rcpt))
+            })
+    }
+}
+
+/// Insert payload for [`ReceivedEmail`]. `raw_email` is the message as received
+/// and is only readable via `fetch_raw_email`; `headers` (name, value) pairs
+/// likewise via `fetch_email_headers`, and `dkim` via `list_dkim_signatures`.
+#[derive(Debug, Clone, Copy)]
+pub struct NewReceivedEmail<'a> {
+    pub temporary_email_id: Uuid,
+    pub from_addr: Option<&'a str>,
+    pub to_addr: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub body_text: Option<&'a str>,
+    pub body_html: Option<&'a str>,
+    pub raw_email: Option<&'a [u8]>,
+    pub headers: &'a [(String, String)],
+    pub is_bounce: bool,
+    pub peer_ip: Option<&'a str>,
+    pub spf_result: Option<SpfResult>,
+    pub dkim: &'a [NewDkimSignature<'a>],
+    pub dmarc_result: Option<DmarcResult>,
+    pub tags: &'a [String],
+    pub country: Option<&'a str>,
+    pub plus_tag: Option<&'a str>,
+    pub original_recipient: Option<&'a str>,
+    /// `to_addr` alone when left empty.
+    pub envelope_recipients: &'a [String],
+    pub header_recipients: &'a [String],
+    /// When the SMTP server accepted the first recipient; `None` for mail
+    /// that did not come over SMTP.
+    pub accepted_at: Option<DateTime<Utc>>,
 }
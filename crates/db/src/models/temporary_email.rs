@@ -8,4 +8,26 @@ pub struct TemporaryEmail {
     pub id: Uuid,
     pub temp_email_addr: String,
     pub created_at: DateTime<Utc>,
+    pub is_honeypot: bool,
+    pub expires_at: DateTime<Utc>,
+    pub is_active: bool,
+    pub is_public: bool,
+    /// Account that owns the address, if it was created while logged in.
+    pub user_id: Option<Uuid>,
+    /// When a scheduled address starts accepting mail; `None` if it did on
+    /// creation.
+    pub activate_at: Option<DateTime<Utc>>,
+}
+
+impl TemporaryEmail {
+    /// Whether the address currently accepts and serves mail. Honeypots never
+    /// expire.
+    pub fn is_live(&self) -> bool {
+        self.is_honeypot || (self.is_active && self.expires_at > Utc::now() && !self.is_scheduled())
+    }
+
+    /// Whether the address is waiting for its `activate_at`.
+    pub fn is_scheduled(&self) -> bool {
+        self.activate_at.is_some_and(|at| at > Utc::now())
+    }
 }
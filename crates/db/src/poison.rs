@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+const POISON_COLUMNS: &str = "id, peer_ip, mail_from, recipients, error, attempts, size, \
+                              first_seen_at, last_seen_at";
+
+/// A quarantined message; the raw bytes come from [`fetch_poison_raw`].
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PoisonMessage {
+    pub id: Uuid,
+    pub peer_ip: Option<String>,
+    pub mail_from: Option<String>,
+    pub recipients: Vec<String>,
+    /// What went wrong on the latest attempt.
+    pub error: String,
+    /// Times this exact message was received and failed or was refused.
+    pub attempts: i32,
+    pub size: i64,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewPoisonMessage<'a> {
+    /// SHA-256 of `raw_email`.
+    pub digest: &'a [u8],
+    pub peer_ip: Option<&'a str>,
+    pub mail_from: Option<&'a str>,
+    pub recipients: &'a [String],
+    pub error: &'a str,
+    pub raw_email: &'a [u8],
+}
+
+/// Records a failed attempt: inserts the message, or bumps `attempts` and
+/// replaces `error` when the same bytes were quarantined before.
+pub async fn record_poison_message(
+    pool: &PgPool,
+    msg: &NewPoisonMessage<'_>,
+) -> Result<PoisonMessage, sqlx::Error> {
+    sqlx::query_as::<_, PoisonMessage>(&format!(
+        "INSERT INTO poison_message \
+         (digest, peer_ip, mail_from, recipients, error, size, raw_email) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7) \
+         ON CONFLICT (digest) DO UPDATE SET \
+         attempts = poison_message.attempts + 1, error = EXCLUDED.error, \
+         peer_ip = EXCLUDED.peer_ip, last_seen_at = now() \
+         RETURNING {POISON_COLUMNS}"
+    ))
+    .bind(msg.digest)
+    .bind(msg.peer_ip)
+    .bind(msg.mail_from)
+    .bind(msg.recipients)
+    .bind(msg.error)
+    .bind(msg.raw_email.len() as i64)
+    .bind(msg.raw_email)
+    .fetch_one(pool)
+    .await
+}
+
+/// If the message with `digest` has already failed `min_attempts` times,
+/// counts this delivery as another attempt and returns it. The caller should
+/// then refuse the message without processing it.
+pub async fn refuse_known_poison(
+    pool: &PgPool,
+    digest: &[u8],
+    min_attempts: i32,
+) -> Result<Option<PoisonMessage>, sqlx::Error> {
+    sqlx::query_as::<_, PoisonMessage>(&format!(
+        "UPDATE poison_message SET attempts = attempts + 1, last_seen_at = now() \
+         WHERE digest = $1 AND attempts >= $2 \
+         RETURNING {POISON_COLUMNS}"
+    ))
+    .bind(digest)
+    .bind(min_attempts)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn list_poison_messages(pool: &PgPool) -> Result<Vec<PoisonMessage>, sqlx::Error> {
+    sqlx::query_as::<_, PoisonMessage>(&format!(
+        "SELECT {POISON_COLUMNS} FROM poison_message ORDER BY last_seen_at DESC"
+    ))
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find_poison_message(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<PoisonMessage>, sqlx::Error> {
+    sqlx::query_as::<_, PoisonMessage>(&format!(
+        "SELECT {POISON_COLUMNS} FROM poison_message WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn fetch_poison_raw(pool: &PgPool, id: Uuid) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar("SELECT raw_email FROM poison_message WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Releases a message from quarantine; the next delivery is processed again.
+pub async fn delete_poison_message(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query("DELETE FROM poison_message WHERE id = $1")
+        .bind(id)
+        .execute(pool)
+        .await?
+        .rows_affected()
+        > 0)
+}
@@ -0,0 +1,29 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Stores the rendered preview of a message, replacing an older one.
+pub async fn store_email_preview(
+    pool: &PgPool,
+    received_email_id: Uuid,
+    png: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO email_preview (received_email_id, png) VALUES ($1, $2) \
+         ON CONFLICT (received_email_id) DO UPDATE SET png = EXCLUDED.png, created_at = now()",
+    )
+    .bind(received_email_id)
+    .bind(png)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn fetch_email_preview(
+    pool: &PgPool,
+    received_email_id: Uuid,
+) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar("SELECT png FROM email_preview WHERE received_email_id = $1")
+        .bind(received_email_id)
+        .fetch_optional(pool)
+        .await
+}
@@ -0,0 +1,190 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PurgeStrategy {
+    /// `DELETE ... LIMIT n` in short transactions; keeps locks and WAL bursts
+    /// small and lets autovacuum keep up.
+    Batched,
+    /// `TRUNCATE received_email` in one transaction. Fastest, but takes an
+    /// ACCESS EXCLUSIVE lock that stalls SMTP inserts until it commits.
+    Truncate,
+}
+
+impl FromStr for PurgeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "batched" => Ok(Self::Batched),
+            "truncate" => Ok(Self::Truncate),
+            _ => Err(format!("expected batched or truncate, got {s:?}")),
+        }
+    }
+}
+
+impl fmt::Display for PurgeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Batched => "batched",
+            Self::Truncate => "truncate",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PurgeOptions {
+    pub strategy: PurgeStrategy,
+    pub batch_size: i64,
+    /// Per-statement cap; `Duration::ZERO` leaves the server default.
+    pub statement_timeout: Duration,
+}
+
+impl Default for PurgeOptions {
+    fn default() -> Self {
+        Self {
+            strategy: PurgeStrategy::Batched,
+            batch_size: 5_000,
+            statement_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PurgeResult {
+    pub emails_deleted: i64,
+    pub inboxes_deleted: i64,
+    pub duration: Duration,
+}
+
+pub async fn purge_all_data(pool: &PgPool) -> Result<PurgeResult, sqlx::Error> {
+    purge_all_data_with(pool, &PurgeOptions::default()).await
+}
+
+/// Deletes all received mail and every non-honeypot address.
+pub async fn purge_all_data_with(
+    pool: &PgPool,
+    opts: &PurgeOptions,
+) -> Result<PurgeResult, sqlx::Error> {
+    let started = Instant::now();
+    let (emails, inboxes) = match opts.strategy {
+        PurgeStrategy::Batched => purge_batched(pool, opts).await?,
+        PurgeStrategy::Truncate => purge_truncate(pool, opts).await?,
+    };
+    let duration = started.elapsed();
+
+    let strategy = opts.strategy.to_string();
+    metrics::histogram!("purge_duration_seconds", "strategy" => strategy)
+        .record(duration.as_secs_f64());
+    metrics::counter!("purge_rows_deleted_total", "table" => "received_email")
+        .increment(emails as u64);
+    metrics::counter!("purge_rows_deleted_total", "table" => "temporary_email")
+        .increment(inboxes as u64);
+
+    Ok(PurgeResult {
+        emails_deleted: emails,
+        inboxes_deleted: inboxes,
+        duration,
+    })
+}
+
+async fn purge_batched(pool: &PgPool, opts: &PurgeOptions) -> Result<(i64, i64), sqlx::Error> {
+    let emails = delete_in_batches(
+        pool,
+        opts,
+        "DELETE FROM received_email WHERE id IN (SELECT id FROM received_email LIMIT $1)",
+    )
+    .await?;
+    // Honeypots never expire; everything else goes.
+    let inboxes = delete_in_batches(
+        pool,
+        opts,
+        "DELETE FROM temporary_email WHERE id IN \
+         (SELECT id FROM temporary_email WHERE NOT is_honeypot LIMIT $1)",
+    )
+    .await?;
+    delete_in_batches(
+        pool,
+        opts,
+        "DELETE FROM address_batch WHERE idempotency_key IN \
+         (SELECT idempotency_key FROM address_batch LIMIT $1)",
+    )
+    .await?;
+    delete_in_batches(
+        pool,
+        opts,
+        "DELETE FROM poison_message WHERE id IN (SELECT id FROM poison_message LIMIT $1)",
+    )
+    .await?;
+    Ok((emails, inboxes))
+}
+
+async fn delete_in_batches(
+    pool: &PgPool,
+    opts: &PurgeOptions,
+    sql: &str,
+) -> Result<i64, sqlx::Error> {
+    let batch_size = opts.batch_size.max(1);
+    let mut total = 0;
+    loop {
+        let mut tx = pool.begin().await?;
+        set_statement_timeout(&mut tx, opts.statement_timeout).await?;
+        let deleted = sqlx::query(sql)
+            .bind(batch_size)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() as i64;
+        tx.commit().await?;
+
+        total += deleted;
+        if deleted < batch_size {
+            return Ok(total);
+        }
+    }
+}
+
+async fn purge_truncate(pool: &PgPool, opts: &PurgeOptions) -> Result<(i64, i64), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    set_statement_timeout(&mut tx, opts.statement_timeout).await?;
+
+    let emails = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM received_email")
+        .fetch_one(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "TRUNCATE received_email, email_share, email_attachment, email_preview, webhook_delivery, \
+         forward_delivery, email_dkim_signature, poison_message",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let inboxes = sqlx::query("DELETE FROM temporary_email WHERE NOT is_honeypot")
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    sqlx::query("DELETE FROM address_batch")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((emails, inboxes as i64))
+}
+
+async fn set_statement_timeout(
+    tx: &mut Transaction<'_, Postgres>,
+    timeout: Duration,
+) -> Result<(), sqlx::Error> {
+    if timeout.is_zero() {
+        return Ok(());
+    }
+    // SET does not take bind parameters; the value is an integer we format.
+    sqlx::query(&format!(
+        "SET LOCAL statement_timeout = {}",
+        timeout.as_millis()
+    ))
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Share of a quota from which it is reported as nearly used up.
+pub const QUOTA_WARN_PERCENT: u64 = 80;
+
+/// How much one mailbox may hold. Zero means no limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MailboxQuota {
+    pub max_messages: u64,
+    /// Bytes as stored: text, HTML and original message, after compression.
+    pub max_bytes: u64,
+}
+
+/// What one mailbox holds.
+#[derive(Debug, Clone, Copy, Default, FromRow)]
+pub struct MailboxUsage {
+    pub messages: i64,
+    pub bytes: i64,
+}
+
+/// A quota that is at least [`QUOTA_WARN_PERCENT`] used.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct QuotaWarning {
+    /// `messages` or `bytes`.
+    pub quota: &'static str,
+    pub used: u64,
+    pub limit: u64,
+}
+
+impl MailboxQuota {
+    pub fn is_limited(&self) -> bool {
+        self.max_messages > 0 || self.max_bytes > 0
+    }
+
+    /// Whether another message would go over a limit.
+    pub fn is_full(&self, usage: &MailboxUsage) -> bool {
+        self.limits(usage).any(|(_, used, limit)| used >= limit)
+    }
+
+    pub fn warnings(&self, usage: &MailboxUsage) -> Vec<QuotaWarning> {
+        self.limits(usage)
+            .filter(|&(_, used, limit)| used * 100 >= limit * QUOTA_WARN_PERCENT)
+            .map(|(quota, used, limit)| QuotaWarning { quota, used, limit })
+            .collect()
+    }
+
+    fn limits(&self, usage: &MailboxUsage) -> impl Iterator<Item = (&'static str, u64, u64)> {
+        let used = |n: i64| u64::try_from(n).unwrap_or_default();
+        [
+            ("messages", used(usage.messages), self.max_messages),
+            ("bytes", used(usage.bytes), self.max_bytes),
+        ]
+        .into_iter()
+        .filter(|&(_, _, limit)| limit > 0)
+    }
+}
+
+pub async fn mailbox_usage(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<MailboxUsage, sqlx::Error> {
+    sqlx::query_as::<_, MailboxUsage>(
+        "SELECT count(*) AS messages, \
+                coalesce(sum(coalesce(octet_length(body_text), 0) \
+                             + coalesce(octet_length(body_html), 0) \
+                             + coalesce(octet_length(raw_email), 0)), 0)::int8 AS bytes \
+         FROM received_email WHERE temporary_email_id = $1 AND deleted_at IS NULL",
+    )
+    .bind(temporary_email_id)
+    .fetch_one(pool)
+    .await
+}
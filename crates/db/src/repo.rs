@@ -1,90 +1,1173 @@
-use crate::models::{ReceivedEmail, TemporaryEmail};
+use crate::attachment::{insert_attachment_rows, NewAttachment};
+use crate::compression::{self, BodyCompression, StoredBodies};
+use crate::dkim::insert_dkim_rows;
+use crate::forward::queue_forward_deliveries;
+use crate::models::{
+    BlockedLocalPart, DmarcResult, EmailShare, NewReceivedEmail, ReceivedEmail, SenderReputation,
+    Session, SpfResult, TemporaryEmail, User,
+};
+use crate::webhook::queue_webhook_deliveries;
+use crate::ADDRESS_TTL;
 use chrono::{DateTime, Utc};
-use sqlx::PgPool;
+use serde_json::{Map, Value};
+use sqlx::{PgConnection, PgPool};
+use std::time::Duration;
 use uuid::Uuid;
 
+const TEMPORARY_EMAIL_COLUMNS: &str =
+    "id, temp_email_addr, created_at, is_honeypot, expires_at, is_active, is_public, user_id, \
+     activate_at";
+
 pub async fn insert_temporary_email(
     pool: &PgPool,
     temp_email_addr: &str,
 ) -> Result<TemporaryEmail, sqlx::Error> {
-    sqlx::query_as::<_, TemporaryEmail>(
-        "INSERT INTO temporary_email (temp_email_addr) VALUES ($1) RETURNING id, temp_email_addr, created_at",
-    )
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email (temp_email_addr) VALUES ($1) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addr)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn insert_honeypot_email(
+    pool: &PgPool,
+    temp_email_addr: &str,
+) -> Result<TemporaryEmail, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email (temp_email_addr, is_honeypot) VALUES ($1, true) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addr)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn insert_public_temporary_email(
+    pool: &PgPool,
+    temp_email_addr: &str,
+) -> Result<TemporaryEmail, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email (temp_email_addr, is_public) VALUES ($1, true) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
     .bind(temp_email_addr)
     .fetch_one(pool)
     .await
 }
 
+/// An address that accepts and serves mail only from `activate_at`, and
+/// expires [`ADDRESS_TTL`](crate::ADDRESS_TTL) after that.
+pub async fn insert_scheduled_temporary_email(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    is_public: bool,
+    activate_at: DateTime<Utc>,
+) -> Result<TemporaryEmail, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email (temp_email_addr, is_public, activate_at, expires_at) \
+         VALUES ($1, $2, $3, $3 + make_interval(secs => $4)) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addr)
+    .bind(is_public)
+    .bind(activate_at)
+    .bind(ADDRESS_TTL.as_secs_f64())
+    .fetch_one(pool)
+    .await
+}
+
+/// An address created together with its access token hash, so it never
+/// exists without one. With `activate_at` it is scheduled like
+/// [`insert_scheduled_temporary_email`].
+pub async fn insert_temporary_email_with_token(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    is_public: bool,
+    activate_at: Option<DateTime<Utc>>,
+    token_hash: &[u8],
+) -> Result<TemporaryEmail, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email \
+         (temp_email_addr, is_public, activate_at, expires_at, token_hash) \
+         VALUES ($1, $2, $3, COALESCE($3, now()) + make_interval(secs => $4), $5) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addr)
+    .bind(is_public)
+    .bind(activate_at)
+    .bind(ADDRESS_TTL.as_secs_f64())
+    .bind(token_hash)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn list_honeypot_emails(pool: &PgPool) -> Result<Vec<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "SELECT {TEMPORARY_EMAIL_COLUMNS} FROM temporary_email \
+         WHERE is_honeypot ORDER BY created_at ASC"
+    ))
+    .fetch_all(pool)
+    .await
+}
+
+/// An idempotency key a batch is created under, with a hash of the request
+/// that created it.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchKey<'a> {
+    pub idempotency_key: &'a str,
+    pub request_hash: &'a [u8],
+}
+
+/// `token_hashes[i]` is stored as the token hash of `temp_email_addrs[i]`.
+pub async fn insert_temporary_email_batch(
+    pool: &PgPool,
+    batch: Option<BatchKey<'_>>,
+    temp_email_addrs: &[String],
+    token_hashes: &[Vec<u8>],
+) -> Result<Vec<TemporaryEmail>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(batch) = batch {
+        sqlx::query("INSERT INTO address_batch (idempotency_key, request_hash) VALUES ($1, $2)")
+            .bind(batch.idempotency_key)
+            .bind(batch.request_hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    let rows = sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "INSERT INTO temporary_email (temp_email_addr, batch_key, token_hash) \
+         SELECT addr, $2, hash FROM unnest($1::text[], $3::bytea[]) AS t (addr, hash) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addrs)
+    .bind(batch.map(|b| b.idempotency_key))
+    .bind(token_hashes)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(rows)
+}
+
+/// The `request_hash` the batch under `idempotency_key` was created with;
+/// `None` when there is no such batch. The batch outlives its addresses.
+pub async fn fetch_batch_request_hash(
+    pool: &PgPool,
+    idempotency_key: &str,
+) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    sqlx::query_scalar("SELECT request_hash FROM address_batch WHERE idempotency_key = $1")
+        .bind(idempotency_key)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_temporary_emails_by_batch(
+    pool: &PgPool,
+    idempotency_key: &str,
+) -> Result<Vec<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "SELECT {TEMPORARY_EMAIL_COLUMNS} FROM temporary_email \
+         WHERE batch_key = $1 ORDER BY created_at ASC, temp_email_addr ASC"
+    ))
+    .bind(idempotency_key)
+    .fetch_all(pool)
+    .await
+}
+
 pub async fn find_temporary_email_by_addr(
     pool: &PgPool,
     temp_email_addr: &str,
 ) -> Result<Option<TemporaryEmail>, sqlx::Error> {
-    sqlx::query_as::<_, TemporaryEmail>(
-        "SELECT id, temp_email_addr, created_at FROM temporary_email WHERE temp_email_addr = $1",
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "SELECT {TEMPORARY_EMAIL_COLUMNS} \
+         FROM temporary_email WHERE temp_email_addr = $1"
+    ))
+    .bind(temp_email_addr)
+    .fetch_optional(pool)
+    .await
+}
+
+/// The mailbox `alias_addr` delivers into, if it is an alias.
+pub async fn find_temporary_email_by_alias(
+    pool: &PgPool,
+    alias_addr: &str,
+) -> Result<Option<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "SELECT {TEMPORARY_EMAIL_COLUMNS} FROM temporary_email \
+         WHERE id = (SELECT temporary_email_id FROM address_alias WHERE alias_addr = $1)"
+    ))
+    .bind(alias_addr)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Marks addresses whose `expires_at` has passed as inactive. Honeypots are
+/// exempt from expiry.
+pub async fn deactivate_expired_addresses(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE temporary_email SET is_active = false \
+         WHERE is_active AND NOT is_honeypot AND expires_at <= now()",
     )
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+pub async fn fetch_mailbox_token_hash(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    Ok(sqlx::query_scalar::<_, Option<Vec<u8>>>(
+        "SELECT token_hash FROM temporary_email WHERE id = $1",
+    )
+    .bind(temporary_email_id)
+    .fetch_optional(pool)
+    .await?
+    .flatten())
+}
+
+/// Sets the token hash, but only if it is still `current` (`None` to issue
+/// the first token). Returns false when another request got there first.
+pub async fn replace_mailbox_token_hash(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    current: Option<&[u8]>,
+    new: &[u8],
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE temporary_email SET token_hash = $3 \
+         WHERE id = $1 AND token_hash IS NOT DISTINCT FROM $2",
+    )
+    .bind(temporary_email_id)
+    .bind(current)
+    .bind(new)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+/// Sets the token hash of every mailbox in `ids`, `token_hashes[i]` for
+/// `ids[i]`, whatever it was before.
+pub async fn replace_mailbox_token_hashes(
+    pool: &PgPool,
+    ids: &[Uuid],
+    token_hashes: &[Vec<u8>],
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE temporary_email t SET token_hash = n.hash \
+         FROM unnest($1::uuid[], $2::bytea[]) AS n (id, hash) WHERE t.id = n.id",
+    )
+    .bind(ids)
+    .bind(token_hashes)
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Deletes mail older than `retention` from public mailboxes.
+pub async fn delete_expired_public_messages(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "DELETE FROM received_email r USING temporary_email t \
+         WHERE r.temporary_email_id = t.id AND t.is_public \
+           AND r.received_at < now() - make_interval(secs => $1)",
+    )
+    .bind(retention.as_secs_f64())
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Reactivates an address that expired less than `grace` ago, giving it a
+/// fresh [`ADDRESS_TTL`](crate::ADDRESS_TTL). Returns `None` when the address
+/// is unknown, still live, or past the grace window.
+pub async fn reactivate_temporary_email(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    grace: Duration,
+) -> Result<Option<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "UPDATE temporary_email \
+         SET is_active = true, expires_at = now() + make_interval(secs => $2) \
+         WHERE temp_email_addr = $1 AND NOT is_honeypot \
+           AND (NOT is_active OR expires_at <= now()) \
+           AND expires_at > now() - make_interval(secs => $3) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
+    .bind(temp_email_addr)
+    .bind(ADDRESS_TTL.as_secs_f64())
+    .bind(grace.as_secs_f64())
+    .fetch_optional(pool)
+    .await
+}
+
+/// Pushes a live address's `expires_at` forward by `by`, but never past
+/// `created_at + max_lifetime`. Returns `None` when the address is unknown, a
+/// honeypot, expired, or already at that limit.
+pub async fn extend_temporary_email(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    by: Duration,
+    max_lifetime: Duration,
+) -> Result<Option<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "UPDATE temporary_email \
+         SET expires_at = LEAST(expires_at + make_interval(secs => $2), \
+                                created_at + make_interval(secs => $3)) \
+         WHERE temp_email_addr = $1 AND NOT is_honeypot \
+           AND is_active AND expires_at > now() \
+           AND expires_at < created_at + make_interval(secs => $3) \
+         RETURNING {TEMPORARY_EMAIL_COLUMNS}"
+    ))
     .bind(temp_email_addr)
+    .bind(by.as_secs_f64())
+    .bind(max_lifetime.as_secs_f64())
     .fetch_optional(pool)
     .await
 }
 
+/// Gives an unowned address to `user_id`. Returns false if it already has an
+/// owner.
+pub async fn claim_temporary_email(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("UPDATE temporary_email SET user_id = $2 WHERE id = $1 AND user_id IS NULL")
+            .bind(temporary_email_id)
+            .bind(user_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
+
+pub async fn list_temporary_emails_by_owner(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<TemporaryEmail>, sqlx::Error> {
+    sqlx::query_as::<_, TemporaryEmail>(&format!(
+        "SELECT {TEMPORARY_EMAIL_COLUMNS} \
+         FROM temporary_email WHERE user_id = $1 ORDER BY created_at DESC"
+    ))
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn list_taken_addresses(
+    pool: &PgPool,
+    temp_email_addrs: &[String],
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(
+        "SELECT temp_email_addr FROM temporary_email WHERE temp_email_addr = ANY($1) \
+         UNION ALL \
+         SELECT alias_addr FROM address_alias WHERE alias_addr = ANY($1)",
+    )
+    .bind(temp_email_addrs)
+    .fetch_all(pool)
+    .await
+}
+
+#[derive(sqlx::FromRow)]
+struct ReceivedEmailRow {
+    id: Uuid,
+    temporary_email_id: Uuid,
+    from_addr: Option<String>,
+    to_addr: Option<String>,
+    subject: Option<String>,
+    body_text: Option<String>,
+    body_html: Option<Vec<u8>>,
+    is_compressed: bool,
+    received_at: DateTime<Utc>,
+    redacted_at: Option<DateTime<Utc>>,
+    is_bounce: bool,
+    peer_ip: Option<String>,
+    spf_result: Option<String>,
+    dmarc_result: Option<String>,
+    tags: Vec<String>,
+    country: Option<String>,
+    plus_tag: Option<String>,
+    original_recipient: Option<String>,
+    envelope_recipients: Vec<String>,
+    header_recipients: Vec<String>,
+    is_read: bool,
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl ReceivedEmailRow {
+    fn into_model(self) -> Result<ReceivedEmail, sqlx::Error> {
+        let body_html = self
+            .body_html
+            .map(|b| compression::decode(b, self.is_compressed))
+            .transpose()?
+            .map(|b| String::from_utf8_lossy(&b).into_owned());
+        let spf_result = self
+            .spf_result
+            .map(SpfResult::try_from)
+            .transpose()
+            .map_err(|e| sqlx::Error::Decode(e.into()))?;
+        let dmarc_result = self
+            .dmarc_result
+            .map(DmarcResult::try_from)
+            .transpose()
+            .map_err(|e| sqlx::Error::Decode(e.into()))?;
+        Ok(ReceivedEmail {
+            id: self.id,
+            temporary_email_id: self.temporary_email_id,
+            from_addr: self.from_addr,
+            to_addr: self.to_addr,
+            subject: self.subject,
+            body_text: self.body_text,
+            body_html,
+            received_at: self.received_at,
+            redacted_at: self.redacted_at,
+            is_bounce: self.is_bounce,
+            peer_ip: self.peer_ip,
+            spf_result,
+            dmarc_result,
+            tags: self.tags,
+            country: self.country,
+            plus_tag: self.plus_tag,
+            original_recipient: self.original_recipient,
+            envelope_recipients: self.envelope_recipients,
+            header_recipients: self.header_recipients,
+            is_read: self.is_read,
+            deleted_at: self.deleted_at,
+        })
+    }
+}
+
+const RECEIVED_EMAIL_COLUMNS: &str = "id, temporary_email_id, from_addr, to_addr, subject, \
+     body_text, body_html, is_compressed, received_at, redacted_at, is_bounce, \
+     peer_ip, spf_result, dmarc_result, tags, country, plus_tag, original_recipient, \
+     envelope_recipients, header_recipients, is_read, deleted_at";
+
+/// `is_bounce` restricts the result to bounces (`true`) or to regular mail
+/// (`false`); `None` returns both. `plus_tag` keeps only mail sent with that
+/// `+tag`.
 pub async fn list_received_emails(
     pool: &PgPool,
     temporary_email_id: Uuid,
     since: Option<DateTime<Utc>>,
+    is_bounce: Option<bool>,
+    plus_tag: Option<&str>,
 ) -> Result<Vec<ReceivedEmail>, sqlx::Error> {
-    sqlx::query_as::<_, ReceivedEmail>(
-        "SELECT id, temporary_email_id, from_addr, to_addr, subject, body_text, received_at \
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "SELECT {RECEIVED_EMAIL_COLUMNS} \
          FROM received_email \
-         WHERE temporary_email_id = $1 AND ($2::timestamptz IS NULL OR received_at > $2) \
-         ORDER BY received_at ASC",
-    )
+         WHERE temporary_email_id = $1 AND deleted_at IS NULL \
+           AND ($2::timestamptz IS NULL OR received_at > $2) \
+           AND ($3::boolean IS NULL OR is_bounce = $3) \
+           AND ($4::text IS NULL OR plus_tag = $4) \
+         ORDER BY received_at ASC"
+    ))
     .bind(temporary_email_id)
     .bind(since)
+    .bind(is_bounce)
+    .bind(plus_tag)
     .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(ReceivedEmailRow::into_model)
+    .collect()
+}
+
+/// Unread messages in the mailbox received after `since`, if given.
+pub async fn count_unread_emails(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    since: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT COUNT(*) FROM received_email \
+         WHERE temporary_email_id = $1 AND NOT is_read AND deleted_at IS NULL \
+           AND ($2::timestamptz IS NULL OR received_at > $2)",
+    )
+    .bind(temporary_email_id)
+    .bind(since)
+    .fetch_one(pool)
     .await
 }
 
-pub async fn insert_received_email(
+/// Messages in the mailbox whose subject or plain-text body match `query`,
+/// best match first. `query` takes web-search syntax: words (stemmed, so
+/// `password` finds `passwords`), `"quoted phrases"`, `or` and `-excluded`.
+pub async fn search_emails_by_address(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    query: &str,
+    since: Option<DateTime<Utc>>,
+    limit: i64,
+) -> Result<Vec<ReceivedEmail>, sqlx::Error> {
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "SELECT {RECEIVED_EMAIL_COLUMNS} \
+         FROM received_email, websearch_to_tsquery('english', $2) AS query \
+         WHERE temporary_email_id = \
+               (SELECT id FROM temporary_email WHERE temp_email_addr = $1) \
+           AND search_vector @@ query AND deleted_at IS NULL \
+           AND ($3::timestamptz IS NULL OR received_at > $3) \
+         ORDER BY ts_rank(search_vector, query) DESC, received_at DESC \
+         LIMIT $4"
+    ))
+    .bind(temp_email_addr)
+    .bind(query)
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(ReceivedEmailRow::into_model)
+    .collect()
+}
+
+#[derive(sqlx::FromRow)]
+struct ImapMessageRow {
+    imap_uid: i64,
+    #[sqlx(flatten)]
+    email: ReceivedEmailRow,
+}
+
+/// Messages in the mailbox with their IMAP UIDs, in UID order. UIDs come from
+/// one sequence shared by all mailboxes, so they only ever grow.
+pub async fn list_imap_messages(
     pool: &PgPool,
     temporary_email_id: Uuid,
-    from_addr: Option<&str>,
-    to_addr: Option<&str>,
-    subject: Option<&str>,
-    body_text: Option<&str>,
-) -> Result<ReceivedEmail, sqlx::Error> {
-    sqlx::query_as::<_, ReceivedEmail>(
-        "INSERT INTO received_email (temporary_email_id, from_addr, to_addr, subject, body_text) \
-         VALUES ($1, $2, $3, $4, $5) \
-         RETURNING id, temporary_email_id, from_addr, to_addr, subject, body_text, received_at",
+    since: Option<DateTime<Utc>>,
+) -> Result<Vec<(i64, ReceivedEmail)>, sqlx::Error> {
+    sqlx::query_as::<_, ImapMessageRow>(&format!(
+        "SELECT imap_uid, {RECEIVED_EMAIL_COLUMNS} \
+         FROM received_email \
+         WHERE temporary_email_id = $1 AND deleted_at IS NULL \
+           AND ($2::timestamptz IS NULL OR received_at > $2) \
+         ORDER BY imap_uid ASC"
+    ))
+    .bind(temporary_email_id)
+    .bind(since)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Ok((row.imap_uid, row.email.into_model()?)))
+    .collect()
+}
+
+/// Moves messages of one mailbox to its trash, or all of them when `ids` is
+/// `None`; ids of other mailboxes are ignored. Trashed messages are hidden
+/// everywhere but [`list_trashed_emails`] until restored or purged by
+/// [`purge_trashed_emails`]. Returns how many were trashed.
+pub async fn trash_received_emails(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    ids: Option<&[Uuid]>,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE received_email SET deleted_at = now() \
+         WHERE temporary_email_id = $1 AND deleted_at IS NULL \
+           AND ($2::uuid[] IS NULL OR id = ANY($2))",
     )
     .bind(temporary_email_id)
-    .bind(from_addr)
-    .bind(to_addr)
-    .bind(subject)
-    .bind(body_text)
+    .bind(ids)
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// The mailbox's trash, most recently deleted first.
+pub async fn list_trashed_emails(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<ReceivedEmail>, sqlx::Error> {
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "SELECT {RECEIVED_EMAIL_COLUMNS} \
+         FROM received_email \
+         WHERE temporary_email_id = $1 AND deleted_at IS NOT NULL \
+         ORDER BY deleted_at DESC, received_at DESC"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(ReceivedEmailRow::into_model)
+    .collect()
+}
+
+/// Takes messages of one mailbox out of its trash, or all of them when
+/// `ids` is `None`. Restored messages get new IMAP UIDs, since clients may
+/// have seen the old ones expunged. Returns how many were restored.
+pub async fn restore_received_emails(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    ids: Option<&[Uuid]>,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE received_email SET deleted_at = NULL, imap_uid = DEFAULT \
+         WHERE temporary_email_id = $1 AND deleted_at IS NOT NULL \
+           AND ($2::uuid[] IS NULL OR id = ANY($2))",
+    )
+    .bind(temporary_email_id)
+    .bind(ids)
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Deletes messages that have been in the trash longer than `grace`, with
+/// their attachments and shares.
+pub async fn purge_trashed_emails(pool: &PgPool, grace: Duration) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(
+        "DELETE FROM received_email \
+         WHERE deleted_at < now() - make_interval(secs => $1)",
+    )
+    .bind(grace.as_secs_f64())
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// Moves every message of `source_id` into `target_id` and deactivates the
+/// source, in one transaction. Moved messages get new IMAP UIDs, since a
+/// UID must not be lower than those the target already handed out.
+/// Returns how many were moved.
+pub async fn merge_temporary_emails(
+    pool: &PgPool,
+    source_id: Uuid,
+    target_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    // Locked in a fixed order so merges in opposite directions cannot
+    // deadlock; deliveries to either wait until the merge is done.
+    sqlx::query("SELECT id FROM temporary_email WHERE id = ANY($1) ORDER BY id FOR UPDATE")
+        .bind(&[source_id, target_id][..])
+        .execute(&mut *tx)
+        .await?;
+    let moved = sqlx::query(
+        "UPDATE received_email SET temporary_email_id = $2, imap_uid = DEFAULT \
+         WHERE temporary_email_id = $1",
+    )
+    .bind(source_id)
+    .bind(target_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query("UPDATE temporary_email SET is_active = false WHERE id = $1")
+        .bind(source_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
+/// `LISTEN` channel announcing every stored message; see [`new_mail_payload`].
+pub const NEW_MAIL_CHANNEL: &str = "new_mail";
+
+/// Payload of a [`NEW_MAIL_CHANNEL`] notification:
+/// `<temporary_email_id>:<received_email_id>`.
+pub fn new_mail_payload(temporary_email_id: Uuid, id: Uuid) -> String {
+    format!("{temporary_email_id}:{id}")
+}
+
+/// Inverse of [`new_mail_payload`].
+pub fn parse_new_mail_payload(payload: &str) -> Option<(Uuid, Uuid)> {
+    let (temp, id) = payload.split_once(':')?;
+    Some((temp.parse().ok()?, id.parse().ok()?))
+}
+
+/// `LISTEN` channel naming the addresses and aliases a statement deleted,
+/// (de)activated, rescheduled or changed the expiry of, so caches of address
+/// lookups can drop them. Sent by database triggers once per statement,
+/// whatever changed the rows; see [`parse_address_changed_payload`].
+pub const ADDRESS_CHANGED_CHANNEL: &str = "address_changed";
+
+/// Addresses named by an [`ADDRESS_CHANGED_CHANNEL`] payload, or `None` when
+/// too many changed at once to list and every cached lookup should go.
+pub fn parse_address_changed_payload(payload: &str) -> Option<Vec<&str>> {
+    (payload != "*").then(|| payload.lines().collect())
+}
+
+/// Stores a message and, on commit, notifies [`NEW_MAIL_CHANNEL`] so other
+/// processes learn about it without polling.
+pub async fn insert_received_email(
+    pool: &PgPool,
+    email: &NewReceivedEmail<'_>,
+    compression: BodyCompression,
+) -> Result<ReceivedEmail, sqlx::Error> {
+    let stored = compression::encode(
+        email.body_html.map(str::as_bytes),
+        email.raw_email,
+        compression,
+    )?;
+    let mut tx = pool.begin().await?;
+    let row = insert_received_row(&mut tx, email, &stored).await?;
+    tx.commit().await?;
+    row.into_model()
+}
+
+/// Delivers one message to several mailboxes in a single transaction: a row
+/// per `(temporary_email_id, to_addr, plus_tag, original_recipient,
+/// envelope_recipients)` in `recipients`, replacing those fields of `email`,
+/// each with its own copy of `attachments` and its own
+/// [`NEW_MAIL_CHANNEL`] notification. Either every recipient gets the
+/// message or none does. Bodies are encoded once.
+pub async fn insert_received_email_for_recipients(
+    pool: &PgPool,
+    email: &NewReceivedEmail<'_>,
+    recipients: &[(Uuid, &str, Option<&str>, Option<&str>, &[String])],
+    attachments: &[NewAttachment<'_>],
+    compression: BodyCompression,
+) -> Result<Vec<ReceivedEmail>, sqlx::Error> {
+    let stored = compression::encode(
+        email.body_html.map(str::as_bytes),
+        email.raw_email,
+        compression,
+    )?;
+    let mut tx = pool.begin().await?;
+    let mut rows = Vec::with_capacity(recipients.len());
+    for &(temporary_email_id, to_addr, plus_tag, original_recipient, envelope_recipients) in
+        recipients
+    {
+        let email = NewReceivedEmail {
+            temporary_email_id,
+            to_addr: Some(to_addr),
+            plus_tag,
+            original_recipient,
+            envelope_recipients,
+            ..*email
+        };
+        let row = insert_received_row(&mut tx, &email, &stored).await?;
+        insert_attachment_rows(&mut tx, row.id, attachments).await?;
+        rows.push(row);
+    }
+    tx.commit().await?;
+    rows.into_iter().map(ReceivedEmailRow::into_model).collect()
+}
+
+async fn insert_received_row(
+    conn: &mut PgConnection,
+    email: &NewReceivedEmail<'_>,
+    stored: &StoredBodies,
+) -> Result<ReceivedEmailRow, sqlx::Error> {
+    let envelope_recipients = match (email.envelope_recipients, email.to_addr) {
+        ([], Some(to_addr)) => vec![to_addr.to_owned()],
+        (rcpts, _) => rcpts.to_vec(),
+    };
+    let row = sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "INSERT INTO received_email \
+         (temporary_email_id, from_addr, to_addr, subject, body_text, body_html, raw_email, is_compressed, is_bounce, headers, peer_ip, spf_result, dmarc_result, tags, country, plus_tag, original_recipient, envelope_recipients, header_recipients, accepted_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) \
+         RETURNING {RECEIVED_EMAIL_COLUMNS}"
+    ))
+    .bind(email.temporary_email_id)
+    .bind(email.from_addr)
+    .bind(email.to_addr)
+    .bind(email.subject)
+    .bind(email.body_text)
+    .bind(stored.body_html.as_deref())
+    .bind(stored.raw_email.as_deref())
+    .bind(stored.is_compressed)
+    .bind(email.is_bounce)
+    .bind(headers_json(email.headers))
+    .bind(email.peer_ip)
+    .bind(email.spf_result.map(SpfResult::as_str))
+    .bind(email.dmarc_result.map(DmarcResult::as_str))
+    .bind(email.tags)
+    .bind(email.country)
+    .bind(email.plus_tag)
+    .bind(email.original_recipient)
+    .bind(&envelope_recipients)
+    .bind(email.header_recipients)
+    .bind(email.accepted_at)
+    .fetch_one(&mut *conn)
+    .await?;
+    insert_dkim_rows(conn, row.id, email.dkim).await?;
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(NEW_MAIL_CHANNEL)
+        .bind(new_mail_payload(row.temporary_email_id, row.id))
+        .execute(&mut *conn)
+        .await?;
+    queue_webhook_deliveries(conn, row.temporary_email_id, row.id).await?;
+    queue_forward_deliveries(conn, row.temporary_email_id, row.id).await?;
+    Ok(row)
+}
+
+/// A message by id, only if it was delivered to `temporary_email_id` and is
+/// not in its trash.
+pub async fn find_received_email(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    id: Uuid,
+) -> Result<Option<ReceivedEmail>, sqlx::Error> {
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "SELECT {RECEIVED_EMAIL_COLUMNS} FROM received_email \
+         WHERE id = $1 AND temporary_email_id = $2 AND deleted_at IS NULL"
+    ))
+    .bind(id)
+    .bind(temporary_email_id)
+    .fetch_optional(pool)
+    .await?
+    .map(ReceivedEmailRow::into_model)
+    .transpose()
+}
+
+pub async fn find_received_email_by_id(
+    pool: &PgPool,
+    id: Uuid,
+) -> Result<Option<ReceivedEmail>, sqlx::Error> {
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "SELECT {RECEIVED_EMAIL_COLUMNS} FROM received_email WHERE id = $1"
+    ))
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .map(ReceivedEmailRow::into_model)
+    .transpose()
+}
+
+pub async fn insert_email_share(
+    pool: &PgPool,
+    received_email_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> Result<EmailShare, sqlx::Error> {
+    sqlx::query_as::<_, EmailShare>(
+        "INSERT INTO email_share (received_email_id, expires_at) VALUES ($1, $2) \
+         RETURNING id, received_email_id, expires_at, revoked_at, created_at",
+    )
+    .bind(received_email_id)
+    .bind(expires_at)
     .fetch_one(pool)
     .await
 }
 
-pub async fn purge_all_data(pool: &PgPool) -> Result<PurgeResult, sqlx::Error> {
-    let emails = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM received_email")
-        .fetch_one(pool)
+pub async fn find_email_share(pool: &PgPool, id: Uuid) -> Result<Option<EmailShare>, sqlx::Error> {
+    sqlx::query_as::<_, EmailShare>(
+        "SELECT id, received_email_id, expires_at, revoked_at, created_at \
+         FROM email_share WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Returns false when no unrevoked share `id` exists for the message.
+pub async fn revoke_email_share(
+    pool: &PgPool,
+    id: Uuid,
+    received_email_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "UPDATE email_share SET revoked_at = now() \
+         WHERE id = $1 AND received_email_id = $2 AND revoked_at IS NULL",
+    )
+    .bind(id)
+    .bind(received_email_id)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+pub const REDACTION_NOTICE: &str = "[This message was redacted by the operator.]";
+
+/// Marks a message of the mailbox read or unread; `None` if it is not there.
+pub async fn set_received_email_read(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    id: Uuid,
+    is_read: bool,
+) -> Result<Option<ReceivedEmail>, sqlx::Error> {
+    sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "UPDATE received_email SET is_read = $3 \
+         WHERE id = $1 AND temporary_email_id = $2 AND deleted_at IS NULL \
+         RETURNING {RECEIVED_EMAIL_COLUMNS}"
+    ))
+    .bind(id)
+    .bind(temporary_email_id)
+    .bind(is_read)
+    .fetch_optional(pool)
+    .await?
+    .map(ReceivedEmailRow::into_model)
+    .transpose()
+}
+
+/// Replaces a message's bodies and raw source with [`REDACTION_NOTICE`] and
+/// drops its headers and attachments, keeping sender, recipient, subject and
+/// timestamps for the audit trail.
+pub async fn redact_received_email(
+    pool: &PgPool,
+    id: Uuid,
+    reason: Option<&str>,
+) -> Result<Option<ReceivedEmail>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM email_attachment WHERE received_email_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM email_preview WHERE received_email_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
         .await?;
-    let inboxes = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM temporary_email")
-        .fetch_one(pool)
+    let redacted = sqlx::query_as::<_, ReceivedEmailRow>(&format!(
+        "UPDATE received_email \
+         SET body_text = $2, body_html = NULL, raw_email = NULL, headers = NULL, \
+             is_compressed = false, \
+             redacted_at = now(), redaction_reason = $3 \
+         WHERE id = $1 \
+         RETURNING {RECEIVED_EMAIL_COLUMNS}"
+    ))
+    .bind(id)
+    .bind(REDACTION_NOTICE)
+    .bind(reason)
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    redacted.map(ReceivedEmailRow::into_model).transpose()
+}
+
+/// The message exactly as it arrived over SMTP, if it was kept.
+pub async fn fetch_raw_email(pool: &PgPool, id: Uuid) -> Result<Option<Vec<u8>>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (Option<Vec<u8>>, bool)>(
+        "SELECT raw_email, is_compressed FROM received_email WHERE id = $1",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    match row {
+        Some((Some(raw), is_compressed)) => compression::decode(raw, is_compressed).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Header fields grouped by lowercased name, so repeated ones such as
+/// `Received` keep every value in order. `None` (stored as NULL) when there
+/// are none.
+fn headers_json(headers: &[(String, String)]) -> Option<Value> {
+    if headers.is_empty() {
+        return None;
+    }
+    let mut fields = Map::new();
+    for (name, value) in headers {
+        let values = fields
+            .entry(name.to_ascii_lowercase())
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(values) = values {
+            values.push(Value::String(value.clone()));
+        }
+    }
+    Some(Value::Object(fields))
+}
+
+/// The message's header fields as stored by [`insert_received_email`];
+/// `None` for unknown ids, older messages and redacted ones.
+pub async fn fetch_email_headers(pool: &PgPool, id: Uuid) -> Result<Option<Value>, sqlx::Error> {
+    let headers: Option<Option<Value>> =
+        sqlx::query_scalar("SELECT headers FROM received_email WHERE id = $1")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(headers.flatten())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CompressionBackfill {
+    pub rows_scanned: u64,
+    pub rows_compressed: u64,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+/// Rewrites uncompressed bodies using `compression`, `batch_size` rows per
+/// transaction. Rows that would not shrink are left as they are.
+pub async fn compress_stored_bodies(
+    pool: &PgPool,
+    compression: BodyCompression,
+    batch_size: i64,
+) -> Result<CompressionBackfill, sqlx::Error> {
+    let mut result = CompressionBackfill::default();
+    if compression == BodyCompression::None {
+        return Ok(result);
+    }
+
+    let mut after = Uuid::nil();
+    loop {
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query_as::<_, (Uuid, Option<Vec<u8>>, Option<Vec<u8>>)>(
+            "SELECT id, body_html, raw_email FROM received_email \
+             WHERE NOT is_compressed AND (body_html IS NOT NULL OR raw_email IS NOT NULL) \
+               AND id > $1 \
+             ORDER BY id LIMIT $2 FOR UPDATE SKIP LOCKED",
+        )
+        .bind(after)
+        .bind(batch_size)
+        .fetch_all(&mut *tx)
         .await?;
+        let Some((last, _, _)) = rows.last() else {
+            break;
+        };
+        after = *last;
+
+        for (id, body_html, raw_email) in rows {
+            let before =
+                body_html.as_ref().map_or(0, Vec::len) + raw_email.as_ref().map_or(0, Vec::len);
+            let stored =
+                compression::encode(body_html.as_deref(), raw_email.as_deref(), compression)?;
+            result.rows_scanned += 1;
+            result.bytes_before += before as u64;
+            result.bytes_after += stored.len() as u64;
+            if !stored.is_compressed {
+                continue;
+            }
+            sqlx::query(
+                "UPDATE received_email SET body_html = $2, raw_email = $3, is_compressed = true \
+                 WHERE id = $1",
+            )
+            .bind(id)
+            .bind(stored.body_html)
+            .bind(stored.raw_email)
+            .execute(&mut *tx)
+            .await?;
+            result.rows_compressed += 1;
+        }
+        tx.commit().await?;
+    }
+
+    Ok(result)
+}
+
+pub async fn list_blocked_local_parts(pool: &PgPool) -> Result<Vec<BlockedLocalPart>, sqlx::Error> {
+    sqlx::query_as::<_, BlockedLocalPart>(
+        "SELECT id, pattern, is_regex, reason, created_at FROM blocked_local_part \
+         ORDER BY created_at ASC",
+    )
+    .fetch_all(pool)
+    .await
+}
 
-    sqlx::query("TRUNCATE received_email, temporary_email")
+pub async fn insert_blocked_local_part(
+    pool: &PgPool,
+    pattern: &str,
+    is_regex: bool,
+    reason: Option<&str>,
+) -> Result<BlockedLocalPart, sqlx::Error> {
+    sqlx::query_as::<_, BlockedLocalPart>(
+        "INSERT INTO blocked_local_part (pattern, is_regex, reason) VALUES ($1, $2, $3) \
+         RETURNING id, pattern, is_regex, reason, created_at",
+    )
+    .bind(pattern)
+    .bind(is_regex)
+    .bind(reason)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn delete_blocked_local_part(pool: &PgPool, id: Uuid) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query("DELETE FROM blocked_local_part WHERE id = $1")
+        .bind(id)
         .execute(pool)
         .await?;
+    Ok(res.rows_affected() > 0)
+}
+
+pub async fn record_honeypot_hit(
+    pool: &PgPool,
+    sender_domain: &str,
+    ip: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO sender_reputation (sender_domain, honeypot_hits, last_ip) VALUES ($1, 1, $2) \
+         ON CONFLICT (sender_domain) DO UPDATE SET \
+             honeypot_hits = sender_reputation.honeypot_hits + 1, \
+             last_ip = COALESCE(EXCLUDED.last_ip, sender_reputation.last_ip), \
+             last_seen = now()",
+    )
+    .bind(sender_domain)
+    .bind(ip)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
 
-    Ok(PurgeResult {
-        emails_deleted: emails,
-        inboxes_deleted: inboxes,
-    })
+pub async fn list_sender_reputation(
+    pool: &PgPool,
+    limit: i64,
+) -> Result<Vec<SenderReputation>, sqlx::Error> {
+    sqlx::query_as::<_, SenderReputation>(
+        "SELECT sender_domain, honeypot_hits, last_ip, first_seen, last_seen \
+         FROM sender_reputation ORDER BY honeypot_hits DESC, last_seen DESC LIMIT $1",
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
 }
 
-pub struct PurgeResult {
-    pub emails_deleted: i64,
-    pub inboxes_deleted: i64,
+pub async fn insert_session(
+    pool: &PgPool,
+    user_id: Option<Uuid>,
+    refresh_hash: &[u8],
+    refresh_expires_at: DateTime<Utc>,
+) -> Result<Session, sqlx::Error> {
+    sqlx::query_as::<_, Session>(
+        "INSERT INTO session (user_id, refresh_hash, refresh_expires_at) VALUES ($1, $2, $3) \
+         RETURNING id, user_id, refresh_expires_at, created_at",
+    )
+    .bind(user_id)
+    .bind(refresh_hash)
+    .bind(refresh_expires_at)
+    .fetch_one(pool)
+    .await
+}
+
+/// Swaps an unexpired refresh token for a new one. Returns `None` when
+/// `current_hash` is unknown, expired or was already used.
+pub async fn rotate_session_refresh(
+    pool: &PgPool,
+    current_hash: &[u8],
+    new_hash: &[u8],
+    refresh_expires_at: DateTime<Utc>,
+) -> Result<Option<Session>, sqlx::Error> {
+    sqlx::query_as::<_, Session>(
+        "UPDATE session SET refresh_hash = $2, refresh_expires_at = $3 \
+         WHERE refresh_hash = $1 AND refresh_expires_at > now() \
+         RETURNING id, user_id, refresh_expires_at, created_at",
+    )
+    .bind(current_hash)
+    .bind(new_hash)
+    .bind(refresh_expires_at)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_expired_sessions(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM session WHERE refresh_expires_at <= now()")
+            .execute(pool)
+            .await?
+            .rows_affected(),
+    )
+}
+
+/// Finds or creates the account for an OIDC identity and records the login.
+pub async fn upsert_user(
+    pool: &PgPool,
+    issuer: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> Result<User, sqlx::Error> {
+    sqlx::query_as::<_, User>(
+        "INSERT INTO users (issuer, subject, email) VALUES ($1, $2, $3) \
+         ON CONFLICT (issuer, subject) \
+         DO UPDATE SET email = COALESCE(EXCLUDED.email, users.email), last_login_at = now() \
+         RETURNING id, issuer, subject, email, created_at, last_login_at",
+    )
+    .bind(issuer)
+    .bind(subject)
+    .bind(email)
+    .fetch_one(pool)
+    .await
 }
@@ -0,0 +1,102 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A sender whose mail is dropped: for one mailbox, or for all of them when
+/// `temporary_email_id` is `None`. An address matches only itself, a domain
+/// also its subdomains.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct BlockedSender {
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Option<Uuid>,
+    pub sender: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Which blocks a message's senders ran into.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SenderBlocks {
+    /// A global block: the message is refused for everyone.
+    pub global: bool,
+    /// Mailboxes, among those asked about, that blocked a sender.
+    pub mailboxes: Vec<Uuid>,
+}
+
+const BLOCKED_SENDER_COLUMNS: &str = "temporary_email_id, sender, created_at";
+
+/// `None` when `sender` is already blocked there.
+pub async fn insert_blocked_sender(
+    pool: &PgPool,
+    temporary_email_id: Option<Uuid>,
+    sender: &str,
+) -> Result<Option<BlockedSender>, sqlx::Error> {
+    sqlx::query_as::<_, BlockedSender>(&format!(
+        "INSERT INTO blocked_sender (temporary_email_id, sender) VALUES ($1, lower($2)) \
+         ON CONFLICT DO NOTHING \
+         RETURNING {BLOCKED_SENDER_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(sender)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Blocks of one mailbox, or the global ones, oldest first.
+pub async fn list_blocked_senders(
+    pool: &PgPool,
+    temporary_email_id: Option<Uuid>,
+) -> Result<Vec<BlockedSender>, sqlx::Error> {
+    sqlx::query_as::<_, BlockedSender>(&format!(
+        "SELECT {BLOCKED_SENDER_COLUMNS} FROM blocked_sender \
+         WHERE temporary_email_id IS NOT DISTINCT FROM $1 ORDER BY created_at, sender"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns false when `sender` was not blocked there.
+pub async fn delete_blocked_sender(
+    pool: &PgPool,
+    temporary_email_id: Option<Uuid>,
+    sender: &str,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query(
+        "DELETE FROM blocked_sender \
+         WHERE temporary_email_id IS NOT DISTINCT FROM $1 AND sender = lower($2)",
+    )
+    .bind(temporary_email_id)
+    .bind(sender)
+    .execute(pool)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+/// Checks the addresses a message came from (envelope sender, `From:`)
+/// against the global blocks and those of `mailbox_ids`.
+pub async fn find_sender_blocks(
+    pool: &PgPool,
+    mailbox_ids: &[Uuid],
+    senders: &[&str],
+) -> Result<SenderBlocks, sqlx::Error> {
+    let matched: Vec<Option<Uuid>> = sqlx::query_scalar(
+        "SELECT DISTINCT b.temporary_email_id \
+         FROM blocked_sender b, unnest($2::text[]) AS s (addr) \
+         WHERE (b.temporary_email_id IS NULL OR b.temporary_email_id = ANY($1)) \
+           AND (b.sender = lower(s.addr) \
+                OR (position('@' in b.sender) = 0 \
+                    AND right(lower(s.addr), length(b.sender) + 1) \
+                        IN ('@' || b.sender, '.' || b.sender)))",
+    )
+    .bind(mailbox_ids)
+    .bind(senders)
+    .fetch_all(pool)
+    .await?;
+    Ok(SenderBlocks {
+        global: matched.contains(&None),
+        mailboxes: matched.into_iter().flatten().collect(),
+    })
+}
@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+
+/// An SMTP AUTH login; the password hash is never loaded into this.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SmtpUser {
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Creates the user or replaces its password.
+pub async fn upsert_smtp_user(
+    pool: &PgPool,
+    username: &str,
+    password_hash: &str,
+) -> Result<SmtpUser, sqlx::Error> {
+    sqlx::query_as::<_, SmtpUser>(
+        "INSERT INTO smtp_user (username, password_hash) VALUES ($1, $2) \
+         ON CONFLICT (username) DO UPDATE SET password_hash = EXCLUDED.password_hash \
+         RETURNING username, created_at",
+    )
+    .bind(username)
+    .bind(password_hash)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn find_smtp_user_password_hash(
+    pool: &PgPool,
+    username: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar("SELECT password_hash FROM smtp_user WHERE username = $1")
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn list_smtp_users(pool: &PgPool) -> Result<Vec<SmtpUser>, sqlx::Error> {
+    sqlx::query_as::<_, SmtpUser>("SELECT username, created_at FROM smtp_user ORDER BY username")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn delete_smtp_user(pool: &PgPool, username: &str) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query("DELETE FROM smtp_user WHERE username = $1")
+        .bind(username)
+        .execute(pool)
+        .await?
+        .rows_affected()
+        > 0)
+}
@@ -0,0 +1,173 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgArguments;
+use sqlx::{FromRow, PgPool, Postgres};
+use std::fmt;
+use std::str::FromStr;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A message without its bodies, for listing a mailbox.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct EmailSummary {
+    pub id: Uuid,
+    pub from_addr: Option<String>,
+    pub to_addr: Option<String>,
+    pub subject: Option<String>,
+    pub received_at: DateTime<Utc>,
+    pub is_bounce: bool,
+    pub plus_tag: Option<String>,
+    pub is_read: bool,
+}
+
+/// Where a page of summaries ends: the last one's `received_at` and `id`.
+/// Written `<received_at>,<id>`, with the time to the microsecond so it
+/// round-trips through Postgres exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SummaryCursor {
+    pub received_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl SummaryCursor {
+    pub fn of(summary: &EmailSummary) -> Self {
+        Self {
+            received_at: summary.received_at,
+            id: summary.id,
+        }
+    }
+}
+
+impl fmt::Display for SummaryCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self
+            .received_at
+            .to_rfc3339_opts(SecondsFormat::Micros, true);
+        write!(f, "{at},{}", self.id)
+    }
+}
+
+impl FromStr for SummaryCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (at, id) = s
+            .rsplit_once(',')
+            .ok_or_else(|| format!("expected <received_at>,<id>, got {s:?}"))?;
+        let received_at = DateTime::parse_from_rfc3339(at)
+            .map_err(|e| format!("bad received_at {at:?}: {e}"))?
+            .with_timezone(&Utc);
+        let id = id.parse().map_err(|e| format!("bad id {id:?}: {e}"))?;
+        Ok(Self { received_at, id })
+    }
+}
+
+/// One page of a mailbox, newest first.
+#[derive(Debug, Clone)]
+pub struct SummaryPage {
+    pub items: Vec<EmailSummary>,
+    /// Passed back as `after` for the next page; `None` on the last one.
+    pub next_cursor: Option<SummaryCursor>,
+    /// Messages in the mailbox matching the filter, on every page.
+    pub total: i64,
+}
+
+/// Which messages to list; the default keeps them all.
+#[derive(Debug, Clone, Default)]
+pub struct SummaryFilter {
+    /// Sender address, compared case-insensitively.
+    pub from: Option<String>,
+    /// Case-insensitive substring of the subject.
+    pub subject_contains: Option<String>,
+    /// Received after.
+    pub since: Option<DateTime<Utc>>,
+    /// Received before.
+    pub until: Option<DateTime<Utc>>,
+    pub unread_only: bool,
+}
+
+/// `$1` is the address, `$2`..`$6` the filter, as bound by [`bind_filter`].
+const SUMMARY_WHERE: &str = "temporary_email_id = \
+           (SELECT id FROM temporary_email WHERE temp_email_addr = $1) \
+       AND deleted_at IS NULL \
+       AND ($2::timestamptz IS NULL OR received_at > $2) \
+       AND ($3::timestamptz IS NULL OR received_at < $3) \
+       AND ($4::text IS NULL OR lower(from_addr) = lower($4)) \
+       AND ($5::text IS NULL OR subject ILIKE $5) \
+       AND NOT ($6 AND is_read)";
+
+fn bind_filter<'q, O>(
+    query: sqlx::query::QueryAs<'q, Postgres, O, PgArguments>,
+    temp_email_addr: &'q str,
+    filter: &'q SummaryFilter,
+) -> sqlx::query::QueryAs<'q, Postgres, O, PgArguments> {
+    query
+        .bind(temp_email_addr)
+        .bind(filter.since)
+        .bind(filter.until)
+        .bind(filter.from.as_deref())
+        .bind(filter.subject_contains.as_deref().map(contains_pattern))
+        .bind(filter.unread_only)
+}
+
+/// An `ILIKE` pattern matching `s` anywhere, with its wildcards escaped.
+fn contains_pattern(s: &str) -> String {
+    let mut pattern = String::with_capacity(s.len() + 2);
+    pattern.push('%');
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Up to `limit` summaries of the mailbox's messages matching `filter`,
+/// newest first, starting past `after`. Keyset pagination, so a page costs
+/// the same however deep it is and mail arriving meanwhile does not shift
+/// later pages.
+pub async fn list_email_summaries_by_address(
+    pool: &PgPool,
+    temp_email_addr: &str,
+    filter: &SummaryFilter,
+    after: Option<SummaryCursor>,
+    limit: i64,
+) -> Result<SummaryPage, sqlx::Error> {
+    let (total,): (i64,) = bind_filter(
+        sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM received_email WHERE {SUMMARY_WHERE}"
+        )),
+        temp_email_addr,
+        filter,
+    )
+    .fetch_one(pool)
+    .await?;
+    let mut items = bind_filter(
+        sqlx::query_as::<_, EmailSummary>(&format!(
+            "SELECT id, from_addr, to_addr, subject, received_at, is_bounce, plus_tag, is_read \
+             FROM received_email \
+             WHERE {SUMMARY_WHERE} \
+               AND ($7::timestamptz IS NULL OR (received_at, id) < ($7, $8)) \
+             ORDER BY received_at DESC, id DESC \
+             LIMIT $9"
+        )),
+        temp_email_addr,
+        filter,
+    )
+    .bind(after.map(|c| c.received_at))
+    .bind(after.map(|c| c.id))
+    .bind(limit + 1)
+    .fetch_all(pool)
+    .await?;
+
+    let more = items.len() as i64 > limit;
+    items.truncate(limit.max(0) as usize);
+    let next_cursor = items.last().filter(|_| more).map(SummaryCursor::of);
+    Ok(SummaryPage {
+        items,
+        next_cursor,
+        total,
+    })
+}
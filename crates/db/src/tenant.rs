@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+const TENANT_SETTINGS_COLUMNS: &str =
+    "api_key_id, smtp_banner_domain, auto_reply_from, share_page_name, share_page_logo_url, updated_at";
+
+/// Branding of the tenant that owns an API key.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct TenantSettings {
+    pub api_key_id: Uuid,
+    /// Names the receiving host in SMTP replies for the tenant's addresses.
+    pub smtp_banner_domain: Option<String>,
+    /// `From` of auto-replies sent on the tenant's behalf.
+    pub auto_reply_from: Option<String>,
+    pub share_page_name: Option<String>,
+    pub share_page_logo_url: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TenantSettingsUpdate {
+    pub smtp_banner_domain: Option<String>,
+    pub auto_reply_from: Option<String>,
+    pub share_page_name: Option<String>,
+    pub share_page_logo_url: Option<String>,
+}
+
+/// Replaces all settings of a tenant. Returns `None` for an unknown key.
+pub async fn upsert_tenant_settings(
+    pool: &PgPool,
+    api_key_id: Uuid,
+    update: &TenantSettingsUpdate,
+) -> Result<Option<TenantSettings>, sqlx::Error> {
+    sqlx::query_as::<_, TenantSettings>(&format!(
+        "INSERT INTO tenant_settings \
+             (api_key_id, smtp_banner_domain, auto_reply_from, share_page_name, share_page_logo_url) \
+         SELECT id, $2, $3, $4, $5 FROM api_key WHERE id = $1 \
+         ON CONFLICT (api_key_id) DO UPDATE SET \
+             smtp_banner_domain = EXCLUDED.smtp_banner_domain, \
+             auto_reply_from = EXCLUDED.auto_reply_from, \
+             share_page_name = EXCLUDED.share_page_name, \
+             share_page_logo_url = EXCLUDED.share_page_logo_url, \
+             updated_at = now() \
+         RETURNING {TENANT_SETTINGS_COLUMNS}"
+    ))
+    .bind(api_key_id)
+    .bind(&update.smtp_banner_domain)
+    .bind(&update.auto_reply_from)
+    .bind(&update.share_page_name)
+    .bind(&update.share_page_logo_url)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn find_tenant_settings(
+    pool: &PgPool,
+    api_key_id: Uuid,
+) -> Result<Option<TenantSettings>, sqlx::Error> {
+    sqlx::query_as::<_, TenantSettings>(&format!(
+        "SELECT {TENANT_SETTINGS_COLUMNS} FROM tenant_settings WHERE api_key_id = $1"
+    ))
+    .bind(api_key_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Settings of the tenant an address was created for, if any.
+pub async fn find_tenant_settings_for_address(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Option<TenantSettings>, sqlx::Error> {
+    sqlx::query_as::<_, TenantSettings>(&format!(
+        "SELECT {TENANT_SETTINGS_COLUMNS} FROM tenant_settings \
+         WHERE api_key_id = (SELECT api_key_id FROM temporary_email WHERE id = $1)"
+    ))
+    .bind(temporary_email_id)
+    .fetch_optional(pool)
+    .await
+}
@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Width of the periods a mailbox's activity is counted in, aligned to UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineBucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl TimelineBucket {
+    /// Also the `date_trunc` field name.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+}
+
+impl fmt::Display for TimelineBucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TimelineBucket {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hour" => Ok(Self::Hour),
+            "day" => Ok(Self::Day),
+            other => Err(format!("expected hour or day, got {other:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct PeriodActivity {
+    /// Start of the period.
+    pub start: DateTime<Utc>,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SenderActivity {
+    /// Lowercased; `None` for the null sender.
+    pub from_addr: Option<String>,
+    pub count: i64,
+    pub first_at: DateTime<Utc>,
+    pub last_at: DateTime<Utc>,
+}
+
+/// Messages per `bucket` received since `since`, oldest period first. Periods
+/// without mail are left out.
+pub async fn mailbox_activity_by_period(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    bucket: TimelineBucket,
+    since: DateTime<Utc>,
+) -> Result<Vec<PeriodActivity>, sqlx::Error> {
+    sqlx::query_as::<_, PeriodActivity>(
+        "SELECT date_trunc($2, received_at, 'UTC') AS start, count(*) AS count \
+         FROM received_email \
+         WHERE temporary_email_id = $1 AND received_at >= $3 \
+         GROUP BY 1 \
+         ORDER BY 1",
+    )
+    .bind(temporary_email_id)
+    .bind(bucket.as_str())
+    .bind(since)
+    .fetch_all(pool)
+    .await
+}
+
+/// The `limit` busiest senders since `since`, busiest first.
+pub async fn mailbox_activity_by_sender(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    since: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<SenderActivity>, sqlx::Error> {
+    sqlx::query_as::<_, SenderActivity>(
+        "SELECT lower(from_addr) AS from_addr, count(*) AS count, \
+                min(received_at) AS first_at, max(received_at) AS last_at \
+         FROM received_email \
+         WHERE temporary_email_id = $1 AND received_at >= $2 \
+         GROUP BY 1 \
+         ORDER BY count DESC, last_at DESC \
+         LIMIT $3",
+    )
+    .bind(temporary_email_id)
+    .bind(since)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// How a watch rule's pattern is matched against subject and body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchKind {
+    /// Case-insensitive literal text.
+    #[default]
+    Substring,
+    /// A regular expression; its capture groups are sent with the event.
+    Regex,
+}
+
+impl WatchKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Substring => "substring",
+            Self::Regex => "regex",
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "substring" => Ok(Self::Substring),
+            "regex" => Ok(Self::Regex),
+            other => Err(format!("expected substring or regex, got {other:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for WatchKind {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WatchRule {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Uuid,
+    #[sqlx(try_from = "String")]
+    pub kind: WatchKind,
+    pub pattern: String,
+    pub webhook_url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+const WATCH_RULE_COLUMNS: &str = "id, temporary_email_id, kind, pattern, webhook_url, created_at";
+
+pub async fn insert_watch_rule(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    kind: WatchKind,
+    pattern: &str,
+    webhook_url: &str,
+) -> Result<WatchRule, sqlx::Error> {
+    sqlx::query_as::<_, WatchRule>(&format!(
+        "INSERT INTO watch_rule (temporary_email_id, kind, pattern, webhook_url) \
+         VALUES ($1, $2, $3, $4) \
+         RETURNING {WATCH_RULE_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(kind.as_str())
+    .bind(pattern)
+    .bind(webhook_url)
+    .fetch_one(pool)
+    .await
+}
+
+/// Rules of one address, oldest first.
+pub async fn list_watch_rules(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<WatchRule>, sqlx::Error> {
+    list_watch_rules_for(pool, &[temporary_email_id]).await
+}
+
+/// Rules of any of `temporary_email_ids`, oldest first; what ingestion
+/// evaluates for the recipients of one message.
+pub async fn list_watch_rules_for(
+    pool: &PgPool,
+    temporary_email_ids: &[Uuid],
+) -> Result<Vec<WatchRule>, sqlx::Error> {
+    sqlx::query_as::<_, WatchRule>(&format!(
+        "SELECT {WATCH_RULE_COLUMNS} FROM watch_rule \
+         WHERE temporary_email_id = ANY($1) \
+         ORDER BY created_at, id"
+    ))
+    .bind(temporary_email_ids)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn delete_watch_rule(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM watch_rule WHERE id = $1 AND temporary_email_id = $2")
+            .bind(id)
+            .bind(temporary_email_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct WebhookSubscription {
+    pub id: Uuid,
+    #[serde(skip_serializing)]
+    pub temporary_email_id: Uuid,
+    pub url: String,
+    pub created_at: DateTime<Utc>,
+}
+
+const WEBHOOK_SUBSCRIPTION_COLUMNS: &str = "id, temporary_email_id, url, created_at";
+
+/// A queued delivery claimed by [`claim_webhook_deliveries`], with what is
+/// needed to send it.
+#[derive(Debug, Clone, FromRow)]
+pub struct PendingWebhookDelivery {
+    pub id: Uuid,
+    pub subscription_id: Uuid,
+    pub received_email_id: Uuid,
+    /// Attempts before this one.
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+pub async fn insert_webhook_subscription(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    url: &str,
+    secret: &str,
+) -> Result<WebhookSubscription, sqlx::Error> {
+    sqlx::query_as::<_, WebhookSubscription>(&format!(
+        "INSERT INTO webhook_subscription (temporary_email_id, url, secret) \
+         VALUES ($1, $2, $3) \
+         RETURNING {WEBHOOK_SUBSCRIPTION_COLUMNS}"
+    ))
+    .bind(temporary_email_id)
+    .bind(url)
+    .bind(secret)
+    .fetch_one(pool)
+    .await
+}
+
+/// Subscriptions of one address, oldest first.
+pub async fn list_webhook_subscriptions(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<WebhookSubscription>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookSubscription>(&format!(
+        "SELECT {WEBHOOK_SUBSCRIPTION_COLUMNS} FROM webhook_subscription \
+         WHERE temporary_email_id = $1 \
+         ORDER BY created_at, id"
+    ))
+    .bind(temporary_email_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Drops the subscription and whatever it still had queued.
+pub async fn delete_webhook_subscription(
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+    id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM webhook_subscription WHERE id = $1 AND temporary_email_id = $2")
+            .bind(id)
+            .bind(temporary_email_id)
+            .execute(pool)
+            .await?
+            .rows_affected()
+            > 0,
+    )
+}
+
+/// Notified when a priority delivery is queued, so workers need not wait
+/// for their next poll.
+pub const WEBHOOK_PRIORITY_CHANNEL: &str = "webhook_priority";
+
+/// Queues the message for every subscription of its address; called in the
+/// transaction that stores it, so a stored message is never missed. Mail
+/// from a sender the address expects is queued with priority.
+pub(crate) async fn queue_webhook_deliveries(
+    conn: &mut PgConnection,
+    temporary_email_id: Uuid,
+    received_email_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let queued: Vec<bool> = sqlx::query_scalar(
+        "WITH sender AS ( \
+             SELECT lower(substring(from_addr FROM '@([^@]+)$')) AS domain \
+             FROM received_email WHERE id = $2 \
+         ) \
+         INSERT INTO webhook_delivery (subscription_id, received_email_id, priority) \
+         SELECT s.id, $2, EXISTS ( \
+             SELECT 1 FROM sender_expectation e, sender \
+             WHERE e.temporary_email_id = $1 AND e.expires_at > now() \
+               AND (sender.domain = e.from_domain \
+                    OR right(sender.domain, length(e.from_domain) + 1) = '.' || e.from_domain) \
+         ) \
+         FROM webhook_subscription s WHERE s.temporary_email_id = $1 \
+         RETURNING priority",
+    )
+    .bind(temporary_email_id)
+    .bind(received_email_id)
+    .fetch_all(&mut *conn)
+    .await?;
+    if queued.contains(&true) {
+        sqlx::query("SELECT pg_notify($1, '')")
+            .bind(WEBHOOK_PRIORITY_CHANNEL)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Takes up to `limit` due deliveries, priority ones first and then the
+/// oldest, and hides them from other workers for `lease`; a worker that dies
+/// mid-delivery only delays them. Several processes can share the queue.
+pub async fn claim_webhook_deliveries(
+    pool: &PgPool,
+    limit: i64,
+    lease: Duration,
+) -> Result<Vec<PendingWebhookDelivery>, sqlx::Error> {
+    sqlx::query_as::<_, PendingWebhookDelivery>(
+        "WITH due AS ( \
+             SELECT id FROM webhook_delivery \
+             WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= now() \
+             ORDER BY priority DESC, next_attempt_at \
+             LIMIT $1 \
+             FOR UPDATE SKIP LOCKED \
+         ), claimed AS ( \
+             UPDATE webhook_delivery d \
+             SET next_attempt_at = now() + make_interval(secs => $2::float8) \
+             FROM due WHERE d.id = due.id \
+             RETURNING d.id, d.subscription_id, d.received_email_id, d.attempts \
+         ) \
+         SELECT c.id, c.subscription_id, c.received_email_id, c.attempts, s.url, s.secret \
+         FROM claimed c JOIN webhook_subscription s ON s.id = c.subscription_id",
+    )
+    .bind(limit)
+    .bind(lease.as_secs_f64())
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn complete_webhook_delivery(pool: &PgPool, id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE webhook_delivery \
+         SET attempts = attempts + 1, delivered_at = now(), last_error = NULL \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Records a failed attempt: the delivery is tried again after `retry_in`,
+/// or given up on when that is `None`.
+pub async fn fail_webhook_delivery(
+    pool: &PgPool,
+    id: Uuid,
+    error: &str,
+    retry_in: Option<Duration>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE webhook_delivery \
+         SET attempts = attempts + 1, \
+             last_error = $2, \
+             next_attempt_at = now() + make_interval(secs => coalesce($3::float8, 0)), \
+             failed_at = CASE WHEN $3::float8 IS NULL THEN now() END \
+         WHERE id = $1",
+    )
+    .bind(id)
+    .bind(error)
+    .bind(retry_in.map(|d| d.as_secs_f64()))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
@@ -107,14 +107,386 @@ async fn insert_temp_addr_and_list_received_emails() {
     .await
     .expect("insert new email");
 
-    let all = db::list_received_emails(&pool, temp.id, None)
+    let all = db::list_received_emails(&pool, temp.id, None, None, None)
         .await
         .expect("list all emails");
     assert_eq!(all.len(), 2);
 
-    let recent = db::list_received_emails(&pool, temp.id, Some(cursor))
+    let recent = db::list_received_emails(&pool, temp.id, Some(cursor), None, None)
         .await
         .expect("list filtered emails");
     assert_eq!(recent.len(), 1);
     assert_eq!(recent[0].subject.as_deref(), Some("new"));
 }
+
+#[tokio::test]
+async fn purge_keeps_honeypot_addresses() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    db::insert_temporary_email(&pool, "user@temp.test")
+        .await
+        .expect("insert temporary_email");
+    db::insert_honeypot_email(&pool, "trap@temp.test")
+        .await
+        .expect("insert honeypot");
+
+    let result = db::purge_all_data(&pool).await.expect("purge");
+    assert_eq!(result.inboxes_deleted, 1);
+
+    assert!(db::find_temporary_email_by_addr(&pool, "user@temp.test")
+        .await
+        .expect("lookup")
+        .is_none());
+    let trap = db::find_temporary_email_by_addr(&pool, "trap@temp.test")
+        .await
+        .expect("lookup")
+        .expect("honeypot survives purge");
+    assert!(trap.is_honeypot);
+}
+
+#[tokio::test]
+async fn bodies_round_trip_through_compression_and_backfill() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    let temp = db::insert_temporary_email(&pool, "zip@temp.test")
+        .await
+        .expect("insert temporary_email");
+    let html = "<p>hello</p>".repeat(500);
+    let raw = format!("Subject: big\r\n\r\n{html}\r\n");
+    let email = db::NewReceivedEmail {
+        temporary_email_id: temp.id,
+        from_addr: Some("a@sender.test"),
+        to_addr: Some("zip@temp.test"),
+        subject: Some("big"),
+        body_text: None,
+        body_html: Some(&html),
+        raw_email: Some(raw.as_bytes()),
+        headers: &[],
+        is_bounce: false,
+        peer_ip: None,
+        spf_result: None,
+        dkim: &[],
+        dmarc_result: None,
+        tags: &[],
+        country: None,
+        plus_tag: None,
+        original_recipient: None,
+        envelope_recipients: &[],
+        header_recipients: &[],
+        accepted_at: None,
+    };
+
+    let compressed = db::insert_received_email(&pool, &email, db::BodyCompression::default())
+        .await
+        .expect("insert compressed");
+    let plain = db::insert_received_email(&pool, &email, db::BodyCompression::None)
+        .await
+        .expect("insert plain");
+    assert_eq!(compressed.body_html.as_deref(), Some(html.as_str()));
+    assert_eq!(plain.body_html.as_deref(), Some(html.as_str()));
+
+    let backfill = db::compress_stored_bodies(&pool, db::BodyCompression::default(), 10)
+        .await
+        .expect("backfill");
+    assert_eq!(backfill.rows_compressed, 1);
+    assert!(backfill.bytes_after < backfill.bytes_before);
+
+    let (flagged,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM received_email WHERE is_compressed")
+            .fetch_one(&pool)
+            .await
+            .expect("count compressed");
+    assert_eq!(flagged, 2);
+
+    for id in [compressed.id, plain.id] {
+        let stored = db::fetch_raw_email(&pool, id)
+            .await
+            .expect("fetch raw")
+            .expect("raw kept");
+        assert_eq!(stored, raw.as_bytes());
+    }
+    let listed = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list");
+    assert!(listed
+        .iter()
+        .all(|e| e.body_html.as_deref() == Some(html.as_str())));
+}
+
+#[tokio::test]
+async fn large_bodies_of_distinct_words_are_stored_and_searchable() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    let temp = db::insert_temporary_email(&pool, "words@temp.test")
+        .await
+        .expect("insert temporary_email");
+    // Well past the 1 MiB tsvector limit once every word is its own lexeme.
+    let text = (0..250_000)
+        .map(|i| format!("word{i}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let email = db::NewReceivedEmail {
+        temporary_email_id: temp.id,
+        from_addr: Some("a@sender.test"),
+        to_addr: Some("words@temp.test"),
+        subject: Some("dictionary"),
+        body_text: Some(&text),
+        body_html: None,
+        raw_email: None,
+        headers: &[],
+        is_bounce: false,
+        peer_ip: None,
+        spf_result: None,
+        dkim: &[],
+        dmarc_result: None,
+        tags: &[],
+        country: None,
+        plus_tag: None,
+        original_recipient: None,
+        envelope_recipients: &[],
+        header_recipients: &[],
+        accepted_at: None,
+    };
+    let stored = db::insert_received_email(&pool, &email, db::BodyCompression::None)
+        .await
+        .expect("insert large body");
+    assert_eq!(stored.body_text.as_deref(), Some(text.as_str()));
+
+    let found = db::search_emails_by_address(&pool, "words@temp.test", "word7", None, 10)
+        .await
+        .expect("search");
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, stored.id);
+    let past_cap = db::search_emails_by_address(&pool, "words@temp.test", "word249999", None, 10)
+        .await
+        .expect("search");
+    assert!(past_cap.is_empty());
+}
+
+#[tokio::test]
+async fn batched_and_truncate_purges_agree() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    for strategy in [db::PurgeStrategy::Batched, db::PurgeStrategy::Truncate] {
+        for i in 0..7 {
+            let temp = db::insert_temporary_email(&pool, &format!("u{i}@temp.test"))
+                .await
+                .expect("insert temporary_email");
+            sqlx::query("INSERT INTO received_email (temporary_email_id, subject) VALUES ($1, 'x')")
+                .bind(temp.id)
+                .execute(&pool)
+                .await
+                .expect("insert email");
+        }
+
+        let opts = db::PurgeOptions {
+            strategy,
+            batch_size: 3,
+            ..Default::default()
+        };
+        let result = db::purge_all_data_with(&pool, &opts).await.expect("purge");
+        assert_eq!(result.emails_deleted, 7, "{strategy}");
+        assert_eq!(result.inboxes_deleted, 7, "{strategy}");
+
+        let (left,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM temporary_email")
+            .fetch_one(&pool)
+            .await
+            .expect("count");
+        assert_eq!(left, 0, "{strategy}");
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn first_reads_feed_latency_percentiles_per_sender() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    let temp = db::insert_temporary_email(&pool, "slo@temp.test")
+        .await
+        .expect("insert temporary_email");
+    let mut ids = Vec::new();
+    for (from, secs_ago) in [
+        (Some("codes@OTP.example"), 2),
+        (Some("codes@otp.example"), 4),
+        (Some("news@slow.example"), 60),
+        (None, 5),
+    ] {
+        let (id,): (uuid::Uuid,) = sqlx::query_as(
+            "INSERT INTO received_email (temporary_email_id, from_addr, accepted_at) \
+             VALUES ($1, $2, $3) RETURNING id",
+        )
+        .bind(temp.id)
+        .bind(from)
+        .bind(Utc::now() - Duration::seconds(secs_ago))
+        .fetch_one(&pool)
+        .await
+        .expect("insert email");
+        ids.push(id);
+    }
+    // Not taken over SMTP.
+    let (api,): (uuid::Uuid,) = sqlx::query_as(
+        "INSERT INTO received_email (temporary_email_id, from_addr) \
+         VALUES ($1, 'x@otp.example') RETURNING id",
+    )
+    .bind(temp.id)
+    .fetch_one(&pool)
+    .await
+    .expect("insert email");
+    ids.push(api);
+
+    let latencies = db::mark_first_read(&pool, &ids).await.expect("first read");
+    assert_eq!(latencies.len(), 4);
+    assert!(latencies.iter().all(|&s| s > 1.0), "{latencies:?}");
+    // Only the first read counts.
+    let again = db::mark_first_read(&pool, &ids).await.expect("read again");
+    assert!(again.is_empty());
+
+    let since = Utc::now() - Duration::hours(1);
+    let stats = db::delivery_latency_by_sender(&pool, since, 10)
+        .await
+        .expect("latency");
+    let domains: Vec<_> = stats.iter().map(|s| s.sender_domain.as_str()).collect();
+    assert_eq!(domains, ["slow.example", "otp.example"]);
+    assert_eq!(stats[1].messages, 2);
+    assert!(stats[1].p50_secs >= 2.0 && stats[1].p95_secs < stats[0].p50_secs);
+    assert!(stats[1].max_secs >= 4.0);
+
+    let later = Utc::now() + Duration::hours(1);
+    let none = db::delivery_latency_by_sender(&pool, later, 10)
+        .await
+        .expect("latency");
+    assert!(none.is_empty());
+}
+
+#[tokio::test]
+async fn address_changes_are_announced_for_cache_invalidation() {
+    let (_container, url) = start_postgres()
+        .await
+        .expect("docker/postgres must start for integration test");
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("run_migrations");
+
+    let kept = db::insert_temporary_email(&pool, "kept@temp.test")
+        .await
+        .expect("insert temporary_email");
+    let gone = db::insert_temporary_email(&pool, "gone@temp.test")
+        .await
+        .expect("insert temporary_email");
+    db::insert_address_alias(&pool, gone.id, "alias@temp.test")
+        .await
+        .expect("insert alias")
+        .expect("alias free");
+
+    let mut listener = sqlx::postgres::PgListener::connect_with(&pool)
+        .await
+        .expect("listener");
+    listener
+        .listen(db::ADDRESS_CHANGED_CHANNEL)
+        .await
+        .expect("listen");
+
+    sqlx::query("UPDATE temporary_email SET is_public = is_public WHERE id = $1")
+        .bind(kept.id)
+        .execute(&pool)
+        .await
+        .expect("unrelated update");
+    sqlx::query(
+        "UPDATE temporary_email SET expires_at = now() - interval '1 minute' WHERE id = $1",
+    )
+    .bind(kept.id)
+    .execute(&pool)
+    .await
+    .expect("expire");
+    let swept = db::deactivate_expired_addresses(&pool)
+        .await
+        .expect("sweep");
+    assert_eq!(swept, 1);
+    sqlx::query("DELETE FROM temporary_email WHERE id = $1")
+        .bind(gone.id)
+        .execute(&pool)
+        .await
+        .expect("delete");
+
+    let mut announced = Vec::new();
+    for _ in 0..4 {
+        let n = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+            .await
+            .expect("notification within 5s")
+            .expect("recv");
+        announced.push(n.payload().to_owned());
+    }
+    announced[2..].sort();
+    assert_eq!(
+        announced,
+        [
+            "kept@temp.test",
+            "kept@temp.test",
+            "alias@temp.test",
+            "gone@temp.test"
+        ]
+    );
+
+    // A statement touching many addresses sends one notification.
+    let pair = ["pair-a@temp.test".to_owned(), "pair-b@temp.test".to_owned()];
+    db::insert_temporary_email_batch(&pool, None, &pair, &[vec![1], vec![2]])
+        .await
+        .expect("insert pair");
+    sqlx::query("DELETE FROM temporary_email WHERE temp_email_addr = ANY($1)")
+        .bind(&pair[..])
+        .execute(&pool)
+        .await
+        .expect("delete pair");
+    let bulk: Vec<String> = (0..500)
+        .map(|i| format!("bulk-address-{i:04}@temp.test"))
+        .collect();
+    let hashes = vec![Vec::new(); bulk.len()];
+    db::insert_temporary_email_batch(&pool, None, &bulk, &hashes)
+        .await
+        .expect("insert bulk");
+    sqlx::query("UPDATE temporary_email SET is_active = false WHERE temp_email_addr = ANY($1)")
+        .bind(&bulk)
+        .execute(&pool)
+        .await
+        .expect("deactivate bulk");
+
+    let mut batched = Vec::new();
+    for _ in 0..2 {
+        let n = tokio::time::timeout(std::time::Duration::from_secs(5), listener.recv())
+            .await
+            .expect("notification within 5s")
+            .expect("recv");
+        batched.push(n.payload().to_owned());
+    }
+    assert_eq!(
+        db::parse_address_changed_payload(&batched[0]),
+        Some(vec!["pair-a@temp.test", "pair-b@temp.test"])
+    );
+    // Too many to list under the payload limit.
+    assert_eq!(db::parse_address_changed_payload(&batched[1]), None);
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(500), listener.recv())
+            .await
+            .is_err(),
+        "one notification per statement"
+    );
+}
@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use db::{
+    fetch_batch_request_hash, find_temporary_email_by_alias, insert_temporary_email_batch,
+    insert_temporary_email_with_token, list_taken_addresses, list_temporary_emails_by_batch,
+    replace_mailbox_token_hashes, BatchKey, LocalPartBlocklist, TemporaryEmail,
+};
+use sqlx::postgres::PgPool;
+use std::collections::{HashMap, HashSet};
+
+use crate::generator::{self, full_address};
+use crate::token;
+
+const MAX_ATTEMPTS: usize = 4;
+const SUGGESTION_COUNT: usize = 3;
+const SUGGESTION_CANDIDATES: usize = 6;
+
+#[derive(Debug)]
+pub enum CreateAddressError {
+    FailedToFindUniqueName,
+    UsernameTaken {
+        suggestions: Vec<String>,
+    },
+    /// The idempotency key already created a batch for a different request.
+    IdempotencyKeyReused,
+    /// The idempotency key's batch has expired or been purged.
+    BatchGone,
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CreateAddressError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+pub(crate) fn is_unique_violation(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Database(dbe) if dbe.code().is_some_and(|c| c == "23505"))
+}
+
+/// How a single address is created, whatever name it ends up with.
+#[derive(Debug, Clone, Copy)]
+pub struct AddressOptions<'a> {
+    pub public: bool,
+    pub activate_at: Option<DateTime<Utc>>,
+    /// Stored with the row, so the address is never live without its token.
+    pub token_hash: &'a [u8],
+}
+
+pub async fn create_temporary_email(
+    pool: &PgPool,
+    domain: &str,
+    blocked: &LocalPartBlocklist,
+    username: Option<&str>,
+    allow_suffix: bool,
+    options: AddressOptions<'_>,
+) -> Result<TemporaryEmail, CreateAddressError> {
+    let AddressOptions {
+        public,
+        activate_at,
+        token_hash,
+    } = options;
+    // `None` when the address is taken, by another address or an alias.
+    let insert = |addr: String| async move {
+        if find_temporary_email_by_alias(pool, &addr).await?.is_some() {
+            return Ok(None);
+        }
+        let inserted =
+            insert_temporary_email_with_token(pool, &addr, public, activate_at, token_hash).await;
+        match inserted {
+            Ok(row) => Ok(Some(row)),
+            Err(e) if is_unique_violation(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    };
+
+    let Some(username) = username else {
+        for collisions in 0..MAX_ATTEMPTS {
+            let Some(local) = generator::random_local_part(collisions, blocked) else {
+                continue;
+            };
+            match insert(full_address(&local, domain)).await? {
+                Some(row) => return Ok(row),
+                None => {
+                    tracing::warn!(collisions = collisions + 1, "random address collision");
+                }
+            }
+        }
+        return Err(CreateAddressError::FailedToFindUniqueName);
+    };
+
+    if let Some(row) = insert(full_address(username, domain)).await? {
+        return Ok(row);
+    }
+
+    if allow_suffix {
+        for collisions in 0..MAX_ATTEMPTS {
+            let Some(local) = generator::with_suffix(username, collisions, blocked) else {
+                continue;
+            };
+            if let Some(row) = insert(full_address(&local, domain)).await? {
+                return Ok(row);
+            }
+        }
+        return Err(CreateAddressError::FailedToFindUniqueName);
+    }
+
+    let candidates = generator::suggestions(username, SUGGESTION_CANDIDATES, blocked);
+    let addrs: Vec<String> = candidates.iter().map(|l| full_address(l, domain)).collect();
+    let taken = list_taken_addresses(pool, &addrs).await?;
+    let suggestions = candidates
+        .into_iter()
+        .zip(&addrs)
+        .filter(|(_, addr)| !taken.contains(addr))
+        .map(|(local, _)| local)
+        .take(SUGGESTION_COUNT)
+        .collect();
+
+    Err(CreateAddressError::UsernameTaken { suggestions })
+}
+
+/// Each address comes with its own access token. Replaying an idempotency key
+/// with the same request returns the batch it created with fresh tokens,
+/// since the caller evidently never saw the first ones.
+pub async fn create_temporary_email_batch(
+    pool: &PgPool,
+    domain: &str,
+    blocked: &LocalPartBlocklist,
+    pepper: &[u8],
+    batch: Option<BatchKey<'_>>,
+    count: usize,
+    username: Option<&str>,
+) -> Result<Vec<(TemporaryEmail, String)>, CreateAddressError> {
+    if let Some(batch) = batch {
+        if let Some(existing) = replay_batch(pool, pepper, batch).await? {
+            return Ok(existing);
+        }
+    }
+
+    'attempts: for collisions in 0..MAX_ATTEMPTS {
+        let mut addrs = HashSet::with_capacity(count);
+        while addrs.len() < count {
+            let local = match username {
+                Some(username) => generator::with_suffix(username, collisions, blocked),
+                None => generator::random_local_part(collisions, blocked),
+            };
+            let Some(local) = local else {
+                continue 'attempts;
+            };
+            addrs.insert(full_address(&local, domain));
+        }
+        let addrs: Vec<String> = addrs.into_iter().collect();
+        let tokens: Vec<String> = addrs.iter().map(|_| token::generate()).collect();
+        let hashes: Vec<Vec<u8>> = tokens.iter().map(|t| token::hash(pepper, t)).collect();
+
+        match insert_temporary_email_batch(pool, batch, &addrs, &hashes).await {
+            Ok(rows) => {
+                let mut tokens: HashMap<String, String> = addrs.into_iter().zip(tokens).collect();
+                return Ok(rows
+                    .into_iter()
+                    .map(|row| {
+                        let token = tokens.remove(&row.temp_email_addr).unwrap_or_default();
+                        (row, token)
+                    })
+                    .collect());
+            }
+            Err(e) if is_unique_violation(&e) => {
+                // A concurrent request with the same key may have won the race.
+                if let Some(batch) = batch {
+                    if let Some(existing) = replay_batch(pool, pepper, batch).await? {
+                        return Ok(existing);
+                    }
+                }
+                tracing::warn!(collisions = collisions + 1, "batch address collision");
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(CreateAddressError::FailedToFindUniqueName)
+}
+
+/// The addresses of an earlier batch under the same key, with new tokens
+/// replacing the old; `None` when the key is new. A batch is only replayed
+/// whole: once any of its addresses has expired it is gone.
+async fn replay_batch(
+    pool: &PgPool,
+    pepper: &[u8],
+    batch: BatchKey<'_>,
+) -> Result<Option<Vec<(TemporaryEmail, String)>>, CreateAddressError> {
+    let Some(request_hash) = fetch_batch_request_hash(pool, batch.idempotency_key).await? else {
+        return Ok(None);
+    };
+    if request_hash != batch.request_hash {
+        return Err(CreateAddressError::IdempotencyKeyReused);
+    }
+    let existing = list_temporary_emails_by_batch(pool, batch.idempotency_key).await?;
+    if existing.is_empty() || !existing.iter().all(TemporaryEmail::is_live) {
+        return Err(CreateAddressError::BatchGone);
+    }
+    let ids: Vec<_> = existing.iter().map(|row| row.id).collect();
+    let tokens: Vec<String> = existing.iter().map(|_| token::generate()).collect();
+    let hashes: Vec<Vec<u8>> = tokens.iter().map(|t| token::hash(pepper, t)).collect();
+    replace_mailbox_token_hashes(pool, &ids, &hashes).await?;
+    Ok(Some(existing.into_iter().zip(tokens).collect()))
+}
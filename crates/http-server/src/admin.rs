@@ -0,0 +1,1103 @@
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+use chrono::{NaiveDate, Utc};
+use db::{
+    compile_local_part_pattern, delete_blocked_local_part, delete_blocked_sender,
+    delete_mail_domain, delete_poison_message, delete_smtp_user, delivery_latency_by_sender,
+    fetch_mailbox_token_hash, fetch_poison_raw, find_poison_message, find_temporary_email_by_addr,
+    find_tenant_settings, insert_api_key, insert_blocked_local_part, insert_blocked_sender,
+    insert_honeypot_email, list_api_keys, list_blocked_local_parts, list_blocked_senders,
+    list_honeypot_emails, list_mail_domains, list_poison_messages, list_sender_reputation,
+    list_smtp_users, list_usage_daily, redact_received_email, replace_mailbox_token_hash,
+    revoke_api_key, upsert_mail_domain, upsert_smtp_user, upsert_tenant_settings, ApiKey,
+    BlockedLocalPart, BlockedSender, DomainPolicy, LocalPartBlocklist, MailDomain, PoisonMessage,
+    ReceivedEmail, SenderLatency, SenderReputation, SmtpUser, TemporaryEmail, TenantSettings,
+    TenantSettingsUpdate, UsageDaily,
+};
+use serde::{Deserialize, Serialize};
+use smtp::CountryCount;
+use std::convert::Infallible;
+use std::net::IpAddr;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use utoipa::OpenApi;
+use uuid::Uuid;
+
+use crate::address::is_unique_violation;
+use crate::api::{db_error, err, require_pool, AccessTokenResponse};
+use crate::block::{normalize_sender, BlockSenderBody};
+use crate::config::is_hostname;
+use crate::dns::{self, MxHost};
+use crate::generator::{self, full_address};
+use crate::{lookup, token, AppState};
+
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/blocklist", get(list_blocklist).post(add_blocklist_entry))
+        .route("/blocklist/:id", delete(remove_blocklist_entry))
+        .route(
+            "/blocked-senders",
+            get(list_global_blocks).post(block_sender_globally),
+        )
+        .route("/blocked-senders/:sender", delete(unblock_sender_globally))
+        .route("/honeypots", get(list_honeypots).post(create_honeypot))
+        .route("/addresses/:address/token", post(issue_address_token))
+        .route("/domains", get(list_domains))
+        .route("/domains/:domain", put(put_domain).delete(remove_domain))
+        .route("/smtp-users", get(list_smtp_logins))
+        .route(
+            "/smtp-users/:username",
+            put(put_smtp_login).delete(remove_smtp_login),
+        )
+        .route("/sender-reputation", get(sender_reputation))
+        .route("/dns-check", get(dns_check))
+        .route("/tail", get(tail))
+        .route("/countries", get(countries))
+        .route("/latency", get(delivery_latency))
+        .route("/metrics", get(render_metrics))
+        .route("/messages/:id/redact", post(redact_message))
+        .route("/poison-messages", get(list_poison))
+        .route(
+            "/poison-messages/:id",
+            get(get_poison).delete(release_poison),
+        )
+        .route("/poison-messages/:id/raw", get(poison_raw))
+        .route("/api-keys", get(list_keys).post(create_key))
+        .route("/api-keys/:id", delete(revoke_key))
+        .route(
+            "/api-keys/:id/branding",
+            get(get_branding).put(put_branding),
+        )
+        .route("/usage", get(usage_export))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+/// The routes of [`router`], which [`crate::openapi`] nests under `/admin`.
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_blocklist,
+    add_blocklist_entry,
+    remove_blocklist_entry,
+    list_global_blocks,
+    block_sender_globally,
+    unblock_sender_globally,
+    list_honeypots,
+    create_honeypot,
+    issue_address_token,
+    list_domains,
+    put_domain,
+    remove_domain,
+    list_smtp_logins,
+    put_smtp_login,
+    remove_smtp_login,
+    sender_reputation,
+    dns_check,
+    tail,
+    countries,
+    delivery_latency,
+    render_metrics,
+    redact_message,
+    list_poison,
+    get_poison,
+    release_poison,
+    poison_raw,
+    list_keys,
+    create_key,
+    revoke_key,
+    get_branding,
+    put_branding,
+    usage_export,
+))]
+pub struct AdminApi;
+
+async fn require_admin(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return err(StatusCode::NOT_FOUND, "admin api disabled");
+    };
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match presented {
+        Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+            next.run(req).await
+        }
+        _ => err(StatusCode::UNAUTHORIZED, "admin token required"),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BlocklistEntryBody {
+    pub pattern: String,
+    #[serde(default)]
+    pub is_regex: bool,
+    pub reason: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/blocklist",
+    tag = "admin",
+    summary = "List blocked local parts",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_blocklist(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BlockedLocalPart>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_blocked_local_parts(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[utoipa::path(
+    post,
+    path = "/blocklist",
+    tag = "admin",
+    summary = "Block a local part or pattern",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn add_blocklist_entry(
+    State(state): State<AppState>,
+    Json(body): Json<BlocklistEntryBody>,
+) -> Result<(StatusCode, Json<BlockedLocalPart>), Response> {
+    let pool = require_pool(&state).await?;
+
+    let pattern = body.pattern.trim();
+    if pattern.is_empty() {
+        return Err(err(StatusCode::BAD_REQUEST, "pattern must not be empty"));
+    }
+    let pattern = if body.is_regex {
+        compile_local_part_pattern(pattern)
+            .map_err(|e| err(StatusCode::BAD_REQUEST, &format!("invalid regex: {e}")))?;
+        pattern.to_owned()
+    } else {
+        pattern.to_ascii_lowercase()
+    };
+
+    let row = insert_blocked_local_part(&pool, &pattern, body.is_regex, body.reason.as_deref())
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                err(StatusCode::CONFLICT, "pattern already blocked")
+            } else {
+                db_error(e)
+            }
+        })?;
+    state.blocklist.invalidate().await;
+
+    tracing::info!(pattern = %row.pattern, is_regex = row.is_regex, "local-part blocked");
+    Ok((StatusCode::CREATED, Json(row)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/blocklist/{id}",
+    tag = "admin",
+    summary = "Remove a blocklist entry",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn remove_blocklist_entry(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    let deleted = delete_blocked_local_part(&pool, id)
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown blocklist entry"));
+    }
+    state.blocklist.invalidate().await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/blocked-senders",
+    tag = "admin",
+    summary = "List globally blocked senders",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_global_blocks(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<BlockedSender>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_blocked_senders(&pool, None).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[utoipa::path(
+    post,
+    path = "/blocked-senders",
+    tag = "admin",
+    summary = "Refuse mail from an address or domain",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn block_sender_globally(
+    State(state): State<AppState>,
+    Json(body): Json<BlockSenderBody>,
+) -> Result<(StatusCode, Json<BlockedSender>), Response> {
+    let pool = require_pool(&state).await?;
+    let sender = normalize_sender(&body.sender)?;
+    let row = insert_blocked_sender(&pool, None, &sender)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::CONFLICT, "sender already blocked"))?;
+    tracing::info!(sender = %row.sender, "sender blocked globally");
+    Ok((StatusCode::CREATED, Json(row)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/blocked-senders/{sender}",
+    tag = "admin",
+    summary = "Unblock a sender globally",
+    params(("sender" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn unblock_sender_globally(
+    State(state): State<AppState>,
+    Path(sender): Path<String>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    let deleted = delete_blocked_sender(&pool, None, sender.trim())
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "sender is not blocked"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HoneypotBody {
+    pub username: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/honeypots",
+    tag = "admin",
+    summary = "List honeypot addresses",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_honeypots(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<TemporaryEmail>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_honeypot_emails(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+// Honeypots skip the local-part blocklist: seeding them under names spammers
+// guess (admin, billing, ...) is the point.
+#[utoipa::path(
+    post,
+    path = "/honeypots",
+    tag = "admin",
+    summary = "Create a honeypot address",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn create_honeypot(
+    State(state): State<AppState>,
+    Json(body): Json<HoneypotBody>,
+) -> Result<(StatusCode, Json<TemporaryEmail>), Response> {
+    let pool = require_pool(&state).await?;
+
+    let unrestricted = LocalPartBlocklist::default();
+    let local = match body.username.as_deref().map(str::trim) {
+        Some(raw) if !raw.is_empty() => generator::validate_username(raw, &unrestricted)
+            .map_err(|msg| err(StatusCode::BAD_REQUEST, &msg))?,
+        _ => generator::random_local_part(0, &unrestricted)
+            .ok_or_else(|| err(StatusCode::SERVICE_UNAVAILABLE, "could not generate a name"))?,
+    };
+
+    let row = insert_honeypot_email(&pool, &full_address(&local, &state.mail_domain))
+        .await
+        .map_err(|e| {
+            if is_unique_violation(&e) {
+                err(StatusCode::CONFLICT, "address already exists")
+            } else {
+                db_error(e)
+            }
+        })?;
+
+    tracing::info!(addr = %row.temp_email_addr, "honeypot created");
+    Ok((StatusCode::CREATED, Json(row)))
+}
+
+/// Replaces a mailbox's access token, or gives one to a mailbox created
+/// without, such as a catch-all address.
+#[utoipa::path(
+    post,
+    path = "/addresses/{address}/token",
+    tag = "admin",
+    summary = "Issue a mailbox access token",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn issue_address_token(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<AccessTokenResponse>, Response> {
+    let pool = require_pool(&state).await?;
+    let temp = find_temporary_email_by_addr(&pool, &address.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    let current = fetch_mailbox_token_hash(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+
+    let access_token = token::generate();
+    let hash = token::hash(&state.token_pepper, &access_token);
+    if !replace_mailbox_token_hash(&pool, temp.id, current.as_deref(), &hash)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(err(StatusCode::CONFLICT, "token changed concurrently"));
+    }
+
+    tracing::info!(addr = %temp.temp_email_addr, "access token issued by admin");
+    Ok(Json(AccessTokenResponse { access_token }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DomainRuleBody {
+    pub policy: DomainPolicy,
+    /// Required for `webhook`, refused otherwise.
+    pub webhook_url: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/domains",
+    tag = "admin",
+    summary = "List domain routing rules",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_domains(State(state): State<AppState>) -> Result<Json<Vec<MailDomain>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_mail_domains(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+/// Sets how the SMTP server routes recipients at `domain`; takes effect with
+/// the next `RCPT TO`.
+#[utoipa::path(
+    put,
+    path = "/domains/{domain}",
+    tag = "admin",
+    summary = "Set a domain's routing rule",
+    params(("domain" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn put_domain(
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+    Json(body): Json<DomainRuleBody>,
+) -> Result<Json<MailDomain>, Response> {
+    let pool = require_pool(&state).await?;
+    let domain = domain.trim().to_ascii_lowercase();
+    if !is_hostname(&domain) {
+        return Err(err(StatusCode::BAD_REQUEST, "domain must be a host name"));
+    }
+    let webhook_url = body
+        .webhook_url
+        .map(|u| u.trim().to_owned())
+        .filter(|u| !u.is_empty());
+    check_webhook_url(body.policy, webhook_url.as_deref())
+        .map_err(|m| err(StatusCode::BAD_REQUEST, m))?;
+    let row = upsert_mail_domain(&pool, &domain, body.policy, webhook_url.as_deref())
+        .await
+        .map_err(db_error)?;
+    tracing::info!(domain = %row.domain, policy = %row.policy, "domain routing updated");
+    Ok(Json(row))
+}
+
+fn check_webhook_url(policy: DomainPolicy, url: Option<&str>) -> Result<(), &'static str> {
+    match (policy, url) {
+        (DomainPolicy::Webhook, None) => Err("webhook_url is required"),
+        (DomainPolicy::Webhook, Some(url))
+            if !(url.starts_with("https://") || url.starts_with("http://")) =>
+        {
+            Err("webhook_url must be an http(s) URL")
+        }
+        (DomainPolicy::Webhook, Some(_)) | (_, None) => Ok(()),
+        (_, Some(_)) => Err("webhook_url is only allowed with the webhook policy"),
+    }
+}
+
+/// Drops the rule; the domain goes back to accepting existing addresses only.
+#[utoipa::path(
+    delete,
+    path = "/domains/{domain}",
+    tag = "admin",
+    summary = "Remove a domain's routing rule",
+    params(("domain" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn remove_domain(
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    let deleted = delete_mail_domain(&pool, domain.trim())
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "no rule for this domain"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SmtpLoginBody {
+    pub password: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/smtp-users",
+    tag = "admin",
+    summary = "List SMTP AUTH logins",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_smtp_logins(State(state): State<AppState>) -> Result<Json<Vec<SmtpUser>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_smtp_users(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+/// Creates an SMTP AUTH login or replaces its password. Only the Argon2
+/// hash is stored.
+#[utoipa::path(
+    put,
+    path = "/smtp-users/{username}",
+    tag = "admin",
+    summary = "Set an SMTP AUTH login",
+    params(("username" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn put_smtp_login(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+    Json(body): Json<SmtpLoginBody>,
+) -> Result<Json<SmtpUser>, Response> {
+    let pool = require_pool(&state).await?;
+    let username = username.trim().to_owned();
+    if username.is_empty() || username.contains(['\0', ':']) {
+        return Err(err(StatusCode::BAD_REQUEST, "invalid username"));
+    }
+    if body.password.len() < 12 {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "password must be at least 12 characters",
+        ));
+    }
+    let hash = tokio::task::spawn_blocking(move || smtp::auth::hash_password(&body.password))
+        .await
+        .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))?
+        .map_err(|e| err(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+    let row = upsert_smtp_user(&pool, &username, &hash)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(username = %row.username, "smtp login saved");
+    Ok(Json(row))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/smtp-users/{username}",
+    tag = "admin",
+    summary = "Remove an SMTP AUTH login",
+    params(("username" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn remove_smtp_login(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    let deleted = delete_smtp_user(&pool, username.trim())
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown smtp user"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReputationQuery {
+    pub limit: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/sender-reputation",
+    tag = "admin",
+    summary = "Honeypot hits per sender domain",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn sender_reputation(
+    State(state): State<AppState>,
+    Query(q): Query<ReputationQuery>,
+) -> Result<Json<Vec<SenderReputation>>, Response> {
+    let pool = require_pool(&state).await?;
+    let limit = q.limit.unwrap_or(100).clamp(1, 1000);
+    let rows = list_sender_reputation(&pool, limit)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DnsCheckQuery {
+    pub domain: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DnsCheckResponse {
+    pub domain: String,
+    pub public_ip: Option<IpAddr>,
+    pub mx: Vec<MxHost>,
+    pub spf: Vec<String>,
+    pub ptr: Vec<String>,
+    /// `None` when PUBLIC_IP is not configured and there is nothing to compare against.
+    pub routes_here: Option<bool>,
+    pub problems: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/dns-check",
+    tag = "admin",
+    summary = "MX, SPF and PTR report for a domain",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn dns_check(
+    State(state): State<AppState>,
+    Query(q): Query<DnsCheckQuery>,
+) -> Result<Json<DnsCheckResponse>, Response> {
+    let domain = match q.domain.as_deref().map(str::trim) {
+        Some(d) if !d.is_empty() => d.trim_end_matches('.').to_ascii_lowercase(),
+        _ => state.mail_domain.to_string(),
+    };
+    if !is_hostname(&domain) {
+        return Err(err(StatusCode::BAD_REQUEST, "invalid domain"));
+    }
+
+    let resolver = dns::resolver();
+    let mut problems = Vec::new();
+
+    let mx = dns::lookup_mx(&resolver, &domain)
+        .await
+        .unwrap_or_else(|e| {
+            problems.push(format!("MX lookup failed: {e}"));
+            Vec::new()
+        });
+    if mx.is_empty() {
+        problems.push(format!("{domain} has no MX records"));
+    }
+    for host in mx.iter().filter(|h| h.addrs.is_empty()) {
+        problems.push(format!("MX host {} does not resolve", host.exchange));
+    }
+
+    let spf = dns::lookup_spf(&resolver, &domain)
+        .await
+        .unwrap_or_else(|e| {
+            problems.push(format!("SPF lookup failed: {e}"));
+            Vec::new()
+        });
+    if spf.len() > 1 {
+        problems.push("multiple SPF records; receivers will treat this as an error".into());
+    }
+
+    let mut ptr = Vec::new();
+    let routes_here = match state.public_ip {
+        None => {
+            problems.push("PUBLIC_IP is not set; cannot tell whether MX points here".into());
+            None
+        }
+        Some(ip) => {
+            match dns::lookup_ptr(&resolver, ip).await {
+                Ok(names) if names.is_empty() => problems.push(format!("{ip} has no PTR record")),
+                Ok(names) => ptr = names,
+                Err(e) => problems.push(format!("PTR lookup failed: {e}")),
+            }
+            let here = mx.iter().any(|h| h.addrs.contains(&ip));
+            if !here && !mx.is_empty() {
+                problems.push(format!("no MX host for {domain} resolves to {ip}"));
+            }
+            Some(here)
+        }
+    };
+
+    Ok(Json(DnsCheckResponse {
+        domain,
+        public_ip: state.public_ip,
+        mx,
+        spf,
+        ptr,
+        routes_here,
+        problems,
+    }))
+}
+
+/// Live SSE feed of SMTP ingestion outcomes. Subscribers that fall behind get a
+/// `lagged` event with the number of events they missed instead of a backlog.
+#[utoipa::path(
+    get,
+    path = "/tail",
+    tag = "admin",
+    summary = "Server-Sent Events of SMTP ingestion",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn tail(State(state): State<AppState>) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = BroadcastStream::new(state.ingest_events.subscribe()).map(|item| {
+        Ok(match item {
+            Ok(event) => Event::default()
+                .event("ingest")
+                .json_data(&event)
+                .unwrap_or_else(|_| Event::default().comment("unserializable event")),
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                Event::default().event("lagged").data(missed.to_string())
+            }
+        })
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[utoipa::path(
+    get,
+    path = "/countries",
+    tag = "admin",
+    summary = "SMTP ingestion counts per client country",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn countries(State(state): State<AppState>) -> Json<Vec<CountryCount>> {
+    Json(state.ingest_events.by_country())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LatencyQuery {
+    /// Days of first reads to include; 7 by default, at most 90.
+    pub days: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// Seconds from accepting a message over SMTP to a client first being
+/// shown it, per sender domain, slowest p95 first.
+#[utoipa::path(
+    get,
+    path = "/latency",
+    tag = "admin",
+    summary = "Seconds from SMTP acceptance to first read, per sender domain",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn delivery_latency(
+    State(state): State<AppState>,
+    Query(q): Query<LatencyQuery>,
+) -> Result<Json<Vec<SenderLatency>>, Response> {
+    let pool = require_pool(&state).await?;
+    let days = q.days.unwrap_or(7).clamp(1, 90);
+    let limit = q.limit.unwrap_or(100).clamp(1, 1000);
+    let since = Utc::now() - chrono::Duration::days(days);
+    let rows = delivery_latency_by_sender(&pool, since, limit)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "admin",
+    summary = "Prometheus metrics",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn render_metrics(State(state): State<AppState>) -> Response {
+    if let Some(pool) = state.pool.read().await.as_ref() {
+        db::record_pool_metrics(pool);
+    }
+    match &state.metrics {
+        Some(handle) => handle.render().into_response(),
+        None => err(StatusCode::NOT_FOUND, "metrics recorder not installed"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RedactBody {
+    pub reason: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/messages/{id}/redact",
+    tag = "admin",
+    summary = "Redact a message",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn redact_message(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<RedactBody>,
+) -> Result<Json<ReceivedEmail>, Response> {
+    let pool = require_pool(&state).await?;
+    let reason = body
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty());
+    let row = redact_received_email(&pool, id, reason)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown message"))?;
+
+    tracing::warn!(message_id = %id, reason = reason.unwrap_or(""), "message redacted");
+    Ok(Json(row))
+}
+
+#[utoipa::path(
+    get,
+    path = "/poison-messages",
+    tag = "admin",
+    summary = "List quarantined messages",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_poison(State(state): State<AppState>) -> Result<Json<Vec<PoisonMessage>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_poison_messages(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[utoipa::path(
+    get,
+    path = "/poison-messages/{id}",
+    tag = "admin",
+    summary = "One quarantined message",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn get_poison(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PoisonMessage>, Response> {
+    let pool = require_pool(&state).await?;
+    let row = find_poison_message(&pool, id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown poison message"))?;
+    Ok(Json(row))
+}
+
+/// The quarantined bytes, as a download so nothing renders them.
+#[utoipa::path(
+    get,
+    path = "/poison-messages/{id}/raw",
+    tag = "admin",
+    summary = "Raw bytes of a quarantined message",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn poison_raw(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Response> {
+    let pool = require_pool(&state).await?;
+    let raw = fetch_poison_raw(&pool, id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown poison message"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "message/rfc822".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{id}.eml\""),
+            ),
+        ],
+        raw,
+    )
+        .into_response())
+}
+
+/// Drops a message from quarantine so the next delivery is processed again.
+#[utoipa::path(
+    delete,
+    path = "/poison-messages/{id}",
+    tag = "admin",
+    summary = "Release a quarantined message",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn release_poison(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    if !delete_poison_message(&pool, id).await.map_err(db_error)? {
+        return Err(err(StatusCode::NOT_FOUND, "unknown poison message"));
+    }
+    tracing::info!(poison_id = %id, "poison message released");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiKeyBody {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    /// Shown once; only a hash is stored.
+    pub api_key: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    tag = "admin",
+    summary = "List metering keys",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn list_keys(State(state): State<AppState>) -> Result<Json<Vec<ApiKey>>, Response> {
+    let pool = require_pool(&state).await?;
+    let rows = list_api_keys(&pool).await.map_err(db_error)?;
+    Ok(Json(rows))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    tag = "admin",
+    summary = "Issue a metering key",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn create_key(
+    State(state): State<AppState>,
+    Json(body): Json<ApiKeyBody>,
+) -> Result<(StatusCode, Json<CreatedApiKey>), Response> {
+    let pool = require_pool(&state).await?;
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(err(StatusCode::BAD_REQUEST, "name must not be empty"));
+    }
+    let api_key = token::generate();
+    let key = insert_api_key(&pool, name, &token::hash(&state.token_pepper, &api_key))
+        .await
+        .map_err(db_error)?;
+    tracing::info!(api_key = %key.id, name = %key.name, "api key created");
+    Ok((StatusCode::CREATED, Json(CreatedApiKey { key, api_key })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api-keys/{id}",
+    tag = "admin",
+    summary = "Revoke a metering key",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn revoke_key(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let pool = require_pool(&state).await?;
+    if !revoke_api_key(&pool, id).await.map_err(db_error)? {
+        return Err(err(StatusCode::NOT_FOUND, "unknown api key"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+const MAX_SHARE_PAGE_NAME_CHARS: usize = 64;
+
+#[utoipa::path(
+    get,
+    path = "/api-keys/{id}/branding",
+    tag = "admin",
+    summary = "Tenant branding",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn get_branding(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TenantSettings>, Response> {
+    let pool = require_pool(&state).await?;
+    find_tenant_settings(&pool, id)
+        .await
+        .map_err(db_error)?
+        .map(Json)
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "no branding for this api key"))
+}
+
+/// Replaces the tenant's branding; omitted or empty fields fall back to the
+/// deployment defaults.
+#[utoipa::path(
+    put,
+    path = "/api-keys/{id}/branding",
+    tag = "admin",
+    summary = "Replace tenant branding",
+    params(("id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn put_branding(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(body): Json<TenantSettingsUpdate>,
+) -> Result<Json<TenantSettings>, Response> {
+    let pool = require_pool(&state).await?;
+    let update = normalize_branding(body).map_err(|m| err(StatusCode::BAD_REQUEST, m))?;
+    upsert_tenant_settings(&pool, id, &update)
+        .await
+        .map_err(db_error)?
+        .map(Json)
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown api key"))
+}
+
+fn normalize_branding(body: TenantSettingsUpdate) -> Result<TenantSettingsUpdate, &'static str> {
+    let clean = |v: Option<String>| v.map(|s| s.trim().to_owned()).filter(|s| !s.is_empty());
+    let update = TenantSettingsUpdate {
+        smtp_banner_domain: clean(body.smtp_banner_domain).map(|d| d.to_ascii_lowercase()),
+        auto_reply_from: clean(body.auto_reply_from).map(|a| a.to_ascii_lowercase()),
+        share_page_name: clean(body.share_page_name),
+        share_page_logo_url: clean(body.share_page_logo_url),
+    };
+
+    if let Some(domain) = &update.smtp_banner_domain {
+        if !is_hostname(domain) {
+            return Err("smtp_banner_domain must be a host name");
+        }
+    }
+    if let Some(addr) = &update.auto_reply_from {
+        let valid = addr.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && local
+                    .bytes()
+                    .all(|b| b.is_ascii_graphic() && !b"<>@\",;".contains(&b))
+                && is_hostname(domain)
+        });
+        if !valid {
+            return Err("auto_reply_from must be an email address");
+        }
+    }
+    if let Some(name) = &update.share_page_name {
+        if name.chars().count() > MAX_SHARE_PAGE_NAME_CHARS || name.chars().any(char::is_control) {
+            return Err("share_page_name must be at most 64 printable characters");
+        }
+    }
+    if let Some(url) = &update.share_page_logo_url {
+        if !url.starts_with("https://") || url.chars().any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err("share_page_logo_url must be an https URL");
+        }
+    }
+    Ok(update)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub api_key_id: Option<Uuid>,
+    /// `json` (default) or `csv`.
+    pub format: Option<String>,
+}
+
+/// Daily usage totals, by default for the last 30 days. Only days that have
+/// been rolled up by the janitor are included, so today is always missing.
+#[utoipa::path(
+    get,
+    path = "/usage",
+    tag = "admin",
+    summary = "Daily usage totals as JSON or CSV",
+    responses((status = "default", description = "See README.md")),
+    security(("admin" = []))
+)]
+async fn usage_export(
+    State(state): State<AppState>,
+    Query(q): Query<UsageQuery>,
+) -> Result<Response, Response> {
+    let pool = require_pool(&state).await?;
+    let to = q.to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = q.from.unwrap_or(to - chrono::Duration::days(30));
+    if from > to {
+        return Err(err(StatusCode::BAD_REQUEST, "from must not be after to"));
+    }
+    let rows = list_usage_daily(&pool, from, to, q.api_key_id)
+        .await
+        .map_err(db_error)?;
+
+    match q.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(rows).into_response()),
+        "csv" => Ok((
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            usage_csv(&rows),
+        )
+            .into_response()),
+        _ => Err(err(StatusCode::BAD_REQUEST, "format must be json or csv")),
+    }
+}
+
+fn usage_csv(rows: &[UsageDaily]) -> String {
+    let mut out = String::from("day,api_key_id,kind,quantity\n");
+    for r in rows {
+        out.push_str(&format!(
+            "{},{},{},{}\n",
+            r.day, r.api_key_id, r.kind, r.quantity
+        ));
+    }
+    out
+}
@@ -0,0 +1,111 @@
+//! `/api/email/:address/aliases`: further addresses of a mailbox, at its
+//! domain. Mail to an alias lands in the same inbox, with the alias as its
+//! `to_addr`; the SMTP server resolves aliases when an address is not found.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::{delete_address_alias, insert_address_alias, list_address_aliases, AddressAlias};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::api::{db_error, err};
+use crate::generator::{full_address, validate_username};
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+/// Aliases one mailbox may have.
+pub const MAX_ALIASES: usize = 10;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = CreateAlias)]
+pub struct CreateAliasBody {
+    /// Local part; the alias gets the mailbox's domain.
+    pub username: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/aliases",
+    tag = "api",
+    summary = "List further addresses of the mailbox",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = Vec<AddressAlias>)),
+    security(("mailbox" = []))
+)]
+pub async fn list_aliases(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<AddressAlias>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let aliases = list_address_aliases(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(aliases))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/aliases",
+    tag = "api",
+    summary = "Add an address delivering into the mailbox",
+    params(("address" = String, Path)),
+    request_body = CreateAliasBody,
+    responses((status = 200, description = "OK", body = AddressAlias)),
+    security(("mailbox" = []))
+)]
+pub async fn create_alias(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<CreateAliasBody>,
+) -> Result<(StatusCode, Json<AddressAlias>), Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let blocked = state.blocklist.get(&pool).await.map_err(db_error)?;
+    let local = validate_username(&body.username, &blocked)
+        .map_err(|msg| err(StatusCode::BAD_REQUEST, &msg))?;
+    let Some((_, domain)) = temp.temp_email_addr.rsplit_once('@') else {
+        return Err(err(StatusCode::BAD_REQUEST, "mailbox has no domain"));
+    };
+
+    let existing = list_address_aliases(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    if existing.len() >= MAX_ALIASES {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can have at most {MAX_ALIASES} aliases"),
+        ));
+    }
+    let alias = insert_address_alias(&pool, temp.id, &full_address(&local, domain))
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::CONFLICT, "address is taken"))?;
+    tracing::info!(addr = %temp.temp_email_addr, alias = %alias.alias_addr, "alias created");
+    Ok((StatusCode::CREATED, Json(alias)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/aliases/{alias}",
+    tag = "api",
+    summary = "Remove an alias",
+    params(("address" = String, Path), ("alias" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn delete_alias(
+    State(state): State<AppState>,
+    Path((address, alias)): Path<(String, String)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let deleted = delete_address_alias(&pool, temp.id, alias.trim())
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown alias"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
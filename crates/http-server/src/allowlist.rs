@@ -0,0 +1,161 @@
+//! Network allowlist for the admin API and developer endpoints (`/admin/*`,
+//! `/api/dev/*`), checked before the admin token. Behind a reverse proxy
+//! the client is taken from `X-Forwarded-For`, but only when the connection
+//! comes from one of the trusted proxies.
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use crate::api::err;
+use crate::AppState;
+
+/// Path prefixes the allowlist applies to.
+const GUARDED: &[&str] = &["/admin", "/api/dev"];
+
+/// An IPv4 or IPv6 network such as `10.0.0.0/8`; a bare address is a
+/// network of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                (u128::from(u32::from(net)), u128::from(u32::from(ip)), 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        (net ^ ip)
+            .checked_shr(bits - u32::from(self.prefix))
+            .unwrap_or(0)
+            == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = canonical(
+            addr.parse::<IpAddr>()
+                .map_err(|_| format!("{s:?} is not an IP address or network"))?,
+        );
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|&p| p <= max)
+                .ok_or_else(|| format!("{s:?} has an invalid prefix length"))?,
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) as plain IPv4, as a
+/// dual-stack listener reports them.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IpAllowlist {
+    /// Empty allows everyone.
+    pub allowed: Vec<Cidr>,
+    /// Peers whose `X-Forwarded-For` is believed.
+    pub trusted_proxies: Vec<Cidr>,
+}
+
+impl IpAllowlist {
+    pub fn is_enabled(&self) -> bool {
+        !self.allowed.is_empty()
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        !self.is_enabled() || self.allowed.iter().any(|c| c.contains(ip))
+    }
+
+    /// The client behind `peer`: the right-most `X-Forwarded-For` entry not
+    /// added by a trusted proxy, or `peer` itself.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let trusted = |ip: IpAddr| self.trusted_proxies.iter().any(|c| c.contains(ip));
+        if !trusted(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        let hops = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .collect::<Vec<_>>();
+        for hop in hops.into_iter().rev() {
+            let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// Answers **403** to guarded paths from outside the allowlist. Requests
+/// whose peer address is unknown are refused too.
+pub async fn restrict(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let allowlist = &state.admin_allowlist;
+    let path = req.uri().path();
+    let guarded = GUARDED.iter().any(|p| {
+        path.strip_prefix(p)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    });
+    if !guarded || !allowlist.is_enabled() {
+        return next.run(req).await;
+    }
+
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let client = peer.map(|peer| allowlist.client_ip(peer, req.headers()));
+    if client.is_some_and(|ip| allowlist.allows(ip)) {
+        return next.run(req).await;
+    }
+
+    tracing::warn!(
+        client = ?client,
+        peer = ?peer,
+        method = %req.method(),
+        path,
+        "admin request refused by IP allowlist"
+    );
+    metrics::counter!("admin_ip_denied_total").increment(1);
+    err(StatusCode::FORBIDDEN, "access denied")
+}
@@ -1,52 +1,164 @@
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    Json,
+    Extension, Json,
 };
 use chrono::{DateTime, Utc};
 use db::{
-    find_temporary_email_by_addr, insert_temporary_email, list_received_emails, ReceivedEmail,
+    attribute_temporary_emails, claim_temporary_email, count_unread_emails, extend_temporary_email,
+    fetch_mailbox_token_hash, find_temporary_email_by_addr, list_received_emails,
+    list_temporary_emails_by_owner, reactivate_temporary_email, replace_mailbox_token_hash,
+    search_emails_by_address, BatchKey, LocalPartBlocklist, QuotaWarning, ReceivedEmail,
+    TemporaryEmail,
 };
-use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::{IntoParams, ToSchema};
 
+use crate::address::{
+    create_temporary_email, create_temporary_email_batch, AddressOptions, CreateAddressError,
+};
+use crate::generator;
+use crate::html;
+use crate::latency;
+use crate::lookup;
+use crate::metering::MeteredKey;
+use crate::policy::{self, MailboxPolicy};
+use crate::quota;
+use crate::session::SessionClaims;
+use crate::token;
 use crate::AppState;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = CreateAddress)]
 pub struct CreateTempAddressBody {
     pub username: Option<String>,
+    #[serde(default)]
+    pub allow_suffix: bool,
+    /// Readable by anyone who knows the address; see [`MailboxPolicy`].
+    #[serde(default)]
+    pub public: bool,
+    /// Reject mail and serve nothing until then; the address expires
+    /// [`db::ADDRESS_TTL`] after it.
+    pub activate_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = Address)]
 pub struct CreateTempAddressResponse {
     pub temp_email_addr: String,
+    pub expires_at: DateTime<Utc>,
+    pub public: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub activate_at: Option<DateTime<Utc>>,
+    /// Only present when the address is created; it cannot be retrieved later.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_token: Option<String>,
+}
+
+impl From<TemporaryEmail> for CreateTempAddressResponse {
+    fn from(row: TemporaryEmail) -> Self {
+        Self {
+            temp_email_addr: row.temp_email_addr,
+            expires_at: row.expires_at,
+            public: row.is_public,
+            activate_at: row.activate_at,
+            access_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = AccessToken)]
+pub struct AccessTokenResponse {
+    pub access_token: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize)]
+pub struct UsernameTakenResponse {
+    pub error: &'static str,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = GenerateBatch)]
+pub struct GenerateBatchBody {
+    #[schema(minimum = 1, maximum = 100)]
+    pub count: usize,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = ExtendAddress)]
+pub struct ExtendAddressBody {
+    #[schema(minimum = 1)]
+    pub minutes: u32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct InboxByAddressQuery {
     pub address: String,
     pub since: Option<String>,
+    /// `true` for bounces only, `false` to leave them out; both when unset.
+    pub bounces: Option<bool>,
+    /// Only mail sent to `local+tag@domain`.
+    pub tag: Option<String>,
+    /// Alternative to `Authorization: Bearer …`.
+    pub token: Option<String>,
+    /// Sanitize HTML bodies as `/html` serves them.
+    #[serde(default)]
+    pub sanitized: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchMailboxQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = SearchResults)]
+pub struct SearchMailboxResponse {
+    pub temp_email_addr: String,
+    pub messages: Vec<ReceivedEmail>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = Inbox)]
 pub struct PollInboxResponse {
     pub temp_email_addr: String,
     pub new_mail_count: usize,
+    /// Unread messages in the whole mailbox, not only those returned.
+    pub unread_count: i64,
     pub next_since: Option<DateTime<Utc>>,
     pub messages: Vec<ReceivedEmail>,
+    /// Quotas at least 80% used; see [`quota`].
+    pub warnings: Vec<QuotaWarning>,
 }
 
-fn err(status: StatusCode, msg: &str) -> Response {
+pub(crate) fn err(status: StatusCode, msg: &str) -> Response {
     (status, msg.to_owned()).into_response()
 }
 
-fn db_error(e: sqlx::Error) -> Response {
+/// Why a mailbox that is not live cannot be used: **409** while it waits for
+/// its `activate_at`, **410** once it has expired.
+pub(crate) fn not_live(temp: &TemporaryEmail) -> Response {
+    if temp.is_scheduled() {
+        err(StatusCode::CONFLICT, "address is not active yet")
+    } else {
+        err(StatusCode::GONE, "temporary address has expired")
+    }
+}
+
+pub(crate) fn db_error(e: sqlx::Error) -> Response {
     tracing::error!(error = %e, "database");
     err(StatusCode::INTERNAL_SERVER_ERROR, "database error")
 }
 
-async fn require_pool(state: &AppState) -> Result<sqlx::postgres::PgPool, Response> {
+pub(crate) async fn require_pool(state: &AppState) -> Result<sqlx::postgres::PgPool, Response> {
     state
         .pool
         .read()
@@ -55,105 +167,544 @@ async fn require_pool(state: &AppState) -> Result<sqlx::postgres::PgPool, Respon
         .ok_or_else(|| err(StatusCode::SERVICE_UNAVAILABLE, "database not ready"))
 }
 
-fn is_unique_violation(e: &sqlx::Error) -> bool {
-    matches!(e, sqlx::Error::Database(dbe) if dbe.code().is_some_and(|c| c == "23505"))
+impl IntoResponse for CreateAddressError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::FailedToFindUniqueName => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, "1")],
+                "could not allocate a unique address; try again",
+            )
+                .into_response(),
+            Self::UsernameTaken { suggestions } => (
+                StatusCode::CONFLICT,
+                Json(UsernameTakenResponse {
+                    error: "username is taken",
+                    suggestions,
+                }),
+            )
+                .into_response(),
+            Self::IdempotencyKeyReused => err(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request",
+            ),
+            Self::BatchGone => err(
+                StatusCode::CONFLICT,
+                "the batch of this Idempotency-Key has expired",
+            ),
+            Self::Database(e) => db_error(e),
+        }
+    }
 }
 
+/// Addresses created with a logged-in session belong to that account.
+#[utoipa::path(
+    post,
+    path = "/api/temporary-address",
+    tag = "api",
+    summary = "Create an address",
+    request_body = CreateTempAddressBody,
+    responses((status = 200, description = "OK", body = CreateTempAddressResponse))
+)]
 pub async fn create_temporary_address(
     State(state): State<AppState>,
+    session: Option<SessionClaims>,
+    metered: Option<Extension<MeteredKey>>,
     Json(body): Json<CreateTempAddressBody>,
 ) -> Result<Json<CreateTempAddressResponse>, Response> {
     let pool = require_pool(&state).await?;
-    let domain = &*state.mail_domain;
-
-    for _ in 0..3u8 {
-        let addr = full_address(&generate_local_part(body.username.as_deref()), domain);
-        match insert_temporary_email(&pool, &addr).await {
-            Ok(row) => {
-                return Ok(Json(CreateTempAddressResponse {
-                    temp_email_addr: row.temp_email_addr,
-                }))
-            }
-            Err(e) if is_unique_violation(&e) => continue,
-            Err(e) => return Err(db_error(e)),
+    let blocked = state.blocklist.get(&pool).await.map_err(db_error)?;
+
+    let username = requested_username(body.username.as_deref(), &blocked)
+        .map_err(|msg| err(StatusCode::BAD_REQUEST, &msg))?;
+    if let Some(at) = body.activate_at {
+        let now = Utc::now();
+        if at <= now {
+            return Err(err(
+                StatusCode::BAD_REQUEST,
+                "activate_at must be in the future",
+            ));
+        }
+        let ahead = (at - now).to_std().unwrap_or_default();
+        if ahead > state.max_address_lifetime {
+            return Err(err(StatusCode::BAD_REQUEST, "activate_at is too far ahead"));
         }
     }
 
-    Err(err(
-        StatusCode::CONFLICT,
-        "could not allocate a unique address; try again",
+    let access_token = token::generate();
+    let row = create_temporary_email(
+        &pool,
+        &state.mail_domain,
+        &blocked,
+        username.as_deref(),
+        body.allow_suffix,
+        AddressOptions {
+            public: body.public,
+            activate_at: body.activate_at,
+            token_hash: &token::hash(&state.token_pepper, &access_token),
+        },
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    if let Some(user_id) = session.and_then(|s| s.uid) {
+        claim_temporary_email(&pool, row.id, user_id)
+            .await
+            .map_err(db_error)?;
+    }
+    bill(&pool, metered, &[row.id]).await?;
+
+    Ok(Json(CreateTempAddressResponse {
+        access_token: Some(access_token),
+        ..row.into()
+    }))
+}
+
+fn requested_username(
+    raw: Option<&str>,
+    blocked: &LocalPartBlocklist,
+) -> Result<Option<String>, String> {
+    raw.map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|raw| generator::validate_username(raw, blocked))
+        .transpose()
+}
+
+pub const MAX_BATCH_COUNT: usize = 100;
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+#[utoipa::path(
+    post,
+    path = "/api/email/generate-batch",
+    tag = "api",
+    summary = "Create up to 100 addresses at once",
+    request_body = GenerateBatchBody,
+    responses((status = 200, description = "OK", body = Vec<CreateTempAddressResponse>))
+)]
+pub async fn generate_batch(
+    State(state): State<AppState>,
+    session: Option<SessionClaims>,
+    metered: Option<Extension<MeteredKey>>,
+    headers: HeaderMap,
+    Json(body): Json<GenerateBatchBody>,
+) -> Result<Json<Vec<CreateTempAddressResponse>>, Response> {
+    let pool = require_pool(&state).await?;
+
+    if body.count == 0 || body.count > MAX_BATCH_COUNT {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("count must be between 1 and {MAX_BATCH_COUNT}"),
+        ));
+    }
+
+    let blocked = state.blocklist.get(&pool).await.map_err(db_error)?;
+    let username = requested_username(body.username.as_deref(), &blocked)
+        .map_err(|msg| err(StatusCode::BAD_REQUEST, &msg))?;
+    let key = idempotency_key(&headers).map_err(|msg| err(StatusCode::BAD_REQUEST, msg))?;
+    let key = match key {
+        Some(key) => Some(scoped_idempotency_key(
+            &key,
+            metered.as_ref().map(|Extension(m)| m),
+            session.as_ref(),
+        )?),
+        None => None,
+    };
+    let request_hash = batch_request_hash(body.count, username.as_deref());
+    let batch = key.as_deref().map(|idempotency_key| BatchKey {
+        idempotency_key,
+        request_hash: &request_hash,
+    });
+
+    let rows = create_temporary_email_batch(
+        &pool,
+        &state.mail_domain,
+        &blocked,
+        &state.token_pepper,
+        batch,
+        body.count,
+        username.as_deref(),
+    )
+    .await
+    .map_err(IntoResponse::into_response)?;
+
+    let ids: Vec<_> = rows.iter().map(|(r, _)| r.id).collect();
+    bill(&pool, metered, &ids).await?;
+
+    Ok(Json(
+        rows.into_iter()
+            .map(|(row, access_token)| CreateTempAddressResponse {
+                access_token: Some(access_token),
+                ..row.into()
+            })
+            .collect(),
     ))
 }
 
+async fn bill(
+    pool: &sqlx::postgres::PgPool,
+    metered: Option<Extension<MeteredKey>>,
+    ids: &[uuid::Uuid],
+) -> Result<(), Response> {
+    if let Some(Extension(MeteredKey(key))) = metered {
+        attribute_temporary_emails(pool, ids, key)
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, &'static str> {
+    let Some(raw) = headers.get("idempotency-key") else {
+        return Ok(None);
+    };
+    let key = raw
+        .to_str()
+        .map(str::trim)
+        .map_err(|_| "invalid Idempotency-Key header")?;
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err("invalid Idempotency-Key header");
+    }
+    Ok(Some(key.to_owned()))
+}
+
+/// Prefixes an idempotency key with its caller, the API key or else the
+/// account or session, so that callers cannot replay each other's batches.
+fn scoped_idempotency_key(
+    key: &str,
+    metered: Option<&MeteredKey>,
+    session: Option<&SessionClaims>,
+) -> Result<String, Response> {
+    let scope = match (metered, session) {
+        (Some(MeteredKey(id)), _) => format!("api-key:{id}"),
+        (None, Some(SessionClaims { uid: Some(uid), .. })) => format!("user:{uid}"),
+        (None, Some(session)) => format!("session:{}", session.sid),
+        (None, None) => {
+            return Err(err(
+                StatusCode::BAD_REQUEST,
+                "Idempotency-Key needs an API key or a session",
+            ))
+        }
+    };
+    Ok(format!("{scope}/{key}"))
+}
+
+/// What a replay must match for its idempotency key to return the batch.
+fn batch_request_hash(count: usize, username: Option<&str>) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(count.to_be_bytes());
+    if let Some(username) = username {
+        hasher.update(b"\0");
+        hasher.update(username.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/inbox/poll",
+    tag = "api",
+    summary = "Messages delivered to an address",
+    params(InboxByAddressQuery),
+    responses((status = 200, description = "OK", body = PollInboxResponse)),
+    security(("mailbox" = []))
+)]
 pub async fn poll_inbox_by_address(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(q): Query<InboxByAddressQuery>,
-) -> Result<Json<PollInboxResponse>, Response> {
+) -> Result<Response, Response> {
     let pool = require_pool(&state).await?;
 
     let addr = q.address.trim();
     if addr.is_empty() || !addr.contains('@') {
-        return Err(err(StatusCode::BAD_REQUEST, "invalid or missing address"));
+        return Err(lookup::not_found());
     }
 
     let temp = find_temporary_email_by_addr(&pool, addr)
         .await
         .map_err(db_error)?
-        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown temporary address"))?;
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    if state.poll_requires_token {
+        let presented = token::bearer(&headers).or(q.token.as_deref());
+        policy::authenticate(&state, &pool, &temp, presented).await?;
+    }
+    if !temp.is_live() {
+        return Err(not_live(&temp));
+    }
 
     let since =
         parse_since(q.since.as_deref()).map_err(|msg| err(StatusCode::BAD_REQUEST, &msg))?;
+    let oldest_visible =
+        MailboxPolicy::of(&temp, state.public_retention).oldest_visible(Utc::now());
 
-    let messages = list_received_emails(&pool, temp.id, since)
+    let tag = q.tag.as_deref().map(str::trim).filter(|t| !t.is_empty());
+    let visible = since.max(oldest_visible);
+    let list = list_received_emails(&pool, temp.id, visible, q.bounces, tag);
+    let mut messages = db::timed("http", "list_messages", list)
         .await
         .map_err(db_error)?;
+    if q.sanitized {
+        for message in &mut messages {
+            html::sanitize_body(message, &state.html_display);
+        }
+    }
+
+    let ids: Vec<_> = messages.iter().map(|m| m.id).collect();
+    latency::record_first_read(&pool, &ids).await;
+    let unread_count = count_unread_emails(&pool, temp.id, oldest_visible)
+        .await
+        .map_err(db_error)?;
+    let warnings = quota::warnings(&state, &pool, temp.id).await?;
 
     let new_mail_count = messages.len();
     let next_since = messages.iter().map(|m| m.received_at).max().or(since);
 
-    Ok(Json(PollInboxResponse {
+    let response = Json(PollInboxResponse {
         temp_email_addr: temp.temp_email_addr,
         new_mail_count,
+        unread_count,
         next_since,
         messages,
+        warnings: warnings.clone(),
+    });
+    Ok(quota::with_headers(response, &warnings))
+}
+
+const MAX_SEARCH_QUERY_LEN: usize = 200;
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+/// Full-text search over subjects and plain-text bodies, best match first.
+/// Public mailboxes only search what they would show.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/search",
+    tag = "api",
+    summary = "Full-text search of a mailbox",
+    params(("address" = String, Path), SearchMailboxQuery),
+    responses((status = 200, description = "OK", body = SearchMailboxResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn search_mailbox(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<SearchMailboxQuery>,
+) -> Result<Json<SearchMailboxResponse>, Response> {
+    let pool = require_pool(&state).await?;
+
+    let addr = address.trim().to_ascii_lowercase();
+    if !addr.contains('@') {
+        return Err(lookup::not_found());
+    }
+    let temp = find_temporary_email_by_addr(&pool, &addr)
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    if !temp.is_live() {
+        return Err(not_live(&temp));
+    }
+
+    let query = q.q.trim();
+    if query.is_empty() {
+        return Err(err(StatusCode::BAD_REQUEST, "search query is required"));
+    }
+    if query.len() > MAX_SEARCH_QUERY_LEN {
+        return Err(err(StatusCode::BAD_REQUEST, "search query is too long"));
+    }
+    let limit = q
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let oldest_visible =
+        MailboxPolicy::of(&temp, state.public_retention).oldest_visible(Utc::now());
+
+    let messages = search_emails_by_address(&pool, &addr, query, oldest_visible, limit)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(SearchMailboxResponse {
+        temp_email_addr: temp.temp_email_addr,
+        messages,
     }))
 }
 
-fn parse_since(s: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
-    let Some(raw) = s.map(str::trim).filter(|x| !x.is_empty()) else {
-        return Ok(None);
-    };
-    DateTime::parse_from_rfc3339(raw)
-        .map(|dt| Some(dt.with_timezone(&Utc)))
-        .map_err(|_| format!("since must be RFC3339, got {raw:?}"))
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/reactivate",
+    tag = "api",
+    summary = "Bring back an expired address",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = CreateTempAddressResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn reactivate_address(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<CreateTempAddressResponse>, Response> {
+    let pool = require_pool(&state).await?;
+
+    let addr = address.trim().to_ascii_lowercase();
+    if !addr.contains('@') {
+        return Err(lookup::not_found());
+    }
+
+    if let Some(row) = reactivate_temporary_email(&pool, &addr, state.reactivation_grace)
+        .await
+        .map_err(db_error)?
+    {
+        tracing::info!(addr = %row.temp_email_addr, "address reactivated");
+        return Ok(Json(row.into()));
+    }
+
+    // Nothing was updated; work out why for the caller.
+    match find_temporary_email_by_addr(&pool, &addr)
+        .await
+        .map_err(db_error)?
+    {
+        Some(t) if t.is_honeypot => Err(lookup::not_found()),
+        Some(t) if t.is_live() || t.is_scheduled() => {
+            Err(err(StatusCode::CONFLICT, "address has not expired"))
+        }
+        Some(_) => Err(err(StatusCode::GONE, "reactivation window has passed")),
+        None => Err(lookup::not_found()),
+    }
 }
 
-fn rand_lower(rng: &mut impl Rng, len: usize) -> String {
-    rng.sample_iter(&Alphanumeric)
-        .take(len)
-        .map(|b| (b as char).to_ascii_lowercase())
-        .collect()
+/// Longest single extension; more needs another call.
+pub const MAX_EXTEND_MINUTES: u32 = 24 * 60;
+
+/// Moves a live address's expiry later, up to `max_address_lifetime` after it
+/// was created.
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/extend",
+    tag = "api",
+    summary = "Push back an address's expiry",
+    params(("address" = String, Path)),
+    request_body = ExtendAddressBody,
+    responses((status = 200, description = "OK", body = CreateTempAddressResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn extend_address(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<ExtendAddressBody>,
+) -> Result<Json<CreateTempAddressResponse>, Response> {
+    let pool = require_pool(&state).await?;
+
+    let addr = address.trim().to_ascii_lowercase();
+    if !addr.contains('@') {
+        return Err(lookup::not_found());
+    }
+    if body.minutes == 0 || body.minutes > MAX_EXTEND_MINUTES {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("minutes must be between 1 and {MAX_EXTEND_MINUTES}"),
+        ));
+    }
+
+    let by = std::time::Duration::from_secs(u64::from(body.minutes) * 60);
+    if let Some(row) = extend_temporary_email(&pool, &addr, by, state.max_address_lifetime)
+        .await
+        .map_err(db_error)?
+    {
+        tracing::info!(addr = %row.temp_email_addr, until = %row.expires_at, "address extended");
+        return Ok(Json(row.into()));
+    }
+
+    match find_temporary_email_by_addr(&pool, &addr)
+        .await
+        .map_err(db_error)?
+    {
+        Some(t) if t.is_honeypot => Err(lookup::not_found()),
+        Some(t) if t.is_live() || t.is_scheduled() => Err(err(
+            StatusCode::CONFLICT,
+            "address has reached its maximum lifetime",
+        )),
+        Some(_) => Err(err(StatusCode::GONE, "temporary address has expired")),
+        None => Err(lookup::not_found()),
+    }
 }
 
-fn full_address(local: &str, domain: &str) -> String {
-    format!("{local}@{domain}")
+#[utoipa::path(
+    get,
+    path = "/api/account/addresses",
+    tag = "api",
+    summary = "Addresses owned by the account",
+    responses((status = 200, description = "OK", body = Vec<CreateTempAddressResponse>)),
+    security(("session" = []))
+)]
+pub async fn list_account_addresses(
+    State(state): State<AppState>,
+    session: SessionClaims,
+) -> Result<Json<Vec<CreateTempAddressResponse>>, Response> {
+    let user_id = session
+        .uid
+        .ok_or_else(|| err(StatusCode::UNAUTHORIZED, "login required"))?;
+    let pool = require_pool(&state).await?;
+    let rows = list_temporary_emails_by_owner(&pool, user_id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
 }
 
-fn generate_local_part(username: Option<&str>) -> String {
-    let mut rng = rand::thread_rng();
+/// Issues a new access token, invalidating the current one. A mailbox that
+/// never had a token, such as a catch-all address, gets its first one here
+/// only for a session of the owning account; an admin can issue one with
+/// `POST /admin/addresses/:address/token`.
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/token",
+    tag = "api",
+    summary = "Replace the mailbox access token",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = AccessTokenResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn rotate_token(
+    State(state): State<AppState>,
+    session: Option<SessionClaims>,
+    Path(address): Path<String>,
+) -> Result<Json<AccessTokenResponse>, Response> {
+    let pool = require_pool(&state).await?;
+    let temp = find_temporary_email_by_addr(&pool, &address.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .ok_or_else(lookup::not_found)?;
+    // The policy layer has already checked the caller's token or session,
+    // but lets anyone through to a token-less mailbox when
+    // `open_tokenless_mailboxes` is set.
+    let current = fetch_mailbox_token_hash(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    let owner = temp.user_id.is_some() && session.and_then(|s| s.uid) == temp.user_id;
+    if current.is_none() && !owner {
+        return Err(lookup::not_found());
+    }
 
-    let prefix = username
-        .map(str::trim)
-        .filter(|s| !s.is_empty())
-        .map(|s| {
-            s.chars()
-                .filter(|c| c.is_ascii_alphanumeric())
-                .take(5)
-                .collect::<String>()
-                .to_lowercase()
-        })
-        .filter(|s| !s.is_empty())
-        .unwrap_or_else(|| rand_lower(&mut rng, 5));
+    let access_token = token::generate();
+    let rotated = replace_mailbox_token_hash(
+        &pool,
+        temp.id,
+        current.as_deref(),
+        &token::hash(&state.token_pepper, &access_token),
+    )
+    .await
+    .map_err(db_error)?;
+    if !rotated {
+        return Err(lookup::not_found());
+    }
 
-    format!("{prefix}{}", rand_lower(&mut rng, 3))
+    tracing::info!(addr = %temp.temp_email_addr, "access token rotated");
+    Ok(Json(AccessTokenResponse { access_token }))
+}
+
+fn parse_since(s: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(raw) = s.map(str::trim).filter(|x| !x.is_empty()) else {
+        return Ok(None);
+    };
+    DateTime::parse_from_rfc3339(raw)
+        .map(|dt| Some(dt.with_timezone(&Utc)))
+        .map_err(|_| format!("since must be RFC3339, got {raw:?}"))
 }
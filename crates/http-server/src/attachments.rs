@@ -0,0 +1,158 @@
+//! Downloads belonging to a received message: its attachments, its
+//! original source and its header fields.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use db::{fetch_attachment, fetch_email_headers, fetch_raw_email, list_attachments, Attachment};
+use uuid::Uuid;
+
+use crate::api::{db_error, err};
+use crate::share::owned_email;
+use crate::{throttle, AppState};
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/attachments",
+    tag = "api",
+    summary = "List attachments",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn list_email_attachments(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Json<Vec<Attachment>>, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let attachments = list_attachments(&pool, email.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(attachments))
+}
+
+/// Serves the stored bytes as a download. The declared type is passed
+/// through, but `nosniff` and `attachment` keep browsers from rendering it.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/attachments/{attachment_id}",
+    tag = "api",
+    summary = "Download an attachment",
+    params(("address" = String, Path), ("email_id" = Uuid, Path), ("attachment_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn download_attachment(
+    State(state): State<AppState>,
+    Path((address, email_id, attachment_id)): Path<(String, Uuid, Uuid)>,
+) -> Result<Response, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let (attachment, content) = fetch_attachment(&pool, email.id, attachment_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "unknown attachment"))?;
+
+    let content_type = HeaderValue::from_str(&attachment.content_type)
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let disposition = HeaderValue::from_str(&content_disposition(attachment.filename.as_deref()))
+        .unwrap_or(HeaderValue::from_static("attachment"));
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+            (header::CONTENT_LENGTH, HeaderValue::from(content.len())),
+        ],
+        throttle::body(content, state.download_bandwidth),
+    )
+        .into_response())
+}
+
+/// `attachment` with an ASCII fallback name plus the exact name as
+/// `filename*` (RFC 6266), so odd characters can't break out of the header.
+pub(crate) fn content_disposition(filename: Option<&str>) -> String {
+    let Some(name) = filename.map(str::trim).filter(|n| !n.is_empty()) else {
+        return "attachment".into();
+    };
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' | '!' | '#'..='[' | ']'..='~' if c != '%' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::new();
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    format!("attachment; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// The message exactly as received over SMTP, for checking headers such as
+/// DKIM signatures or importing into a mail client. Redacted messages have
+/// no source left.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/raw",
+    tag = "api",
+    summary = "Original message as message/rfc822",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn download_raw(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Response, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let raw = fetch_raw_email(&pool, email.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "original message not stored"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "message/rfc822".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.eml\"", email.id),
+            ),
+            (header::CONTENT_LENGTH, raw.len().to_string()),
+        ],
+        throttle::body(raw, state.download_bandwidth),
+    )
+        .into_response())
+}
+
+/// Header fields as `{"received": ["…", "…"], "subject": ["…"], …}`: names
+/// lowercased, every value of a repeated field kept in order. Messages
+/// stored before headers were kept, and redacted ones, have none.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/headers",
+    tag = "api",
+    summary = "Header fields as JSON",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_headers(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let headers = fetch_email_headers(&pool, email.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "headers not stored"))?;
+    Ok(Json(headers))
+}
@@ -0,0 +1,125 @@
+//! `/api/email/:address/block`: senders a mailbox no longer wants mail
+//! from. The SMTP server checks the envelope sender and the `From:` address
+//! of every message against them, and against the global list kept under
+//! `/admin/blocked-senders`; a blocked message is not stored, so it reaches
+//! neither the inbox nor the mailbox's webhooks.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::{delete_blocked_sender, insert_blocked_sender, list_blocked_senders, BlockedSender};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use crate::api::{db_error, err};
+use crate::config::is_hostname;
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+/// Senders one mailbox may block.
+pub const MAX_BLOCKED_SENDERS: usize = 100;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = BlockSender)]
+pub struct BlockSenderBody {
+    /// An address, or a domain to block it and its subdomains.
+    pub sender: String,
+}
+
+/// `sender` lowercased, or why it cannot be blocked. A bare TLD is refused,
+/// as it would block a large part of the internet.
+pub(crate) fn normalize_sender(sender: &str) -> Result<String, Response> {
+    let sender = sender.trim().trim_start_matches('@').to_ascii_lowercase();
+    let domain = match sender.rsplit_once('@') {
+        Some((local, domain)) if !local.is_empty() => domain,
+        Some(_) => "",
+        None => &sender,
+    };
+    if !is_hostname(domain) || !domain.contains('.') {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "sender must be an address or a domain such as example.com",
+        ));
+    }
+    Ok(sender)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/block",
+    tag = "api",
+    summary = "List senders the mailbox blocks",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = Vec<BlockedSender>)),
+    security(("mailbox" = []))
+)]
+pub async fn list_blocks(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<BlockedSender>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let blocked = list_blocked_senders(&pool, Some(temp.id))
+        .await
+        .map_err(db_error)?;
+    Ok(Json(blocked))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/block",
+    tag = "api",
+    summary = "Drop mail from an address or domain",
+    params(("address" = String, Path)),
+    request_body = BlockSenderBody,
+    responses((status = 200, description = "OK", body = BlockedSender)),
+    security(("mailbox" = []))
+)]
+pub async fn block_sender(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<BlockSenderBody>,
+) -> Result<(StatusCode, Json<BlockedSender>), Response> {
+    let sender = normalize_sender(&body.sender)?;
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let existing = list_blocked_senders(&pool, Some(temp.id))
+        .await
+        .map_err(db_error)?;
+    if existing.len() >= MAX_BLOCKED_SENDERS {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can block at most {MAX_BLOCKED_SENDERS} senders"),
+        ));
+    }
+    let blocked = insert_blocked_sender(&pool, Some(temp.id), &sender)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::CONFLICT, "sender already blocked"))?;
+    tracing::info!(addr = %temp.temp_email_addr, sender = %blocked.sender, "sender blocked");
+    Ok((StatusCode::CREATED, Json(blocked)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/block/{sender}",
+    tag = "api",
+    summary = "Unblock a sender",
+    params(("address" = String, Path), ("sender" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn unblock_sender(
+    State(state): State<AppState>,
+    Path((address, sender)): Path<(String, String)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let deleted = delete_blocked_sender(&pool, Some(temp.id), sender.trim())
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "sender is not blocked"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
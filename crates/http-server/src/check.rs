@@ -0,0 +1,143 @@
+//! `--check`: validate a deployment without serving traffic.
+
+use db::{connect_pool, pending_migrations};
+use std::time::Duration;
+
+use crate::config::{Config, ConfigError};
+use crate::dns;
+
+const DB_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+pub(crate) struct Report {
+    pub failed: usize,
+    pub warned: usize,
+}
+
+impl Report {
+    pub fn ok(&mut self, what: &str, detail: impl AsRef<str>) {
+        println!("[ok]   {what}: {}", detail.as_ref());
+    }
+
+    pub fn warn(&mut self, what: &str, detail: impl AsRef<str>) {
+        self.warned += 1;
+        println!("[warn] {what}: {}", detail.as_ref());
+    }
+
+    pub fn fail(&mut self, what: &str, detail: impl AsRef<str>) {
+        self.failed += 1;
+        println!("[fail] {what}: {}", detail.as_ref());
+    }
+
+    pub fn skip(&mut self, what: &str, detail: impl AsRef<str>) {
+        println!("[skip] {what}: {}", detail.as_ref());
+    }
+}
+
+/// Prints a readiness report to stdout and returns the process exit code:
+/// 0 when nothing failed (warnings allowed), 1 otherwise.
+pub async fn run(config: Result<Config, Vec<ConfigError>>) -> i32 {
+    let mut report = Report::default();
+
+    let config = match config {
+        Ok(c) => {
+            report.ok("config", "all settings valid");
+            Some(c)
+        }
+        Err(errors) => {
+            for e in &errors {
+                report.fail("config", e.to_string());
+            }
+            None
+        }
+    };
+
+    check_database(&mut report).await;
+
+    match &config {
+        Some(config) => check_dns(&mut report, config).await,
+        None => report.skip("dns", "config invalid"),
+    }
+
+    report.skip(
+        "tls",
+        "HTTPS is terminated by the reverse proxy; SMTP does not offer STARTTLS",
+    );
+
+    println!(
+        "\n{} failed, {} warnings — {}",
+        report.failed,
+        report.warned,
+        if report.failed == 0 {
+            "ready"
+        } else {
+            "not ready"
+        }
+    );
+    i32::from(report.failed > 0)
+}
+
+async fn check_database(report: &mut Report) {
+    let pool = match tokio::time::timeout(DB_TIMEOUT, connect_pool()).await {
+        Ok(Ok(pool)) => pool,
+        Ok(Err(e)) => return report.fail("database", format!("connect failed: {e}")),
+        Err(_) => {
+            return report.fail(
+                "database",
+                format!("connect timed out after {}s", DB_TIMEOUT.as_secs()),
+            )
+        }
+    };
+    report.ok("database", "connected");
+
+    match pending_migrations(&pool).await {
+        Ok(pending) if pending.is_empty() => report.ok("migrations", "all applied"),
+        // The server applies these on startup, so this is not fatal.
+        Ok(pending) => report.warn(
+            "migrations",
+            format!(
+                "{} pending ({}), will be applied on startup",
+                pending.len(),
+                pending
+                    .iter()
+                    .map(|(version, desc)| format!("{version} {desc}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        ),
+        Err(e) => report.fail("migrations", e.to_string()),
+    }
+}
+
+async fn check_dns(report: &mut Report, config: &Config) {
+    let resolver = dns::resolver();
+    let domain = &*config.mail_domain;
+
+    match dns::lookup_mx(&resolver, domain).await {
+        Err(e) => report.fail("mx", format!("lookup for {domain} failed: {e}")),
+        Ok(hosts) if hosts.is_empty() => report.fail("mx", format!("{domain} has no MX records")),
+        Ok(hosts) => {
+            let listed = hosts
+                .iter()
+                .map(|h| format!("{} {}", h.preference, h.exchange))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match config.public_ip {
+                None => report.warn("mx", format!("{listed} (PUBLIC_IP unset, not compared)")),
+                Some(ip) if hosts.iter().any(|h| h.addrs.contains(&ip)) => {
+                    report.ok("mx", format!("{listed} → {ip}"))
+                }
+                Some(ip) => report.fail("mx", format!("{listed}; none resolve to {ip}")),
+            }
+        }
+    }
+
+    let Some(ip) = config.public_ip else {
+        return report.skip("ptr", "PUBLIC_IP unset");
+    };
+    match dns::lookup_ptr(&resolver, ip).await {
+        Err(e) => report.warn("ptr", format!("lookup for {ip} failed: {e}")),
+        Ok(names) if names.is_empty() => report.warn("ptr", format!("{ip} has no PTR record")),
+        Ok(names) => report.ok("ptr", format!("{ip} → {}", names.join(", "))),
+    }
+}
@@ -0,0 +1,872 @@
+use axum::http::HeaderValue;
+use db::{MailboxQuota, PurgeOptions};
+use imap::ImapConfig;
+use outbound_policy::PolicyConfig;
+use outbound_relay::{RelayConfig, RelayCredentials, RelayPool, RelayTls};
+use smtp::{BuiltinStage, Pipeline, SmtpAuth, SmtpConfig};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::allowlist::{Cidr, IpAllowlist};
+use crate::forwarding::ForwardConfig;
+use crate::html::DisplayOptions;
+use crate::image_proxy::ImageProxyConfig;
+use crate::janitor::{JanitorConfig, Schedule};
+use crate::links::LinkCheckConfig;
+use crate::oidc::{OidcConfig, Provider};
+use crate::otp::OtpPatterns;
+use crate::read_hooks::ReadHooks;
+use crate::retry::RetryPolicy;
+use crate::session::{SessionConfig, SessionKeys};
+use crate::throttle::Bandwidth;
+use crate::token;
+use crate::webhooks::WebhookConfig;
+
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub mail_domain: Arc<str>,
+    pub http_host: String,
+    pub http_port: u16,
+    pub smtp_host: String,
+    pub smtp_port: u16,
+    pub janitor: JanitorConfig,
+    pub reactivation_grace: Duration,
+    pub max_address_lifetime: Duration,
+    pub admin_token: Option<Arc<str>>,
+    pub public_ip: Option<IpAddr>,
+    pub public_base_url: Option<Arc<str>>,
+    pub share_secret: Option<Arc<[u8]>>,
+    pub lookup_floor: Duration,
+    pub token_pepper: Option<Arc<[u8]>>,
+    pub sessions: SessionConfig,
+    /// Whether `SESSION_JWT_KEYS` was set; otherwise keys are per-process.
+    pub session_keys_configured: bool,
+    pub oidc: Option<OidcConfig>,
+    pub smtp: SmtpConfig,
+    pub imap_host: String,
+    /// Unset leaves the IMAP server off.
+    pub imap_port: Option<u16>,
+    pub imap: ImapConfig,
+    /// Headless-renderer sidecar for `/preview.png`; unset turns previews off.
+    pub renderer_url: Option<String>,
+    pub renderer_timeout: Duration,
+    pub allow_private_webhooks: bool,
+    pub webhooks: WebhookConfig,
+    /// Smarthosts for forwarded mail; unset turns forwarding off.
+    pub relays: Option<RelayConfig>,
+    pub outbound_policy: PolicyConfig,
+    pub forwarding: ForwardConfig,
+    pub download_bandwidth: Option<Bandwidth>,
+    pub poll_requires_token: bool,
+    pub open_tokenless_mailboxes: bool,
+    pub html_display: DisplayOptions,
+    /// Serves `/api/proxy/image`; unset leaves it off.
+    pub image_proxy: Option<ImageProxyConfig>,
+    /// Checks for `/links`; unset marks links `unchecked`.
+    pub link_check: Option<LinkCheckConfig>,
+    pub otp_patterns: OtpPatterns,
+    pub admin_allowlist: IpAllowlist,
+    /// The WASM plugins' `on_api_read` hooks.
+    pub read_hooks: ReadHooks,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Vec<ConfigError>> {
+        let mut env = Env::default();
+
+        let mail_domain = env
+            .optional("MAIL_DOMAIN")
+            .or_else(|| env.optional("DOMAIN"))
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if mail_domain.is_empty() {
+            env.error("MAIL_DOMAIN", "MAIL_DOMAIN or DOMAIN must be set");
+        } else if !is_hostname(&mail_domain) {
+            env.error(
+                "MAIL_DOMAIN",
+                format!("{mail_domain:?} is not a valid domain"),
+            );
+        }
+
+        let mut local_domains = vec![mail_domain.clone()];
+        if let Some(raw) = env.optional("SMTP_EXTRA_DOMAINS") {
+            for domain in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let domain = domain.to_ascii_lowercase();
+                if is_hostname(&domain) {
+                    local_domains.push(domain);
+                } else {
+                    env.error(
+                        "SMTP_EXTRA_DOMAINS",
+                        format!("{domain:?} is not a valid domain"),
+                    );
+                }
+            }
+        }
+
+        match env.optional("DATABASE_URL") {
+            None => env.error("DATABASE_URL", "must be set"),
+            Some(url) if !url.starts_with("postgres://") && !url.starts_with("postgresql://") => {
+                env.error("DATABASE_URL", "must be a postgres:// URL")
+            }
+            Some(_) => {}
+        }
+
+        let janitor = janitor_config(&mut env);
+
+        if let Some(raw) = env.optional("CORS_ALLOWED_ORIGINS") {
+            for origin in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let scheme_ok = origin.starts_with("http://") || origin.starts_with("https://");
+                if !scheme_ok || HeaderValue::from_str(origin).is_err() {
+                    env.error(
+                        "CORS_ALLOWED_ORIGINS",
+                        format!("{origin:?} is not an origin"),
+                    );
+                }
+            }
+        }
+
+        let public_base_url = env.optional("PUBLIC_BASE_URL");
+        if let Some(url) = &public_base_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                env.error("PUBLIC_BASE_URL", format!("{url:?} is not an http(s) URL"));
+            }
+        }
+
+        let max_address_lifetime = env.secs(
+            "ADDRESS_MAX_LIFETIME_SECS",
+            Duration::from_secs(7 * 24 * 60 * 60),
+        );
+        if max_address_lifetime < db::ADDRESS_TTL {
+            env.error(
+                "ADDRESS_MAX_LIFETIME_SECS",
+                format!("must be at least {}", db::ADDRESS_TTL.as_secs()),
+            );
+        }
+
+        let renderer_url = env.optional("RENDERER_URL");
+        if let Some(url) = &renderer_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                env.error("RENDERER_URL", format!("{url:?} is not an http(s) URL"));
+            }
+        }
+
+        let share_secret = env.optional("SHARE_LINK_SECRET");
+        if share_secret.as_ref().is_some_and(|s| s.len() < 32) {
+            env.error("SHARE_LINK_SECRET", "must be at least 32 bytes");
+        }
+
+        let token_pepper = env.optional("TOKEN_PEPPER");
+        if token_pepper
+            .as_ref()
+            .is_some_and(|p| p.len() > token::MAX_PEPPER_LEN)
+        {
+            env.error(
+                "TOKEN_PEPPER",
+                format!("must be at most {} bytes", token::MAX_PEPPER_LEN),
+            );
+        }
+
+        let session_defaults = SessionConfig::default();
+        let session_keys = env.optional("SESSION_JWT_KEYS");
+        let sessions = SessionConfig {
+            keys: match session_keys.as_deref().map(SessionKeys::parse) {
+                None => session_defaults.keys,
+                Some(Ok(keys)) => keys,
+                Some(Err(e)) => {
+                    env.error("SESSION_JWT_KEYS", e);
+                    session_defaults.keys
+                }
+            },
+            access_ttl: env.secs("SESSION_ACCESS_TTL_SECS", session_defaults.access_ttl),
+            refresh_ttl: env.secs("SESSION_REFRESH_TTL_SECS", session_defaults.refresh_ttl),
+        };
+        if sessions.access_ttl.is_zero() {
+            env.error("SESSION_ACCESS_TTL_SECS", "must be greater than 0");
+        }
+        if sessions.refresh_ttl.is_zero() {
+            env.error("SESSION_REFRESH_TTL_SECS", "must be greater than 0");
+        }
+
+        let oidc = oidc_config(&mut env);
+
+        let auth_users = env.optional("SMTP_AUTH_USERS");
+        let smtp_auth = SmtpAuth {
+            required: env.parse("SMTP_AUTH_REQUIRED", false),
+            users: match auth_users.as_deref().map(smtp::auth::parse_users) {
+                None => Vec::new(),
+                Some(Ok(users)) => users,
+                Some(Err(e)) => {
+                    env.error("SMTP_AUTH_USERS", e);
+                    Vec::new()
+                }
+            },
+        };
+
+        let geoip = match env.optional("GEOIP_COUNTRY_DB") {
+            None => None,
+            Some(path) => match smtp::GeoIp::open(&path) {
+                Ok(geoip) => Some(Arc::new(geoip)),
+                Err(e) => {
+                    env.error("GEOIP_COUNTRY_DB", format!("cannot open {path:?}: {e}"));
+                    None
+                }
+            },
+        };
+
+        let webhook_defaults = WebhookConfig::default();
+        let webhooks = WebhookConfig {
+            retry: retry_policy(
+                &mut env,
+                [
+                    "WEBHOOK_MAX_ATTEMPTS",
+                    "WEBHOOK_RETRY_BASE_SECS",
+                    "WEBHOOK_RETRY_MAX_SECS",
+                ],
+                webhook_defaults.retry,
+            ),
+            timeout: env.secs("WEBHOOK_TIMEOUT_SECS", webhook_defaults.timeout),
+            ..webhook_defaults
+        };
+        if webhooks.timeout.is_zero() {
+            env.error("WEBHOOK_TIMEOUT_SECS", "must be greater than 0");
+        }
+
+        let relays = relay_config(&mut env, &mail_domain);
+        let outbound_policy = policy_config(&mut env);
+        let forward_defaults = ForwardConfig::default();
+        let forwarding = ForwardConfig {
+            retry: retry_policy(
+                &mut env,
+                [
+                    "FORWARD_MAX_ATTEMPTS",
+                    "FORWARD_RETRY_BASE_SECS",
+                    "FORWARD_RETRY_MAX_SECS",
+                ],
+                forward_defaults.retry,
+            ),
+            ..forward_defaults
+        };
+
+        // Unset or 0 leaves downloads unlimited.
+        let download_bandwidth = env
+            .parse_optional::<u64>("DOWNLOAD_RATE_LIMIT_BYTES_PER_SEC")
+            .filter(|&rate| rate > 0)
+            .map(|bytes_per_sec| Bandwidth {
+                bytes_per_sec,
+                burst: env.parse("DOWNLOAD_BURST_BYTES", 1024 * 1024),
+            });
+
+        let image_proxy = env.optional("IMAGE_PROXY_URL");
+        if let Some(url) = &image_proxy {
+            let ok =
+                url.starts_with('/') || url.starts_with("http://") || url.starts_with("https://");
+            if !ok || HeaderValue::from_str(url).is_err() {
+                env.error(
+                    "IMAGE_PROXY_URL",
+                    "must be a path like /api/proxy/image or an http(s) URL",
+                );
+            }
+        }
+        let image_proxy_secret = env.optional("IMAGE_PROXY_SECRET");
+        if image_proxy_secret.as_ref().is_some_and(|s| s.len() < 32) {
+            env.error("IMAGE_PROXY_SECRET", "must be at least 32 bytes");
+        }
+        let image_proxy_config = env
+            .parse("IMAGE_PROXY_ENABLED", false)
+            .then(|| image_proxy_config(&mut env));
+        // The built-in proxy is used unless another one is named.
+        let image_proxy = match image_proxy_config {
+            Some(_) => image_proxy.or_else(|| Some("/api/proxy/image".into())),
+            None => image_proxy,
+        };
+        let html_display = DisplayOptions {
+            strip_tracking_pixels: env.parse("STRIP_TRACKING_PIXELS", false),
+            image_proxy: image_proxy.map(Into::into),
+            image_proxy_key: image_proxy_secret.map(|s| s.into_bytes().into()),
+        };
+
+        let link_check = link_check_config(&mut env);
+        let otp_patterns = otp_patterns(&mut env);
+
+        let admin_allowlist = IpAllowlist {
+            allowed: cidr_list(&mut env, "ADMIN_ALLOWED_CIDRS"),
+            trusted_proxies: cidr_list(&mut env, "TRUSTED_PROXIES"),
+        };
+
+        let pepper: Arc<[u8]> = token_pepper
+            .as_ref()
+            .map_or_else(|| Arc::from(&[][..]), |p| p.as_bytes().into());
+        let imap_defaults = ImapConfig::default();
+        let imap = ImapConfig {
+            banner_domain: env.string("SMTP_BANNER_DOMAIN", &mail_domain),
+            verify_token: Arc::new(move |presented, stored| {
+                token::verify(&pepper, presented, stored)
+            }),
+            public_retention: janitor.public_retention,
+            idle_timeout: env.secs("IMAP_IDLE_TIMEOUT_SECS", imap_defaults.idle_timeout),
+        };
+        if imap.idle_timeout.is_zero() {
+            env.error("IMAP_IDLE_TIMEOUT_SECS", "must be greater than 0");
+        }
+
+        let (pipeline, read_hooks) = smtp_pipeline(&mut env);
+        let defaults = SmtpConfig::default();
+        let config = Config {
+            mail_domain: mail_domain.as_str().into(),
+            http_host: env.string("HTTP_HOST", "127.0.0.0"),
+            http_port: env.parse("HTTP_PORT", 3001),
+            smtp_host: env.string("SMTP_HOST", "0.0.0.0"),
+            smtp_port: env.parse("SMTP_PORT", 25),
+            janitor,
+            reactivation_grace: env.secs("REACTIVATION_GRACE_SECS", Duration::from_secs(60 * 60)),
+            max_address_lifetime,
+            admin_token: env.optional("ADMIN_TOKEN").map(Into::into),
+            public_ip: env.parse_optional("PUBLIC_IP"),
+            public_base_url: public_base_url.map(|u| u.trim_end_matches('/').into()),
+            share_secret: share_secret.map(|s| s.into_bytes().into()),
+            lookup_floor: Duration::from_millis(env.parse("LOOKUP_FLOOR_MS", 50)),
+            token_pepper: token_pepper.map(|p| p.into_bytes().into()),
+            sessions,
+            session_keys_configured: session_keys.is_some(),
+            oidc,
+            smtp: SmtpConfig {
+                unknown_rcpt_threshold: env.parse(
+                    "SMTP_UNKNOWN_RCPT_THRESHOLD",
+                    defaults.unknown_rcpt_threshold,
+                ),
+                unknown_rcpt_window: env.secs(
+                    "SMTP_UNKNOWN_RCPT_WINDOW_SECS",
+                    defaults.unknown_rcpt_window,
+                ),
+                abuse_block_duration: env
+                    .secs("SMTP_ABUSE_BLOCK_SECS", defaults.abuse_block_duration),
+                max_connections_per_ip: env.parse(
+                    "SMTP_MAX_CONNECTIONS_PER_IP",
+                    defaults.max_connections_per_ip,
+                ),
+                max_messages_per_ip_per_minute: env.parse(
+                    "SMTP_MAX_MESSAGES_PER_IP_PER_MINUTE",
+                    defaults.max_messages_per_ip_per_minute,
+                ),
+                body_compression: env.parse("BODY_COMPRESSION", defaults.body_compression),
+                loop_marker: mail_domain.clone(),
+                max_hops: env.parse("SMTP_MAX_HOPS", defaults.max_hops),
+                banner_domain: env.string("SMTP_BANNER_DOMAIN", &mail_domain),
+                max_recipients: env.parse("SMTP_MAX_RECIPIENTS", defaults.max_recipients),
+                max_messages_per_session: env.parse(
+                    "SMTP_MAX_MESSAGES_PER_SESSION",
+                    defaults.max_messages_per_session,
+                ),
+                max_commands_per_session: env.parse(
+                    "SMTP_MAX_COMMANDS_PER_SESSION",
+                    defaults.max_commands_per_session,
+                ),
+                idle_timeout: env.secs("SMTP_IDLE_TIMEOUT_SECS", defaults.idle_timeout),
+                session_timeout: env.secs("SMTP_SESSION_TIMEOUT_SECS", defaults.session_timeout),
+                greeting_delay: Duration::from_millis(env.parse("SMTP_GREETING_DELAY_MS", 0)),
+                early_talkers: env.parse("SMTP_EARLY_TALKERS", defaults.early_talkers),
+                helo_policy: env.parse("SMTP_HELO_POLICY", defaults.helo_policy),
+                reject_unknown_at_rcpt: env.parse(
+                    "SMTP_REJECT_UNKNOWN_AT_RCPT",
+                    defaults.reject_unknown_at_rcpt,
+                ),
+                local_domains,
+                catch_all: env.parse("SMTP_CATCH_ALL", false),
+                processing_timeout: env.secs(
+                    "SMTP_PROCESSING_TIMEOUT_SECS",
+                    defaults.processing_timeout,
+                ),
+                poison_threshold: env.parse("SMTP_POISON_THRESHOLD", defaults.poison_threshold),
+                max_message_size: env.parse(
+                    "SMTP_MAX_MESSAGE_BYTES",
+                    defaults.max_message_size,
+                ),
+                mailbox_quota: MailboxQuota {
+                    max_messages: env.parse("MAILBOX_MAX_MESSAGES", 0),
+                    max_bytes: env.parse("MAILBOX_MAX_BYTES", 0),
+                },
+                auth: smtp_auth,
+                check_spf: env.parse("SMTP_SPF_CHECK", true),
+                check_dkim: env.parse("SMTP_DKIM_CHECK", true),
+                check_dmarc: env.parse("SMTP_DMARC_CHECK", true),
+                geoip,
+                pipeline,
+                ..defaults
+            },
+            imap_host: env.string("IMAP_HOST", "0.0.0.0"),
+            imap_port: env.parse_optional("IMAP_PORT"),
+            imap,
+            renderer_url,
+            renderer_timeout: env.secs("RENDERER_TIMEOUT_SECS", Duration::from_secs(20)),
+            allow_private_webhooks: env.parse("WEBHOOK_ALLOW_PRIVATE_TARGETS", false),
+            webhooks,
+            relays,
+            outbound_policy,
+            forwarding,
+            download_bandwidth,
+            poll_requires_token: env.parse("POLL_REQUIRES_TOKEN", true),
+            open_tokenless_mailboxes: env.parse("OPEN_TOKENLESS_MAILBOXES", false),
+            html_display,
+            image_proxy: image_proxy_config,
+            link_check,
+            otp_patterns,
+            admin_allowlist,
+            read_hooks,
+        };
+        // A zero deadline would time out every session at the greeting.
+        if config.smtp.idle_timeout.is_zero() {
+            env.error("SMTP_IDLE_TIMEOUT_SECS", "must be greater than 0");
+        }
+        if config.smtp.session_timeout.is_zero() {
+            env.error("SMTP_SESSION_TIMEOUT_SECS", "must be greater than 0");
+        }
+        // Nor could any message be parsed within a zero deadline; each would
+        // end up quarantined and then refused.
+        if config.smtp.processing_timeout.is_zero() {
+            env.error("SMTP_PROCESSING_TIMEOUT_SECS", "must be greater than 0");
+        }
+
+        if env.errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(env.errors)
+        }
+    }
+
+    pub fn http_bind_addr(&self) -> String {
+        format!("{}:{}", self.http_host, self.http_port)
+    }
+}
+
+fn janitor_config(env: &mut Env) -> JanitorConfig {
+    let defaults = JanitorConfig::default();
+
+    let schedule = match env.parse_optional::<u64>("PURGE_INTERVAL_SECS") {
+        Some(0) => {
+            env.error("PURGE_INTERVAL_SECS", "must be greater than 0");
+            defaults.schedule
+        }
+        Some(secs) => Schedule::Every(Duration::from_secs(secs)),
+        None => {
+            let hour = env.parse("PURGE_HOUR_UTC", 3u32);
+            if hour > 23 {
+                env.error("PURGE_HOUR_UTC", "must be between 0 and 23");
+            }
+            Schedule::DailyAt(hour.min(23))
+        }
+    };
+
+    let purge = PurgeOptions {
+        strategy: env.parse("PURGE_STRATEGY", defaults.purge.strategy),
+        batch_size: env.parse("PURGE_BATCH_SIZE", defaults.purge.batch_size),
+        statement_timeout: env.secs(
+            "PURGE_STATEMENT_TIMEOUT_SECS",
+            defaults.purge.statement_timeout,
+        ),
+    };
+    if purge.batch_size < 1 {
+        env.error("PURGE_BATCH_SIZE", "must be at least 1");
+    }
+
+    let expiry_sweep_interval = env.secs("EXPIRY_SWEEP_SECS", defaults.expiry_sweep_interval);
+    if expiry_sweep_interval.is_zero() {
+        env.error("EXPIRY_SWEEP_SECS", "must be greater than 0");
+    }
+
+    let public_retention = env.secs("PUBLIC_MAILBOX_RETENTION_SECS", defaults.public_retention);
+    if public_retention.is_zero() {
+        env.error("PUBLIC_MAILBOX_RETENTION_SECS", "must be greater than 0");
+    }
+
+    let trash_retention = env.secs("TRASH_RETENTION_SECS", defaults.trash_retention);
+
+    let usage_rollup_interval = env.secs("USAGE_ROLLUP_SECS", defaults.usage_rollup_interval);
+    if usage_rollup_interval.is_zero() {
+        env.error("USAGE_ROLLUP_SECS", "must be greater than 0");
+    }
+
+    JanitorConfig {
+        enabled: env.parse("PURGE_ENABLED", defaults.enabled),
+        schedule,
+        jitter: env.secs("PURGE_JITTER_SECS", defaults.jitter),
+        purge,
+        expiry_sweep_interval,
+        public_retention,
+        trash_retention,
+        usage_rollup_interval,
+    }
+}
+
+fn image_proxy_config(env: &mut Env) -> ImageProxyConfig {
+    let defaults = ImageProxyConfig::default();
+    let config = ImageProxyConfig {
+        max_bytes: env.parse("IMAGE_PROXY_MAX_BYTES", defaults.max_bytes),
+        timeout: env.secs("IMAGE_PROXY_TIMEOUT_SECS", defaults.timeout),
+        cache_ttl: env.secs("IMAGE_PROXY_CACHE_TTL_SECS", defaults.cache_ttl),
+        cache_bytes: env.parse("IMAGE_PROXY_CACHE_BYTES", defaults.cache_bytes),
+        ..defaults
+    };
+    if config.max_bytes == 0 {
+        env.error("IMAGE_PROXY_MAX_BYTES", "must be greater than 0");
+    }
+    if config.timeout.is_zero() {
+        env.error("IMAGE_PROXY_TIMEOUT_SECS", "must be greater than 0");
+    }
+    config
+}
+
+/// On when `RELAY_HOSTS` lists smarthosts as `name=host:port[,…]`;
+/// `RELAY_ROUTES` sends recipient domains to other hosts, as
+/// `domain=name[+name][,…]`. Connections need STARTTLS unless
+/// `RELAY_TLS=none`, and log in with `RELAY_USERNAME` and `RELAY_PASSWORD`
+/// when both are set.
+fn relay_config(env: &mut Env, mail_domain: &str) -> Option<RelayConfig> {
+    let raw = env.optional("RELAY_HOSTS")?;
+    let hosts = match outbound_relay::parse_hosts(&raw) {
+        Ok(hosts) if hosts.is_empty() => {
+            env.error("RELAY_HOSTS", "lists no hosts");
+            return None;
+        }
+        Ok(hosts) => hosts,
+        Err(e) => {
+            env.error("RELAY_HOSTS", e);
+            return None;
+        }
+    };
+    let raw_routes = env.optional("RELAY_ROUTES");
+    let routes = match raw_routes.as_deref().map(outbound_relay::parse_routes) {
+        None => HashMap::new(),
+        Some(Ok(routes)) => routes,
+        Some(Err(e)) => {
+            env.error("RELAY_ROUTES", e);
+            HashMap::new()
+        }
+    };
+    let credentials = match (
+        env.optional("RELAY_USERNAME"),
+        env.optional("RELAY_PASSWORD"),
+    ) {
+        (Some(username), Some(password)) => Some(RelayCredentials { username, password }),
+        (None, None) => None,
+        _ => {
+            env.error(
+                "RELAY_PASSWORD",
+                "RELAY_USERNAME and RELAY_PASSWORD go together",
+            );
+            None
+        }
+    };
+    let defaults = RelayConfig::default();
+    let config = RelayConfig {
+        hosts,
+        routes,
+        helo_name: env.string("RELAY_HELO_NAME", mail_domain),
+        timeout: env.secs("RELAY_TIMEOUT_SECS", defaults.timeout),
+        tls: env.parse("RELAY_TLS", defaults.tls),
+        credentials,
+        ..defaults
+    };
+    if config.timeout.is_zero() {
+        env.error("RELAY_TIMEOUT_SECS", "must be greater than 0");
+    }
+    if config.credentials.is_some() && config.tls == RelayTls::None {
+        env.error("RELAY_TLS", "must be required when RELAY_PASSWORD is set");
+    }
+    if let Err(e) = RelayPool::new(config.clone()) {
+        env.error("RELAY_ROUTES", e);
+    }
+    Some(config)
+}
+
+/// Limits on forwarded mail. `OUTBOUND_BLOCKED_DOMAINS` is comma-separated;
+/// a domain blocks its subdomains too.
+fn policy_config(env: &mut Env) -> PolicyConfig {
+    let defaults = PolicyConfig::default();
+    let blocked = env.optional("OUTBOUND_BLOCKED_DOMAINS").unwrap_or_default();
+    PolicyConfig {
+        max_size: env.parse("OUTBOUND_MAX_BYTES", defaults.max_size),
+        blocked_recipient_domains: blocked
+            .split(',')
+            .map(|d| d.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect(),
+        rate_limit: env.parse("OUTBOUND_RATE_LIMIT", defaults.rate_limit),
+        rate_window: env.secs("OUTBOUND_RATE_WINDOW_SECS", defaults.rate_window),
+        ..defaults
+    }
+}
+
+/// Attempts, first retry delay and longest retry delay, read from `keys`
+/// in that order.
+fn retry_policy(env: &mut Env, keys: [&'static str; 3], defaults: RetryPolicy) -> RetryPolicy {
+    let [max_attempts, retry_base, retry_max] = keys;
+    let policy = RetryPolicy {
+        max_attempts: env.parse(max_attempts, defaults.max_attempts),
+        retry_base: env.secs(retry_base, defaults.retry_base),
+        retry_max: env.secs(retry_max, defaults.retry_max),
+    };
+    if policy.max_attempts == 0 {
+        env.error(max_attempts, "must be greater than 0");
+    }
+    policy
+}
+
+/// On when `LINK_BLOCKLIST_FILE` or `SAFE_BROWSING_API_KEY` is set. The
+/// file lists one domain per line; `#` starts a comment.
+fn link_check_config(env: &mut Env) -> Option<LinkCheckConfig> {
+    let file = env.optional("LINK_BLOCKLIST_FILE");
+    let safe_browsing_key = env.optional("SAFE_BROWSING_API_KEY");
+    if file.is_none() && safe_browsing_key.is_none() {
+        return None;
+    }
+    let blocklist = match file.map(std::fs::read_to_string) {
+        None => Vec::new(),
+        Some(Ok(contents)) => contents
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|domain| !domain.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        Some(Err(e)) => {
+            env.error("LINK_BLOCKLIST_FILE", format!("cannot be read: {e}"));
+            Vec::new()
+        }
+    };
+    let defaults = LinkCheckConfig::default();
+    let config = LinkCheckConfig {
+        blocklist,
+        safe_browsing_key,
+        timeout: env.secs("LINK_CHECK_TIMEOUT_SECS", defaults.timeout),
+        ..defaults
+    };
+    if config.timeout.is_zero() {
+        env.error("LINK_CHECK_TIMEOUT_SECS", "must be greater than 0");
+    }
+    Some(config)
+}
+
+/// `OTP_PATTERNS_FILE` lists one regex per line, tried in order; blank
+/// lines and lines starting with `#` are skipped. Unset keeps the built-in
+/// patterns.
+fn otp_patterns(env: &mut Env) -> OtpPatterns {
+    let Some(path) = env.optional("OTP_PATTERNS_FILE") else {
+        return OtpPatterns::default();
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            env.error("OTP_PATTERNS_FILE", format!("cannot be read: {e}"));
+            return OtpPatterns::default();
+        }
+    };
+    let patterns: Vec<&str> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    if patterns.is_empty() {
+        env.error("OTP_PATTERNS_FILE", "lists no patterns");
+        return OtpPatterns::default();
+    }
+    OtpPatterns::compile(&patterns).unwrap_or_else(|e| {
+        env.error("OTP_PATTERNS_FILE", format!("invalid pattern: {e}"));
+        OtpPatterns::default()
+    })
+}
+
+/// Login is enabled by setting the `OIDC_*` variables: all four for an
+/// OpenID Connect issuer, or all but `OIDC_ISSUER` with `OIDC_PROVIDER=github`.
+/// Setting only some is an error rather than a silently disabled login.
+fn oidc_config(env: &mut Env) -> Option<OidcConfig> {
+    const KEYS: [&str; 3] = ["OIDC_CLIENT_ID", "OIDC_CLIENT_SECRET", "OIDC_REDIRECT_URL"];
+    let kind = env
+        .optional("OIDC_PROVIDER")
+        .map(|kind| kind.to_ascii_lowercase());
+    let issuer = env.optional("OIDC_ISSUER");
+    let values = KEYS.map(|key| env.optional(key));
+    if kind.is_none() && issuer.is_none() && values.iter().all(Option::is_none) {
+        return None;
+    }
+    let provider = match (kind.as_deref(), issuer) {
+        (None | Some("oidc"), Some(issuer)) => Some(Provider::Oidc { issuer }),
+        (None | Some("oidc"), None) => {
+            env.error("OIDC_ISSUER", "must be set when any OIDC_* variable is set");
+            None
+        }
+        (Some("github"), None) => Some(Provider::github()),
+        (Some("github"), Some(_)) => {
+            env.error("OIDC_ISSUER", "is not used with OIDC_PROVIDER=github");
+            None
+        }
+        (Some(other), _) => {
+            env.error(
+                "OIDC_PROVIDER",
+                format!("expected oidc or github, got {other:?}"),
+            );
+            None
+        }
+    };
+    for (key, value) in KEYS.iter().zip(&values) {
+        if value.is_none() {
+            env.error(key, "must be set when any OIDC_* variable is set");
+        }
+    }
+    let (Some(provider), [Some(client_id), Some(client_secret), Some(redirect_url)]) =
+        (provider, values)
+    else {
+        return None;
+    };
+    let issuer = match &provider {
+        Provider::Oidc { issuer } => Some(issuer.as_str()),
+        Provider::GitHub { .. } => None,
+    };
+    for (key, url) in [
+        ("OIDC_ISSUER", issuer),
+        ("OIDC_REDIRECT_URL", Some(redirect_url.as_str())),
+    ] {
+        let Some(url) = url else { continue };
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            env.error(key, format!("{url:?} is not an http(s) URL"));
+        }
+    }
+    Some(OidcConfig {
+        provider,
+        client_id,
+        client_secret,
+        redirect_url,
+    })
+}
+
+#[derive(Default)]
+struct Env {
+    errors: Vec<ConfigError>,
+}
+
+impl Env {
+    fn error(&mut self, key: &'static str, message: impl Into<String>) {
+        self.errors.push(ConfigError {
+            key,
+            message: message.into(),
+        });
+    }
+
+    fn optional(&self, key: &str) -> Option<String> {
+        std::env::var(key)
+            .ok()
+            .map(|v| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+    }
+
+    fn string(&self, key: &str, default: &str) -> String {
+        self.optional(key).unwrap_or_else(|| default.into())
+    }
+
+    fn parse<T>(&mut self, key: &'static str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let Some(raw) = self.optional(key) else {
+            return default;
+        };
+        raw.parse().unwrap_or_else(|e| {
+            self.error(key, format!("invalid value {raw:?}: {e}"));
+            default
+        })
+    }
+
+    fn parse_optional<T>(&mut self, key: &'static str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let raw = self.optional(key)?;
+        match raw.parse() {
+            Ok(v) => Some(v),
+            Err(e) => {
+                self.error(key, format!("invalid value {raw:?}: {e}"));
+                None
+            }
+        }
+    }
+
+    fn secs(&mut self, key: &'static str, default: Duration) -> Duration {
+        Duration::from_secs(self.parse(key, default.as_secs()))
+    }
+}
+
+/// `SMTP_DISABLED_STAGES`: built-in ingestion stages to skip,
+/// comma-separated. With the `wasm-plugins` feature, `SMTP_WASM_PLUGINS`
+/// lists module files to add as stages, in order, whose `on_api_read` hooks
+/// also guard the HTTP API's reads.
+fn smtp_pipeline(env: &mut Env) -> (Pipeline, ReadHooks) {
+    let raw = env.optional("SMTP_DISABLED_STAGES").unwrap_or_default();
+    let mut pipeline = Pipeline::default();
+    for name in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match name.parse::<BuiltinStage>() {
+            Ok(stage) => pipeline = pipeline.without(stage),
+            Err(e) => env.error("SMTP_DISABLED_STAGES", e),
+        }
+    }
+    #[cfg(not(feature = "wasm-plugins"))]
+    let read_hooks = ReadHooks::default();
+    #[cfg(feature = "wasm-plugins")]
+    let read_hooks = {
+        let fuel = env.parse("SMTP_WASM_FUEL", smtp::wasm::DEFAULT_FUEL);
+        let max_memory = env.parse("SMTP_WASM_MAX_MEMORY_BYTES", smtp::wasm::DEFAULT_MAX_MEMORY);
+        if max_memory == 0 {
+            env.error("SMTP_WASM_MAX_MEMORY_BYTES", "must be greater than 0");
+        }
+        let raw = env.optional("SMTP_WASM_PLUGINS").unwrap_or_default();
+        let mut plugins = Vec::new();
+        for path in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match smtp::wasm::WasmStage::load(path) {
+                Ok(stage) => {
+                    let stage = stage.with_fuel(fuel).with_max_memory(max_memory);
+                    pipeline = pipeline.with_stage(stage.clone());
+                    plugins.push(stage);
+                }
+                Err(e) => env.error("SMTP_WASM_PLUGINS", e),
+            }
+        }
+        ReadHooks::new(plugins)
+    };
+    (pipeline, read_hooks)
+}
+
+/// Comma-separated networks; unset is empty.
+fn cidr_list(env: &mut Env, key: &'static str) -> Vec<Cidr> {
+    let raw = env.optional(key).unwrap_or_default();
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| s.parse::<Cidr>().map_err(|e| env.error(key, e)).ok())
+        .collect()
+}
+
+pub(crate) fn is_hostname(s: &str) -> bool {
+    s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
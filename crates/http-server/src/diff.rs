@@ -0,0 +1,205 @@
+//! Side-by-side comparison of two messages in one mailbox, e.g. two versions
+//! of a template. Text bodies are compared line by line; HTML bodies as a
+//! flattened DOM (one line per opening tag, with sorted attributes, or per
+//! text node, indented by depth), so reformatting the markup does not show up
+//! as a change.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::ReceivedEmail;
+use scraper::{ElementRef, Html, Node};
+use serde::{Deserialize, Serialize};
+use similar::{DiffTag, TextDiff};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::api::err;
+use crate::share::owned_email;
+use crate::AppState;
+
+/// Largest text plus HTML body of either message that is compared.
+pub const MAX_DIFF_INPUT_BYTES: usize = 256 * 1024;
+/// After this the diff is coarser (whole runs replaced) but still correct.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(500);
+/// Elements nested deeper are flattened to their text, which keeps the
+/// recursion bounded.
+const MAX_DOM_DEPTH: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub a: Uuid,
+    pub b: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmailDiff {
+    pub a: Uuid,
+    pub b: Uuid,
+    pub subject: FieldDiff,
+    pub text: Vec<Hunk>,
+    pub html: Vec<Hunk>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldDiff {
+    pub equal: bool,
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HunkOp {
+    Equal,
+    /// Only in `a`.
+    Delete,
+    /// Only in `b`.
+    Insert,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hunk {
+    pub op: HunkOp,
+    pub lines: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/diff",
+    tag = "api",
+    summary = "Compare two messages",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn diff_emails(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<DiffQuery>,
+) -> Result<Json<EmailDiff>, Response> {
+    let (_, a) = owned_email(&state, &address, q.a).await?;
+    let (_, b) = owned_email(&state, &address, q.b).await?;
+    let size = |e: &ReceivedEmail| {
+        e.body_text.as_deref().map_or(0, str::len) + e.body_html.as_deref().map_or(0, str::len)
+    };
+    if size(&a) > MAX_DIFF_INPUT_BYTES || size(&b) > MAX_DIFF_INPUT_BYTES {
+        return Err(err(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "messages are too large to compare",
+        ));
+    }
+
+    // Parsing and diffing up to 2 × 256 KiB can stall an async worker.
+    let diff = tokio::task::spawn_blocking(move || compare(a, b))
+        .await
+        .map_err(|_| err(StatusCode::INTERNAL_SERVER_ERROR, "diff failed"))?;
+    Ok(Json(diff))
+}
+
+fn compare(a: ReceivedEmail, b: ReceivedEmail) -> EmailDiff {
+    let text_lines = |e: &ReceivedEmail| -> Vec<String> {
+        e.body_text
+            .as_deref()
+            .map(|t| t.lines().map(str::to_owned).collect())
+            .unwrap_or_default()
+    };
+    let dom_lines = |e: &ReceivedEmail| e.body_html.as_deref().map(flatten_html);
+    EmailDiff {
+        a: a.id,
+        b: b.id,
+        text: diff_lines(&text_lines(&a), &text_lines(&b)),
+        html: diff_lines(
+            &dom_lines(&a).unwrap_or_default(),
+            &dom_lines(&b).unwrap_or_default(),
+        ),
+        subject: FieldDiff {
+            equal: a.subject == b.subject,
+            a: a.subject,
+            b: b.subject,
+        },
+    }
+}
+
+/// `html` as one line per element or non-blank text node.
+pub fn flatten_html(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let mut lines = Vec::new();
+    flatten_element(document.root_element(), 0, &mut lines);
+    lines
+}
+
+fn flatten_element(element: ElementRef<'_>, depth: usize, lines: &mut Vec<String>) {
+    let indent = "  ".repeat(depth);
+    let mut attrs: Vec<(&str, &str)> = element.value().attrs().collect();
+    attrs.sort_unstable();
+    let mut open = format!("{indent}<{}", element.value().name());
+    for (name, value) in attrs {
+        open.push_str(&format!(" {name}=\"{value}\""));
+    }
+    open.push('>');
+    lines.push(open);
+
+    if depth >= MAX_DOM_DEPTH {
+        let text = element.text().flat_map(str::split_whitespace);
+        let text = text.collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            lines.push(format!("{indent}  {text}"));
+        }
+        return;
+    }
+    for child in element.children() {
+        match child.value() {
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    flatten_element(child, depth + 1, lines);
+                }
+            }
+            Node::Text(text) => {
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    lines.push(format!("{indent}  {text}"));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn diff_lines(a: &[String], b: &[String]) -> Vec<Hunk> {
+    let old: Vec<&str> = a.iter().map(String::as_str).collect();
+    let new: Vec<&str> = b.iter().map(String::as_str).collect();
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_slices(&old, &new);
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut push = |op: HunkOp, lines: &[String]| {
+        if lines.is_empty() {
+            return;
+        }
+        match hunks.last_mut() {
+            Some(last) if last.op == op => last.lines.extend_from_slice(lines),
+            _ => hunks.push(Hunk {
+                op,
+                lines: lines.to_vec(),
+            }),
+        }
+    };
+    for op in diff.ops() {
+        let (tag, old_range, new_range) = op.as_tag_tuple();
+        match tag {
+            DiffTag::Equal => push(HunkOp::Equal, &a[old_range]),
+            DiffTag::Delete => push(HunkOp::Delete, &a[old_range]),
+            DiffTag::Insert => push(HunkOp::Insert, &b[new_range]),
+            DiffTag::Replace => {
+                push(HunkOp::Delete, &a[old_range]);
+                push(HunkOp::Insert, &b[new_range]);
+            }
+        }
+    }
+    hunks
+}
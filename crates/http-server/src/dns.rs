@@ -0,0 +1,95 @@
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use serde::Serialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MxHost {
+    pub preference: u16,
+    pub exchange: String,
+    pub addrs: Vec<IpAddr>,
+}
+
+pub fn resolver() -> TokioAsyncResolver {
+    TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+        tracing::warn!(error = %e, "no system resolver config, using defaults");
+        TokioAsyncResolver::tokio(Default::default(), Default::default())
+    })
+}
+
+/// MX records for `domain`, lowest preference first, each resolved to its
+/// A/AAAA addresses. A domain with no MX records yields an empty list.
+pub async fn lookup_mx(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+) -> Result<Vec<MxHost>, ResolveError> {
+    let records = match resolver.mx_lookup(fqdn(domain)).await {
+        Ok(lookup) => lookup.iter().cloned().collect::<Vec<_>>(),
+        Err(e) if is_no_records(&e) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut hosts = Vec::with_capacity(records.len());
+    for mx in records {
+        let exchange = mx.exchange().to_utf8();
+        let addrs = match resolver.lookup_ip(exchange.as_str()).await {
+            Ok(lookup) => lookup.iter().collect(),
+            Err(e) if is_no_records(&e) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        hosts.push(MxHost {
+            preference: mx.preference(),
+            exchange: exchange.trim_end_matches('.').to_owned(),
+            addrs,
+        });
+    }
+    hosts.sort_by_key(|h| h.preference);
+    Ok(hosts)
+}
+
+pub async fn lookup_ptr(
+    resolver: &TokioAsyncResolver,
+    ip: IpAddr,
+) -> Result<Vec<String>, ResolveError> {
+    match resolver.reverse_lookup(ip).await {
+        Ok(lookup) => Ok(lookup
+            .iter()
+            .map(|name| name.to_utf8().trim_end_matches('.').to_owned())
+            .collect()),
+        Err(e) if is_no_records(&e) => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// TXT records on `domain` that declare an SPF policy (`v=spf1 ...`).
+/// More than one is a misconfiguration receivers treat as a permerror.
+pub async fn lookup_spf(
+    resolver: &TokioAsyncResolver,
+    domain: &str,
+) -> Result<Vec<String>, ResolveError> {
+    let lookup = match resolver.txt_lookup(fqdn(domain)).await {
+        Ok(lookup) => lookup,
+        Err(e) if is_no_records(&e) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(lookup
+        .iter()
+        .map(|txt| {
+            txt.iter()
+                .map(|part| String::from_utf8_lossy(part))
+                .collect::<String>()
+        })
+        .filter(|record| {
+            let lower = record.to_ascii_lowercase();
+            lower == "v=spf1" || lower.starts_with("v=spf1 ")
+        })
+        .collect())
+}
+
+fn fqdn(domain: &str) -> String {
+    format!("{}.", domain.trim_end_matches('.'))
+}
+
+fn is_no_records(e: &ResolveError) -> bool {
+    matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. })
+}
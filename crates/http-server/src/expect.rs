@@ -0,0 +1,110 @@
+//! `/api/email/:address/expect`: senders a mailbox is waiting for, e.g. the
+//! service about to send it a one-time code. Until the expectation runs out,
+//! mail whose envelope sender is at that domain or a subdomain skips the
+//! SMTP server's per-IP message limit, and its webhook deliveries are sent
+//! ahead of the queue.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::{list_sender_expectations, upsert_sender_expectation, SenderExpectation};
+use serde::Deserialize;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use crate::api::{db_error, err};
+use crate::config::is_hostname;
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+/// Senders one mailbox may expect at a time.
+pub const MAX_EXPECTATIONS: usize = 10;
+const DEFAULT_TTL_SECS: u64 = 10 * 60;
+const MAX_TTL_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = ExpectSender)]
+pub struct ExpectSenderBody {
+    pub from_domain: String,
+    /// Defaults to ten minutes; at most an hour.
+    #[schema(minimum = 1, maximum = 3600)]
+    pub ttl_secs: Option<u64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/expect",
+    tag = "api",
+    summary = "List senders the mailbox expects",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = Vec<SenderExpectation>)),
+    security(("mailbox" = []))
+)]
+pub async fn list_expectations(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<SenderExpectation>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let expected = list_sender_expectations(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(expected))
+}
+
+/// Expecting a domain again restarts its clock.
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/expect",
+    tag = "api",
+    summary = "Expect mail from a domain, skipping throttling",
+    params(("address" = String, Path)),
+    request_body = ExpectSenderBody,
+    responses((status = 200, description = "OK", body = SenderExpectation)),
+    security(("mailbox" = []))
+)]
+pub async fn expect_sender(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<ExpectSenderBody>,
+) -> Result<Json<SenderExpectation>, Response> {
+    let domain = body
+        .from_domain
+        .trim()
+        .trim_end_matches('.')
+        .to_ascii_lowercase();
+    // A bare TLD would let every sender under it through.
+    if !is_hostname(&domain) || !domain.contains('.') {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "from_domain must be a host name such as example.com",
+        ));
+    }
+    let ttl = match body.ttl_secs.unwrap_or(DEFAULT_TTL_SECS) {
+        secs @ 1..=MAX_TTL_SECS => Duration::from_secs(secs),
+        _ => {
+            return Err(err(
+                StatusCode::BAD_REQUEST,
+                &format!("ttl_secs must be between 1 and {MAX_TTL_SECS}"),
+            ))
+        }
+    };
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let existing = list_sender_expectations(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    if existing.len() >= MAX_EXPECTATIONS && !existing.iter().any(|e| e.from_domain == domain) {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can expect at most {MAX_EXPECTATIONS} senders at once"),
+        ));
+    }
+    let expected = upsert_sender_expectation(&pool, temp.id, &domain, ttl)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(addr = %temp.temp_email_addr, from_domain = %domain, "sender expected");
+    Ok(Json(expected))
+}
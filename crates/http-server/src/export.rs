@@ -0,0 +1,347 @@
+//! `/api/email/:address/export`: every message a mailbox shows, as one
+//! download for importing into a mail client or keeping after the address
+//! expires. Messages go out in the order received, each as soon as it is
+//! read, so large mailboxes are not held in memory.
+//!
+//! A message's original source is used when stored. Otherwise, e.g. for a
+//! redacted message, one is put together from the fields still kept, with
+//! UTF-8 header fields (RFC 6532).
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use db::{fetch_raw_email, list_received_emails, ReceivedEmail};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::io;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::api::{db_error, err};
+use crate::attachments::content_disposition;
+use crate::policy::MailboxPolicy;
+use crate::watch::live_mailbox;
+use crate::{throttle, AppState};
+
+/// Entries a zip archive without the zip64 extensions can hold.
+const MAX_ZIP_ENTRIES: usize = u16::MAX as usize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// One `mboxrd` file (RFC 4155).
+    #[default]
+    Mbox,
+    /// One `.eml` file per message, stored uncompressed.
+    Zip,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/export",
+    tag = "api",
+    summary = "All messages as one mbox or zip download",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn export_mailbox(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<ExportQuery>,
+) -> Result<Response, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let oldest_visible =
+        MailboxPolicy::of(&temp, state.public_retention).oldest_visible(Utc::now());
+    let messages = list_received_emails(&pool, temp.id, oldest_visible, None, None)
+        .await
+        .map_err(db_error)?;
+    if q.format == ExportFormat::Zip && messages.len() > MAX_ZIP_ENTRIES {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "too many messages for a zip archive; use format=mbox",
+        ));
+    }
+
+    let (content_type, extension) = match q.format {
+        ExportFormat::Mbox => ("application/mbox", "mbox"),
+        ExportFormat::Zip => ("application/zip", "zip"),
+    };
+    let filename = format!("{}.{extension}", temp.temp_email_addr);
+    tracing::info!(
+        addr = %temp.temp_email_addr,
+        messages = messages.len(),
+        format = extension,
+        "mailbox exported"
+    );
+
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(write_archive(pool, messages, q.format, tx));
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                content_disposition(Some(&filename)),
+            ),
+        ],
+        throttle::stream(ReceiverStream::new(rx), state.download_bandwidth),
+    )
+        .into_response())
+}
+
+/// Sends the archive in one chunk per message. A database error ends the
+/// body early, so the client sees a failed download rather than a short
+/// archive.
+async fn write_archive(
+    pool: PgPool,
+    messages: Vec<ReceivedEmail>,
+    format: ExportFormat,
+    tx: mpsc::Sender<io::Result<Bytes>>,
+) {
+    let mut zip = ZipWriter::default();
+    for email in &messages {
+        let message = match fetch_raw_email(&pool, email.id).await {
+            Ok(raw) => raw.unwrap_or_else(|| reconstruct(email)),
+            Err(e) => {
+                tracing::error!(error = %e, email_id = %email.id, "export failed");
+                let _ = tx.send(Err(io::Error::other(e))).await;
+                return;
+            }
+        };
+        let chunk = match format {
+            ExportFormat::Mbox => Ok(mbox_entry(email, &message)),
+            ExportFormat::Zip => zip.entry(&entry_name(email), email.received_at, &message),
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        if tx.send(Ok(chunk.into())).await.is_err() {
+            // The client went away.
+            return;
+        }
+    }
+    if format == ExportFormat::Zip {
+        let _ = tx.send(zip.finish().map(Bytes::from)).await;
+    }
+}
+
+fn entry_name(email: &ReceivedEmail) -> String {
+    format!(
+        "{}-{}.eml",
+        email.received_at.format("%Y%m%d-%H%M%S"),
+        email.id
+    )
+}
+
+/// A message as an `mboxrd` entry: a `From ` separator line, the message
+/// with LF line endings and every `From ` line, quoted or not, quoted once
+/// more, then a blank line.
+fn mbox_entry(email: &ReceivedEmail, message: &[u8]) -> Vec<u8> {
+    let sender = email
+        .from_addr
+        .as_deref()
+        .filter(|a| !email.is_bounce && !a.is_empty() && !a.contains(char::is_whitespace))
+        .unwrap_or("MAILER-DAEMON");
+    let mut out = format!(
+        "From {sender} {}\n",
+        email.received_at.format("%a %b %e %H:%M:%S %Y")
+    )
+    .into_bytes();
+    let message = message
+        .strip_suffix(b"\n")
+        .map(|m| m.strip_suffix(b"\r").unwrap_or(m))
+        .unwrap_or(message);
+    for line in message.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let unquoted = &line[line.iter().take_while(|&&b| b == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            out.push(b'>');
+        }
+        out.extend_from_slice(line);
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+    out
+}
+
+/// A message built from the stored fields, for mail whose source is gone.
+fn reconstruct(email: &ReceivedEmail) -> Vec<u8> {
+    let mut out = String::new();
+    let mut field = |name: &str, value: &str| {
+        let value: String = value
+            .chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        out.push_str(&format!("{name}: {value}\r\n"));
+    };
+    if let Some(from) = email.from_addr.as_deref() {
+        field("From", from);
+    }
+    if let Some(to) = email.to_addr.as_deref() {
+        field("To", to);
+    }
+    if let Some(subject) = email.subject.as_deref() {
+        field("Subject", subject);
+    }
+    field("Date", &email.received_at.to_rfc2822());
+    field("MIME-Version", "1.0");
+
+    let text = email.body_text.as_deref();
+    let html = email.body_html.as_deref();
+    match (text, html) {
+        (Some(text), Some(html)) => {
+            let boundary = format!("=_{}", email.id.simple());
+            out.push_str(&format!(
+                "Content-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n"
+            ));
+            for (subtype, body) in [("plain", text), ("html", html)] {
+                out.push_str(&format!("--{boundary}\r\n"));
+                push_part(&mut out, subtype, body);
+                out.push_str("\r\n");
+            }
+            out.push_str(&format!("--{boundary}--\r\n"));
+        }
+        (None, Some(html)) => push_part(&mut out, "html", html),
+        (text, None) => push_part(&mut out, "plain", text.unwrap_or_default()),
+    }
+    out.into_bytes()
+}
+
+fn push_part(out: &mut String, subtype: &str, body: &str) {
+    out.push_str(&format!(
+        "Content-Type: text/{subtype}; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\r\n"
+    ));
+    for line in body.lines() {
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+}
+
+/// Writes a zip archive an entry at a time, keeping only the central
+/// directory until [`ZipWriter::finish`]. Entries are stored rather than
+/// compressed; without the zip64 extensions the archive stops at 4 GiB.
+#[derive(Default)]
+struct ZipWriter {
+    offset: u64,
+    central: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    fn entry(&mut self, name: &str, modified: DateTime<Utc>, data: &[u8]) -> io::Result<Vec<u8>> {
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        self.entries = self.entries.checked_add(1).ok_or_else(too_large)?;
+        let (time, date) = dos_datetime(modified);
+        let crc = crc32(data);
+
+        let mut local = Vec::with_capacity(30 + name.len() + data.len());
+        local.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        local.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        local.extend_from_slice(&UTF8_NAME.to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes()); // stored
+        local.extend_from_slice(&time.to_le_bytes());
+        local.extend_from_slice(&date.to_le_bytes());
+        local.extend_from_slice(&crc.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&size.to_le_bytes());
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(data);
+
+        let c = &mut self.central;
+        c.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        c.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        c.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        c.extend_from_slice(&UTF8_NAME.to_le_bytes());
+        c.extend_from_slice(&0u16.to_le_bytes()); // stored
+        c.extend_from_slice(&time.to_le_bytes());
+        c.extend_from_slice(&date.to_le_bytes());
+        c.extend_from_slice(&crc.to_le_bytes());
+        c.extend_from_slice(&size.to_le_bytes());
+        c.extend_from_slice(&size.to_le_bytes());
+        c.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // Extra field, comment, disk number, internal and external attributes.
+        c.extend_from_slice(&[0; 12]);
+        c.extend_from_slice(&offset.to_le_bytes());
+        c.extend_from_slice(name.as_bytes());
+
+        self.offset += local.len() as u64;
+        Ok(local)
+    }
+
+    /// The central directory and its end record.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        let offset = u32::try_from(self.offset).map_err(|_| too_large())?;
+        let size = u32::try_from(self.central.len()).map_err(|_| too_large())?;
+        let mut out = self.central;
+        out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        out.extend_from_slice(&[0; 4]); // disk numbers
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&size.to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        Ok(out)
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("archive too large for zip")
+}
+
+/// General purpose flag: names are UTF-8.
+const UTF8_NAME: u16 = 1 << 11;
+
+/// MS-DOS time and date, which start in 1980 and count seconds in twos.
+fn dos_datetime(at: DateTime<Utc>) -> (u16, u16) {
+    if at.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (at.hour() << 11) | (at.minute() << 5) | (at.second() / 2);
+    let date = (((at.year() - 1980).min(127) as u32) << 9) | (at.month() << 5) | at.day();
+    (time as u16, date as u16)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |c, &b| {
+        CRC32_TABLE[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8)
+    })
+}
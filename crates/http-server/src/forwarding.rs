@@ -0,0 +1,585 @@
+//! `/api/email/:address/forwards`: real mailboxes an address passes its mail
+//! on to, so it can stand in for a private address rather than only be read
+//! on the web. Storing a message queues a `forward_delivery` per rule in the
+//! same transaction, and [`run_forward_worker`] checks each against the
+//! outbound policy and hands it to the relays, retrying outages with
+//! exponential backoff.
+//!
+//! A new rule is pending: its target is mailed a link,
+//! `/api/forwards/{rule_id}/confirm?exp={unix}&sig={hex}` with `sig`
+//! HMAC-SHA256 over `forward.rule_id.forward_to.exp`, and nothing is
+//! forwarded until someone opens it and agrees. Opening the link only shows
+//! a page; the agreement is a `POST` to the same URL, so mail scanners that
+//! follow links do not confirm rules. Holding a mailbox's token is not
+//! enough to have its mail sent to somebody else.
+//!
+//! A forward is the original message, unchanged but for `X-Loop` and
+//! `X-Forwarded-To` fields on top, sent with the address itself as the
+//! envelope sender so bounces land in its inbox. The SMTP server drops mail
+//! carrying our `X-Loop`, so two addresses forwarding to each other through
+//! an outside mailbox cannot loop.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, Response},
+    Json,
+};
+use chrono::{TimeZone, Utc};
+use db::{
+    claim_forward_deliveries, complete_forward_delivery, confirm_forwarding_rule,
+    delete_forwarding_rule, fail_forward_delivery, fetch_raw_email, find_forwarding_rule,
+    insert_forwarding_rule, list_attachments, list_forwarding_rules, ForwardingRule,
+    PendingForward,
+};
+use hmac::{Hmac, Mac};
+use outbound_policy::{
+    Attachment, OutboundKind, OutboundMessage, OutboundPolicy, PolicyConfig, PolicyViolation,
+};
+use outbound_relay::{Envelope, RelayConfig, RelayError, RelayPool};
+use serde::Deserialize;
+use sha2::Sha256;
+use sqlx::postgres::PgPool;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::api::{db_error, err, require_pool};
+use crate::config::is_hostname;
+use crate::retry::RetryPolicy;
+use crate::share::{from_hex, to_hex};
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Rules one mailbox may have, pending or not.
+pub const MAX_FORWARDS: usize = 3;
+/// How long the link in a confirmation message works.
+const CONFIRM_TTL_SECS: i64 = 3 * 24 * 60 * 60;
+/// Forwards claimed and sent concurrently per round.
+const BATCH_SIZE: i64 = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ForwardConfig {
+    /// How often the queue is checked for due forwards.
+    pub poll_interval: Duration,
+    pub retry: RetryPolicy,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            retry: RetryPolicy {
+                max_attempts: 8,
+                retry_base: Duration::from_secs(60),
+                retry_max: Duration::from_secs(60 * 60),
+            },
+        }
+    }
+}
+
+/// What sends forwarded mail; without it rules cannot be created.
+pub struct Outbound {
+    pub relays: RelayPool,
+    pub policy: OutboundPolicy,
+    /// Written as `X-Loop:` on every forward.
+    pub loop_marker: String,
+}
+
+impl Outbound {
+    /// Fails if a relay route names a host that is not configured.
+    pub fn new(
+        relays: RelayConfig,
+        policy: PolicyConfig,
+        loop_marker: impl Into<String>,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            relays: RelayPool::new(relays)?,
+            policy: OutboundPolicy::new(policy),
+            loop_marker: loop_marker.into(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = CreateForward)]
+pub struct CreateForwardBody {
+    pub forward_to: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConfirmQuery {
+    pub exp: i64,
+    pub sig: String,
+}
+
+/// `forward_to` lowercased, or why mail cannot be forwarded there. Our own
+/// domain is refused: its mail is already readable here.
+fn normalize_target(forward_to: &str, mail_domain: &str) -> Result<String, Response> {
+    let forward_to = forward_to.trim().to_ascii_lowercase();
+    let valid = forward_to.rsplit_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && local
+                .chars()
+                .all(|c| c.is_ascii_graphic() && !"<>()[],;:\\\"@".contains(c))
+            && is_hostname(domain)
+            && domain.contains('.')
+    });
+    if !valid {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "forward_to must be an email address",
+        ));
+    }
+    let domain = forward_to.rsplit_once('@').map_or("", |(_, d)| d);
+    let ours = domain == mail_domain
+        || domain
+            .strip_suffix(mail_domain)
+            .is_some_and(|sub| sub.ends_with('.'));
+    if ours {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "mail cannot be forwarded to this service",
+        ));
+    }
+    Ok(forward_to)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/forwards",
+    tag = "api",
+    summary = "List mailboxes the address forwards to",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = Vec<ForwardingRule>)),
+    security(("mailbox" = []))
+)]
+pub async fn list_forwards(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<ForwardingRule>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let rules = list_forwarding_rules(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(rules))
+}
+
+/// Creates a pending rule and mails its target the confirmation link. The
+/// rule is dropped again when that message cannot be sent.
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/forwards",
+    tag = "api",
+    summary = "Forward new mail to a real mailbox",
+    params(("address" = String, Path)),
+    request_body = CreateForwardBody,
+    responses((status = 200, description = "OK", body = ForwardingRule)),
+    security(("mailbox" = []))
+)]
+pub async fn create_forward(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<CreateForwardBody>,
+) -> Result<(StatusCode, Json<ForwardingRule>), Response> {
+    let Some(outbound) = state.outbound.clone() else {
+        return Err(err(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "forwarding is not configured",
+        ));
+    };
+    let forward_to = normalize_target(&body.forward_to, &state.mail_domain)?;
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let existing = list_forwarding_rules(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    if existing.len() >= MAX_FORWARDS {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can forward to at most {MAX_FORWARDS} addresses"),
+        ));
+    }
+    let rule = insert_forwarding_rule(&pool, temp.id, &forward_to)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::CONFLICT, "already forwarded there"))?;
+
+    if let Err(refusal) =
+        request_confirmation(&state, &outbound, &temp.temp_email_addr, &rule).await
+    {
+        if let Err(e) = delete_forwarding_rule(&pool, temp.id, rule.id).await {
+            tracing::error!(rule = %rule.id, error = %e, "failed to drop unconfirmable rule");
+        }
+        return Err(refusal);
+    }
+    tracing::info!(
+        addr = %temp.temp_email_addr,
+        rule = %rule.id,
+        forward_to = %rule.forward_to,
+        "forwarding rule created, awaiting confirmation"
+    );
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// Mails the rule's target the link that confirms it.
+async fn request_confirmation(
+    state: &AppState,
+    outbound: &Outbound,
+    addr: &str,
+    rule: &ForwardingRule,
+) -> Result<(), Response> {
+    let exp = Utc::now().timestamp() + CONFIRM_TTL_SECS;
+    let sig = to_hex(&sign(&state.share_secret, rule, exp).finalize().into_bytes());
+    let path = format!("/api/forwards/{}/confirm?exp={exp}&sig={sig}", rule.id);
+    let url = match state.public_base_url.as_deref() {
+        Some(base) => format!("{}{path}", base.trim_end_matches('/')),
+        None => path,
+    };
+    let data = format!(
+        "{loop_header}: {marker}\r\n\
+         Auto-Submitted: auto-generated\r\n\
+         Date: {date}\r\n\
+         Message-ID: <forward-{id}@{marker}>\r\n\
+         From: <{addr}>\r\n\
+         To: <{to}>\r\n\
+         Subject: Confirm forwarding from {addr}\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         \r\n\
+         {addr} asked to have its mail forwarded to this address.\r\n\
+         \r\n\
+         To agree, open this link within {days} days and confirm:\r\n\
+         \r\n\
+         {url}\r\n\
+         \r\n\
+         If you did not ask for this, ignore this message; nothing will be\r\n\
+         forwarded.\r\n",
+        loop_header = smtp::LOOP_HEADER,
+        marker = outbound.loop_marker,
+        date = Utc::now().to_rfc2822(),
+        id = rule.id,
+        to = rule.forward_to,
+        days = CONFIRM_TTL_SECS / (24 * 60 * 60),
+    )
+    .into_bytes();
+
+    let recipients = vec![rule.forward_to.clone()];
+    let message = OutboundMessage {
+        kind: OutboundKind::AutoReply,
+        sender: addr.to_owned(),
+        recipients: recipients.clone(),
+        size: data.len(),
+        attachments: Vec::new(),
+    };
+    outbound.policy.check(&message).map_err(|v| match v {
+        PolicyViolation::RateLimited { .. } => err(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many forwarding requests, try again later",
+        ),
+        _ => err(
+            StatusCode::BAD_REQUEST,
+            "mail cannot be sent to this address",
+        ),
+    })?;
+    let envelope = Envelope {
+        mail_from: Some(addr),
+        recipients: &recipients,
+        data: &data,
+    };
+    let outcome = outbound.relays.send(&envelope).await.into_iter().next();
+    match outcome.map(|o| o.result) {
+        Some(Ok(_)) => Ok(()),
+        failed => {
+            let error = failed.and_then(Result::err).unwrap_or(RelayError::NoRelay);
+            tracing::warn!(rule = %rule.id, %error, "forwarding confirmation not sent");
+            Err(err(
+                StatusCode::BAD_GATEWAY,
+                "the confirmation message could not be sent",
+            ))
+        }
+    }
+}
+
+/// Opened from the confirmation message: a page asking the target to agree,
+/// which changes nothing, since link scanners open it too.
+#[utoipa::path(
+    get,
+    path = "/api/forwards/{rule_id}/confirm",
+    tag = "api",
+    summary = "Page asking to agree to an address's forwards",
+    params(("rule_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn show_confirmation(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(q): Query<ConfirmQuery>,
+) -> Result<Html<String>, Response> {
+    let pool = require_pool(&state).await?;
+    let rule = verified_rule(&state, &pool, id, &q).await?;
+    let forward_to = ammonia::clean_text(&rule.forward_to);
+    Ok(Html(format!(
+        r#"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Confirm forwarding</title>
+</head>
+<body>
+<p>Mail sent to a temporary address will be forwarded to {forward_to}.</p>
+<form method="post">
+<button type="submit">Forward it to me</button>
+</form>
+<p>If you did not ask for this, close this page; nothing will be forwarded.</p>
+</body>
+</html>
+"#
+    )))
+}
+
+/// Sent by the page's form, so it takes no token: the signed link is the
+/// proof that the target agreed.
+#[utoipa::path(
+    post,
+    path = "/api/forwards/{rule_id}/confirm",
+    tag = "api",
+    summary = "Agree to receive an address's forwards",
+    params(("rule_id" = Uuid, Path), ConfirmQuery),
+    responses((status = 200, description = "OK", body = ForwardingRule))
+)]
+pub async fn confirm_forward(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(q): Query<ConfirmQuery>,
+) -> Result<Json<ForwardingRule>, Response> {
+    let pool = require_pool(&state).await?;
+    let rule = verified_rule(&state, &pool, id, &q).await?;
+    let rule = confirm_forwarding_rule(&pool, rule.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid_link)?;
+    tracing::info!(rule = %rule.id, forward_to = %rule.forward_to, "forwarding rule confirmed");
+    Ok(Json(rule))
+}
+
+/// One answer for every failure so links can't be probed.
+fn invalid_link() -> Response {
+    err(
+        StatusCode::NOT_FOUND,
+        "invalid or expired confirmation link",
+    )
+}
+
+/// The rule a confirmation link names, if the link is genuine and, for a
+/// pending rule, unexpired.
+async fn verified_rule(
+    state: &AppState,
+    pool: &PgPool,
+    id: Uuid,
+    q: &ConfirmQuery,
+) -> Result<ForwardingRule, Response> {
+    let rule = find_forwarding_rule(pool, id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid_link)?;
+    let sig = from_hex(&q.sig).ok_or_else(invalid_link)?;
+    sign(&state.share_secret, &rule, q.exp)
+        .verify_slice(&sig)
+        .map_err(|_| invalid_link())?;
+    let exp = Utc
+        .timestamp_opt(q.exp, 0)
+        .single()
+        .ok_or_else(invalid_link)?;
+    if rule.confirmed_at.is_none() && exp <= Utc::now() {
+        return Err(invalid_link());
+    }
+    Ok(rule)
+}
+
+fn sign(secret: &[u8], rule: &ForwardingRule, exp: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(format!("forward.{}.{}.{exp}", rule.id, rule.forward_to).as_bytes());
+    mac
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/forwards/{rule_id}",
+    tag = "api",
+    summary = "Stop forwarding to a mailbox",
+    params(("address" = String, Path), ("rule_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn delete_forward(
+    State(state): State<AppState>,
+    Path((address, id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let deleted = delete_forwarding_rule(&pool, temp.id, id)
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown forwarding rule"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Sends due forwards every `poll_interval`, forever.
+pub async fn run_forward_worker(pool: PgPool, outbound: Arc<Outbound>, config: ForwardConfig) {
+    // Long enough for a forward to time out on every relay in turn.
+    let relays = outbound.relays.config();
+    let lease = relays
+        .timeout
+        .saturating_mul(relays.hosts.len().max(1) as u32)
+        + Duration::from_secs(30);
+
+    let mut ticker = tokio::time::interval(config.poll_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        loop {
+            let batch = match claim_forward_deliveries(&pool, BATCH_SIZE, lease).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to claim forwards");
+                    break;
+                }
+            };
+            let full = batch.len() as i64 == BATCH_SIZE;
+            let mut sends = JoinSet::new();
+            for delivery in batch {
+                let (pool, outbound) = (pool.clone(), Arc::clone(&outbound));
+                sends.spawn(async move { forward(&pool, &outbound, &config, delivery).await });
+            }
+            while sends.join_next().await.is_some() {}
+            if !full {
+                break;
+            }
+        }
+    }
+}
+
+enum Failure {
+    /// Worth another attempt: after `after`, or the usual backoff.
+    Retry {
+        error: String,
+        after: Option<Duration>,
+    },
+    /// Another attempt would fail the same way.
+    Final(String),
+}
+
+async fn forward(
+    pool: &PgPool,
+    outbound: &Outbound,
+    config: &ForwardConfig,
+    delivery: PendingForward,
+) {
+    let attempt = u32::try_from(delivery.attempts).unwrap_or(0) + 1;
+    let result = match fetch_raw_email(pool, delivery.received_email_id).await {
+        Ok(Some(raw)) => send(pool, outbound, &delivery, &raw).await,
+        // Deleted (the forward goes with it) or redacted since.
+        Ok(None) => Err(Failure::Final("the message source is gone".into())),
+        Err(e) => Err(Failure::Retry {
+            error: e.to_string(),
+            after: None,
+        }),
+    };
+    let recorded = match result {
+        Ok(relay) => {
+            tracing::debug!(forward = %delivery.id, %relay, "message forwarded");
+            metrics::counter!("forward_deliveries_total", "outcome" => "delivered").increment(1);
+            complete_forward_delivery(pool, delivery.id).await
+        }
+        Err(Failure::Retry { error, after }) if config.retry.allows_retry(attempt) => {
+            let retry_in = after.unwrap_or_else(|| config.retry.backoff(attempt));
+            tracing::debug!(
+                forward = %delivery.id,
+                attempt,
+                %error,
+                retry_in_secs = retry_in.as_secs(),
+                "forward failed"
+            );
+            metrics::counter!("forward_deliveries_total", "outcome" => "retried").increment(1);
+            fail_forward_delivery(pool, delivery.id, &error, Some(retry_in)).await
+        }
+        Err(Failure::Retry { error, .. } | Failure::Final(error)) => {
+            tracing::warn!(forward = %delivery.id, attempt, %error, "forward abandoned");
+            metrics::counter!("forward_deliveries_total", "outcome" => "abandoned").increment(1);
+            fail_forward_delivery(pool, delivery.id, &error, None).await
+        }
+    };
+    if let Err(e) = recorded {
+        tracing::error!(forward = %delivery.id, error = %e, "failed to record forward");
+    }
+}
+
+/// The name of the relay that took the message.
+async fn send(
+    pool: &PgPool,
+    outbound: &Outbound,
+    delivery: &PendingForward,
+    raw: &[u8],
+) -> Result<String, Failure> {
+    let attachments = list_attachments(pool, delivery.received_email_id)
+        .await
+        .map_err(|e| Failure::Retry {
+            error: e.to_string(),
+            after: None,
+        })?
+        .into_iter()
+        .map(|a| Attachment {
+            filename: a.filename,
+            content_type: a.content_type,
+        })
+        .collect();
+    let recipients = vec![delivery.forward_to.clone()];
+    let data = stamp(raw, &outbound.loop_marker, &delivery.forward_to);
+    let message = OutboundMessage {
+        kind: OutboundKind::Forward,
+        sender: delivery.temp_email_addr.clone(),
+        recipients: recipients.clone(),
+        size: data.len(),
+        attachments,
+    };
+    outbound.policy.check(&message).map_err(|v| match v {
+        PolicyViolation::RateLimited { retry_after } => Failure::Retry {
+            error: v.to_string(),
+            after: Some(retry_after),
+        },
+        v => Failure::Final(v.to_string()),
+    })?;
+
+    let envelope = Envelope {
+        mail_from: Some(delivery.temp_email_addr.as_str()),
+        recipients: &recipients,
+        data: &data,
+    };
+    let outcome = outbound.relays.send(&envelope).await.into_iter().next();
+    match outcome.map(|o| o.result) {
+        Some(Ok(relay)) => Ok(relay),
+        Some(Err(e @ RelayError::Unavailable { .. })) => Err(Failure::Retry {
+            error: e.to_string(),
+            after: None,
+        }),
+        Some(Err(e)) => Err(Failure::Final(e.to_string())),
+        None => Err(Failure::Final(RelayError::NoRelay.to_string())),
+    }
+}
+
+/// `raw` with our `X-Loop` marker and the forward's target on top.
+fn stamp(raw: &[u8], loop_marker: &str, forward_to: &str) -> Vec<u8> {
+    let mut data = format!(
+        "{}: {loop_marker}\r\nX-Forwarded-To: {forward_to}\r\n",
+        smtp::LOOP_HEADER
+    )
+    .into_bytes();
+    data.extend_from_slice(raw);
+    data
+}
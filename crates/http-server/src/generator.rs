@@ -0,0 +1,89 @@
+use db::LocalPartBlocklist;
+use rand::{distributions::Alphanumeric, Rng};
+
+pub const MIN_USERNAME_LEN: usize = 3;
+pub const MAX_USERNAME_LEN: usize = 32;
+const RANDOM_LOCAL_LEN: usize = 8;
+const SUFFIX_LEN: usize = 3;
+const MAX_EXTRA_LEN: usize = 8;
+const MAX_DRAWS: usize = 16;
+
+fn rand_lower(rng: &mut impl Rng, len: usize) -> String {
+    rng.sample_iter(&Alphanumeric)
+        .take(len)
+        .map(|b| (b as char).to_ascii_lowercase())
+        .collect()
+}
+
+pub fn full_address(local: &str, domain: &str) -> String {
+    format!("{local}@{domain}")
+}
+
+// Each collision buys two more random characters, so a crowded namespace
+// degrades into longer addresses rather than allocation failures.
+fn extra_len(collisions: usize) -> usize {
+    (collisions * 2).min(MAX_EXTRA_LEN)
+}
+
+// Draws until a candidate clears the blocklist; `None` means the operator's
+// patterns leave no room at this length.
+fn draw(blocked: &LocalPartBlocklist, mut make: impl FnMut() -> String) -> Option<String> {
+    (0..MAX_DRAWS)
+        .map(|_| make())
+        .find(|local| !blocked.is_blocked(local))
+}
+
+pub fn random_local_part(collisions: usize, blocked: &LocalPartBlocklist) -> Option<String> {
+    let len = RANDOM_LOCAL_LEN + extra_len(collisions);
+    draw(blocked, || rand_lower(&mut rand::thread_rng(), len))
+}
+
+pub fn with_suffix(
+    username: &str,
+    collisions: usize,
+    blocked: &LocalPartBlocklist,
+) -> Option<String> {
+    let len = SUFFIX_LEN + extra_len(collisions);
+    draw(blocked, || {
+        format!("{username}{}", rand_lower(&mut rand::thread_rng(), len))
+    })
+}
+
+pub fn suggestions(username: &str, n: usize, blocked: &LocalPartBlocklist) -> Vec<String> {
+    let mut out: Vec<String> = Vec::with_capacity(n);
+    for _ in 0..MAX_DRAWS {
+        if out.len() == n {
+            break;
+        }
+        match with_suffix(username, 0, blocked) {
+            Some(candidate) if !out.contains(&candidate) => out.push(candidate),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    out
+}
+
+pub fn validate_username(raw: &str, blocked: &LocalPartBlocklist) -> Result<String, String> {
+    let name = raw.trim().to_ascii_lowercase();
+    let len = name.chars().count();
+    if !(MIN_USERNAME_LEN..=MAX_USERNAME_LEN).contains(&len) {
+        return Err(format!(
+            "username must be {MIN_USERNAME_LEN}-{MAX_USERNAME_LEN} characters"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        return Err("username may only contain letters, digits, '_', '-' and '.'".into());
+    }
+    let edge_ok = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !edge_ok(name.chars().next()) || !edge_ok(name.chars().last()) || name.contains("..") {
+        return Err("username must start and end with a letter or digit".into());
+    }
+    if blocked.is_blocked(&name) {
+        return Err("username is not allowed".into());
+    }
+    Ok(name)
+}
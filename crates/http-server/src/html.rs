@@ -0,0 +1,269 @@
+//! Sanitized HTML bodies, as served by `/api/email/:address/:email_id/html`,
+//! returned in JSON with `?sanitized=true` and sent to the preview renderer.
+//! Optionally drops tracking pixels and routes remote images through an
+//! image proxy, so opening a message does not tell its sender that, when, or
+//! from which IP it was read.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use db::{QuotaWarning, ReceivedEmail};
+use regex::Regex;
+use scraper::{node::Element, Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::api::err;
+use crate::image_proxy;
+use crate::quota;
+use crate::read::{mark_opened, MarkRead};
+use crate::share::owned_email;
+use crate::AppState;
+
+/// Remote `url(...)` references in CSS, quoted or not (`&quot;` inside
+/// serialized `style` attributes).
+static REMOTE_CSS_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?i)url\(\s*(?:&quot;|&#39;|["'])?\s*(?:https?:)?//[^)]*\)"#)
+        .expect("valid regex")
+});
+static CSS_IMPORT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)@import[^;]*;?").expect("valid regex"));
+
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    /// Drop images of at most 1×1 pixel or hidden with `display: none`.
+    pub strip_tracking_pixels: bool,
+    /// Remote images are loaded via `<proxy>?url=<image URL>`, and remote
+    /// CSS backgrounds and imports are dropped. Unset loads them directly
+    /// from the sender, as a mail client would.
+    pub image_proxy: Option<Arc<str>>,
+    /// Adds `&sig=` to proxied image URLs, an HMAC-SHA256 of the image URL,
+    /// which the built-in proxy checks so it cannot be used as an open proxy.
+    pub image_proxy_key: Option<Arc<[u8]>>,
+}
+
+impl DisplayOptions {
+    /// `img-src` for the response's Content-Security-Policy.
+    fn image_sources(&self) -> String {
+        match self.image_proxy.as_deref() {
+            Some(proxy) if proxy.starts_with('/') => "'self' data:".into(),
+            Some(proxy) => format!("{} data:", proxy.split('?').next().unwrap_or(proxy)),
+            None => "* data:".into(),
+        }
+    }
+}
+
+/// Drops scripts, event handlers, frames, forms and `javascript:` URLs but
+/// keeps what affects layout: `<style>` blocks, `style` attributes and the
+/// table attributes email HTML relies on.
+pub fn sanitize(html: &str, options: &DisplayOptions) -> String {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .rm_clean_content_tags(&["style"])
+        .add_tags(&["style"])
+        .add_generic_attributes(&["style"]);
+    if options.strip_tracking_pixels || options.image_proxy.is_some() {
+        let pixels = if options.strip_tracking_pixels {
+            tracking_pixels(html)
+        } else {
+            HashSet::new()
+        };
+        let proxy = options.image_proxy.clone();
+        let key = options.image_proxy_key.clone();
+        builder.attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                ("img", "src") if pixels.contains(value) => None,
+                ("img", "src") => {
+                    let url = proxy
+                        .as_deref()
+                        .and_then(|p| proxied(p, key.as_deref(), value));
+                    Some(url.map_or(Cow::Borrowed(value), Cow::Owned))
+                }
+                _ => Some(Cow::Borrowed(value)),
+            },
+        );
+    }
+    let clean = builder.clean(html).to_string();
+    if options.image_proxy.is_none() {
+        return clean;
+    }
+    let clean = REMOTE_CSS_URL.replace_all(&clean, "none");
+    CSS_IMPORT.replace_all(&clean, "").into_owned()
+}
+
+/// Replaces the message's HTML body with its sanitized form.
+pub(crate) fn sanitize_body(email: &mut ReceivedEmail, options: &DisplayOptions) {
+    if let Some(html) = email.body_html.as_mut() {
+        *html = sanitize(html, options);
+    }
+}
+
+/// `src` of every image that looks like a tracking pixel.
+fn tracking_pixels(html: &str) -> HashSet<String> {
+    let document = Html::parse_document(html);
+    let img = Selector::parse("img").expect("valid selector");
+    document
+        .select(&img)
+        .filter(|el| is_tracking_pixel(el.value()))
+        .filter_map(|el| el.value().attr("src"))
+        .map(str::to_owned)
+        .collect()
+}
+
+fn is_tracking_pixel(img: &Element) -> bool {
+    let style: String = img
+        .attr("style")
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .split_whitespace()
+        .collect();
+    let declared = |property: &str| {
+        style
+            .split(';')
+            .find_map(|d| d.strip_prefix(property)?.strip_prefix(':'))
+    };
+    let tiny = |size: Option<&str>| {
+        size.and_then(|s| s.trim().trim_end_matches("px").parse::<f32>().ok())
+            .is_some_and(|px| px <= 1.0)
+    };
+    (tiny(img.attr("width")) && tiny(img.attr("height")))
+        || (tiny(declared("width")) && tiny(declared("height")))
+        || declared("display") == Some("none")
+}
+
+/// `url` routed through `proxy`, if it is remote, signed with `key`.
+fn proxied(proxy: &str, key: Option<&[u8]>, url: &str) -> Option<String> {
+    let lower = url.trim_start().to_ascii_lowercase();
+    let url = if lower.starts_with("http://") || lower.starts_with("https://") {
+        Cow::Borrowed(url.trim_start())
+    } else if lower.starts_with("//") {
+        Cow::Owned(format!("https:{}", url.trim_start()))
+    } else {
+        return None;
+    };
+    let separator = if proxy.contains('?') { '&' } else { '?' };
+    let mut proxied = format!("{proxy}{separator}url={}", percent_encode(&url));
+    if let Some(key) = key {
+        proxied.push_str("&sig=");
+        proxied.push_str(&image_proxy::sign(key, &url));
+    }
+    Some(proxied)
+}
+
+fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BodyView {
+    /// Sanitize `body_html` as `/html` serves it; off by default, returning
+    /// the body as received.
+    #[serde(default)]
+    pub sanitized: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = MessageDetail)]
+pub struct EmailDetail {
+    #[serde(flatten)]
+    pub email: ReceivedEmail,
+    /// Quotas of the mailbox at least 80% used; see [`quota`].
+    pub warnings: Vec<QuotaWarning>,
+    /// See [`ReceivedEmail::recipient_mismatch`].
+    pub recipient_mismatch: bool,
+}
+
+/// One message, as `poll` lists it.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}",
+    tag = "api",
+    summary = "One message",
+    params(("address" = String, Path), ("email_id" = Uuid, Path), BodyView, MarkRead),
+    responses((status = 200, description = "OK", body = EmailDetail)),
+    security(("mailbox" = []))
+)]
+pub async fn email_detail(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+    Query(read): Query<MarkRead>,
+    Query(view): Query<BodyView>,
+) -> Result<Response, Response> {
+    let (pool, mut email) = owned_email(&state, &address, email_id).await?;
+    mark_opened(&pool, &email, &read).await;
+    email.is_read |= read.mark_read;
+    if view.sanitized {
+        sanitize_body(&mut email, &state.html_display);
+    }
+    let warnings = quota::warnings(&state, &pool, email.temporary_email_id).await?;
+    let detail = Json(EmailDetail {
+        recipient_mismatch: email.recipient_mismatch(),
+        email,
+        warnings: warnings.clone(),
+    });
+    Ok(quota::with_headers(detail, &warnings))
+}
+
+/// The sanitized HTML body, for showing in an iframe. The CSP keeps the
+/// page from running anything or loading more than images and inline styles.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/html",
+    tag = "api",
+    summary = "Sanitized HTML body",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_html(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+    Query(read): Query<MarkRead>,
+) -> Result<Response, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let html = email
+        .body_html
+        .as_deref()
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "message has no HTML body"))?;
+    mark_opened(&pool, &email, &read).await;
+    let options = &state.html_display;
+    let csp = format!(
+        "default-src 'none'; img-src {}; style-src 'unsafe-inline'; sandbox allow-popups",
+        options.image_sources()
+    );
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            ),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(&csp)
+                    .unwrap_or(HeaderValue::from_static("default-src 'none'")),
+            ),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+        ],
+        sanitize(html, options),
+    )
+        .into_response())
+}
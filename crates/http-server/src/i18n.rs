@@ -0,0 +1,588 @@
+//! Translates error bodies into the caller's `Accept-Language`, so frontends
+//! can show them as-is. Handlers keep writing English; a layer swaps the text
+//! of error responses (plain text, or the `error` field of a JSON body) for
+//! the catalog entry of the best supported language. Messages without an
+//! entry stay English.
+
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use serde_json::Value;
+
+/// Error bodies are short; anything larger or unsized is passed through.
+const MAX_LOCALIZED_BODY: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Es,
+    Hi,
+}
+
+impl Lang {
+    pub fn tag(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::Es => "es",
+            Self::Hi => "hi",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split(['-', '_']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::En),
+            "es" => Some(Self::Es),
+            "hi" => Some(Self::Hi),
+            _ => None,
+        }
+    }
+
+    /// Highest-weighted supported language of an `Accept-Language` header;
+    /// earlier entries win ties. Falls back to English.
+    pub fn negotiate(headers: &HeaderMap) -> Self {
+        let Some(raw) = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return Self::En;
+        };
+        let mut best: Option<(Self, f32)> = None;
+        for entry in raw.split(',') {
+            let mut parts = entry.split(';').map(str::trim);
+            let Some(lang) = parts.next().and_then(Self::from_tag) else {
+                continue;
+            };
+            let q = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+                .unwrap_or(0.0);
+            if q > 0.0 && best.is_none_or(|(_, b)| q > b) {
+                best = Some((lang, q));
+            }
+        }
+        best.map_or(Self::En, |(lang, _)| lang)
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::En => &[],
+            Self::Es => ES,
+            Self::Hi => HI,
+        }
+    }
+
+    /// `msg` in this language, or `None` when the catalog has no entry.
+    /// Catalog keys may contain `{}`, which match any text that is carried
+    /// over into the translation in order.
+    pub fn translate(self, msg: &str) -> Option<String> {
+        self.catalog()
+            .iter()
+            .find_map(|(key, text)| fill(text, &captures(key, msg)?))
+    }
+}
+
+/// The text matched by each `{}` of `template`, if `msg` fits it.
+fn captures<'a>(template: &str, msg: &'a str) -> Option<Vec<&'a str>> {
+    let mut pieces = template.split("{}");
+    let first = pieces.next()?;
+    let mut rest = msg.strip_prefix(first)?;
+    let mut caught = Vec::new();
+    let mut pieces = pieces.peekable();
+    while let Some(piece) = pieces.next() {
+        let end = if pieces.peek().is_none() {
+            rest.strip_suffix(piece)?.len()
+        } else if piece.is_empty() {
+            return None;
+        } else {
+            rest.find(piece)?
+        };
+        caught.push(&rest[..end]);
+        rest = &rest[end + piece.len()..];
+    }
+    rest.is_empty().then_some(caught)
+}
+
+fn fill(text: &str, args: &[&str]) -> Option<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut args = args.iter();
+    let mut pieces = text.split("{}");
+    out.push_str(pieces.next()?);
+    for piece in pieces {
+        out.push_str(args.next()?);
+        out.push_str(piece);
+    }
+    Some(out)
+}
+
+/// Localizes error responses; successful ones only get `Vary`.
+pub(crate) async fn localize(req: Request, next: Next) -> Response {
+    let lang = Lang::negotiate(req.headers());
+    let mut res = next.run(req).await;
+    res.headers_mut()
+        .append(header::VARY, HeaderValue::from_static("accept-language"));
+    if lang == Lang::En || !(res.status().is_client_error() || res.status().is_server_error()) {
+        return res;
+    }
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let is_json = content_type.starts_with("application/json");
+    if !is_json && !content_type.starts_with("text/plain") {
+        return res;
+    }
+
+    let fits = res
+        .body()
+        .size_hint()
+        .upper()
+        .is_some_and(|n| n <= MAX_LOCALIZED_BODY as u64);
+    if !fits {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let Ok(bytes) = to_bytes(body, MAX_LOCALIZED_BODY).await else {
+        tracing::warn!("failed to read error body for localization");
+        return Response::from_parts(parts, Body::empty());
+    };
+    let localized = if is_json {
+        serde_json::from_slice::<Value>(&bytes).ok().and_then(|mut v| {
+            let text = lang.translate(v.get("error")?.as_str()?)?;
+            v["error"] = Value::String(text);
+            serde_json::to_vec(&v).ok()
+        })
+    } else {
+        std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(|msg| lang.translate(msg))
+            .map(String::into_bytes)
+    };
+    let Some(localized) = localized else {
+        return Response::from_parts(parts, Body::from(bytes));
+    };
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_LANGUAGE,
+        HeaderValue::from_static(lang.tag()),
+    );
+    Response::from_parts(parts, Body::from(localized))
+}
+
+const ES: &[(&str, &str)] = &[
+    ("database error", "error de la base de datos"),
+    ("database not ready", "la base de datos no está lista"),
+    ("mailbox not found", "buzón no encontrado"),
+    ("invalid API key", "clave de API no válida"),
+    ("username is taken", "el nombre de usuario ya está en uso"),
+    (
+        "could not allocate a unique address; try again",
+        "no se pudo asignar una dirección única; inténtalo de nuevo",
+    ),
+    ("could not generate a name", "no se pudo generar un nombre"),
+    ("address already exists", "la dirección ya existe"),
+    (
+        "username must be {}-{} characters",
+        "el nombre de usuario debe tener entre {} y {} caracteres",
+    ),
+    (
+        "username may only contain letters, digits, '_', '-' and '.'",
+        "el nombre de usuario solo puede contener letras, dígitos, '_', '-' y '.'",
+    ),
+    (
+        "username must start and end with a letter or digit",
+        "el nombre de usuario debe empezar y terminar con una letra o un dígito",
+    ),
+    ("username is not allowed", "ese nombre de usuario no está permitido"),
+    (
+        "count must be between 1 and {}",
+        "count debe estar entre 1 y {}",
+    ),
+    (
+        "invalid Idempotency-Key header",
+        "cabecera Idempotency-Key no válida",
+    ),
+    (
+        "Idempotency-Key needs an API key or a session",
+        "Idempotency-Key requiere una clave de API o una sesión",
+    ),
+    (
+        "Idempotency-Key was already used with a different request",
+        "Idempotency-Key ya se usó con otra solicitud",
+    ),
+    (
+        "the batch of this Idempotency-Key has expired",
+        "el lote de esta Idempotency-Key ha caducado",
+    ),
+    (
+        "since must be RFC3339, got {}",
+        "since debe estar en formato RFC3339; se recibió {}",
+    ),
+    (
+        "temporary address has expired",
+        "la dirección temporal ha caducado",
+    ),
+    ("address has not expired", "la dirección no ha caducado"),
+    (
+        "reactivation window has passed",
+        "el plazo de reactivación ha terminado",
+    ),
+    ("login required", "es necesario iniciar sesión"),
+    (
+        "public mailboxes are read-only",
+        "los buzones públicos son de solo lectura",
+    ),
+    ("unknown message", "mensaje desconocido"),
+    ("unknown attachment", "adjunto desconocido"),
+    ("original message not stored", "el mensaje original no está guardado"),
+    ("headers not stored", "las cabeceras no están guardadas"),
+    (
+        "minutes must be between 1 and {}",
+        "minutes debe estar entre 1 y {}",
+    ),
+    (
+        "address has reached its maximum lifetime",
+        "la dirección ya alcanzó su duración máxima",
+    ),
+    (
+        "days must be between 1 and {}",
+        "days debe estar entre 1 y {}",
+    ),
+    ("search query is required", "falta la consulta de búsqueda"),
+    (
+        "search query is too long",
+        "la consulta de búsqueda es demasiado larga",
+    ),
+    (
+        "messages are too large to compare",
+        "los mensajes son demasiado grandes para compararlos",
+    ),
+    ("pattern is required", "falta el patrón"),
+    (
+        "pattern must be at most {} characters",
+        "el patrón debe tener como máximo {} caracteres",
+    ),
+    (
+        "pattern is not a valid regular expression",
+        "el patrón no es una expresión regular válida",
+    ),
+    (
+        "webhook_url must be an http(s) URL",
+        "webhook_url debe ser una URL http(s)",
+    ),
+    (
+        "webhook_url must not point to a private network",
+        "webhook_url no puede apuntar a una red privada",
+    ),
+    (
+        "a mailbox can have at most {} watch rules",
+        "un buzón puede tener como máximo {} reglas de vigilancia",
+    ),
+    ("unknown watch rule", "regla de vigilancia desconocida"),
+    (
+        "a mailbox can have at most {} webhooks",
+        "un buzón puede tener como máximo {} webhooks",
+    ),
+    ("unknown webhook", "webhook desconocido"),
+    (
+        "a mailbox can have at most {} aliases",
+        "un buzón puede tener como máximo {} alias",
+    ),
+    ("address is taken", "la dirección ya está en uso"),
+    ("unknown alias", "alias desconocido"),
+    ("no code found", "no se encontró ningún código"),
+    (
+        "cannot merge a mailbox into itself",
+        "no se puede fusionar un buzón consigo mismo",
+    ),
+    (
+        "activate_at must be in the future",
+        "activate_at debe estar en el futuro",
+    ),
+    (
+        "activate_at is too far ahead",
+        "activate_at está demasiado lejos",
+    ),
+    (
+        "address is not active yet",
+        "la dirección aún no está activa",
+    ),
+    ("invalid image signature", "firma de imagen no válida"),
+    (
+        "sender must be an address or a domain such as example.com",
+        "el remitente debe ser una dirección o un dominio como example.com",
+    ),
+    (
+        "a mailbox can block at most {} senders",
+        "un buzón puede bloquear como máximo {} remitentes",
+    ),
+    ("sender already blocked", "el remitente ya está bloqueado"),
+    ("sender is not blocked", "el remitente no está bloqueado"),
+    (
+        "forward_to must be an email address",
+        "forward_to debe ser una dirección de correo",
+    ),
+    (
+        "mail cannot be forwarded to this service",
+        "el correo no se puede reenviar a este servicio",
+    ),
+    (
+        "a mailbox can forward to at most {} addresses",
+        "un buzón puede reenviar como máximo a {} direcciones",
+    ),
+    ("already forwarded there", "ya se reenvía a esa dirección"),
+    ("unknown forwarding rule", "regla de reenvío desconocida"),
+    (
+        "forwarding is not configured",
+        "el reenvío no está configurado",
+    ),
+    (
+        "mail cannot be sent to this address",
+        "no se puede enviar correo a esta dirección",
+    ),
+    (
+        "too many forwarding requests, try again later",
+        "demasiadas solicitudes de reenvío, inténtalo más tarde",
+    ),
+    (
+        "the confirmation message could not be sent",
+        "no se pudo enviar el mensaje de confirmación",
+    ),
+    (
+        "invalid or expired confirmation link",
+        "enlace de confirmación no válido o caducado",
+    ),
+    (
+        "previews are not enabled",
+        "las vistas previas no están activadas",
+    ),
+    (
+        "message has no HTML body",
+        "el mensaje no tiene cuerpo HTML",
+    ),
+    (
+        "preview renderer unavailable",
+        "el generador de vistas previas no está disponible",
+    ),
+    ("unknown share link", "enlace compartido desconocido"),
+    (
+        "invalid or expired share link",
+        "enlace compartido no válido o caducado",
+    ),
+    (
+        "invalid or expired session",
+        "sesión no válida o caducada",
+    ),
+    (
+        "invalid or expired refresh token",
+        "token de renovación no válido o caducado",
+    ),
+    ("login is not configured", "el inicio de sesión no está configurado"),
+    (
+        "identity provider unavailable",
+        "el proveedor de identidad no está disponible",
+    ),
+    (
+        "login failed or expired",
+        "el inicio de sesión falló o caducó",
+    ),
+    (
+        "Expected request with `Content-Type: application/json`",
+        "Se esperaba una solicitud con `Content-Type: application/json`",
+    ),
+    (
+        "Failed to parse the request body as JSON: {}",
+        "No se pudo interpretar el cuerpo de la solicitud como JSON: {}",
+    ),
+    (
+        "Failed to deserialize the JSON body into the target type: {}",
+        "El cuerpo JSON no tiene el formato esperado: {}",
+    ),
+];
+
+const HI: &[(&str, &str)] = &[
+    ("database error", "डेटाबेस त्रुटि"),
+    ("database not ready", "डेटाबेस अभी तैयार नहीं है"),
+    ("mailbox not found", "मेलबॉक्स नहीं मिला"),
+    ("invalid API key", "अमान्य API कुंजी"),
+    ("username is taken", "यह यूज़रनेम पहले से लिया जा चुका है"),
+    (
+        "could not allocate a unique address; try again",
+        "अनोखा पता नहीं बन सका; फिर से कोशिश करें",
+    ),
+    ("could not generate a name", "नाम नहीं बनाया जा सका"),
+    ("address already exists", "यह पता पहले से मौजूद है"),
+    (
+        "username must be {}-{} characters",
+        "यूज़रनेम {}-{} अक्षरों का होना चाहिए",
+    ),
+    (
+        "username may only contain letters, digits, '_', '-' and '.'",
+        "यूज़रनेम में केवल अक्षर, अंक, '_', '-' और '.' हो सकते हैं",
+    ),
+    (
+        "username must start and end with a letter or digit",
+        "यूज़रनेम किसी अक्षर या अंक से शुरू और खत्म होना चाहिए",
+    ),
+    ("username is not allowed", "यह यूज़रनेम अनुमत नहीं है"),
+    ("count must be between 1 and {}", "count 1 से {} के बीच होना चाहिए"),
+    ("invalid Idempotency-Key header", "अमान्य Idempotency-Key हेडर"),
+    (
+        "Idempotency-Key needs an API key or a session",
+        "Idempotency-Key के लिए API कुंजी या सत्र आवश्यक है",
+    ),
+    (
+        "Idempotency-Key was already used with a different request",
+        "Idempotency-Key का उपयोग किसी अन्य अनुरोध के साथ पहले ही हो चुका है",
+    ),
+    (
+        "the batch of this Idempotency-Key has expired",
+        "इस Idempotency-Key का बैच समाप्त हो चुका है",
+    ),
+    (
+        "since must be RFC3339, got {}",
+        "since RFC3339 प्रारूप में होना चाहिए, मिला {}",
+    ),
+    ("temporary address has expired", "अस्थायी पते की अवधि समाप्त हो गई है"),
+    ("address has not expired", "पते की अवधि अभी समाप्त नहीं हुई है"),
+    ("reactivation window has passed", "पुनः सक्रिय करने की समय-सीमा बीत चुकी है"),
+    ("login required", "लॉगिन आवश्यक है"),
+    ("public mailboxes are read-only", "सार्वजनिक मेलबॉक्स केवल पढ़ने के लिए हैं"),
+    ("unknown message", "अज्ञात संदेश"),
+    ("unknown attachment", "अज्ञात अटैचमेंट"),
+    ("original message not stored", "मूल संदेश सहेजा नहीं गया है"),
+    ("headers not stored", "हेडर सहेजे नहीं गए हैं"),
+    (
+        "minutes must be between 1 and {}",
+        "minutes 1 से {} के बीच होना चाहिए",
+    ),
+    (
+        "address has reached its maximum lifetime",
+        "पता अपनी अधिकतम अवधि तक पहुँच चुका है",
+    ),
+    (
+        "days must be between 1 and {}",
+        "days 1 से {} के बीच होना चाहिए",
+    ),
+    ("search query is required", "खोज क्वेरी आवश्यक है"),
+    ("search query is too long", "खोज क्वेरी बहुत लंबी है"),
+    (
+        "messages are too large to compare",
+        "संदेश तुलना के लिए बहुत बड़े हैं",
+    ),
+    ("pattern is required", "पैटर्न आवश्यक है"),
+    (
+        "pattern must be at most {} characters",
+        "पैटर्न अधिकतम {} अक्षरों का हो सकता है",
+    ),
+    (
+        "pattern is not a valid regular expression",
+        "पैटर्न मान्य रेगुलर एक्सप्रेशन नहीं है",
+    ),
+    (
+        "webhook_url must be an http(s) URL",
+        "webhook_url एक http(s) URL होना चाहिए",
+    ),
+    (
+        "webhook_url must not point to a private network",
+        "webhook_url किसी निजी नेटवर्क की ओर इशारा नहीं कर सकता",
+    ),
+    (
+        "a mailbox can have at most {} watch rules",
+        "एक मेलबॉक्स में अधिकतम {} वॉच नियम हो सकते हैं",
+    ),
+    ("unknown watch rule", "अज्ञात वॉच नियम"),
+    (
+        "a mailbox can have at most {} webhooks",
+        "एक मेलबॉक्स में अधिकतम {} वेबहुक हो सकते हैं",
+    ),
+    ("unknown webhook", "अज्ञात वेबहुक"),
+    (
+        "a mailbox can have at most {} aliases",
+        "एक मेलबॉक्स में अधिकतम {} उपनाम हो सकते हैं",
+    ),
+    ("address is taken", "यह पता पहले से लिया जा चुका है"),
+    ("unknown alias", "अज्ञात उपनाम"),
+    ("no code found", "कोई कोड नहीं मिला"),
+    (
+        "cannot merge a mailbox into itself",
+        "मेलबॉक्स को खुद में नहीं मिलाया जा सकता",
+    ),
+    (
+        "activate_at must be in the future",
+        "activate_at भविष्य में होना चाहिए",
+    ),
+    ("activate_at is too far ahead", "activate_at बहुत आगे है"),
+    ("address is not active yet", "पता अभी सक्रिय नहीं है"),
+    ("invalid image signature", "अमान्य छवि हस्ताक्षर"),
+    (
+        "sender must be an address or a domain such as example.com",
+        "प्रेषक कोई पता या example.com जैसा डोमेन होना चाहिए",
+    ),
+    (
+        "a mailbox can block at most {} senders",
+        "एक मेलबॉक्स अधिकतम {} प्रेषकों को ब्लॉक कर सकता है",
+    ),
+    ("sender already blocked", "प्रेषक पहले से ब्लॉक है"),
+    ("sender is not blocked", "प्रेषक ब्लॉक नहीं है"),
+    (
+        "forward_to must be an email address",
+        "forward_to एक ईमेल पता होना चाहिए",
+    ),
+    (
+        "mail cannot be forwarded to this service",
+        "मेल को इस सेवा पर फ़ॉरवर्ड नहीं किया जा सकता",
+    ),
+    (
+        "a mailbox can forward to at most {} addresses",
+        "एक मेलबॉक्स अधिकतम {} पतों पर फ़ॉरवर्ड कर सकता है",
+    ),
+    ("already forwarded there", "इस पते पर पहले से फ़ॉरवर्ड हो रहा है"),
+    ("unknown forwarding rule", "अज्ञात फ़ॉरवर्डिंग नियम"),
+    ("forwarding is not configured", "फ़ॉरवर्डिंग कॉन्फ़िगर नहीं है"),
+    (
+        "mail cannot be sent to this address",
+        "इस पते पर मेल नहीं भेजा जा सकता",
+    ),
+    (
+        "too many forwarding requests, try again later",
+        "बहुत अधिक फ़ॉरवर्डिंग अनुरोध, बाद में पुनः प्रयास करें",
+    ),
+    (
+        "the confirmation message could not be sent",
+        "पुष्टि संदेश नहीं भेजा जा सका",
+    ),
+    (
+        "invalid or expired confirmation link",
+        "अमान्य या समाप्त पुष्टि लिंक",
+    ),
+    ("previews are not enabled", "प्रीव्यू सक्षम नहीं हैं"),
+    ("message has no HTML body", "संदेश में HTML बॉडी नहीं है"),
+    ("preview renderer unavailable", "प्रीव्यू रेंडरर उपलब्ध नहीं है"),
+    ("unknown share link", "अज्ञात शेयर लिंक"),
+    ("invalid or expired share link", "अमान्य या समाप्त शेयर लिंक"),
+    ("invalid or expired session", "अमान्य या समाप्त सत्र"),
+    ("invalid or expired refresh token", "अमान्य या समाप्त रिफ्रेश टोकन"),
+    ("login is not configured", "लॉगिन कॉन्फ़िगर नहीं है"),
+    ("identity provider unavailable", "पहचान प्रदाता उपलब्ध नहीं है"),
+    ("login failed or expired", "लॉगिन विफल रहा या समाप्त हो गया"),
+    (
+        "Expected request with `Content-Type: application/json`",
+        "`Content-Type: application/json` वाला अनुरोध अपेक्षित था",
+    ),
+    (
+        "Failed to parse the request body as JSON: {}",
+        "अनुरोध का बॉडी JSON के रूप में पढ़ा नहीं जा सका: {}",
+    ),
+    (
+        "Failed to deserialize the JSON body into the target type: {}",
+        "JSON बॉडी अपेक्षित प्रारूप में नहीं है: {}",
+    ),
+];
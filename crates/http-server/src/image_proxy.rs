@@ -0,0 +1,340 @@
+//! `/api/proxy/image?url=…&sig=…`, the image proxy that [`html`](crate::html)
+//! points remote images at, so the reader's browser never contacts the
+//! sender. With a signing key only URLs signed by [`sign`] are fetched, so
+//! it cannot be used to fetch arbitrary URLs from the server's address.
+//! Only http(s) hosts that resolve to public addresses are fetched,
+//! redirects included, and only raster images up to a size limit are passed
+//! on. Fetched images are kept in memory for a while, which also spares the
+//! sender repeated requests.
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::{redirect, Url};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::api::err;
+use crate::share::{from_hex, to_hex};
+use crate::watch::{is_internal, points_inward};
+use crate::AppState;
+
+const MAX_REDIRECTS: usize = 5;
+/// Formats that cannot carry scripts; SVG in particular is refused.
+const IMAGE_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "image/x-icon",
+    "image/vnd.microsoft.icon",
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageProxyConfig {
+    /// Largest image passed on.
+    pub max_bytes: usize,
+    /// Deadline for fetching one image, redirects included.
+    pub timeout: Duration,
+    /// How long a fetched image is served from memory.
+    pub cache_ttl: Duration,
+    /// Memory for cached images; 0 turns caching off.
+    pub cache_bytes: usize,
+    /// Lets it fetch from loopback and private addresses, for tests.
+    pub allow_private: bool,
+}
+
+impl Default for ImageProxyConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: 5 * 1024 * 1024,
+            timeout: Duration::from_secs(10),
+            cache_ttl: Duration::from_secs(60 * 60),
+            cache_bytes: 64 * 1024 * 1024,
+            allow_private: false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Image {
+    content_type: &'static str,
+    body: Bytes,
+}
+
+enum Failure {
+    Fetch(String),
+    NotAnImage,
+    TooLarge,
+}
+
+pub struct ImageProxy {
+    client: reqwest::Client,
+    config: ImageProxyConfig,
+    cache: Mutex<Cache>,
+}
+
+impl ImageProxy {
+    pub fn new(config: ImageProxyConfig) -> Self {
+        let allow_private = config.allow_private;
+        // Each hop is checked like the first; names are checked as they
+        // are resolved, by `PublicOnly`.
+        let redirects = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !is_http(attempt.url()) {
+                attempt.error("redirect to a non-http(s) URL")
+            } else if !allow_private && points_inward(attempt.url()) {
+                attempt.error("redirect to a private network")
+            } else {
+                attempt.follow()
+            }
+        });
+        // No system proxy: it would resolve names where they cannot be checked.
+        let mut builder = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .redirect(redirects)
+            .no_proxy();
+        if !allow_private {
+            builder = builder.dns_resolver(Arc::new(PublicOnly));
+        }
+        Self {
+            client: builder.build().unwrap_or_default(),
+            config,
+            cache: Mutex::default(),
+        }
+    }
+
+    async fn get(&self, url: &Url) -> Result<Image, Failure> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(url.as_str());
+        if let Some(image) = cached {
+            metrics::counter!("image_proxy_requests_total", "result" => "cached").increment(1);
+            return Ok(image);
+        }
+        let image = self.fetch(url).await?;
+        metrics::counter!("image_proxy_requests_total", "result" => "fetched").increment(1);
+        if self.config.cache_bytes > 0 {
+            self.cache.lock().unwrap_or_else(|e| e.into_inner()).insert(
+                url.as_str(),
+                image.clone(),
+                self.config.cache_ttl,
+                self.config.cache_bytes,
+            );
+        }
+        Ok(image)
+    }
+
+    async fn fetch(&self, url: &Url) -> Result<Image, Failure> {
+        let fetch_error = |e: reqwest::Error| Failure::Fetch(e.to_string());
+        let mut res = self
+            .client
+            .get(url.clone())
+            .header(header::ACCEPT, "image/*")
+            .send()
+            .await
+            .map_err(fetch_error)?;
+        if !res.status().is_success() {
+            return Err(Failure::Fetch(format!("answered {}", res.status())));
+        }
+        let content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or(v).trim().to_ascii_lowercase())
+            .and_then(|t| IMAGE_TYPES.iter().copied().find(|&known| known == t))
+            .ok_or(Failure::NotAnImage)?;
+        let max = self.config.max_bytes;
+        if res.content_length().is_some_and(|len| len > max as u64) {
+            return Err(Failure::TooLarge);
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await.map_err(fetch_error)? {
+            if body.len() + chunk.len() > max {
+                return Err(Failure::TooLarge);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(Image {
+            content_type,
+            body: body.into(),
+        })
+    }
+}
+
+fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// Resolves names like the system does but drops loopback, private and
+/// link-local addresses, so a public name cannot be pointed inward.
+struct PublicOnly;
+
+impl Resolve for PublicOnly {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(public_addrs(name))
+    }
+}
+
+async fn public_addrs(name: Name) -> Result<Addrs, Box<dyn std::error::Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+        .await?
+        .filter(|addr| !is_internal(addr.ip()))
+        .collect();
+    if addrs.is_empty() {
+        return Err("host has no public address".into());
+    }
+    Ok(Box::new(addrs.into_iter()))
+}
+
+/// Images by URL, each until it expires; the oldest go first when full.
+#[derive(Default)]
+struct Cache {
+    images: HashMap<String, (Instant, Image)>,
+    bytes: usize,
+}
+
+impl Cache {
+    fn get(&mut self, url: &str) -> Option<Image> {
+        let (expires, image) = self.images.get(url)?;
+        if *expires > Instant::now() {
+            return Some(image.clone());
+        }
+        self.remove(url);
+        None
+    }
+
+    fn insert(&mut self, url: &str, image: Image, ttl: Duration, capacity: usize) {
+        let size = image.body.len();
+        if size > capacity {
+            return;
+        }
+        self.remove(url);
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .images
+            .iter()
+            .filter(|(_, (expires, _))| *expires <= now)
+            .map(|(url, _)| url.clone())
+            .collect();
+        for url in expired {
+            self.remove(&url);
+        }
+        while self.bytes + size > capacity {
+            let oldest = self
+                .images
+                .iter()
+                .min_by_key(|(_, (expires, _))| *expires)
+                .map(|(url, _)| url.clone());
+            match oldest {
+                Some(url) => self.remove(&url),
+                None => break,
+            }
+        }
+        self.bytes += size;
+        self.images.insert(url.to_owned(), (now + ttl, image));
+    }
+
+    fn remove(&mut self, url: &str) {
+        if let Some((_, image)) = self.images.remove(url) {
+            self.bytes -= image.body.len();
+        }
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(key: &[u8], url: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(url.as_bytes());
+    mac
+}
+
+/// The `sig` that lets the proxy fetch `url`.
+pub fn sign(key: &[u8], url: &str) -> String {
+    to_hex(&mac(key, url).finalize().into_bytes())
+}
+
+fn verify(key: &[u8], url: &str, sig: &str) -> bool {
+    from_hex(sig).is_some_and(|sig| mac(key, url).verify_slice(&sig).is_ok())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImageQuery {
+    url: String,
+    sig: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/proxy/image",
+    tag = "api",
+    summary = "Remote image fetched by the server",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn proxy_image(
+    State(state): State<AppState>,
+    Query(q): Query<ImageQuery>,
+) -> Result<Response, Response> {
+    let proxy = state
+        .image_proxy
+        .as_ref()
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "the image proxy is not enabled"))?;
+    if let Some(key) = &state.html_display.image_proxy_key {
+        if !q.sig.as_deref().is_some_and(|sig| verify(key, &q.url, sig)) {
+            metrics::counter!("image_proxy_requests_total", "result" => "unsigned").increment(1);
+            return Err(err(StatusCode::FORBIDDEN, "invalid image signature"));
+        }
+    }
+    let url = Url::parse(&q.url)
+        .ok()
+        .filter(is_http)
+        .ok_or_else(|| err(StatusCode::BAD_REQUEST, "url must be an http(s) URL"))?;
+    if !proxy.config.allow_private && points_inward(&url) {
+        metrics::counter!("image_proxy_requests_total", "result" => "refused").increment(1);
+        return Err(err(
+            StatusCode::FORBIDDEN,
+            "url must not point to a private network",
+        ));
+    }
+    let image = proxy.get(&url).await.map_err(|failure| {
+        metrics::counter!("image_proxy_requests_total", "result" => "failed").increment(1);
+        let msg = match failure {
+            Failure::Fetch(e) => {
+                tracing::debug!(error = %e, %url, "image proxy fetch failed");
+                "image could not be fetched"
+            }
+            Failure::NotAnImage => "url is not a supported image",
+            Failure::TooLarge => "image is too large",
+        };
+        err(StatusCode::BAD_GATEWAY, msg)
+    })?;
+    let cache_control = format!("public, max-age={}", proxy.config.cache_ttl.as_secs());
+    Ok((
+        [
+            (header::CONTENT_TYPE, image.content_type.to_owned()),
+            (header::CACHE_CONTROL, cache_control),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_owned()),
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; sandbox".to_owned(),
+            ),
+        ],
+        image.body,
+    )
+        .into_response())
+}
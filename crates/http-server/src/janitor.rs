@@ -0,0 +1,166 @@
+//! Background purge of inbox data.
+
+use chrono::{DateTime, Utc};
+use db::{
+    aggregate_usage, deactivate_expired_addresses, delete_expired_public_messages,
+    delete_expired_sender_expectations, delete_expired_sessions, purge_all_data_with,
+    purge_trashed_emails, PurgeOptions,
+};
+use rand::Rng;
+use sqlx::postgres::PgPool;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schedule {
+    /// Once a day at this UTC hour.
+    DailyAt(u32),
+    /// Every interval, starting one interval after startup.
+    Every(Duration),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JanitorConfig {
+    pub enabled: bool,
+    pub schedule: Schedule,
+    /// Up to this much random delay is added to every run so replicas sharing
+    /// a database do not all purge at the same instant.
+    pub jitter: Duration,
+    pub purge: PurgeOptions,
+    /// How often expired addresses are marked inactive.
+    pub expiry_sweep_interval: Duration,
+    /// Mail in public mailboxes older than this is deleted by the expiry sweep.
+    pub public_retention: Duration,
+    /// Deleted mail stays restorable from the trash this long before the
+    /// expiry sweep purges it.
+    pub trash_retention: Duration,
+    /// How often finished days of usage events are rolled up.
+    pub usage_rollup_interval: Duration,
+}
+
+impl Default for JanitorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            schedule: Schedule::DailyAt(3),
+            jitter: Duration::ZERO,
+            purge: PurgeOptions::default(),
+            expiry_sweep_interval: Duration::from_secs(60),
+            public_retention: Duration::from_secs(60 * 60),
+            trash_retention: Duration::from_secs(7 * 24 * 60 * 60),
+            usage_rollup_interval: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub async fn run(pool: PgPool, config: JanitorConfig) {
+    if !config.enabled {
+        tracing::info!("janitor disabled, inbox data will not be purged");
+        return;
+    }
+
+    loop {
+        let wait = until_next_run(Utc::now(), config.schedule) + jitter(config.jitter);
+        tracing::info!(
+            schedule = ?config.schedule,
+            wait_secs = wait.as_secs(),
+            "purge scheduled"
+        );
+        tokio::time::sleep(wait).await;
+
+        match purge_all_data_with(&pool, &config.purge).await {
+            Ok(r) => tracing::info!(
+                strategy = %config.purge.strategy,
+                emails = r.emails_deleted,
+                inboxes = r.inboxes_deleted,
+                duration_ms = r.duration.as_millis() as u64,
+                "purge complete"
+            ),
+            Err(e) => tracing::error!(error = %e, "purge failed"),
+        }
+    }
+}
+
+/// Flips `is_active` off for addresses past `expires_at` and drops expired
+/// sessions, sender expectations, public mailbox mail past its retention and
+/// trashed mail past its grace period. Lookups check both themselves, so this
+/// only has to keep the table roughly current.
+pub async fn run_expiry_sweep(pool: PgPool, config: JanitorConfig) {
+    if !config.enabled {
+        return;
+    }
+
+    let mut ticker = tokio::time::interval(config.expiry_sweep_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match deactivate_expired_addresses(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(addresses = n, "expired addresses deactivated"),
+            Err(e) => tracing::error!(error = %e, "expiry sweep failed"),
+        }
+        match delete_expired_sessions(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(sessions = n, "expired sessions deleted"),
+            Err(e) => tracing::error!(error = %e, "session sweep failed"),
+        }
+        match delete_expired_sender_expectations(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::debug!(expectations = n, "expired sender expectations deleted"),
+            Err(e) => tracing::error!(error = %e, "sender expectation sweep failed"),
+        }
+        match delete_expired_public_messages(&pool, config.public_retention).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(emails = n, "public mailbox mail expired"),
+            Err(e) => tracing::error!(error = %e, "public mailbox sweep failed"),
+        }
+        match purge_trashed_emails(&pool, config.trash_retention).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(emails = n, "trashed mail purged"),
+            Err(e) => tracing::error!(error = %e, "trash sweep failed"),
+        }
+    }
+}
+
+/// Folds each finished UTC day of raw usage events into `usage_daily`.
+pub async fn run_usage_rollup(pool: PgPool, config: JanitorConfig) {
+    if !config.enabled {
+        return;
+    }
+
+    let mut ticker = tokio::time::interval(config.usage_rollup_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        match aggregate_usage(&pool).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!(rows = n, "usage rolled up"),
+            Err(e) => tracing::error!(error = %e, "usage rollup failed"),
+        }
+    }
+}
+
+fn until_next_run(now: DateTime<Utc>, schedule: Schedule) -> Duration {
+    let hour_utc = match schedule {
+        Schedule::Every(interval) => return interval,
+        Schedule::DailyAt(hour) => hour,
+    };
+    let today = now
+        .date_naive()
+        .and_hms_opt(hour_utc, 0, 0)
+        .expect("valid purge hour");
+    let next = if now.naive_utc() >= today {
+        today + chrono::Duration::days(1)
+    } else {
+        today
+    };
+    (next - now.naive_utc())
+        .to_std()
+        .unwrap_or(Duration::from_secs(3600))
+}
+
+fn jitter(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max.as_millis() as u64))
+}
@@ -0,0 +1,22 @@
+//! Time from the SMTP server accepting a message to a client first being
+//! shown it by `poll` or the `events` stream: the number that says whether
+//! one-time codes arrive fast enough. Each first read is observed in the
+//! `mail_read_latency_seconds` histogram; `/admin/latency` breaks the
+//! percentiles down by sender domain.
+
+use db::mark_first_read;
+use sqlx::postgres::PgPool;
+use uuid::Uuid;
+
+/// Stamps the messages as read by a client. A failure is logged rather than
+/// failing the request that showed them.
+pub(crate) async fn record_first_read(pool: &PgPool, ids: &[Uuid]) {
+    match mark_first_read(pool, ids).await {
+        Ok(latencies) => {
+            for secs in latencies {
+                metrics::histogram!("mail_read_latency_seconds").record(secs);
+            }
+        }
+        Err(e) => tracing::warn!(error = %e, "failed to record first read"),
+    }
+}
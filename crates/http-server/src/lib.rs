@@ -1,13 +1,65 @@
+pub mod address;
+pub mod admin;
+pub mod alias;
+pub mod allowlist;
 pub mod api;
+pub mod attachments;
+pub mod block;
+pub mod check;
+pub mod config;
+pub mod diff;
+pub mod dns;
+pub mod expect;
+pub mod export;
+pub mod forwarding;
+pub mod generator;
+pub mod html;
+pub mod i18n;
+pub mod image_proxy;
+pub mod janitor;
+pub mod latency;
+pub mod links;
+pub mod lookup;
+pub mod mail_auth;
+pub mod mail_events;
+pub mod merge;
+pub mod metering;
+pub mod oidc;
+pub mod openapi;
+pub mod otp;
+pub mod policy;
+pub mod preview;
+pub mod quota;
+pub mod read;
+pub mod read_hooks;
+pub mod retry;
+pub mod session;
+pub mod share;
+pub mod source;
+pub mod status;
+pub mod summaries;
+pub mod supervisor;
+pub mod throttle;
+pub mod timeline;
+pub mod token;
+pub mod trash;
+pub mod watch;
+pub mod webhooks;
 
 use axum::{
     extract::State,
-    http::{header, HeaderValue, Method, StatusCode},
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, patch, post},
     Router,
 };
+use db::BlocklistCache;
+use metrics_exporter_prometheus::PrometheusHandle;
+use rand::Rng;
+use smtp::IngestEvents;
 use sqlx::postgres::PgPool;
+use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::RwLock;
@@ -17,17 +69,280 @@ use tower_http::cors::{AllowOrigin, CorsLayer};
 pub struct AppState {
     pub pool: Arc<RwLock<Option<PgPool>>>,
     pub mail_domain: Arc<str>,
+    pub admin_token: Option<Arc<str>>,
+    pub public_ip: Option<IpAddr>,
+    pub blocklist: Arc<BlocklistCache>,
+    pub ingest_events: IngestEvents,
+    /// New-mail notifications relayed from Postgres; see [`mail_events`].
+    pub mail_events: mail_events::MailEvents,
+    pub metrics: Option<PrometheusHandle>,
+    pub reactivation_grace: Duration,
+    /// Longest an address can live from creation through `extend`.
+    pub max_address_lifetime: Duration,
+    /// HMAC key for share links. Random per process unless configured, so
+    /// links die with a restart.
+    pub share_secret: Arc<[u8]>,
+    pub public_base_url: Option<Arc<str>>,
+    /// How long mail stays visible in public mailboxes.
+    pub public_retention: Duration,
+    /// Minimum latency of address-scoped endpoints; see [`lookup`].
+    pub lookup_floor: Duration,
+    /// Key for hashing mailbox access tokens; see [`token`].
+    pub token_pepper: Arc<[u8]>,
+    pub sessions: Arc<session::SessionConfig>,
+    /// `None` when login is not configured; the anonymous flow needs none.
+    pub oidc: Option<Arc<oidc::Oidc>>,
+    /// Background workers, reported by `/readyz`.
+    pub supervisor: supervisor::Supervisor,
+    /// `None` unless `RENDERER_URL` is set; see [`preview`].
+    pub renderer: Option<Arc<preview::Renderer>>,
+    /// Lets watch rules and webhooks target loopback and private addresses;
+    /// see [`watch`].
+    pub allow_private_webhooks: bool,
+    /// Per-response limit on attachment and raw-message downloads.
+    pub download_bandwidth: Option<throttle::Bandwidth>,
+    /// Whether `inbox/poll` needs the mailbox's token like the per-address
+    /// routes; off keeps polling open to anyone who knows the address.
+    pub poll_requires_token: bool,
+    /// Whether private mailboxes without an access token, such as those a
+    /// catch-all domain creates on first delivery, are open to anyone who
+    /// knows the address. Off answers them 404 except to the owner.
+    pub open_tokenless_mailboxes: bool,
+    /// How `/html` and previews treat tracking pixels and remote images.
+    pub html_display: html::DisplayOptions,
+    /// `None` unless `IMAGE_PROXY_ENABLED` is set; see [`image_proxy`].
+    pub image_proxy: Option<Arc<image_proxy::ImageProxy>>,
+    /// `None` unless a link blocklist or Safe Browsing key is configured;
+    /// see [`links`].
+    pub link_checker: Option<Arc<links::LinkChecker>>,
+    /// What `/latest-otp` looks for; see [`otp`].
+    pub otp_patterns: Arc<otp::OtpPatterns>,
+    /// Networks allowed to reach `/admin` and `/api/dev`.
+    pub admin_allowlist: Arc<allowlist::IpAllowlist>,
+    /// The SMTP server's, for warning before it is reached; see [`quota`].
+    pub mailbox_quota: db::MailboxQuota,
+    /// `None` unless `RELAY_HOSTS` is set; see [`forwarding`].
+    pub outbound: Option<Arc<forwarding::Outbound>>,
+    /// WASM plugins asked before a message is served; see [`read_hooks`].
+    pub read_hooks: read_hooks::ReadHooks,
+}
+
+impl AppState {
+    pub fn new(pool: Arc<RwLock<Option<PgPool>>>, mail_domain: Arc<str>) -> Self {
+        Self {
+            pool,
+            mail_domain,
+            admin_token: None,
+            public_ip: None,
+            blocklist: Arc::default(),
+            ingest_events: IngestEvents::default(),
+            mail_events: mail_events::MailEvents::default(),
+            metrics: None,
+            reactivation_grace: Duration::from_secs(60 * 60),
+            max_address_lifetime: Duration::from_secs(7 * 24 * 60 * 60),
+            share_secret: rand::thread_rng().gen::<[u8; 32]>().into(),
+            public_base_url: None,
+            public_retention: Duration::from_secs(60 * 60),
+            lookup_floor: Duration::from_millis(50),
+            token_pepper: Arc::from(&[][..]),
+            sessions: Arc::default(),
+            oidc: None,
+            supervisor: supervisor::Supervisor::default(),
+            renderer: None,
+            allow_private_webhooks: false,
+            download_bandwidth: None,
+            poll_requires_token: true,
+            open_tokenless_mailboxes: false,
+            html_display: html::DisplayOptions::default(),
+            image_proxy: None,
+            link_checker: None,
+            otp_patterns: Arc::default(),
+            admin_allowlist: Arc::default(),
+            mailbox_quota: db::MailboxQuota::default(),
+            outbound: None,
+            read_hooks: read_hooks::ReadHooks::default(),
+        }
+    }
 }
 
 pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/api/health", get(health_check))
-        .route("/api/temporary-address", post(api::create_temporary_address))
-        .route("/api/inbox/poll", get(api::poll_inbox_by_address))
+        .route("/api/openapi.json", get(openapi::openapi_json))
+        .route("/api/docs", get(openapi::swagger_ui))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route(
+            "/api/temporary-address",
+            post(api::create_temporary_address),
+        )
+        .route("/api/email/generate-batch", post(api::generate_batch))
+        .route(
+            "/api/session",
+            get(session::current_session).post(session::create_session),
+        )
+        .route("/api/session/refresh", post(session::refresh_session))
+        .route("/api/auth/login", get(oidc::login))
+        .route("/api/auth/callback", get(oidc::callback))
+        .route("/api/account/addresses", get(api::list_account_addresses))
+        .route("/api/proxy/image", get(image_proxy::proxy_image))
+        .merge(mailbox_router(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            metering::track,
+        ))
+        .nest("/admin", admin::router(state.clone()))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            allowlist::restrict,
+        ))
+        .layer(middleware::from_fn(i18n::localize))
         .layer(build_cors_layer())
         .with_state(state)
 }
 
+/// Routes that look up a mailbox by address. The per-address ones are guarded
+/// by its [`policy::MailboxPolicy`]; all of them get uniform latency.
+fn mailbox_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/email/:address/reactivate",
+            post(api::reactivate_address),
+        )
+        .route("/api/email/:address/extend", post(api::extend_address))
+        .route("/api/email/:address/token", post(api::rotate_token))
+        .route("/api/email/:address/merge", post(merge::merge_mailbox))
+        .route("/api/email/:address/search", get(api::search_mailbox))
+        .route(
+            "/api/email/:address/messages",
+            get(summaries::list_summaries).delete(trash::delete_all_emails),
+        )
+        .route("/api/email/:address/trash", get(trash::list_trash))
+        .route("/api/email/:address/trash/restore", post(trash::restore))
+        .route("/api/email/:address/diff", get(diff::diff_emails))
+        .route(
+            "/api/email/:address/timeline",
+            get(timeline::mailbox_timeline),
+        )
+        .route("/api/email/:address/export", get(export::export_mailbox))
+        .route(
+            "/api/email/:address/watches",
+            get(watch::list_watches).post(watch::create_watch),
+        )
+        .route("/api/email/:address/latest-otp", get(otp::latest_otp))
+        .route(
+            "/api/email/:address/aliases",
+            get(alias::list_aliases).post(alias::create_alias),
+        )
+        .route(
+            "/api/email/:address/aliases/:alias",
+            delete(alias::delete_alias),
+        )
+        .route(
+            "/api/email/:address/block",
+            get(block::list_blocks).post(block::block_sender),
+        )
+        .route(
+            "/api/email/:address/block/:sender",
+            delete(block::unblock_sender),
+        )
+        .route(
+            "/api/email/:address/expect",
+            get(expect::list_expectations).post(expect::expect_sender),
+        )
+        .route(
+            "/api/email/:address/watches/:watch_id",
+            delete(watch::delete_watch),
+        )
+        .route(
+            "/api/email/:address/webhooks",
+            get(webhooks::list_webhooks).post(webhooks::create_webhook),
+        )
+        .route(
+            "/api/email/:address/webhooks/:webhook_id",
+            delete(webhooks::delete_webhook),
+        )
+        .route(
+            "/api/email/:address/forwards",
+            get(forwarding::list_forwards).post(forwarding::create_forward),
+        )
+        .route(
+            "/api/email/:address/forwards/:rule_id",
+            delete(forwarding::delete_forward),
+        )
+        .route(
+            "/api/email/:address/events",
+            get(mail_events::stream_new_mail),
+        )
+        .route(
+            "/api/email/:address/:email_id/share",
+            post(share::create_share),
+        )
+        .route(
+            "/api/email/:address/:email_id/share/:share_id",
+            delete(share::revoke_share),
+        )
+        .route(
+            "/api/email/:address/:email_id/raw",
+            get(attachments::download_raw),
+        )
+        .route(
+            "/api/email/:address/:email_id/source",
+            get(source::email_source),
+        )
+        .route(
+            "/api/email/:address/:email_id",
+            get(html::email_detail).delete(trash::delete_email),
+        )
+        .route("/api/email/:address/:email_id/html", get(html::email_html))
+        .route(
+            "/api/email/:address/:email_id/headers",
+            get(attachments::email_headers),
+        )
+        .route(
+            "/api/email/:address/:email_id/auth",
+            get(mail_auth::email_authentication),
+        )
+        .route("/api/email/:address/:email_id/read", patch(read::set_read))
+        .route(
+            "/api/email/:address/:email_id/links",
+            get(links::email_links),
+        )
+        .route(
+            "/api/email/:address/:email_id/preview.png",
+            get(preview::email_preview),
+        )
+        .route(
+            "/api/email/:address/:email_id/attachments",
+            get(attachments::list_email_attachments),
+        )
+        .route(
+            "/api/email/:address/:email_id/attachments/:attachment_id",
+            get(attachments::download_attachment),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            policy::enforce,
+        ))
+        .route("/api/inbox/poll", get(api::poll_inbox_by_address))
+        .route("/api/share/:share_id", get(share::view_share))
+        .route(
+            "/api/forwards/:rule_id/confirm",
+            get(forwarding::show_confirmation).post(forwarding::confirm_forward),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            lookup::uniform_latency,
+        ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/health",
+    tag = "api",
+    summary = "Liveness check",
+    responses((status = "default", description = "See README.md"))
+)]
 async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     match state.pool.read().await.as_ref() {
         Some(_) => (StatusCode::OK, "OK"),
@@ -35,6 +350,37 @@ async fn health_check(State(state): State<AppState>) -> impl IntoResponse {
     }
 }
 
+/// Liveness for container health checks: unlike `/api/health`, this queries
+/// the database instead of only checking that a pool exists.
+async fn healthz(State(state): State<AppState>) -> impl IntoResponse {
+    let Some(pool) = state.pool.read().await.clone() else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "database not ready");
+    };
+    let ping = sqlx::query("SELECT 1").execute(&pool);
+    match tokio::time::timeout(Duration::from_secs(2), ping).await {
+        Ok(Ok(_)) => (StatusCode::OK, "ok"),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "database unreachable"),
+    }
+}
+
+/// Readiness: the database pool exists and every background worker is
+/// running (or finished on its own). Lists the workers either way.
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    let database = state.pool.read().await.is_some();
+    let ready = database && state.supervisor.healthy();
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = serde_json::json!({
+        "ready": ready,
+        "database": database,
+        "workers": state.supervisor.statuses(),
+    });
+    (status, axum::Json(body))
+}
+
 fn build_cors_layer() -> CorsLayer {
     let raw = std::env::var("CORS_ALLOWED_ORIGINS").unwrap_or_default();
     let origins: Vec<HeaderValue> = raw
@@ -53,7 +399,20 @@ fn build_cors_layer() -> CorsLayer {
 
     CorsLayer::new()
         .allow_origin(AllowOrigin::list(origins))
-        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers([header::CONTENT_TYPE, header::ACCEPT])
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PATCH,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            header::ACCEPT_LANGUAGE,
+            HeaderName::from_static("idempotency-key"),
+            metering::API_KEY_HEADER,
+        ])
         .max_age(Duration::from_secs(86400))
 }
@@ -0,0 +1,340 @@
+//! `/api/email/:address/:email_id/links`: the http(s) links in a message,
+//! each marked `safe`, `suspicious` or `unchecked`, so phishing delivered to
+//! a throwaway inbox can be spotted before anyone clicks. Links are checked
+//! against a local domain blocklist and, with an API key, Google Safe
+//! Browsing; anchors whose text names another site than they lead to are
+//! suspicious either way.
+
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use regex::Regex;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::share::owned_email;
+use crate::AppState;
+
+/// More than any real message has; the rest are dropped.
+const MAX_LINKS: usize = 200;
+/// Safe Browsing answers kept before the cache is cleared.
+const MAX_CACHED: usize = 10_000;
+pub const SAFE_BROWSING_URL: &str = "https://safebrowsing.googleapis.com/v4/threatMatches:find";
+const THREAT_TYPES: &[&str] = &[
+    "MALWARE",
+    "SOCIAL_ENGINEERING",
+    "UNWANTED_SOFTWARE",
+    "POTENTIALLY_HARMFUL_APPLICATION",
+];
+
+static TEXT_URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)https?://[^\s<>"]+"#).expect("valid regex"));
+
+#[derive(Debug, Clone)]
+pub struct LinkCheckConfig {
+    /// Domains whose links, subdomains included, are suspicious.
+    pub blocklist: Vec<String>,
+    /// Unset skips Safe Browsing; note that it sends every link to Google.
+    pub safe_browsing_key: Option<String>,
+    /// Lookup endpoint, replaced in tests.
+    pub safe_browsing_url: String,
+    pub timeout: Duration,
+    /// How long a Safe Browsing answer is reused.
+    pub cache_ttl: Duration,
+}
+
+impl Default for LinkCheckConfig {
+    fn default() -> Self {
+        Self {
+            blocklist: Vec::new(),
+            safe_browsing_key: None,
+            safe_browsing_url: SAFE_BROWSING_URL.into(),
+            timeout: Duration::from_secs(5),
+            cache_ttl: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Safety {
+    Safe,
+    Suspicious,
+    /// No check is configured, or Safe Browsing could not be reached.
+    Unchecked,
+}
+
+impl Safety {
+    fn as_str(self) -> &'static str {
+        match self {
+            Safety::Safe => "safe",
+            Safety::Suspicious => "suspicious",
+            Safety::Unchecked => "unchecked",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Link {
+    pub url: String,
+    /// Anchor text, for links in the HTML body.
+    pub text: Option<String>,
+    pub safety: Safety,
+    /// Why it is suspicious: `blocklisted`, `text shows another site`, or
+    /// the Safe Browsing threat type, lowercased.
+    pub reason: Option<String>,
+}
+
+pub struct LinkChecker {
+    client: reqwest::Client,
+    config: LinkCheckConfig,
+    /// Threat type by URL, `None` for clean ones, until it expires.
+    cache: Mutex<HashMap<String, (Instant, Option<String>)>>,
+}
+
+impl LinkChecker {
+    pub fn new(mut config: LinkCheckConfig) -> Self {
+        for domain in &mut config.blocklist {
+            *domain = domain
+                .trim_start_matches("*.")
+                .trim_matches('.')
+                .to_ascii_lowercase();
+        }
+        config.blocklist.retain(|d| !d.is_empty());
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(config.timeout)
+                .build()
+                .unwrap_or_default(),
+            config,
+            cache: Mutex::default(),
+        }
+    }
+
+    fn is_blocklisted(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.config.blocklist.iter().any(|domain| {
+            host == *domain
+                || host
+                    .strip_suffix(domain.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'))
+        })
+    }
+
+    /// Threat types of the listed URLs, or `None` when Safe Browsing is
+    /// off or failed.
+    async fn threats(&self, urls: &[&str]) -> Option<HashMap<String, String>> {
+        let key = self.config.safe_browsing_key.as_deref()?;
+        let now = Instant::now();
+        let mut threats = HashMap::new();
+        let mut unknown = Vec::new();
+        {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            for &url in urls {
+                match cache.get(url) {
+                    Some((expires, threat)) if *expires > now => {
+                        if let Some(threat) = threat {
+                            threats.insert(url.to_owned(), threat.clone());
+                        }
+                    }
+                    _ => unknown.push(url),
+                }
+            }
+        }
+        if unknown.is_empty() {
+            return Some(threats);
+        }
+        let found = match self.lookup(key, &unknown).await {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!(error = %e, "safe browsing lookup failed");
+                return None;
+            }
+        };
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (expires, _)| *expires > now);
+        if cache.len() + unknown.len() > MAX_CACHED {
+            cache.clear();
+        }
+        for url in unknown {
+            let threat = found.get(url).cloned();
+            cache.insert(url.to_owned(), (now + self.config.cache_ttl, threat));
+        }
+        threats.extend(found);
+        Some(threats)
+    }
+
+    async fn lookup(
+        &self,
+        key: &str,
+        urls: &[&str],
+    ) -> Result<HashMap<String, String>, reqwest::Error> {
+        let entries: Vec<_> = urls
+            .iter()
+            .map(|url| serde_json::json!({ "url": url }))
+            .collect();
+        let body = serde_json::json!({
+            "client": { "clientId": "fake-email", "clientVersion": env!("CARGO_PKG_VERSION") },
+            "threatInfo": {
+                "threatTypes": THREAT_TYPES,
+                "platformTypes": ["ANY_PLATFORM"],
+                "threatEntryTypes": ["URL"],
+                "threatEntries": entries,
+            },
+        });
+        let found: Matches = self
+            .client
+            .post(&self.config.safe_browsing_url)
+            .query(&[("key", key)])
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(found
+            .matches
+            .into_iter()
+            .map(|m| (m.threat.url, m.threat_type.to_ascii_lowercase()))
+            .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct Matches {
+    #[serde(default)]
+    matches: Vec<Match>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Match {
+    threat_type: String,
+    threat: Threat,
+}
+
+#[derive(Deserialize)]
+struct Threat {
+    url: String,
+}
+
+/// Links from the HTML body's anchors, then any others in the text body,
+/// each URL once.
+pub fn extract(html: Option<&str>, text: Option<&str>) -> Vec<(Url, Option<String>)> {
+    let mut links = Vec::new();
+    if let Some(html) = html {
+        let anchors = Selector::parse("a[href]").expect("valid selector");
+        for a in Html::parse_document(html).select(&anchors) {
+            let Some(url) = a.attr("href").and_then(http_url) else {
+                continue;
+            };
+            let text = a.text().flat_map(str::split_whitespace);
+            let text = text.collect::<Vec<_>>().join(" ");
+            links.push((url, Some(text).filter(|t| !t.is_empty())));
+        }
+    }
+    if let Some(text) = text {
+        for m in TEXT_URL.find_iter(text) {
+            let raw = m
+                .as_str()
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '\'']);
+            if let Some(url) = http_url(raw) {
+                links.push((url, None));
+            }
+        }
+    }
+    let mut seen = HashSet::new();
+    links.retain(|(url, _)| seen.insert(url.to_string()));
+    links.truncate(MAX_LINKS);
+    links
+}
+
+fn http_url(raw: &str) -> Option<Url> {
+    Url::parse(raw.trim())
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+/// Whether the anchor text is itself an address on another site, like
+/// `https://bank.example` over a link to somewhere else.
+fn text_shows_another_site(url: &Url, text: &str) -> bool {
+    if text.contains(char::is_whitespace) || !text.contains('.') {
+        return false;
+    }
+    let shown = if text.contains("://") {
+        Url::parse(text)
+    } else {
+        Url::parse(&format!("http://{text}"))
+    };
+    let site = |host: &str| host.trim_start_matches("www.").to_ascii_lowercase();
+    match (shown.ok().as_ref().and_then(Url::host_str), url.host_str()) {
+        (Some(shown), Some(actual)) => shown.contains('.') && site(shown) != site(actual),
+        _ => false,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/links",
+    tag = "api",
+    summary = "Links with a safety annotation",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_links(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Json<Vec<Link>>, Response> {
+    let (_, email) = owned_email(&state, &address, email_id).await?;
+    let found = extract(email.body_html.as_deref(), email.body_text.as_deref());
+    let checker = state.link_checker.as_deref();
+    let threats = match checker {
+        Some(checker) => {
+            let urls: Vec<&str> = found.iter().map(|(url, _)| url.as_str()).collect();
+            checker.threats(&urls).await
+        }
+        None => None,
+    };
+    let safe_browsing = checker.is_some_and(|c| c.config.safe_browsing_key.is_some());
+
+    let links = found
+        .into_iter()
+        .map(|(url, text)| {
+            let reason = if text
+                .as_deref()
+                .is_some_and(|t| text_shows_another_site(&url, t))
+            {
+                Some("text shows another site".to_owned())
+            } else if checker.is_some_and(|c| c.is_blocklisted(&url)) {
+                Some("blocklisted".to_owned())
+            } else {
+                threats.as_ref().and_then(|t| t.get(url.as_str()).cloned())
+            };
+            let safety = match (&reason, checker) {
+                (Some(_), _) => Safety::Suspicious,
+                (None, None) => Safety::Unchecked,
+                (None, Some(_)) if safe_browsing && threats.is_none() => Safety::Unchecked,
+                (None, Some(_)) => Safety::Safe,
+            };
+            metrics::counter!("link_checks_total", "result" => safety.as_str()).increment(1);
+            Link {
+                url: url.into(),
+                text,
+                safety,
+                reason,
+            }
+        })
+        .collect();
+    Ok(Json(links))
+}
@@ -0,0 +1,37 @@
+//! Keeps address-scoped endpoints from telling an attacker which mailboxes
+//! exist: every miss gets the same response, and every response takes at least
+//! a fixed, jittered time.
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use rand::Rng;
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::api::err;
+use crate::AppState;
+
+/// The one answer for malformed, unknown, honeypot or non-owned lookups.
+pub(crate) fn not_found() -> Response {
+    err(StatusCode::NOT_FOUND, "mailbox not found")
+}
+
+/// Holds every response until `lookup_floor` plus up to half of it again has
+/// passed, so a database miss is not measurably faster than a hit.
+pub(crate) async fn uniform_latency(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let floor = state.lookup_floor;
+    let jitter =
+        Duration::from_micros(rand::thread_rng().gen_range(0..=floor.as_micros() as u64 / 2));
+    let deadline = Instant::now() + floor + jitter;
+    let res = next.run(req).await;
+    tokio::time::sleep_until(deadline).await;
+    res
+}
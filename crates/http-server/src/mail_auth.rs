@@ -0,0 +1,53 @@
+//! `/api/email/:address/:email_id/auth`: what the sender of a message
+//! proved about itself, for people testing their own mail setup.
+
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use db::{list_dkim_signatures, DkimSignature, DmarcResult, SpfResult};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::api::db_error;
+use crate::share::owned_email;
+use crate::AppState;
+
+#[derive(Debug, Serialize)]
+pub struct Authentication {
+    /// Address of the SMTP client that delivered the message.
+    pub peer_ip: Option<String>,
+    pub spf: Option<SpfResult>,
+    /// Whether SPF or DKIM passed for the `From:` domain.
+    pub dmarc: Option<DmarcResult>,
+    /// One verdict per `DKIM-Signature` header, in header order.
+    pub dkim: Vec<DkimSignature>,
+}
+
+/// Mail stored before a check was enabled, or with it turned off, has
+/// `null` or an empty list in its place.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/auth",
+    tag = "api",
+    summary = "Sender IP and SPF, DKIM and DMARC results",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_authentication(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Json<Authentication>, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let dkim = list_dkim_signatures(&pool, email.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(Authentication {
+        peer_ip: email.peer_ip,
+        spf: email.spf_result,
+        dmarc: email.dmarc_result,
+        dkim,
+    }))
+}
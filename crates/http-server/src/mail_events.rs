@@ -0,0 +1,153 @@
+//! Push notifications for new mail. Every stored message is announced with
+//! `NOTIFY` (see [`db::NEW_MAIL_CHANNEL`]); one `LISTEN`ing connection per
+//! process fans the announcements out to SSE subscribers, so they arrive no
+//! matter which process did the insert.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Response,
+    },
+};
+use db::{
+    find_received_email, find_temporary_email_by_addr, parse_new_mail_payload, NEW_MAIL_CHANNEL,
+};
+use sqlx::postgres::{PgListener, PgPool};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tokio_stream::{Stream, StreamExt};
+use uuid::Uuid;
+
+use crate::api::{db_error, not_live, require_pool};
+use crate::latency::record_first_read;
+use crate::lookup;
+use crate::AppState;
+
+const CHANNEL_CAPACITY: usize = 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct NewMail {
+    pub temporary_email_id: Uuid,
+    pub email_id: Uuid,
+}
+
+/// In-process fan-out of [`NewMail`]; fed by [`listen`].
+#[derive(Clone)]
+pub struct MailEvents {
+    tx: broadcast::Sender<NewMail>,
+}
+
+impl MailEvents {
+    pub fn subscribe(&self) -> broadcast::Receiver<NewMail> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for MailEvents {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+/// Relays new-mail notifications into `events` for the life of the process,
+/// reconnecting after errors. Messages stored while disconnected are not
+/// replayed; clients catch up by polling.
+pub async fn listen(pool: PgPool, events: MailEvents) {
+    loop {
+        if let Err(e) = relay(&pool, &events).await {
+            tracing::warn!(error = %e, "new-mail listener failed, reconnecting");
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn relay(pool: &PgPool, events: &MailEvents) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(NEW_MAIL_CHANNEL).await?;
+    tracing::info!("listening for new mail");
+    loop {
+        let notification = listener.recv().await?;
+        match parse_new_mail_payload(notification.payload()) {
+            Some((temporary_email_id, email_id)) => {
+                let _ = events.tx.send(NewMail {
+                    temporary_email_id,
+                    email_id,
+                });
+            }
+            None => tracing::warn!(payload = notification.payload(), "bad new-mail payload"),
+        }
+    }
+}
+
+/// `GET /api/email/:address/events`: an `email` event carrying the message,
+/// in the shape `/api/inbox/poll` returns it, for every delivery to the
+/// address. Subscribers that fall behind get `lagged` with the number of
+/// notifications they missed and should poll to catch up.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/events",
+    tag = "api",
+    summary = "Server-Sent Events for new mail",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn stream_new_mail(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    let pool = require_pool(&state).await?;
+    let temp = find_temporary_email_by_addr(&pool, &address.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    if !temp.is_live() {
+        return Err(not_live(&temp));
+    }
+
+    let mailbox = temp.id;
+    let stream = BroadcastStream::new(state.mail_events.subscribe())
+        .filter(move |item| {
+            item.as_ref()
+                .map_or(true, |mail| mail.temporary_email_id == mailbox)
+        })
+        .then(move |item| {
+            let pool = pool.clone();
+            async move {
+                match item {
+                    Ok(mail) => match find_received_email(&pool, mailbox, mail.email_id).await {
+                        Ok(Some(email)) => {
+                            record_first_read(&pool, &[email.id]).await;
+                            Some(
+                                Event::default()
+                                    .event("email")
+                                    .json_data(&email)
+                                    .unwrap_or_else(|_| {
+                                        Event::default().comment("unserializable email")
+                                    }),
+                            )
+                        }
+                        // Gone already, e.g. purged.
+                        Ok(None) => None,
+                        Err(e) => {
+                            tracing::warn!(error = %e, "failed to load new mail");
+                            None
+                        }
+                    },
+                    Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                        Some(Event::default().event("lagged").data(missed.to_string()))
+                    }
+                }
+            }
+        })
+        .filter_map(|event| event.map(Ok));
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
@@ -1,36 +1,71 @@
-use db::{connect_pool, purge_all_data, run_migrations};
-use http_server::{router, AppState};
+use db::{compress_stored_bodies, connect_pool, run_migrations};
+use http_server::config::Config;
+use http_server::forwarding::{self, Outbound};
+use http_server::image_proxy::ImageProxy;
+use http_server::links::LinkChecker;
+use http_server::oidc::Oidc;
+use http_server::preview::Renderer;
+use http_server::mail_events::{self, MailEvents};
+use http_server::supervisor::Supervisor;
+use http_server::{check, janitor, router, status, webhooks, AppState};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use rand::Rng;
 use sqlx::postgres::PgPool;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 
-fn env_or(key: &str, default: &str) -> String {
-    std::env::var(key).unwrap_or_else(|_| default.into())
-}
-
-fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
-    std::env::var(key)
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(default)
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
     dotenvy::dotenv().ok();
 
-    let mail_domain: Arc<str> = std::env::var("MAIL_DOMAIN")
-        .or_else(|_| std::env::var("DOMAIN"))
-        .expect("MAIL_DOMAIN or DOMAIN must be set")
-        .into();
+    if std::env::args().skip(1).any(|a| a == "--check") {
+        std::process::exit(check::run(Config::from_env()).await);
+    }
+
+    let config = match Config::from_env() {
+        Ok(c) => c,
+        Err(errors) => {
+            for e in &errors {
+                tracing::error!(key = e.key, "invalid config: {}", e.message);
+            }
+            std::process::exit(2);
+        }
+    };
+
+    if std::env::args().nth(1).as_deref() == Some("status") {
+        std::process::exit(status::run(&config).await);
+    }
+
+    if std::env::args().skip(1).any(|a| a == "--compress-bodies") {
+        std::process::exit(compress_bodies(&config).await);
+    }
+
+    tracing::info!(domain = %config.mail_domain, "starting fake-email backend");
 
-    tracing::info!(domain = %mail_domain, "starting fake-email backend");
+    let metrics = PrometheusBuilder::new()
+        .install_recorder()
+        .map_err(|e| tracing::warn!(error = %e, "metrics recorder not installed"))
+        .ok();
 
     let pool_slot: Arc<RwLock<Option<PgPool>>> = Arc::new(RwLock::new(None));
+    let new_mail = MailEvents::default();
+    let supervisor = Supervisor::default();
+    let outbound = config.relays.clone().map(|relays| {
+        let policy = config.outbound_policy.clone();
+        let outbound = Outbound::new(relays, policy, &*config.mail_domain)
+            .expect("relay routes are checked with the config");
+        Arc::new(outbound)
+    });
 
     tokio::spawn({
         let pool_slot = Arc::clone(&pool_slot);
+        let config = config.clone();
+        let new_mail = new_mail.clone();
+        let supervisor = supervisor.clone();
+        let outbound = outbound.clone();
         async move {
             let pool = loop {
                 match connect_pool().await {
@@ -40,7 +75,7 @@ async fn main() {
                     }
                     Err(e) => {
                         tracing::error!(error = %e, "database connection failed, retrying in 5s");
-                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                        tokio::time::sleep(Duration::from_secs(5)).await;
                     }
                 }
             };
@@ -53,25 +88,147 @@ async fn main() {
 
             *pool_slot.write().await = Some(pool.clone());
 
-            let purge_hour: u32 = env_parse("PURGE_HOUR_UTC", 3);
-            tokio::spawn(daily_purge_loop(pool.clone(), purge_hour));
-
-            let smtp_host = env_or("SMTP_HOST", "0.0.0.0");
-            let smtp_port: u16 = env_parse("SMTP_PORT", 25);
-            if let Err(e) = smtp::run_server(&smtp_host, smtp_port, pool).await {
-                tracing::error!(error = %e, "smtp server failed");
+            let janitor_config = config.janitor;
+            let p = pool.clone();
+            supervisor.spawn("janitor", move || {
+                let pool = p.clone();
+                async move {
+                    janitor::run(pool, janitor_config).await;
+                    Ok(())
+                }
+            });
+            let p = pool.clone();
+            supervisor.spawn("expiry_sweep", move || {
+                let pool = p.clone();
+                async move {
+                    janitor::run_expiry_sweep(pool, janitor_config).await;
+                    Ok(())
+                }
+            });
+            let p = pool.clone();
+            supervisor.spawn("usage_rollup", move || {
+                let pool = p.clone();
+                async move {
+                    janitor::run_usage_rollup(pool, janitor_config).await;
+                    Ok(())
+                }
+            });
+            let (p, webhook_config) = (pool.clone(), config.webhooks);
+            supervisor.spawn("webhook_delivery", move || {
+                let pool = p.clone();
+                async move {
+                    webhooks::run_delivery_worker(pool, webhook_config).await;
+                    Ok(())
+                }
+            });
+            if let Some(outbound) = outbound {
+                let (p, o, forward_config) = (pool.clone(), outbound.clone(), config.forwarding);
+                supervisor.spawn("forward_delivery", move || {
+                    let (pool, outbound) = (p.clone(), Arc::clone(&o));
+                    async move {
+                        forwarding::run_forward_worker(pool, outbound, forward_config).await;
+                        Ok(())
+                    }
+                });
+                supervisor.spawn("relay_health", move || {
+                    let outbound = Arc::clone(&outbound);
+                    async move {
+                        outbound
+                            .relays
+                            .run_health_checks(Duration::from_secs(60))
+                            .await;
+                        Ok(())
+                    }
+                });
+            }
+            let p = pool.clone();
+            supervisor.spawn("mail_events", move || {
+                let (pool, new_mail) = (p.clone(), new_mail.clone());
+                async move {
+                    mail_events::listen(pool, new_mail).await;
+                    Ok(())
+                }
+            });
+            if let Some(port) = config.imap_port {
+                let (p, imap) = (pool.clone(), config.imap.clone());
+                let host = config.imap_host.clone();
+                supervisor.spawn("imap", move || {
+                    let (host, pool, imap) = (host.clone(), p.clone(), imap.clone());
+                    async move {
+                        imap::run_server(&host, port, pool, imap)
+                            .await
+                            .map_err(|e| e.to_string())
+                    }
+                });
             }
+            supervisor.spawn("smtp", move || {
+                let (host, port) = (config.smtp_host.clone(), config.smtp_port);
+                let (pool, smtp) = (pool.clone(), config.smtp.clone());
+                async move {
+                    smtp::run_server(&host, port, pool, smtp)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+            });
         }
     });
 
-    let state = AppState {
-        pool: pool_slot,
-        mail_domain,
-    };
+    let mut state = AppState::new(pool_slot, Arc::clone(&config.mail_domain));
+    state.admin_token = config.admin_token.clone();
+    state.public_ip = config.public_ip;
+    state.ingest_events = config.smtp.events.clone();
+    state.blocklist = Arc::clone(&config.smtp.blocklist);
+    state.mail_events = new_mail;
+    state.metrics = metrics;
+    state.reactivation_grace = config.reactivation_grace;
+    state.max_address_lifetime = config.max_address_lifetime;
+    state.public_retention = config.janitor.public_retention;
+    state.lookup_floor = config.lookup_floor;
+    if let Some(pepper) = &config.token_pepper {
+        state.token_pepper = Arc::clone(pepper);
+    }
+    state.sessions = Arc::new(config.sessions.clone());
+    state.oidc = config.oidc.clone().map(|c| Arc::new(Oidc::new(c)));
+    state.supervisor = supervisor.clone();
+    state.renderer = config
+        .renderer_url
+        .as_deref()
+        .map(|url| Arc::new(Renderer::new(url, config.renderer_timeout)));
+    state.allow_private_webhooks = config.allow_private_webhooks;
+    state.download_bandwidth = config.download_bandwidth;
+    state.poll_requires_token = config.poll_requires_token;
+    state.open_tokenless_mailboxes = config.open_tokenless_mailboxes;
+    state.html_display = config.html_display.clone();
+    if config.image_proxy.is_some() && state.html_display.image_proxy_key.is_none() {
+        tracing::warn!("IMAGE_PROXY_SECRET not set — proxied image links stop working on restart");
+        state.html_display.image_proxy_key = Some(rand::thread_rng().gen::<[u8; 32]>().into());
+    }
+    state.image_proxy = config.image_proxy.map(|c| Arc::new(ImageProxy::new(c)));
+    state.link_checker = config.link_check.map(|c| Arc::new(LinkChecker::new(c)));
+    state.otp_patterns = Arc::new(config.otp_patterns.clone());
+    state.admin_allowlist = Arc::new(config.admin_allowlist.clone());
+    state.mailbox_quota = config.smtp.mailbox_quota;
+    state.outbound = outbound;
+    state.read_hooks = config.read_hooks.clone();
+    if state.admin_allowlist.is_enabled() {
+        tracing::info!(
+            networks = state.admin_allowlist.allowed.len(),
+            "admin endpoints restricted by IP allowlist"
+        );
+    }
+    if !config.session_keys_configured {
+        tracing::warn!("SESSION_JWT_KEYS not set — sessions stop working on restart");
+    }
+    state.public_base_url = config.public_base_url.clone();
+    match &config.share_secret {
+        Some(secret) => state.share_secret = Arc::clone(secret),
+        None => tracing::warn!("SHARE_LINK_SECRET not set — share links stop working on restart"),
+    }
+    if state.admin_token.is_none() {
+        tracing::info!("ADMIN_TOKEN not set — /admin endpoints disabled");
+    }
 
-    let http_host = env_or("HTTP_HOST", "127.0.0.0");
-    let http_port: u16 = env_parse("HTTP_PORT", 3001);
-    let bind_addr = format!("{http_host}:{http_port}");
+    let bind_addr = config.http_bind_addr();
 
     let listener = tokio::net::TcpListener::bind(&bind_addr)
         .await
@@ -79,45 +236,70 @@ async fn main() {
 
     tracing::info!(%bind_addr, "http listening");
 
-    axum::serve(listener, router(state))
+    let app = router(state).into_make_service_with_connect_info::<SocketAddr>();
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap_or_else(|e| tracing::error!(error = %e, "http server exited with error"));
+
+    tracing::info!("stopping background workers");
+    supervisor.shutdown(Duration::from_secs(10)).await;
 }
 
-async fn daily_purge_loop(pool: PgPool, hour_utc: u32) {
-    use chrono::Utc;
-
-    loop {
-        let now = Utc::now();
-        let today_purge = now
-            .date_naive()
-            .and_hms_opt(hour_utc, 0, 0)
-            .expect("valid purge hour");
-        let next = if now.naive_utc() >= today_purge {
-            today_purge + chrono::Duration::days(1)
-        } else {
-            today_purge
-        };
-
-        let wait = (next - now.naive_utc())
-            .to_std()
-            .unwrap_or(std::time::Duration::from_secs(3600));
+/// Ctrl-C, or SIGTERM from the service manager.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-        tracing::info!(
-            next_purge = %next,
-            wait_secs = wait.as_secs(),
-            "daily purge scheduled"
-        );
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("shutdown signal received");
+}
 
-        tokio::time::sleep(wait).await;
+/// One-off backfill: rewrite bodies stored before compression was enabled.
+async fn compress_bodies(config: &Config) -> i32 {
+    let pool = match connect_pool().await {
+        Ok(p) => p,
+        Err(e) => {
+            tracing::error!(error = %e, "database connection failed");
+            return 1;
+        }
+    };
+    if let Err(e) = run_migrations(&pool).await {
+        tracing::error!(error = %e, "migrations failed");
+        return 1;
+    }
 
-        match purge_all_data(&pool).await {
-            Ok(r) => tracing::info!(
-                emails = r.emails_deleted,
-                inboxes = r.inboxes_deleted,
-                "daily purge complete"
-            ),
-            Err(e) => tracing::error!(error = %e, "daily purge failed"),
+    let mode = config.smtp.body_compression;
+    match compress_stored_bodies(&pool, mode, 500).await {
+        Ok(r) => {
+            tracing::info!(
+                %mode,
+                scanned = r.rows_scanned,
+                compressed = r.rows_compressed,
+                bytes_before = r.bytes_before,
+                bytes_after = r.bytes_after,
+                "body compression backfill complete"
+            );
+            0
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "body compression backfill failed");
+            1
         }
     }
 }
@@ -0,0 +1,91 @@
+//! `POST /api/email/:address/merge`: moves every message of another mailbox
+//! into this one and deactivates the other. The caller must own both: the
+//! policy layer only checks this mailbox's token when it has one, so both
+//! are checked here, with the source's token from the body or else the
+//! credential presented for this mailbox (an account session owning both).
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use db::{find_temporary_email_by_addr, merge_temporary_emails};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::api::{db_error, err};
+use crate::watch::live_mailbox;
+use crate::{lookup, policy, token, AppState};
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[schema(as = MergeMailbox)]
+pub struct MergeBody {
+    /// Address whose messages move here; it may have expired.
+    pub source: String,
+    pub source_token: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = MergeResult)]
+pub struct MergeResponse {
+    pub temp_email_addr: String,
+    pub merged_from: String,
+    pub moved: u64,
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/merge",
+    tag = "api",
+    summary = "Move another owned mailbox's messages here",
+    params(("address" = String, Path)),
+    request_body = MergeBody,
+    responses((status = 200, description = "OK", body = MergeResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn merge_mailbox(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Json(body): Json<MergeBody>,
+) -> Result<Json<MergeResponse>, Response> {
+    let (pool, target) = live_mailbox(&state, &address).await?;
+    let presented = token::presented(&headers, &query);
+    if !policy::authenticate(&state, &pool, &target, presented).await? {
+        return Err(lookup::not_found());
+    }
+
+    let source = find_temporary_email_by_addr(&pool, &body.source.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    if source.id == target.id {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "cannot merge a mailbox into itself",
+        ));
+    }
+    let source_presented = body.source_token.as_deref().or(presented);
+    if !policy::authenticate(&state, &pool, &source, source_presented).await? {
+        return Err(lookup::not_found());
+    }
+
+    let moved = merge_temporary_emails(&pool, source.id, target.id)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(
+        addr = %target.temp_email_addr,
+        from = %source.temp_email_addr,
+        moved,
+        "mailboxes merged"
+    );
+    Ok(Json(MergeResponse {
+        temp_email_addr: target.temp_email_addr,
+        merged_from: source.temp_email_addr,
+        moved,
+    }))
+}
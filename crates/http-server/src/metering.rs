@@ -0,0 +1,51 @@
+//! Usage metering for operators who bill API customers. Requests carrying an
+//! `X-Api-Key` header are attributed to that key; requests without one are
+//! anonymous and not metered.
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderName, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use db::{find_api_key_by_hash, record_usage, UsageKind};
+use uuid::Uuid;
+
+use crate::api::{db_error, err, require_pool};
+use crate::{token, AppState};
+
+pub const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+/// Request extension naming the API key a request is billed to.
+#[derive(Debug, Clone, Copy)]
+pub struct MeteredKey(pub Uuid);
+
+/// Resolves `X-Api-Key`, rejects unknown or revoked keys, and records one
+/// `api_call` per request.
+pub(crate) async fn track(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let Some(raw) = req.headers().get(&API_KEY_HEADER) else {
+        return next.run(req).await;
+    };
+    let invalid = || err(StatusCode::UNAUTHORIZED, "invalid API key");
+    let Ok(presented) = raw.to_str() else {
+        return invalid();
+    };
+    let pool = match require_pool(&state).await {
+        Ok(pool) => pool,
+        Err(res) => return res,
+    };
+    let key = match find_api_key_by_hash(&pool, &token::hash(&state.token_pepper, presented.trim()))
+        .await
+    {
+        Ok(Some(key)) => key,
+        Ok(None) => return invalid(),
+        Err(e) => return db_error(e),
+    };
+
+    req.extensions_mut().insert(MeteredKey(key.id));
+    let res = next.run(req).await;
+    if let Err(e) = record_usage(&pool, key.id, UsageKind::ApiCall, 1).await {
+        tracing::warn!(error = %e, api_key = %key.id, "failed to record usage");
+    }
+    res
+}
@@ -0,0 +1,430 @@
+//! Optional login for persistent accounts, with any OpenID Connect issuer
+//! that publishes `/.well-known/openid-configuration` (Google, Keycloak,
+//! Auth0, ...) or with GitHub, which only speaks plain OAuth2.
+//!
+//! `GET /api/auth/login` redirects to the provider with a signed `state` and a
+//! nonce cookie; `GET /api/auth/callback` exchanges the code and starts a
+//! logged-in session. An OpenID Connect login is vouched for by the ID token,
+//! verified against the provider's JWKS; a GitHub one by the user `/user`
+//! returns for the access token.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Json,
+};
+use chrono::Utc;
+use db::upsert_user;
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use crate::api::{db_error, err, require_pool};
+use crate::session::{self, SessionTokens, TokenKind};
+use crate::{token, AppState};
+
+const LOGIN_TTL: Duration = Duration::from_secs(10 * 60);
+const NONCE_COOKIE: &str = "oidc_nonce";
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where accounts come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provider {
+    Oidc {
+        issuer: String,
+    },
+    /// An OAuth app on GitHub: `web_url` serves the authorization and token
+    /// endpoints, `api_url` the REST API.
+    GitHub {
+        web_url: String,
+        api_url: String,
+    },
+}
+
+impl Provider {
+    pub fn github() -> Self {
+        Self::GitHub {
+            web_url: "https://github.com".into(),
+            api_url: "https://api.github.com".into(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct OidcConfig {
+    pub provider: Provider,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Must match a redirect URI registered with the provider; it should lead
+    /// the browser (or frontend) to `/api/auth/callback` with `code` and `state`.
+    pub redirect_url: String,
+}
+
+impl fmt::Debug for OidcConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcConfig")
+            .field("provider", &self.provider)
+            .field("client_id", &self.client_id)
+            .field("redirect_url", &self.redirect_url)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+pub struct Oidc {
+    config: OidcConfig,
+    http: reqwest::Client,
+    provider: OnceCell<ProviderMetadata>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                // GitHub's API refuses requests without one.
+                .user_agent("fake-email")
+                .build()
+                .expect("default reqwest client builds"),
+            provider: OnceCell::new(),
+        }
+    }
+
+    /// Discovery document of `issuer`, fetched on first use and kept for the
+    /// process.
+    async fn provider(&self, issuer: &str) -> Result<&ProviderMetadata, reqwest::Error> {
+        self.provider
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    issuer.trim_end_matches('/')
+                );
+                self.http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+            })
+            .await
+    }
+}
+
+/// Carried through the provider in `state`; signed with the session keys.
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    nonce: String,
+    exp: i64,
+}
+
+impl TokenKind for LoginState {
+    const AUDIENCE: &'static str = "fake-email/oidc-login";
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    pub code: String,
+    pub state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    nonce: Option<String>,
+}
+
+/// GitHub answers a refused code with 200 and an `error` instead.
+#[derive(Debug, Deserialize)]
+struct GitHubToken {
+    access_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Who logged in, as stored in `users`.
+struct Account {
+    issuer: String,
+    subject: String,
+    /// Only a verified address.
+    email: Option<String>,
+}
+
+fn login_disabled() -> Response {
+    err(StatusCode::NOT_FOUND, "login is not configured")
+}
+
+fn login_rejected() -> Response {
+    err(StatusCode::UNAUTHORIZED, "login failed or expired")
+}
+
+fn provider_error(e: impl fmt::Display) -> Response {
+    tracing::warn!(error = %e, "oidc provider");
+    err(StatusCode::BAD_GATEWAY, "identity provider unavailable")
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/login",
+    tag = "api",
+    summary = "Redirect to the login provider",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn login(State(state): State<AppState>) -> Result<Response, Response> {
+    let oidc = state.oidc.as_deref().ok_or_else(login_disabled)?;
+    let (authorization_endpoint, scope) = match &oidc.config.provider {
+        Provider::Oidc { issuer } => {
+            let provider = oidc.provider(issuer).await.map_err(provider_error)?;
+            (provider.authorization_endpoint.clone(), "openid email")
+        }
+        Provider::GitHub { web_url, .. } => (
+            format!("{web_url}/login/oauth/authorize"),
+            "read:user user:email",
+        ),
+    };
+
+    let nonce = token::generate();
+    let login_state = state.sessions.keys.sign(&LoginState {
+        nonce: nonce.clone(),
+        exp: Utc::now().timestamp() + LOGIN_TTL.as_secs() as i64,
+    });
+    let mut url = reqwest::Url::parse(&authorization_endpoint).map_err(provider_error)?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &oidc.config.client_id)
+        .append_pair("redirect_uri", &oidc.config.redirect_url)
+        .append_pair("scope", scope)
+        .append_pair("state", &login_state);
+    if matches!(oidc.config.provider, Provider::Oidc { .. }) {
+        url.query_pairs_mut().append_pair("nonce", &nonce);
+    }
+
+    let cookie = format!(
+        "{NONCE_COOKIE}={nonce}; Path=/api/auth; Max-Age={}; HttpOnly; Secure; SameSite=Lax",
+        LOGIN_TTL.as_secs()
+    );
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(url.as_str())).into_response())
+}
+
+fn nonce_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(NONCE_COOKIE)?.strip_prefix('='))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/auth/callback",
+    tag = "api",
+    summary = "Finish a login",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<CallbackQuery>,
+) -> Result<Json<SessionTokens>, Response> {
+    let oidc = state.oidc.as_deref().ok_or_else(login_disabled)?;
+
+    // The nonce cookie ties the callback to the browser that started the
+    // login, so a stolen `state` cannot log someone else in.
+    let login_state: LoginState = state
+        .sessions
+        .keys
+        .verify(&q.state)
+        .ok_or_else(login_rejected)?;
+    if nonce_cookie(&headers) != Some(login_state.nonce.as_str()) {
+        return Err(login_rejected());
+    }
+
+    let account = match &oidc.config.provider {
+        Provider::Oidc { issuer } => {
+            oidc_account(oidc, issuer, &q.code, &login_state.nonce).await?
+        }
+        Provider::GitHub { web_url, api_url } => {
+            github_account(oidc, web_url, api_url, &q.code).await?
+        }
+    };
+
+    let pool = require_pool(&state).await?;
+    let user = upsert_user(
+        &pool,
+        &account.issuer,
+        &account.subject,
+        account.email.as_deref(),
+    )
+    .await
+    .map_err(db_error)?;
+    tracing::info!(user_id = %user.id, "oidc login");
+
+    Ok(Json(session::start(&state, Some(user.id)).await?))
+}
+
+/// Trades the authorization code at `token_endpoint`.
+async fn exchange_code<T: DeserializeOwned>(
+    oidc: &Oidc,
+    token_endpoint: &str,
+    code: &str,
+) -> Result<T, Response> {
+    oidc.http
+        .post(token_endpoint)
+        .header(header::ACCEPT, "application/json")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", oidc.config.redirect_url.as_str()),
+            ("client_id", oidc.config.client_id.as_str()),
+            ("client_secret", oidc.config.client_secret.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|e| {
+            tracing::info!(error = %e, "oidc code exchange rejected");
+            login_rejected()
+        })?
+        .json()
+        .await
+        .map_err(provider_error)
+}
+
+async fn oidc_account(
+    oidc: &Oidc,
+    issuer: &str,
+    code: &str,
+    nonce: &str,
+) -> Result<Account, Response> {
+    let provider = oidc.provider(issuer).await.map_err(provider_error)?;
+    let tokens: TokenResponse = exchange_code(oidc, &provider.token_endpoint, code).await?;
+    let claims = verify_id_token(oidc, provider, &tokens.id_token)
+        .await?
+        .filter(|c| c.nonce.as_deref() == Some(nonce))
+        .ok_or_else(login_rejected)?;
+    Ok(Account {
+        issuer: provider.issuer.clone(),
+        subject: claims.sub,
+        email: claims.email.filter(|_| claims.email_verified),
+    })
+}
+
+/// The account is GitHub's numeric user id, which survives renames; the
+/// email is the primary one, if verified.
+async fn github_account(
+    oidc: &Oidc,
+    web_url: &str,
+    api_url: &str,
+    code: &str,
+) -> Result<Account, Response> {
+    let token: GitHubToken =
+        exchange_code(oidc, &format!("{web_url}/login/oauth/access_token"), code).await?;
+    let token = token.access_token.ok_or_else(login_rejected)?;
+    let user: GitHubUser = github_get(oidc, &format!("{api_url}/user"), &token).await?;
+    let emails: Vec<GitHubEmail> =
+        github_get(oidc, &format!("{api_url}/user/emails"), &token).await?;
+    Ok(Account {
+        issuer: web_url.to_owned(),
+        subject: user.id.to_string(),
+        email: emails
+            .into_iter()
+            .find(|e| e.primary && e.verified)
+            .map(|e| e.email),
+    })
+}
+
+async fn github_get<T: DeserializeOwned>(
+    oidc: &Oidc,
+    url: &str,
+    token: &str,
+) -> Result<T, Response> {
+    oidc.http
+        .get(url)
+        .bearer_auth(token)
+        .header(header::ACCEPT, "application/vnd.github+json")
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(provider_error)?
+        .json()
+        .await
+        .map_err(provider_error)
+}
+
+/// `Ok(None)` means the token is not valid; `Err` that the JWKS could not be
+/// fetched.
+async fn verify_id_token(
+    oidc: &Oidc,
+    provider: &ProviderMetadata,
+    id_token: &str,
+) -> Result<Option<IdTokenClaims>, Response> {
+    let Ok(header) = jsonwebtoken::decode_header(id_token) else {
+        return Ok(None);
+    };
+    // Symmetric algorithms would let anyone holding the client secret mint
+    // tokens; only accept the provider's public keys.
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Ok(None);
+    }
+
+    let jwks: JwkSet = oidc
+        .http
+        .get(&provider.jwks_uri)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(provider_error)?
+        .json()
+        .await
+        .map_err(provider_error)?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    };
+    let Some(key) = jwk.and_then(|jwk| DecodingKey::from_jwk(jwk).ok()) else {
+        return Ok(None);
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[&provider.issuer]);
+    validation.set_audience(&[&oidc.config.client_id]);
+    Ok(
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .ok()
+            .map(|data| data.claims),
+    )
+}
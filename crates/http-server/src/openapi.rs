@@ -0,0 +1,157 @@
+//! OpenAPI description of the HTTP API, served at `/api/openapi.json` with a
+//! Swagger UI at `/api/docs`, and written out by `cargo xtask gen-openapi`.
+//! Each handler describes itself with `#[utoipa::path]` and its bodies derive
+//! `ToSchema`; [`ApiDoc`] lists the handlers of [`crate::router`] and nests
+//! [`crate::admin::AdminApi`]. Keep the lists in step with the routers when
+//! adding endpoints (a test in `tests/http_api.rs` fails when they drift apart).
+
+use axum::{
+    http::header,
+    response::{Html, IntoResponse},
+};
+use serde_json::Value;
+use std::sync::OnceLock;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{
+    alias, api, attachments, block, diff, expect, export, forwarding, html, image_proxy, links,
+    mail_auth, mail_events, merge, oidc, preview, read, session, share, source, summaries,
+    timeline, trash, watch, webhooks,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "fake-email"),
+    paths(
+        crate::health_check,
+        openapi_json,
+        swagger_ui,
+        api::create_temporary_address,
+        api::generate_batch,
+        session::create_session,
+        session::current_session,
+        session::refresh_session,
+        oidc::login,
+        oidc::callback,
+        api::list_account_addresses,
+        image_proxy::proxy_image,
+        api::poll_inbox_by_address,
+        api::reactivate_address,
+        api::extend_address,
+        api::rotate_token,
+        merge::merge_mailbox,
+        api::search_mailbox,
+        summaries::list_summaries,
+        trash::delete_all_emails,
+        trash::list_trash,
+        trash::restore,
+        diff::diff_emails,
+        timeline::mailbox_timeline,
+        export::export_mailbox,
+        watch::list_watches,
+        watch::create_watch,
+        watch::delete_watch,
+        otp::latest_otp,
+        alias::list_aliases,
+        alias::create_alias,
+        alias::delete_alias,
+        block::list_blocks,
+        block::block_sender,
+        block::unblock_sender,
+        expect::list_expectations,
+        expect::expect_sender,
+        webhooks::list_webhooks,
+        webhooks::create_webhook,
+        webhooks::delete_webhook,
+        forwarding::list_forwards,
+        forwarding::create_forward,
+        forwarding::delete_forward,
+        mail_events::stream_new_mail,
+        share::create_share,
+        share::revoke_share,
+        attachments::download_raw,
+        source::email_source,
+        html::email_detail,
+        trash::delete_email,
+        html::email_html,
+        attachments::email_headers,
+        mail_auth::email_authentication,
+        read::set_read,
+        links::email_links,
+        preview::email_preview,
+        attachments::list_email_attachments,
+        attachments::download_attachment,
+        share::view_share,
+        forwarding::show_confirmation,
+        forwarding::confirm_forward,
+    ),
+    nest((path = "/admin", api = crate::admin::AdminApi)),
+    modifiers(&SecuritySchemes)
+)]
+pub struct ApiDoc;
+
+/// `mailbox`: a mailbox access token, or a session owning the address.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let bearer = || HttpBuilder::new().scheme(HttpAuthScheme::Bearer);
+        components.add_security_scheme("mailbox", SecurityScheme::Http(bearer().build()));
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::Http(bearer().bearer_format("JWT").build()),
+        );
+        components.add_security_scheme("admin", SecurityScheme::Http(bearer().build()));
+    }
+}
+
+/// `/api/openapi.json`
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    tag = "api",
+    summary = "This document",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn openapi_json() -> impl IntoResponse {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        DOCUMENT.get_or_init(|| document().to_string()).as_str(),
+    )
+}
+
+/// `/api/docs`: Swagger UI, loaded from a CDN, pointed at `/api/openapi.json`.
+#[utoipa::path(
+    get,
+    path = "/api/docs",
+    tag = "api",
+    summary = "Swagger UI for this document",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn swagger_ui() -> Html<&'static str> {
+    Html(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!doctype html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>fake-email API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+<script>
+SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });
+</script>
+</body>
+</html>
+"##;
+
+pub fn document() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).expect("the OpenAPI document serializes")
+}
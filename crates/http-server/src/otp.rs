@@ -0,0 +1,175 @@
+//! `/api/email/:address/latest-otp`: the one-time code in the newest
+//! recent message that has one, so signup tests need not scrape mail
+//! themselves.
+//!
+//! Patterns are tried in order, each against the subject and then the
+//! body, so a code named as one in the body beats a bare number in the
+//! subject. A pattern's first capture group is the code, or the whole
+//! match without one. Candidates without a digit are skipped, since a
+//! pattern like `code: (\w+)` also matches "code: below".
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use db::{list_received_emails, ReceivedEmail};
+use regex::{Regex, RegexBuilder};
+use scraper::{Html, Node};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::api::{db_error, err};
+use crate::policy::MailboxPolicy;
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+const DEFAULT_MINUTES: u32 = 15;
+const MAX_MINUTES: u32 = 24 * 60;
+
+/// Keyword then code, code then "is your", and a lone six-digit number.
+const DEFAULT_PATTERNS: &[&str] = &[
+    r"(?:code|otp|pin|passcode|password|token)\W{0,20}(?:is\W{1,5})?\b([0-9]{4,8})\b",
+    r"\b([0-9]{4,8})\W{1,10}is your\b",
+    r"(?:code|otp|passcode|token)\W{0,20}(?:is\W{1,5})?\b([a-z0-9]{4,10})\b",
+    r"\b([0-9]{6})\b",
+];
+
+/// Patterns codes are looked for with; `OTP_PATTERNS_FILE` replaces the
+/// built-in ones.
+#[derive(Debug, Clone)]
+pub struct OtpPatterns(Vec<Regex>);
+
+impl OtpPatterns {
+    /// Matching is case-insensitive.
+    pub fn compile<S: AsRef<str>>(patterns: &[S]) -> Result<Self, regex::Error> {
+        patterns
+            .iter()
+            .map(|p| {
+                RegexBuilder::new(p.as_ref())
+                    .case_insensitive(true)
+                    .size_limit(1 << 20)
+                    .build()
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// The code in `texts`, searched in order.
+    pub fn find(&self, texts: &[&str]) -> Option<String> {
+        self.0.iter().find_map(|re| {
+            texts.iter().find_map(|text| {
+                re.captures_iter(text)
+                    .filter_map(|c| c.get(1).or_else(|| c.get(0)))
+                    .map(|m| m.as_str())
+                    .find(|code| code.bytes().any(|b| b.is_ascii_digit()))
+                    .map(str::to_owned)
+            })
+        })
+    }
+}
+
+impl Default for OtpPatterns {
+    fn default() -> Self {
+        Self::compile(DEFAULT_PATTERNS).expect("valid built-in patterns")
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LatestOtpQuery {
+    /// How far back to look; 15 by default.
+    pub minutes: Option<u32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LatestOtp {
+    pub code: String,
+    pub email_id: Uuid,
+    pub received_at: DateTime<Utc>,
+    pub from_addr: Option<String>,
+    pub subject: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/latest-otp",
+    tag = "api",
+    summary = "Newest one-time code in recent mail",
+    params(("address" = String, Path), LatestOtpQuery),
+    responses((status = 200, description = "OK", body = LatestOtp)),
+    security(("mailbox" = []))
+)]
+pub async fn latest_otp(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<LatestOtpQuery>,
+) -> Result<Json<LatestOtp>, Response> {
+    let minutes = q.minutes.unwrap_or(DEFAULT_MINUTES);
+    if minutes == 0 || minutes > MAX_MINUTES {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("minutes must be between 1 and {MAX_MINUTES}"),
+        ));
+    }
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let now = Utc::now();
+    let window_start = now - Duration::minutes(i64::from(minutes));
+    let since = MailboxPolicy::of(&temp, state.public_retention)
+        .oldest_visible(now)
+        .map_or(window_start, |oldest| oldest.max(window_start));
+    let messages = list_received_emails(&pool, temp.id, Some(since), Some(false), None)
+        .await
+        .map_err(db_error)?;
+
+    messages
+        .into_iter()
+        .rev()
+        .find_map(|email| {
+            let code = find_code(&state.otp_patterns, &email)?;
+            Some(LatestOtp {
+                code,
+                email_id: email.id,
+                received_at: email.received_at,
+                from_addr: email.from_addr,
+                subject: email.subject,
+            })
+        })
+        .map(Json)
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "no code found"))
+}
+
+/// Searches the subject and the text body, or the HTML body's text when
+/// there is no text body.
+fn find_code(patterns: &OtpPatterns, email: &ReceivedEmail) -> Option<String> {
+    let body = match (&email.body_text, &email.body_html) {
+        (Some(text), _) => text.clone(),
+        (None, Some(html)) => html_text(html),
+        (None, None) => String::new(),
+    };
+    patterns.find(&[email.subject.as_deref().unwrap_or_default(), &body])
+}
+
+/// Visible text of `html`, leaving out style sheets and scripts, whose
+/// colours and numbers would pass for codes.
+fn html_text(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut words = Vec::new();
+    for node in document.root_element().descendants() {
+        let Node::Text(text) = node.value() else {
+            continue;
+        };
+        let hidden = node
+            .parent()
+            .and_then(|p| p.value().as_element())
+            .is_some_and(|e| matches!(e.name(), "style" | "script" | "head" | "title"));
+        if !hidden {
+            words.extend(text.split_whitespace());
+        }
+    }
+    words.join(" ")
+}
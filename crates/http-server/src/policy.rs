@@ -0,0 +1,127 @@
+//! Who may do what to a mailbox: access tokens, and the rules that differ
+//! between private and public mailboxes. Applied as a layer over the
+//! per-address routes so the handlers themselves stay unaware.
+
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use db::{fetch_mailbox_token_hash, find_temporary_email_by_addr, TemporaryEmail};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::api::{db_error, err, require_pool};
+use crate::session::SessionClaims;
+use crate::{lookup, token, AppState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxPolicy {
+    Private,
+    /// Anyone who knows the address can read it; nobody can delete from it,
+    /// and mail older than `retention` is hidden and swept.
+    Public {
+        retention: Duration,
+    },
+}
+
+impl MailboxPolicy {
+    pub fn of(temp: &TemporaryEmail, public_retention: Duration) -> Self {
+        if temp.is_public {
+            Self::Public {
+                retention: public_retention,
+            }
+        } else {
+            Self::Private
+        }
+    }
+
+    /// Messages received before this are not served.
+    pub fn oldest_visible(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Private => None,
+            Self::Public { retention } => chrono::Duration::from_std(*retention)
+                .ok()
+                .and_then(|r| now.checked_sub_signed(r)),
+        }
+    }
+
+    /// `authenticated` means the caller presented the mailbox's access token.
+    pub fn allows(&self, method: &Method, authenticated: bool) -> bool {
+        match self {
+            Self::Private => true,
+            Self::Public { .. } => authenticated || method != Method::DELETE,
+        }
+    }
+}
+
+/// Guards `/api/email/:address/...`: a private mailbox requires its access
+/// token (or a session of the owning account) as a bearer token or
+/// `token` query parameter, and the mailbox's policy must allow the method.
+/// Unknown addresses pass through so handlers report them as usual.
+pub(crate) async fn enforce(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(query): Query<HashMap<String, String>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(address) = params.get("address") else {
+        return next.run(req).await;
+    };
+    let pool = match require_pool(&state).await {
+        Ok(pool) => pool,
+        Err(res) => return res,
+    };
+    let addr = address.trim().to_ascii_lowercase();
+    let lookup = find_temporary_email_by_addr(&pool, &addr);
+    let temp = match db::timed("http", "find_address", lookup).await {
+        Ok(Some(temp)) => temp,
+        Ok(None) => return next.run(req).await,
+        Err(e) => return db_error(e),
+    };
+    let presented = token::presented(req.headers(), &query);
+    let authenticated = match authenticate(&state, &pool, &temp, presented).await {
+        Ok(authenticated) => authenticated,
+        Err(res) => return res,
+    };
+    if !MailboxPolicy::of(&temp, state.public_retention).allows(req.method(), authenticated) {
+        return err(StatusCode::FORBIDDEN, "public mailboxes are read-only");
+    }
+    next.run(req).await
+}
+
+/// Whether `presented` is the mailbox's own token or a session of the account
+/// owning it. A private mailbox answers the uniform **404** to anyone else,
+/// unless it has no token and `open_tokenless_mailboxes` is set.
+pub(crate) async fn authenticate(
+    state: &AppState,
+    pool: &PgPool,
+    temp: &TemporaryEmail,
+    presented: Option<&str>,
+) -> Result<bool, Response> {
+    let stored = fetch_mailbox_token_hash(pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    let authenticated = presented.is_some_and(|presented| {
+        stored
+            .as_deref()
+            .is_some_and(|hash| token::verify(&state.token_pepper, presented, hash))
+            || temp.user_id.is_some_and(|owner| {
+                state
+                    .sessions
+                    .keys
+                    .verify::<SessionClaims>(presented)
+                    .is_some_and(|claims| claims.uid == Some(owner))
+            })
+    });
+    let policy = MailboxPolicy::of(temp, state.public_retention);
+    let open = stored.is_none() && state.open_tokenless_mailboxes;
+    if policy == MailboxPolicy::Private && !authenticated && !open {
+        return Err(lookup::not_found());
+    }
+    Ok(authenticated)
+}
@@ -0,0 +1,113 @@
+//! PNG screenshots of a message's HTML body, for checking how a campaign
+//! renders. A headless-browser sidecar does the rendering: it is sent the
+//! sanitized HTML as a `text/html` POST and answers with an `image/png`.
+//! The first request for a message renders it; later ones are served from
+//! `email_preview`.
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use db::{fetch_email_preview, store_email_preview};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::api::{db_error, err};
+use crate::share::owned_email;
+use crate::{html, AppState};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub struct Renderer {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Renderer {
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+        Self {
+            client,
+            url: url.into(),
+        }
+    }
+
+    /// Sends `html` (already sanitized) and checks that a PNG came back.
+    async fn render(&self, html: String) -> Result<Vec<u8>, String> {
+        let res = self
+            .client
+            .post(&self.url)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(html)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !res.status().is_success() {
+            return Err(format!("renderer answered {}", res.status()));
+        }
+        let png = res.bytes().await.map_err(|e| e.to_string())?;
+        if !png.starts_with(PNG_SIGNATURE) {
+            return Err("renderer did not return a PNG".into());
+        }
+        Ok(png.to_vec())
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/preview.png",
+    tag = "api",
+    summary = "PNG screenshot of the HTML body",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_preview(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Response, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let stored = fetch_email_preview(&pool, email.id)
+        .await
+        .map_err(db_error)?;
+    let png = match stored {
+        Some(png) => png,
+        None => {
+            let renderer = state
+                .renderer
+                .as_ref()
+                .ok_or_else(|| err(StatusCode::NOT_FOUND, "previews are not enabled"))?;
+            let html = email
+                .body_html
+                .as_deref()
+                .ok_or_else(|| err(StatusCode::NOT_FOUND, "message has no HTML body"))?;
+            // The renderer has no origin to resolve a relative proxy URL
+            // against, so images load from their senders; pixels still go.
+            let options = html::DisplayOptions {
+                image_proxy: None,
+                ..state.html_display.clone()
+            };
+            let sanitized = html::sanitize(html, &options);
+            let png = renderer.render(sanitized).await.map_err(|e| {
+                tracing::warn!(error = %e, email_id = %email.id, "preview rendering failed");
+                err(StatusCode::BAD_GATEWAY, "preview renderer unavailable")
+            })?;
+            store_email_preview(&pool, email.id, &png)
+                .await
+                .map_err(db_error)?;
+            png
+        }
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        png,
+    )
+        .into_response())
+}
@@ -0,0 +1,44 @@
+//! Soft-limit warnings. Once a mailbox holds [`db::QUOTA_WARN_PERCENT`] of
+//! its quota, `poll` and the message detail name the quotas nearly used up,
+//! in `warnings` and one `X-Mailbox-Warning` header each, so clients can
+//! prompt a clean-up before the SMTP server starts refusing mail for it.
+
+use axum::{
+    http::{HeaderName, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use db::{mailbox_usage, QuotaWarning};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api::db_error;
+use crate::AppState;
+
+pub const WARNING_HEADER: HeaderName = HeaderName::from_static("x-mailbox-warning");
+
+/// Quotas of the mailbox that are nearly used up; none without a quota.
+pub(crate) async fn warnings(
+    state: &AppState,
+    pool: &PgPool,
+    temporary_email_id: Uuid,
+) -> Result<Vec<QuotaWarning>, Response> {
+    if !state.mailbox_quota.is_limited() {
+        return Ok(Vec::new());
+    }
+    let usage = mailbox_usage(pool, temporary_email_id)
+        .await
+        .map_err(db_error)?;
+    Ok(state.mailbox_quota.warnings(&usage))
+}
+
+/// `response` with a header like `messages 85/100` per warning.
+pub(crate) fn with_headers(response: impl IntoResponse, warnings: &[QuotaWarning]) -> Response {
+    let mut response = response.into_response();
+    for w in warnings {
+        let value = format!("{} {}/{}", w.quota, w.used, w.limit);
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().append(WARNING_HEADER, value);
+        }
+    }
+    response
+}
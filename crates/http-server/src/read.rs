@@ -0,0 +1,67 @@
+//! `/api/email/:address/:email_id/read`: whether a message has been read.
+//! Endpoints that open a message leave it alone unless asked with
+//! `?mark_read=true`, so a preview or a script does not mark mail read.
+
+use axum::{
+    extract::{Path, State},
+    response::Response,
+    Json,
+};
+use db::{set_received_email_read, ReceivedEmail};
+use serde::Deserialize;
+use sqlx::PgPool;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::api::db_error;
+use crate::lookup::not_found;
+use crate::share::owned_email;
+use crate::AppState;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReadState {
+    /// Defaults to `true`; `false` marks the message unread again.
+    pub is_read: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MarkRead {
+    #[serde(default)]
+    pub mark_read: bool,
+}
+
+/// The body may be left out to mark the message read.
+#[utoipa::path(
+    patch,
+    path = "/api/email/{address}/{email_id}/read",
+    tag = "api",
+    summary = "Mark a message read or unread",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = 200, description = "OK", body = ReceivedEmail)),
+    security(("mailbox" = []))
+)]
+pub async fn set_read(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+    body: Option<Json<ReadState>>,
+) -> Result<Json<ReceivedEmail>, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let is_read = body.and_then(|Json(b)| b.is_read).unwrap_or(true);
+    set_received_email_read(&pool, email.temporary_email_id, email.id, is_read)
+        .await
+        .map_err(db_error)?
+        .map(Json)
+        .ok_or_else(not_found)
+}
+
+/// Marks `email` read if the request asked for it. A failure is logged
+/// rather than failing the request that opened the message.
+pub(crate) async fn mark_opened(pool: &PgPool, email: &ReceivedEmail, query: &MarkRead) {
+    if !query.mark_read || email.is_read {
+        return;
+    }
+    if let Err(e) = set_received_email_read(pool, email.temporary_email_id, email.id, true).await {
+        tracing::warn!(email_id = %email.id, error = %e, "failed to mark message read");
+    }
+}
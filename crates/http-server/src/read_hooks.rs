@@ -0,0 +1,60 @@
+//! The `on_api_read` hook of WASM plugins (see `smtp::wasm`), asked before
+//! a single message is served. A message a plugin refuses answers the same
+//! 404 as one that does not exist. Without the `wasm-plugins` feature, or
+//! without plugins, every read is allowed.
+
+use db::ReceivedEmail;
+#[cfg(feature = "wasm-plugins")]
+use smtp::IngestStage;
+use std::fmt;
+#[cfg(feature = "wasm-plugins")]
+use std::sync::Arc;
+
+#[derive(Clone, Default)]
+pub struct ReadHooks {
+    #[cfg(feature = "wasm-plugins")]
+    plugins: Arc<Vec<smtp::wasm::WasmStage>>,
+}
+
+impl ReadHooks {
+    #[cfg(feature = "wasm-plugins")]
+    pub fn new(plugins: Vec<smtp::wasm::WasmStage>) -> Self {
+        Self {
+            plugins: Arc::new(plugins),
+        }
+    }
+
+    /// Whether every plugin lets `email` be served, read through the mailbox
+    /// `address` (`None` for share links).
+    #[cfg_attr(not(feature = "wasm-plugins"), allow(unused_variables))]
+    pub async fn allows(&self, address: Option<&str>, email: &ReceivedEmail) -> bool {
+        #[cfg(feature = "wasm-plugins")]
+        {
+            let input = smtp::wasm::ApiReadInput {
+                address,
+                email_id: email.id,
+                from: email.from_addr.as_deref(),
+                subject: email.subject.as_deref(),
+                tags: &email.tags,
+            };
+            for plugin in self.plugins.iter() {
+                if !plugin.allows_api_read(&input).await {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Debug for ReadHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[cfg(feature = "wasm-plugins")]
+        let plugins: Vec<&str> = self.plugins.iter().map(|p| p.name()).collect();
+        #[cfg(not(feature = "wasm-plugins"))]
+        let plugins: Vec<&str> = Vec::new();
+        f.debug_struct("ReadHooks")
+            .field("plugins", &plugins)
+            .finish()
+    }
+}
@@ -0,0 +1,28 @@
+//! Attempts and exponential backoff shared by the queues that retry
+//! outbound work (webhook POSTs, mailbox forwards).
+
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts before a job is given up on.
+    pub max_attempts: u32,
+    /// Wait after the first failed attempt; doubles after every further one.
+    pub retry_base: Duration,
+    pub retry_max: Duration,
+}
+
+impl RetryPolicy {
+    /// Wait before the next attempt once `attempts` have failed.
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let doublings = attempts.saturating_sub(1).min(20);
+        self.retry_base
+            .saturating_mul(1 << doublings)
+            .min(self.retry_max)
+    }
+
+    /// Whether a job may be tried again after its `attempt`th failure.
+    pub fn allows_retry(&self, attempt: u32) -> bool {
+        attempt < self.max_attempts
+    }
+}
@@ -0,0 +1,276 @@
+//! Anonymous sessions: a short-lived HS256 JWT for requests plus a long-lived,
+//! single-use refresh token to get the next one.
+//!
+//! JWTs carry a `kid`. The first configured key signs; the others only verify,
+//! so a key can be rotated in by putting it first and dropped once every token
+//! signed with its predecessor has expired.
+//!
+//! The same keys sign other short-lived tokens, such as the OIDC login
+//! `state`. Each kind names itself in the `aud` claim, so one kind is never
+//! accepted as another.
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::{request::Parts, StatusCode},
+    response::Response,
+    Json,
+};
+use chrono::{Duration as ChronoDuration, Utc};
+use db::{insert_session, rotate_session_refresh, Session};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::api::{db_error, err, require_pool};
+use crate::{token, AppState};
+
+pub const MIN_KEY_LEN: usize = 32;
+
+/// Claims of a kind of token signed with [`SessionKeys`].
+pub(crate) trait TokenKind: Serialize + DeserializeOwned {
+    /// Written to `aud`; distinct for every kind.
+    const AUDIENCE: &'static str;
+}
+
+#[derive(Serialize)]
+struct Audience<'a, T> {
+    aud: &'static str,
+    #[serde(flatten)]
+    claims: &'a T,
+}
+
+#[derive(Clone)]
+pub struct SessionKeys {
+    current: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl SessionKeys {
+    /// A single random key. Sessions do not survive a restart.
+    pub fn random() -> Self {
+        let secret: [u8; 32] = rand::thread_rng().gen();
+        Self {
+            current: "ephemeral".into(),
+            keys: HashMap::from([("ephemeral".into(), secret.to_vec())]),
+        }
+    }
+
+    /// Parses `kid:secret[,kid:secret...]`; the first entry is the signing key.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut current = None;
+        let mut keys = HashMap::new();
+        for entry in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (kid, secret) = entry
+                .split_once(':')
+                .ok_or_else(|| format!("{entry:?} is not kid:secret"))?;
+            if kid.is_empty() || secret.len() < MIN_KEY_LEN {
+                return Err(format!(
+                    "key {kid:?} needs a name and at least {MIN_KEY_LEN} bytes"
+                ));
+            }
+            if keys
+                .insert(kid.to_owned(), secret.as_bytes().to_vec())
+                .is_some()
+            {
+                return Err(format!("duplicate key id {kid:?}"));
+            }
+            current.get_or_insert_with(|| kid.to_owned());
+        }
+        let current = current.ok_or("no keys given")?;
+        Ok(Self { current, keys })
+    }
+
+    pub(crate) fn sign<T: TokenKind>(&self, claims: &T) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(self.current.clone());
+        jsonwebtoken::encode(
+            &header,
+            &Audience {
+                aud: T::AUDIENCE,
+                claims,
+            },
+            &EncodingKey::from_secret(&self.keys[&self.current]),
+        )
+        .expect("HS256 signing does not fail")
+    }
+
+    /// Checks signature, `exp` and that `aud` is `T`'s: a token is only valid
+    /// as the kind it was issued as.
+    pub(crate) fn verify<T: TokenKind>(&self, jwt: &str) -> Option<T> {
+        let kid = jsonwebtoken::decode_header(jwt).ok()?.kid?;
+        let secret = self.keys.get(&kid)?;
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        validation.set_audience(&[T::AUDIENCE]);
+        validation.set_required_spec_claims(&["exp", "aud"]);
+        jsonwebtoken::decode(jwt, &DecodingKey::from_secret(secret), &validation)
+            .ok()
+            .map(|data| data.claims)
+    }
+}
+
+impl fmt::Debug for SessionKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut kids: Vec<_> = self.keys.keys().collect();
+        kids.sort();
+        f.debug_struct("SessionKeys")
+            .field("current", &self.current)
+            .field("kids", &kids)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    pub keys: SessionKeys,
+    pub access_ttl: Duration,
+    pub refresh_ttl: Duration,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            keys: SessionKeys::random(),
+            access_ttl: Duration::from_secs(15 * 60),
+            refresh_ttl: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionClaims {
+    /// Session id.
+    pub sid: Uuid,
+    /// Account id, for sessions started by logging in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uid: Option<Uuid>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+impl TokenKind for SessionClaims {
+    const AUDIENCE: &'static str = "fake-email/session";
+}
+
+/// Extracts and validates `Authorization: Bearer <jwt>`. Handlers that take
+/// this argument are only reached with a live session.
+#[async_trait]
+impl FromRequestParts<AppState> for SessionClaims {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Response> {
+        token::bearer(&parts.headers)
+            .and_then(|jwt| state.sessions.keys.verify(jwt))
+            .ok_or_else(|| err(StatusCode::UNAUTHORIZED, "invalid or expired session"))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionTokens {
+    pub session_id: Uuid,
+    pub access_token: String,
+    pub token_type: &'static str,
+    pub expires_in: u64,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshBody {
+    pub refresh_token: String,
+}
+
+fn refresh_expiry(config: &SessionConfig) -> chrono::DateTime<Utc> {
+    Utc::now() + ChronoDuration::from_std(config.refresh_ttl).unwrap_or(ChronoDuration::MAX)
+}
+
+fn issue(config: &SessionConfig, session: &Session, refresh_token: String) -> SessionTokens {
+    let now = Utc::now().timestamp();
+    let claims = SessionClaims {
+        sid: session.id,
+        uid: session.user_id,
+        iat: now,
+        exp: now + config.access_ttl.as_secs() as i64,
+    };
+    SessionTokens {
+        session_id: session.id,
+        access_token: config.keys.sign(&claims),
+        token_type: "Bearer",
+        expires_in: config.access_ttl.as_secs(),
+        refresh_token,
+    }
+}
+
+/// Starts a session, logged in as `user_id` if given.
+pub(crate) async fn start(
+    state: &AppState,
+    user_id: Option<Uuid>,
+) -> Result<SessionTokens, Response> {
+    let pool = require_pool(state).await?;
+    let refresh_token = token::generate();
+    let session = insert_session(
+        &pool,
+        user_id,
+        &token::hash(&state.token_pepper, &refresh_token),
+        refresh_expiry(&state.sessions),
+    )
+    .await
+    .map_err(db_error)?;
+    Ok(issue(&state.sessions, &session, refresh_token))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/session",
+    tag = "api",
+    summary = "Start an anonymous session",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn create_session(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<SessionTokens>), Response> {
+    Ok((StatusCode::CREATED, Json(start(&state, None).await?)))
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token.
+/// Each refresh token works once.
+#[utoipa::path(
+    post,
+    path = "/api/session/refresh",
+    tag = "api",
+    summary = "Exchange a refresh token for a new pair",
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn refresh_session(
+    State(state): State<AppState>,
+    Json(body): Json<RefreshBody>,
+) -> Result<Json<SessionTokens>, Response> {
+    let pool = require_pool(&state).await?;
+    let refresh_token = token::generate();
+    let session = rotate_session_refresh(
+        &pool,
+        &token::hash(&state.token_pepper, body.refresh_token.trim()),
+        &token::hash(&state.token_pepper, &refresh_token),
+        refresh_expiry(&state.sessions),
+    )
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| err(StatusCode::UNAUTHORIZED, "invalid or expired refresh token"))?;
+    Ok(Json(issue(&state.sessions, &session, refresh_token)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/session",
+    tag = "api",
+    summary = "Claims of the current session",
+    responses((status = "default", description = "See README.md")),
+    security(("session" = []))
+)]
+pub async fn current_session(claims: SessionClaims) -> Json<SessionClaims> {
+    Json(claims)
+}
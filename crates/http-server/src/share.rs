@@ -0,0 +1,263 @@
+//! Signed, expiring links to a single message.
+//!
+//! A link is `/api/share/{share_id}?exp={unix}&sig={hex}` where `sig` is
+//! HMAC-SHA256 over `share_id.email_id.exp`. The signature stops guessing and
+//! tampering with `exp`; the `email_share` row allows revocation.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use db::{
+    find_email_share, find_received_email, find_received_email_by_id, find_temporary_email_by_addr,
+    find_tenant_settings_for_address, insert_email_share, revoke_email_share, ReceivedEmail,
+    TenantSettings,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::api::{db_error, err, require_pool};
+use crate::lookup;
+use crate::AppState;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const DEFAULT_TTL_SECS: i64 = 24 * 60 * 60;
+const MAX_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateShareBody {
+    pub ttl_secs: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareLinkResponse {
+    pub share_id: Uuid,
+    pub url: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareQuery {
+    pub exp: i64,
+    pub sig: String,
+}
+
+/// What a share link reveals: the text of one message, nothing that would
+/// identify the rest of the mailbox.
+#[derive(Debug, Serialize)]
+pub struct SharedEmail {
+    pub from_addr: Option<String>,
+    pub subject: Option<String>,
+    pub body_text: Option<String>,
+    pub received_at: DateTime<Utc>,
+    pub redacted_at: Option<DateTime<Utc>>,
+    /// Set when the mailbox belongs to a tenant that branded its share page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branding: Option<SharePageBranding>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SharePageBranding {
+    pub name: Option<String>,
+    pub logo_url: Option<String>,
+}
+
+impl From<ReceivedEmail> for SharedEmail {
+    fn from(e: ReceivedEmail) -> Self {
+        Self {
+            from_addr: e.from_addr,
+            subject: e.subject,
+            body_text: e.body_text,
+            received_at: e.received_at,
+            redacted_at: e.redacted_at,
+            branding: None,
+        }
+    }
+}
+
+impl SharePageBranding {
+    fn of(t: TenantSettings) -> Option<Self> {
+        if t.share_page_name.is_none() && t.share_page_logo_url.is_none() {
+            return None;
+        }
+        Some(Self {
+            name: t.share_page_name,
+            logo_url: t.share_page_logo_url,
+        })
+    }
+}
+
+fn sign(secret: &[u8], share_id: Uuid, email_id: Uuid, exp: i64) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(format!("{share_id}.{email_id}.{exp}").as_bytes());
+    mac
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub(crate) async fn owned_email(
+    state: &AppState,
+    address: &str,
+    email_id: Uuid,
+) -> Result<(sqlx::postgres::PgPool, ReceivedEmail), Response> {
+    let pool = require_pool(state).await?;
+    let temp = find_temporary_email_by_addr(&pool, &address.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot && t.is_live())
+        .ok_or_else(lookup::not_found)?;
+    let email = find_received_email(&pool, temp.id, email_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(lookup::not_found)?;
+    let address = Some(temp.temp_email_addr.as_str());
+    if !state.read_hooks.allows(address, &email).await {
+        return Err(lookup::not_found());
+    }
+    Ok((pool, email))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/{email_id}/share",
+    tag = "api",
+    summary = "Create a signed share link",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn create_share(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+    body: Option<Json<CreateShareBody>>,
+) -> Result<(StatusCode, Json<ShareLinkResponse>), Response> {
+    let ttl = body
+        .and_then(|Json(b)| b.ttl_secs)
+        .unwrap_or(DEFAULT_TTL_SECS);
+    if !(1..=MAX_TTL_SECS).contains(&ttl) {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("ttl_secs must be between 1 and {MAX_TTL_SECS}"),
+        ));
+    }
+
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let expires_at = Utc::now() + Duration::seconds(ttl);
+    let share = insert_email_share(&pool, email.id, expires_at)
+        .await
+        .map_err(db_error)?;
+
+    let exp = share.expires_at.timestamp();
+    let sig = to_hex(
+        &sign(&state.share_secret, share.id, email.id, exp)
+            .finalize()
+            .into_bytes(),
+    );
+    let path = format!("/api/share/{}?exp={exp}&sig={sig}", share.id);
+    let url = match state.public_base_url.as_deref() {
+        Some(base) => format!("{}{path}", base.trim_end_matches('/')),
+        None => path,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ShareLinkResponse {
+            share_id: share.id,
+            url,
+            expires_at: share.expires_at,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/{email_id}/share/{share_id}",
+    tag = "api",
+    summary = "Revoke a share link",
+    params(("address" = String, Path), ("email_id" = Uuid, Path), ("share_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    Path((address, email_id, share_id)): Path<(String, Uuid, Uuid)>,
+) -> Result<StatusCode, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    if !revoke_email_share(&pool, share_id, email.id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err(err(StatusCode::NOT_FOUND, "unknown share link"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/share/{share_id}",
+    tag = "api",
+    summary = "View a shared message",
+    params(("share_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md"))
+)]
+pub async fn view_share(
+    State(state): State<AppState>,
+    Path(share_id): Path<Uuid>,
+    Query(q): Query<ShareQuery>,
+) -> Result<Json<SharedEmail>, Response> {
+    let pool = require_pool(&state).await?;
+    // One answer for every failure so links can't be probed.
+    let invalid = || err(StatusCode::NOT_FOUND, "invalid or expired share link");
+
+    let share = find_email_share(&pool, share_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(invalid)?;
+    let sig = from_hex(&q.sig).ok_or_else(invalid)?;
+    sign(
+        &state.share_secret,
+        share.id,
+        share.received_email_id,
+        q.exp,
+    )
+    .verify_slice(&sig)
+    .map_err(|_| invalid())?;
+
+    let exp = Utc.timestamp_opt(q.exp, 0).single().ok_or_else(invalid)?;
+    if share.revoked_at.is_some() || exp <= Utc::now() || q.exp != share.expires_at.timestamp() {
+        return Err(invalid());
+    }
+
+    let email = find_received_email_by_id(&pool, share.received_email_id)
+        .await
+        .map_err(db_error)?
+        .filter(|e| e.deleted_at.is_none())
+        .ok_or_else(invalid)?;
+    if !state.read_hooks.allows(None, &email).await {
+        return Err(invalid());
+    }
+    let tenant = find_tenant_settings_for_address(&pool, email.temporary_email_id)
+        .await
+        .map_err(db_error)?;
+    let mut shared = SharedEmail::from(email);
+    shared.branding = tenant.and_then(SharePageBranding::of);
+    Ok(Json(shared))
+}
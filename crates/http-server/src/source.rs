@@ -0,0 +1,334 @@
+//! `/api/email/:address/:email_id/source`: the original message as text
+//! with its MIME structure marked out, so a client can highlight headers,
+//! boundaries and parts without parsing MIME itself.
+//!
+//! Offsets count UTF-16 code units of `source`, the unit JavaScript strings
+//! are indexed in. Attached messages (`message/rfc822`) are one part; their
+//! insides are not broken down.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::fetch_raw_email;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::api::{db_error, err};
+use crate::share::owned_email;
+use crate::AppState;
+
+/// Nesting beyond this is left unexplored.
+const MAX_DEPTH: usize = 20;
+/// Parts listed per message; any beyond are left out.
+const MAX_PARTS: usize = 1000;
+
+#[derive(Debug, Serialize)]
+pub struct Source {
+    /// The message as received; bytes that are not UTF-8 show as U+FFFD.
+    pub source: String,
+    /// Every part in order of appearance, the message itself first.
+    pub parts: Vec<Part>,
+    /// Delimiter lines between the parts of multipart bodies.
+    pub boundaries: Vec<Boundary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Part {
+    /// IMAP-style section number (`1`, `1.2`, …); empty for the message.
+    pub path: String,
+    /// Lowercased `type/subtype`, defaulted as RFC 2046 says when absent.
+    pub content_type: String,
+    pub transfer_encoding: Option<String>,
+    pub filename: Option<String>,
+    /// Where its header starts.
+    pub start: usize,
+    /// Where its body starts, after the blank line ending the header.
+    pub body_start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Boundary {
+    pub start: usize,
+    /// Just past the line break ending the delimiter line.
+    pub end: usize,
+    /// The final `--boundary--` of a multipart body.
+    pub closing: bool,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/{email_id}/source",
+    tag = "api",
+    summary = "Original message with its MIME parts and boundaries marked",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn email_source(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<Json<Source>, Response> {
+    let (pool, email) = owned_email(&state, &address, email_id).await?;
+    let raw = fetch_raw_email(&pool, email.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| err(StatusCode::NOT_FOUND, "original message not stored"))?;
+    Ok(Json(annotate(&raw)))
+}
+
+/// Splits `raw` into its parts and boundaries.
+pub fn annotate(raw: &[u8]) -> Source {
+    let mut scan = Scan::default();
+    scan.part(raw, 0, raw.len(), String::new(), "text/plain", 0);
+    let offsets = scan
+        .parts
+        .iter()
+        .flat_map(|p| [p.start, p.body_start, p.end])
+        .chain(scan.boundaries.iter().flat_map(|b| [b.start, b.end]))
+        .collect();
+    let (source, units) = decode(raw, offsets);
+    let unit = |byte: &mut usize| *byte = units[&*byte];
+    for part in &mut scan.parts {
+        unit(&mut part.start);
+        unit(&mut part.body_start);
+        unit(&mut part.end);
+    }
+    for boundary in &mut scan.boundaries {
+        unit(&mut boundary.start);
+        unit(&mut boundary.end);
+    }
+    Source {
+        source,
+        parts: scan.parts,
+        boundaries: scan.boundaries,
+    }
+}
+
+/// Parts and boundaries found so far, with byte offsets.
+#[derive(Default)]
+struct Scan {
+    parts: Vec<Part>,
+    boundaries: Vec<Boundary>,
+}
+
+impl Scan {
+    /// Records the part in `raw[start..end]` and, if it is multipart, the
+    /// parts inside it.
+    fn part(
+        &mut self,
+        raw: &[u8],
+        start: usize,
+        end: usize,
+        path: String,
+        default_type: &str,
+        depth: usize,
+    ) {
+        if self.parts.len() >= MAX_PARTS {
+            return;
+        }
+        let body_start = header_end(&raw[start..end]).map_or(end, |len| start + len);
+        let fields = header_fields(&raw[start..body_start]);
+        let field = |name: &str| fields.get(name).map(String::as_str);
+        let content_type = field("content-type").unwrap_or(default_type);
+        let mime_type = media_type(content_type).unwrap_or_else(|| default_type.to_owned());
+        let filename = field("content-disposition")
+            .and_then(|d| param(d, "filename"))
+            .or_else(|| param(content_type, "name"));
+        let boundary = param(content_type, "boundary");
+        self.parts.push(Part {
+            path: path.clone(),
+            content_type: mime_type.clone(),
+            transfer_encoding: field("content-transfer-encoding").map(|e| e.to_ascii_lowercase()),
+            filename,
+            start,
+            body_start,
+            end,
+        });
+
+        let Some(boundary) = boundary.filter(|_| mime_type.starts_with("multipart/")) else {
+            return;
+        };
+        if depth >= MAX_DEPTH {
+            return;
+        }
+        let child_type = if mime_type == "multipart/digest" {
+            "message/rfc822"
+        } else {
+            "text/plain"
+        };
+        let delimiter = format!("--{boundary}");
+        let mut child_start = None;
+        let mut index = 0;
+        let mut child_path = || {
+            index += 1;
+            match path.as_str() {
+                "" => index.to_string(),
+                parent => format!("{parent}.{index}"),
+            }
+        };
+        for (line_start, line_end) in lines(raw, body_start, end) {
+            let line = &raw[line_start..line_end];
+            let Some(rest) = line.strip_prefix(delimiter.as_bytes()) else {
+                continue;
+            };
+            let rest = rest.trim_ascii_end();
+            let closing = rest == b"--";
+            if !closing && !rest.is_empty() {
+                continue;
+            }
+            // The line break before a delimiter belongs to the delimiter.
+            let break_len = [&b"\r\n"[..], b"\n"]
+                .into_iter()
+                .find(|eol| raw[..line_start].ends_with(eol))
+                .map_or(0, <[u8]>::len);
+            let delimiter_start = (line_start - break_len).max(child_start.unwrap_or(body_start));
+            if let Some(child) = child_start.take() {
+                let path = child_path();
+                self.part(raw, child, delimiter_start, path, child_type, depth + 1);
+            }
+            self.boundaries.push(Boundary {
+                start: delimiter_start,
+                end: line_end,
+                closing,
+            });
+            if closing {
+                break;
+            }
+            child_start = Some(line_end);
+        }
+        // A body cut off before its closing delimiter still has a last part.
+        if let Some(child) = child_start {
+            let path = child_path();
+            self.part(raw, child, end, path, child_type, depth + 1);
+        }
+    }
+}
+
+/// Length of the header including the blank line after it, if it ends.
+fn header_end(part: &[u8]) -> Option<usize> {
+    if part.starts_with(b"\r\n") {
+        return Some(2);
+    }
+    if part.starts_with(b"\n") {
+        return Some(1);
+    }
+    let crlf = part
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| i + 4);
+    let lf = part.windows(2).position(|w| w == b"\n\n").map(|i| i + 2);
+    match (crlf, lf) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Unfolded header fields by lowercased name; the first of a repeated
+/// field wins.
+fn header_fields(header: &[u8]) -> HashMap<String, String> {
+    let header = String::from_utf8_lossy(header);
+    let mut fields: Vec<String> = Vec::new();
+    for line in header.lines() {
+        match fields.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => last.push_str(line),
+            _ => fields.push(line.to_owned()),
+        }
+    }
+    let mut by_name = HashMap::new();
+    for field in fields {
+        if let Some((name, value)) = field.split_once(':') {
+            by_name
+                .entry(name.trim().to_ascii_lowercase())
+                .or_insert_with(|| value.trim().to_owned());
+        }
+    }
+    by_name
+}
+
+fn media_type(content_type: &str) -> Option<String> {
+    let media = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    media.contains('/').then_some(media)
+}
+
+/// Value of the `name` parameter of a structured field, unquoted.
+fn param(value: &str, name: &str) -> Option<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ';' if !quoted => params.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    params.push(current);
+    params.iter().skip(1).find_map(|p| {
+        let (key, value) = p.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case(name) {
+            return None;
+        }
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Some(value.replace("\\\"", "\"")).filter(|v| !v.is_empty())
+    })
+}
+
+/// `(start, end)` of each line in `raw[start..end]`, line break included.
+fn lines(raw: &[u8], start: usize, end: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+    let mut at = start;
+    std::iter::from_fn(move || {
+        if at >= end {
+            return None;
+        }
+        let line_start = at;
+        at = raw[at..end]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(end, |i| at + i + 1);
+        Some((line_start, at))
+    })
+}
+
+/// `raw` decoded lossily, and the UTF-16 position in it of each byte
+/// offset asked for.
+fn decode(raw: &[u8], mut offsets: Vec<usize>) -> (String, HashMap<usize, usize>) {
+    offsets.sort_unstable();
+    offsets.dedup();
+    let mut wanted = offsets.into_iter().peekable();
+    let mut units = HashMap::new();
+    let mut source = String::with_capacity(raw.len());
+    let (mut byte, mut unit) = (0, 0);
+    for chunk in raw.utf8_chunks() {
+        let invalid = chunk.invalid();
+        let pieces = chunk
+            .valid()
+            .chars()
+            .map(|c| (c.len_utf8(), c.len_utf16()))
+            .chain((!invalid.is_empty()).then_some((invalid.len(), 1)));
+        for (bytes, len) in pieces {
+            while let Some(at) = wanted.next_if(|&at| at < byte + bytes) {
+                units.insert(at, unit);
+            }
+            byte += bytes;
+            unit += len;
+        }
+        source.push_str(chunk.valid());
+        if !invalid.is_empty() {
+            source.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+    units.extend(wanted.map(|at| (at, unit)));
+    (source, units)
+}
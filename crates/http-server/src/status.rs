@@ -0,0 +1,119 @@
+//! `status`: probe a running instance from inside its container, for use as
+//! a `HEALTHCHECK`. Unlike `--check` it looks at the live process, not the
+//! deployment around it.
+
+use db::connect_pool;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::check::Report;
+use crate::config::Config;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prints one line per probe and returns the exit code: 0 when SMTP, HTTP
+/// and the database all answer, 1 otherwise.
+pub async fn run(config: &Config) -> i32 {
+    let mut report = Report::default();
+
+    match tokio::time::timeout(TIMEOUT, probe_smtp(config)).await {
+        Ok(Ok(banner)) => report.ok("smtp", banner),
+        Ok(Err(e)) => report.fail("smtp", e),
+        Err(_) => report.fail("smtp", format!("no answer within {}s", TIMEOUT.as_secs())),
+    }
+
+    match probe_http(config).await {
+        Ok(()) => report.ok("http", "/healthz answered 200"),
+        Err(e) => report.fail("http", e),
+    }
+
+    match tokio::time::timeout(TIMEOUT, probe_database()).await {
+        Ok(Ok(())) => report.ok("database", "connected"),
+        Ok(Err(e)) => report.fail("database", e),
+        Err(_) => report.fail("database", format!("no answer within {}s", TIMEOUT.as_secs())),
+    }
+
+    i32::from(report.failed > 0)
+}
+
+/// Where to reach a listener bound to `host`; wildcard binds are probed on
+/// loopback.
+fn local_host(host: &str) -> &str {
+    match host {
+        "0.0.0.0" | "" => "127.0.0.1",
+        "::" => "::1",
+        host => host,
+    }
+}
+
+/// Greets with `HELO` and quits; returns the greeting.
+async fn probe_smtp(config: &Config) -> Result<String, String> {
+    let host = local_host(&config.smtp_host);
+    let stream = TcpStream::connect((host, config.smtp_port))
+        .await
+        .map_err(|e| format!("connect to {host}:{} failed: {e}", config.smtp_port))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    let banner = read_reply(&mut reader, "220").await?;
+    for (command, code) in [("HELO status.localhost", "250"), ("QUIT", "221")] {
+        writer
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await
+            .map_err(|e| format!("{command}: {e}"))?;
+        read_reply(&mut reader, code)
+            .await
+            .map_err(|e| format!("{command}: {e}"))?;
+    }
+    Ok(banner)
+}
+
+async fn read_reply(
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    code: &str,
+) -> Result<String, String> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .map_err(|e| e.to_string())?;
+    let line = line.trim_end();
+    if line.starts_with(code) {
+        Ok(line.to_owned())
+    } else {
+        Err(format!("expected {code}, got {line:?}"))
+    }
+}
+
+async fn probe_http(config: &Config) -> Result<(), String> {
+    let host = local_host(&config.http_host);
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host.to_owned()
+    };
+    let url = format!("http://{host}:{}/healthz", config.http_port);
+    let res = reqwest::Client::new()
+        .get(&url)
+        .timeout(TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| format!("{url}: {e}"))?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("{url} answered {}", res.status()))
+    }
+}
+
+async fn probe_database() -> Result<(), String> {
+    let pool = connect_pool()
+        .await
+        .map_err(|e| format!("connect failed: {e}"))?;
+    sqlx::query("SELECT 1")
+        .execute(&pool)
+        .await
+        .map_err(|e| format!("query failed: {e}"))?;
+    Ok(())
+}
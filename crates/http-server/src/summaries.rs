@@ -0,0 +1,115 @@
+//! `GET /api/email/:address/messages`: the mailbox a page at a time, newest
+//! first and without bodies, for clients showing a message list. Pages are
+//! keyed on the last message of the previous one (`?after=` with its
+//! `next_cursor`) rather than an offset, so deep pages stay cheap and mail
+//! arriving between requests neither repeats nor skips entries. Filters
+//! narrow both the pages and `total`.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use db::{list_email_summaries_by_address, EmailSummary, SummaryCursor, SummaryFilter};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::api::{db_error, err};
+use crate::policy::MailboxPolicy;
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// `next_cursor` of the previous page.
+    pub after: Option<String>,
+    pub limit: Option<i64>,
+    /// Sender address.
+    pub from: Option<String>,
+    pub subject_contains: Option<String>,
+    /// RFC 3339; received after.
+    pub since: Option<String>,
+    /// RFC 3339; received before.
+    pub until: Option<String>,
+    #[serde(default)]
+    pub unread_only: bool,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = SummaryPage)]
+pub struct ListResponse {
+    pub items: Vec<EmailSummary>,
+    /// `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Messages the mailbox shows that match the filters, across all pages.
+    pub total: i64,
+}
+
+fn parse_time(name: &str, raw: Option<&str>) -> Result<Option<DateTime<Utc>>, Response> {
+    let Some(raw) = raw.map(str::trim).filter(|s| !s.is_empty()) else {
+        return Ok(None);
+    };
+    DateTime::parse_from_rfc3339(raw)
+        .map(|dt| Some(dt.with_timezone(&Utc)))
+        .map_err(|_| {
+            err(
+                StatusCode::BAD_REQUEST,
+                &format!("{name} must be RFC3339, got {raw:?}"),
+            )
+        })
+}
+
+fn trimmed(s: Option<String>) -> Option<String> {
+    s.map(|s| s.trim().to_owned()).filter(|s| !s.is_empty())
+}
+
+/// Public mailboxes only list what they would show, whatever `since` asks.
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/messages",
+    tag = "api",
+    summary = "Message summaries, a page at a time",
+    params(("address" = String, Path), ListQuery),
+    responses((status = 200, description = "OK", body = ListResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn list_summaries(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<ListQuery>,
+) -> Result<Json<ListResponse>, Response> {
+    let after = q
+        .after
+        .as_deref()
+        .map(str::parse::<SummaryCursor>)
+        .transpose()
+        .map_err(|e| err(StatusCode::BAD_REQUEST, &format!("invalid cursor: {e}")))?;
+    let limit = q.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let since = parse_time("since", q.since.as_deref())?;
+    let until = parse_time("until", q.until.as_deref())?;
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let oldest_visible =
+        MailboxPolicy::of(&temp, state.public_retention).oldest_visible(Utc::now());
+    let filter = SummaryFilter {
+        from: trimmed(q.from),
+        subject_contains: trimmed(q.subject_contains),
+        since: since.max(oldest_visible),
+        until,
+        unread_only: q.unread_only,
+    };
+    let page = list_email_summaries_by_address(&pool, &temp.temp_email_addr, &filter, after, limit)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(ListResponse {
+        items: page.items,
+        next_cursor: page.next_cursor.map(|c| c.to_string()),
+        total: page.total,
+    }))
+}
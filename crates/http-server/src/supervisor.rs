@@ -0,0 +1,188 @@
+//! Background workers (janitor loops, the new-mail listener, the SMTP
+//! server) run under a [`Supervisor`]: one that panics or fails is restarted
+//! after a growing delay, its state is reported by `/readyz` and as metrics,
+//! and [`Supervisor::shutdown`] stops them all.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerState {
+    Running,
+    /// Failed and waiting to be started again.
+    Restarting,
+    /// Returned `Ok`, e.g. a janitor loop that is disabled. Not restarted.
+    Finished,
+    /// Stopped by shutdown.
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WorkerStatus {
+    pub name: &'static str,
+    pub state: WorkerState,
+    pub restarts: u32,
+    /// Panic message or error of the last failure.
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct Supervisor {
+    workers: Arc<Mutex<BTreeMap<&'static str, WorkerStatus>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            workers: Arc::default(),
+            handles: Arc::default(),
+            shutdown: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Supervisor {
+    /// Runs `make()` as worker `name`, starting it again whenever it panics
+    /// or returns `Err`. The delay before a restart doubles up to a minute
+    /// and starts over once a run has lasted that long.
+    pub fn spawn<F, Fut>(&self, name: &'static str, make: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        set_state(&self.workers, name, WorkerState::Running, None);
+        let workers = Arc::clone(&self.workers);
+        let mut shutdown = self.shutdown.subscribe();
+        let supervise = async move {
+            let mut backoff = INITIAL_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let mut task = tokio::spawn(make());
+                let outcome = tokio::select! {
+                    outcome = &mut task => outcome,
+                    _ = shutdown.wait_for(|stop| *stop) => {
+                        task.abort();
+                        set_state(&workers, name, WorkerState::Stopped, None);
+                        return;
+                    }
+                };
+                let error = match outcome {
+                    Ok(Ok(())) => {
+                        set_state(&workers, name, WorkerState::Finished, None);
+                        return;
+                    }
+                    Ok(Err(e)) => e,
+                    Err(e) if e.is_panic() => {
+                        format!("panicked: {}", panic_message(e.into_panic()))
+                    }
+                    Err(e) => e.to_string(),
+                };
+
+                if started.elapsed() >= MAX_BACKOFF {
+                    backoff = INITIAL_BACKOFF;
+                }
+                tracing::error!(
+                    worker = name,
+                    error = %error,
+                    retry_in_secs = backoff.as_secs(),
+                    "background worker failed"
+                );
+                metrics::counter!("worker_restarts_total", "worker" => name).increment(1);
+                set_state(&workers, name, WorkerState::Restarting, Some(error));
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = shutdown.wait_for(|stop| *stop) => {
+                        set_state(&workers, name, WorkerState::Stopped, None);
+                        return;
+                    }
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                set_state(&workers, name, WorkerState::Running, None);
+            }
+        };
+        let handle = tokio::spawn(supervise);
+        self.handles.lock().expect("supervisor lock").push(handle);
+    }
+
+    pub fn statuses(&self) -> Vec<WorkerStatus> {
+        self.workers
+            .lock()
+            .expect("supervisor lock")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Every worker is running or has finished on its own.
+    pub fn healthy(&self) -> bool {
+        self.statuses()
+            .iter()
+            .all(|w| matches!(w.state, WorkerState::Running | WorkerState::Finished))
+    }
+
+    /// Stops every worker and waits up to `grace` for them to wind down.
+    pub async fn shutdown(&self, grace: Duration) {
+        self.shutdown.send_replace(true);
+        let handles = std::mem::take(&mut *self.handles.lock().expect("supervisor lock"));
+        let all = join_all(handles);
+        if tokio::time::timeout(grace, all).await.is_err() {
+            tracing::warn!(
+                "background workers did not stop within {}s",
+                grace.as_secs()
+            );
+        }
+    }
+}
+
+async fn join_all(handles: Vec<JoinHandle<()>>) {
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+fn set_state(
+    workers: &Mutex<BTreeMap<&'static str, WorkerStatus>>,
+    name: &'static str,
+    state: WorkerState,
+    error: Option<String>,
+) {
+    let up = if state == WorkerState::Running {
+        1.0
+    } else {
+        0.0
+    };
+    metrics::gauge!("worker_up", "worker" => name).set(up);
+    let mut workers = workers.lock().expect("supervisor lock");
+    let status = workers.entry(name).or_insert(WorkerStatus {
+        name,
+        state,
+        restarts: 0,
+        last_error: None,
+    });
+    if state == WorkerState::Restarting {
+        status.restarts += 1;
+    }
+    status.state = state;
+    if error.is_some() {
+        status.last_error = error;
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
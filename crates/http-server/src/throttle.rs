@@ -0,0 +1,112 @@
+//! Soft bandwidth limit for attachment, raw-message and export downloads,
+//! so a few large downloads cannot saturate a small host's uplink. Each
+//! response gets its own token bucket; there is no global limit.
+
+use axum::body::{Body, Bytes};
+use std::future::Future;
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::time::{Instant, Sleep};
+use tokio_stream::Stream;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Smallest amount worth waking up for, so slow rates do not send tiny chunks.
+const MIN_CHUNK: u64 = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth {
+    /// Sustained rate.
+    pub bytes_per_sec: u64,
+    /// Sent at full speed before the rate applies, so small files are not
+    /// slowed down at all.
+    pub burst: u64,
+}
+
+/// Response body for `content`, limited to `limit` when set.
+pub fn body(content: Vec<u8>, limit: Option<Bandwidth>) -> Body {
+    match limit {
+        Some(limit) => Body::from_stream(ReaderStream::new(Throttled::new(
+            Cursor::new(content),
+            limit,
+        ))),
+        None => Body::from(content),
+    }
+}
+
+/// Response body for content produced as it is sent, limited to `limit`
+/// when set.
+pub fn stream<S>(chunks: S, limit: Option<Bandwidth>) -> Body
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + Unpin + 'static,
+{
+    match limit {
+        Some(limit) => Body::from_stream(ReaderStream::new(Throttled::new(
+            StreamReader::new(chunks),
+            limit,
+        ))),
+        None => Body::from_stream(chunks),
+    }
+}
+
+/// Reads from `inner` no faster than the bucket refills.
+pub struct Throttled<R> {
+    inner: R,
+    limit: Bandwidth,
+    tokens: f64,
+    refilled_at: Instant,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<R> Throttled<R> {
+    pub fn new(inner: R, limit: Bandwidth) -> Self {
+        Self {
+            inner,
+            limit,
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+            sleep: None,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let earned =
+            now.duration_since(self.refilled_at).as_secs_f64() * self.limit.bytes_per_sec as f64;
+        self.tokens = (self.tokens + earned).min(self.limit.burst.max(MIN_CHUNK) as f64);
+        self.refilled_at = now;
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Throttled<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                this.sleep = None;
+            }
+            this.refill();
+            let wanted = MIN_CHUNK.min(buf.remaining() as u64).max(1) as f64;
+            if this.tokens >= wanted {
+                break;
+            }
+            let wait = (wanted - this.tokens) / this.limit.bytes_per_sec.max(1) as f64;
+            this.sleep = Some(Box::pin(tokio::time::sleep(Duration::from_secs_f64(wait))));
+        }
+
+        let allowed = (this.tokens as usize).min(buf.remaining());
+        let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        let read = limited.filled().len();
+        buf.advance(read);
+        this.tokens -= read as f64;
+        Poll::Ready(Ok(()))
+    }
+}
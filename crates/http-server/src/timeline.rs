@@ -0,0 +1,105 @@
+//! Activity of a mailbox over time, for charting long-lived test mailboxes:
+//! message counts per hour or day, and the busiest senders, over a window
+//! ending now.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use db::{
+    find_temporary_email_by_addr, mailbox_activity_by_period, mailbox_activity_by_sender,
+    PeriodActivity, SenderActivity, TimelineBucket,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::api::{db_error, err, not_live, require_pool};
+use crate::policy::MailboxPolicy;
+use crate::{lookup, AppState};
+
+const DEFAULT_DAYS: u32 = 7;
+/// Longest window per bucket, which bounds the number of periods returned.
+const MAX_HOURLY_DAYS: u32 = 31;
+const MAX_DAILY_DAYS: u32 = 366;
+/// Senders listed, busiest first.
+const MAX_SENDERS: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct TimelineQuery {
+    #[serde(default)]
+    pub bucket: TimelineBucket,
+    pub days: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineResponse {
+    pub temp_email_addr: String,
+    pub bucket: TimelineBucket,
+    /// Start of the window; public mailboxes only count mail they still show.
+    pub since: DateTime<Utc>,
+    pub total: i64,
+    /// Periods without mail are left out.
+    pub periods: Vec<PeriodActivity>,
+    pub senders: Vec<SenderActivity>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/timeline",
+    tag = "api",
+    summary = "Messages per period and per sender",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn mailbox_timeline(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Query(q): Query<TimelineQuery>,
+) -> Result<Json<TimelineResponse>, Response> {
+    let max_days = match q.bucket {
+        TimelineBucket::Hour => MAX_HOURLY_DAYS,
+        TimelineBucket::Day => MAX_DAILY_DAYS,
+    };
+    let days = q.days.unwrap_or(DEFAULT_DAYS);
+    if days == 0 || days > max_days {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("days must be between 1 and {max_days}"),
+        ));
+    }
+
+    let pool = require_pool(&state).await?;
+    let addr = address.trim().to_ascii_lowercase();
+    let temp = find_temporary_email_by_addr(&pool, &addr)
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot)
+        .ok_or_else(lookup::not_found)?;
+    if !temp.is_live() {
+        return Err(not_live(&temp));
+    }
+
+    let now = Utc::now();
+    let window_start = now - chrono::Duration::days(i64::from(days));
+    let since = MailboxPolicy::of(&temp, state.public_retention)
+        .oldest_visible(now)
+        .map_or(window_start, |oldest| oldest.max(window_start));
+
+    let periods = mailbox_activity_by_period(&pool, temp.id, q.bucket, since)
+        .await
+        .map_err(db_error)?;
+    let senders = mailbox_activity_by_sender(&pool, temp.id, since, MAX_SENDERS)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(TimelineResponse {
+        temp_email_addr: temp.temp_email_addr,
+        bucket: q.bucket,
+        since,
+        total: periods.iter().map(|p| p.count).sum(),
+        periods,
+        senders,
+    }))
+}
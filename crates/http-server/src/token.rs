@@ -0,0 +1,52 @@
+//! Mailbox access tokens. Tokens are 256 random bits, so a keyed BLAKE2b hash
+//! is enough at rest; a slow password hash like Argon2 would only add latency
+//! to every request without making a random token any harder to guess.
+
+use axum::http::{header, HeaderMap};
+use blake2::digest::consts::U32;
+use blake2::digest::Mac;
+use blake2::Blake2bMac;
+use rand::Rng;
+use std::collections::HashMap;
+
+type TokenMac = Blake2bMac<U32>;
+
+/// Longest pepper BLAKE2b accepts as a key.
+pub const MAX_PEPPER_LEN: usize = 64;
+
+pub fn generate() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn mac(pepper: &[u8], token: &str) -> TokenMac {
+    let mut mac = TokenMac::new_with_salt_and_personal(pepper, &[], b"fake-email-token")
+        .expect("pepper length is validated by config");
+    mac.update(token.as_bytes());
+    mac
+}
+
+pub fn hash(pepper: &[u8], token: &str) -> Vec<u8> {
+    mac(pepper, token).finalize().into_bytes().to_vec()
+}
+
+/// Constant-time comparison of `token` against a stored hash.
+pub fn verify(pepper: &[u8], token: &str, stored: &[u8]) -> bool {
+    mac(pepper, token).verify_slice(stored).is_ok()
+}
+
+/// `Authorization: Bearer …`, else the `token` query parameter for clients
+/// that cannot set headers, such as `EventSource` or a download link.
+pub(crate) fn presented<'a>(
+    headers: &'a HeaderMap,
+    query: &'a HashMap<String, String>,
+) -> Option<&'a str> {
+    bearer(headers).or_else(|| query.get("token").map(String::as_str))
+}
+
+pub(crate) fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
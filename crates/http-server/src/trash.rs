@@ -0,0 +1,126 @@
+//! Deleting mail, and the trash that makes it undoable. A deleted message is
+//! hidden from the mailbox but listed under `/api/email/:address/trash` and
+//! can be restored until the expiry sweep purges it, once it has been there
+//! for [`JanitorConfig::trash_retention`](crate::janitor::JanitorConfig).
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::{list_trashed_emails, restore_received_emails, trash_received_emails, ReceivedEmail};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::db_error;
+use crate::lookup::not_found;
+use crate::watch::live_mailbox;
+use crate::AppState;
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = TrashResult)]
+pub struct TrashResponse {
+    pub trashed: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RestoreBody {
+    /// Messages to restore; everything in the trash when left out.
+    pub ids: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = RestoreResult)]
+pub struct RestoreResponse {
+    pub restored: u64,
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/{email_id}",
+    tag = "api",
+    summary = "Move a message to the trash",
+    params(("address" = String, Path), ("email_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn delete_email(
+    State(state): State<AppState>,
+    Path((address, email_id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let trashed = trash_received_emails(&pool, temp.id, Some(&[email_id]))
+        .await
+        .map_err(db_error)?;
+    if trashed == 0 {
+        return Err(not_found());
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Empties the mailbox into its trash.
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/messages",
+    tag = "api",
+    summary = "Move every message to the trash",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = TrashResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn delete_all_emails(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<TrashResponse>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let trashed = trash_received_emails(&pool, temp.id, None)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(addr = %temp.temp_email_addr, trashed, "mailbox emptied into trash");
+    Ok(Json(TrashResponse { trashed }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/trash",
+    tag = "api",
+    summary = "Deleted messages not yet purged",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = Vec<ReceivedEmail>)),
+    security(("mailbox" = []))
+)]
+pub async fn list_trash(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<ReceivedEmail>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let trashed = list_trashed_emails(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(trashed))
+}
+
+/// The body may be left out to restore the whole trash.
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/trash/restore",
+    tag = "api",
+    summary = "Restore deleted messages",
+    params(("address" = String, Path)),
+    responses((status = 200, description = "OK", body = RestoreResponse)),
+    security(("mailbox" = []))
+)]
+pub async fn restore(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    body: Option<Json<RestoreBody>>,
+) -> Result<Json<RestoreResponse>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let ids = body.and_then(|Json(b)| b.ids);
+    let restored = restore_received_emails(&pool, temp.id, ids.as_deref())
+        .await
+        .map_err(db_error)?;
+    Ok(Json(RestoreResponse { restored }))
+}
@@ -0,0 +1,191 @@
+//! Watch rules of a mailbox: a substring or regex that incoming mail is
+//! matched against, and the webhook a match is POSTed to. Matching happens in
+//! the SMTP server; see `smtp::watch` for the event sent.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use db::{
+    delete_watch_rule, find_temporary_email_by_addr, insert_watch_rule, list_watch_rules,
+    TemporaryEmail, WatchKind, WatchRule,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use smtp::watch::{compile, MAX_PATTERN_LEN};
+use sqlx::postgres::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use crate::api::{db_error, err, require_pool};
+use crate::{lookup, AppState};
+
+/// Rules one mailbox may have; each is evaluated for every message.
+pub const MAX_WATCH_RULES: usize = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWatchBody {
+    #[serde(default)]
+    pub kind: WatchKind,
+    pub pattern: String,
+    pub webhook_url: String,
+}
+
+pub(crate) async fn live_mailbox(
+    state: &AppState,
+    address: &str,
+) -> Result<(PgPool, TemporaryEmail), Response> {
+    let pool = require_pool(state).await?;
+    let temp = find_temporary_email_by_addr(&pool, &address.trim().to_ascii_lowercase())
+        .await
+        .map_err(db_error)?
+        .filter(|t| !t.is_honeypot && t.is_live())
+        .ok_or_else(lookup::not_found)?;
+    Ok((pool, temp))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/watches",
+    tag = "api",
+    summary = "List watch rules",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn list_watches(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<WatchRule>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let rules = list_watch_rules(&pool, temp.id).await.map_err(db_error)?;
+    Ok(Json(rules))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/watches",
+    tag = "api",
+    summary = "Add a watch rule",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn create_watch(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<CreateWatchBody>,
+) -> Result<(StatusCode, Json<WatchRule>), Response> {
+    if body.pattern.is_empty() {
+        return Err(err(StatusCode::BAD_REQUEST, "pattern is required"));
+    }
+    if body.pattern.chars().count() > MAX_PATTERN_LEN {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            &format!("pattern must be at most {MAX_PATTERN_LEN} characters"),
+        ));
+    }
+    if compile(body.kind, &body.pattern).is_err() {
+        return Err(err(
+            StatusCode::BAD_REQUEST,
+            "pattern is not a valid regular expression",
+        ));
+    }
+    let webhook_url = body.webhook_url.trim();
+    check_webhook_url(webhook_url, state.allow_private_webhooks)
+        .map_err(|msg| err(StatusCode::BAD_REQUEST, msg))?;
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let existing = list_watch_rules(&pool, temp.id).await.map_err(db_error)?;
+    if existing.len() >= MAX_WATCH_RULES {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can have at most {MAX_WATCH_RULES} watch rules"),
+        ));
+    }
+    let rule = insert_watch_rule(&pool, temp.id, body.kind, &body.pattern, webhook_url)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(addr = %temp.temp_email_addr, rule = %rule.id, "watch rule created");
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/watches/{watch_id}",
+    tag = "api",
+    summary = "Remove a watch rule",
+    params(("address" = String, Path), ("watch_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn delete_watch(
+    State(state): State<AppState>,
+    Path((address, id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let deleted = delete_watch_rule(&pool, temp.id, id)
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown watch rule"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Mailbox owners are not trusted with the server's network: unless
+/// `allow_private` is set, loopback, private and link-local IP literals and
+/// `localhost` are refused. Names are not resolved, so a public name that
+/// points inward still gets through; firewall the server if that matters.
+pub(crate) fn check_webhook_url(url: &str, allow_private: bool) -> Result<(), &'static str> {
+    let parsed = Url::parse(url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or("webhook_url must be an http(s) URL")?;
+    if !allow_private && points_inward(&parsed) {
+        return Err("webhook_url must not point to a private network");
+    }
+    Ok(())
+}
+
+/// Whether `url`'s host is `localhost` or a loopback, private or link-local
+/// IP literal. Names are not resolved.
+pub(crate) fn points_inward(url: &Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_internal(ip),
+        Err(_) => {
+            let name = host.trim_end_matches('.').to_ascii_lowercase();
+            name.is_empty() || name == "localhost" || name.ends_with(".localhost")
+        }
+    }
+}
+
+pub(crate) fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 100.64.0.0/10, carrier-grade NAT.
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_internal(IpAddr::V4(v4)),
+            None => {
+                let first = ip.segments()[0];
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // fc00::/7, unique local.
+                    || first & 0xfe00 == 0xfc00
+                    // fe80::/10, link-local.
+                    || first & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
@@ -0,0 +1,333 @@
+//! Webhook subscriptions: a callback URL that is sent every message an
+//! address receives, e.g. to drive a CI pipeline. Storing a message queues a
+//! `webhook_delivery` per subscription in the same transaction, and
+//! [`run_delivery_worker`] POSTs them as JSON signed with the subscription's
+//! secret, retrying failures with exponential backoff.
+//!
+//! The signature is `X-Webhook-Signature: sha256=<hex>`, HMAC-SHA256 over
+//! `<X-Webhook-Timestamp>.<body>`; receivers should also reject stale
+//! timestamps.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Json,
+};
+use chrono::Utc;
+use db::{
+    claim_webhook_deliveries, complete_webhook_delivery, delete_webhook_subscription,
+    fail_webhook_delivery, find_received_email_by_id, insert_webhook_subscription,
+    list_webhook_subscriptions, PendingWebhookDelivery, ReceivedEmail, WebhookSubscription,
+    WEBHOOK_PRIORITY_CHANNEL,
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::postgres::{PgListener, PgPool};
+use std::time::Duration;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+use crate::api::{db_error, err};
+use crate::retry::RetryPolicy;
+use crate::watch::{check_webhook_url, live_mailbox};
+use crate::{token, AppState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Subscriptions one mailbox may have.
+pub const MAX_WEBHOOKS: usize = 5;
+/// Deliveries claimed and sent concurrently per round.
+const BATCH_SIZE: i64 = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct WebhookConfig {
+    /// How often the queue is checked for due deliveries.
+    pub poll_interval: Duration,
+    pub retry: RetryPolicy,
+    /// Deadline for one POST.
+    pub timeout: Duration,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            retry: RetryPolicy {
+                max_attempts: 8,
+                retry_base: Duration::from_secs(30),
+                retry_max: Duration::from_secs(60 * 60),
+            },
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookBody {
+    pub url: String,
+}
+
+/// Only returned on creation: the secret is not shown again.
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub subscription: WebhookSubscription,
+    pub secret: String,
+}
+
+/// What a subscription's URL is sent for every message.
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub event: &'static str,
+    pub delivery_id: Uuid,
+    pub subscription_id: Uuid,
+    /// The message as `poll` returns it.
+    pub email: &'a ReceivedEmail,
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/email/{address}/webhooks",
+    tag = "api",
+    summary = "List webhook subscriptions",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+) -> Result<Json<Vec<WebhookSubscription>>, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let subscriptions = list_webhook_subscriptions(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    Ok(Json(subscriptions))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/email/{address}/webhooks",
+    tag = "api",
+    summary = "Subscribe a URL to new mail",
+    params(("address" = String, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Path(address): Path<String>,
+    Json(body): Json<CreateWebhookBody>,
+) -> Result<(StatusCode, Json<CreatedWebhook>), Response> {
+    let url = body.url.trim();
+    check_webhook_url(url, state.allow_private_webhooks)
+        .map_err(|msg| err(StatusCode::BAD_REQUEST, msg))?;
+
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let existing = list_webhook_subscriptions(&pool, temp.id)
+        .await
+        .map_err(db_error)?;
+    if existing.len() >= MAX_WEBHOOKS {
+        return Err(err(
+            StatusCode::CONFLICT,
+            &format!("a mailbox can have at most {MAX_WEBHOOKS} webhooks"),
+        ));
+    }
+    let secret = token::generate();
+    let subscription = insert_webhook_subscription(&pool, temp.id, url, &secret)
+        .await
+        .map_err(db_error)?;
+    tracing::info!(
+        addr = %temp.temp_email_addr,
+        webhook = %subscription.id,
+        "webhook subscription created"
+    );
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedWebhook {
+            subscription,
+            secret,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/email/{address}/webhooks/{webhook_id}",
+    tag = "api",
+    summary = "Remove a webhook subscription",
+    params(("address" = String, Path), ("webhook_id" = Uuid, Path)),
+    responses((status = "default", description = "See README.md")),
+    security(("mailbox" = []))
+)]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Path((address, id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, Response> {
+    let (pool, temp) = live_mailbox(&state, &address).await?;
+    let deleted = delete_webhook_subscription(&pool, temp.id, id)
+        .await
+        .map_err(db_error)?;
+    if !deleted {
+        return Err(err(StatusCode::NOT_FOUND, "unknown webhook"));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `sha256=<hex>` over `<timestamp>.<body>`.
+pub fn signature(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac accepts any key length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
+
+/// Sends due deliveries every `poll_interval`, forever, and at once when a
+/// priority delivery is queued.
+pub async fn run_delivery_worker(pool: PgPool, config: WebhookConfig) {
+    let client = reqwest::Client::builder()
+        .timeout(config.timeout)
+        .build()
+        .unwrap_or_default();
+    // Long enough for the slowest delivery of a batch to finish.
+    let lease = config.timeout + Duration::from_secs(30);
+
+    let mut ticker = tokio::time::interval(config.poll_interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut priority = priority_listener(&pool).await;
+    loop {
+        let woken = match &mut priority {
+            Some(listener) => tokio::select! {
+                _ = ticker.tick() => Ok(()),
+                notified = listener.recv() => notified.map(drop),
+            },
+            None => {
+                ticker.tick().await;
+                Ok(())
+            }
+        };
+        if let Err(e) = woken {
+            tracing::warn!(error = %e, "lost the priority webhook listener, polling only");
+            priority = None;
+        }
+        loop {
+            let batch = match claim_webhook_deliveries(&pool, BATCH_SIZE, lease).await {
+                Ok(batch) => batch,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to claim webhook deliveries");
+                    break;
+                }
+            };
+            let full = batch.len() as i64 == BATCH_SIZE;
+            let mut sends = JoinSet::new();
+            for delivery in batch {
+                let (pool, client) = (pool.clone(), client.clone());
+                sends.spawn(async move { deliver(&pool, &client, &config, delivery).await });
+            }
+            while sends.join_next().await.is_some() {}
+            if !full {
+                break;
+            }
+        }
+    }
+}
+
+/// Listens for [`WEBHOOK_PRIORITY_CHANNEL`]; without it deliveries still go
+/// out on the next poll.
+async fn priority_listener(pool: &PgPool) -> Option<PgListener> {
+    let listen = async {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(WEBHOOK_PRIORITY_CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    };
+    match listen.await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to listen for priority webhooks");
+            None
+        }
+    }
+}
+
+async fn deliver(
+    pool: &PgPool,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    delivery: PendingWebhookDelivery,
+) {
+    let attempt = u32::try_from(delivery.attempts).unwrap_or(0) + 1;
+    let result = match find_received_email_by_id(pool, delivery.received_email_id).await {
+        Ok(Some(email)) => send(client, &delivery, &email).await,
+        // Deleted since; the delivery goes with it.
+        Ok(None) => return,
+        Err(e) => Err(e.to_string()),
+    };
+    let recorded = match result {
+        Ok(()) => {
+            metrics::counter!("webhook_deliveries_total", "outcome" => "delivered").increment(1);
+            complete_webhook_delivery(pool, delivery.id).await
+        }
+        Err(error) if !config.retry.allows_retry(attempt) => {
+            tracing::warn!(delivery = %delivery.id, attempt, %error, "webhook delivery abandoned");
+            metrics::counter!("webhook_deliveries_total", "outcome" => "abandoned").increment(1);
+            fail_webhook_delivery(pool, delivery.id, &error, None).await
+        }
+        Err(error) => {
+            let retry_in = config.retry.backoff(attempt);
+            tracing::debug!(
+                delivery = %delivery.id,
+                attempt,
+                %error,
+                retry_in_secs = retry_in.as_secs(),
+                "webhook delivery failed"
+            );
+            metrics::counter!("webhook_deliveries_total", "outcome" => "retried").increment(1);
+            fail_webhook_delivery(pool, delivery.id, &error, Some(retry_in)).await
+        }
+    };
+    if let Err(e) = recorded {
+        tracing::error!(delivery = %delivery.id, error = %e, "failed to record webhook delivery");
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    delivery: &PendingWebhookDelivery,
+    email: &ReceivedEmail,
+) -> Result<(), String> {
+    let body = serde_json::to_vec(&WebhookPayload {
+        event: "email.received",
+        delivery_id: delivery.id,
+        subscription_id: delivery.subscription_id,
+        email,
+    })
+    .map_err(|e| e.to_string())?;
+    let timestamp = Utc::now().timestamp();
+    let res = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery.id.to_string())
+        .header("X-Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "X-Webhook-Signature",
+            signature(&delivery.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook answered {}", res.status()))
+    }
+}
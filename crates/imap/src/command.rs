@@ -0,0 +1,275 @@
+//! Command syntax: a tag, a name, and arguments that are atoms, quoted
+//! strings, literals or parenthesized lists.
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    /// Also bracketed fetch items such as `BODY[HEADER.FIELDS (FROM)]<0.512>`,
+    /// spaces and all.
+    Atom(String),
+    /// Quoted string or literal.
+    String(Vec<u8>),
+    List(Vec<Arg>),
+}
+
+impl Arg {
+    pub fn atom(&self) -> Option<&str> {
+        match self {
+            Self::Atom(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// An atom or string (IMAP's `astring`) as text.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Self::Atom(s) => Some(s.clone()),
+            Self::String(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            Self::List(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub tag: String,
+    /// Upper-cased.
+    pub name: String,
+    pub args: Vec<Arg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// `None` when not even the tag could be read.
+    pub tag: Option<String>,
+    pub message: &'static str,
+}
+
+/// Parses one command. `line` has no final line break but still holds the
+/// `{n}` markers and line breaks of any literals read with it.
+pub fn parse(line: &[u8]) -> Result<Command, ParseError> {
+    let mut parser = Parser {
+        input: line,
+        pos: 0,
+    };
+    let tag = parser.word();
+    if tag.is_empty() || tag.iter().any(|&b| b == b'+' || !is_atom_char(b)) {
+        return Err(ParseError {
+            tag: None,
+            message: "invalid tag",
+        });
+    }
+    let tag = String::from_utf8_lossy(tag).into_owned();
+    parser.skip_spaces();
+    let name = parser.word().to_ascii_uppercase();
+    if name.is_empty() {
+        return Err(ParseError {
+            tag: Some(tag),
+            message: "missing command",
+        });
+    }
+    match parser.args(false) {
+        Ok(args) => Ok(Command {
+            tag,
+            name: String::from_utf8_lossy(&name).into_owned(),
+            args,
+        }),
+        Err(message) => Err(ParseError {
+            tag: Some(tag),
+            message,
+        }),
+    }
+}
+
+/// Length of the literal announced at the end of `line` (`{42}`), which
+/// the client sends once it has been told to go ahead.
+pub fn literal_length(line: &[u8]) -> Option<usize> {
+    let open = line.iter().rposition(|&b| b == b'{')?;
+    let digits = line[open + 1..].strip_suffix(b"}")?;
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+fn is_atom_char(b: u8) -> bool {
+    b > b' ' && b < 0x7f && !b"(){\"".contains(&b)
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    /// Everything up to the next space.
+    fn word(&mut self) -> &'a [u8] {
+        let start = self.pos;
+        while self.peek().is_some_and(|b| b != b' ') {
+            self.pos += 1;
+        }
+        &self.input[start..self.pos]
+    }
+
+    fn args(&mut self, nested: bool) -> Result<Vec<Arg>, &'static str> {
+        let mut args = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None if nested => return Err("unterminated list"),
+                None => return Ok(args),
+                Some(b')') if nested => {
+                    self.pos += 1;
+                    return Ok(args);
+                }
+                Some(b')') => return Err("unexpected )"),
+                Some(b'(') => {
+                    self.pos += 1;
+                    args.push(Arg::List(self.args(true)?));
+                }
+                Some(b'"') => args.push(Arg::String(self.quoted()?)),
+                Some(b'{') => args.push(Arg::String(self.literal()?)),
+                Some(_) => args.push(Arg::Atom(self.atom()?)),
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Vec<u8>, &'static str> {
+        self.pos += 1;
+        let mut s = Vec::new();
+        loop {
+            match self.peek() {
+                None => return Err("unterminated string"),
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    let escaped = self.input.get(self.pos + 1).ok_or("unterminated string")?;
+                    s.push(*escaped);
+                    self.pos += 2;
+                }
+                Some(b) => {
+                    s.push(b);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<Vec<u8>, &'static str> {
+        let rest = &self.input[self.pos..];
+        let close = rest
+            .iter()
+            .position(|&b| b == b'}')
+            .ok_or("invalid literal")?;
+        let len: usize = std::str::from_utf8(&rest[1..close])
+            .ok()
+            .and_then(|n| n.trim_end_matches('+').parse().ok())
+            .ok_or("invalid literal")?;
+        let start = self.pos + close + 1;
+        let start = if self.input[start..].starts_with(b"\r\n") {
+            start + 2
+        } else if self.input[start..].starts_with(b"\n") {
+            start + 1
+        } else {
+            return Err("invalid literal");
+        };
+        let end = start.checked_add(len).ok_or("invalid literal")?;
+        let content = self.input.get(start..end).ok_or("truncated literal")?;
+        self.pos = end;
+        Ok(content.to_vec())
+    }
+
+    /// An atom, taking a `[...]` section with whatever it contains.
+    fn atom(&mut self) -> Result<String, &'static str> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            if b == b'[' {
+                let close = self.input[self.pos..]
+                    .iter()
+                    .position(|&b| b == b']')
+                    .ok_or("unterminated [")?;
+                self.pos += close + 1;
+            } else if is_atom_char(b) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.pos == start {
+            return Err("invalid character");
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+}
+
+/// Message numbers or UIDs such as `1:4,7,9:*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceSet(Vec<(Bound, Bound)>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Bound {
+    Number(u32),
+    /// `*`: the highest number in use.
+    Last,
+}
+
+impl Bound {
+    fn resolve(self, last: u32) -> u32 {
+        match self {
+            Self::Number(n) => n,
+            Self::Last => last,
+        }
+    }
+}
+
+impl FromStr for Bound {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "*" => Ok(Self::Last),
+            _ => match s.parse::<u32>() {
+                Ok(n) if n > 0 && s.bytes().all(|b| b.is_ascii_digit()) => Ok(Self::Number(n)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+impl SequenceSet {
+    /// Whether `n` is in the set, with `*` standing for `last`.
+    pub fn contains(&self, n: u32, last: u32) -> bool {
+        self.0.iter().any(|&(a, b)| {
+            let (a, b) = (a.resolve(last), b.resolve(last));
+            a.min(b) <= n && n <= a.max(b)
+        })
+    }
+}
+
+impl FromStr for SequenceSet {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|range| match range.split_once(':') {
+                Some((a, b)) => Ok((a.parse()?, b.parse()?)),
+                None => range.parse().map(|n| (n, n)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self(ranges))
+    }
+}
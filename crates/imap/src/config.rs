@@ -0,0 +1,41 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Checks an access token against a mailbox's stored hash. The HTTP server
+/// passes its own check, so a token works the same over both.
+pub type TokenVerifier = Arc<dyn Fn(&str, &[u8]) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+pub struct ImapConfig {
+    /// Host name in the greeting.
+    pub banner_domain: String,
+    pub verify_token: TokenVerifier,
+    /// Mail older than this is hidden in public mailboxes, as over HTTP.
+    pub public_retention: Duration,
+    /// Connections without a command for this long are closed. RFC 3501
+    /// asks for at least 30 minutes.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ImapConfig {
+    /// Accepts no token, so only mailboxes without one can be opened.
+    fn default() -> Self {
+        Self {
+            banner_domain: "fake-email".into(),
+            verify_token: Arc::new(|_, _| false),
+            public_retention: Duration::from_secs(60 * 60),
+            idle_timeout: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl fmt::Debug for ImapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImapConfig")
+            .field("banner_domain", &self.banner_domain)
+            .field("public_retention", &self.public_retention)
+            .field("idle_timeout", &self.idle_timeout)
+            .finish_non_exhaustive()
+    }
+}
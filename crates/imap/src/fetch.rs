@@ -0,0 +1,457 @@
+//! FETCH data items and how each is answered.
+
+use crate::command::Arg;
+use crate::mailbox::Message;
+use crate::mime::{self, Part};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Flags,
+    Uid,
+    InternalDate,
+    Size,
+    Envelope,
+    /// `BODYSTRUCTURE`, or `BODY` without extension data.
+    BodyStructure {
+        extended: bool,
+    },
+    Section(Section),
+}
+
+impl Item {
+    /// Whether answering it needs the message itself.
+    fn needs_content(&self, message: &Message) -> bool {
+        match self {
+            Self::Flags | Self::Uid | Self::InternalDate => false,
+            Self::Size => message.size.is_none(),
+            Self::Envelope | Self::BodyStructure { .. } | Self::Section(_) => true,
+        }
+    }
+
+    /// Fetching it marks the message `\Seen`.
+    pub fn sets_seen(&self) -> bool {
+        matches!(self, Self::Section(s) if !s.peek)
+    }
+}
+
+pub fn needs_content(items: &[Item], message: &Message) -> bool {
+    items.iter().any(|item| item.needs_content(message))
+}
+
+/// A `BODY[...]` item or one of its `RFC822` aliases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    /// Name in the response, such as `BODY[1.MIME]` or `RFC822.HEADER`.
+    label: String,
+    peek: bool,
+    /// Part numbers; empty for the whole message.
+    path: Vec<u32>,
+    text: SectionText,
+    /// `<start.length>`
+    partial: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SectionText {
+    All,
+    Header,
+    Text,
+    Mime,
+    Fields { names: Vec<String>, exclude: bool },
+}
+
+impl Section {
+    fn alias(label: &str, peek: bool, text: SectionText) -> Item {
+        Item::Section(Self {
+            label: label.into(),
+            peek,
+            path: Vec::new(),
+            text,
+            partial: None,
+        })
+    }
+
+    /// `BODY[<section>]<partial>` or `BODY.PEEK[...]`, upper-cased.
+    fn parse(item: &str) -> Result<Self, &'static str> {
+        let (peek, rest) = match item.strip_prefix("BODY.PEEK[") {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                item.strip_prefix("BODY[").ok_or("unknown fetch item")?,
+            ),
+        };
+        let (spec, tail) = rest.split_once(']').ok_or("invalid section")?;
+        let partial = if tail.is_empty() {
+            None
+        } else {
+            let range = tail
+                .strip_prefix('<')
+                .and_then(|t| t.strip_suffix('>'))
+                .and_then(|t| t.split_once('.'))
+                .ok_or("invalid partial range")?;
+            let start = range.0.parse().map_err(|_| "invalid partial range")?;
+            let length = range.1.parse().map_err(|_| "invalid partial range")?;
+            Some((start, length))
+        };
+        let label = match partial {
+            Some((start, _)) => format!("BODY[{spec}]<{start}>"),
+            None => format!("BODY[{spec}]"),
+        };
+
+        let mut path = Vec::new();
+        let mut rest = spec;
+        while let Some(number) = rest
+            .split('.')
+            .next()
+            .filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        {
+            match number.parse::<u32>() {
+                Ok(n) if n > 0 => path.push(n),
+                _ => return Err("invalid section"),
+            }
+            rest = rest[number.len()..].strip_prefix('.').unwrap_or("");
+        }
+        let text = match rest {
+            "" => SectionText::All,
+            "HEADER" => SectionText::Header,
+            "TEXT" => SectionText::Text,
+            "MIME" if !path.is_empty() => SectionText::Mime,
+            _ => {
+                let (names, exclude) = match rest.strip_prefix("HEADER.FIELDS.NOT") {
+                    Some(names) => (names, true),
+                    None => (
+                        rest.strip_prefix("HEADER.FIELDS")
+                            .ok_or("invalid section")?,
+                        false,
+                    ),
+                };
+                let names = names
+                    .trim()
+                    .strip_prefix('(')
+                    .and_then(|n| n.strip_suffix(')'))
+                    .ok_or("invalid section")?
+                    .split_whitespace()
+                    .map(|n| n.trim_matches('"').to_owned())
+                    .collect();
+                SectionText::Fields { names, exclude }
+            }
+        };
+        Ok(Self {
+            label,
+            peek,
+            path,
+            text,
+            partial,
+        })
+    }
+
+    /// The bytes the section names; empty for parts that do not exist.
+    fn extract(&self, raw: &[u8], message: &Part) -> Vec<u8> {
+        let content = if self.path.is_empty() {
+            match &self.text {
+                SectionText::All => Some(raw.to_vec()),
+                text => section_of(message, text),
+            }
+        } else {
+            message
+                .locate(&self.path)
+                .and_then(|part| match &self.text {
+                    SectionText::All => Some(part.body.to_vec()),
+                    SectionText::Mime => Some(part.header.to_vec()),
+                    text => section_of(part.message()?, text),
+                })
+        };
+        let content = content.unwrap_or_default();
+        match self.partial {
+            Some((start, length)) => {
+                let start = (start as usize).min(content.len());
+                let end = start.saturating_add(length as usize).min(content.len());
+                content[start..end].to_vec()
+            }
+            None => content,
+        }
+    }
+}
+
+/// `HEADER`, `TEXT` or `HEADER.FIELDS` of a message.
+fn section_of(message: &Part, text: &SectionText) -> Option<Vec<u8>> {
+    match text {
+        SectionText::Header => Some(message.header.to_vec()),
+        SectionText::Text => Some(message.body.to_vec()),
+        SectionText::Fields { names, exclude } => {
+            Some(mime::select_fields(message.header, names, *exclude))
+        }
+        SectionText::All | SectionText::Mime => None,
+    }
+}
+
+/// The requested items: one, a macro (`ALL`, `FAST`, `FULL`) or a list.
+pub fn parse_items(args: &[Arg]) -> Result<Vec<Item>, &'static str> {
+    let names: Vec<&str> = match args {
+        [Arg::Atom(name)] => vec![name],
+        [Arg::List(list)] => list
+            .iter()
+            .map(|a| a.atom().ok_or("invalid fetch item"))
+            .collect::<Result<_, _>>()?,
+        _ => return Err("invalid fetch items"),
+    };
+    let mut items = Vec::new();
+    for name in names {
+        items.extend(parse_item(&name.to_ascii_uppercase())?);
+    }
+    if items.is_empty() {
+        return Err("no fetch items");
+    }
+    Ok(items)
+}
+
+fn parse_item(name: &str) -> Result<Vec<Item>, &'static str> {
+    Ok(match name {
+        "ALL" => vec![Item::Flags, Item::InternalDate, Item::Size, Item::Envelope],
+        "FAST" => vec![Item::Flags, Item::InternalDate, Item::Size],
+        "FULL" => vec![
+            Item::Flags,
+            Item::InternalDate,
+            Item::Size,
+            Item::Envelope,
+            Item::BodyStructure { extended: false },
+        ],
+        "FLAGS" => vec![Item::Flags],
+        "UID" => vec![Item::Uid],
+        "INTERNALDATE" => vec![Item::InternalDate],
+        "RFC822.SIZE" => vec![Item::Size],
+        "ENVELOPE" => vec![Item::Envelope],
+        "BODY" => vec![Item::BodyStructure { extended: false }],
+        "BODYSTRUCTURE" => vec![Item::BodyStructure { extended: true }],
+        "RFC822" => vec![Section::alias("RFC822", false, SectionText::All)],
+        "RFC822.HEADER" => vec![Section::alias("RFC822.HEADER", true, SectionText::Header)],
+        "RFC822.TEXT" => vec![Section::alias("RFC822.TEXT", false, SectionText::Text)],
+        _ => vec![Item::Section(Section::parse(name)?)],
+    })
+}
+
+/// The untagged FETCH response for the message numbered `seq`. `content`
+/// is there when [`needs_content`] says so; `flags_changed` adds `FLAGS`
+/// when fetching set `\Seen` and the client did not ask for them.
+pub fn response(
+    seq: u32,
+    message: &Message,
+    items: &[Item],
+    content: Option<&[u8]>,
+    flags_changed: bool,
+) -> Vec<u8> {
+    let parsed = content.map(Part::parse);
+    let mut out = format!("* {seq} FETCH (").into_bytes();
+    let mut first = true;
+    let mut write = |out: &mut Vec<u8>, item: &Item| {
+        if !std::mem::take(&mut first) {
+            out.push(b' ');
+        }
+        match item {
+            Item::Flags => out.extend(format!("FLAGS {}", message.flags.list()).bytes()),
+            Item::Uid => out.extend(format!("UID {}", message.uid).bytes()),
+            Item::InternalDate => out.extend(
+                message
+                    .email
+                    .received_at
+                    .format("INTERNALDATE \"%d-%b-%Y %H:%M:%S +0000\"")
+                    .to_string()
+                    .bytes(),
+            ),
+            Item::Size => {
+                let size = message
+                    .size
+                    .or_else(|| content.and_then(|c| u32::try_from(c.len()).ok()))
+                    .unwrap_or_default();
+                out.extend(format!("RFC822.SIZE {size}").bytes());
+            }
+            Item::Envelope => {
+                out.extend_from_slice(b"ENVELOPE ");
+                envelope(out, parsed.as_ref().map_or(&[][..], |p| p.header));
+            }
+            Item::BodyStructure { extended } => match &parsed {
+                Some(part) => {
+                    out.extend_from_slice(if *extended {
+                        b"BODYSTRUCTURE "
+                    } else {
+                        b"BODY "
+                    });
+                    body_structure(out, part, *extended);
+                }
+                None => out.extend_from_slice(b"BODY NIL"),
+            },
+            Item::Section(section) => {
+                out.extend(section.label.bytes());
+                out.push(b' ');
+                let bytes = match (content, &parsed) {
+                    (Some(raw), Some(part)) => section.extract(raw, part),
+                    _ => Vec::new(),
+                };
+                literal(out, &bytes);
+            }
+        }
+    };
+    for item in items {
+        write(&mut out, item);
+    }
+    if flags_changed && !items.contains(&Item::Flags) {
+        write(&mut out, &Item::Flags);
+    }
+    out.extend_from_slice(b")\r\n");
+    out
+}
+
+/// `(date subject from sender reply-to to cc bcc in-reply-to message-id)`
+fn envelope(out: &mut Vec<u8>, header: &[u8]) {
+    let field = |name: &str| mime::field(header, name);
+    let from = field("from");
+    out.push(b'(');
+    nstring(out, field("date").as_deref());
+    out.push(b' ');
+    nstring(out, field("subject").as_deref());
+    for list in [
+        from.clone(),
+        field("sender").or_else(|| from.clone()),
+        field("reply-to").or(from),
+        field("to"),
+        field("cc"),
+        field("bcc"),
+    ] {
+        out.push(b' ');
+        address_list(out, list.as_deref());
+    }
+    out.push(b' ');
+    nstring(out, field("in-reply-to").as_deref());
+    out.push(b' ');
+    nstring(out, field("message-id").as_deref());
+    out.push(b')');
+}
+
+fn address_list(out: &mut Vec<u8>, value: Option<&str>) {
+    let addresses = value.map(mime::addresses).unwrap_or_default();
+    if addresses.is_empty() {
+        out.extend_from_slice(b"NIL");
+        return;
+    }
+    out.push(b'(');
+    for address in addresses {
+        out.push(b'(');
+        nstring(out, address.name.as_deref());
+        out.extend_from_slice(b" NIL ");
+        string(out, address.mailbox.as_bytes());
+        out.push(b' ');
+        nstring(out, address.host.as_deref());
+        out.push(b')');
+    }
+    out.push(b')');
+}
+
+fn body_structure(out: &mut Vec<u8>, part: &Part, extended: bool) {
+    let content_type = part.content_type();
+    out.push(b'(');
+    if part.is_multipart() {
+        for child in &part.parts {
+            body_structure(out, child, extended);
+        }
+        out.push(b' ');
+        string(out, content_type.subtype.to_ascii_uppercase().as_bytes());
+        if extended {
+            out.push(b' ');
+            params(out, &content_type.params);
+            out.push(b' ');
+            disposition(out, part);
+        }
+        out.push(b')');
+        return;
+    }
+
+    string(out, content_type.kind.to_ascii_uppercase().as_bytes());
+    out.push(b' ');
+    string(out, content_type.subtype.to_ascii_uppercase().as_bytes());
+    out.push(b' ');
+    params(out, &content_type.params);
+    out.push(b' ');
+    nstring(out, part.field("content-id").as_deref());
+    out.push(b' ');
+    nstring(out, part.field("content-description").as_deref());
+    out.push(b' ');
+    let encoding = part
+        .field("content-transfer-encoding")
+        .filter(|e| !e.is_empty())
+        .map_or("7BIT".into(), |e| e.to_ascii_uppercase());
+    string(out, encoding.as_bytes());
+    out.extend(format!(" {}", part.body.len()).bytes());
+    let lines = part.body.iter().filter(|&&b| b == b'\n').count();
+    if let Some(message) = part.message() {
+        out.push(b' ');
+        envelope(out, message.header);
+        out.push(b' ');
+        body_structure(out, message, extended);
+        out.extend(format!(" {lines}").bytes());
+    } else if content_type.kind == "text" {
+        out.extend(format!(" {lines}").bytes());
+    }
+    if extended {
+        out.extend_from_slice(b" NIL ");
+        disposition(out, part);
+    }
+    out.push(b')');
+}
+
+fn params(out: &mut Vec<u8>, params: &[(String, String)]) {
+    if params.is_empty() {
+        out.extend_from_slice(b"NIL");
+        return;
+    }
+    out.push(b'(');
+    for (i, (name, value)) in params.iter().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+        string(out, name.to_ascii_uppercase().as_bytes());
+        out.push(b' ');
+        string(out, value.as_bytes());
+    }
+    out.push(b')');
+}
+
+fn disposition(out: &mut Vec<u8>, part: &Part) {
+    let Some(value) = part.field("content-disposition") else {
+        out.extend_from_slice(b"NIL");
+        return;
+    };
+    let (kind, disposition_params) = mime::parameterized(&value);
+    out.push(b'(');
+    string(out, kind.to_ascii_uppercase().as_bytes());
+    out.push(b' ');
+    params(out, &disposition_params);
+    out.push(b')');
+}
+
+/// A quoted string when `s` can be one, else a literal.
+pub fn string(out: &mut Vec<u8>, s: &[u8]) {
+    let quotable = s.len() < 1024 && s.iter().all(|&b| (b' '..0x7f).contains(&b));
+    if !quotable {
+        return literal(out, s);
+    }
+    out.push(b'"');
+    for &b in s {
+        if b == b'"' || b == b'\\' {
+            out.push(b'\\');
+        }
+        out.push(b);
+    }
+    out.push(b'"');
+}
+
+fn nstring(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => string(out, s.as_bytes()),
+        None => out.extend_from_slice(b"NIL"),
+    }
+}
+
+fn literal(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend(format!("{{{}}}\r\n", s.len()).bytes());
+    out.extend_from_slice(s);
+}
@@ -0,0 +1,144 @@
+//! A small IMAP4rev1 server (RFC 3501), so a temporary mailbox can be read
+//! in an ordinary mail client. Log in with the address as the user name and
+//! its access token as the password; the only mailbox is `INBOX`. Messages
+//! cannot be added, copied or moved, flags last for the session, and
+//! `EXPUNGE` deletes mail wherever the HTTP API would allow it.
+//!
+//! There is no TLS: run it on localhost or behind a TLS-terminating proxy.
+
+mod command;
+mod config;
+mod fetch;
+mod mailbox;
+mod mime;
+mod search;
+mod session;
+
+pub use config::{ImapConfig, TokenVerifier};
+
+use session::{Session, CAPABILITIES};
+use sqlx::postgres::PgPool;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+const MAX_LINE_LEN: usize = 8192;
+/// Literals only ever carry command arguments, as nothing can be appended.
+const MAX_LITERAL_LEN: usize = 8192;
+
+struct Server {
+    pool: PgPool,
+    config: ImapConfig,
+}
+
+pub async fn run_server(
+    host: &str,
+    port: u16,
+    pool: PgPool,
+    config: ImapConfig,
+) -> Result<(), std::io::Error> {
+    let listener = TcpListener::bind((host, port)).await?;
+    tracing::info!(%host, port, "imap listening");
+    serve(listener, pool, config).await
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    pool: PgPool,
+    config: ImapConfig,
+) -> Result<(), std::io::Error> {
+    let server = Arc::new(Server { pool, config });
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let server = Arc::clone(&server);
+        tokio::spawn(async move {
+            metrics::counter!("imap_sessions_total").increment(1);
+            if let Err(e) = handle_client(socket, &server).await {
+                tracing::debug!(peer = %peer.ip(), error = %e, "imap session ended");
+            }
+        });
+    }
+}
+
+async fn handle_client(socket: TcpStream, server: &Server) -> Result<(), std::io::Error> {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    writer
+        .write_all(
+            format!(
+                "* OK [CAPABILITY {CAPABILITIES}] {} imap ready\r\n",
+                server.config.banner_domain
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    let mut session = Session::default();
+    let mut out = Vec::new();
+    while !session.closing {
+        let read = read_command(&mut reader, &mut writer);
+        let line = match tokio::time::timeout(server.config.idle_timeout, read).await {
+            Ok(line) => line?,
+            Err(_) => {
+                writer.write_all(b"* BYE idle for too long\r\n").await?;
+                break;
+            }
+        };
+        let Some(line) = line else {
+            break;
+        };
+
+        out.clear();
+        match command::parse(&line) {
+            Ok(command) => session.run(server, &command, &mut out).await,
+            Err(e) => out.extend(
+                format!("{} BAD {}\r\n", e.tag.as_deref().unwrap_or("*"), e.message).bytes(),
+            ),
+        }
+        writer.write_all(&out).await?;
+    }
+    Ok(())
+}
+
+/// One command, including any literals, which the client is told to send
+/// as they come up. `None` once the client has hung up.
+async fn read_command(
+    reader: &mut BufReader<OwnedReadHalf>,
+    writer: &mut OwnedWriteHalf,
+) -> Result<Option<Vec<u8>>, std::io::Error> {
+    let mut text = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        let n = (&mut *reader)
+            .take(MAX_LINE_LEN as u64 + 1)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if line.len() > MAX_LINE_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "line exceeds maximum length",
+            ));
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(&line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        text.extend_from_slice(line);
+        let Some(len) = command::literal_length(line) else {
+            return Ok(Some(text));
+        };
+        if len > MAX_LITERAL_LEN {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "literal exceeds maximum length",
+            ));
+        }
+        writer.write_all(b"+ go ahead\r\n").await?;
+        text.extend_from_slice(b"\r\n");
+        let start = text.len();
+        text.resize(start + len, 0);
+        reader.read_exact(&mut text[start..]).await?;
+    }
+}
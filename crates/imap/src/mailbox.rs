@@ -0,0 +1,223 @@
+//! The selected mailbox: a snapshot of its messages in UID order, numbered
+//! from 1, with flags that last as long as the session.
+
+use base64::Engine;
+use db::{fetch_raw_email, ReceivedEmail, TemporaryEmail};
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+
+use crate::command::SequenceSet;
+
+/// System flags, in the order of [`Flags`]' bits.
+const FLAG_NAMES: [&str; 5] = ["\\Seen", "\\Answered", "\\Flagged", "\\Deleted", "\\Draft"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const SEEN: Self = Self(1);
+    pub const DELETED: Self = Self(1 << 3);
+
+    /// `None` for keywords, which are not kept.
+    pub fn parse(name: &str) -> Option<Self> {
+        FLAG_NAMES
+            .iter()
+            .position(|f| f.eq_ignore_ascii_case(name))
+            .map(|i| Self(1 << i))
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// All system flags, for the `FLAGS` response.
+    pub fn all() -> Self {
+        Self((1 << FLAG_NAMES.len()) - 1)
+    }
+
+    /// As a parenthesized list, e.g. `(\Seen \Deleted)`.
+    pub fn list(self) -> String {
+        let names: Vec<&str> = FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| self.0 & (1 << i) != 0)
+            .map(|(_, name)| *name)
+            .collect();
+        format!("({})", names.join(" "))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub uid: u32,
+    pub email: ReceivedEmail,
+    pub flags: Flags,
+    /// Length of its [`content`], once that has been loaded.
+    pub size: Option<u32>,
+}
+
+/// Rows from `list_imap_messages` as messages. UIDs come from a 64-bit
+/// sequence; the few past IMAP's 32 bits could not be addressed anyway.
+pub fn messages(rows: Vec<(i64, ReceivedEmail)>) -> Vec<Message> {
+    rows.into_iter()
+        .filter_map(|(uid, email)| {
+            Some(Message {
+                uid: u32::try_from(uid).ok()?,
+                email,
+                flags: Flags::default(),
+                size: None,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Mailbox {
+    pub temp: TemporaryEmail,
+    /// Opened with `EXAMINE`, or by a login that may not delete.
+    pub read_only: bool,
+    pub messages: Vec<Message>,
+}
+
+impl Mailbox {
+    /// The address's creation time, so a mailbox deleted and created again
+    /// under the same name does not look like the same one to a client.
+    pub fn uid_validity(&self) -> u32 {
+        u32::try_from(self.temp.created_at.timestamp())
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    pub fn last_uid(&self) -> u32 {
+        self.messages.last().map_or(0, |m| m.uid)
+    }
+
+    /// One past the highest UID. UIDs are shared between mailboxes, so the
+    /// next message may well get a higher one.
+    pub fn uid_next(&self) -> u32 {
+        self.last_uid().saturating_add(1)
+    }
+
+    /// Indexes into `messages` of those in `set`, by UID or sequence number.
+    pub fn matching(&self, set: &SequenceSet, by_uid: bool) -> Vec<usize> {
+        let last = if by_uid {
+            self.last_uid()
+        } else {
+            u32::try_from(self.messages.len()).unwrap_or(u32::MAX)
+        };
+        (0..self.messages.len())
+            .filter(|&i| {
+                let n = if by_uid {
+                    self.messages[i].uid
+                } else {
+                    sequence_number(i)
+                };
+                set.contains(n, last)
+            })
+            .collect()
+    }
+
+    /// Removes the messages for which `gone` holds and returns the untagged
+    /// `EXPUNGE` responses, each numbered after the removals before it.
+    pub fn expunge(&mut self, gone: impl Fn(&Message) -> bool) -> String {
+        let mut responses = String::new();
+        let mut i = 0;
+        while i < self.messages.len() {
+            if gone(&self.messages[i]) {
+                self.messages.remove(i);
+                responses.push_str(&format!("* {} EXPUNGE\r\n", sequence_number(i)));
+            } else {
+                i += 1;
+            }
+        }
+        responses
+    }
+
+    /// Brings the snapshot in line with `current`, the mailbox as it is now,
+    /// and returns the untagged responses telling the client.
+    pub fn sync(&mut self, current: Vec<Message>) -> String {
+        let present: HashSet<u32> = current.iter().map(|m| m.uid).collect();
+        let mut responses = self.expunge(|m| !present.contains(&m.uid));
+        let last = self.last_uid();
+        let before = self.messages.len();
+        self.messages
+            .extend(current.into_iter().filter(|m| m.uid > last));
+        if self.messages.len() != before {
+            responses.push_str(&format!("* {} EXISTS\r\n", self.messages.len()));
+        }
+        responses
+    }
+}
+
+/// The sequence number of the message at `index`.
+pub fn sequence_number(index: usize) -> u32 {
+    u32::try_from(index + 1).unwrap_or(u32::MAX)
+}
+
+/// The message as served: as received, or put together from the stored
+/// fields when the original was not kept or has been redacted.
+pub async fn content(pool: &PgPool, email: &ReceivedEmail) -> Result<Vec<u8>, sqlx::Error> {
+    let raw = db::timed("imap", "fetch_raw", fetch_raw_email(pool, email.id)).await?;
+    Ok(match raw {
+        Some(raw) => raw,
+        None => rebuild(email),
+    })
+}
+
+fn rebuild(email: &ReceivedEmail) -> Vec<u8> {
+    let mut message = format!("Date: {}\r\n", email.received_at.to_rfc2822());
+    let fields = [
+        ("From", &email.from_addr),
+        ("To", &email.to_addr),
+        ("Subject", &email.subject),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value {
+            message.push_str(&format!("{name}: {}\r\n", header_value(value)));
+        }
+    }
+    message.push_str("MIME-Version: 1.0\r\n");
+    match (email.body_text.as_deref(), email.body_html.as_deref()) {
+        (Some(text), Some(html)) => {
+            let boundary = format!("=_{}", email.id.simple());
+            message.push_str(&format!(
+                "Content-Type: multipart/alternative; boundary=\"{boundary}\"\r\n\r\n"
+            ));
+            for (subtype, body) in [("plain", text), ("html", html)] {
+                message.push_str(&format!("--{boundary}\r\n"));
+                message.push_str(&text_part(subtype, body));
+            }
+            message.push_str(&format!("--{boundary}--\r\n"));
+        }
+        (None, Some(html)) => message.push_str(&text_part("html", html)),
+        (text, None) => message.push_str(&text_part("plain", text.unwrap_or_default())),
+    }
+    message.into_bytes()
+}
+
+fn text_part(subtype: &str, body: &str) -> String {
+    let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+    format!(
+        "Content-Type: text/{subtype}; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\r\n{body}\r\n"
+    )
+}
+
+/// On one line, and as an encoded word unless it is plain ASCII.
+fn header_value(value: &str) -> String {
+    let value = value.replace(['\r', '\n'], " ");
+    if value.is_ascii() {
+        value
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(value);
+        format!("=?utf-8?b?{encoded}?=")
+    }
+}
@@ -0,0 +1,366 @@
+//! Just enough MIME for FETCH: where each part's header and body are, its
+//! content type, and the parts of multipart and `message/rfc822` bodies.
+//! Nothing is decoded; clients get the bytes as they were received.
+
+/// Nesting beyond this is treated as an opaque body.
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug)]
+pub struct Part<'a> {
+    /// Header fields with the blank line that ends them.
+    pub header: &'a [u8],
+    pub body: &'a [u8],
+    /// Parts of a multipart body, or the message in a `message/rfc822` one.
+    pub parts: Vec<Part<'a>>,
+    /// Content type when the header names none: `message/rfc822` inside a
+    /// `multipart/digest`, else `text/plain`.
+    in_digest: bool,
+}
+
+impl<'a> Part<'a> {
+    pub fn parse(raw: &'a [u8]) -> Self {
+        Self::parse_nested(raw, 0, false)
+    }
+
+    fn parse_nested(raw: &'a [u8], depth: usize, in_digest: bool) -> Self {
+        let (header, body) = split_header(raw);
+        let mut part = Self {
+            header,
+            body,
+            parts: Vec::new(),
+            in_digest,
+        };
+        if depth >= MAX_DEPTH {
+            return part;
+        }
+        let content_type = part.content_type();
+        if content_type.kind == "multipart" {
+            if let Some(boundary) = content_type.param("boundary") {
+                let digest = content_type.subtype == "digest";
+                part.parts = split_multipart(body, boundary)
+                    .into_iter()
+                    .map(|raw| Self::parse_nested(raw, depth + 1, digest))
+                    .collect();
+            }
+        } else if content_type.is_message() {
+            part.parts = vec![Self::parse_nested(body, depth + 1, false)];
+        }
+        part
+    }
+
+    pub fn field(&self, name: &str) -> Option<String> {
+        field(self.header, name)
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        self.field("content-type")
+            .and_then(|v| ContentType::parse(&v))
+            .unwrap_or_else(|| {
+                if self.in_digest {
+                    ContentType::new("message", "rfc822")
+                } else {
+                    ContentType::new("text", "plain")
+                }
+            })
+    }
+
+    /// Multipart with at least one part; an empty one is shown as opaque.
+    pub fn is_multipart(&self) -> bool {
+        self.content_type().kind == "multipart" && !self.parts.is_empty()
+    }
+
+    /// The message inside a `message/rfc822` part.
+    pub fn message(&self) -> Option<&Part<'a>> {
+        if self.content_type().is_message() {
+            self.parts.first()
+        } else {
+            None
+        }
+    }
+
+    /// The part IMAP numbers `path` (`[1, 2]` for `1.2`) within this
+    /// message. A message that is not multipart has its body as part 1.
+    pub fn locate(&self, path: &[u32]) -> Option<&Part<'a>> {
+        let Some((&n, rest)) = path.split_first() else {
+            return Some(self);
+        };
+        if !self.is_multipart() {
+            return (n == 1 && rest.is_empty()).then_some(self);
+        }
+        let index = usize::try_from(n).ok()?.checked_sub(1)?;
+        let child = self.parts.get(index)?;
+        if rest.is_empty() {
+            Some(child)
+        } else if child.is_multipart() {
+            child.locate(rest)
+        } else {
+            child.message()?.locate(rest)
+        }
+    }
+}
+
+/// Splits after the blank line ending the header. Without one, it is all
+/// header.
+pub fn split_header(raw: &[u8]) -> (&[u8], &[u8]) {
+    let mut pos = 0;
+    while pos < raw.len() {
+        let end = line_end(raw, pos);
+        let line = &raw[pos..end];
+        if line == b"\r\n" || line == b"\n" {
+            return raw.split_at(end);
+        }
+        pos = end;
+    }
+    (raw, &[])
+}
+
+/// Index just past the line starting at `pos`, including its line break.
+fn line_end(raw: &[u8], pos: usize) -> usize {
+    raw[pos..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(raw.len(), |i| pos + i + 1)
+}
+
+/// The parts between `--boundary` lines. The line break before a delimiter
+/// belongs to the delimiter; anything before the first and after the last
+/// is preamble and epilogue.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = line_end(body, pos);
+        let line = body[pos..end].trim_ascii_end();
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            let closing = rest.starts_with(b"--");
+            if closing || rest.trim_ascii().is_empty() {
+                if let Some(start) = start {
+                    let part = &body[start..pos];
+                    let part = part
+                        .strip_suffix(b"\n")
+                        .map(|p| p.strip_suffix(b"\r").unwrap_or(p))
+                        .unwrap_or(part);
+                    parts.push(part);
+                }
+                if closing {
+                    return parts;
+                }
+                start = Some(end);
+            }
+        }
+        pos = end;
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// Header fields as received, each with its continuation lines.
+pub fn raw_fields(header: &[u8]) -> Vec<&[u8]> {
+    let mut fields = Vec::new();
+    let mut start = None;
+    let mut pos = 0;
+    while pos < header.len() {
+        let end = line_end(header, pos);
+        let line = &header[pos..end];
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        if !matches!(line[0], b' ' | b'\t') {
+            if let Some(start) = start {
+                fields.push(&header[start..pos]);
+            }
+            start = Some(pos);
+        }
+        pos = end;
+    }
+    if let Some(start) = start {
+        fields.push(&header[start..pos]);
+    }
+    fields
+}
+
+/// Name of a raw field, as written.
+pub fn field_name(raw: &[u8]) -> &[u8] {
+    let colon = raw.iter().position(|&b| b == b':').unwrap_or(raw.len());
+    raw[..colon].trim_ascii()
+}
+
+/// Value of the first field called `name`, unfolded.
+pub fn field(header: &[u8], name: &str) -> Option<String> {
+    raw_fields(header)
+        .into_iter()
+        .find(|f| field_name(f).eq_ignore_ascii_case(name.as_bytes()))
+        .map(|f| {
+            let value = f
+                .iter()
+                .position(|&b| b == b':')
+                .map_or(&[][..], |i| &f[i + 1..]);
+            let unfolded: Vec<u8> = value
+                .iter()
+                .copied()
+                .filter(|&b| b != b'\r' && b != b'\n')
+                .collect();
+            String::from_utf8_lossy(unfolded.trim_ascii()).into_owned()
+        })
+}
+
+/// The fields named in `names` (or, with `exclude`, all others) followed by
+/// a blank line, as `BODY[HEADER.FIELDS (...)]` returns them.
+pub fn select_fields(header: &[u8], names: &[String], exclude: bool) -> Vec<u8> {
+    let mut selected = Vec::new();
+    for f in raw_fields(header) {
+        let name = field_name(f);
+        if names
+            .iter()
+            .any(|n| name.eq_ignore_ascii_case(n.as_bytes()))
+            != exclude
+        {
+            selected.extend_from_slice(f);
+        }
+    }
+    selected.extend_from_slice(b"\r\n");
+    selected
+}
+
+/// `Content-Type` and `Content-Disposition` style values: a lower-cased
+/// token followed by `; name=value` parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    pub kind: String,
+    pub subtype: String,
+    /// Names lower-cased, values unquoted.
+    pub params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    fn new(kind: &str, subtype: &str) -> Self {
+        let params = if kind == "text" {
+            vec![("charset".into(), "us-ascii".into())]
+        } else {
+            Vec::new()
+        };
+        Self {
+            kind: kind.into(),
+            subtype: subtype.into(),
+            params,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let (token, params) = parameterized(value);
+        let (kind, subtype) = token.split_once('/')?;
+        let (kind, subtype) = (kind.trim(), subtype.trim());
+        if kind.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind: kind.into(),
+            subtype: subtype.into(),
+            params,
+        })
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_message(&self) -> bool {
+        self.kind == "message" && self.subtype == "rfc822"
+    }
+}
+
+/// Splits `token; a=1; b="x;y"` into the lower-cased token and parameters.
+pub fn parameterized(value: &str) -> (String, Vec<(String, String)>) {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => current.extend(chars.next()),
+            ';' if !quoted => pieces.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    pieces.push(current);
+    let mut pieces = pieces.into_iter();
+    let token = pieces
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let params = pieces
+        .filter_map(|p| {
+            let (name, value) = p.split_once('=')?;
+            Some((name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    (token, params)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub name: Option<String>,
+    pub mailbox: String,
+    pub host: Option<String>,
+}
+
+/// The addresses in an address-list field such as `To`. Group names are
+/// dropped and their members listed like any other.
+pub fn addresses(value: &str) -> Vec<Address> {
+    let mut items = Vec::new();
+    let mut current = String::new();
+    let (mut quoted, mut angle) = (false, false);
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            ',' | ';' if !quoted && !angle => {
+                items.push(std::mem::take(&mut current));
+                continue;
+            }
+            ':' if !quoted && !angle => {
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current);
+    items.iter().filter_map(|item| address(item)).collect()
+}
+
+fn address(item: &str) -> Option<Address> {
+    let item = item.trim();
+    let (name, addr) = match (item.find('<'), item.rfind('>')) {
+        (Some(open), Some(close)) if open < close => {
+            let name = item[..open].trim().trim_matches('"').trim();
+            (Some(name).filter(|n| !n.is_empty()), &item[open + 1..close])
+        }
+        _ => (None, item),
+    };
+    let addr = addr.trim();
+    if addr.is_empty() {
+        return None;
+    }
+    let (mailbox, host) = match addr.rsplit_once('@') {
+        Some((mailbox, host)) => (mailbox, Some(host.to_owned())),
+        None => (addr, None),
+    };
+    Some(Address {
+        name: name.map(str::to_owned),
+        mailbox: mailbox.to_owned(),
+        host,
+    })
+}
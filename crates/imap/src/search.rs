@@ -0,0 +1,199 @@
+//! SEARCH criteria. Addresses, subject and bodies are matched against the
+//! stored fields; other header fields, sent dates and sizes need the
+//! message itself.
+
+use chrono::{DateTime, NaiveDate};
+
+use crate::command::{Arg, SequenceSet};
+use crate::mailbox::{Flags, Message};
+use crate::mime;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
+    All,
+    /// None of the messages: `NEW`, `RECENT` and keywords, which are never set.
+    Nothing,
+    Flag(Flags),
+    Unflag(Flags),
+    From(String),
+    To(String),
+    Subject(String),
+    Body(String),
+    Text(String),
+    /// Field name and the text its value must contain.
+    Header(String, String),
+    Before(NaiveDate),
+    On(NaiveDate),
+    Since(NaiveDate),
+    SentBefore(NaiveDate),
+    SentOn(NaiveDate),
+    SentSince(NaiveDate),
+    Larger(u32),
+    Smaller(u32),
+    Sequence(SequenceSet),
+    Uid(SequenceSet),
+    Not(Box<Key>),
+    Or(Box<Key>, Box<Key>),
+    And(Vec<Key>),
+}
+
+/// What a key is matched against. `content` is there when
+/// [`Key::needs_content`] says so.
+pub struct Candidate<'a> {
+    pub message: &'a Message,
+    pub seq: u32,
+    pub last_seq: u32,
+    pub last_uid: u32,
+    pub content: Option<&'a [u8]>,
+}
+
+/// The criteria after any `CHARSET`, all of which must match.
+pub fn parse(args: &[Arg]) -> Result<Key, &'static str> {
+    let mut args = args.iter();
+    let mut keys = Vec::new();
+    while !args.as_slice().is_empty() {
+        keys.push(key(&mut args)?);
+    }
+    if keys.is_empty() {
+        return Err("missing search criteria");
+    }
+    Ok(Key::And(keys))
+}
+
+fn key(args: &mut std::slice::Iter<'_, Arg>) -> Result<Key, &'static str> {
+    let arg = args.next().ok_or("missing search criteria")?;
+    let name = match arg {
+        Arg::List(list) => return parse(list),
+        Arg::Atom(name) => name.to_ascii_uppercase(),
+        Arg::String(_) => return Err("unexpected string"),
+    };
+    let mut text = || -> Result<String, &'static str> {
+        let arg = args.next().ok_or("missing search argument")?;
+        Ok(arg.text().ok_or("invalid search argument")?.to_lowercase())
+    };
+    let key = match name.as_str() {
+        "ALL" | "OLD" => Key::All,
+        "NEW" | "RECENT" => Key::Nothing,
+        "KEYWORD" => {
+            text()?;
+            Key::Nothing
+        }
+        "UNKEYWORD" => {
+            text()?;
+            Key::All
+        }
+        "ANSWERED" | "DELETED" | "DRAFT" | "FLAGGED" | "SEEN" => {
+            Key::Flag(flag(&name).ok_or("unknown flag")?)
+        }
+        "UNANSWERED" | "UNDELETED" | "UNDRAFT" | "UNFLAGGED" | "UNSEEN" => {
+            Key::Unflag(flag(&name[2..]).ok_or("unknown flag")?)
+        }
+        "FROM" => Key::From(text()?),
+        "TO" => Key::To(text()?),
+        "CC" => Key::Header("cc".into(), text()?),
+        "BCC" => Key::Header("bcc".into(), text()?),
+        "SUBJECT" => Key::Subject(text()?),
+        "BODY" => Key::Body(text()?),
+        "TEXT" => Key::Text(text()?),
+        "HEADER" => {
+            let field = text()?;
+            Key::Header(field, text()?)
+        }
+        "BEFORE" => Key::Before(date(&text()?)?),
+        "ON" => Key::On(date(&text()?)?),
+        "SINCE" => Key::Since(date(&text()?)?),
+        "SENTBEFORE" => Key::SentBefore(date(&text()?)?),
+        "SENTON" => Key::SentOn(date(&text()?)?),
+        "SENTSINCE" => Key::SentSince(date(&text()?)?),
+        "LARGER" => Key::Larger(text()?.parse().map_err(|_| "invalid size")?),
+        "SMALLER" => Key::Smaller(text()?.parse().map_err(|_| "invalid size")?),
+        "UID" => Key::Uid(text()?.parse().map_err(|_| "invalid sequence set")?),
+        "NOT" => Key::Not(Box::new(key(args)?)),
+        "OR" => {
+            let first = key(args)?;
+            Key::Or(Box::new(first), Box::new(key(args)?))
+        }
+        _ => Key::Sequence(name.parse().map_err(|_| "unknown search key")?),
+    };
+    Ok(key)
+}
+
+fn flag(name: &str) -> Option<Flags> {
+    Flags::parse(&format!("\\{name}"))
+}
+
+/// `1-Feb-1994`
+fn date(s: &str) -> Result<NaiveDate, &'static str> {
+    NaiveDate::parse_from_str(s, "%d-%b-%Y").map_err(|_| "invalid date")
+}
+
+impl Key {
+    pub fn needs_content(&self) -> bool {
+        match self {
+            Self::Header(..)
+            | Self::Text(_)
+            | Self::SentBefore(_)
+            | Self::SentOn(_)
+            | Self::SentSince(_)
+            | Self::Larger(_)
+            | Self::Smaller(_) => true,
+            Self::Not(key) => key.needs_content(),
+            Self::Or(a, b) => a.needs_content() || b.needs_content(),
+            Self::And(keys) => keys.iter().any(Key::needs_content),
+            _ => false,
+        }
+    }
+
+    pub fn matches(&self, c: &Candidate) -> bool {
+        let email = &c.message.email;
+        let has = |field: &Option<String>, needle: &str| {
+            field
+                .as_deref()
+                .is_some_and(|f| f.to_lowercase().contains(needle))
+        };
+        let header = || mime::split_header(c.content.unwrap_or_default()).0;
+        let internal = email.received_at.date_naive();
+        let sent = || {
+            mime::field(header(), "date")
+                .and_then(|d| DateTime::parse_from_rfc2822(&d).ok())
+                .map_or(internal, |d| d.date_naive())
+        };
+        let size = || c.content.map_or(0, <[u8]>::len);
+        match self {
+            Self::All => true,
+            Self::Nothing => false,
+            Self::Flag(flag) => c.message.flags.contains(*flag),
+            Self::Unflag(flag) => !c.message.flags.contains(*flag),
+            Self::From(s) => has(&email.from_addr, s),
+            Self::To(s) => has(&email.to_addr, s),
+            Self::Subject(s) => has(&email.subject, s),
+            Self::Body(s) => has(&email.body_text, s) || has(&email.body_html, s),
+            Self::Text(s) => {
+                has(&email.body_text, s)
+                    || has(&email.body_html, s)
+                    || String::from_utf8_lossy(header()).to_lowercase().contains(s)
+            }
+            Self::Header(name, s) => mime::raw_fields(header())
+                .into_iter()
+                .filter(|f| mime::field_name(f).eq_ignore_ascii_case(name.as_bytes()))
+                .any(|f| {
+                    let f = String::from_utf8_lossy(f).to_lowercase();
+                    f.split_once(':')
+                        .is_some_and(|(_, value)| value.contains(s))
+                }),
+            Self::Before(d) => internal < *d,
+            Self::On(d) => internal == *d,
+            Self::Since(d) => internal >= *d,
+            Self::SentBefore(d) => sent() < *d,
+            Self::SentOn(d) => sent() == *d,
+            Self::SentSince(d) => sent() >= *d,
+            Self::Larger(n) => size() > *n as usize,
+            Self::Smaller(n) => size() < *n as usize,
+            Self::Sequence(set) => set.contains(c.seq, c.last_seq),
+            Self::Uid(set) => set.contains(c.message.uid, c.last_uid),
+            Self::Not(key) => !key.matches(c),
+            Self::Or(a, b) => a.matches(c) || b.matches(c),
+            Self::And(keys) => keys.iter().all(|k| k.matches(c)),
+        }
+    }
+}
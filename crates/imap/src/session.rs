@@ -0,0 +1,550 @@
+//! One connection's state and the commands that act on it.
+
+use chrono::Utc;
+use db::{
+    fetch_mailbox_token_hash, find_temporary_email_by_addr, list_imap_messages,
+    trash_received_emails, TemporaryEmail,
+};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::command::{Arg, Command, SequenceSet};
+use crate::fetch::{self, Item};
+use crate::mailbox::{self, sequence_number, Flags, Mailbox, Message};
+use crate::search::{self, Candidate};
+use crate::Server;
+
+pub const CAPABILITIES: &str = "IMAP4rev1 UNSELECT";
+/// Failed logins before the connection is closed.
+const MAX_LOGIN_FAILURES: u32 = 3;
+/// Pause before answering a failed login, to slow down guessing.
+const LOGIN_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+/// How a command completed.
+enum Done {
+    Ok(String),
+    No(String),
+    Bad(String),
+}
+
+type Outcome = Result<Done, Done>;
+
+fn ok(text: &str) -> Outcome {
+    Ok(Done::Ok(text.into()))
+}
+
+fn no(text: &str) -> Done {
+    Done::No(text.into())
+}
+
+fn bad(text: &str) -> Done {
+    Done::Bad(text.into())
+}
+
+fn unavailable(e: sqlx::Error) -> Done {
+    tracing::error!(error = %e, "imap query failed");
+    no("[UNAVAILABLE] try again later")
+}
+
+struct Login {
+    temp: TemporaryEmail,
+    /// Private mailboxes, and public ones opened with their access token.
+    may_delete: bool,
+}
+
+#[derive(Default)]
+pub struct Session {
+    login: Option<Login>,
+    selected: Option<Mailbox>,
+    login_failures: u32,
+    /// Set once the connection should be closed.
+    pub closing: bool,
+}
+
+impl Session {
+    /// Runs `command`, appending its untagged responses and its completion
+    /// to `out`.
+    pub async fn run(&mut self, server: &Server, command: &Command, out: &mut Vec<u8>) {
+        let (status, text) = match self.dispatch(server, command, out).await {
+            Ok(Done::Ok(text)) | Err(Done::Ok(text)) => ("OK", text),
+            Ok(Done::No(text)) | Err(Done::No(text)) => ("NO", text),
+            Ok(Done::Bad(text)) | Err(Done::Bad(text)) => ("BAD", text),
+        };
+        out.extend(format!("{} {status} {text}\r\n", command.tag).bytes());
+    }
+
+    async fn dispatch(&mut self, server: &Server, command: &Command, out: &mut Vec<u8>) -> Outcome {
+        let args = command.args.as_slice();
+        match command.name.as_str() {
+            "CAPABILITY" => {
+                out.extend(format!("* CAPABILITY {CAPABILITIES}\r\n").bytes());
+                ok("CAPABILITY completed")
+            }
+            "NOOP" | "CHECK" => {
+                self.refresh(server, out).await?;
+                ok("NOOP completed")
+            }
+            "LOGOUT" => {
+                out.extend_from_slice(b"* BYE logging out\r\n");
+                self.closing = true;
+                ok("LOGOUT completed")
+            }
+            "LOGIN" => self.login(server, args, out).await,
+            "AUTHENTICATE" => Err(no("[CANNOT] use LOGIN")),
+            "STARTTLS" => Err(bad("TLS is not offered")),
+            "SELECT" | "EXAMINE" => {
+                self.select(server, args, command.name == "EXAMINE", out)
+                    .await
+            }
+            "LIST" | "LSUB" => self.list(&command.name, args, out),
+            "STATUS" => self.status(server, args, out).await,
+            "SUBSCRIBE" | "UNSUBSCRIBE" => {
+                self.logged_in()?;
+                match args {
+                    [name] if is_inbox(name) => ok("done"),
+                    _ => Err(no("[NONEXISTENT] only INBOX exists")),
+                }
+            }
+            "CREATE" | "DELETE" | "RENAME" | "APPEND" | "COPY" | "MOVE" => {
+                self.logged_in()?;
+                Err(no("[CANNOT] mail can only be read and deleted here"))
+            }
+            "CLOSE" => self.close(server).await,
+            "UNSELECT" => {
+                self.selected()?;
+                self.selected = None;
+                ok("UNSELECT completed")
+            }
+            "EXPUNGE" => {
+                out.extend(self.delete_flagged(server).await?.bytes());
+                ok("EXPUNGE completed")
+            }
+            "FETCH" => self.fetch(server, args, false, out).await,
+            "STORE" => self.store(args, false, out),
+            "SEARCH" => self.search(server, args, false, out).await,
+            "UID" => {
+                let Some((Arg::Atom(name), args)) = args.split_first() else {
+                    return Err(bad("UID needs a command"));
+                };
+                match name.to_ascii_uppercase().as_str() {
+                    "FETCH" => self.fetch(server, args, true, out).await,
+                    "STORE" => self.store(args, true, out),
+                    "SEARCH" => self.search(server, args, true, out).await,
+                    "COPY" | "MOVE" => Err(no("[CANNOT] mail can only be read and deleted here")),
+                    _ => Err(bad("unknown UID command")),
+                }
+            }
+            _ => Err(bad("unknown command")),
+        }
+    }
+
+    fn logged_in(&self) -> Result<&Login, Done> {
+        self.login.as_ref().ok_or_else(|| bad("log in first"))
+    }
+
+    fn selected(&mut self) -> Result<&mut Mailbox, Done> {
+        self.selected
+            .as_mut()
+            .ok_or_else(|| bad("select a mailbox first"))
+    }
+
+    /// `LOGIN <address> <access token>`
+    async fn login(&mut self, server: &Server, args: &[Arg], out: &mut Vec<u8>) -> Outcome {
+        if self.login.is_some() {
+            return Err(bad("already logged in"));
+        }
+        let (Some(user), Some(password)) = (
+            args.first().and_then(Arg::text),
+            args.get(1).and_then(Arg::text),
+        ) else {
+            return Err(bad("LOGIN needs an address and an access token"));
+        };
+        match authenticate(server, &user, &password)
+            .await
+            .map_err(unavailable)?
+        {
+            Ok(login) => {
+                metrics::counter!("imap_logins_total", "result" => "ok").increment(1);
+                self.login = Some(login);
+                Ok(Done::Ok(format!("[CAPABILITY {CAPABILITIES}] logged in")))
+            }
+            Err(reason) => {
+                metrics::counter!("imap_logins_total", "result" => "failed").increment(1);
+                self.login_failures += 1;
+                tokio::time::sleep(LOGIN_FAILURE_DELAY).await;
+                if self.login_failures >= MAX_LOGIN_FAILURES {
+                    out.extend_from_slice(b"* BYE too many failed logins\r\n");
+                    self.closing = true;
+                }
+                Err(no(reason))
+            }
+        }
+    }
+
+    async fn select(
+        &mut self,
+        server: &Server,
+        args: &[Arg],
+        examine: bool,
+        out: &mut Vec<u8>,
+    ) -> Outcome {
+        let login = self.logged_in()?;
+        let (temp, may_delete) = (login.temp.clone(), login.may_delete);
+        self.selected = None;
+        match args {
+            [name] if is_inbox(name) => {}
+            [_] => return Err(no("[NONEXISTENT] only INBOX exists")),
+            _ => return Err(bad("SELECT needs a mailbox name")),
+        }
+        let messages = load(server, &temp).await?;
+        let mailbox = Mailbox {
+            temp,
+            read_only: examine || !may_delete,
+            messages,
+        };
+
+        let exists = mailbox.messages.len();
+        out.extend(format!("* FLAGS {}\r\n", Flags::all().list()).bytes());
+        out.extend(format!("* {exists} EXISTS\r\n* 0 RECENT\r\n").bytes());
+        if exists > 0 {
+            out.extend_from_slice(b"* OK [UNSEEN 1] first unseen message\r\n");
+        }
+        out.extend_from_slice(b"* OK [PERMANENTFLAGS ()] flags last for this session only\r\n");
+        out.extend(
+            format!(
+                "* OK [UIDVALIDITY {}] UIDs valid\r\n",
+                mailbox.uid_validity()
+            )
+            .bytes(),
+        );
+        out.extend(
+            format!(
+                "* OK [UIDNEXT {}] predicted next UID\r\n",
+                mailbox.uid_next()
+            )
+            .bytes(),
+        );
+        let access = if mailbox.read_only {
+            "READ-ONLY"
+        } else {
+            "READ-WRITE"
+        };
+        self.selected = Some(mailbox);
+        let command = if examine { "EXAMINE" } else { "SELECT" };
+        Ok(Done::Ok(format!("[{access}] {command} completed")))
+    }
+
+    /// `LIST` and `LSUB`: INBOX is the only mailbox, with no hierarchy.
+    fn list(&self, command: &str, args: &[Arg], out: &mut Vec<u8>) -> Outcome {
+        self.logged_in()?;
+        let [_, pattern] = args else {
+            return Err(bad("LIST needs a reference and a pattern"));
+        };
+        let pattern = pattern.text().ok_or_else(|| bad("invalid pattern"))?;
+        if pattern.is_empty() {
+            out.extend(format!("* {command} (\\Noselect) \"/\" \"\"\r\n").bytes());
+        } else if glob(pattern.to_ascii_uppercase().as_bytes(), b"INBOX") {
+            out.extend(format!("* {command} (\\HasNoChildren) \"/\" INBOX\r\n").bytes());
+        }
+        Ok(Done::Ok(format!("{command} completed")))
+    }
+
+    async fn status(&mut self, server: &Server, args: &[Arg], out: &mut Vec<u8>) -> Outcome {
+        let temp = self.logged_in()?.temp.clone();
+        let [name, Arg::List(items)] = args else {
+            return Err(bad("STATUS needs a mailbox name and items"));
+        };
+        if !is_inbox(name) {
+            return Err(no("[NONEXISTENT] only INBOX exists"));
+        }
+        let fresh;
+        let mailbox = match &self.selected {
+            Some(mailbox) => mailbox,
+            None => {
+                let messages = load(server, &temp).await?;
+                fresh = Mailbox {
+                    temp,
+                    read_only: true,
+                    messages,
+                };
+                &fresh
+            }
+        };
+        let mut values = Vec::new();
+        for item in items {
+            let item = item.atom().unwrap_or_default().to_ascii_uppercase();
+            let value = match item.as_str() {
+                "MESSAGES" => mailbox.messages.len(),
+                "RECENT" => 0,
+                "UIDNEXT" => mailbox.uid_next() as usize,
+                "UIDVALIDITY" => mailbox.uid_validity() as usize,
+                "UNSEEN" => mailbox
+                    .messages
+                    .iter()
+                    .filter(|m| !m.flags.contains(Flags::SEEN))
+                    .count(),
+                _ => return Err(bad("unknown STATUS item")),
+            };
+            values.push(format!("{item} {value}"));
+        }
+        out.extend(format!("* STATUS INBOX ({})\r\n", values.join(" ")).bytes());
+        ok("STATUS completed")
+    }
+
+    async fn close(&mut self, server: &Server) -> Outcome {
+        if !self.selected()?.read_only {
+            self.delete_flagged(server).await?;
+        }
+        self.selected = None;
+        ok("CLOSE completed")
+    }
+
+    /// Deletes messages flagged `\Deleted` and returns the `EXPUNGE`
+    /// responses for them.
+    async fn delete_flagged(&mut self, server: &Server) -> Result<String, Done> {
+        let selected = self.selected()?;
+        if selected.read_only {
+            return Err(no("[READ-ONLY] mailbox is read-only"));
+        }
+        let deleted = |m: &Message| m.flags.contains(Flags::DELETED);
+        let ids: Vec<Uuid> = selected
+            .messages
+            .iter()
+            .filter(|&m| deleted(m))
+            .map(|m| m.email.id)
+            .collect();
+        if !ids.is_empty() {
+            trash_received_emails(&server.pool, selected.temp.id, Some(&ids[..]))
+                .await
+                .map_err(unavailable)?;
+            metrics::counter!("imap_messages_expunged_total").increment(ids.len() as u64);
+        }
+        Ok(selected.expunge(deleted))
+    }
+
+    async fn fetch(
+        &mut self,
+        server: &Server,
+        args: &[Arg],
+        by_uid: bool,
+        out: &mut Vec<u8>,
+    ) -> Outcome {
+        let [set, items @ ..] = args else {
+            return Err(bad("FETCH needs messages and items"));
+        };
+        let set = sequence_set(set)?;
+        let mut items = fetch::parse_items(items).map_err(bad)?;
+        if by_uid && !items.contains(&Item::Uid) {
+            items.insert(0, Item::Uid);
+        }
+        let selected = self.selected()?;
+        let marks_seen = !selected.read_only && items.iter().any(Item::sets_seen);
+        for index in selected.matching(&set, by_uid) {
+            let message = &mut selected.messages[index];
+            let content = if fetch::needs_content(&items, message) {
+                let content = mailbox::content(&server.pool, &message.email)
+                    .await
+                    .map_err(unavailable)?;
+                message.size = u32::try_from(content.len()).ok();
+                Some(content)
+            } else {
+                None
+            };
+            let flags_changed = marks_seen && !message.flags.contains(Flags::SEEN);
+            if flags_changed {
+                message.flags.insert(Flags::SEEN);
+            }
+            out.extend(fetch::response(
+                sequence_number(index),
+                message,
+                &items,
+                content.as_deref(),
+                flags_changed,
+            ));
+        }
+        ok("FETCH completed")
+    }
+
+    /// `STORE <messages> [+|-]FLAGS[.SILENT] <flags>`. Keywords are ignored.
+    fn store(&mut self, args: &[Arg], by_uid: bool, out: &mut Vec<u8>) -> Outcome {
+        let [set, action, flags @ ..] = args else {
+            return Err(bad("STORE needs messages, an action and flags"));
+        };
+        let set = sequence_set(set)?;
+        let action = action.atom().unwrap_or_default().to_ascii_uppercase();
+        let (action, silent) = match action.strip_suffix(".SILENT") {
+            Some(action) => (action, true),
+            None => (action.as_str(), false),
+        };
+        if !["FLAGS", "+FLAGS", "-FLAGS"].contains(&action) {
+            return Err(bad("invalid STORE action"));
+        }
+        let flags = match flags {
+            [Arg::List(list)] => list.as_slice(),
+            _ => flags,
+        };
+        let mut changed = Flags::default();
+        for flag in flags.iter().filter_map(Arg::atom).filter_map(Flags::parse) {
+            changed.insert(flag);
+        }
+
+        let selected = self.selected()?;
+        if selected.read_only {
+            return Err(no("[READ-ONLY] mailbox is read-only"));
+        }
+        for index in selected.matching(&set, by_uid) {
+            let message = &mut selected.messages[index];
+            match action {
+                "+FLAGS" => message.flags.insert(changed),
+                "-FLAGS" => message.flags.remove(changed),
+                _ => message.flags = changed,
+            }
+            if !silent {
+                let uid = if by_uid {
+                    format!(" UID {}", message.uid)
+                } else {
+                    String::new()
+                };
+                out.extend(
+                    format!(
+                        "* {} FETCH (FLAGS {}{uid})\r\n",
+                        sequence_number(index),
+                        message.flags.list()
+                    )
+                    .bytes(),
+                );
+            }
+        }
+        ok("STORE completed")
+    }
+
+    async fn search(
+        &mut self,
+        server: &Server,
+        args: &[Arg],
+        by_uid: bool,
+        out: &mut Vec<u8>,
+    ) -> Outcome {
+        let args = match args {
+            [Arg::Atom(word), charset, rest @ ..] if word.eq_ignore_ascii_case("CHARSET") => {
+                let charset = charset.text().unwrap_or_default();
+                if !["UTF-8", "US-ASCII"]
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(&charset))
+                {
+                    return Err(no("[BADCHARSET (UTF-8 US-ASCII)] unsupported charset"));
+                }
+                rest
+            }
+            _ => args,
+        };
+        let key = search::parse(args).map_err(bad)?;
+        let selected = self.selected()?;
+        let last_seq = u32::try_from(selected.messages.len()).unwrap_or(u32::MAX);
+        let last_uid = selected.last_uid();
+        let mut found = String::new();
+        for (index, message) in selected.messages.iter_mut().enumerate() {
+            let content = if key.needs_content() {
+                let content = mailbox::content(&server.pool, &message.email)
+                    .await
+                    .map_err(unavailable)?;
+                message.size = u32::try_from(content.len()).ok();
+                Some(content)
+            } else {
+                None
+            };
+            let candidate = Candidate {
+                message,
+                seq: sequence_number(index),
+                last_seq,
+                last_uid,
+                content: content.as_deref(),
+            };
+            if key.matches(&candidate) {
+                let n = if by_uid { message.uid } else { candidate.seq };
+                found.push_str(&format!(" {n}"));
+            }
+        }
+        out.extend(format!("* SEARCH{found}\r\n").bytes());
+        ok("SEARCH completed")
+    }
+
+    /// Tells the client about mail that arrived or went away since it
+    /// last looked.
+    async fn refresh(&mut self, server: &Server, out: &mut Vec<u8>) -> Result<(), Done> {
+        let Some(selected) = self.selected.as_mut() else {
+            return Ok(());
+        };
+        let current = load(server, &selected.temp).await?;
+        out.extend(selected.sync(current).bytes());
+        Ok(())
+    }
+}
+
+/// The address's mailbox if `password` opens it. Private mailboxes need
+/// their access token, so those without one cannot log in at all; public
+/// ones anyone may read, but only the token lets a client delete from them.
+async fn authenticate(
+    server: &Server,
+    user: &str,
+    password: &str,
+) -> Result<Result<Login, &'static str>, sqlx::Error> {
+    const INVALID: &str = "[AUTHENTICATIONFAILED] invalid address or access token";
+    let addr = user.trim().to_ascii_lowercase();
+    let lookup = find_temporary_email_by_addr(&server.pool, &addr);
+    let Some(temp) = db::timed("imap", "find_address", lookup)
+        .await?
+        .filter(|t| !t.is_honeypot)
+    else {
+        return Ok(Err(INVALID));
+    };
+    let stored = fetch_mailbox_token_hash(&server.pool, temp.id).await?;
+    let presented = stored
+        .as_deref()
+        .is_some_and(|hash| (server.config.verify_token)(password, hash));
+    if !presented && !temp.is_public {
+        return Ok(Err(INVALID));
+    }
+    if !temp.is_live() {
+        return Ok(Err("[EXPIRED] address has expired"));
+    }
+    Ok(Ok(Login {
+        temp,
+        may_delete: presented,
+    }))
+}
+
+/// The mailbox's messages; public mailboxes only show recent mail.
+async fn load(server: &Server, temp: &TemporaryEmail) -> Result<Vec<Message>, Done> {
+    let since = if temp.is_public {
+        chrono::Duration::from_std(server.config.public_retention)
+            .ok()
+            .and_then(|r| Utc::now().checked_sub_signed(r))
+    } else {
+        None
+    };
+    let list = list_imap_messages(&server.pool, temp.id, since);
+    db::timed("imap", "list_messages", list)
+        .await
+        .map(mailbox::messages)
+        .map_err(unavailable)
+}
+
+fn is_inbox(name: &Arg) -> bool {
+    name.text().is_some_and(|n| n.eq_ignore_ascii_case("INBOX"))
+}
+
+fn sequence_set(arg: &Arg) -> Result<SequenceSet, Done> {
+    arg.atom()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| bad("invalid sequence set"))
+}
+
+/// `*` and `%` match anything, since there is no hierarchy.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*' | b'%', rest)) => (0..=name.len()).any(|i| glob(rest, &name[i..])),
+        Some((&c, rest)) => name.first() == Some(&c) && glob(rest, &name[1..]),
+    }
+}
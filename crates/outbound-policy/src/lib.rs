@@ -0,0 +1,227 @@
+//! Checks every message we are about to send (replies, forwards, auto-replies)
+//! against operator policy before it reaches a relay. Callers build an
+//! [`OutboundMessage`] and must not send when [`OutboundPolicy::check`] fails.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const PRUNE_AT: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutboundKind {
+    Reply,
+    Forward,
+    AutoReply,
+}
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboundMessage {
+    pub kind: OutboundKind,
+    /// The local mailbox sending; rate limits are keyed on it.
+    pub sender: String,
+    pub recipients: Vec<String>,
+    pub size: usize,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    TooLarge { size: usize, max: usize },
+    TooManyRecipients { count: usize, max: usize },
+    NoRecipients,
+    BlockedAttachment { name: String },
+    BlockedRecipientDomain { domain: String },
+    InvalidRecipient { addr: String },
+    RateLimited { retry_after: Duration },
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { size, max } => write!(f, "message is {size} bytes, limit is {max}"),
+            Self::TooManyRecipients { count, max } => {
+                write!(f, "{count} recipients, limit is {max}")
+            }
+            Self::NoRecipients => f.write_str("message has no recipients"),
+            Self::BlockedAttachment { name } => write!(f, "attachment {name:?} is not allowed"),
+            Self::BlockedRecipientDomain { domain } => {
+                write!(f, "sending to {domain} is not allowed")
+            }
+            Self::InvalidRecipient { addr } => write!(f, "{addr:?} is not an email address"),
+            Self::RateLimited { retry_after } => {
+                write!(
+                    f,
+                    "rate limited, retry in {}s",
+                    retry_after.as_secs().max(1)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+#[derive(Debug, Clone)]
+pub struct PolicyConfig {
+    pub max_size: usize,
+    pub max_recipients: usize,
+    /// Lowercase extensions without the dot, e.g. `exe`.
+    pub blocked_extensions: HashSet<String>,
+    /// Lowercase MIME types, e.g. `application/x-msdownload`.
+    pub blocked_content_types: HashSet<String>,
+    /// Lowercase domains; subdomains are blocked too.
+    pub blocked_recipient_domains: HashSet<String>,
+    /// Messages one sender may send per `rate_window`. 0 disables the limit.
+    pub rate_limit: u32,
+    pub rate_window: Duration,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        let set = |items: &[&str]| items.iter().map(|s| s.to_string()).collect();
+        Self {
+            max_size: 10 * 1024 * 1024,
+            max_recipients: 5,
+            blocked_extensions: set(&[
+                "exe", "scr", "bat", "cmd", "com", "pif", "js", "vbs", "jar", "msi", "ps1",
+            ]),
+            blocked_content_types: set(&[
+                "application/x-msdownload",
+                "application/x-dosexec",
+                "application/java-archive",
+            ]),
+            blocked_recipient_domains: HashSet::new(),
+            rate_limit: 20,
+            rate_window: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+pub struct OutboundPolicy {
+    config: PolicyConfig,
+    sent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl OutboundPolicy {
+    pub fn new(config: PolicyConfig) -> Self {
+        Self {
+            config,
+            sent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &PolicyConfig {
+        &self.config
+    }
+
+    /// Validates `msg` and, if it passes, counts it against the sender's rate
+    /// limit. A message that fails is not counted.
+    pub fn check(&self, msg: &OutboundMessage) -> Result<(), PolicyViolation> {
+        self.check_content(msg)?;
+        self.take_rate_slot(&msg.sender.to_ascii_lowercase())
+    }
+
+    fn check_content(&self, msg: &OutboundMessage) -> Result<(), PolicyViolation> {
+        let cfg = &self.config;
+        if msg.size > cfg.max_size {
+            return Err(PolicyViolation::TooLarge {
+                size: msg.size,
+                max: cfg.max_size,
+            });
+        }
+        if msg.recipients.is_empty() {
+            return Err(PolicyViolation::NoRecipients);
+        }
+        if msg.recipients.len() > cfg.max_recipients {
+            return Err(PolicyViolation::TooManyRecipients {
+                count: msg.recipients.len(),
+                max: cfg.max_recipients,
+            });
+        }
+
+        for rcpt in &msg.recipients {
+            let domain = match rcpt.rsplit_once('@') {
+                Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {
+                    domain.to_ascii_lowercase()
+                }
+                _ => {
+                    return Err(PolicyViolation::InvalidRecipient { addr: rcpt.clone() });
+                }
+            };
+            if self.domain_blocked(&domain) {
+                return Err(PolicyViolation::BlockedRecipientDomain { domain });
+            }
+        }
+
+        for att in &msg.attachments {
+            let content_type = att.content_type.to_ascii_lowercase();
+            let extension = att
+                .filename
+                .as_deref()
+                .and_then(|f| f.rsplit_once('.'))
+                .map(|(_, ext)| ext.to_ascii_lowercase());
+            let blocked = cfg.blocked_content_types.contains(&content_type)
+                || extension.is_some_and(|ext| cfg.blocked_extensions.contains(&ext));
+            if blocked {
+                return Err(PolicyViolation::BlockedAttachment {
+                    name: att.filename.clone().unwrap_or(content_type),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn domain_blocked(&self, domain: &str) -> bool {
+        let blocked = &self.config.blocked_recipient_domains;
+        let mut rest = domain;
+        loop {
+            if blocked.contains(rest) {
+                return true;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn take_rate_slot(&self, sender: &str) -> Result<(), PolicyViolation> {
+        let (limit, window) = (self.config.rate_limit as usize, self.config.rate_window);
+        if limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut sent = self.sent.lock().expect("outbound rate lock");
+        if sent.len() >= PRUNE_AT {
+            sent.retain(|_, times| {
+                times
+                    .back()
+                    .is_some_and(|t| now.duration_since(*t) < window)
+            });
+        }
+
+        let times = sent.entry(sender.to_owned()).or_default();
+        while times
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= window)
+        {
+            times.pop_front();
+        }
+        if times.len() >= limit {
+            let oldest = *times.front().expect("non-empty at limit");
+            return Err(PolicyViolation::RateLimited {
+                retry_after: window.saturating_sub(now.duration_since(oldest)),
+            });
+        }
+        times.push_back(now);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use outbound_policy::{
+    Attachment, OutboundKind, OutboundMessage, OutboundPolicy, PolicyConfig, PolicyViolation,
+};
+
+fn message(recipients: &[&str]) -> OutboundMessage {
+    OutboundMessage {
+        kind: OutboundKind::Forward,
+        sender: "me@fake-email.site".into(),
+        recipients: recipients.iter().map(|r| r.to_string()).collect(),
+        size: 1024,
+        attachments: Vec::new(),
+    }
+}
+
+#[test]
+fn rejects_blocked_attachments_and_domains() {
+    let mut config = PolicyConfig::default();
+    config
+        .blocked_recipient_domains
+        .insert("blocked.test".into());
+    let policy = OutboundPolicy::new(config);
+
+    assert!(policy.check(&message(&["a@ok.test"])).is_ok());
+    assert_eq!(
+        policy.check(&message(&["a@mx.blocked.test"])),
+        Err(PolicyViolation::BlockedRecipientDomain {
+            domain: "mx.blocked.test".into()
+        })
+    );
+
+    let mut msg = message(&["a@ok.test"]);
+    msg.attachments.push(Attachment {
+        filename: Some("invoice.PDF.exe".into()),
+        content_type: "application/octet-stream".into(),
+    });
+    assert!(matches!(
+        policy.check(&msg),
+        Err(PolicyViolation::BlockedAttachment { .. })
+    ));
+
+    let mut msg = message(&["a@ok.test"]);
+    msg.size = policy.config().max_size + 1;
+    assert!(matches!(
+        policy.check(&msg),
+        Err(PolicyViolation::TooLarge { .. })
+    ));
+}
+
+#[test]
+fn rate_limits_per_sender_and_ignores_rejected_messages() {
+    let policy = OutboundPolicy::new(PolicyConfig {
+        rate_limit: 2,
+        ..Default::default()
+    });
+
+    assert!(policy.check(&message(&["bad-address"])).is_err());
+    assert!(policy.check(&message(&["a@ok.test"])).is_ok());
+    assert!(policy.check(&message(&["b@ok.test"])).is_ok());
+    assert!(matches!(
+        policy.check(&message(&["c@ok.test"])),
+        Err(PolicyViolation::RateLimited { .. })
+    ));
+
+    let mut other = message(&["c@ok.test"]);
+    other.sender = "someone-else@fake-email.site".into();
+    assert!(policy.check(&other).is_ok());
+}
@@ -0,0 +1,111 @@
+//! Hands one message to a smarthost over `lettre`'s SMTP transport, with
+//! STARTTLS and `AUTH` as configured in [`RelayConfig`].
+
+use lettre::address::{Address, Envelope as SmtpEnvelope};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::transport::smtp::Error;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use std::fmt;
+
+use crate::{Envelope, RelayConfig, RelayTls, Smarthost};
+
+/// A complete, possibly multi-line, SMTP reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    pub code: u16,
+    /// Text of every line, joined with spaces.
+    pub text: String,
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code, self.text)
+    }
+}
+
+pub(crate) enum Failure {
+    /// The host is unreachable, misbehaving, could not secure the
+    /// connection or answered `4xx`: try another.
+    Host(String),
+    /// `5xx` to the envelope or the data, or an envelope no host would take:
+    /// the message itself is refused.
+    Rejected(Reply),
+}
+
+fn transport(
+    host: &Smarthost,
+    config: &RelayConfig,
+) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let tls = match config.tls {
+        RelayTls::Required => Tls::Required(
+            TlsParameters::new(host.host.clone()).map_err(|e| format!("TLS setup: {e}"))?,
+        ),
+        RelayTls::None => Tls::None,
+    };
+    let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host.host)
+        .port(host.port)
+        .hello_name(ClientId::Domain(config.helo_name.clone()))
+        .timeout(Some(config.timeout))
+        .tls(tls);
+    if let Some(login) = &config.credentials {
+        builder = builder.credentials(Credentials::new(
+            login.username.clone(),
+            login.password.clone(),
+        ));
+    }
+    Ok(builder.build())
+}
+
+pub(crate) async fn deliver(
+    host: &Smarthost,
+    config: &RelayConfig,
+    envelope: &Envelope<'_>,
+) -> Result<(), Failure> {
+    let smtp_envelope =
+        smtp_envelope(envelope).map_err(|text| Failure::Rejected(Reply { code: 553, text }))?;
+    // lettre dot-stuffs the data and ends it with CRLF `.` CRLF itself.
+    let data = envelope.data.strip_suffix(b"\r\n").unwrap_or(envelope.data);
+    transport(host, config)
+        .map_err(Failure::Host)?
+        .send_raw(&smtp_envelope, data)
+        .await
+        .map(drop)
+        .map_err(classify)
+}
+
+/// Connects, secures and authenticates as for a delivery, then quits; any
+/// failure is the host's.
+pub(crate) async fn probe(host: &Smarthost, config: &RelayConfig) -> Result<(), String> {
+    match transport(host, config)?.test_connection().await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("did not accept a session".into()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn smtp_envelope(envelope: &Envelope<'_>) -> Result<SmtpEnvelope, String> {
+    let parse = |addr: &str| {
+        addr.parse::<Address>()
+            .map_err(|e| format!("invalid address {addr:?}: {e}"))
+    };
+    let from = envelope.mail_from.map(parse).transpose()?;
+    let to = envelope
+        .recipients
+        .iter()
+        .map(|rcpt| parse(rcpt))
+        .collect::<Result<Vec<_>, _>>()?;
+    SmtpEnvelope::new(from, to).map_err(|e| e.to_string())
+}
+
+fn classify(e: Error) -> Failure {
+    let code = e.status().and_then(|code| code.to_string().parse().ok());
+    match code {
+        Some(code) if e.is_permanent() => Failure::Rejected(Reply {
+            code,
+            text: e.to_string(),
+        }),
+        _ => Failure::Host(e.to_string()),
+    }
+}
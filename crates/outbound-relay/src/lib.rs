@@ -0,0 +1,371 @@
+//! Hands outgoing mail (replies, forwards, auto-replies) to one of several
+//! smarthosts. Hosts are tried in configured order; one that keeps failing is
+//! skipped until its cooldown ends, so a provider outage costs one timeout
+//! rather than one per message. Check messages with `outbound-policy` first.
+
+mod client;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub use client::Reply;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Smarthost {
+    /// Short label used in routes, logs and status, e.g. `primary`.
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    /// In order of preference.
+    pub hosts: Vec<Smarthost>,
+    /// Lowercase recipient domain to the names of the hosts (in order) that
+    /// serve it instead of `hosts`.
+    pub routes: HashMap<String, Vec<String>>,
+    /// Name sent in `EHLO`.
+    pub helo_name: String,
+    /// Consecutive failures before a host is skipped.
+    pub failure_threshold: u32,
+    /// How long a failing host is skipped.
+    pub cooldown: Duration,
+    /// Deadline for one delivery attempt or health check.
+    pub timeout: Duration,
+    pub tls: RelayTls,
+    /// `AUTH` login sent to every host once the connection is secured.
+    pub credentials: Option<RelayCredentials>,
+}
+
+/// How connections to smarthosts are secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RelayTls {
+    /// `STARTTLS` with a certificate valid for the host's name; a host that
+    /// does not offer it fails like an unreachable one.
+    #[default]
+    Required,
+    /// Plain text, for an MTA on the same machine or a TLS tunnel.
+    None,
+}
+
+impl FromStr for RelayTls {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "required" => Ok(Self::Required),
+            "none" => Ok(Self::None),
+            _ => Err(format!("expected required or none, got {s:?}")),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct RelayCredentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for RelayCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RelayCredentials")
+            .field("username", &self.username)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            hosts: Vec::new(),
+            routes: HashMap::new(),
+            helo_name: "localhost".into(),
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(60),
+            timeout: Duration::from_secs(30),
+            tls: RelayTls::Required,
+            credentials: None,
+        }
+    }
+}
+
+/// Parses `name=host:port[,name=host:port]`.
+pub fn parse_hosts(raw: &str) -> Result<Vec<Smarthost>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parsed = entry.split_once('=').and_then(|(name, addr)| {
+                let (host, port) = addr.rsplit_once(':')?;
+                let port = port.parse().ok()?;
+                (!name.is_empty() && !host.is_empty()).then(|| Smarthost {
+                    name: name.to_owned(),
+                    host: host.to_owned(),
+                    port,
+                })
+            });
+            parsed.ok_or_else(|| format!("expected name=host:port, got {entry:?}"))
+        })
+        .collect()
+}
+
+/// Parses `domain=name[+name][,domain=name]`, e.g. `gmail.com=backup+primary`.
+pub fn parse_routes(raw: &str) -> Result<HashMap<String, Vec<String>>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((domain, names)) if !domain.is_empty() && !names.is_empty() => Ok((
+                domain.to_ascii_lowercase(),
+                names.split('+').map(|n| n.trim().to_owned()).collect(),
+            )),
+            _ => Err(format!("expected domain=name[+name], got {entry:?}")),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum RelayError {
+    /// A smarthost refused the message or a recipient with a `5xx`; another
+    /// host would too, so none was tried.
+    Rejected { relay: String, reply: Reply },
+    /// Every host for the route failed; the message should be retried later.
+    Unavailable { last_error: String },
+    /// Nothing is configured for the route.
+    NoRelay,
+}
+
+impl fmt::Display for RelayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected { relay, reply } => write!(f, "{relay} rejected the message: {reply}"),
+            Self::Unavailable { last_error } => {
+                write!(f, "no relay accepted the message: {last_error}")
+            }
+            Self::NoRelay => f.write_str("no relay configured"),
+        }
+    }
+}
+
+impl std::error::Error for RelayError {}
+
+pub struct Envelope<'a> {
+    /// `None` for the null sender.
+    pub mail_from: Option<&'a str>,
+    pub recipients: &'a [String],
+    /// The full message with CRLF line endings, not yet dot-stuffed.
+    pub data: &'a [u8],
+}
+
+/// Result for the recipients sharing one route.
+#[derive(Debug)]
+pub struct Outcome {
+    pub recipients: Vec<String>,
+    /// Name of the host that accepted the message.
+    pub result: Result<String, RelayError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayStatus {
+    pub name: String,
+    /// False while the host is being skipped.
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    down_until: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl Health {
+    fn is_up(&self, now: Instant) -> bool {
+        self.down_until.filter(|until| *until > now).is_none()
+    }
+}
+
+pub struct RelayPool {
+    config: RelayConfig,
+    health: Mutex<Vec<Health>>,
+}
+
+impl RelayPool {
+    /// Fails if a route names a host that is not configured.
+    pub fn new(config: RelayConfig) -> Result<Self, String> {
+        for (domain, names) in &config.routes {
+            if let Some(unknown) = names
+                .iter()
+                .find(|n| !config.hosts.iter().any(|h| &h.name == *n))
+            {
+                return Err(format!(
+                    "route for {domain} names unknown relay {unknown:?}"
+                ));
+            }
+        }
+        let health = config.hosts.iter().map(|_| Health::default()).collect();
+        Ok(Self {
+            config,
+            health: Mutex::new(health),
+        })
+    }
+
+    pub fn config(&self) -> &RelayConfig {
+        &self.config
+    }
+
+    /// Delivers to every recipient, one SMTP transaction per route. Within a
+    /// route, healthy hosts are tried in order, then skipped ones as a last
+    /// resort.
+    pub async fn send(&self, envelope: &Envelope<'_>) -> Vec<Outcome> {
+        let mut groups: Vec<(Vec<usize>, Vec<String>)> = Vec::new();
+        for rcpt in envelope.recipients {
+            let route = self.route(rcpt);
+            match groups.iter_mut().find(|(r, _)| *r == route) {
+                Some((_, rcpts)) => rcpts.push(rcpt.clone()),
+                None => groups.push((route, vec![rcpt.clone()])),
+            }
+        }
+
+        let mut outcomes = Vec::new();
+        for (route, recipients) in groups {
+            let group = Envelope {
+                mail_from: envelope.mail_from,
+                recipients: &recipients,
+                data: envelope.data,
+            };
+            let result = self.send_via(&route, &group).await;
+            outcomes.push(Outcome { recipients, result });
+        }
+        outcomes
+    }
+
+    /// Indexes into `config.hosts` for a recipient, in order of preference.
+    fn route(&self, rcpt: &str) -> Vec<usize> {
+        let domain = rcpt
+            .rsplit_once('@')
+            .map(|(_, d)| d.to_ascii_lowercase())
+            .unwrap_or_default();
+        match self.config.routes.get(&domain) {
+            Some(names) => names
+                .iter()
+                .filter_map(|n| self.config.hosts.iter().position(|h| &h.name == n))
+                .collect(),
+            None => (0..self.config.hosts.len()).collect(),
+        }
+    }
+
+    async fn send_via(
+        &self,
+        route: &[usize],
+        envelope: &Envelope<'_>,
+    ) -> Result<String, RelayError> {
+        let mut last_error = None;
+        for i in self.by_health(route) {
+            let host = &self.config.hosts[i];
+            let attempt = tokio::time::timeout(
+                self.config.timeout,
+                client::deliver(host, &self.config, envelope),
+            )
+            .await
+            .unwrap_or_else(|_| Err(client::Failure::Host("timed out".into())));
+            match attempt {
+                Ok(()) => {
+                    self.record_success(i);
+                    return Ok(host.name.clone());
+                }
+                Err(client::Failure::Rejected(reply)) => {
+                    // The host is fine; the message is not.
+                    self.record_success(i);
+                    return Err(RelayError::Rejected {
+                        relay: host.name.clone(),
+                        reply,
+                    });
+                }
+                Err(client::Failure::Host(error)) => {
+                    tracing::warn!(relay = %host.name, %error, "relay failed, trying the next");
+                    self.record_failure(i, &error);
+                    last_error = Some(format!("{}: {error}", host.name));
+                }
+            }
+        }
+        match last_error {
+            Some(last_error) => Err(RelayError::Unavailable { last_error }),
+            None => Err(RelayError::NoRelay),
+        }
+    }
+
+    /// `route` with hosts in cooldown moved to the end.
+    fn by_health(&self, route: &[usize]) -> Vec<usize> {
+        let now = Instant::now();
+        let health = self.health.lock().expect("relay health lock");
+        let (up, down): (Vec<usize>, Vec<usize>) =
+            route.iter().partition(|&&i| health[i].is_up(now));
+        up.into_iter().chain(down).collect()
+    }
+
+    fn record_success(&self, i: usize) {
+        let mut health = self.health.lock().expect("relay health lock");
+        health[i] = Health::default();
+    }
+
+    fn record_failure(&self, i: usize, error: &str) {
+        let mut health = self.health.lock().expect("relay health lock");
+        let h = &mut health[i];
+        h.consecutive_failures += 1;
+        h.last_error = Some(error.to_owned());
+        if h.consecutive_failures >= self.config.failure_threshold {
+            if h.down_until.is_none() {
+                tracing::warn!(relay = %self.config.hosts[i].name, "relay marked down");
+            }
+            h.down_until = Some(Instant::now() + self.config.cooldown);
+        }
+    }
+
+    /// Opens and quits a session with every host and records the result, so a
+    /// recovered host is used again before its cooldown ends and a dead one
+    /// is skipped before a message has to wait on it.
+    pub async fn check_health(&self) {
+        for (i, host) in self.config.hosts.iter().enumerate() {
+            let probe =
+                tokio::time::timeout(self.config.timeout, client::probe(host, &self.config))
+                    .await
+                    .unwrap_or_else(|_| Err("timed out".into()));
+            match probe {
+                Ok(()) => self.record_success(i),
+                Err(error) => self.record_failure(i, &error),
+            }
+        }
+    }
+
+    /// Runs [`check_health`](Self::check_health) every `interval`, forever.
+    pub async fn run_health_checks(&self, interval: Duration) {
+        loop {
+            self.check_health().await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    pub fn status(&self) -> Vec<RelayStatus> {
+        let now = Instant::now();
+        let health = self.health.lock().expect("relay health lock");
+        self.config
+            .hosts
+            .iter()
+            .zip(health.iter())
+            .map(|(host, h)| RelayStatus {
+                name: host.name.clone(),
+                healthy: h.is_up(now),
+                consecutive_failures: h.consecutive_failures,
+                last_error: h.last_error.clone(),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,206 @@
+use outbound_relay::{Envelope, RelayConfig, RelayError, RelayPool, RelayTls, Smarthost};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+/// A plain-text smarthost that answers `rcpt_reply` to every `RCPT TO` and
+/// sends each accepted message's raw DATA (still dot-stuffed) down the
+/// channel.
+async fn fake_relay(
+    name: &str,
+    rcpt_reply: &'static str,
+) -> (Smarthost, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind relay");
+    let port = listener.local_addr().expect("addr").port();
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (r, mut w) = stream.into_split();
+                let mut reader = BufReader::new(r);
+                w.write_all(b"220 fake relay\r\n").await.ok();
+                let mut line = String::new();
+                let mut data: Option<String> = None;
+                while reader.read_line(&mut line).await.unwrap_or(0) > 0 {
+                    if let Some(body) = data.as_mut() {
+                        if line == ".\r\n" {
+                            tx.send(data.take().unwrap()).ok();
+                            w.write_all(b"250 queued\r\n").await.ok();
+                        } else {
+                            body.push_str(&line);
+                        }
+                    } else {
+                        let reply: &[u8] = match line.get(..4).unwrap_or("") {
+                            "EHLO" => b"250-fake\r\n250 PIPELINING\r\n",
+                            "RCPT" => rcpt_reply.as_bytes(),
+                            "DATA" => {
+                                data = Some(String::new());
+                                b"354 go ahead\r\n"
+                            }
+                            "QUIT" => b"221 bye\r\n",
+                            _ => b"250 ok\r\n",
+                        };
+                        w.write_all(reply).await.ok();
+                    }
+                    line.clear();
+                }
+            });
+        }
+    });
+    let host = Smarthost {
+        name: name.into(),
+        host: "127.0.0.1".into(),
+        port,
+    };
+    (host, rx)
+}
+
+/// A port nothing listens on.
+async fn dead_relay(name: &str) -> Smarthost {
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+    let port = listener.local_addr().expect("addr").port();
+    drop(listener);
+    Smarthost {
+        name: name.into(),
+        host: "127.0.0.1".into(),
+        port,
+    }
+}
+
+fn envelope<'a>(recipients: &'a [String], data: &'a [u8]) -> Envelope<'a> {
+    Envelope {
+        mail_from: Some("me@fake-email.site"),
+        recipients,
+        data,
+    }
+}
+
+#[tokio::test]
+async fn fails_over_and_skips_a_dead_primary() {
+    let primary = dead_relay("primary").await;
+    let (backup, mut received) = fake_relay("backup", "250 ok\r\n").await;
+    let pool = RelayPool::new(RelayConfig {
+        hosts: vec![primary, backup],
+        failure_threshold: 2,
+        cooldown: Duration::from_secs(600),
+        tls: RelayTls::None,
+        ..Default::default()
+    })
+    .expect("pool");
+
+    let rcpts = vec!["a@ok.test".to_owned()];
+    for _ in 0..3 {
+        let outcomes = pool
+            .send(&envelope(&rcpts, b"Subject: hi\r\n\r\nhi\r\n"))
+            .await;
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].result.as_ref().expect("delivered"), "backup");
+        assert!(received
+            .recv()
+            .await
+            .expect("message")
+            .contains("Subject: hi"));
+    }
+
+    let status = pool.status();
+    assert!(!status[0].healthy, "{status:?}");
+    // Skipped once down, so it stopped collecting failures.
+    assert_eq!(status[0].consecutive_failures, 2);
+    assert!(status[1].healthy);
+}
+
+#[tokio::test]
+async fn routes_domains_to_their_own_relays() {
+    let (primary, mut to_primary) = fake_relay("primary", "250 ok\r\n").await;
+    let (backup, mut to_backup) = fake_relay("backup", "250 ok\r\n").await;
+    let pool = RelayPool::new(RelayConfig {
+        hosts: vec![primary, backup],
+        routes: outbound_relay::parse_routes("Special.test=backup").expect("routes"),
+        tls: RelayTls::None,
+        ..Default::default()
+    })
+    .expect("pool");
+
+    let rcpts = vec!["a@ok.test".to_owned(), "b@special.test".to_owned()];
+    let outcomes = pool.send(&envelope(&rcpts, b"\r\n.hidden\r\nend")).await;
+    assert_eq!(outcomes.len(), 2);
+    assert_eq!(outcomes[0].recipients, ["a@ok.test"]);
+    assert_eq!(outcomes[0].result.as_ref().expect("primary"), "primary");
+    assert_eq!(outcomes[1].recipients, ["b@special.test"]);
+    assert_eq!(outcomes[1].result.as_ref().expect("backup"), "backup");
+
+    // Leading dots are stuffed and the data is CRLF-terminated.
+    assert_eq!(
+        to_primary.recv().await.expect("message"),
+        "\r\n..hidden\r\nend\r\n"
+    );
+    assert_eq!(
+        to_backup.recv().await.expect("message"),
+        "\r\n..hidden\r\nend\r\n"
+    );
+}
+
+#[tokio::test]
+async fn permanent_rejections_do_not_fail_over() {
+    let (primary, _) = fake_relay("primary", "550 5.1.1 no such user\r\n").await;
+    let (backup, mut to_backup) = fake_relay("backup", "250 ok\r\n").await;
+    let pool = RelayPool::new(RelayConfig {
+        hosts: vec![primary, backup],
+        failure_threshold: 1,
+        tls: RelayTls::None,
+        ..Default::default()
+    })
+    .expect("pool");
+
+    let rcpts = vec!["nobody@ok.test".to_owned()];
+    let outcomes = pool.send(&envelope(&rcpts, b"hi\r\n")).await;
+    match &outcomes[0].result {
+        Err(RelayError::Rejected { relay, reply }) => {
+            assert_eq!(relay, "primary");
+            assert_eq!(reply.code, 550);
+        }
+        other => panic!("expected a rejection, got {other:?}"),
+    }
+    assert!(pool.status()[0].healthy);
+    assert!(to_backup.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn required_tls_refuses_hosts_without_starttls() {
+    let (primary, mut received) = fake_relay("primary", "250 ok\r\n").await;
+    let pool = RelayPool::new(RelayConfig {
+        hosts: vec![primary],
+        ..Default::default()
+    })
+    .expect("pool");
+
+    let rcpts = vec!["a@ok.test".to_owned()];
+    let outcomes = pool.send(&envelope(&rcpts, b"secret\r\n")).await;
+    assert!(
+        matches!(outcomes[0].result, Err(RelayError::Unavailable { .. })),
+        "{outcomes:?}"
+    );
+    assert!(received.try_recv().is_err());
+}
+
+#[test]
+fn parses_hosts_and_rejects_unknown_route_targets() {
+    let hosts =
+        outbound_relay::parse_hosts("primary=smtp.a.test:587, backup=10.0.0.2:25").expect("hosts");
+    assert_eq!(hosts[1].host, "10.0.0.2");
+    assert_eq!(hosts[1].port, 25);
+    assert!(outbound_relay::parse_hosts("primary=smtp.a.test").is_err());
+
+    let routes = outbound_relay::parse_routes("gmail.com=tertiary").expect("routes");
+    assert!(RelayPool::new(RelayConfig {
+        hosts,
+        routes,
+        ..Default::default()
+    })
+    .is_err());
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PRUNE_AT: usize = 10_000;
+const MESSAGE_WINDOW: Duration = Duration::from_secs(60);
+
+struct Entry {
+    window_start: Instant,
+    rejections: u32,
+    blocked_until: Option<Instant>,
+}
+
+// Counts unknown-recipient rejections per peer IP so dictionary harvesters
+// get cut off at accept time instead of probing the whole namespace.
+pub(crate) struct UnknownRecipientThrottle {
+    threshold: u32,
+    window: Duration,
+    block_for: Duration,
+    entries: Mutex<HashMap<IpAddr, Entry>>,
+}
+
+impl UnknownRecipientThrottle {
+    pub(crate) fn new(threshold: u32, window: Duration, block_for: Duration) -> Self {
+        Self {
+            threshold,
+            window,
+            block_for,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn is_blocked(&self, ip: IpAddr) -> bool {
+        let entries = self.entries.lock().expect("throttle lock");
+        entries
+            .get(&ip)
+            .and_then(|e| e.blocked_until)
+            .is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Returns true when this rejection tipped the IP over the threshold.
+    pub(crate) fn record_rejection(&self, ip: IpAddr) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("throttle lock");
+        if entries.len() >= PRUNE_AT {
+            let (window, block_for) = (self.window, self.block_for);
+            entries.retain(|_, e| {
+                now.duration_since(e.window_start) < window.max(block_for)
+                    || e.blocked_until.is_some_and(|until| now < until)
+            });
+        }
+
+        let entry = entries.entry(ip).or_insert(Entry {
+            window_start: now,
+            rejections: 0,
+            blocked_until: None,
+        });
+        if now.duration_since(entry.window_start) >= self.window {
+            entry.window_start = now;
+            entry.rejections = 0;
+        }
+        entry.rejections += 1;
+        if entry.rejections >= self.threshold && entry.blocked_until.is_none_or(|u| u <= now) {
+            entry.blocked_until = Some(now + self.block_for);
+            entry.rejections = 0;
+            return true;
+        }
+        false
+    }
+}
+
+struct Peer {
+    connections: usize,
+    window_start: Instant,
+    messages: u32,
+}
+
+// Open connections and recent messages per peer IP, so a single sender
+// cannot spawn unbounded sessions or flood the database.
+pub(crate) struct PeerLimits {
+    max_connections: usize,
+    max_messages_per_minute: u32,
+    peers: Mutex<HashMap<IpAddr, Peer>>,
+}
+
+impl PeerLimits {
+    pub(crate) fn new(max_connections: usize, max_messages_per_minute: u32) -> Self {
+        Self {
+            max_connections,
+            max_messages_per_minute,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new connection from `ip`, or `None` if it already has as
+    /// many open as allowed. The connection counts until the slot is dropped.
+    pub(crate) fn connect(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionSlot> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock().expect("peer limits lock");
+        if peers.len() >= PRUNE_AT {
+            peers.retain(|_, p| {
+                p.connections > 0 || now.duration_since(p.window_start) < MESSAGE_WINDOW
+            });
+        }
+        let peer = peers.entry(ip).or_insert(Peer {
+            connections: 0,
+            window_start: now,
+            messages: 0,
+        });
+        if self.max_connections > 0 && peer.connections >= self.max_connections {
+            return None;
+        }
+        peer.connections += 1;
+        Some(ConnectionSlot {
+            limits: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Counts a message from `ip`; false once it has started more than
+    /// allowed in the last minute.
+    pub(crate) fn allow_message(&self, ip: IpAddr) -> bool {
+        if self.max_messages_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut peers = self.peers.lock().expect("peer limits lock");
+        let Some(peer) = peers.get_mut(&ip) else {
+            return true;
+        };
+        if now.duration_since(peer.window_start) >= MESSAGE_WINDOW {
+            peer.window_start = now;
+            peer.messages = 0;
+        }
+        if peer.messages >= self.max_messages_per_minute {
+            return false;
+        }
+        peer.messages += 1;
+        true
+    }
+}
+
+/// One open connection, counted against its peer until dropped.
+pub(crate) struct ConnectionSlot {
+    limits: Arc<PeerLimits>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut peers = self.limits.peers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(peer) = peers.get_mut(&self.ip) {
+            peer.connections = peer.connections.saturating_sub(1);
+            if peer.connections == 0 && peer.window_start.elapsed() >= MESSAGE_WINDOW {
+                peers.remove(&self.ip);
+            }
+        }
+    }
+}
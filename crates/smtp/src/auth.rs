@@ -0,0 +1,174 @@
+//! `AUTH PLAIN` and `AUTH LOGIN` (RFC 4954) for deployments that only take
+//! mail from known submitters. Credentials come from config (plaintext, for
+//! small setups) or the `smtp_user` table (Argon2 hashes).
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::fmt;
+
+#[derive(Clone, Default)]
+pub struct SmtpAuth {
+    /// `MAIL FROM` is answered with `530` until the session authenticates.
+    /// `AUTH` is only advertised when this is set.
+    pub required: bool,
+    /// `(username, password)` pairs checked before the `smtp_user` table.
+    pub users: Vec<(String, String)>,
+}
+
+impl fmt::Debug for SmtpAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.users.iter().map(|(u, _)| u.as_str()).collect();
+        f.debug_struct("SmtpAuth")
+            .field("required", &self.required)
+            .field("users", &names)
+            .finish()
+    }
+}
+
+/// Parses `user:password,user2:password2`. Passwords may contain `:` but not
+/// `,`.
+pub fn parse_users(raw: &str) -> Result<Vec<(String, String)>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((user, password)) if !user.is_empty() && !password.is_empty() => {
+                Ok((user.to_owned(), password.to_owned()))
+            }
+            _ => Err(format!("expected user:password, got {entry:?}")),
+        })
+        .collect()
+}
+
+/// Argon2id hash in PHC format, as stored in `smtp_user.password_hash`.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// What a client sent after `AUTH`, once decoded.
+pub(crate) enum Step {
+    /// Send `334 <challenge>` and feed the answer to [`Mechanism::answer`].
+    Challenge(&'static str),
+    Credentials {
+        username: String,
+        password: String,
+    },
+}
+
+pub(crate) enum Mechanism {
+    Plain,
+    /// `username` once the first answer is in.
+    Login {
+        username: Option<String>,
+    },
+}
+
+pub(crate) enum AuthError {
+    /// Not base64 or not the shape the mechanism expects: `501`.
+    Malformed,
+    /// Neither `PLAIN` nor `LOGIN`: `504`.
+    Unsupported,
+}
+
+/// `334` challenges of `LOGIN`: base64 of `Username:` and `Password:`.
+const LOGIN_USERNAME: &str = "VXNlcm5hbWU6";
+const LOGIN_PASSWORD: &str = "UGFzc3dvcmQ6";
+
+impl Mechanism {
+    /// Starts `AUTH <mechanism> [initial-response]`.
+    pub fn start(args: &str) -> Result<(Self, Step), AuthError> {
+        let mut words = args.split_whitespace();
+        let name = words.next().unwrap_or_default().to_ascii_uppercase();
+        let initial = words.next();
+        let mut mechanism = match name.as_str() {
+            "PLAIN" => Self::Plain,
+            "LOGIN" => Self::Login { username: None },
+            _ => return Err(AuthError::Unsupported),
+        };
+        let step = match initial {
+            Some(response) => mechanism.answer(response)?,
+            None if matches!(mechanism, Self::Plain) => Step::Challenge(""),
+            None => Step::Challenge(LOGIN_USERNAME),
+        };
+        Ok((mechanism, step))
+    }
+
+    /// Takes the client's base64 answer to the last challenge. `=` is an
+    /// empty initial response (RFC 4954 section 4).
+    pub fn answer(&mut self, response: &str) -> Result<Step, AuthError> {
+        let decoded = match response.trim() {
+            "=" => Vec::new(),
+            response => BASE64.decode(response).map_err(|_| AuthError::Malformed)?,
+        };
+        let decoded = String::from_utf8(decoded).map_err(|_| AuthError::Malformed)?;
+        match self {
+            // authzid NUL authcid NUL passwd; a differing authzid is refused.
+            Self::Plain => {
+                let mut parts = decoded.split('\0');
+                let (Some(authzid), Some(username), Some(password), None) =
+                    (parts.next(), parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(AuthError::Malformed);
+                };
+                if !authzid.is_empty() && authzid != username {
+                    return Err(AuthError::Malformed);
+                }
+                Ok(Step::Credentials {
+                    username: username.to_owned(),
+                    password: password.to_owned(),
+                })
+            }
+            Self::Login { username: None } => {
+                *self = Self::Login {
+                    username: Some(decoded),
+                };
+                Ok(Step::Challenge(LOGIN_PASSWORD))
+            }
+            Self::Login {
+                username: Some(username),
+            } => Ok(Step::Credentials {
+                username: std::mem::take(username),
+                password: decoded,
+            }),
+        }
+    }
+}
+
+/// Checks the credentials against config, then the `smtp_user` table.
+pub(crate) async fn verify(
+    auth: &SmtpAuth,
+    pool: &PgPool,
+    username: &str,
+    password: &str,
+) -> Result<bool, sqlx::Error> {
+    if let Some((_, expected)) = auth.users.iter().find(|(u, _)| u == username) {
+        // Comparing digests keeps the timing independent of where the
+        // passwords first differ.
+        return Ok(Sha256::digest(expected) == Sha256::digest(password));
+    }
+    let Some(stored) = db::find_smtp_user_password_hash(pool, username).await? else {
+        return Ok(false);
+    };
+    let password = password.to_owned();
+    let verified = tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&stored).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false);
+    Ok(verified)
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use db::{BlocklistCache, BodyCompression, MailboxQuota};
+
+use crate::auth::SmtpAuth;
+use crate::events::IngestEvents;
+use crate::geoip::GeoIp;
+use crate::greeting::EarlyTalkers;
+use crate::helo::HeloPolicy;
+use crate::pipeline::Pipeline;
+
+#[derive(Debug, Clone)]
+pub struct SmtpConfig {
+    /// Unknown-recipient rejections from one IP (within `unknown_rcpt_window`)
+    /// before that IP is turned away at connect time. 0 disables the throttle.
+    pub unknown_rcpt_threshold: u32,
+    pub unknown_rcpt_window: Duration,
+    pub abuse_block_duration: Duration,
+    /// Connections one IP may have open at once; more are answered with
+    /// `421` at connect. 0 disables.
+    pub max_connections_per_ip: usize,
+    /// `MAIL FROM`s one IP may send per minute; more get `450`. 0 disables.
+    pub max_messages_per_ip_per_minute: u32,
+    /// Where ingestion outcomes are published; subscribe to a clone of this
+    /// before calling `serve` to watch live traffic.
+    pub events: IngestEvents,
+    /// Local parts catch-all domains do not create; share it with the HTTP
+    /// API so that its edits reach `RCPT TO` at once.
+    pub blocklist: Arc<BlocklistCache>,
+    pub body_compression: BodyCompression,
+    /// Value of our `X-Loop` header; mail arriving with it is dropped.
+    pub loop_marker: String,
+    /// Messages with more `Received:` headers than this are rejected. 0 disables.
+    pub max_hops: usize,
+    /// Host name in the greeting and EHLO reply. Tenants with their own
+    /// banner domain see theirs once a recipient of theirs is accepted.
+    pub banner_domain: String,
+    /// Further `RCPT TO`s in one transaction get `452`. 0 disables.
+    pub max_recipients: usize,
+    /// Messages accepted per connection before the next `MAIL FROM` is
+    /// answered with `421` and the connection closed. 0 disables.
+    pub max_messages_per_session: usize,
+    /// Commands read on one connection before it is answered with `421`
+    /// and closed. 0 disables.
+    pub max_commands_per_session: usize,
+    /// How long the client may go without sending the next command, or the
+    /// next piece of a message, before it gets `421` and is disconnected.
+    pub idle_timeout: Duration,
+    /// Longest a connection may last in all, however busy.
+    pub session_timeout: Duration,
+    /// Pause before the greeting; clients that talk during it are handled
+    /// as `early_talkers` says. Zero greets at once.
+    pub greeting_delay: Duration,
+    pub early_talkers: EarlyTalkers,
+    /// What to do about `EHLO`/`HELO` arguments that are malformed, a bare
+    /// IP address, or `banner_domain`.
+    pub helo_policy: HeloPolicy,
+    /// Answer unknown recipients with `550` at `RCPT TO`. When off they get
+    /// `250`, the message is still read, and then dropped for them.
+    pub reject_unknown_at_rcpt: bool,
+    /// Recipient domains mail is accepted for, besides those with a routing
+    /// rule. Others get `550 Relay not permitted`; empty accepts any domain.
+    pub local_domains: Vec<String>,
+    /// Treat `local_domains` (every domain, when it is empty) without a
+    /// routing rule as `catch_all`: any local part is accepted and its
+    /// address created on first delivery.
+    pub catch_all: bool,
+    /// Deadline for parsing one message. Overruns and parser panics put the
+    /// message in quarantine instead of storing it.
+    pub processing_timeout: Duration,
+    /// Failures of the same message before it is refused with `554` without
+    /// being processed again; earlier failures get `451` so a sender retries.
+    pub poison_threshold: u32,
+    /// Largest message accepted, in bytes, advertised as `SIZE`. Longer
+    /// messages are discarded as they stream in and answered with `552`.
+    /// 0 disables.
+    pub max_message_size: usize,
+    /// Recipients whose mailbox holds this much already get `552` at
+    /// `RCPT TO`. Unlimited by default.
+    pub mailbox_quota: MailboxQuota,
+    /// SMTP AUTH; off by default, so anyone may send to known addresses.
+    pub auth: SmtpAuth,
+    /// Check the sender's SPF record for each stored message. Off by
+    /// default as it queries DNS.
+    pub check_spf: bool,
+    /// Verify the DKIM signatures of each stored message; also queries DNS.
+    pub check_dkim: bool,
+    /// Evaluate the `From:` domain's DMARC policy on the SPF and DKIM
+    /// results; without those checks it has less to go on.
+    pub check_dmarc: bool,
+    /// Looks up the country of each client for ingestion events and stored
+    /// messages.
+    pub geoip: Option<Arc<GeoIp>>,
+    /// Built-in ingestion stages to skip and stages to add before mail is
+    /// stored.
+    pub pipeline: Pipeline,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        Self {
+            unknown_rcpt_threshold: 20,
+            unknown_rcpt_window: Duration::from_secs(10 * 60),
+            abuse_block_duration: Duration::from_secs(60 * 60),
+            max_connections_per_ip: 10,
+            max_messages_per_ip_per_minute: 60,
+            events: IngestEvents::default(),
+            blocklist: Arc::default(),
+            body_compression: BodyCompression::default(),
+            loop_marker: "fake-email".into(),
+            max_hops: 50,
+            banner_domain: "fake-email".into(),
+            max_recipients: 50,
+            max_messages_per_session: 100,
+            max_commands_per_session: 1000,
+            // RFC 5321 section 4.5.3.2 suggests five minutes.
+            idle_timeout: Duration::from_secs(5 * 60),
+            session_timeout: Duration::from_secs(30 * 60),
+            greeting_delay: Duration::ZERO,
+            early_talkers: EarlyTalkers::default(),
+            helo_policy: HeloPolicy::default(),
+            reject_unknown_at_rcpt: true,
+            local_domains: Vec::new(),
+            catch_all: false,
+            processing_timeout: Duration::from_secs(30),
+            poison_threshold: 2,
+            max_message_size: 10 * 1024 * 1024,
+            mailbox_quota: MailboxQuota::default(),
+            auth: SmtpAuth::default(),
+            check_spf: false,
+            check_dkim: false,
+            check_dmarc: false,
+            geoip: None,
+            pipeline: Pipeline::default(),
+        }
+    }
+}
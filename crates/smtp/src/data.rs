@@ -0,0 +1,84 @@
+//! The `DATA` phase: message content up to the terminating `.` line, read as
+//! raw bytes so 8-bit and binary content reaches the parser unchanged.
+
+use bytes::BytesMut;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Longest piece of a line read at once. Longer lines are not an error here
+/// (only the message size is capped); they just arrive in several pieces.
+const CHUNK_LEN: u64 = 64 * 1024;
+
+pub(crate) enum Message {
+    /// Dot-unstuffed content with CRLF line endings.
+    Complete(BytesMut),
+    /// The content went past the size limit. The rest was read and thrown
+    /// away; `size` counts all of it.
+    TooLarge { size: usize },
+}
+
+/// Reads one message. `None` means the peer hung up before the final `.`.
+/// `max_size` of 0 disables the limit. A client that sends nothing for
+/// `idle` fails the read with `TimedOut`.
+pub(crate) async fn read_message<R>(
+    reader: &mut R,
+    max_size: usize,
+    idle: Duration,
+) -> std::io::Result<Option<Message>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut data = BytesMut::new();
+    let mut size = 0;
+    let mut oversized = false;
+    let mut piece = Vec::new();
+    // Whether `piece` starts a line, so a leading dot is significant.
+    let mut line_start = true;
+
+    loop {
+        piece.clear();
+        let mut chunk = (&mut *reader).take(CHUNK_LEN);
+        let n = crate::within_idle(idle, chunk.read_until(b'\n', &mut piece)).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        let complete = piece.ends_with(b"\n");
+        let mut content = piece.as_slice();
+        if complete {
+            content = content.strip_suffix(b"\n").unwrap_or(content);
+            content = content.strip_suffix(b"\r").unwrap_or(content);
+        }
+        if line_start {
+            if complete && content == b"." {
+                return Ok(Some(if oversized {
+                    Message::TooLarge { size }
+                } else {
+                    Message::Complete(data)
+                }));
+            }
+            content = content.strip_prefix(b".").unwrap_or(content);
+        }
+        line_start = complete;
+
+        // A CR that ended the previous piece was the first half of this CRLF.
+        let split_crlf = complete && content.is_empty() && data.ends_with(b"\r");
+        let ending: &[u8] = match (complete, split_crlf) {
+            (false, _) => b"",
+            (true, false) => b"\r\n",
+            (true, true) => b"\n",
+        };
+        size += content.len() + ending.len();
+        if oversized {
+            continue;
+        }
+        if max_size > 0 && size > max_size {
+            // Keep reading to the terminating dot so the rest of the message
+            // isn't taken for commands, but stop storing it.
+            oversized = true;
+            data = BytesMut::new();
+            continue;
+        }
+        data.extend_from_slice(content);
+        data.extend_from_slice(ending);
+    }
+}
@@ -0,0 +1,426 @@
+//! DKIM (RFC 6376): every `DKIM-Signature` header of a message is checked
+//! against the key its signer publishes in DNS. As with SPF the verdicts
+//! are only recorded with the message.
+//!
+//! `rsa-sha256` and `ed25519-sha256` (RFC 8463) are verified; `rsa-sha1`
+//! signatures are reported as unusable, as RFC 8301 asks.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use db::{DkimResult, NewDkimSignature};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::dns::Dns;
+
+/// Signatures checked per message; any beyond are ignored.
+const MAX_SIGNATURES: usize = 5;
+const MIN_RSA_BITS: usize = 1024;
+const KEY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The verdict on one signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verdict {
+    pub domain: String,
+    pub selector: String,
+    pub result: DkimResult,
+    /// Why it did not pass.
+    pub reason: Option<&'static str>,
+}
+
+impl Verdict {
+    pub fn as_new(&self) -> NewDkimSignature<'_> {
+        NewDkimSignature {
+            domain: &self.domain,
+            selector: &self.selector,
+            result: self.result,
+            reason: self.reason,
+        }
+    }
+}
+
+/// Checks the signatures of `message`, which has CRLF line endings, in
+/// the order they appear. A message without any yields nothing.
+pub async fn verify<D: Dns>(dns: &D, message: &[u8]) -> Vec<Verdict> {
+    let (fields, body) = split_message(message);
+    let mut verdicts = Vec::new();
+    for field in fields
+        .iter()
+        .filter(|f| field_name(f).eq_ignore_ascii_case(b"dkim-signature"))
+        .take(MAX_SIGNATURES)
+    {
+        let field = String::from_utf8_lossy(field);
+        let tags = parse_tags(field_value(&field)).unwrap_or_default();
+        let tag = |name: &str| tags.get(name).cloned().unwrap_or_default();
+        let (domain, selector) = (tag("d").to_ascii_lowercase(), tag("s"));
+        let (result, reason) = match check(dns, &field, &fields, body).await {
+            Ok(()) => (DkimResult::Pass, None),
+            Err((result, reason)) => (result, Some(reason)),
+        };
+        verdicts.push(Verdict {
+            domain,
+            selector,
+            result,
+            reason,
+        });
+    }
+    verdicts
+}
+
+type Failure = (DkimResult, &'static str);
+
+fn perm(reason: &'static str) -> Failure {
+    (DkimResult::PermError, reason)
+}
+
+fn fail(reason: &'static str) -> Failure {
+    (DkimResult::Fail, reason)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Algorithm {
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Canon {
+    Simple,
+    Relaxed,
+}
+
+struct Signature {
+    algorithm: Algorithm,
+    signature: Vec<u8>,
+    body_hash: Vec<u8>,
+    header_canon: Canon,
+    body_canon: Canon,
+    domain: String,
+    selector: String,
+    /// `h=`, lowercased.
+    headers: Vec<String>,
+    /// `l=`: how much of the body is signed.
+    length: Option<usize>,
+}
+
+async fn check<D: Dns>(dns: &D, field: &str, fields: &[&[u8]], body: &[u8]) -> Result<(), Failure> {
+    let sig = parse_signature(field)?;
+
+    let body = canon_body(body, sig.body_canon);
+    let signed_body = match sig.length {
+        Some(len) => body.get(..len).ok_or(fail("body is shorter than l="))?,
+        None => &body[..],
+    };
+    if Sha256::digest(signed_body)[..] != sig.body_hash[..] {
+        return Err(fail("body hash mismatch"));
+    }
+
+    let key = fetch_key(dns, &sig).await?;
+    let digest = Sha256::digest(signed_headers(&sig, field, fields));
+    let valid = match (sig.algorithm, key) {
+        (Algorithm::RsaSha256, Key::Rsa(key)) => key
+            .verify(Pkcs1v15Sign::new::<Sha256>(), &digest, &sig.signature)
+            .is_ok(),
+        (Algorithm::Ed25519Sha256, Key::Ed25519(key)) => {
+            let signature = ed25519_dalek::Signature::from_slice(&sig.signature)
+                .map_err(|_| perm("malformed ed25519 signature"))?;
+            key.verify_strict(&digest, &signature).is_ok()
+        }
+        _ => return Err(perm("key type does not match a=")),
+    };
+    if !valid {
+        return Err(fail("signature does not verify"));
+    }
+    Ok(())
+}
+
+fn parse_signature(field: &str) -> Result<Signature, Failure> {
+    let tags = parse_tags(field_value(field)).ok_or(perm("malformed tag list"))?;
+    let required = |name: &str| tags.get(name).ok_or(perm("required tag missing"));
+
+    if required("v")? != "1" {
+        return Err(perm("unsupported version"));
+    }
+    let algorithm = match required("a")?.to_ascii_lowercase().as_str() {
+        "rsa-sha256" => Algorithm::RsaSha256,
+        "ed25519-sha256" => Algorithm::Ed25519Sha256,
+        "rsa-sha1" => return Err(perm("rsa-sha1 is not accepted")),
+        _ => return Err(perm("unknown algorithm")),
+    };
+    let (header_canon, body_canon) = match tags.get("c") {
+        None => (Canon::Simple, Canon::Simple),
+        Some(c) => {
+            let c = c.to_ascii_lowercase();
+            let (header, body) = c.split_once('/').unwrap_or((c.as_str(), "simple"));
+            (parse_canon(header)?, parse_canon(body)?)
+        }
+    };
+    let domain = required("d")?.to_ascii_lowercase();
+    let selector = required("s")?.clone();
+    let headers: Vec<String> = required("h")?
+        .split(':')
+        .map(|h| h.trim().to_ascii_lowercase())
+        .collect();
+    if !headers.iter().any(|h| h == "from") {
+        return Err(perm("From is not signed"));
+    }
+    if let Some(identity) = tags.get("i") {
+        let identity_domain = identity.rsplit_once('@').map_or("", |(_, d)| d);
+        let identity_domain = identity_domain.to_ascii_lowercase();
+        if identity_domain != domain && !identity_domain.ends_with(&format!(".{domain}")) {
+            return Err(perm("i= is not within d="));
+        }
+    }
+    if let Some(expires) = tags.get("x") {
+        let expires: i64 = expires.parse().map_err(|_| perm("malformed x="))?;
+        if expires < chrono::Utc::now().timestamp() {
+            return Err(perm("signature expired"));
+        }
+    }
+    let length = tags
+        .get("l")
+        .map(|l| l.parse().map_err(|_| perm("malformed l=")))
+        .transpose()?;
+    Ok(Signature {
+        algorithm,
+        signature: decode_base64(required("b")?)?,
+        body_hash: decode_base64(required("bh")?)?,
+        header_canon,
+        body_canon,
+        domain,
+        selector,
+        headers,
+        length,
+    })
+}
+
+fn parse_canon(s: &str) -> Result<Canon, Failure> {
+    match s {
+        "simple" => Ok(Canon::Simple),
+        "relaxed" => Ok(Canon::Relaxed),
+        _ => Err(perm("unknown canonicalization")),
+    }
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, Failure> {
+    let compact: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    BASE64.decode(compact).map_err(|_| perm("malformed base64"))
+}
+
+enum Key {
+    Rsa(RsaPublicKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
+}
+
+async fn fetch_key<D: Dns>(dns: &D, sig: &Signature) -> Result<Key, Failure> {
+    let name = format!("{}._domainkey.{}", sig.selector, sig.domain);
+    let records = tokio::time::timeout(KEY_TIMEOUT, dns.txt(&name))
+        .await
+        .map_err(|_| (DkimResult::TempError, "key lookup timed out"))?
+        .map_err(|_| (DkimResult::TempError, "key lookup failed"))?;
+    let tags = records
+        .iter()
+        .find_map(|record| parse_tags(record))
+        .ok_or(perm("no key published"))?;
+    if tags.get("v").is_some_and(|v| v != "DKIM1") {
+        return Err(perm("unsupported key version"));
+    }
+    let data = tags.get("p").ok_or(perm("key record without p="))?;
+    if data.is_empty() {
+        return Err(perm("key revoked"));
+    }
+    let data = decode_base64(data)?;
+    match tags.get("k").map_or("rsa", String::as_str) {
+        "rsa" => {
+            let key = RsaPublicKey::from_public_key_der(&data)
+                .or_else(|_| RsaPublicKey::from_pkcs1_der(&data))
+                .map_err(|_| perm("malformed rsa key"))?;
+            if key.size() * 8 < MIN_RSA_BITS {
+                return Err(perm("rsa key too short"));
+            }
+            Ok(Key::Rsa(key))
+        }
+        "ed25519" => {
+            let bytes: [u8; 32] = data.try_into().map_err(|_| perm("malformed ed25519 key"))?;
+            ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+                .map(Key::Ed25519)
+                .map_err(|_| perm("malformed ed25519 key"))
+        }
+        _ => Err(perm("unknown key type")),
+    }
+}
+
+/// What the header hash covers: the fields named in `h=`, each taken from
+/// the bottom up, then the signature field itself with `b=` emptied and no
+/// final line break.
+fn signed_headers(sig: &Signature, own: &str, fields: &[&[u8]]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut used: HashMap<&str, usize> = HashMap::new();
+    for name in &sig.headers {
+        let instances: Vec<&[u8]> = fields
+            .iter()
+            .copied()
+            .filter(|f| field_name(f).eq_ignore_ascii_case(name.as_bytes()))
+            .collect();
+        let taken = used.entry(name.as_str()).or_default();
+        // A name listed more often than it occurs signs its absence.
+        if let Some(field) = instances.iter().rev().nth(*taken) {
+            data.extend(canon_header(field, sig.header_canon));
+            *taken += 1;
+        }
+    }
+    let own = canon_header(without_signature(own).as_bytes(), sig.header_canon);
+    data.extend(own.strip_suffix(b"\r\n").unwrap_or(&own[..]));
+    data
+}
+
+/// The field with the value of its `b=` tag removed.
+fn without_signature(field: &str) -> String {
+    let Some((name, value)) = field.split_once(':') else {
+        return field.to_owned();
+    };
+    let value: Vec<&str> = value
+        .split(';')
+        .map(|tag| match tag.split_once('=') {
+            Some((tag_name, _)) if tag_name.trim() == "b" => &tag[..=tag_name.len()],
+            _ => tag,
+        })
+        .collect();
+    let mut stripped = format!("{name}:{}", value.join(";"));
+    if field.ends_with("\r\n") && !stripped.ends_with("\r\n") {
+        stripped.push_str("\r\n");
+    }
+    stripped
+}
+
+fn canon_header(field: &[u8], canon: Canon) -> Vec<u8> {
+    if canon == Canon::Simple {
+        return field.to_vec();
+    }
+    let colon = field.iter().position(|&b| b == b':').unwrap_or(field.len());
+    let name = field[..colon].trim_ascii().to_ascii_lowercase();
+    let value = field.get(colon + 1..).unwrap_or_default();
+    let unfolded: Vec<u8> = value
+        .iter()
+        .copied()
+        .filter(|&b| b != b'\r' && b != b'\n')
+        .collect();
+    let mut out = name;
+    out.push(b':');
+    out.extend(compress_wsp(unfolded.trim_ascii()));
+    out.extend(b"\r\n");
+    out
+}
+
+fn canon_body(body: &[u8], canon: Canon) -> Vec<u8> {
+    let mut out = match canon {
+        Canon::Simple => body.to_vec(),
+        Canon::Relaxed => {
+            let lines: Vec<Vec<u8>> = split_lines(body)
+                .map(|line| {
+                    let mut line = compress_wsp(line);
+                    while line.last() == Some(&b' ') {
+                        line.pop();
+                    }
+                    line
+                })
+                .collect();
+            lines.join(&b"\r\n"[..])
+        }
+    };
+    while out.ends_with(b"\r\n") {
+        out.truncate(out.len() - 2);
+    }
+    if canon == Canon::Simple || !out.is_empty() {
+        out.extend(b"\r\n");
+    }
+    out
+}
+
+fn split_lines(body: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = Some(body);
+    std::iter::from_fn(move || {
+        let current = rest?;
+        match current.windows(2).position(|w| w == b"\r\n") {
+            Some(i) => {
+                rest = Some(&current[i + 2..]);
+                Some(&current[..i])
+            }
+            None => {
+                rest = None;
+                Some(current)
+            }
+        }
+    })
+}
+
+/// Runs of spaces and tabs become one space.
+fn compress_wsp(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for &b in bytes {
+        let wsp = b == b' ' || b == b'\t';
+        if !wsp {
+            out.push(b);
+        } else if out.last() != Some(&b' ') {
+            out.push(b' ');
+        }
+    }
+    out
+}
+
+/// Header fields as sent, folding and final CRLF included, and the body.
+pub(crate) fn split_message(message: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let (header, body) = match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => (&message[..i + 2], &message[i + 4..]),
+        None => (message, &[][..]),
+    };
+    let mut fields: Vec<&[u8]> = Vec::new();
+    let mut start = 0;
+    for (i, pair) in header.windows(2).enumerate() {
+        let end = i + 2;
+        let folded = matches!(header.get(end), Some(b' ' | b'\t'));
+        if pair == b"\r\n" && !folded {
+            fields.push(&header[start..end]);
+            start = end;
+        }
+    }
+    if start < header.len() {
+        fields.push(&header[start..]);
+    }
+    (fields, body)
+}
+
+pub(crate) fn field_name(field: &[u8]) -> &[u8] {
+    let colon = field.iter().position(|&b| b == b':').unwrap_or(0);
+    field[..colon].trim_ascii()
+}
+
+fn field_value(field: &str) -> &str {
+    field.split_once(':').map_or("", |(_, value)| value)
+}
+
+/// `tag=value; tag=value`, whitespace around names and values dropped.
+/// `None` if a tag is malformed or repeated.
+pub(crate) fn parse_tags(list: &str) -> Option<HashMap<String, String>> {
+    let mut tags = HashMap::new();
+    for tag in list.split(';') {
+        if tag.trim().is_empty() {
+            continue;
+        }
+        let (name, value) = tag.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        if tags.insert(name.to_owned(), value).is_some() {
+            return None;
+        }
+    }
+    Some(tags)
+}
@@ -0,0 +1,175 @@
+//! DMARC (RFC 7489): whether SPF or DKIM passed for a domain aligned with
+//! the `From:` header, under the policy that domain publishes. Like the
+//! checks it builds on, the verdict is only recorded with the message; the
+//! policy's `p=` is never enforced.
+//!
+//! The organizational domain is approximated as the last two labels, or
+//! three under common country-code second levels like `co.uk`, instead of
+//! consulting the Public Suffix List.
+
+use db::{DkimResult, DmarcResult, SpfResult};
+use std::time::Duration;
+
+use crate::dkim::{self, Verdict};
+use crate::dns::Dns;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Second-level labels under two-letter TLDs that registries hand out
+/// names below, e.g. `example.co.uk`.
+const COUNTRY_SECOND_LEVELS: &[&str] = &["ac", "co", "com", "edu", "gov", "ne", "net", "or", "org"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Alignment {
+    Relaxed,
+    Strict,
+}
+
+/// Evaluates `message`, which has CRLF line endings, given the SPF result
+/// for `mail_from` (or `helo` for bounces) and the DKIM verdicts. Without
+/// a single `From:` domain, or a published policy, the result is `none`.
+pub async fn evaluate<D: Dns>(
+    dns: &D,
+    message: &[u8],
+    mail_from: Option<&str>,
+    helo: &str,
+    spf: Option<SpfResult>,
+    dkim: &[Verdict],
+) -> DmarcResult {
+    let Some(from) = header_from_domain(message) else {
+        return DmarcResult::None;
+    };
+    let policy = match tokio::time::timeout(TIMEOUT, find_policy(dns, &from)).await {
+        Ok(Ok(Some(policy))) => policy,
+        Ok(Ok(None)) => return DmarcResult::None,
+        Ok(Err(result)) => return result,
+        Err(_) => return DmarcResult::TempError,
+    };
+    let spf_domain = match mail_from.and_then(|addr| addr.rsplit_once('@')) {
+        Some((_, domain)) => domain,
+        None => helo,
+    };
+    let spf_aligned = spf == Some(SpfResult::Pass) && aligned(spf_domain, &from, policy.spf);
+    let dkim_aligned = dkim
+        .iter()
+        .any(|v| v.result == DkimResult::Pass && aligned(&v.domain, &from, policy.dkim));
+    if spf_aligned || dkim_aligned {
+        DmarcResult::Pass
+    } else {
+        DmarcResult::Fail
+    }
+}
+
+struct Policy {
+    spf: Alignment,
+    dkim: Alignment,
+}
+
+/// The policy at `_dmarc.<domain>`, falling back to the organizational
+/// domain's.
+async fn find_policy<D: Dns>(dns: &D, domain: &str) -> Result<Option<Policy>, DmarcResult> {
+    let org = organizational_domain(domain);
+    let mut names = vec![domain];
+    if org != domain {
+        names.push(org);
+    }
+    for name in names {
+        let records = dns
+            .txt(&format!("_dmarc.{name}"))
+            .await
+            .map_err(|_| DmarcResult::TempError)?;
+        let mut records = records.iter().filter(|r| is_dmarc(r));
+        let (Some(record), None) = (records.next(), records.next()) else {
+            // None, or more than one, which counts as none.
+            continue;
+        };
+        return parse_policy(record).map(Some);
+    }
+    Ok(None)
+}
+
+fn is_dmarc(record: &str) -> bool {
+    let mut tags = record.split(';');
+    tags.next()
+        .and_then(|v| v.split_once('='))
+        .is_some_and(|(name, value)| name.trim() == "v" && value.trim() == "DMARC1")
+}
+
+fn parse_policy(record: &str) -> Result<Policy, DmarcResult> {
+    let tags = dkim::parse_tags(record).ok_or(DmarcResult::PermError)?;
+    if let Some(p) = tags.get("p") {
+        if !matches!(
+            p.to_ascii_lowercase().as_str(),
+            "none" | "quarantine" | "reject"
+        ) {
+            return Err(DmarcResult::PermError);
+        }
+    }
+    let alignment = |tag: &str| match tags.get(tag).map(|v| v.to_ascii_lowercase()).as_deref() {
+        None | Some("r") => Ok(Alignment::Relaxed),
+        Some("s") => Ok(Alignment::Strict),
+        Some(_) => Err(DmarcResult::PermError),
+    };
+    Ok(Policy {
+        spf: alignment("aspf")?,
+        dkim: alignment("adkim")?,
+    })
+}
+
+fn aligned(domain: &str, from: &str, mode: Alignment) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    match mode {
+        Alignment::Strict => domain == from,
+        Alignment::Relaxed => organizational_domain(&domain) == organizational_domain(from),
+    }
+}
+
+fn organizational_domain(domain: &str) -> &str {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let keep = match labels.as_slice() {
+        [.., second, tld] if tld.len() == 2 && COUNTRY_SECOND_LEVELS.contains(second) => 3,
+        _ => 2,
+    };
+    if labels.len() <= keep {
+        return domain;
+    }
+    let dropped: usize = labels[..labels.len() - keep]
+        .iter()
+        .map(|label| label.len() + 1)
+        .sum();
+    &domain[dropped..]
+}
+
+/// The domain of the author address, if there is exactly one `From:`
+/// field naming exactly one address.
+fn header_from_domain(message: &[u8]) -> Option<String> {
+    let addr = header_from_address(message)?;
+    let (_, domain) = addr.rsplit_once('@')?;
+    let domain = domain.trim_end_matches('.');
+    domain.contains('.').then(|| domain.to_owned())
+}
+
+/// The author address, lowercased, if there is exactly one `From:` field
+/// naming exactly one address.
+pub(crate) fn header_from_address(message: &[u8]) -> Option<String> {
+    let (fields, _) = dkim::split_message(message);
+    let mut from = fields
+        .iter()
+        .filter(|f| dkim::field_name(f).eq_ignore_ascii_case(b"from"));
+    let (Some(field), None) = (from.next(), from.next()) else {
+        return None;
+    };
+    let field = String::from_utf8_lossy(field);
+    let value = field.split_once(':')?.1.trim();
+    if value.matches('<').count() > 1 {
+        return None;
+    }
+    let addr = match (value.find('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    let addr = addr.trim();
+    if addr.contains(',') || !addr.contains('@') {
+        return None;
+    }
+    Some(addr.to_ascii_lowercase())
+}
@@ -0,0 +1,92 @@
+//! DNS for checking where mail comes from, behind a trait so checks can
+//! be tested against fixed records.
+
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+use std::future::Future;
+use std::net::IpAddr;
+
+/// The DNS queries SPF, DKIM and DMARC checks need. A name without the records
+/// asked for answers with an empty list; [`DnsError`] means the query
+/// failed and a later one might succeed.
+pub trait Dns: Sync {
+    /// TXT records at `name`, each with its strings joined.
+    fn txt(&self, name: &str) -> impl Future<Output = Result<Vec<String>, DnsError>> + Send;
+    /// A records at `name`, or AAAA records if `ipv6`.
+    fn addrs(
+        &self,
+        name: &str,
+        ipv6: bool,
+    ) -> impl Future<Output = Result<Vec<IpAddr>, DnsError>> + Send;
+    /// Exchange names of the MX records at `name`.
+    fn mx(&self, name: &str) -> impl Future<Output = Result<Vec<String>, DnsError>> + Send;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsError;
+
+/// [`Dns`] through the system's resolver configuration.
+pub struct SystemDns(TokioAsyncResolver);
+
+impl Default for SystemDns {
+    fn default() -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "no system resolver config, using defaults");
+            TokioAsyncResolver::tokio(Default::default(), Default::default())
+        });
+        Self(resolver)
+    }
+}
+
+impl Dns for SystemDns {
+    async fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        let Some(lookup) = answer(self.0.txt_lookup(fqdn(name)).await)? else {
+            return Ok(Vec::new());
+        };
+        Ok(lookup
+            .iter()
+            .map(|txt| {
+                txt.iter()
+                    .map(|part| String::from_utf8_lossy(part))
+                    .collect()
+            })
+            .collect())
+    }
+
+    async fn addrs(&self, name: &str, ipv6: bool) -> Result<Vec<IpAddr>, DnsError> {
+        let addrs = if ipv6 {
+            answer(self.0.ipv6_lookup(fqdn(name)).await)?
+                .map(|lookup| lookup.iter().map(|aaaa| IpAddr::V6(aaaa.0)).collect())
+        } else {
+            answer(self.0.ipv4_lookup(fqdn(name)).await)?
+                .map(|lookup| lookup.iter().map(|a| IpAddr::V4(a.0)).collect())
+        };
+        Ok(addrs.unwrap_or_default())
+    }
+
+    async fn mx(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        let Some(lookup) = answer(self.0.mx_lookup(fqdn(name)).await)? else {
+            return Ok(Vec::new());
+        };
+        Ok(lookup
+            .iter()
+            .map(|mx| mx.exchange().to_utf8().trim_end_matches('.').to_owned())
+            .collect())
+    }
+}
+
+/// `Ok(None)` for a name without the records asked for.
+fn answer<T>(result: Result<T, ResolveError>) -> Result<Option<T>, DnsError> {
+    match result {
+        Ok(lookup) => Ok(Some(lookup)),
+        Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(None),
+        Err(e) => {
+            tracing::debug!(error = %e, "dns lookup failed");
+            Err(DnsError)
+        }
+    }
+}
+
+fn fqdn(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
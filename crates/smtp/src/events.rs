@@ -0,0 +1,163 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Disposition {
+    Delivered,
+    /// Posted to the webhook of the recipient's domain.
+    Forwarded,
+    Honeypot,
+    UnknownRecipient,
+    TooLarge,
+    Throttled,
+    Loop,
+    Quarantined,
+    /// The sender is on the global blocklist or the recipient's.
+    Blocked,
+    /// Discarded or rejected by a stage added to the ingestion pipeline.
+    Filtered,
+    Failed,
+}
+
+impl Disposition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Delivered => "delivered",
+            Self::Forwarded => "forwarded",
+            Self::Honeypot => "honeypot",
+            Self::UnknownRecipient => "unknown_recipient",
+            Self::TooLarge => "too_large",
+            Self::Throttled => "throttled",
+            Self::Loop => "loop",
+            Self::Quarantined => "quarantined",
+            Self::Blocked => "blocked",
+            Self::Filtered => "filtered",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// One ingestion outcome, already redacted: the recipient keeps only the
+/// first two characters of its local part and the sender only its domain.
+#[derive(Debug, Clone, Serialize)]
+pub struct IngestEvent {
+    pub at: DateTime<Utc>,
+    pub recipient: Option<String>,
+    pub sender_domain: Option<String>,
+    pub size: usize,
+    pub disposition: Disposition,
+    /// Where the client connected from, when a GeoIP database is set.
+    pub country: Option<String>,
+}
+
+impl IngestEvent {
+    pub(crate) fn new(
+        disposition: Disposition,
+        recipient: Option<&str>,
+        sender: Option<&str>,
+        size: usize,
+    ) -> Self {
+        Self {
+            at: Utc::now(),
+            recipient: recipient.map(redact_address),
+            sender_domain: sender
+                .and_then(|s| s.rsplit_once('@'))
+                .map(|(_, domain)| domain.to_ascii_lowercase()),
+            size,
+            disposition,
+            country: None,
+        }
+    }
+
+    pub(crate) fn with_country(mut self, country: Option<&str>) -> Self {
+        self.country = country.map(str::to_owned);
+        self
+    }
+}
+
+/// Events of one disposition from one country since startup; `country` is
+/// `None` where it is unknown.
+#[derive(Debug, Clone, Serialize)]
+pub struct CountryCount {
+    pub country: Option<String>,
+    pub disposition: Disposition,
+    pub count: u64,
+}
+
+/// Broadcast feed of [`IngestEvent`]s. Publishing never blocks the SMTP
+/// session: with no subscribers events are dropped, and slow subscribers lag.
+#[derive(Clone)]
+pub struct IngestEvents {
+    tx: broadcast::Sender<IngestEvent>,
+    by_country: Arc<Mutex<HashMap<(Option<String>, Disposition), u64>>>,
+}
+
+impl IngestEvents {
+    pub fn subscribe(&self) -> broadcast::Receiver<IngestEvent> {
+        self.tx.subscribe()
+    }
+
+    /// Event counts by country and disposition, most frequent first.
+    pub fn by_country(&self) -> Vec<CountryCount> {
+        let counts = self.by_country.lock().unwrap_or_else(|e| e.into_inner());
+        let mut counts: Vec<CountryCount> = counts
+            .iter()
+            .map(|((country, disposition), &count)| CountryCount {
+                country: country.clone(),
+                disposition: *disposition,
+                count,
+            })
+            .collect();
+        counts.sort_by(|a, b| {
+            (b.count.cmp(&a.count))
+                .then_with(|| a.country.cmp(&b.country))
+                .then_with(|| a.disposition.as_str().cmp(b.disposition.as_str()))
+        });
+        counts
+    }
+
+    /// Also counts the event in `smtp_ingest_total` by disposition, so
+    /// per-recipient outcomes show up in metrics without a subscriber, and
+    /// by country for [`by_country`](Self::by_country).
+    pub(crate) fn publish(&self, event: IngestEvent) {
+        metrics::counter!("smtp_ingest_total", "disposition" => event.disposition.as_str())
+            .increment(1);
+        *self
+            .by_country
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry((event.country.clone(), event.disposition))
+            .or_default() += 1;
+        let _ = self.tx.send(event);
+    }
+}
+
+impl Default for IngestEvents {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(CHANNEL_CAPACITY).0,
+            by_country: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Debug for IngestEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IngestEvents")
+            .field("subscribers", &self.tx.receiver_count())
+            .finish()
+    }
+}
+
+fn redact_address(addr: &str) -> String {
+    let (local, domain) = addr.rsplit_once('@').unwrap_or((addr, ""));
+    let shown: String = local.chars().take(2).collect();
+    format!("{shown}***@{domain}")
+}
@@ -0,0 +1,35 @@
+//! Country of connecting clients, from a MaxMind GeoIP2 or GeoLite2
+//! Country (or City) database file.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)?,
+        })
+    }
+
+    /// ISO 3166-1 alpha-2 code, e.g. `DE`; `None` for addresses the
+    /// database does not place, such as private ones.
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let found: geoip2::Country = self.reader.lookup(ip).ok()?;
+        found.country?.iso_code.map(str::to_owned)
+    }
+}
+
+impl fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoIp")
+            .field("database", &self.reader.metadata.database_type)
+            .finish()
+    }
+}
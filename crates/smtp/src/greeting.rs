@@ -0,0 +1,63 @@
+//! The pause before the `220` greeting. A client must wait for it before
+//! sending (RFC 5321 section 3.1); spam software often starts at once, which
+//! gives it away at the cost of a few seconds per connection.
+
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Tag put on messages from clients that talked before the greeting.
+pub const EARLY_TALKER_TAG: &str = "early-talker";
+
+/// What happens to a client that talks before the greeting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EarlyTalkers {
+    /// Answered with `554` and disconnected.
+    #[default]
+    Reject,
+    /// Served as usual, with its messages tagged `early-talker`.
+    Tag,
+}
+
+impl EarlyTalkers {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Reject => "reject",
+            Self::Tag => "tag",
+        }
+    }
+}
+
+impl fmt::Display for EarlyTalkers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EarlyTalkers {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Ok(Self::Reject),
+            "tag" => Ok(Self::Tag),
+            _ => Err(format!("expected reject or tag, got {s:?}")),
+        }
+    }
+}
+
+/// Waits out `delay` unless the client sends something first, which is
+/// left buffered. Returns whether it did; a client hanging up does not
+/// count.
+pub(crate) async fn talks_early<R>(reader: &mut R, delay: Duration) -> std::io::Result<bool>
+where
+    R: AsyncBufRead + Unpin,
+{
+    match tokio::time::timeout(delay, reader.fill_buf()).await {
+        Ok(Ok(buf)) => Ok(!buf.is_empty()),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Ok(false),
+    }
+}
@@ -0,0 +1,127 @@
+//! Checks on the `EHLO`/`HELO` argument. A client should give its own
+//! domain name or an address literal like `[192.0.2.1]` (RFC 5321 section
+//! 4.1.4); spam software often sends junk, a bare IP address, or the name
+//! of the server it is talking to.
+//!
+//! Single-label names such as `localhost` pass: too many legitimate
+//! clients send one.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Tag put on messages from a client whose argument is malformed.
+pub const INVALID_TAG: &str = "helo-invalid";
+/// Tag put on messages from a client claiming to be a bare IP or us.
+pub const FORGED_TAG: &str = "helo-forged";
+
+/// What happens to a client whose argument fails the check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeloPolicy {
+    /// Nothing; the check is only counted.
+    Ignore,
+    /// Its messages are tagged `helo-invalid` or `helo-forged`.
+    #[default]
+    Tag,
+    /// The `EHLO` is refused; the client may try again with another name.
+    Reject,
+}
+
+impl HeloPolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ignore => "ignore",
+            Self::Tag => "tag",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+impl fmt::Display for HeloPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HeloPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ignore" => Ok(Self::Ignore),
+            "tag" => Ok(Self::Tag),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("expected ignore, tag or reject, got {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeloVerdict {
+    Valid,
+    /// Missing, or neither a domain nor an address literal.
+    Invalid,
+    /// A bare IP address, or `own_domain`.
+    Forged,
+}
+
+impl HeloVerdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::Forged => "forged",
+        }
+    }
+
+    /// The tag for a failed check.
+    pub fn tag(self) -> Option<&'static str> {
+        match self {
+            Self::Valid => None,
+            Self::Invalid => Some(INVALID_TAG),
+            Self::Forged => Some(FORGED_TAG),
+        }
+    }
+}
+
+/// Checks the argument a client greeted `own_domain` with.
+pub fn check(name: Option<&str>, own_domain: &str) -> HeloVerdict {
+    let Some(name) = name else {
+        return HeloVerdict::Invalid;
+    };
+    if name.parse::<IpAddr>().is_ok() {
+        return HeloVerdict::Forged;
+    }
+    if let Some(literal) = name.strip_prefix('[').and_then(|n| n.strip_suffix(']')) {
+        let parsed = match literal.strip_prefix("IPv6:") {
+            Some(v6) => v6.parse::<Ipv6Addr>().is_ok(),
+            None => literal.parse::<Ipv4Addr>().is_ok(),
+        };
+        return if parsed {
+            HeloVerdict::Valid
+        } else {
+            HeloVerdict::Invalid
+        };
+    }
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if !is_domain(name) {
+        return HeloVerdict::Invalid;
+    }
+    if name.eq_ignore_ascii_case(own_domain.trim_end_matches('.')) {
+        return HeloVerdict::Forged;
+    }
+    HeloVerdict::Valid
+}
+
+/// Letters, digits and inner hyphens in labels of up to 63 characters.
+fn is_domain(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
@@ -1,45 +1,238 @@
-use db::{find_temporary_email_by_addr, insert_received_email};
-use mail_parser::MessageParser;
+mod abuse;
+pub mod auth;
+mod config;
+mod data;
+pub mod dkim;
+pub mod dmarc;
+pub mod dns;
+mod events;
+mod geoip;
+mod greeting;
+pub mod helo;
+mod loops;
+mod parse;
+pub mod path;
+pub mod pipeline;
+mod session;
+pub mod spf;
+#[cfg(feature = "wasm-plugins")]
+pub mod wasm;
+pub mod watch;
+mod webhook;
+
+pub use auth::SmtpAuth;
+pub use config::SmtpConfig;
+pub use events::{CountryCount, Disposition, IngestEvent, IngestEvents};
+pub use geoip::GeoIp;
+pub use greeting::EarlyTalkers;
+pub use helo::HeloPolicy;
+pub use loops::LOOP_HEADER;
+pub use pipeline::{BuiltinStage, IncomingMessage, IngestStage, Pipeline, Verdict};
+
+use abuse::{PeerLimits, UnknownRecipientThrottle};
+use auth::{AuthError, Mechanism, Step};
+use data::Message;
+use helo::HeloVerdict;
+use loops::LoopVerdict;
+use parse::ParsedMessage;
+use path::{ForwardPath, MailFrom, RcptTo};
+use session::{Forward, Phase, Recipient, Session, Transaction};
+use chrono::{DateTime, Utc};
+use db::{
+    find_mail_domain, find_mailboxes_expecting, find_sender_blocks, find_temporary_email_by_addr,
+    find_temporary_email_by_alias, find_tenant_settings_for_address,
+    insert_received_email_for_recipients, insert_temporary_email, is_api_created, mailbox_usage,
+    record_honeypot_hit, record_message_usage, record_poison_message, refuse_known_poison,
+    BlocklistCache, BodyCompression, DmarcResult, DomainPolicy, MailboxQuota, NewAttachment,
+    NewDkimSignature, NewPoisonMessage, NewReceivedEmail, SpfResult,
+};
+use sha2::{Digest, Sha256};
 use sqlx::postgres::PgPool;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
 
 const MAX_LINE_LEN: usize = 4096;
-const MAX_DATA_BYTES: usize = 10 * 1024 * 1024;
+/// Failed `AUTH` attempts before the connection is closed.
+const MAX_AUTH_FAILURES: u32 = 3;
 
-#[derive(Clone)]
-struct Recipient {
-    id: uuid::Uuid,
-    addr: String,
+struct Server {
+    pool: PgPool,
+    unknown_rcpts: UnknownRecipientThrottle,
+    peer_limits: Arc<PeerLimits>,
+    events: IngestEvents,
+    blocklist: Arc<BlocklistCache>,
+    body_compression: BodyCompression,
+    loop_marker: String,
+    max_hops: usize,
+    banner_domain: String,
+    max_recipients: usize,
+    max_messages_per_session: usize,
+    max_commands_per_session: usize,
+    idle_timeout: Duration,
+    session_timeout: Duration,
+    greeting_delay: Duration,
+    early_talkers: EarlyTalkers,
+    helo_policy: HeloPolicy,
+    reject_unknown_at_rcpt: bool,
+    /// Lowercased, without a trailing dot.
+    local_domains: Vec<String>,
+    catch_all: bool,
+    processing_timeout: Duration,
+    poison_threshold: u32,
+    max_message_size: usize,
+    mailbox_quota: MailboxQuota,
+    /// Posts mail for `webhook` domains and watch rule matches.
+    http: reqwest::Client,
+    auth: SmtpAuth,
+    /// Present when SPF, DKIM or DMARC is checked.
+    dns: Option<dns::SystemDns>,
+    check_spf: bool,
+    check_dkim: bool,
+    check_dmarc: bool,
+    geoip: Option<Arc<GeoIp>>,
+    pipeline: Pipeline,
 }
 
-pub async fn run_server(host: &str, port: u16, pool: PgPool) -> Result<(), std::io::Error> {
+impl Server {
+    /// In `local_domains`, or any domain when none are configured.
+    fn is_configured_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.');
+        self.local_domains.is_empty() || self.local_domains.iter().any(|d| d == domain)
+    }
+}
+
+pub async fn run_server(
+    host: &str,
+    port: u16,
+    pool: PgPool,
+    config: SmtpConfig,
+) -> Result<(), std::io::Error> {
     let listener = TcpListener::bind((host, port)).await?;
     tracing::info!(%host, port, "smtp listening");
-    run_server_on_listener(listener, pool).await
+    serve(listener, pool, config).await
 }
 
 pub async fn run_server_on_listener(
     listener: TcpListener,
     pool: PgPool,
 ) -> Result<(), std::io::Error> {
+    serve(listener, pool, SmtpConfig::default()).await
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    pool: PgPool,
+    config: SmtpConfig,
+) -> Result<(), std::io::Error> {
+    let server = Arc::new(Server {
+        pool,
+        unknown_rcpts: UnknownRecipientThrottle::new(
+            config.unknown_rcpt_threshold,
+            config.unknown_rcpt_window,
+            config.abuse_block_duration,
+        ),
+        peer_limits: Arc::new(PeerLimits::new(
+            config.max_connections_per_ip,
+            config.max_messages_per_ip_per_minute,
+        )),
+        events: config.events,
+        blocklist: config.blocklist,
+        body_compression: config.body_compression,
+        loop_marker: config.loop_marker,
+        max_hops: config.max_hops,
+        banner_domain: config.banner_domain,
+        max_recipients: config.max_recipients,
+        max_messages_per_session: config.max_messages_per_session,
+        max_commands_per_session: config.max_commands_per_session,
+        idle_timeout: config.idle_timeout,
+        session_timeout: config.session_timeout,
+        greeting_delay: config.greeting_delay,
+        early_talkers: config.early_talkers,
+        helo_policy: config.helo_policy,
+        reject_unknown_at_rcpt: config.reject_unknown_at_rcpt,
+        local_domains: config
+            .local_domains
+            .iter()
+            .map(|d| d.trim_end_matches('.').to_ascii_lowercase())
+            .collect(),
+        catch_all: config.catch_all,
+        processing_timeout: config.processing_timeout,
+        poison_threshold: config.poison_threshold.max(1),
+        max_message_size: config.max_message_size,
+        mailbox_quota: config.mailbox_quota,
+        http: webhook::client(),
+        auth: config.auth,
+        dns: (config.check_spf || config.check_dkim || config.check_dmarc)
+            .then(dns::SystemDns::default),
+        check_spf: config.check_spf,
+        check_dkim: config.check_dkim,
+        check_dmarc: config.check_dmarc,
+        geoip: config.geoip,
+        pipeline: config.pipeline,
+    });
+
     loop {
-        let (socket, _) = listener.accept().await?;
-        let pool = pool.clone();
+        let (mut socket, peer) = listener.accept().await?;
+        let country = server.geoip.as_deref().and_then(|g| g.country(peer.ip()));
+        let throttled = || {
+            IngestEvent::new(Disposition::Throttled, None, None, 0).with_country(country.as_deref())
+        };
+        if server.unknown_rcpts.is_blocked(peer.ip()) {
+            tracing::debug!(peer = %peer.ip(), "refusing throttled peer");
+            server.events.publish(throttled());
+            tokio::spawn(async move {
+                let _ = socket
+                    .write_all(b"421 4.7.0 too many unknown recipients, try again later\r\n")
+                    .await;
+            });
+            continue;
+        }
+        let Some(slot) = server.peer_limits.connect(peer.ip()) else {
+            tracing::debug!(peer = %peer.ip(), "refusing peer over its connection limit");
+            server.events.publish(throttled());
+            tokio::spawn(async move {
+                let _ = socket
+                    .write_all(b"421 4.7.0 too many connections from your address\r\n")
+                    .await;
+            });
+            continue;
+        };
+        let server = Arc::clone(&server);
         tokio::spawn(async move {
-            if let Err(e) = handle_client(socket, pool).await {
+            let _slot = slot;
+            if let Err(e) = handle_client(socket, peer.ip(), country, &server).await {
                 tracing::error!(error = %e, "smtp session failed");
             }
         });
     }
 }
 
+/// Runs one read from the client, failing with `TimedOut` if it sends
+/// nothing for `idle`.
+async fn within_idle<T>(
+    idle: Duration,
+    read: impl Future<Output = Result<T, std::io::Error>>,
+) -> Result<T, std::io::Error> {
+    tokio::time::timeout(idle, read).await.unwrap_or_else(|_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "client idle",
+        ))
+    })
+}
+
 async fn read_limited_line(
     reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
-    buf: &mut String,
+    buf: &mut Vec<u8>,
+    idle: Duration,
 ) -> Result<usize, std::io::Error> {
     buf.clear();
-    let n = reader.read_line(buf).await?;
+    let n = within_idle(idle, reader.read_until(b'\n', buf)).await?;
     if n > MAX_LINE_LEN {
         return Err(std::io::Error::new(
             std::io::ErrorKind::InvalidData,
@@ -49,60 +242,166 @@ async fn read_limited_line(
     Ok(n)
 }
 
-async fn handle_client(socket: TcpStream, pool: PgPool) -> Result<(), std::io::Error> {
+/// Runs the session under its time limits, closing with `421` when the
+/// client idles or the session runs too long.
+async fn handle_client(
+    socket: TcpStream,
+    peer: IpAddr,
+    country: Option<String>,
+    server: &Server,
+) -> Result<(), std::io::Error> {
     let (reader, mut writer) = socket.into_split();
     let mut reader = BufReader::new(reader);
 
-    writer.write_all(b"220 fake-email smtp ready\r\n").await?;
-
-    let mut mail_from: Option<String> = None;
-    let mut recipients: Vec<Recipient> = Vec::new();
-    let mut in_data = false;
-    let mut data_buf = String::new();
-    let mut line = String::new();
+    let session = Session::new(peer, country);
+    let session = converse(&mut reader, &mut writer, session, server);
+    let reason = match tokio::time::timeout(server.session_timeout, session).await {
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::TimedOut => {
+            tracing::info!(%peer, "smtp client idle, closing");
+            "idle"
+        }
+        Ok(result) => return result,
+        Err(_) => {
+            tracing::info!(%peer, "smtp session too long, closing");
+            "session"
+        }
+    };
+    metrics::counter!("smtp_sessions_reaped_total", "reason" => reason).increment(1);
+    writer
+        .write_all(b"421 4.4.2 Timeout, closing connection\r\n")
+        .await
+}
 
-    loop {
-        let n = read_limited_line(&mut reader, &mut line).await?;
-        if n == 0 {
-            break;
+async fn converse(
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    mut session: Session,
+    server: &Server,
+) -> Result<(), std::io::Error> {
+    let peer = session.peer;
+    if !server.greeting_delay.is_zero()
+        && greeting::talks_early(reader, server.greeting_delay).await?
+    {
+        let action = server.early_talkers;
+        tracing::info!(%peer, %action, "smtp client talked before the greeting");
+        metrics::counter!("smtp_early_talkers_total", "action" => action.as_str()).increment(1);
+        if action == EarlyTalkers::Reject {
+            return writer
+                .write_all(b"554 5.5.0 talking before the greeting, closing\r\n")
+                .await;
         }
+        session.tags.push(greeting::EARLY_TALKER_TAG.to_owned());
+    }
+    writer
+        .write_all(format!("220 {} smtp ready\r\n", server.banner_domain).as_bytes())
+        .await?;
 
-        let cmd = line.trim_end_matches(['\r', '\n']);
+    let mut line = Vec::new();
 
-        if in_data {
-            if cmd == "." {
-                persist_message(&pool, mail_from.as_deref(), &recipients, &data_buf).await;
-                data_buf.clear();
-                mail_from = None;
-                recipients.clear();
-                in_data = false;
-                writer.write_all(b"250 queued\r\n").await?;
-            } else {
-                if data_buf.len() + cmd.len() + 2 > MAX_DATA_BYTES {
-                    data_buf.clear();
-                    in_data = false;
-                    mail_from = None;
-                    recipients.clear();
-                    writer.write_all(b"552 message too large\r\n").await?;
-                    continue;
+    loop {
+        if session.phase == Phase::Data {
+            let read = data::read_message(reader, server.max_message_size, server.idle_timeout);
+            let Some(message) = read.await? else {
+                break;
+            };
+            let reply = match message {
+                Message::Complete(data) => {
+                    if let Some(tx) = session.transaction.as_mut() {
+                        tx.data = data;
+                    }
+                    match finish_data(server, &mut session).await {
+                        DataOutcome::Accepted(reply) => {
+                            session.messages_accepted += 1;
+                            reply
+                        }
+                        DataOutcome::Refused(reply) => reply,
+                    }
                 }
-                let destuffed = cmd.strip_prefix('.').unwrap_or(cmd);
-                data_buf.push_str(destuffed);
-                data_buf.push_str("\r\n");
-            }
+                Message::TooLarge { size } => {
+                    if let Some(tx) = &session.transaction {
+                        let from = tx.sender();
+                        for rcpt in &tx.recipients {
+                            let event = IngestEvent::new(
+                                Disposition::TooLarge,
+                                Some(&rcpt.addr),
+                                from.as_deref(),
+                                size,
+                            );
+                            server
+                                .events
+                                .publish(event.with_country(session.country.as_deref()));
+                        }
+                    }
+                    MESSAGE_TOO_LARGE.into()
+                }
+            };
+            session.reset();
+            writer.write_all(reply.as_bytes()).await?;
             continue;
         }
 
+        let n = read_limited_line(reader, &mut line, server.idle_timeout).await?;
+        if n == 0 {
+            break;
+        }
+        session.commands += 1;
+        if server.max_commands_per_session > 0 && session.commands > server.max_commands_per_session
+        {
+            tracing::warn!(%peer, "too many commands in one session, closing");
+            writer
+                .write_all(b"421 4.7.0 too many commands in this session, closing\r\n")
+                .await?;
+            break;
+        }
+
+        // Commands are ASCII; stray bytes only have to not break parsing.
+        let cmd = String::from_utf8_lossy(&line);
+        let cmd = cmd.trim_end_matches(['\r', '\n']);
+
         let upper = cmd.to_ascii_uppercase();
 
         if upper.starts_with("EHLO") || upper.starts_with("HELO") {
-            writer.write_all(b"250 fake-email\r\n").await?;
+            let name = cmd.get(4..).map(str::trim).filter(|s| !s.is_empty());
+            let verdict = helo::check(name, &server.banner_domain);
+            metrics::counter!("smtp_helo_checks_total", "result" => verdict.as_str()).increment(1);
+            if verdict != HeloVerdict::Valid {
+                tracing::debug!(%peer, helo = name, result = verdict.as_str(), "bad helo");
+            }
+            if server.helo_policy == HeloPolicy::Reject {
+                let refusal: &[u8] = match verdict {
+                    HeloVerdict::Valid => b"",
+                    HeloVerdict::Invalid => b"501 5.5.2 invalid EHLO argument\r\n",
+                    HeloVerdict::Forged => b"550 5.7.1 EHLO argument is not your name\r\n",
+                };
+                if !refusal.is_empty() {
+                    writer.write_all(refusal).await?;
+                    continue;
+                }
+            }
+            session.ehlo_name = name.map(str::to_owned);
+            session
+                .tags
+                .retain(|t| t != helo::INVALID_TAG && t != helo::FORGED_TAG);
+            if let (HeloPolicy::Tag, Some(tag)) = (server.helo_policy, verdict.tag()) {
+                session.tags.push(tag.to_owned());
+            }
+            session.esmtp = upper.starts_with("EHLO");
+            session.reset();
+            let reply = if session.esmtp {
+                ehlo_reply(
+                    &server.banner_domain,
+                    server.max_message_size,
+                    server.auth.required,
+                )
+            } else {
+                format!("250 {}\r\n", server.banner_domain)
+            };
+            writer.write_all(reply.as_bytes()).await?;
             continue;
         }
 
         if upper == "RSET" {
-            mail_from = None;
-            recipients.clear();
+            session.reset();
             writer.write_all(b"250 reset\r\n").await?;
             continue;
         }
@@ -112,36 +411,198 @@ async fn handle_client(socket: TcpStream, pool: PgPool) -> Result<(), std::io::E
             break;
         }
 
+        if upper == "AUTH" || upper.starts_with("AUTH ") {
+            let args = cmd[4..].trim().to_owned();
+            let Some(reply) = authenticate(server, &mut session, &args, reader, writer).await?
+            else {
+                break;
+            };
+            writer.write_all(reply.as_bytes()).await?;
+            if session.auth_failures >= MAX_AUTH_FAILURES {
+                tracing::warn!(%peer, "too many failed AUTH attempts, closing");
+                writer
+                    .write_all(b"421 4.7.0 too many authentication failures, closing\r\n")
+                    .await?;
+                break;
+            }
+            continue;
+        }
+
         if upper.starts_with("MAIL FROM:") {
-            let Some(addr) = extract_path(cmd) else {
+            if server.max_messages_per_session > 0
+                && session.messages_accepted >= server.max_messages_per_session
+            {
+                writer
+                    .write_all(b"421 4.7.0 too many messages in this session, closing\r\n")
+                    .await?;
+                break;
+            }
+            if server.auth.required && session.authenticated.is_none() {
+                writer
+                    .write_all(b"530 5.7.0 Authentication required\r\n")
+                    .await?;
+                continue;
+            }
+            let mut expected_by = None;
+            if !server.peer_limits.allow_message(peer) {
+                expected_by = expecting_mailboxes(server, cmd).await;
+                if expected_by.is_none() {
+                    let event = IngestEvent::new(Disposition::Throttled, None, None, 0);
+                    server
+                        .events
+                        .publish(event.with_country(session.country.as_deref()));
+                    writer.write_all(TOO_MANY_MESSAGES.as_bytes()).await?;
+                    continue;
+                }
+                tracing::debug!(%peer, "throttled client sends from an expected domain");
+                metrics::counter!("smtp_expected_sender_bypass_total").increment(1);
+            }
+            let Ok(parsed) = path::parse_mail_from(cmd) else {
                 writer.write_all(b"501 bad MAIL FROM\r\n").await?;
                 continue;
             };
-            mail_from = Some(addr);
-            recipients.clear();
+            let max_size = server.max_message_size;
+            let smtputf8 = match check_mail_params(&parsed, session.esmtp, max_size) {
+                Ok(smtputf8) => smtputf8,
+                Err(reply) => {
+                    writer.write_all(reply.as_bytes()).await?;
+                    continue;
+                }
+            };
+            let mut tx = Transaction::new(parsed.path);
+            tx.smtputf8 = smtputf8;
+            tx.expected_by = expected_by;
+            session.transaction = Some(tx);
             writer.write_all(b"250 ok\r\n").await?;
             continue;
         }
 
         if upper.starts_with("RCPT TO:") {
-            if mail_from.is_none() {
+            let Some(tx) = session.transaction.as_mut() else {
                 writer.write_all(b"503 MAIL FROM required first\r\n").await?;
                 continue;
-            }
-            let Some(addr) = extract_path(cmd) else {
-                writer.write_all(b"501 bad RCPT TO\r\n").await?;
+            };
+            if server.max_recipients > 0 && tx.accepted_recipients() >= server.max_recipients {
+                writer.write_all(b"452 4.5.3 Too many recipients\r\n").await?;
                 continue;
+            }
+            let rcpt = match path::parse_rcpt_to(cmd) {
+                Ok(rcpt) => rcpt,
+                Err(_) => {
+                    writer.write_all(b"501 bad RCPT TO\r\n").await?;
+                    continue;
+                }
             };
+            let original_recipient = match check_rcpt_params(&rcpt, session.esmtp) {
+                Ok(orcpt) => orcpt,
+                Err(reply) => {
+                    writer.write_all(reply.as_bytes()).await?;
+                    continue;
+                }
+            };
+            let addr_lower = match rcpt.path {
+                ForwardPath::Mailbox(mailbox) if !mailbox.is_ascii() && !tx.smtputf8 => {
+                    writer.write_all(SMTPUTF8_REQUIRED.as_bytes()).await?;
+                    continue;
+                }
+                ForwardPath::Mailbox(mailbox) => mailbox.address(),
+                // No postmaster inbox exists; treat it like any unknown name.
+                ForwardPath::Postmaster => "postmaster".to_owned(),
+            };
+            let domain = addr_lower.rsplit_once('@').map(|(_, domain)| domain);
+            match is_local_domain(server, domain).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::debug!(%peer, rcpt = addr_lower, "refusing relay");
+                    metrics::counter!("smtp_relay_denied_total").increment(1);
+                    writer
+                        .write_all(b"550 5.7.1 Relay not permitted\r\n")
+                        .await?;
+                    continue;
+                }
+                Err(_) => {
+                    writer.write_all(b"451 temporary local error\r\n").await?;
+                    continue;
+                }
+            }
 
-            let addr_lower = addr.to_ascii_lowercase();
-
-            match find_temporary_email_by_addr(&pool, &addr_lower).await {
-                Ok(Some(temp)) => {
-                    recipients.push(Recipient { id: temp.id, addr: addr_lower });
+            let lookup = lookup_recipient(server, &addr_lower);
+            match db::timed("smtp", "lookup_recipient", lookup).await {
+                Ok(Some(Resolved::Mailbox(rcpt))) if !tx.admits(rcpt.id) => {
+                    writer.write_all(TOO_MANY_MESSAGES.as_bytes()).await?;
+                }
+                Ok(Some(Resolved::Forward(_))) if tx.expected_by.is_some() => {
+                    writer.write_all(TOO_MANY_MESSAGES.as_bytes()).await?;
+                }
+                // Named twice, perhaps as another `+tag` form; it still gets
+                // the message once, recording each form.
+                Ok(Some(Resolved::Mailbox(rcpt)))
+                    if tx.recipients.iter().any(|r| r.id == rcpt.id) =>
+                {
+                    for named in tx.recipients.iter_mut().filter(|r| r.id == rcpt.id) {
+                        if !named.envelope_addrs().contains(&rcpt.addr) {
+                            named.other_addrs.push(rcpt.addr.clone());
+                        }
+                    }
+                    writer.write_all(b"250 ok\r\n").await?;
+                }
+                Ok(Some(Resolved::Mailbox(rcpt))) => match is_mailbox_full(server, &rcpt).await {
+                    Ok(false) => {
+                        let from = tx.sender();
+                        let refused = server
+                            .pipeline
+                            .refusing_stage(peer, from.as_deref(), &rcpt.addr)
+                            .await;
+                        if let Some(stage) = refused {
+                            tracing::debug!(%peer, rcpt = addr_lower, stage, "recipient refused");
+                            writer.write_all(RECIPIENT_REFUSED.as_bytes()).await?;
+                            continue;
+                        }
+                        tx.accepted_at.get_or_insert_with(Utc::now);
+                        tx.recipients.push(Recipient {
+                            original_recipient,
+                            ..rcpt
+                        });
+                        writer.write_all(b"250 ok\r\n").await?;
+                    }
+                    Ok(true) => {
+                        tracing::debug!(%peer, rcpt = addr_lower, "mailbox full");
+                        metrics::counter!("smtp_mailbox_full_total").increment(1);
+                        writer.write_all(MAILBOX_FULL.as_bytes()).await?;
+                    }
+                    Err(_) => {
+                        writer.write_all(b"451 temporary local error\r\n").await?;
+                    }
+                },
+                Ok(Some(Resolved::Forward(fwd))) => {
+                    if !tx.forwards.iter().any(|f| f.addr == fwd.addr) {
+                        tx.forwards.push(fwd);
+                    }
                     writer.write_all(b"250 ok\r\n").await?;
                 }
                 Ok(None) => {
-                    writer.write_all(b"550 unknown recipient\r\n").await?;
+                    let event = IngestEvent::new(
+                        Disposition::UnknownRecipient,
+                        Some(&addr_lower),
+                        tx.sender().as_deref(),
+                        0,
+                    );
+                    server
+                        .events
+                        .publish(event.with_country(session.country.as_deref()));
+                    if server.reject_unknown_at_rcpt {
+                        writer.write_all(b"550 5.1.1 User unknown\r\n").await?;
+                    } else {
+                        tx.deferred_unknown += 1;
+                        writer.write_all(b"250 ok\r\n").await?;
+                    }
+                    if server.unknown_rcpts.record_rejection(peer) {
+                        tracing::warn!(%peer, "too many unknown recipients, blocking peer");
+                        writer
+                            .write_all(b"421 4.7.0 too many unknown recipients, closing\r\n")
+                            .await?;
+                        break;
+                    }
                 }
                 Err(_) => {
                     writer.write_all(b"451 temporary local error\r\n").await?;
@@ -151,12 +612,16 @@ async fn handle_client(socket: TcpStream, pool: PgPool) -> Result<(), std::io::E
         }
 
         if upper == "DATA" {
-            if recipients.is_empty() {
+            let Some(tx) = session.transaction.as_mut() else {
+                writer.write_all(b"503 MAIL FROM required first\r\n").await?;
+                continue;
+            };
+            if tx.accepted_recipients() == 0 {
                 writer.write_all(b"554 no valid recipients\r\n").await?;
                 continue;
             }
-            in_data = true;
-            data_buf.clear();
+            tx.data.clear();
+            session.phase = Phase::Data;
             writer.write_all(b"354 end with <CRLF>.<CRLF>\r\n").await?;
             continue;
         }
@@ -167,30 +632,851 @@ async fn handle_client(socket: TcpStream, pool: PgPool) -> Result<(), std::io::E
     Ok(())
 }
 
-async fn persist_message(pool: &PgPool, from_addr: Option<&str>, rcpts: &[Recipient], raw: &str) {
-    let parsed = MessageParser::default().parse(raw.as_bytes());
-    let subject = parsed.as_ref().and_then(|m| m.subject()).map(|s| s.to_string());
-    let body_text = parsed.as_ref().and_then(|m| m.body_text(0)).map(|s| s.into_owned());
+const TOO_MANY_MESSAGES: &str =
+    "450 4.7.1 too many messages from your address, try again later\r\n";
+const MESSAGE_TOO_LARGE: &str = "552 5.3.4 Message size exceeds fixed limit\r\n";
+const MAILBOX_FULL: &str = "552 5.2.2 Mailbox full\r\n";
+const SENDER_BLOCKED: &str = "550 5.7.1 Sender blocked\r\n";
+const RECIPIENT_REFUSED: &str = "550 5.7.1 Recipient refused by policy\r\n";
+const STORAGE_FAILED: &str = "451 4.3.0 temporary failure\r\n";
+const QUARANTINED: &str = "554 5.6.0 message quarantined after repeated processing failures\r\n";
+const SMTPUTF8_REQUIRED: &str = "553 5.6.7 SMTPUTF8 required for non-ASCII addresses\r\n";
 
-    for rcpt in rcpts {
-        if let Err(e) = insert_received_email(
-            pool,
-            rcpt.id,
-            from_addr,
-            Some(&rcpt.addr),
-            subject.as_deref(),
-            body_text.as_deref(),
-        )
-        .await
-        {
-            tracing::error!(error = %e, rcpt = %rcpt.addr, "failed to persist email");
+/// Multi-line `EHLO` reply listing the extensions we honour. `SIZE 0`
+/// means no fixed limit (RFC 1870).
+fn ehlo_reply(banner_domain: &str, max_message_size: usize, auth: bool) -> String {
+    let auth = if auth { "250-AUTH PLAIN LOGIN\r\n" } else { "" };
+    format!(
+        "250-{banner_domain}\r\n\
+         250-SIZE {max_message_size}\r\n\
+         {auth}\
+         250-8BITMIME\r\n\
+         250-SMTPUTF8\r\n\
+         250-DSN\r\n\
+         250 PIPELINING\r\n"
+    )
+}
+
+/// Runs an `AUTH` exchange, reading the client's answers to `334`
+/// challenges. Returns the final reply, or `None` if the client hung up
+/// mid-exchange.
+async fn authenticate(
+    server: &Server,
+    session: &mut Session,
+    args: &str,
+    reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    writer: &mut tokio::net::tcp::OwnedWriteHalf,
+) -> Result<Option<&'static str>, std::io::Error> {
+    if !server.auth.required {
+        return Ok(Some("502 5.5.1 AUTH not enabled\r\n"));
+    }
+    if !session.esmtp {
+        return Ok(Some("503 5.5.1 EHLO first\r\n"));
+    }
+    if session.authenticated.is_some() {
+        return Ok(Some("503 5.5.1 already authenticated\r\n"));
+    }
+    if session.transaction.is_some() {
+        return Ok(Some("503 5.5.1 AUTH not allowed within a transaction\r\n"));
+    }
+
+    let auth_error = |e: AuthError| match e {
+        AuthError::Malformed => "501 5.5.2 cannot decode AUTH response\r\n",
+        AuthError::Unsupported => "504 5.5.4 unrecognized authentication mechanism\r\n",
+    };
+    let (mut mechanism, mut step) = match Mechanism::start(args) {
+        Ok(started) => started,
+        Err(e) => return Ok(Some(auth_error(e))),
+    };
+    let mut line = Vec::new();
+    let (username, password) = loop {
+        match step {
+            Step::Credentials { username, password } => break (username, password),
+            Step::Challenge(challenge) => {
+                writer
+                    .write_all(format!("334 {challenge}\r\n").as_bytes())
+                    .await?;
+                if read_limited_line(reader, &mut line, server.idle_timeout).await? == 0 {
+                    return Ok(None);
+                }
+                let answer = String::from_utf8_lossy(&line);
+                let answer = answer.trim_end_matches(['\r', '\n']);
+                if answer == "*" {
+                    return Ok(Some("501 5.0.0 authentication cancelled\r\n"));
+                }
+                step = match mechanism.answer(answer) {
+                    Ok(step) => step,
+                    Err(e) => return Ok(Some(auth_error(e))),
+                };
+            }
+        }
+    };
+
+    let peer = session.peer;
+    match auth::verify(&server.auth, &server.pool, &username, &password).await {
+        Ok(true) => {
+            tracing::info!(%peer, username, "smtp client authenticated");
+            session.authenticated = Some(username);
+            Ok(Some("235 2.7.0 Authentication successful\r\n"))
+        }
+        Ok(false) => {
+            tracing::warn!(%peer, username, "smtp authentication failed");
+            session.auth_failures += 1;
+            Ok(Some("535 5.7.8 Authentication credentials invalid\r\n"))
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to look up smtp user");
+            Ok(Some("454 4.7.0 Temporary authentication failure\r\n"))
         }
     }
 }
 
-fn extract_path(cmd: &str) -> Option<String> {
-    let start = cmd.find('<')?;
-    let end = cmd[start + 1..].find('>')? + start + 1;
-    let value = cmd[start + 1..end].trim();
-    if value.is_empty() { None } else { Some(value.to_string()) }
+/// Validates the ESMTP parameters of `MAIL FROM`. Returns whether `SMTPUTF8`
+/// was requested, or the reply rejecting the command.
+fn check_mail_params(
+    mail: &MailFrom,
+    esmtp: bool,
+    max_message_size: usize,
+) -> Result<bool, &'static str> {
+    if !mail.params.is_empty() && !esmtp {
+        return Err("555 5.5.4 parameters require EHLO\r\n");
+    }
+    let mut smtputf8 = false;
+    for param in &mail.params {
+        match (param.keyword.as_str(), param.value.as_deref()) {
+            ("SIZE", Some(_)) => match mail.size() {
+                Some(size) if max_message_size > 0 && size > max_message_size => {
+                    return Err(MESSAGE_TOO_LARGE)
+                }
+                Some(_) => {}
+                None => return Err("501 5.5.4 malformed SIZE parameter\r\n"),
+            },
+            ("BODY", Some(body))
+                if body.eq_ignore_ascii_case("7BIT") || body.eq_ignore_ascii_case("8BITMIME") => {}
+            ("SMTPUTF8", None) => smtputf8 = true,
+            // DSN: accepted so senders asking for notifications are not
+            // refused; no DSN is ever sent, as mail is stored or refused
+            // within the session.
+            ("RET", Some(ret))
+                if ret.eq_ignore_ascii_case("FULL") || ret.eq_ignore_ascii_case("HDRS") => {}
+            ("ENVID", Some(_)) => {
+                if mail.envelope_id().is_err() {
+                    return Err("501 5.5.4 malformed ENVID parameter\r\n");
+                }
+            }
+            _ => return Err("555 5.5.4 unsupported MAIL FROM parameter\r\n"),
+        }
+    }
+    if !smtputf8 && mail.path.mailbox().is_some_and(|m| !m.is_ascii()) {
+        return Err(SMTPUTF8_REQUIRED);
+    }
+    Ok(smtputf8)
+}
+
+/// Validates the ESMTP parameters of `RCPT TO`, which are those of DSN.
+/// Returns the decoded `ORCPT`, or the reply rejecting the command.
+fn check_rcpt_params(rcpt: &RcptTo, esmtp: bool) -> Result<Option<String>, &'static str> {
+    if !rcpt.params.is_empty() && !esmtp {
+        return Err("555 5.5.4 parameters require EHLO\r\n");
+    }
+    for param in &rcpt.params {
+        match (param.keyword.as_str(), param.value.as_deref()) {
+            ("NOTIFY", Some(notify)) => {
+                let valid = notify.eq_ignore_ascii_case("NEVER")
+                    || notify.split(',').all(|kind| {
+                        ["SUCCESS", "FAILURE", "DELAY"]
+                            .iter()
+                            .any(|k| kind.eq_ignore_ascii_case(k))
+                    });
+                if !valid {
+                    return Err("501 5.5.4 malformed NOTIFY parameter\r\n");
+                }
+            }
+            ("ORCPT", Some(_)) => {}
+            _ => return Err("555 5.5.4 unsupported RCPT TO parameter\r\n"),
+        }
+    }
+    rcpt.original_recipient()
+        .map_err(|_| "501 5.5.4 malformed ORCPT parameter\r\n")
+}
+
+/// Addresses expecting mail from the domain of the `MAIL FROM` in `cmd`,
+/// if any; a client over its message limit may still deliver to them.
+async fn expecting_mailboxes(server: &Server, cmd: &str) -> Option<Vec<Uuid>> {
+    let parsed = path::parse_mail_from(cmd).ok()?;
+    let sender = parsed.path.mailbox()?;
+    match find_mailboxes_expecting(&server.pool, &sender.domain).await {
+        Ok(ids) if !ids.is_empty() => Some(ids),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to look up expected senders");
+            None
+        }
+    }
+}
+
+/// Where `RCPT TO` sends an accepted address.
+enum Resolved {
+    Mailbox(Recipient),
+    Forward(Forward),
+}
+
+/// Whether mail for `domain` is ours to take: it is configured, has a
+/// routing rule, or no domains are configured. `<Postmaster>` has none and
+/// is always local.
+async fn is_local_domain(server: &Server, domain: Option<&str>) -> Result<bool, sqlx::Error> {
+    let Some(domain) = domain else {
+        return Ok(true);
+    };
+    if server.is_configured_domain(domain) {
+        return Ok(true);
+    }
+    Ok(find_mail_domain(&server.pool, domain).await?.is_some())
+}
+
+/// Applies the routing rule of the address's domain (see [`DomainPolicy`]):
+/// a live address with its tenant's banner domain, or the webhook to forward
+/// to. `None` for addresses the rule refuses, unknown or expired ones.
+/// `local+tag@domain` is delivered to `local@domain` with the tag recorded,
+/// and an alias to the mailbox it belongs to.
+async fn lookup_recipient(server: &Server, addr: &str) -> Result<Option<Resolved>, sqlx::Error> {
+    let pool = &server.pool;
+    let (mailbox, plus_tag) = path::split_plus_tag(addr);
+    let domain = addr.rsplit_once('@').map(|(_, domain)| domain);
+    let rule = match domain {
+        Some(domain) => find_mail_domain(pool, domain).await?,
+        None => None,
+    };
+    let policy = match &rule {
+        Some(rule) => rule.policy,
+        None if server.catch_all && domain.is_some_and(|d| server.is_configured_domain(d)) => {
+            DomainPolicy::CatchAll
+        }
+        None => DomainPolicy::default(),
+    };
+    if let Some(webhook_url) = rule.and_then(|r| r.webhook_url) {
+        return Ok(Some(Resolved::Forward(Forward {
+            addr: addr.to_owned(),
+            webhook_url,
+        })));
+    }
+
+    let found = match find_temporary_email_by_addr(pool, &mailbox).await? {
+        Some(temp) => Some(temp),
+        None => find_temporary_email_by_alias(pool, &mailbox).await?,
+    };
+    let temp = match found {
+        Some(temp) => temp,
+        None if policy == DomainPolicy::CatchAll => {
+            // Names the API would refuse to hand out are not created here either.
+            let (local, _) = mailbox.rsplit_once('@').unwrap_or((mailbox.as_str(), ""));
+            if server.blocklist.get(pool).await?.is_blocked(local) {
+                return Ok(None);
+            }
+            match insert_temporary_email(pool, &mailbox).await {
+                Ok(temp) => {
+                    tracing::info!(addr = mailbox, "created catch-all address");
+                    temp
+                }
+                // Created by a concurrent delivery.
+                Err(e) => find_temporary_email_by_addr(pool, &mailbox)
+                    .await?
+                    .ok_or(e)?,
+            }
+        }
+        None => return Ok(None),
+    };
+    if !temp.is_live() {
+        return Ok(None);
+    }
+    // Honeypots are created by operators and stay reachable.
+    if policy == DomainPolicy::ApiOnly
+        && !temp.is_honeypot
+        && !is_api_created(pool, temp.id).await?
+    {
+        return Ok(None);
+    }
+    let banner_domain = match find_tenant_settings_for_address(pool, temp.id).await {
+        Ok(settings) => settings.and_then(|s| s.smtp_banner_domain),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to load tenant settings");
+            None
+        }
+    };
+    Ok(Some(Resolved::Mailbox(Recipient {
+        id: temp.id,
+        addr: addr.to_owned(),
+        plus_tag: plus_tag.map(str::to_owned),
+        original_recipient: None,
+        other_addrs: Vec::new(),
+        honeypot: temp.is_honeypot,
+        banner_domain,
+    })))
+}
+
+/// Whether the recipient's mailbox is at its quota. Honeypots have none.
+async fn is_mailbox_full(server: &Server, rcpt: &Recipient) -> Result<bool, sqlx::Error> {
+    if rcpt.honeypot || !server.mailbox_quota.is_limited() {
+        return Ok(false);
+    }
+    let usage = mailbox_usage(&server.pool, rcpt.id).await?;
+    Ok(server.mailbox_quota.is_full(&usage))
+}
+
+/// How a finished `DATA` ended, with the reply to send.
+enum DataOutcome {
+    /// The message was taken, or dropped on purpose behind a `250`.
+    Accepted(String),
+    /// The message was refused or deferred.
+    Refused(String),
+}
+
+/// Stores or drops the message of a finished `DATA`.
+async fn finish_data(server: &Server, session: &mut Session) -> DataOutcome {
+    let peer = session.peer;
+    let helo = session.ehlo_name.clone().unwrap_or_default();
+    let tls = session.tls;
+    let tags = session.tags.clone();
+    let country = session.country.clone();
+    let country = country.as_deref();
+    let Some(tx) = session.transaction.as_mut() else {
+        return DataOutcome::Refused("503 no transaction\r\n".into());
+    };
+    let from = tx.sender();
+    let size = tx.data.len();
+    if tx.recipients.is_empty() && tx.forwards.is_empty() {
+        // Only recipients deferred to this point; nothing to store.
+        return DataOutcome::Refused("550 5.1.1 User unknown\r\n".into());
+    }
+    match loops::detect(&tx.data, &server.loop_marker, server.max_hops) {
+        None => {
+            tracing::debug!(
+                %peer,
+                helo,
+                tls,
+                recipients = tx.recipients.len() + tx.forwards.len(),
+                size,
+                "message received"
+            );
+            let raw: Arc<[u8]> = Arc::from(&tx.data[..]);
+            let digest = Sha256::digest(&raw);
+            if server.pipeline.runs(BuiltinStage::PoisonCheck) {
+                let threshold = server.poison_threshold as i32;
+                match refuse_known_poison(&server.pool, &digest, threshold).await {
+                    Ok(Some(poison)) => {
+                        tracing::warn!(
+                            %peer,
+                            id = %poison.id,
+                            attempts = poison.attempts,
+                            "refusing quarantined message"
+                        );
+                        publish_quarantined(server, &tx.recipients, from.as_deref(), size, country);
+                        return DataOutcome::Refused(QUARANTINED.into());
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!(error = %e, "failed to check poison messages"),
+                }
+            }
+            if server.pipeline.runs(BuiltinStage::SenderBlocks) {
+                if let Some(reply) = apply_sender_blocks(server, tx, &raw, country).await {
+                    return DataOutcome::Refused(reply);
+                }
+            }
+            // Forward-only messages are passed on unparsed.
+            let mut stored = None;
+            if !tx.recipients.is_empty() {
+                let parsed = match parse_with_deadline(Arc::clone(&raw), server.processing_timeout)
+                    .await
+                {
+                    Ok(parsed) => parsed,
+                    Err(error) => {
+                        let reply = quarantine(server, peer, country, tx, &raw, &digest, &error);
+                        return DataOutcome::Refused(reply.await);
+                    }
+                };
+                let mut provenance = check_sender(server, peer, from.as_deref(), &helo, &raw).await;
+                provenance.tags = tags;
+                provenance.country = country.map(str::to_owned);
+                provenance.accepted_at = tx.accepted_at;
+                match run_stages(server, peer, tx, &raw, &parsed, &mut provenance, country).await {
+                    Verdict::Reject(reply) => return DataOutcome::Refused(reply),
+                    Verdict::Discard => {}
+                    _ => stored = Some((parsed, provenance)),
+                }
+            }
+            if let Err(reply) = forward_message(server, peer, country, tx, &raw).await {
+                return DataOutcome::Refused(reply);
+            }
+            if let Some((parsed, provenance)) = &stored {
+                let persisted = persist_message(
+                    server,
+                    provenance,
+                    from.as_deref(),
+                    tx.is_bounce(),
+                    &tx.recipients,
+                    &raw,
+                    parsed,
+                )
+                .await;
+                // The sender keeps its copy and tries again.
+                if persisted.is_err() {
+                    return DataOutcome::Refused(STORAGE_FAILED.into());
+                }
+            }
+            let banner = tx.recipients.iter().find_map(|r| r.banner_domain.as_deref());
+            DataOutcome::Accepted(format!(
+                "250 queued by {}\r\n",
+                banner.unwrap_or(&server.banner_domain)
+            ))
+        }
+        // Bouncing our own message would feed the loop; swallow it.
+        Some(LoopVerdict::OwnMarker) => {
+            tracing::warn!(%peer, "dropping message carrying our X-Loop marker");
+            publish_loop(server, &tx.recipients, from.as_deref(), size, country);
+            DataOutcome::Accepted("250 queued\r\n".into())
+        }
+        Some(LoopVerdict::TooManyHops(hops)) => {
+            tracing::warn!(%peer, hops, "rejecting looping message");
+            publish_loop(server, &tx.recipients, from.as_deref(), size, country);
+            DataOutcome::Refused("554 5.4.6 mail loop detected (too many hops)\r\n".into())
+        }
+    }
+}
+
+/// Checks the envelope sender and the `From:` address against the sender
+/// blocklists. A global block refuses the message; a mailbox's block drops
+/// that recipient without a word, as the others may still want the message.
+/// The lists are not consulted when the database fails.
+async fn apply_sender_blocks(
+    server: &Server,
+    tx: &mut Transaction,
+    raw: &[u8],
+    country: Option<&str>,
+) -> Option<String> {
+    let from = tx.sender();
+    let header_from = dmarc::header_from_address(raw);
+    let senders: Vec<&str> = from
+        .iter()
+        .chain(&header_from)
+        .map(String::as_str)
+        .collect();
+    if senders.is_empty() {
+        return None;
+    }
+    let ids: Vec<Uuid> = tx.recipients.iter().map(|r| r.id).collect();
+    let blocks = match find_sender_blocks(&server.pool, &ids, &senders).await {
+        Ok(blocks) => blocks,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to check sender blocks");
+            return None;
+        }
+    };
+    let publish = |rcpt: &str| {
+        let event = IngestEvent::new(Disposition::Blocked, Some(rcpt), from.as_deref(), raw.len());
+        server.events.publish(event.with_country(country));
+    };
+    if blocks.global {
+        tracing::info!(
+            from = from.as_deref(),
+            header_from = header_from.as_deref(),
+            "refusing mail from a blocked sender"
+        );
+        metrics::counter!("smtp_blocked_senders_total", "scope" => "global").increment(1);
+        for rcpt in &tx.recipients {
+            publish(&rcpt.addr);
+        }
+        for fwd in &tx.forwards {
+            publish(&fwd.addr);
+        }
+        return Some(SENDER_BLOCKED.into());
+    }
+    tx.recipients.retain(|rcpt| {
+        let blocked = blocks.mailboxes.contains(&rcpt.id);
+        if blocked {
+            tracing::debug!(rcpt = %rcpt.addr, "dropping mail from a blocked sender");
+            metrics::counter!("smtp_blocked_senders_total", "scope" => "mailbox").increment(1);
+            publish(&rcpt.addr);
+        }
+        !blocked
+    });
+    None
+}
+
+/// Where a message came from and what SPF, DKIM and DMARC said about it.
+struct Provenance {
+    peer_ip: String,
+    spf: Option<SpfResult>,
+    dkim: Vec<dkim::Verdict>,
+    dmarc: Option<DmarcResult>,
+    /// Tags from the session it arrived in.
+    tags: Vec<String>,
+    country: Option<String>,
+    /// When its first recipient was accepted.
+    accepted_at: Option<DateTime<Utc>>,
+}
+
+/// Runs the SPF and DKIM checks that are enabled, side by side, then
+/// DMARC on their results.
+async fn check_sender(
+    server: &Server,
+    peer: IpAddr,
+    from: Option<&str>,
+    helo: &str,
+    raw: &[u8],
+) -> Provenance {
+    let mut provenance = Provenance {
+        peer_ip: peer.to_string(),
+        spf: None,
+        dkim: Vec::new(),
+        dmarc: None,
+        tags: Vec::new(),
+        country: None,
+        accepted_at: None,
+    };
+    let Some(dns) = &server.dns else {
+        return provenance;
+    };
+    let spf = async {
+        if !server.check_spf {
+            return None;
+        }
+        let result = spf::check(dns, peer, from, helo).await;
+        tracing::debug!(%peer, from, helo, spf = %result, "spf checked");
+        metrics::counter!("smtp_spf_results_total", "result" => result.as_str()).increment(1);
+        Some(result)
+    };
+    let dkim = async {
+        if !server.check_dkim {
+            return Vec::new();
+        }
+        let verdicts = dkim::verify(dns, raw).await;
+        for v in &verdicts {
+            tracing::debug!(
+                %peer,
+                domain = %v.domain,
+                selector = %v.selector,
+                dkim = %v.result,
+                reason = v.reason,
+                "dkim checked"
+            );
+            metrics::counter!("smtp_dkim_results_total", "result" => v.result.as_str())
+                .increment(1);
+        }
+        verdicts
+    };
+    (provenance.spf, provenance.dkim) = tokio::join!(spf, dkim);
+    if server.check_dmarc {
+        let result = dmarc::evaluate(dns, raw, from, helo, provenance.spf, &provenance.dkim).await;
+        tracing::debug!(%peer, from, dmarc = %result, "dmarc checked");
+        metrics::counter!("smtp_dmarc_results_total", "result" => result.as_str()).increment(1);
+        provenance.dmarc = Some(result);
+    }
+    provenance
+}
+
+/// Runs the stages added to the pipeline over a message about to be stored.
+/// When one discards or rejects it, every mailbox recipient gets a
+/// `filtered` event.
+async fn run_stages(
+    server: &Server,
+    peer: IpAddr,
+    tx: &Transaction,
+    raw: &[u8],
+    parsed: &ParsedMessage,
+    provenance: &mut Provenance,
+    country: Option<&str>,
+) -> Verdict {
+    let from = tx.sender();
+    let recipients: Vec<String> = tx.recipients.iter().map(|r| r.addr.clone()).collect();
+    let message = IncomingMessage {
+        peer,
+        mail_from: from.as_deref(),
+        recipients: &recipients,
+        raw,
+        subject: parsed.subject.as_deref(),
+        headers: &parsed.headers,
+        tags: &provenance.tags,
+        spf: provenance.spf,
+        dmarc: provenance.dmarc,
+    };
+    let mut tags = Vec::new();
+    let verdict = server.pipeline.process(&message, &mut tags).await;
+    provenance.tags.extend(tags);
+    if matches!(verdict, Verdict::Discard | Verdict::Reject(_)) {
+        for rcpt in &recipients {
+            let event = IngestEvent::new(
+                Disposition::Filtered,
+                Some(rcpt),
+                from.as_deref(),
+                raw.len(),
+            );
+            server.events.publish(event.with_country(country));
+        }
+    }
+    verdict
+}
+
+/// POSTs the message to the webhook of every forwarded recipient, once per
+/// webhook. Runs before anything is stored: if a webhook fails the sender
+/// gets `451` and its retry delivers to the mailboxes once (webhooks that
+/// already answered may see the message again).
+async fn forward_message(
+    server: &Server,
+    peer: IpAddr,
+    country: Option<&str>,
+    tx: &Transaction,
+    raw: &[u8],
+) -> Result<(), String> {
+    let from = tx.sender();
+    let mut urls: Vec<&str> = tx.forwards.iter().map(|f| f.webhook_url.as_str()).collect();
+    urls.sort_unstable();
+    urls.dedup();
+    for url in urls {
+        let rcpts: Vec<&str> = tx
+            .forwards
+            .iter()
+            .filter(|f| f.webhook_url == url)
+            .map(|f| f.addr.as_str())
+            .collect();
+        let sent = webhook::forward(&server.http, url, from.as_deref(), &rcpts, peer, raw).await;
+        let disposition = match &sent {
+            Ok(()) => Disposition::Forwarded,
+            Err(e) => {
+                tracing::warn!(%peer, error = %e, "webhook delivery failed");
+                Disposition::Failed
+            }
+        };
+        for rcpt in &rcpts {
+            let event = IngestEvent::new(disposition, Some(rcpt), from.as_deref(), raw.len());
+            server.events.publish(event.with_country(country));
+        }
+        if sent.is_err() {
+            return Err("451 4.4.0 forwarding failed, try again later\r\n".into());
+        }
+    }
+    Ok(())
+}
+
+/// Parses on a blocking thread so a pathological message can neither stall
+/// the runtime nor take the session down with a panic. A parser that runs
+/// past the deadline is abandoned, not killed; its thread finishes on its own.
+async fn parse_with_deadline(raw: Arc<[u8]>, deadline: Duration) -> Result<ParsedMessage, String> {
+    let task = tokio::task::spawn_blocking(move || ParsedMessage::parse(&raw));
+    match tokio::time::timeout(deadline, task).await {
+        Ok(Ok(parsed)) => Ok(parsed),
+        Ok(Err(e)) if e.is_panic() => {
+            let payload = e.into_panic();
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("parser panicked: {msg}"))
+        }
+        Ok(Err(e)) => Err(format!("parser task failed: {e}")),
+        Err(_) => Err(format!("processing exceeded {}s", deadline.as_secs_f64())),
+    }
+}
+
+/// Records a failed message and answers `451` until it has failed
+/// `poison_threshold` times, then `554`.
+async fn quarantine(
+    server: &Server,
+    peer: IpAddr,
+    country: Option<&str>,
+    tx: &Transaction,
+    raw: &[u8],
+    digest: &[u8],
+    error: &str,
+) -> String {
+    let from = tx.sender();
+    let recipients: Vec<String> = tx.recipients.iter().map(|r| r.addr.clone()).collect();
+    tracing::error!(%peer, error, size = raw.len(), "quarantining message");
+    publish_quarantined(server, &tx.recipients, from.as_deref(), raw.len(), country);
+    let recorded = record_poison_message(
+        &server.pool,
+        &NewPoisonMessage {
+            digest,
+            peer_ip: Some(&peer.to_string()),
+            mail_from: from.as_deref(),
+            recipients: &recipients,
+            error,
+            raw_email: raw,
+        },
+    )
+    .await;
+    match recorded {
+        Ok(poison) if poison.attempts >= server.poison_threshold as i32 => QUARANTINED.into(),
+        Ok(_) => "451 4.3.0 message could not be processed, try again later\r\n".into(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to record poison message");
+            "451 4.3.0 message could not be processed, try again later\r\n".into()
+        }
+    }
+}
+
+fn publish_quarantined(
+    server: &Server,
+    rcpts: &[Recipient],
+    from_addr: Option<&str>,
+    size: usize,
+    country: Option<&str>,
+) {
+    for rcpt in rcpts {
+        let event = IngestEvent::new(Disposition::Quarantined, Some(&rcpt.addr), from_addr, size);
+        server.events.publish(event.with_country(country));
+    }
+}
+
+fn publish_loop(
+    server: &Server,
+    rcpts: &[Recipient],
+    from_addr: Option<&str>,
+    size: usize,
+    country: Option<&str>,
+) {
+    for rcpt in rcpts {
+        let event = IngestEvent::new(Disposition::Loop, Some(&rcpt.addr), from_addr, size);
+        server.events.publish(event.with_country(country));
+    }
+}
+
+/// Stores the message once per recipient, all in one transaction, so a
+/// multi-recipient message lands in every inbox or in none. On failure
+/// nothing was stored and the client must be told to retry.
+async fn persist_message(
+    server: &Server,
+    provenance: &Provenance,
+    from_addr: Option<&str>,
+    is_bounce: bool,
+    rcpts: &[Recipient],
+    raw: &[u8],
+    parsed: &ParsedMessage,
+) -> Result<(), sqlx::Error> {
+    let pool = &server.pool;
+    let attachments: Vec<NewAttachment> = parsed
+        .attachments
+        .iter()
+        .map(|a| NewAttachment {
+            filename: a.filename.as_deref(),
+            content_type: &a.content_type,
+            content: &a.content,
+        })
+        .collect();
+
+    let dkim: Vec<NewDkimSignature> = provenance.dkim.iter().map(dkim::Verdict::as_new).collect();
+    let email = NewReceivedEmail {
+        temporary_email_id: Uuid::nil(),
+        from_addr,
+        to_addr: None,
+        subject: parsed.subject.as_deref(),
+        body_text: parsed.body_text.as_deref(),
+        body_html: parsed.body_html.as_deref(),
+        raw_email: Some(raw),
+        headers: &parsed.headers,
+        is_bounce,
+        peer_ip: Some(&provenance.peer_ip),
+        spf_result: provenance.spf,
+        dkim: &dkim,
+        dmarc_result: provenance.dmarc,
+        tags: &provenance.tags,
+        country: provenance.country.as_deref(),
+        plus_tag: None,
+        original_recipient: None,
+        envelope_recipients: &[],
+        header_recipients: &parsed.recipients,
+        accepted_at: provenance.accepted_at,
+    };
+    let envelopes: Vec<Vec<String>> = rcpts.iter().map(Recipient::envelope_addrs).collect();
+    let targets: Vec<_> = rcpts
+        .iter()
+        .zip(&envelopes)
+        .map(|(r, envelope)| {
+            (
+                r.id,
+                r.addr.as_str(),
+                r.plus_tag.as_deref(),
+                r.original_recipient.as_deref(),
+                envelope.as_slice(),
+            )
+        })
+        .collect();
+    let insert = insert_received_email_for_recipients(
+        pool,
+        &email,
+        &targets,
+        &attachments,
+        server.body_compression,
+    );
+    let rows = match db::timed("smtp", "insert_message", insert).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!(error = %e, recipients = rcpts.len(), "failed to persist email");
+            for rcpt in rcpts {
+                let event =
+                    IngestEvent::new(Disposition::Failed, Some(&rcpt.addr), from_addr, raw.len());
+                server
+                    .events
+                    .publish(event.with_country(provenance.country.as_deref()));
+            }
+            return Err(e);
+        }
+    };
+
+    let (mut delivered, mut honeypot) = (0, 0);
+    for (rcpt, row) in rcpts.iter().zip(&rows) {
+        let disposition = if rcpt.honeypot {
+            tracing::debug!(rcpt = %rcpt.addr, id = %row.id, "stored for honeypot");
+            honeypot += 1;
+            Disposition::Honeypot
+        } else {
+            tracing::debug!(rcpt = %rcpt.addr, id = %row.id, "stored");
+            delivered += 1;
+            if let Err(e) = record_message_usage(pool, rcpt.id).await {
+                tracing::warn!(error = %e, rcpt = %rcpt.addr, "failed to record usage");
+            }
+            Disposition::Delivered
+        };
+        let event = IngestEvent::new(disposition, Some(&rcpt.addr), from_addr, raw.len());
+        server
+            .events
+            .publish(event.with_country(provenance.country.as_deref()));
+    }
+    tracing::info!(
+        recipients = rcpts.len(),
+        delivered,
+        honeypot,
+        "message stored"
+    );
+
+    let deliveries: Vec<watch::Delivery> = rcpts
+        .iter()
+        .zip(&rows)
+        .filter(|(rcpt, _)| !rcpt.honeypot)
+        .map(|(rcpt, row)| watch::Delivery {
+            temporary_email_id: rcpt.id,
+            addr: &rcpt.addr,
+            email_id: row.id,
+        })
+        .collect();
+    watch::fire(
+        pool,
+        &server.http,
+        &deliveries,
+        from_addr,
+        parsed.subject.as_deref(),
+        parsed.body_text.as_deref(),
+    )
+    .await;
+
+    if rcpts.iter().any(|r| r.honeypot) {
+        let sender_domain = from_addr
+            .and_then(|a| a.rsplit_once('@'))
+            .map(|(_, d)| d.to_ascii_lowercase())
+            .unwrap_or_default();
+        if let Err(e) = record_honeypot_hit(pool, &sender_domain, Some(&provenance.peer_ip)).await {
+            tracing::error!(error = %e, "failed to record honeypot hit");
+        }
+    }
+    Ok(())
 }
@@ -0,0 +1,48 @@
+//! Mail-loop detection on the header block of an incoming message.
+
+/// Header stamped on anything we send out, so it can be recognised if it
+/// comes back in.
+pub const LOOP_HEADER: &str = "X-Loop";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LoopVerdict {
+    /// Carries our own `X-Loop` marker: one of our messages came back.
+    OwnMarker,
+    /// More `Received:` hops than allowed.
+    TooManyHops(usize),
+}
+
+pub(crate) fn detect(raw: &[u8], marker: &str, max_hops: usize) -> Option<LoopVerdict> {
+    let headers = header_block(raw);
+    let mut hops = 0;
+    for (name, value) in header_fields(&headers) {
+        if name.eq_ignore_ascii_case("received") {
+            hops += 1;
+        } else if name.eq_ignore_ascii_case(LOOP_HEADER)
+            && !marker.is_empty()
+            && value.trim().eq_ignore_ascii_case(marker)
+        {
+            return Some(LoopVerdict::OwnMarker);
+        }
+    }
+    (max_hops > 0 && hops > max_hops).then_some(LoopVerdict::TooManyHops(hops))
+}
+
+/// The text before the first empty line. Header names are ASCII; anything
+/// else in values is replaced, which is fine for matching.
+fn header_block(raw: &[u8]) -> std::borrow::Cow<'_, str> {
+    let end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map_or(raw.len(), |i| i + 2);
+    String::from_utf8_lossy(&raw[..end])
+}
+
+/// `(name, first line of value)` for each header field; continuation lines are
+/// skipped since only names and short values matter here.
+fn header_fields(raw: &str) -> impl Iterator<Item = (&str, &str)> {
+    raw.split("\r\n")
+        .take_while(|line| !line.is_empty())
+        .filter(|line| !line.starts_with([' ', '\t']))
+        .filter_map(|line| line.split_once(':'))
+}
@@ -0,0 +1,105 @@
+//! Extracting what we store from a raw message. Kept free of I/O so it can
+//! run on a blocking thread under a deadline.
+
+use mail_parser::{Address, Message, MessageParser, MimeHeaders};
+
+pub(crate) struct ParsedAttachment {
+    pub filename: Option<String>,
+    pub content_type: String,
+    pub content: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(crate) struct ParsedMessage {
+    pub subject: Option<String>,
+    pub body_text: Option<String>,
+    pub body_html: Option<String>,
+    pub attachments: Vec<ParsedAttachment>,
+    /// Header fields as (name, value), see [`header_fields`].
+    pub headers: Vec<(String, String)>,
+    /// Addresses in `To:` and `Cc:`, lowercased and without duplicates.
+    pub recipients: Vec<String>,
+}
+
+impl ParsedMessage {
+    /// Unparseable input yields an empty message; the raw bytes are stored
+    /// regardless.
+    pub fn parse(raw: &[u8]) -> Self {
+        let headers = header_fields(raw);
+        let Some(message) = MessageParser::default().parse(raw) else {
+            return Self {
+                headers,
+                ..Self::default()
+            };
+        };
+        let attachments = message
+            .attachments()
+            .map(|part| {
+                let content_type = part
+                    .content_type()
+                    .map(|ct| match ct.subtype() {
+                        Some(sub) => format!("{}/{sub}", ct.ctype()),
+                        None => ct.ctype().to_owned(),
+                    })
+                    .unwrap_or_else(|| "application/octet-stream".into())
+                    .to_ascii_lowercase();
+                ParsedAttachment {
+                    filename: part.attachment_name().map(str::to_owned),
+                    content_type,
+                    content: part.contents().to_vec(),
+                }
+            })
+            .collect();
+        Self {
+            subject: message.subject().map(str::to_owned),
+            body_text: message.body_text(0).map(|s| s.into_owned()),
+            body_html: message.body_html(0).map(|s| s.into_owned()),
+            attachments,
+            headers,
+            recipients: header_recipients(&message),
+        }
+    }
+}
+
+fn header_recipients(message: &Message<'_>) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for field in [message.to(), message.cc()].into_iter().flatten() {
+        let addrs: Vec<_> = match field {
+            Address::List(list) => list.iter().collect(),
+            Address::Group(groups) => groups.iter().flat_map(|g| &g.addresses).collect(),
+        };
+        for addr in addrs.into_iter().filter_map(|a| a.address.as_deref()) {
+            let addr = addr.to_ascii_lowercase();
+            if !recipients.contains(&addr) {
+                recipients.push(addr);
+            }
+        }
+    }
+    recipients
+}
+
+/// Every field of the header section in order, unfolded but otherwise as
+/// written (encoded words are not decoded), so trace fields such as
+/// `Received` and `DKIM-Signature` read exactly as they arrived. Lines that
+/// are neither a field nor a continuation are skipped.
+fn header_fields(raw: &[u8]) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in raw.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str(&line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim_end().to_owned(), value.trim_start().to_owned()));
+        }
+    }
+    for (_, value) in &mut fields {
+        value.truncate(value.trim_end().len());
+    }
+    fields
+}
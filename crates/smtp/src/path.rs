@@ -0,0 +1,388 @@
+//! RFC 5321 paths and ESMTP parameters of `MAIL FROM` and `RCPT TO`.
+
+use std::fmt;
+
+const MAX_LOCAL_PART_LEN: usize = 64;
+const MAX_DOMAIN_LEN: usize = 255;
+/// Longest `ENVID` value, before decoding (RFC 3461, 4.4).
+const MAX_ENVID_LEN: usize = 100;
+/// Longest `ORCPT` value, before decoding (RFC 3461, 4.2).
+const MAX_ORCPT_LEN: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mailbox {
+    /// Without quoting: `"a b"@x` has local part `a b`.
+    pub local_part: String,
+    /// A host name or an address literal including its brackets.
+    pub domain: String,
+}
+
+impl Mailbox {
+    /// Lowercased `local@domain`, the form addresses are stored in.
+    pub fn address(&self) -> String {
+        self.to_string().to_ascii_lowercase()
+    }
+
+    /// False for internationalized addresses, which need `SMTPUTF8`.
+    pub fn is_ascii(&self) -> bool {
+        self.local_part.is_ascii() && self.domain.is_ascii()
+    }
+}
+
+impl fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if is_dot_string(&self.local_part) {
+            f.write_str(&self.local_part)?;
+        } else {
+            f.write_str("\"")?;
+            for c in self.local_part.chars() {
+                if matches!(c, '"' | '\\') {
+                    f.write_str("\\")?;
+                }
+                write!(f, "{c}")?;
+            }
+            f.write_str("\"")?;
+        }
+        write!(f, "@{}", self.domain)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReversePath {
+    /// `<>`: bounces and other mail that must not be answered.
+    Null,
+    Mailbox(Mailbox),
+}
+
+impl ReversePath {
+    pub fn mailbox(&self) -> Option<&Mailbox> {
+        match self {
+            Self::Null => None,
+            Self::Mailbox(m) => Some(m),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForwardPath {
+    /// `<Postmaster>` without a domain, which every server must accept.
+    Postmaster,
+    Mailbox(Mailbox),
+}
+
+/// `KEYWORD[=value]`; keywords are uppercased.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EsmtpParam {
+    pub keyword: String,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailFrom {
+    pub path: ReversePath,
+    pub params: Vec<EsmtpParam>,
+}
+
+impl MailFrom {
+    /// Declared message size from the `SIZE` parameter.
+    pub fn size(&self) -> Option<usize> {
+        param(&self.params, "SIZE")?.parse().ok()
+    }
+
+    /// The sender's transaction id from the DSN parameter `ENVID`, decoded.
+    pub fn envelope_id(&self) -> Result<Option<String>, PathError> {
+        let Some(value) = param(&self.params, "ENVID") else {
+            return Ok(None);
+        };
+        if value.len() > MAX_ENVID_LEN {
+            return Err(PathError::BadParameter);
+        }
+        decode_xtext(value).map(Some).ok_or(PathError::BadParameter)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RcptTo {
+    pub path: ForwardPath,
+    pub params: Vec<EsmtpParam>,
+}
+
+impl RcptTo {
+    /// The address from the DSN parameter `ORCPT=<addr-type>;<xtext>`,
+    /// decoded: whom the sender addressed before any forwarding rewrote the
+    /// recipient. The address type (`rfc822`, `utf-8`, ...) is dropped.
+    pub fn original_recipient(&self) -> Result<Option<String>, PathError> {
+        let Some(value) = param(&self.params, "ORCPT") else {
+            return Ok(None);
+        };
+        let (addr_type, xtext) = value.split_once(';').ok_or(PathError::BadParameter)?;
+        let type_ok = !addr_type.is_empty()
+            && addr_type
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+        if !type_ok || xtext.is_empty() || value.len() > MAX_ORCPT_LEN {
+            return Err(PathError::BadParameter);
+        }
+        decode_xtext(xtext).map(Some).ok_or(PathError::BadParameter)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The command does not start with `MAIL FROM:` / `RCPT TO:`.
+    WrongCommand,
+    MissingBrackets,
+    BadLocalPart,
+    LocalPartTooLong,
+    BadDomain,
+    DomainTooLong,
+    BadParameter,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::WrongCommand => "unexpected command",
+            Self::MissingBrackets => "path must be enclosed in <>",
+            Self::BadLocalPart => "malformed local part",
+            Self::LocalPartTooLong => "local part exceeds 64 characters",
+            Self::BadDomain => "malformed domain",
+            Self::DomainTooLong => "domain exceeds 255 characters",
+            Self::BadParameter => "malformed ESMTP parameter",
+        })
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Parses a whole `MAIL FROM:<path> [params]` command line.
+pub fn parse_mail_from(cmd: &str) -> Result<MailFrom, PathError> {
+    let rest = strip_verb(cmd, "MAIL FROM:")?;
+    let (path, params) = split_path(rest)?;
+    let path = if path.is_empty() {
+        ReversePath::Null
+    } else {
+        ReversePath::Mailbox(parse_mailbox(strip_source_route(path))?)
+    };
+    Ok(MailFrom {
+        path,
+        params: parse_params(params)?,
+    })
+}
+
+/// Parses a whole `RCPT TO:<path> [params]` command line.
+pub fn parse_rcpt_to(cmd: &str) -> Result<RcptTo, PathError> {
+    let rest = strip_verb(cmd, "RCPT TO:")?;
+    let (path, params) = split_path(rest)?;
+    let path = if path.eq_ignore_ascii_case("postmaster") {
+        ForwardPath::Postmaster
+    } else {
+        ForwardPath::Mailbox(parse_mailbox(strip_source_route(path))?)
+    };
+    Ok(RcptTo {
+        path,
+        params: parse_params(params)?,
+    })
+}
+
+/// Splits a stored-form address `local+tag@domain` into the mailbox
+/// `local@domain` and `tag`. Generated and chosen usernames cannot contain
+/// `+`, so the tag starts at the first one; an empty tag counts as none.
+pub fn split_plus_tag(addr: &str) -> (String, Option<&str>) {
+    let Some((local, domain)) = addr.rsplit_once('@') else {
+        return (addr.to_owned(), None);
+    };
+    match local.split_once('+') {
+        Some((base, tag)) if !base.is_empty() && !base.starts_with('"') => (
+            format!("{base}@{domain}"),
+            Some(tag).filter(|t| !t.is_empty()),
+        ),
+        _ => (addr.to_owned(), None),
+    }
+}
+
+/// Decodes RFC 3461 `xtext`, in which `+XX` is the byte with hex value
+/// `XX` and `+`, `=` and anything outside printable ASCII must be written
+/// that way. `None` when malformed or when the bytes are not UTF-8.
+pub fn decode_xtext(s: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => {
+                let hex = std::str::from_utf8(rest.get(..2)?).ok()?;
+                if !hex.bytes().all(|h| h.is_ascii_hexdigit()) {
+                    return None;
+                }
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &rest[2..];
+            }
+            b'=' => return None,
+            33..=126 => bytes.push(b),
+            _ => return None,
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn strip_verb<'a>(cmd: &'a str, verb: &str) -> Result<&'a str, PathError> {
+    match cmd.get(..verb.len()) {
+        Some(head) if head.eq_ignore_ascii_case(verb) => Ok(cmd[verb.len()..].trim_start()),
+        _ => Err(PathError::WrongCommand),
+    }
+}
+
+/// Splits `<path> params` at the closing bracket, which may appear inside a
+/// quoted local part.
+fn split_path(s: &str) -> Result<(&str, &str), PathError> {
+    let inner = s.strip_prefix('<').ok_or(PathError::MissingBrackets)?;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '>' if !quoted => {
+                let params = &inner[i + 1..];
+                if !params.is_empty() && !params.starts_with(' ') {
+                    return Err(PathError::BadParameter);
+                }
+                return Ok((&inner[..i], params));
+            }
+            _ => {}
+        }
+    }
+    Err(PathError::MissingBrackets)
+}
+
+/// Drops an obsolete `@relay1,@relay2:` source route; it must be ignored.
+fn strip_source_route(path: &str) -> &str {
+    if path.starts_with('@') {
+        if let Some((_, mailbox)) = path.split_once(':') {
+            return mailbox;
+        }
+    }
+    path
+}
+
+fn parse_mailbox(s: &str) -> Result<Mailbox, PathError> {
+    let (local_part, domain) = if s.starts_with('"') {
+        parse_quoted_local(s)?
+    } else {
+        let (local, domain) = s.rsplit_once('@').ok_or(PathError::BadLocalPart)?;
+        if !is_dot_string(local) {
+            return Err(PathError::BadLocalPart);
+        }
+        (local.to_owned(), domain)
+    };
+    if local_part.len() > MAX_LOCAL_PART_LEN {
+        return Err(PathError::LocalPartTooLong);
+    }
+    if domain.len() > MAX_DOMAIN_LEN {
+        return Err(PathError::DomainTooLong);
+    }
+    if !is_domain(domain) && !is_address_literal(domain) {
+        return Err(PathError::BadDomain);
+    }
+    Ok(Mailbox {
+        local_part,
+        domain: domain.to_owned(),
+    })
+}
+
+/// `"..."@domain` into the unescaped local part and the domain.
+fn parse_quoted_local(s: &str) -> Result<(String, &str), PathError> {
+    let mut local = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let domain = s[i + 1..]
+                    .strip_prefix('@')
+                    .ok_or(PathError::BadLocalPart)?;
+                return Ok((local, domain));
+            }
+            '\\' => match chars.next() {
+                Some((_, c)) if (' '..='~').contains(&c) => local.push(c),
+                _ => return Err(PathError::BadLocalPart),
+            },
+            ' '..='~' => local.push(c),
+            _ if !c.is_ascii() => local.push(c),
+            _ => return Err(PathError::BadLocalPart),
+        }
+    }
+    Err(PathError::BadLocalPart)
+}
+
+/// RFC 5322 atext, plus any non-ASCII character as RFC 6531 allows.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || !c.is_ascii()
+}
+
+fn is_dot_string(s: &str) -> bool {
+    !s.is_empty()
+        && s.split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext))
+}
+
+fn is_domain(s: &str) -> bool {
+    !s.is_empty()
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || !c.is_ascii())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+fn is_address_literal(s: &str) -> bool {
+    s.strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .is_some_and(|inner| {
+            !inner.is_empty()
+                && inner
+                    .bytes()
+                    .all(|b| (33..=126).contains(&b) && !matches!(b, b'[' | b'\\' | b']'))
+        })
+}
+
+fn parse_params(s: &str) -> Result<Vec<EsmtpParam>, PathError> {
+    s.split(' ')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (keyword, value) = match p.split_once('=') {
+                Some((k, v)) => (k, Some(v)),
+                None => (p, None),
+            };
+            let keyword_ok = keyword
+                .bytes()
+                .next()
+                .is_some_and(|b| b.is_ascii_alphanumeric())
+                && keyword
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+            let value_ok = value.is_none_or(|v| {
+                !v.is_empty() && v.bytes().all(|b| (33..=126).contains(&b) && b != b'=')
+            });
+            if !keyword_ok || !value_ok {
+                return Err(PathError::BadParameter);
+            }
+            Ok(EsmtpParam {
+                keyword: keyword.to_ascii_uppercase(),
+                value: value.map(str::to_owned),
+            })
+        })
+        .collect()
+}
+
+fn param<'a>(params: &'a [EsmtpParam], keyword: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|p| p.keyword == keyword)
+        .and_then(|p| p.value.as_deref())
+}
@@ -0,0 +1,232 @@
+//! What a finished message goes through before it is stored, as a chain
+//! set up at startup: loop detection, the poison check, sender blocks,
+//! parsing, SPF/DKIM/DMARC, then any [`IngestStage`]s an embedder added, in
+//! the order added, then forwarding and storage. Added stages can also
+//! refuse recipients at `RCPT TO`. Operators can switch off the built-in
+//! stages named by [`BuiltinStage`]; the SPF, DKIM and DMARC checks have
+//! switches of their own in [`SmtpConfig`](crate::SmtpConfig).
+
+use std::fmt;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use db::{DmarcResult, SpfResult};
+
+/// Built-in stages that may be left out of the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinStage {
+    /// Refuse messages that already failed processing `poison_threshold`
+    /// times, without parsing them again.
+    PoisonCheck,
+    /// Drop mail from senders on the global or a mailbox's blocklist.
+    SenderBlocks,
+}
+
+impl BuiltinStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::PoisonCheck => "poison-check",
+            Self::SenderBlocks => "sender-blocks",
+        }
+    }
+}
+
+impl fmt::Display for BuiltinStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for BuiltinStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "poison-check" => Ok(Self::PoisonCheck),
+            "sender-blocks" => Ok(Self::SenderBlocks),
+            other => Err(format!(
+                "unknown stage {other:?}; expected poison-check or sender-blocks"
+            )),
+        }
+    }
+}
+
+/// A message about to be stored, as a stage sees it.
+#[derive(Debug)]
+pub struct IncomingMessage<'a> {
+    pub peer: IpAddr,
+    /// `None` for a null reverse path.
+    pub mail_from: Option<&'a str>,
+    /// Addresses of the mailboxes it is for; webhook-domain recipients are
+    /// not included.
+    pub recipients: &'a [String],
+    pub raw: &'a [u8],
+    pub subject: Option<&'a str>,
+    /// Header fields as (name, value), unfolded but not decoded.
+    pub headers: &'a [(String, String)],
+    /// Tags so far, from the session and earlier stages.
+    pub tags: &'a [String],
+    pub spf: Option<SpfResult>,
+    pub dmarc: Option<DmarcResult>,
+}
+
+/// What a stage decided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Continue,
+    /// Continue, storing the message with this tag.
+    Tag(String),
+    /// Answer `250` but store nothing; webhook-domain recipients still get
+    /// the message.
+    Discard,
+    /// Refuse the message with this reply, e.g. `550 5.7.1 not wanted`.
+    /// Replies that are not a 4xx or 5xx code are sent as `554 5.7.1`.
+    Reject(String),
+}
+
+/// A step added to the chain. Stages run one after another, each seeing
+/// the tags of the ones before; the first to discard or reject ends the
+/// chain.
+pub trait IngestStage: Send + Sync {
+    /// Names the stage in logs and the `smtp_ingest_stage_total` metric.
+    fn name(&self) -> &str;
+
+    fn process<'a>(
+        &'a self,
+        message: &'a IncomingMessage<'a>,
+    ) -> Pin<Box<dyn Future<Output = Verdict> + Send + 'a>>;
+
+    /// Called at `RCPT TO` once `rcpt` has resolved to a mailbox, before
+    /// any message is read; `false` refuses the recipient with `550 5.7.1`.
+    /// Accepts everyone unless overridden.
+    fn accepts_recipient<'a>(
+        &'a self,
+        _peer: IpAddr,
+        _mail_from: Option<&'a str>,
+        _rcpt: &'a str,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(async { true })
+    }
+}
+
+/// The configurable part of the chain; by default every built-in stage
+/// runs and nothing is added.
+#[derive(Clone, Default)]
+pub struct Pipeline {
+    disabled: Vec<BuiltinStage>,
+    stages: Vec<Arc<dyn IngestStage>>,
+}
+
+impl Pipeline {
+    pub fn without(mut self, stage: BuiltinStage) -> Self {
+        if !self.disabled.contains(&stage) {
+            self.disabled.push(stage);
+        }
+        self
+    }
+
+    /// Appends `stage`; it runs after those added before it.
+    pub fn with_stage(mut self, stage: impl IngestStage + 'static) -> Self {
+        self.stages.push(Arc::new(stage));
+        self
+    }
+
+    pub fn runs(&self, stage: BuiltinStage) -> bool {
+        !self.disabled.contains(&stage)
+    }
+
+    /// Runs the added stages over `message`, stopping at the first that
+    /// discards or rejects it; tags they asked for are appended to `tags`.
+    pub(crate) async fn process(
+        &self,
+        message: &IncomingMessage<'_>,
+        tags: &mut Vec<String>,
+    ) -> Verdict {
+        let mut seen = message.tags.to_vec();
+        for stage in &self.stages {
+            let message = IncomingMessage {
+                tags: &seen,
+                ..*message
+            };
+            let verdict = stage.process(&message).await;
+            let label = match &verdict {
+                Verdict::Continue => "continue",
+                Verdict::Tag(_) => "tag",
+                Verdict::Discard => "discard",
+                Verdict::Reject(_) => "reject",
+            };
+            metrics::counter!(
+                "smtp_ingest_stage_total",
+                "stage" => stage.name().to_owned(),
+                "verdict" => label
+            )
+            .increment(1);
+            match verdict {
+                Verdict::Continue => {}
+                Verdict::Tag(tag) => {
+                    if !seen.contains(&tag) {
+                        seen.push(tag.clone());
+                        tags.push(tag);
+                    }
+                }
+                Verdict::Discard => {
+                    tracing::debug!(stage = stage.name(), "message discarded");
+                    return Verdict::Discard;
+                }
+                Verdict::Reject(reply) => {
+                    tracing::info!(stage = stage.name(), reply = %reply, "message rejected");
+                    return Verdict::Reject(smtp_reply(&reply));
+                }
+            }
+        }
+        Verdict::Continue
+    }
+
+    /// The name of the first added stage refusing `rcpt`, if any.
+    pub(crate) async fn refusing_stage(
+        &self,
+        peer: IpAddr,
+        mail_from: Option<&str>,
+        rcpt: &str,
+    ) -> Option<&str> {
+        for stage in &self.stages {
+            if !stage.accepts_recipient(peer, mail_from, rcpt).await {
+                metrics::counter!(
+                    "smtp_ingest_stage_total",
+                    "stage" => stage.name().to_owned(),
+                    "verdict" => "refuse_recipient"
+                )
+                .increment(1);
+                return Some(stage.name());
+            }
+        }
+        None
+    }
+}
+
+/// `reply` as a single CRLF-terminated line with a 4xx or 5xx code.
+fn smtp_reply(reply: &str) -> String {
+    let line = reply.lines().next().unwrap_or_default().trim();
+    let coded = line.len() >= 3
+        && matches!(line.as_bytes()[0], b'4' | b'5')
+        && line.as_bytes()[..3].iter().all(u8::is_ascii_digit)
+        && (line.len() == 3 || line.as_bytes()[3] == b' ');
+    if coded {
+        format!("{line}\r\n")
+    } else {
+        format!("554 5.7.1 {line}\r\n")
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<&str> = self.stages.iter().map(|s| s.name()).collect();
+        f.debug_struct("Pipeline")
+            .field("disabled", &self.disabled)
+            .field("stages", &stages)
+            .finish()
+    }
+}
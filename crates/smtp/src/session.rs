@@ -0,0 +1,155 @@
+//! Per-connection SMTP state: who is connected, what they said in `EHLO`,
+//! and the mail transaction in progress.
+
+use std::net::IpAddr;
+
+use bytes::BytesMut;
+use chrono::{DateTime, Utc};
+
+use crate::path::ReversePath;
+
+#[derive(Clone)]
+pub(crate) struct Recipient {
+    pub id: uuid::Uuid,
+    /// As given in `RCPT TO`, including any `+tag`.
+    pub addr: String,
+    pub plus_tag: Option<String>,
+    /// From the DSN `ORCPT` parameter, when the client sent one.
+    pub original_recipient: Option<String>,
+    /// Other `RCPT TO` addresses of the transaction reaching the same
+    /// mailbox, e.g. another `+tag` form of it.
+    pub other_addrs: Vec<String>,
+    pub honeypot: bool,
+    /// Banner domain of the tenant owning the address, if it set one.
+    pub banner_domain: Option<String>,
+}
+
+impl Recipient {
+    /// Every address the mailbox was named as, in `RCPT TO` order.
+    pub fn envelope_addrs(&self) -> Vec<String> {
+        std::iter::once(&self.addr)
+            .chain(&self.other_addrs)
+            .cloned()
+            .collect()
+    }
+}
+
+/// A recipient at a `webhook` domain; nothing is stored for it.
+#[derive(Clone)]
+pub(crate) struct Forward {
+    pub addr: String,
+    pub webhook_url: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    /// Reading commands.
+    Command,
+    /// Between `354` and the terminating `.`; lines are message content.
+    Data,
+}
+
+/// Everything between `MAIL FROM` and the end of `DATA` (or `RSET`).
+pub(crate) struct Transaction {
+    pub mail_from: ReversePath,
+    /// `MAIL FROM` carried `SMTPUTF8`, so recipients may be non-ASCII.
+    pub smtputf8: bool,
+    pub recipients: Vec<Recipient>,
+    pub forwards: Vec<Forward>,
+    /// Unknown recipients answered with `250` because rejection is deferred.
+    pub deferred_unknown: usize,
+    /// Set when the client is over its message limit but the sender is one
+    /// these addresses expect; no other recipient is accepted.
+    pub expected_by: Option<Vec<uuid::Uuid>>,
+    /// When the first stored recipient was accepted.
+    pub accepted_at: Option<DateTime<Utc>>,
+    /// The message as received, dot-unstuffed, with CRLF line endings.
+    pub data: BytesMut,
+}
+
+impl Transaction {
+    pub fn new(mail_from: ReversePath) -> Self {
+        Self {
+            mail_from,
+            smtputf8: false,
+            recipients: Vec::new(),
+            forwards: Vec::new(),
+            deferred_unknown: 0,
+            expected_by: None,
+            accepted_at: None,
+            data: BytesMut::new(),
+        }
+    }
+
+    /// Every `RCPT TO` answered with `250`, including deferred unknowns.
+    pub fn accepted_recipients(&self) -> usize {
+        self.recipients.len() + self.forwards.len() + self.deferred_unknown
+    }
+
+    /// Whether the address may receive this message; see `expected_by`.
+    pub fn admits(&self, id: uuid::Uuid) -> bool {
+        match &self.expected_by {
+            Some(ids) => ids.contains(&id),
+            None => true,
+        }
+    }
+
+    /// The sender address; `None` for the null sender.
+    pub fn sender(&self) -> Option<String> {
+        self.mail_from.mailbox().map(ToString::to_string)
+    }
+
+    pub fn is_bounce(&self) -> bool {
+        self.mail_from == ReversePath::Null
+    }
+}
+
+pub(crate) struct Session {
+    pub peer: IpAddr,
+    /// Where `peer` is, when a GeoIP database is set.
+    pub country: Option<String>,
+    /// Argument of the last `EHLO`/`HELO`.
+    pub ehlo_name: Option<String>,
+    /// Greeted with `EHLO`, so ESMTP parameters may be used.
+    pub esmtp: bool,
+    /// Always false until STARTTLS is supported; TLS is terminated in front
+    /// of us if at all.
+    pub tls: bool,
+    pub phase: Phase,
+    pub transaction: Option<Transaction>,
+    /// Messages answered `250` on this connection; refused and deferred
+    /// ones do not count.
+    pub messages_accepted: usize,
+    /// Command lines read on this connection.
+    pub commands: usize,
+    /// Username of a successful `AUTH`; kept across `RSET` and `EHLO`.
+    pub authenticated: Option<String>,
+    pub auth_failures: u32,
+    /// Put on every message stored from this connection.
+    pub tags: Vec<String>,
+}
+
+impl Session {
+    pub fn new(peer: IpAddr, country: Option<String>) -> Self {
+        Self {
+            peer,
+            country,
+            ehlo_name: None,
+            esmtp: false,
+            tls: false,
+            phase: Phase::Command,
+            transaction: None,
+            messages_accepted: 0,
+            commands: 0,
+            authenticated: None,
+            auth_failures: 0,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Drops the transaction in progress, as `RSET` does.
+    pub fn reset(&mut self) {
+        self.phase = Phase::Command;
+        self.transaction = None;
+    }
+}
@@ -0,0 +1,445 @@
+//! Sender Policy Framework (RFC 7208): whether the connecting IP may send
+//! mail for the `MAIL FROM` domain. The result is only recorded with the
+//! message; nothing is refused because of it.
+//!
+//! `ptr` is counted against the lookup limit but never matches, as the RFC
+//! allows, and `exp=` is ignored since no explanation is ever shown.
+
+use db::SpfResult;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::dns::{Dns, DnsError};
+
+/// Mechanisms and modifiers that query DNS, per check.
+const MAX_LOOKUPS: usize = 10;
+/// Hosts looked at for one `mx` mechanism.
+const MAX_MX_HOSTS: usize = 10;
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Checks `ip` against the policy of the `mail_from` domain. A null sender
+/// is checked as `postmaster` at the `helo` name, as RFC 7208 asks.
+pub async fn check<D: Dns>(dns: &D, ip: IpAddr, mail_from: Option<&str>, helo: &str) -> SpfResult {
+    let sender = match mail_from {
+        Some(addr) if addr.contains('@') => addr.to_owned(),
+        _ => format!("postmaster@{helo}"),
+    };
+    let domain = sender
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut check = Check {
+        dns,
+        ip: ip.to_canonical(),
+        sender: &sender,
+        helo,
+        lookups: 0,
+    };
+    tokio::time::timeout(TIMEOUT, check.check_host(domain))
+        .await
+        .unwrap_or(SpfResult::TempError)
+}
+
+struct Check<'a, D> {
+    dns: &'a D,
+    ip: IpAddr,
+    sender: &'a str,
+    helo: &'a str,
+    lookups: usize,
+}
+
+impl<D: Dns> Check<'_, D> {
+    /// Boxed because `include` and `redirect=` recurse.
+    fn check_host(
+        &mut self,
+        domain: String,
+    ) -> Pin<Box<dyn Future<Output = SpfResult> + Send + '_>> {
+        Box::pin(async move {
+            if !is_domain(&domain) {
+                return SpfResult::None;
+            }
+            let Ok(txt) = self.dns.txt(&domain).await else {
+                return SpfResult::TempError;
+            };
+            let mut records = txt.iter().filter(|record| is_spf(record));
+            let Some(record) = records.next() else {
+                return SpfResult::None;
+            };
+            if records.next().is_some() {
+                return SpfResult::PermError;
+            }
+            let Some(record) = parse_record(record) else {
+                return SpfResult::PermError;
+            };
+            for directive in &record.directives {
+                match self.matches(&directive.mechanism, &domain).await {
+                    Ok(true) => return directive.qualifier,
+                    Ok(false) => {}
+                    Err(result) => return result,
+                }
+            }
+            let Some(target) = record.redirect else {
+                return SpfResult::Neutral;
+            };
+            let target = match self
+                .count_lookup()
+                .and_then(|()| self.expand(&target, &domain))
+            {
+                Ok(target) => target,
+                Err(result) => return result,
+            };
+            match self.check_host(target).await {
+                SpfResult::None => SpfResult::PermError,
+                result => result,
+            }
+        })
+    }
+
+    async fn matches(&mut self, mechanism: &Mechanism, domain: &str) -> Result<bool, SpfResult> {
+        let ipv6 = self.ip.is_ipv6();
+        match mechanism {
+            Mechanism::All => Ok(true),
+            Mechanism::Ip(net, prefix) => Ok(in_network(self.ip, *net, *prefix)),
+            Mechanism::Ptr => self.count_lookup().map(|()| false),
+            Mechanism::Include(spec) => {
+                self.count_lookup()?;
+                let target = self.expand(spec, domain)?;
+                match self.check_host(target).await {
+                    SpfResult::Pass => Ok(true),
+                    SpfResult::Fail | SpfResult::SoftFail | SpfResult::Neutral => Ok(false),
+                    SpfResult::TempError => Err(SpfResult::TempError),
+                    SpfResult::PermError | SpfResult::None => Err(SpfResult::PermError),
+                }
+            }
+            Mechanism::A { spec, v4, v6 } => {
+                self.count_lookup()?;
+                let target = self.target(spec.as_deref(), domain)?;
+                let addrs = self.dns.addrs(&target, ipv6).await.map_err(temp_error)?;
+                Ok(addrs.iter().any(|&a| self.in_prefix(a, *v4, *v6)))
+            }
+            Mechanism::Mx { spec, v4, v6 } => {
+                self.count_lookup()?;
+                let target = self.target(spec.as_deref(), domain)?;
+                let hosts = self.dns.mx(&target).await.map_err(temp_error)?;
+                if hosts.len() > MAX_MX_HOSTS {
+                    return Err(SpfResult::PermError);
+                }
+                for host in hosts {
+                    let addrs = self.dns.addrs(&host, ipv6).await.map_err(temp_error)?;
+                    if addrs.iter().any(|&a| self.in_prefix(a, *v4, *v6)) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Mechanism::Exists(spec) => {
+                self.count_lookup()?;
+                let target = self.expand(spec, domain)?;
+                let addrs = self.dns.addrs(&target, false).await.map_err(temp_error)?;
+                Ok(!addrs.is_empty())
+            }
+        }
+    }
+
+    fn count_lookup(&mut self) -> Result<(), SpfResult> {
+        self.lookups += 1;
+        if self.lookups > MAX_LOOKUPS {
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+
+    fn in_prefix(&self, addr: IpAddr, v4: u8, v6: u8) -> bool {
+        let prefix = if addr.is_ipv4() { v4 } else { v6 };
+        in_network(self.ip, addr, prefix)
+    }
+
+    /// The domain an `a` or `mx` mechanism looks up: its own, else the
+    /// one being checked.
+    fn target(&self, spec: Option<&str>, domain: &str) -> Result<String, SpfResult> {
+        match spec {
+            Some(spec) => self.expand(spec, domain),
+            None => Ok(domain.to_owned()),
+        }
+    }
+
+    /// Expands the macros (`%{d}`, `%{ir}`, ...) in a domain-spec.
+    fn expand(&self, spec: &str, domain: &str) -> Result<String, SpfResult> {
+        let mut out = String::new();
+        let mut rest = spec;
+        while let Some(i) = rest.find('%') {
+            out.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            rest = match after.chars().next() {
+                Some('%') => {
+                    out.push('%');
+                    &after[1..]
+                }
+                Some('_') => {
+                    out.push(' ');
+                    &after[1..]
+                }
+                Some('-') => {
+                    out.push_str("%20");
+                    &after[1..]
+                }
+                Some('{') => {
+                    let close = after.find('}').ok_or(SpfResult::PermError)?;
+                    let value = self
+                        .macro_value(&after[1..close], domain)
+                        .ok_or(SpfResult::PermError)?;
+                    out.push_str(&value);
+                    &after[close + 1..]
+                }
+                _ => return Err(SpfResult::PermError),
+            };
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    /// One `%{...}`: a letter, optionally the number of parts to keep, `r`
+    /// to reverse them, and the delimiters to split on.
+    fn macro_value(&self, body: &str, domain: &str) -> Option<String> {
+        let mut chars = body.chars();
+        let (local, sender_domain) = self.sender.rsplit_once('@')?;
+        let value = match chars.next()?.to_ascii_lowercase() {
+            's' => self.sender.to_owned(),
+            'l' => local.to_owned(),
+            'o' => sender_domain.to_owned(),
+            'd' => domain.to_owned(),
+            'i' => dotted(self.ip),
+            'p' => "unknown".to_owned(),
+            'v' if self.ip.is_ipv4() => "in-addr".to_owned(),
+            'v' => "ip6".to_owned(),
+            'h' => self.helo.to_owned(),
+            _ => return None,
+        };
+        let rest = chars.as_str();
+        let (digits, rest) = rest.split_at(
+            rest.find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len()),
+        );
+        let (reverse, delimiters) = match rest.strip_prefix(['r', 'R']) {
+            Some(delimiters) => (true, delimiters),
+            None => (false, rest),
+        };
+        if !delimiters.chars().all(|c| ".-+,/_=".contains(c)) {
+            return None;
+        }
+        let delimiters = if delimiters.is_empty() {
+            "."
+        } else {
+            delimiters
+        };
+        let mut parts: Vec<&str> = value.split(|c| delimiters.contains(c)).collect();
+        if reverse {
+            parts.reverse();
+        }
+        if !digits.is_empty() {
+            let keep: usize = digits.parse().ok().filter(|&n| n > 0)?;
+            parts = parts.split_off(parts.len().saturating_sub(keep));
+        }
+        Some(parts.join("."))
+    }
+}
+
+fn temp_error(_: DnsError) -> SpfResult {
+    SpfResult::TempError
+}
+
+enum Mechanism {
+    All,
+    Include(String),
+    /// `spec` is the domain to look up if not the one being checked;
+    /// `v4`/`v6` are the prefix lengths the addresses found are widened to.
+    A {
+        spec: Option<String>,
+        v4: u8,
+        v6: u8,
+    },
+    Mx {
+        spec: Option<String>,
+        v4: u8,
+        v6: u8,
+    },
+    Ptr,
+    Ip(IpAddr, u8),
+    Exists(String),
+}
+
+struct Directive {
+    /// What a match means.
+    qualifier: SpfResult,
+    mechanism: Mechanism,
+}
+
+struct Record {
+    directives: Vec<Directive>,
+    redirect: Option<String>,
+}
+
+fn is_spf(record: &str) -> bool {
+    let lower = record.to_ascii_lowercase();
+    lower == "v=spf1" || lower.starts_with("v=spf1 ")
+}
+
+/// `None` for a record with a syntax error, which is a permerror.
+fn parse_record(record: &str) -> Option<Record> {
+    let mut directives = Vec::new();
+    let mut redirect = None;
+    for term in record["v=spf1".len()..].split_ascii_whitespace() {
+        if let Some((name, value)) = modifier(term) {
+            if name.eq_ignore_ascii_case("redirect") && redirect.replace(value.to_owned()).is_some()
+            {
+                return None;
+            }
+            continue;
+        }
+        directives.push(parse_directive(term)?);
+    }
+    Some(Record {
+        directives,
+        redirect,
+    })
+}
+
+/// `name=value`; names other than `redirect` are ignored.
+fn modifier(term: &str) -> Option<(&str, &str)> {
+    let (name, value) = term.split_once('=')?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    valid.then_some((name, value))
+}
+
+fn parse_directive(term: &str) -> Option<Directive> {
+    let (qualifier, term) = match term.as_bytes().first()? {
+        b'+' => (SpfResult::Pass, &term[1..]),
+        b'-' => (SpfResult::Fail, &term[1..]),
+        b'~' => (SpfResult::SoftFail, &term[1..]),
+        b'?' => (SpfResult::Neutral, &term[1..]),
+        _ => (SpfResult::Pass, term),
+    };
+    let (name, arg) = term.split_at(term.find([':', '/']).unwrap_or(term.len()));
+    let mechanism = match name.to_ascii_lowercase().as_str() {
+        "all" if arg.is_empty() => Mechanism::All,
+        "include" => Mechanism::Include(domain_spec(arg)?),
+        "exists" => Mechanism::Exists(domain_spec(arg)?),
+        "ptr" if arg.is_empty() || domain_spec(arg).is_some() => Mechanism::Ptr,
+        "a" => {
+            let (spec, v4, v6) = dual_cidr(arg)?;
+            Mechanism::A { spec, v4, v6 }
+        }
+        "mx" => {
+            let (spec, v4, v6) = dual_cidr(arg)?;
+            Mechanism::Mx { spec, v4, v6 }
+        }
+        "ip4" => {
+            let (addr, len) = split_prefix(arg.strip_prefix(':')?);
+            let addr: Ipv4Addr = addr.parse().ok()?;
+            Mechanism::Ip(addr.into(), prefix(len, 32)?)
+        }
+        "ip6" => {
+            let (addr, len) = split_prefix(arg.strip_prefix(':')?);
+            let addr: Ipv6Addr = addr.parse().ok()?;
+            Mechanism::Ip(addr.into(), prefix(len, 128)?)
+        }
+        _ => return None,
+    };
+    Some(Directive {
+        qualifier,
+        mechanism,
+    })
+}
+
+fn domain_spec(arg: &str) -> Option<String> {
+    arg.strip_prefix(':')
+        .filter(|spec| !spec.is_empty())
+        .map(str::to_owned)
+}
+
+/// `addr/len`, with `len` `None` when absent.
+fn split_prefix(arg: &str) -> (&str, Option<&str>) {
+    match arg.split_once('/') {
+        Some((addr, len)) => (addr, Some(len)),
+        None => (arg, None),
+    }
+}
+
+fn prefix(len: Option<&str>, max: u8) -> Option<u8> {
+    match len {
+        None => Some(max),
+        Some(len) if len.bytes().all(|b| b.is_ascii_digit()) => {
+            len.parse().ok().filter(|&n| n <= max)
+        }
+        Some(_) => None,
+    }
+}
+
+/// The argument of `a` and `mx`: `[:domain][/v4-len][//v6-len]`.
+fn dual_cidr(arg: &str) -> Option<(Option<String>, u8, u8)> {
+    let (spec, cidr) = match arg.strip_prefix(':') {
+        Some(rest) => {
+            let (spec, cidr) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            if spec.is_empty() {
+                return None;
+            }
+            (Some(spec.to_owned()), cidr)
+        }
+        None => (None, arg),
+    };
+    let (v4, v6) = match cidr.strip_prefix('/') {
+        None if cidr.is_empty() => (32, 128),
+        None => return None,
+        Some(cidr) => match cidr.strip_prefix('/') {
+            Some(v6) => (32, prefix(Some(v6), 128)?),
+            None => match cidr.split_once("//") {
+                Some((v4, v6)) => (prefix(Some(v4), 32)?, prefix(Some(v6), 128)?),
+                None => (prefix(Some(cidr), 32)?, 128),
+            },
+        },
+    };
+    Some((spec, v4, v6))
+}
+
+fn in_network(ip: IpAddr, net: IpAddr, prefix: u8) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+/// `%{i}`: dotted quad, or an IPv6 address as 32 dot-separated nibbles.
+fn dotted(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .flat_map(|b| [b >> 4, b & 0xf])
+            .map(|nibble| format!("{nibble:x}"))
+            .collect::<Vec<_>>()
+            .join("."),
+    }
+}
+
+/// A name SPF can be checked for: dotted labels, no address literal.
+fn is_domain(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    name.contains('.')
+        && name.len() <= 253
+        && name
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63 && !label.contains(['[', ']', ' ']))
+}
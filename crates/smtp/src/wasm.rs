@@ -0,0 +1,245 @@
+//! Pipeline stages written as WebAssembly modules, for filtering without
+//! forking the server. A module is run with no imports at all, so it can
+//! look at what it is given and answer, but has no clock, randomness,
+//! files or network; each call gets a fresh instance, a fuel budget and a
+//! cap on its linear memory.
+//!
+//! A plugin exports `memory` and `alloc(len: i32) -> i32`, which returns
+//! where the host may write `len` bytes of input, and any of these hooks,
+//! each taking `(ptr: i32, len: i32)` pointing at a JSON document:
+//!
+//! - `on_rcpt`, with `{"peer", "mail_from", "rcpt"}`: nonzero refuses the
+//!   recipient.
+//! - `on_message`, with `{"peer", "mail_from", "recipients", "subject",
+//!   "headers", "tags", "spf", "dmarc", "size"}`: `0` continues, `1`
+//!   discards and `2` rejects the message.
+//! - `on_api_read`, with `{"address", "email_id", "from", "subject",
+//!   "tags"}`, before the HTTP API serves one message (`address` is `null`
+//!   for share links): nonzero hides it, answering 404.
+//!
+//! A plugin that traps, runs out of fuel or answers an unknown code is
+//! logged and ignored, so a broken filter does not lose mail.
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::Path;
+use std::pin::Pin;
+
+use serde::Serialize;
+use uuid::Uuid;
+use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::pipeline::{IncomingMessage, IngestStage, Verdict};
+
+/// Fuel a call may burn, roughly one unit per instruction, unless
+/// [`WasmStage::with_fuel`] says otherwise.
+pub const DEFAULT_FUEL: u64 = 10_000_000;
+
+/// Bytes of linear memory an instance may hold, unless
+/// [`WasmStage::with_max_memory`] says otherwise. A module declaring more
+/// fails to instantiate; `memory.grow` past it returns -1.
+pub const DEFAULT_MAX_MEMORY: usize = 16 << 20;
+
+/// One plugin module, as an [`IngestStage`].
+#[derive(Clone)]
+pub struct WasmStage {
+    name: String,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    max_memory: usize,
+}
+
+#[derive(Serialize)]
+struct RcptInput<'a> {
+    peer: IpAddr,
+    mail_from: Option<&'a str>,
+    rcpt: &'a str,
+}
+
+#[derive(Serialize)]
+struct MessageInput<'a> {
+    peer: IpAddr,
+    mail_from: Option<&'a str>,
+    recipients: &'a [String],
+    subject: Option<&'a str>,
+    headers: &'a [(String, String)],
+    tags: &'a [String],
+    spf: Option<&'static str>,
+    dmarc: Option<&'static str>,
+    size: usize,
+}
+
+/// What `on_api_read` is given about the message about to be served.
+#[derive(Serialize)]
+pub struct ApiReadInput<'a> {
+    /// The mailbox it is read through; `None` for share links.
+    pub address: Option<&'a str>,
+    pub email_id: Uuid,
+    pub from: Option<&'a str>,
+    pub subject: Option<&'a str>,
+    pub tags: &'a [String],
+}
+
+impl WasmStage {
+    /// Compiles the module at `path`, named after its file stem. Fails if
+    /// it does not compile, imports anything, or lacks `memory` or `alloc`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "wasm".into());
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        let module =
+            Module::from_file(&engine, path).map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Some(import) = module.imports().next() {
+            return Err(format!(
+                "{}: imports {}::{}; plugins may not import anything",
+                path.display(),
+                import.module(),
+                import.name()
+            ));
+        }
+        for export in ["memory", "alloc"] {
+            if module.get_export(export).is_none() {
+                return Err(format!("{}: does not export {export}", path.display()));
+            }
+        }
+        Ok(Self {
+            name,
+            engine,
+            module,
+            fuel: DEFAULT_FUEL,
+            max_memory: DEFAULT_MAX_MEMORY,
+        })
+    }
+
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    pub fn with_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = bytes;
+        self
+    }
+
+    /// Whether `on_api_read` lets the HTTP API serve a message; plugins
+    /// without the hook, or failing, allow it.
+    pub async fn allows_api_read(&self, input: &ApiReadInput<'_>) -> bool {
+        let Ok(input) = serde_json::to_vec(input) else {
+            return true;
+        };
+        !matches!(self.call("on_api_read", input).await, Some(code) if code != 0)
+    }
+
+    /// Runs `hook` on a blocking thread; `None` when the module does not
+    /// export it or the call failed.
+    async fn call(&self, hook: &'static str, input: Vec<u8>) -> Option<i32> {
+        self.module.get_export(hook)?;
+        let (engine, module) = (self.engine.clone(), self.module.clone());
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .instances(1)
+            .build();
+        let fuel = self.fuel;
+        let task =
+            tokio::task::spawn_blocking(move || run(&engine, &module, limits, fuel, hook, &input));
+        match task.await {
+            Ok(Ok(code)) => Some(code),
+            Ok(Err(e)) => {
+                tracing::warn!(plugin = %self.name, hook, error = %e, "wasm plugin failed");
+                metrics::counter!("smtp_wasm_plugin_errors_total", "plugin" => self.name.clone())
+                    .increment(1);
+                None
+            }
+            Err(e) => {
+                tracing::warn!(plugin = %self.name, hook, error = %e, "wasm plugin task failed");
+                None
+            }
+        }
+    }
+}
+
+/// Instantiates `module` afresh, copies `input` into it and calls `hook`.
+fn run(
+    engine: &Engine,
+    module: &Module,
+    limits: StoreLimits,
+    fuel: u64,
+    hook: &str,
+    input: &[u8],
+) -> wasmtime::Result<i32> {
+    let mut store = Store::new(engine, limits);
+    store.limiter(|limits| limits);
+    store.set_fuel(fuel)?;
+    let instance = Instance::new(&mut store, module, &[])?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| wasmtime::Error::msg("no memory export"))?;
+    let len = i32::try_from(input.len())?;
+    let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+    let ptr = alloc.call(&mut store, len)?;
+    memory.write(&mut store, usize::try_from(ptr)?, input)?;
+    let hook = instance.get_typed_func::<(i32, i32), i32>(&mut store, hook)?;
+    hook.call(&mut store, (ptr, len))
+}
+
+impl IngestStage for WasmStage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process<'a>(
+        &'a self,
+        message: &'a IncomingMessage<'a>,
+    ) -> Pin<Box<dyn Future<Output = Verdict> + Send + 'a>> {
+        Box::pin(async move {
+            let input = MessageInput {
+                peer: message.peer,
+                mail_from: message.mail_from,
+                recipients: message.recipients,
+                subject: message.subject,
+                headers: message.headers,
+                tags: message.tags,
+                spf: message.spf.map(|r| r.as_str()),
+                dmarc: message.dmarc.map(|r| r.as_str()),
+                size: message.raw.len(),
+            };
+            let Ok(input) = serde_json::to_vec(&input) else {
+                return Verdict::Continue;
+            };
+            match self.call("on_message", input).await {
+                Some(1) => Verdict::Discard,
+                Some(2) => Verdict::Reject(format!("550 5.7.1 rejected by {}", self.name)),
+                Some(0) | None => Verdict::Continue,
+                Some(code) => {
+                    tracing::warn!(plugin = %self.name, code, "unknown wasm plugin verdict");
+                    Verdict::Continue
+                }
+            }
+        })
+    }
+
+    fn accepts_recipient<'a>(
+        &'a self,
+        peer: IpAddr,
+        mail_from: Option<&'a str>,
+        rcpt: &'a str,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(async move {
+            let input = RcptInput {
+                peer,
+                mail_from,
+                rcpt,
+            };
+            let Ok(input) = serde_json::to_vec(&input) else {
+                return true;
+            };
+            !matches!(self.call("on_rcpt", input).await, Some(code) if code != 0)
+        })
+    }
+}
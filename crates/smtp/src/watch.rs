@@ -0,0 +1,138 @@
+//! Per-mailbox watch rules: once a message is stored, each rule of its
+//! recipients is matched against the subject, then the plain-text body, and
+//! the first match is POSTed as JSON to the rule's webhook. Delivery is best
+//! effort; a failing webhook is logged and never delays or fails the message.
+
+use db::{list_watch_rules_for, WatchKind};
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Longest pattern a rule may have.
+pub const MAX_PATTERN_LEN: usize = 500;
+/// Bound on the compiled program, so one rule cannot eat the server's memory.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// The matcher for a rule. Substrings match case-insensitively; regexes as
+/// written (use `(?i)` for case-insensitive ones).
+pub fn compile(kind: WatchKind, pattern: &str) -> Result<Regex, regex::Error> {
+    let source = match kind {
+        WatchKind::Substring => regex::escape(pattern),
+        WatchKind::Regex => pattern.to_owned(),
+    };
+    RegexBuilder::new(&source)
+        .case_insensitive(kind == WatchKind::Substring)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+/// A stored copy of the message, for one watched recipient.
+pub(crate) struct Delivery<'a> {
+    pub temporary_email_id: Uuid,
+    pub addr: &'a str,
+    pub email_id: Uuid,
+}
+
+/// What is POSTed to a rule's webhook.
+#[derive(Debug, Serialize)]
+struct WatchEvent {
+    event: &'static str,
+    rule_id: Uuid,
+    address: String,
+    email_id: Uuid,
+    from: Option<String>,
+    subject: Option<String>,
+    /// `subject` or `body_text`.
+    field: &'static str,
+    /// The whole match.
+    #[serde(rename = "match")]
+    matched: String,
+    /// Numbered capture groups, from 1; `null` for groups that did not take
+    /// part in the match.
+    groups: Vec<Option<String>>,
+    named: BTreeMap<String, Option<String>>,
+}
+
+pub(crate) async fn fire(
+    pool: &PgPool,
+    http: &reqwest::Client,
+    deliveries: &[Delivery<'_>],
+    from_addr: Option<&str>,
+    subject: Option<&str>,
+    body_text: Option<&str>,
+) {
+    if deliveries.is_empty() {
+        return;
+    }
+    let ids: Vec<Uuid> = deliveries.iter().map(|d| d.temporary_email_id).collect();
+    let rules = match list_watch_rules_for(pool, &ids).await {
+        Ok(rules) => rules,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to load watch rules");
+            return;
+        }
+    };
+
+    for rule in rules {
+        let Some(delivery) = deliveries
+            .iter()
+            .find(|d| d.temporary_email_id == rule.temporary_email_id)
+        else {
+            continue;
+        };
+        let regex = match compile(rule.kind, &rule.pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                tracing::warn!(rule = %rule.id, error = %e, "skipping invalid watch rule");
+                continue;
+            }
+        };
+        let found = [("subject", subject), ("body_text", body_text)]
+            .into_iter()
+            .find_map(|(field, text)| Some((field, regex.captures(text?)?)));
+        let Some((field, caps)) = found else {
+            continue;
+        };
+
+        let owned = |m: Option<regex::Match<'_>>| m.map(|m| m.as_str().to_owned());
+        let event = WatchEvent {
+            event: "watch.matched",
+            rule_id: rule.id,
+            address: delivery.addr.to_owned(),
+            email_id: delivery.email_id,
+            from: from_addr.map(str::to_owned),
+            subject: subject.map(str::to_owned),
+            field,
+            matched: caps[0].to_owned(),
+            groups: caps.iter().skip(1).map(owned).collect(),
+            named: regex
+                .capture_names()
+                .flatten()
+                .map(|name| (name.to_owned(), owned(caps.name(name))))
+                .collect(),
+        };
+        tracing::debug!(rule = %rule.id, rcpt = %delivery.addr, field, "watch rule matched");
+        let http = http.clone();
+        tokio::spawn(async move {
+            if let Err(e) = post(&http, &rule.webhook_url, &event).await {
+                tracing::warn!(rule = %event.rule_id, error = %e, "watch webhook failed");
+            }
+        });
+    }
+}
+
+async fn post(http: &reqwest::Client, url: &str, event: &WatchEvent) -> Result<(), String> {
+    let res = http
+        .post(url)
+        .json(event)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook answered {}", res.status()))
+    }
+}
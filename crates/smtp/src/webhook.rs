@@ -0,0 +1,48 @@
+//! Delivery for `webhook` domains: the message is POSTed as-is instead of
+//! being stored.
+
+use reqwest::header::{HeaderValue, CONTENT_TYPE};
+use std::net::IpAddr;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .unwrap_or_default()
+}
+
+/// POSTs `raw` as `message/rfc822` to `url`, with the envelope in
+/// `X-Mail-From` (empty for the null sender), one `X-Rcpt-To` per recipient
+/// and `X-Peer-Ip`. Anything but a 2xx answer is an error.
+pub(crate) async fn forward(
+    client: &reqwest::Client,
+    url: &str,
+    mail_from: Option<&str>,
+    rcpt_to: &[&str],
+    peer: IpAddr,
+    raw: &[u8],
+) -> Result<(), String> {
+    // SMTPUTF8 addresses are sent as raw UTF-8, which `from_str` refuses.
+    let value = |s: &str| HeaderValue::from_bytes(s.as_bytes()).map_err(|e| e.to_string());
+    let mut request = client
+        .post(url)
+        .header(CONTENT_TYPE, "message/rfc822")
+        .header("X-Mail-From", value(mail_from.unwrap_or_default())?)
+        .header("X-Peer-Ip", peer.to_string());
+    for rcpt in rcpt_to {
+        request = request.header("X-Rcpt-To", value(rcpt)?);
+    }
+    let res = request
+        .body(raw.to_vec())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("webhook answered {}", res.status()))
+    }
+}
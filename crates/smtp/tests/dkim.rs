@@ -0,0 +1,153 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use db::DkimResult;
+use ed25519_dalek::{Signer, SigningKey};
+use rsa::pkcs8::EncodePublicKey;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+use smtp::dkim::verify;
+use smtp::dns::{Dns, DnsError};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Key records by name; `None` fails like a DNS outage.
+#[derive(Default)]
+struct KeyDns(HashMap<String, Option<String>>);
+
+impl KeyDns {
+    fn with_key(mut self, name: &str, record: Option<String>) -> Self {
+        self.0.insert(name.to_owned(), record);
+        self
+    }
+}
+
+impl Dns for KeyDns {
+    async fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        match self.0.get(name) {
+            Some(Some(record)) => Ok(vec![record.clone()]),
+            Some(None) => Err(DnsError),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn addrs(&self, _name: &str, _ipv6: bool) -> Result<Vec<IpAddr>, DnsError> {
+        Ok(Vec::new())
+    }
+
+    async fn mx(&self, _name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(Vec::new())
+    }
+}
+
+const HEADERS: &str = "From: Alice <alice@example.com>\r\nSubject: Hi there\r\n";
+const BODY: &str = "Hello world\r\n";
+
+/// Signs `HEADERS` and `BODY` with relaxed/relaxed canonicalization, which
+/// for these single-line fields is lowercasing the names and dropping the
+/// space after the colon.
+fn signed_message(algorithm: &str, sign: impl Fn(&[u8]) -> Vec<u8>) -> String {
+    let body_hash = BASE64.encode(Sha256::digest(BODY));
+    let tags = format!(
+        "v=1; a={algorithm}; c=relaxed/relaxed; d=example.com; s=sel; h=from:subject; \
+         bh={body_hash}; b="
+    );
+    let signed =
+        format!("from:Alice <alice@example.com>\r\nsubject:Hi there\r\ndkim-signature:{tags}");
+    let signature = BASE64.encode(sign(&Sha256::digest(signed)));
+    format!("DKIM-Signature: {tags}{signature}\r\n{HEADERS}\r\n{BODY}")
+}
+
+fn ed25519_fixture() -> (String, KeyDns) {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let message = signed_message("ed25519-sha256", |digest| {
+        key.sign(digest).to_bytes().to_vec()
+    });
+    let record = format!(
+        "v=DKIM1; k=ed25519; p={}",
+        BASE64.encode(key.verifying_key().to_bytes())
+    );
+    let dns = KeyDns::default().with_key("sel._domainkey.example.com", Some(record));
+    (message, dns)
+}
+
+#[tokio::test]
+async fn verifies_ed25519_and_rsa_signatures() {
+    let (message, dns) = ed25519_fixture();
+    let verdicts = verify(&dns, message.as_bytes()).await;
+    assert_eq!(verdicts.len(), 1);
+    assert_eq!(verdicts[0].domain, "example.com");
+    assert_eq!(verdicts[0].selector, "sel");
+    assert_eq!(
+        verdicts[0].result,
+        DkimResult::Pass,
+        "{:?}",
+        verdicts[0].reason
+    );
+
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).expect("rsa key");
+    let message = signed_message("rsa-sha256", |digest| {
+        key.sign(Pkcs1v15Sign::new::<Sha256>(), digest)
+            .expect("rsa sign")
+    });
+    let der = RsaPublicKey::from(&key)
+        .to_public_key_der()
+        .expect("public key der");
+    let record = format!("v=DKIM1; k=rsa; p={}", BASE64.encode(der.as_bytes()));
+    let dns = KeyDns::default().with_key("sel._domainkey.example.com", Some(record));
+    let verdicts = verify(&dns, message.as_bytes()).await;
+    assert_eq!(
+        verdicts[0].result,
+        DkimResult::Pass,
+        "{:?}",
+        verdicts[0].reason
+    );
+}
+
+#[tokio::test]
+async fn tampering_fails_the_signature() {
+    let (message, dns) = ed25519_fixture();
+
+    let body_changed = message.replace("Hello world", "Hello w0rld");
+    let verdicts = verify(&dns, body_changed.as_bytes()).await;
+    assert_eq!(verdicts[0].result, DkimResult::Fail);
+    assert_eq!(verdicts[0].reason, Some("body hash mismatch"));
+
+    let subject_changed = message.replace("Subject: Hi there", "Subject: Hi  there!");
+    let verdicts = verify(&dns, subject_changed.as_bytes()).await;
+    assert_eq!(verdicts[0].result, DkimResult::Fail);
+    assert_eq!(verdicts[0].reason, Some("signature does not verify"));
+
+    // Relaxed canonicalization tolerates whitespace and case changes.
+    let rewrapped = message.replace("Subject: Hi there", "SUBJECT:   Hi\r\n there");
+    let verdicts = verify(&dns, rewrapped.as_bytes()).await;
+    assert_eq!(
+        verdicts[0].result,
+        DkimResult::Pass,
+        "{:?}",
+        verdicts[0].reason
+    );
+}
+
+#[tokio::test]
+async fn reports_missing_revoked_and_unreachable_keys() {
+    let (message, _) = ed25519_fixture();
+    let name = "sel._domainkey.example.com";
+
+    let missing = KeyDns::default();
+    let revoked = KeyDns::default().with_key(name, Some("v=DKIM1; k=ed25519; p=".into()));
+    let down = KeyDns::default().with_key(name, None);
+    for (dns, result, reason) in [
+        (missing, DkimResult::PermError, "no key published"),
+        (revoked, DkimResult::PermError, "key revoked"),
+        (down, DkimResult::TempError, "key lookup failed"),
+    ] {
+        let verdicts = verify(&dns, message.as_bytes()).await;
+        assert_eq!(verdicts[0].result, result);
+        assert_eq!(verdicts[0].reason, Some(reason));
+    }
+
+    let unsigned = format!("{HEADERS}\r\n{BODY}");
+    assert!(verify(&KeyDns::default(), unsigned.as_bytes())
+        .await
+        .is_empty());
+}
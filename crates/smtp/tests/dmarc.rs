@@ -0,0 +1,162 @@
+use db::{DkimResult, DmarcResult, SpfResult};
+use smtp::dkim::Verdict;
+use smtp::dmarc::evaluate;
+use smtp::dns::{Dns, DnsError};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// TXT records by name; `None` fails like a DNS outage.
+#[derive(Default)]
+struct PolicyDns(HashMap<&'static str, Option<Vec<&'static str>>>);
+
+impl PolicyDns {
+    fn with_txt(mut self, name: &'static str, records: Option<Vec<&'static str>>) -> Self {
+        self.0.insert(name, records);
+        self
+    }
+}
+
+impl Dns for PolicyDns {
+    async fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        match self.0.get(name) {
+            Some(Some(records)) => Ok(records.iter().map(|r| r.to_string()).collect()),
+            Some(None) => Err(DnsError),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn addrs(&self, _name: &str, _ipv6: bool) -> Result<Vec<IpAddr>, DnsError> {
+        Ok(Vec::new())
+    }
+
+    async fn mx(&self, _name: &str) -> Result<Vec<String>, DnsError> {
+        Ok(Vec::new())
+    }
+}
+
+fn message(from: &str) -> String {
+    format!("From: {from}\r\nSubject: hi\r\n\r\nbody\r\n")
+}
+
+fn signed(domain: &str, result: DkimResult) -> Verdict {
+    Verdict {
+        domain: domain.into(),
+        selector: "sel".into(),
+        result,
+        reason: None,
+    }
+}
+
+#[tokio::test]
+async fn passes_on_aligned_spf_or_dkim() {
+    let dns = PolicyDns::default()
+        .with_txt("_dmarc.example.com", Some(vec!["v=DMARC1; p=reject"]))
+        .with_txt(
+            "_dmarc.strict.example",
+            Some(vec!["v=DMARC1; p=none; aspf=s; adkim=s"]),
+        );
+    let msg = message("Alice <alice@news.example.com>");
+    let dmarc = |mail_from, spf, dkim: Vec<Verdict>| {
+        let msg = msg.clone();
+        let dns = &dns;
+        async move { evaluate(dns, msg.as_bytes(), mail_from, "mta.test", spf, &dkim).await }
+    };
+
+    // Relaxed alignment: the organizational domains match, and the
+    // policy is found at the organizational domain.
+    let spf_pass = Some(SpfResult::Pass);
+    assert_eq!(
+        dmarc(Some("bounce@mail.example.com"), spf_pass, vec![]).await,
+        DmarcResult::Pass
+    );
+    assert_eq!(
+        dmarc(None, None, vec![signed("example.com", DkimResult::Pass)]).await,
+        DmarcResult::Pass
+    );
+    // Passing, but for an unrelated domain.
+    assert_eq!(
+        dmarc(
+            Some("bounce@esp.test"),
+            spf_pass,
+            vec![signed("esp.test", DkimResult::Pass)]
+        )
+        .await,
+        DmarcResult::Fail
+    );
+    // Aligned, but not passing.
+    assert_eq!(
+        dmarc(
+            Some("alice@example.com"),
+            Some(SpfResult::SoftFail),
+            vec![signed("example.com", DkimResult::Fail)]
+        )
+        .await,
+        DmarcResult::Fail
+    );
+
+    let strict = message("bob@strict.example");
+    let result = evaluate(
+        &dns,
+        strict.as_bytes(),
+        Some("bounce@mail.strict.example"),
+        "mta.test",
+        spf_pass,
+        &[signed("strict.example", DkimResult::Pass)],
+    )
+    .await;
+    assert_eq!(result, DmarcResult::Pass);
+    let result = evaluate(
+        &dns,
+        strict.as_bytes(),
+        Some("bounce@mail.strict.example"),
+        "mta.test",
+        spf_pass,
+        &[signed("mail.strict.example", DkimResult::Pass)],
+    )
+    .await;
+    assert_eq!(result, DmarcResult::Fail);
+}
+
+#[tokio::test]
+async fn reports_missing_broken_and_unreachable_policies() {
+    let dns = PolicyDns::default()
+        .with_txt("_dmarc.example.com", Some(vec!["v=DMARC1; p=reject"]))
+        .with_txt("_dmarc.broken.example", Some(vec!["v=DMARC1; p=maybe"]))
+        .with_txt("_dmarc.down.example", None)
+        .with_txt(
+            "_dmarc.twice.example",
+            Some(vec!["v=DMARC1; p=none", "v=DMARC1; p=reject"]),
+        )
+        .with_txt("_dmarc.shop.co.uk", Some(vec!["v=DMARC1; p=none"]));
+    let spf = Some(SpfResult::Pass);
+
+    for (from, expected) in [
+        ("a@nopolicy.example", DmarcResult::None),
+        ("a@broken.example", DmarcResult::PermError),
+        ("a@down.example", DmarcResult::TempError),
+        ("a@twice.example", DmarcResult::None),
+        // co.uk is not an organizational domain of its own.
+        ("a@www.shop.co.uk", DmarcResult::Fail),
+    ] {
+        let msg = message(from);
+        let result = evaluate(
+            &dns,
+            msg.as_bytes(),
+            Some("x@other.test"),
+            "mta.test",
+            spf,
+            &[],
+        )
+        .await;
+        assert_eq!(result, expected, "{from}");
+    }
+
+    for header in [
+        "From: a@example.com\r\nFrom: b@example.com\r\n\r\nbody\r\n",
+        "From: a@example.com, b@example.com\r\n\r\nbody\r\n",
+        "Subject: no author\r\n\r\nbody\r\n",
+    ] {
+        let result = evaluate(&dns, header.as_bytes(), None, "mta.test", spf, &[]).await;
+        assert_eq!(result, DmarcResult::None, "{header:?}");
+    }
+}
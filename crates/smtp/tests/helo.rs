@@ -0,0 +1,39 @@
+use smtp::helo::{check, HeloVerdict};
+
+#[test]
+fn accepts_domains_and_address_literals() {
+    for name in [
+        "mail.example.com",
+        "MX1.Example.COM.",
+        "localhost",
+        "[192.0.2.1]",
+        "[IPv6:2001:db8::1]",
+    ] {
+        assert_eq!(
+            check(Some(name), "mx.fake.test"),
+            HeloVerdict::Valid,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn flags_malformed_and_forged_names() {
+    for name in [
+        None,
+        Some("my_host.example"),
+        Some("-bad.example"),
+        Some("a..example"),
+        Some("[192.0.2.300]"),
+        Some("[2001:db8::1]"),
+    ] {
+        let verdict = check(name, "mx.fake.test");
+        assert_eq!(verdict, HeloVerdict::Invalid, "{name:?}");
+        assert_eq!(verdict.tag(), Some("helo-invalid"));
+    }
+    for name in ["192.0.2.1", "2001:db8::1", "MX.Fake.Test", "mx.fake.test."] {
+        let verdict = check(Some(name), "mx.fake.test");
+        assert_eq!(verdict, HeloVerdict::Forged, "{name}");
+        assert_eq!(verdict.tag(), Some("helo-forged"));
+    }
+}
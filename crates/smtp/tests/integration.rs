@@ -2,7 +2,7 @@ use serial_test::serial;
 use testcontainers::core::{IntoContainerPort, WaitFor};
 use testcontainers::runners::AsyncRunner;
 use testcontainers::{GenericImage, ImageExt};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 
 async fn start_postgres() -> (testcontainers::ContainerAsync<GenericImage>, String) {
@@ -48,6 +48,19 @@ async fn read_line(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> St
     line
 }
 
+/// Reads a possibly multi-line reply (`250-...` continuations) in full.
+async fn read_reply(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>) -> Vec<String> {
+    let mut lines = Vec::new();
+    loop {
+        let line = read_line(reader).await;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line.trim_end().to_owned());
+        if last {
+            return lines;
+        }
+    }
+}
+
 async fn write_line(writer: &mut tokio::net::tcp::OwnedWriteHalf, s: &str) {
     writer
         .write_all(format!("{s}\r\n").as_bytes())
@@ -55,6 +68,35 @@ async fn write_line(writer: &mut tokio::net::tcp::OwnedWriteHalf, s: &str) {
         .expect("write line");
 }
 
+/// Accepts one HTTP request, answers `200` and returns its head and body.
+async fn receive_one_post(listener: TcpListener) -> (String, Vec<u8>) {
+    let (stream, _) = listener.accept().await.expect("accept webhook");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let mut head = String::new();
+    loop {
+        let line = read_line(&mut reader).await;
+        if line == "\r\n" {
+            break;
+        }
+        head.push_str(&line);
+    }
+    let len = head
+        .lines()
+        .find_map(|l| {
+            let (name, value) = l.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse::<usize>().expect("content-length"))
+        })
+        .expect("content-length header");
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await.expect("read body");
+    w.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+        .await
+        .expect("write response");
+    (head, body)
+}
+
 #[tokio::test]
 #[serial]
 async fn smtp_stores_mail_for_known_recipient() {
@@ -84,7 +126,7 @@ async fn smtp_stores_mail_for_known_recipient() {
     assert!(banner.starts_with("220"));
 
     write_line(&mut w, "EHLO test").await;
-    assert!(read_line(&mut reader).await.starts_with("250"));
+    assert!(read_reply(&mut reader).await[0].starts_with("250"));
 
     write_line(&mut w, "MAIL FROM:<sender@example.com>").await;
     assert!(read_line(&mut reader).await.starts_with("250"));
@@ -106,12 +148,17 @@ async fn smtp_stores_mail_for_known_recipient() {
 
     tokio::time::sleep(std::time::Duration::from_millis(250)).await;
 
-    let rows = db::list_received_emails(&pool, temp.id, None)
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
         .await
         .expect("list received");
     assert_eq!(rows.len(), 1);
     assert_eq!(rows[0].to_addr.as_deref(), Some(to_addr));
     assert_eq!(rows[0].subject.as_deref(), Some("hello from smtp"));
+    assert_eq!(rows[0].peer_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(rows[0].spf_result, None);
+    assert_eq!(rows[0].dmarc_result, None);
+    assert!(rows[0].tags.is_empty());
+    assert_eq!(rows[0].country, None);
     assert!(rows[0]
         .body_text
         .as_deref()
@@ -143,7 +190,7 @@ async fn smtp_rejects_unknown_recipient() {
 
     let _ = read_line(&mut reader).await;
     write_line(&mut w, "EHLO test").await;
-    let _ = read_line(&mut reader).await;
+    let _ = read_reply(&mut reader).await;
 
     write_line(&mut w, "MAIL FROM:<sender@example.com>").await;
     let _ = read_line(&mut reader).await;
@@ -163,3 +210,2329 @@ async fn smtp_rejects_unknown_recipient() {
 
     server.abort();
 }
+
+#[tokio::test]
+#[serial]
+async fn smtp_refuses_to_relay_for_foreign_domains() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    db::insert_temporary_email(&pool, "dana@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::upsert_mail_domain(&pool, "catch.test", db::DomainPolicy::CatchAll, None)
+        .await
+        .expect("catch_all rule");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        local_domains: vec!["SMTP.test".into()],
+        ..Default::default()
+    };
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "EHLO client.example").await;
+    let _ = read_reply(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<sender@example.com>").await;
+    let _ = read_line(&mut reader).await;
+
+    for rcpt in [
+        "victim@gmail.com",
+        "dana@smtp.test.evil.example",
+        "x@[192.0.2.1]",
+    ] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        let reply = read_line(&mut reader).await;
+        assert!(
+            reply.starts_with("550 5.7.1 Relay not permitted"),
+            "{rcpt}: {reply}"
+        );
+    }
+    // Configured, routed by a rule, and the postmaster.
+    for rcpt in ["dana@SMTP.test", "anyone@catch.test", "Postmaster"] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        let reply = read_line(&mut reader).await;
+        assert!(!reply.contains("Relay"), "{rcpt}: {reply}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_honeypot_delivery_feeds_sender_reputation() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    db::insert_honeypot_email(&pool, "trap@smtp.test")
+        .await
+        .expect("insert honeypot");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "EHLO test").await;
+    let _ = read_reply(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<bulk@Spammer.example>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "RCPT TO:<trap@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: cheap pills").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "buy now").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "QUIT").await;
+    let _ = read_line(&mut reader).await;
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+
+    let rows = db::list_sender_reputation(&pool, 10)
+        .await
+        .expect("list reputation");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].sender_domain, "spammer.example");
+    assert_eq!(rows[0].honeypot_hits, 1);
+    assert_eq!(rows[0].last_ip.as_deref(), Some("127.0.0.1"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_throttles_dictionary_harvesting() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        unknown_rcpt_threshold: 2,
+        ..Default::default()
+    };
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, pool, config).await.expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<harvester@example.com>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "RCPT TO:<admin@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550"));
+    write_line(&mut w, "RCPT TO:<info@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550"));
+    assert!(read_line(&mut reader).await.starts_with("421"));
+
+    let stream = TcpStream::connect(bound).await.expect("reconnect smtp");
+    let (r, _w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    assert!(read_line(&mut reader).await.starts_with("421"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_publishes_redacted_ingest_events() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    db::insert_temporary_email(&pool, "carol@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig::default();
+    let ingest_events = config.events.clone();
+    let mut events = ingest_events.subscribe();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, pool, config).await.expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<someone@Sender.example>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "RCPT TO:<nobody@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550"));
+    write_line(&mut w, "RCPT TO:<carol@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: tail").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hi").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let rejected = events.recv().await.expect("unknown recipient event");
+    assert!(matches!(rejected.disposition, smtp::Disposition::UnknownRecipient));
+    assert_eq!(rejected.recipient.as_deref(), Some("no***@smtp.test"));
+    assert_eq!(rejected.sender_domain.as_deref(), Some("sender.example"));
+    // No GeoIP database is configured.
+    assert_eq!(rejected.country, None);
+
+    let delivered = events.recv().await.expect("delivery event");
+    assert!(matches!(delivered.disposition, smtp::Disposition::Delivered));
+    assert_eq!(delivered.recipient.as_deref(), Some("ca***@smtp.test"));
+    assert!(delivered.size > 0);
+
+    let counts = ingest_events.by_country();
+    assert_eq!(counts.len(), 2);
+    assert!(counts.iter().all(|c| c.country.is_none() && c.count == 1));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_detects_mail_loops() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "loop@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        loop_marker: "smtp.test".into(),
+        max_hops: 3,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    let headers: [&[&str]; 2] = [
+        &["X-Loop: smtp.test", "Subject: came back"],
+        &["Received: a", "Received: b", "Received: c", "Received: d"],
+    ];
+    let mut replies = Vec::new();
+    for block in headers {
+        write_line(&mut w, "MAIL FROM:<bouncer@example.com>").await;
+        let _ = read_line(&mut reader).await;
+        write_line(&mut w, "RCPT TO:<loop@smtp.test>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "DATA").await;
+        let _ = read_line(&mut reader).await;
+        for h in block {
+            write_line(&mut w, h).await;
+        }
+        write_line(&mut w, "").await;
+        write_line(&mut w, "body").await;
+        write_line(&mut w, ".").await;
+        replies.push(read_line(&mut reader).await);
+    }
+    assert!(replies[0].starts_with("250"), "marker is dropped silently");
+    assert!(replies[1].starts_with("554"), "hop limit rejects");
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert!(rows.is_empty());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_uses_tenant_banner_domain() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "branded@smtp.test")
+        .await
+        .expect("insert temp address");
+    let key = db::insert_api_key(&pool, "acme", b"acme-key-hash")
+        .await
+        .expect("insert api key");
+    db::attribute_temporary_emails(&pool, &[temp.id], key.id)
+        .await
+        .expect("attribute");
+    db::upsert_tenant_settings(
+        &pool,
+        key.id,
+        &db::TenantSettingsUpdate {
+            smtp_banner_domain: Some("mx.acme.test".into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .expect("upsert tenant settings")
+    .expect("known api key");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        banner_domain: "smtp.test".into(),
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    assert_eq!(read_line(&mut reader).await.trim_end(), "220 smtp.test smtp ready");
+
+    write_line(&mut w, "EHLO sender.example").await;
+    assert_eq!(read_reply(&mut reader).await[0], "250-smtp.test");
+    write_line(&mut w, "MAIL FROM:<someone@sender.example>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "RCPT TO:<branded@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: hi").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert_eq!(read_line(&mut reader).await.trim_end(), "250 queued by mx.acme.test");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_stores_null_sender_mail_as_bounce() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "bounced@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    for (from, subject) in [("<>", "Undelivered Mail"), ("<a@sender.example>", "hi")] {
+        write_line(&mut w, &format!("MAIL FROM:{from}")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"), "{from}");
+        write_line(&mut w, "RCPT TO:<bounced@smtp.test>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "DATA").await;
+        let _ = read_line(&mut reader).await;
+        write_line(&mut w, &format!("Subject: {subject}")).await;
+        write_line(&mut w, "").await;
+        write_line(&mut w, "body").await;
+        write_line(&mut w, ".").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let bounces = db::list_received_emails(&pool, temp.id, None, Some(true), None)
+        .await
+        .expect("list bounces");
+    assert_eq!(bounces.len(), 1);
+    assert!(bounces[0].is_bounce);
+    assert_eq!(bounces[0].from_addr, None);
+    assert_eq!(bounces[0].subject.as_deref(), Some("Undelivered Mail"));
+    let regular = db::list_received_emails(&pool, temp.id, None, Some(false), None)
+        .await
+        .expect("list regular mail");
+    assert_eq!(regular.len(), 1);
+    assert_eq!(regular[0].from_addr.as_deref(), Some("a@sender.example"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_caps_recipients_and_messages_per_session() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    for local in ["one", "two", "three"] {
+        db::insert_temporary_email(&pool, &format!("{local}@smtp.test"))
+            .await
+            .expect("insert temp address");
+    }
+    db::insert_blocked_sender(&pool, None, "blocked.example")
+        .await
+        .expect("block sender");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        max_recipients: 2,
+        max_messages_per_session: 1,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    // A refused message does not count towards the cap.
+    write_line(&mut w, "MAIL FROM:<a@blocked.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<one@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: refused").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("550"));
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    let mut replies = Vec::new();
+    for local in ["one", "two", "three"] {
+        write_line(&mut w, &format!("RCPT TO:<{local}@smtp.test>")).await;
+        replies.push(read_line(&mut reader).await);
+    }
+    assert!(replies[0].starts_with("250"));
+    assert!(replies[1].starts_with("250"));
+    assert!(replies[2].starts_with("452"), "{}", replies[2]);
+
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: capped").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("421"));
+    assert_eq!(read_line(&mut reader).await, "", "server closes the session");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_refuses_mail_for_full_mailboxes() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    for local in ["full", "roomy"] {
+        db::insert_temporary_email(&pool, &format!("{local}@smtp.test"))
+            .await
+            .expect("insert temp address");
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        mailbox_quota: db::MailboxQuota {
+            max_messages: 1,
+            max_bytes: 0,
+        },
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<full@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: first").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<full@smtp.test>").await;
+    let full = read_line(&mut reader).await;
+    assert!(full.starts_with("552 5.2.2"), "{full}");
+    write_line(&mut w, "RCPT TO:<roomy@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_drops_mail_from_blocked_senders() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let picky = db::insert_temporary_email(&pool, "picky@smtp.test")
+        .await
+        .expect("insert temp address");
+    let open = db::insert_temporary_email(&pool, "open@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::insert_blocked_sender(&pool, Some(picky.id), "News@Shop.example")
+        .await
+        .expect("block for mailbox")
+        .expect("not yet blocked");
+    db::insert_blocked_sender(&pool, None, "bad.example")
+        .await
+        .expect("block globally")
+        .expect("not yet blocked");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    // Blocked by one recipient only: the other still gets it.
+    write_line(&mut w, "MAIL FROM:<news@shop.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    for rcpt in ["picky@smtp.test", "open@smtp.test"] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+    }
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "Subject: sale").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    // Blocked globally by its From: address, at a subdomain.
+    write_line(&mut w, "MAIL FROM:<relay@fine.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<open@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "From: Spammer <x@mail.bad.example>").await;
+    write_line(&mut w, "Subject: spam").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    let refused = read_line(&mut reader).await;
+    assert!(refused.starts_with("550 5.7.1"), "{refused}");
+
+    let list = |id| db::list_received_emails(&pool, id, None, None, None);
+    assert!(list(picky.id).await.expect("list received").is_empty());
+    let rows = list(open.id).await.expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("sale"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_can_defer_unknown_recipients_until_after_data() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        reject_unknown_at_rcpt: false,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<nobody@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: lost").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert_eq!(
+        read_line(&mut reader).await.trim_end(),
+        "550 5.1.1 User unknown"
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_keeps_8bit_bodies_intact() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "latin1@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<latin1@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    let message: &[u8] = b"Subject: menu\r\n\
+        Content-Type: text/plain; charset=iso-8859-1\r\n\
+        Content-Transfer-Encoding: 8bit\r\n\
+        \r\n\
+        caf\xe9 cr\xe8me\r\n";
+    w.write_all(message).await.expect("write body");
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].body_text.as_deref().map(str::trim_end), Some("café crème"));
+    let raw = db::fetch_raw_email(&pool, rows[0].id)
+        .await
+        .expect("fetch raw")
+        .expect("raw stored");
+    assert_eq!(raw, message);
+    let headers = db::fetch_email_headers(&pool, rows[0].id)
+        .await
+        .expect("fetch headers")
+        .expect("headers stored");
+    assert_eq!(headers["subject"][0], "menu");
+    assert_eq!(headers["content-transfer-encoding"][0], "8bit");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_stores_decoded_attachments() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "files@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<files@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    for line in [
+        "Subject: report",
+        "MIME-Version: 1.0",
+        "Content-Type: multipart/mixed; boundary=\"b1\"",
+        "",
+        "--b1",
+        "Content-Type: text/plain",
+        "",
+        "see attached",
+        "--b1",
+        "Content-Type: text/csv; name=\"report.csv\"",
+        "Content-Disposition: attachment; filename=\"report.csv\"",
+        "Content-Transfer-Encoding: base64",
+        "",
+        "YSxiCjEsMgo=",
+        "--b1--",
+        ".",
+    ] {
+        write_line(&mut w, line).await;
+    }
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    let attachments = db::list_attachments(&pool, rows[0].id)
+        .await
+        .expect("list attachments");
+    assert_eq!(attachments.len(), 1);
+    assert_eq!(attachments[0].filename.as_deref(), Some("report.csv"));
+    assert_eq!(attachments[0].content_type, "text/csv");
+    let (_, content) = db::fetch_attachment(&pool, rows[0].id, attachments[0].id)
+        .await
+        .expect("fetch attachment")
+        .expect("attachment stored");
+    assert_eq!(content, b"a,b\n1,2\n");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_quarantines_messages_that_fail_processing() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "stuck@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    // No parse can finish in zero time, so every message counts as hung.
+    let config = smtp::SmtpConfig {
+        processing_timeout: std::time::Duration::ZERO,
+        poison_threshold: 2,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    let mut replies = Vec::new();
+    for _ in 0..3 {
+        write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "RCPT TO:<stuck@smtp.test>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "DATA").await;
+        assert!(read_line(&mut reader).await.starts_with("354"));
+        write_line(&mut w, "Subject: pathological").await;
+        write_line(&mut w, "").await;
+        write_line(&mut w, "body").await;
+        write_line(&mut w, ".").await;
+        replies.push(read_line(&mut reader).await);
+    }
+    assert!(replies[0].starts_with("451 4.3.0"), "{}", replies[0]);
+    assert!(replies[1].starts_with("554 5.6.0"), "{}", replies[1]);
+    assert!(replies[2].starts_with("554 5.6.0"), "{}", replies[2]);
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert!(rows.is_empty());
+    let poison = db::list_poison_messages(&pool).await.expect("list poison");
+    assert_eq!(poison.len(), 1);
+    assert_eq!(poison[0].attempts, 3);
+    assert_eq!(poison[0].recipients, vec!["stuck@smtp.test".to_owned()]);
+    assert!(poison[0].error.contains("exceeded"), "{}", poison[0].error);
+    let raw = db::fetch_poison_raw(&pool, poison[0].id)
+        .await
+        .expect("fetch raw")
+        .expect("raw kept");
+    assert_eq!(raw, b"Subject: pathological\r\n\r\nbody\r\n");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_advertises_and_honors_esmtp_extensions() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "ünïcode@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "EHLO client.example").await;
+    let ehlo = read_reply(&mut reader).await;
+    assert_eq!(ehlo.len(), 6, "{ehlo:?}");
+    for ext in ["SIZE ", "8BITMIME", "SMTPUTF8", "PIPELINING", "DSN"] {
+        assert!(ehlo[1..].iter().any(|l| l[4..].starts_with(ext)), "{ext}: {ehlo:?}");
+    }
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example> SIZE=999999999999").await;
+    assert!(read_line(&mut reader).await.starts_with("552 5.3.4"));
+    write_line(&mut w, "MAIL FROM:<a@sender.example> BODY=BINARYMIME").await;
+    assert!(read_line(&mut reader).await.starts_with("555"));
+    write_line(&mut w, "MAIL FROM:<a@sender.example> BODY=8BITMIME").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<ünïcode@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("553 5.6.7"));
+    write_line(&mut w, "RSET").await;
+    let _ = read_line(&mut reader).await;
+
+    // The whole transaction in one write; replies come back in order.
+    w.write_all(
+        "MAIL FROM:<a@sender.example> SIZE=100 SMTPUTF8\r\n\
+         RCPT TO:<ünïcode@smtp.test>\r\n\
+         DATA\r\n"
+            .as_bytes(),
+    )
+    .await
+    .expect("write pipelined commands");
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: pipelined").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("pipelined"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_accepts_dsn_parameters_and_keeps_orcpt() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "fwd@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    // Without EHLO, RCPT TO takes no parameters.
+    write_line(&mut w, "HELO client.example").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<fwd@smtp.test> NOTIFY=NEVER").await;
+    assert!(read_line(&mut reader).await.starts_with("555"));
+
+    write_line(&mut w, "EHLO client.example").await;
+    let _ = read_reply(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<a@sender.example> ENVID=a=b").await;
+    assert!(read_line(&mut reader).await.starts_with("501"));
+    write_line(&mut w, "MAIL FROM:<a@sender.example> RET=HDRS ENVID=QQ+2B1").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    for (params, reply) in [
+        ("NOTIFY=SOMETIMES", "501"),
+        ("NOTIFY=NEVER,SUCCESS", "501"),
+        ("ORCPT=bob@old.test", "501"),
+        ("XFORWARD=1", "555"),
+    ] {
+        write_line(&mut w, &format!("RCPT TO:<fwd@smtp.test> {params}")).await;
+        let line = read_line(&mut reader).await;
+        assert!(line.starts_with(reply), "{params}: {line}");
+    }
+    write_line(
+        &mut w,
+        "RCPT TO:<fwd@smtp.test> NOTIFY=SUCCESS,FAILURE ORCPT=rfc822;bob+2Btag@old.test",
+    )
+    .await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: forwarded").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(
+        rows[0].original_recipient.as_deref(),
+        Some("bob+tag@old.test")
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_records_envelope_and_header_recipients() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let listed = db::insert_temporary_email(&pool, "listed@smtp.test")
+        .await
+        .expect("insert listed address");
+    let hidden = db::insert_temporary_email(&pool, "hidden@smtp.test")
+        .await
+        .expect("insert hidden address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    for rcpt in [
+        "listed@smtp.test",
+        "listed+team@smtp.test",
+        "listed@smtp.test",
+        "hidden@smtp.test",
+    ] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"), "{rcpt}");
+    }
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(
+        &mut w,
+        "To: Listed <Listed@smtp.test>, other@elsewhere.test",
+    )
+    .await;
+    write_line(&mut w, "Cc: team: listed+team@smtp.test;").await;
+    write_line(&mut w, "Subject: who is this for").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "body").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    // One copy per mailbox, however many forms of it were named.
+    let rows = db::list_received_emails(&pool, listed.id, None, None, None)
+        .await
+        .expect("list listed");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(
+        rows[0].envelope_recipients,
+        ["listed@smtp.test", "listed+team@smtp.test"]
+    );
+    assert_eq!(
+        rows[0].header_recipients,
+        [
+            "listed@smtp.test",
+            "other@elsewhere.test",
+            "listed+team@smtp.test"
+        ]
+    );
+    assert!(!rows[0].recipient_mismatch());
+
+    // Bcc'd: delivered by the envelope, absent from the headers.
+    let rows = db::list_received_emails(&pool, hidden.id, None, None, None)
+        .await
+        .expect("list hidden");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].envelope_recipients, ["hidden@smtp.test"]);
+    assert!(rows[0].recipient_mismatch());
+
+    server.abort();
+}
+
+struct SubjectFilter;
+
+impl smtp::IngestStage for SubjectFilter {
+    fn name(&self) -> &str {
+        "subject-filter"
+    }
+
+    fn process<'a>(
+        &'a self,
+        message: &'a smtp::IncomingMessage<'a>,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = smtp::Verdict> + Send + 'a>> {
+        Box::pin(async move {
+            match message.subject.unwrap_or_default() {
+                s if s.contains("buy now") => smtp::Verdict::Reject("550 5.7.1 no ads".into()),
+                s if s.contains("newsletter") => smtp::Verdict::Discard,
+                _ => smtp::Verdict::Tag("screened".into()),
+            }
+        })
+    }
+
+    fn accepts_recipient<'a>(
+        &'a self,
+        _peer: std::net::IpAddr,
+        _mail_from: Option<&'a str>,
+        rcpt: &'a str,
+    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = bool> + Send + 'a>> {
+        Box::pin(async move { !rcpt.starts_with("closed@") })
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_runs_configured_ingestion_stages() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "screened@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::insert_temporary_email(&pool, "closed@smtp.test")
+        .await
+        .expect("insert closed address");
+    db::insert_blocked_sender(&pool, None, "blocked.example")
+        .await
+        .expect("block sender");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        pipeline: smtp::Pipeline::default()
+            .without(smtp::BuiltinStage::SenderBlocks)
+            .with_stage(SubjectFilter),
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    for (subject, reply) in [
+        ("buy now", "550 5.7.1 no ads"),
+        ("weekly newsletter", "250"),
+        ("hello", "250"),
+    ] {
+        // The sender is blocked, but that stage is off.
+        write_line(&mut w, "MAIL FROM:<a@blocked.example>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "RCPT TO:<closed@smtp.test>").await;
+        assert!(read_line(&mut reader).await.starts_with("550 5.7.1"));
+        write_line(&mut w, "RCPT TO:<screened@smtp.test>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, "DATA").await;
+        assert!(read_line(&mut reader).await.starts_with("354"));
+        write_line(&mut w, &format!("Subject: {subject}")).await;
+        write_line(&mut w, "").await;
+        write_line(&mut w, "body").await;
+        write_line(&mut w, ".").await;
+        let line = read_line(&mut reader).await;
+        assert!(line.starts_with(reply), "{subject}: {line}");
+    }
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("hello"));
+    assert!(rows[0].tags.iter().any(|t| t == "screened"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_refuses_messages_over_the_size_limit() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "big@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        max_message_size: 64,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "EHLO client.example").await;
+    let ehlo = read_reply(&mut reader).await;
+    assert!(ehlo.iter().any(|l| &l[4..] == "SIZE 64"), "{ehlo:?}");
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<big@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: too big").await;
+    write_line(&mut w, "").await;
+    for _ in 0..10 {
+        write_line(&mut w, "0123456789abcdef").await;
+    }
+    // Looks like a command, but is still message content.
+    write_line(&mut w, "QUIT").await;
+    write_line(&mut w, ".").await;
+    assert_eq!(
+        read_line(&mut reader).await.trim_end(),
+        "552 5.3.4 Message size exceeds fixed limit"
+    );
+
+    // The session carries on after the refusal.
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<big@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: small").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("small"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_reads_long_binary_lines_in_data() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "binary@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<binary@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+
+    // One body line far over the command line limit, with bytes that are
+    // not UTF-8, and a dot-stuffed line.
+    let long_line: Vec<u8> = (0..200_000u32).map(|i| (i % 200) as u8 + 14).collect();
+    let mut message = b"Subject: binary\r\n\r\n".to_vec();
+    message.extend_from_slice(&long_line);
+    message.extend_from_slice(b"\r\n");
+    w.write_all(&message).await.expect("write body");
+    write_line(&mut w, "..leading dot").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    let raw = db::fetch_raw_email(&pool, rows[0].id)
+        .await
+        .expect("fetch raw")
+        .expect("raw stored");
+    message.extend_from_slice(b".leading dot\r\n");
+    assert_eq!(raw, message);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_delivers_plus_addressed_mail_to_the_base_mailbox() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "dana@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::upsert_mail_domain(&pool, "catch.test", db::DomainPolicy::CatchAll, None)
+        .await
+        .expect("catch_all rule");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    for rcpt in [
+        "dana+shop@smtp.test",
+        "Dana+News@smtp.test",
+        "qa+run1@catch.test",
+    ] {
+        write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"), "{rcpt}");
+        write_line(&mut w, "DATA").await;
+        assert!(read_line(&mut reader).await.starts_with("354"));
+        write_line(&mut w, &format!("Subject: for {rcpt}")).await;
+        write_line(&mut w, "").await;
+        write_line(&mut w, "hi").await;
+        write_line(&mut w, ".").await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+    }
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    let tags: Vec<_> = rows.iter().map(|r| r.plus_tag.as_deref()).collect();
+    assert_eq!(tags, [Some("shop"), Some("news")]);
+    assert_eq!(rows[0].to_addr.as_deref(), Some("dana+shop@smtp.test"));
+
+    let shop = db::list_received_emails(&pool, temp.id, None, None, Some("shop"))
+        .await
+        .expect("list tagged");
+    assert_eq!(shop.len(), 1);
+    assert_eq!(shop[0].subject.as_deref(), Some("for dana+shop@smtp.test"));
+
+    // The catch-all address is created without the tag.
+    assert!(
+        db::find_temporary_email_by_addr(&pool, "qa+run1@catch.test")
+            .await
+            .expect("lookup tagged")
+            .is_none()
+    );
+    let created = db::find_temporary_email_by_addr(&pool, "qa@catch.test")
+        .await
+        .expect("lookup base")
+        .expect("catch-all address created");
+    let rows = db::list_received_emails(&pool, created.id, None, None, Some("run1"))
+        .await
+        .expect("list catch-all");
+    assert_eq!(rows.len(), 1);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_delivers_alias_mail_into_the_owning_mailbox() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "erin@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::insert_address_alias(&pool, temp.id, "Billing@smtp.test")
+        .await
+        .expect("insert alias")
+        .expect("alias is free");
+    assert!(db::insert_address_alias(&pool, temp.id, "erin@smtp.test")
+        .await
+        .expect("insert taken alias")
+        .is_none());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<billing+march@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: invoice").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hi").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].to_addr.as_deref(), Some("billing+march@smtp.test"));
+    assert_eq!(rows[0].plus_tag.as_deref(), Some("march"));
+
+    // Removed aliases are unknown recipients again.
+    assert!(
+        db::delete_address_alias(&pool, temp.id, "billing@smtp.test")
+            .await
+            .expect("delete alias")
+    );
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<billing@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_catch_all_mode_creates_addresses_on_first_delivery() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    db::upsert_mail_domain(&pool, "strict.test", db::DomainPolicy::Registered, None)
+        .await
+        .expect("registered rule");
+    db::insert_blocked_local_part(&pool, "admin", false, None)
+        .await
+        .expect("block exact");
+    db::insert_blocked_local_part(&pool, "root-.*", true, None)
+        .await
+        .expect("block pattern");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let blocklist = std::sync::Arc::new(db::BlocklistCache::default());
+    let config = smtp::SmtpConfig {
+        local_domains: vec!["smtp.test".into()],
+        catch_all: true,
+        blocklist: std::sync::Arc::clone(&blocklist),
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<ci@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    // A rule still wins over the catch-all default.
+    write_line(&mut w, "RCPT TO:<invented@strict.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550 5.1.1"));
+    // Nor are blocklisted names created.
+    for blocked in ["Admin@smtp.test", "root-1@smtp.test"] {
+        write_line(&mut w, &format!("RCPT TO:<{blocked}>")).await;
+        assert!(read_line(&mut reader).await.starts_with("550 5.1.1"));
+    }
+    // The list is cached; entries added later apply once it is invalidated.
+    db::insert_blocked_local_part(&pool, "billing", false, None)
+        .await
+        .expect("block later");
+    blocklist.invalidate().await;
+    write_line(&mut w, "RCPT TO:<billing@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("550 5.1.1"));
+    write_line(&mut w, "RCPT TO:<test-run-42@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: welcome").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hi").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let temp = db::find_temporary_email_by_addr(&pool, "test-run-42@smtp.test")
+        .await
+        .expect("lookup")
+        .expect("address created");
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("welcome"));
+    for never in [
+        "invented@strict.test",
+        "admin@smtp.test",
+        "root-1@smtp.test",
+    ] {
+        assert!(
+            db::find_temporary_email_by_addr(&pool, never)
+                .await
+                .expect("lookup")
+                .is_none(),
+            "{never}"
+        );
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_delivers_to_every_recipient_once() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let mut inboxes = Vec::new();
+    for local in ["first", "second", "third"] {
+        let temp = db::insert_temporary_email(&pool, &format!("{local}@smtp.test"))
+            .await
+            .expect("insert temp address");
+        inboxes.push(temp);
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    for rcpt in ["first", "second", "FIRST", "third"] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}@smtp.test>")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"), "{rcpt}");
+    }
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: to everyone").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hello all").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    for temp in &inboxes {
+        let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+            .await
+            .expect("list received");
+        assert_eq!(rows.len(), 1, "{}", temp.temp_email_addr);
+        assert_eq!(rows[0].subject.as_deref(), Some("to everyone"));
+        assert_eq!(
+            rows[0].to_addr.as_deref(),
+            Some(temp.temp_email_addr.as_str())
+        );
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_defers_messages_it_cannot_store() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "fragile@smtp.test")
+        .await
+        .expect("insert temp address");
+    for statement in [
+        "CREATE FUNCTION refuse_insert() RETURNS trigger AS $$ \
+         BEGIN RAISE EXCEPTION 'storage is down'; END $$ LANGUAGE plpgsql",
+        "CREATE TRIGGER refuse_insert BEFORE INSERT ON received_email \
+         FOR EACH ROW EXECUTE FUNCTION refuse_insert()",
+    ] {
+        sqlx::query(statement)
+            .execute(&pool)
+            .await
+            .expect("install failing trigger");
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    /// Sends the same message and returns the reply to its final dot.
+    async fn send(
+        w: &mut tokio::net::tcp::OwnedWriteHalf,
+        reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>,
+    ) -> String {
+        write_line(w, "MAIL FROM:<a@sender.example>").await;
+        assert!(read_line(reader).await.starts_with("250"));
+        write_line(w, "RCPT TO:<fragile@smtp.test>").await;
+        assert!(read_line(reader).await.starts_with("250"));
+        write_line(w, "DATA").await;
+        assert!(read_line(reader).await.starts_with("354"));
+        write_line(w, "Subject: keep me").await;
+        write_line(w, "").await;
+        write_line(w, "retry until stored").await;
+        write_line(w, ".").await;
+        read_line(reader).await
+    }
+
+    let reply = send(&mut w, &mut reader).await;
+    assert!(reply.starts_with("451 4.3.0"), "{reply}");
+    sqlx::query("DROP TRIGGER refuse_insert ON received_email")
+        .execute(&pool)
+        .await
+        .expect("drop trigger");
+    let reply = send(&mut w, &mut reader).await;
+    assert!(reply.starts_with("250"), "{reply}");
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].subject.as_deref(), Some("keep me"));
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_routes_recipients_by_domain_rule() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let webhook = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind webhook");
+    let hook_url = format!("http://{}/inbound", webhook.local_addr().expect("addr"));
+    let received = tokio::spawn(receive_one_post(webhook));
+
+    db::upsert_mail_domain(&pool, "api.test", db::DomainPolicy::ApiOnly, None)
+        .await
+        .expect("api_only rule");
+    db::upsert_mail_domain(&pool, "catch.test", db::DomainPolicy::CatchAll, None)
+        .await
+        .expect("catch_all rule");
+    db::upsert_mail_domain(
+        &pool,
+        "hook.test",
+        db::DomainPolicy::Webhook,
+        Some(&hook_url),
+    )
+    .await
+    .expect("webhook rule");
+
+    let key = db::insert_api_key(&pool, "routing", b"routing-key")
+        .await
+        .expect("api key");
+    let via_api = db::insert_temporary_email(&pool, "made@api.test")
+        .await
+        .expect("insert api address");
+    db::attribute_temporary_emails(&pool, &[via_api.id], key.id)
+        .await
+        .expect("attribute");
+    db::insert_temporary_email(&pool, "manual@api.test")
+        .await
+        .expect("insert manual address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    for (rcpt, code) in [
+        ("manual@api.test", "550"),
+        ("made@api.test", "250"),
+        ("anything@catch.test", "250"),
+        ("whoever@hook.test", "250"),
+    ] {
+        write_line(&mut w, &format!("RCPT TO:<{rcpt}>")).await;
+        assert!(read_line(&mut reader).await.starts_with(code), "{rcpt}");
+    }
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: routed").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hello").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let (head, body) = received.await.expect("webhook task");
+    assert!(head.starts_with("POST /inbound "), "{head}");
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("x-rcpt-to: whoever@hook.test"), "{head}");
+    assert!(head.contains("x-mail-from: a@sender.example"), "{head}");
+    assert!(head.contains("content-type: message/rfc822"), "{head}");
+    assert!(String::from_utf8_lossy(&body).contains("Subject: routed"));
+    assert!(db::find_temporary_email_by_addr(&pool, "whoever@hook.test")
+        .await
+        .expect("lookup")
+        .is_none());
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    for addr in ["made@api.test", "anything@catch.test"] {
+        let temp = db::find_temporary_email_by_addr(&pool, addr)
+            .await
+            .expect("lookup")
+            .unwrap_or_else(|| panic!("{addr} missing"));
+        let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+            .await
+            .expect("list received");
+        assert_eq!(rows.len(), 1, "{addr}");
+    }
+    let manual = db::find_temporary_email_by_addr(&pool, "manual@api.test")
+        .await
+        .expect("lookup")
+        .expect("manual address");
+    let rows = db::list_received_emails(&pool, manual.id, None, None, None)
+        .await
+        .expect("list received");
+    assert!(rows.is_empty());
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_requires_auth_when_enabled() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let temp = db::insert_temporary_email(&pool, "authed@smtp.test")
+        .await
+        .expect("insert temp address");
+    let hash = smtp::auth::hash_password("stored-password-1").expect("hash");
+    db::upsert_smtp_user(&pool, "stored", &hash)
+        .await
+        .expect("insert smtp user");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        auth: smtp::SmtpAuth {
+            required: true,
+            users: vec![("relay".into(), "relay-password".into())],
+        },
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "EHLO client.example").await;
+    let ehlo = read_reply(&mut reader).await;
+    assert!(ehlo.iter().any(|l| l == "250-AUTH PLAIN LOGIN"), "{ehlo:?}");
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("530"));
+
+    // Config user, wrong password.
+    write_line(&mut w, "AUTH PLAIN AHJlbGF5AHdyb25nLXBhc3N3b3Jk").await;
+    assert!(read_line(&mut reader).await.starts_with("535"));
+    write_line(&mut w, "AUTH CRAM-MD5").await;
+    assert!(read_line(&mut reader).await.starts_with("504"));
+
+    // Table user over LOGIN, one challenge at a time.
+    write_line(&mut w, "AUTH LOGIN").await;
+    assert_eq!(read_line(&mut reader).await, "334 VXNlcm5hbWU6\r\n");
+    write_line(&mut w, "c3RvcmVk").await;
+    assert_eq!(read_line(&mut reader).await, "334 UGFzc3dvcmQ6\r\n");
+    write_line(&mut w, "c3RvcmVkLXBhc3N3b3JkLTE=").await;
+    assert!(read_line(&mut reader).await.starts_with("235"));
+    write_line(&mut w, "AUTH PLAIN AHJlbGF5AHJlbGF5LXBhc3N3b3Jk").await;
+    assert!(read_line(&mut reader).await.starts_with("503"));
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<authed@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: authenticated").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "hello").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    // Config user over PLAIN on a second connection; three failures close it.
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "EHLO client.example").await;
+    let _ = read_reply(&mut reader).await;
+    write_line(&mut w, "AUTH PLAIN").await;
+    assert!(read_line(&mut reader).await.starts_with("334"));
+    write_line(&mut w, "AHJlbGF5AHJlbGF5LXBhc3N3b3Jk").await;
+    assert!(read_line(&mut reader).await.starts_with("235"));
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "EHLO client.example").await;
+    let _ = read_reply(&mut reader).await;
+    for _ in 0..3 {
+        write_line(&mut w, "AUTH PLAIN AHJlbGF5AHdyb25nLXBhc3N3b3Jk").await;
+        assert!(read_line(&mut reader).await.starts_with("535"));
+    }
+    assert!(read_line(&mut reader).await.starts_with("421"));
+
+    tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_fires_watch_rules_with_captured_groups() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let webhook = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind webhook");
+    let hook_url = format!("http://{}/watch", webhook.local_addr().expect("addr"));
+    let received = tokio::spawn(receive_one_post(webhook));
+
+    let temp = db::insert_temporary_email(&pool, "watched@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::insert_watch_rule(
+        &pool,
+        temp.id,
+        db::WatchKind::Regex,
+        r"order #(?P<order>\d+)(-(\w+))?",
+        &hook_url,
+    )
+    .await
+    .expect("insert regex rule");
+    db::insert_watch_rule(
+        &pool,
+        temp.id,
+        db::WatchKind::Substring,
+        "never mentioned",
+        "http://127.0.0.1:9/unused",
+    )
+    .await
+    .expect("insert substring rule");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::run_server_on_listener(listener, server_pool)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<shop@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RCPT TO:<watched@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: Your receipt").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "Thanks for order #4521, it ships today.").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let (head, body) = received.await.expect("webhook task");
+    assert!(head.starts_with("POST /watch "), "{head}");
+    assert!(head.to_ascii_lowercase().contains("content-type: application/json"));
+    let body = String::from_utf8(body).expect("utf-8 body");
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    for part in [
+        r#""event":"watch.matched""#,
+        r#""address":"watched@smtp.test""#,
+        &format!(r#""email_id":"{}""#, rows[0].id),
+        r#""from":"shop@sender.example""#,
+        r#""subject":"Your receipt""#,
+        r#""field":"body_text""#,
+        r#""match":"order #4521""#,
+        r#""groups":["4521",null,null]"#,
+        r#""named":{"order":"4521"}"#,
+    ] {
+        assert!(body.contains(part), "{part} missing from {body}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_times_out_idle_clients_and_caps_commands() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+    db::insert_temporary_email(&pool, "idle@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        max_commands_per_session: 5,
+        idle_timeout: std::time::Duration::from_millis(300),
+        ..Default::default()
+    };
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    // Silent after the greeting.
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, _w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    assert!(read_line(&mut reader).await.starts_with("220"));
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("421 4.4.2"), "{reply}");
+    assert_eq!(
+        read_line(&mut reader).await,
+        "",
+        "server closes the session"
+    );
+
+    // Silent halfway through a message.
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "EHLO client.test").await;
+    let _ = read_reply(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "RCPT TO:<idle@smtp.test>").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    w.write_all(b"Subject: unfinished").await.expect("write");
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("421"), "{reply}");
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    for _ in 0..5 {
+        write_line(&mut w, "NOOP").await;
+        let _ = read_line(&mut reader).await;
+    }
+    write_line(&mut w, "NOOP").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("421 4.7.0"), "{reply}");
+    assert_eq!(
+        read_line(&mut reader).await,
+        "",
+        "server closes the session"
+    );
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_rejects_or_tags_clients_talking_before_the_greeting() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+    let temp = db::insert_temporary_email(&pool, "early@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let mut servers = Vec::new();
+    let mut bound = Vec::new();
+    for early_talkers in [smtp::EarlyTalkers::Reject, smtp::EarlyTalkers::Tag] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+        bound.push(listener.local_addr().expect("local addr"));
+        let config = smtp::SmtpConfig {
+            greeting_delay: std::time::Duration::from_millis(300),
+            early_talkers,
+            ..Default::default()
+        };
+        let server_pool = pool.clone();
+        servers.push(tokio::spawn(async move {
+            smtp::serve(listener, server_pool, config)
+                .await
+                .expect("smtp serve");
+        }));
+    }
+
+    let stream = TcpStream::connect(bound[0]).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    write_line(&mut w, "EHLO impatient.test").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("554"), "{reply}");
+    assert_eq!(
+        read_line(&mut reader).await,
+        "",
+        "server closes the session"
+    );
+
+    // Waiting for the greeting is fine either way; talking early is only
+    // noted when tagging.
+    for early in [false, true] {
+        let stream = TcpStream::connect(bound[1]).await.expect("connect smtp");
+        let (r, mut w) = stream.into_split();
+        let mut reader = BufReader::new(r);
+        if !early {
+            assert!(read_line(&mut reader).await.starts_with("220"));
+        }
+        write_line(&mut w, "EHLO client.test").await;
+        if early {
+            assert!(read_line(&mut reader).await.starts_with("220"));
+        }
+        assert!(read_reply(&mut reader).await[0].starts_with("250"));
+        for command in [
+            "MAIL FROM:<a@sender.example>",
+            "RCPT TO:<early@smtp.test>",
+            "DATA",
+        ] {
+            write_line(&mut w, command).await;
+            let _ = read_line(&mut reader).await;
+        }
+        let subject = if early { "early" } else { "patient" };
+        write_line(&mut w, &format!("Subject: {subject}\r\n\r\nbody\r\n.")).await;
+        assert!(read_line(&mut reader).await.starts_with("250"));
+    }
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    let mut tags: Vec<_> = rows
+        .iter()
+        .map(|r| (r.subject.clone().unwrap_or_default(), r.tags.clone()))
+        .collect();
+    tags.sort();
+    assert_eq!(
+        tags,
+        [
+            ("early".to_owned(), vec!["early-talker".to_owned()]),
+            ("patient".to_owned(), vec![]),
+        ]
+    );
+
+    for server in servers {
+        server.abort();
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_refuses_or_tags_forged_helo() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+    let temp = db::insert_temporary_email(&pool, "helo@smtp.test")
+        .await
+        .expect("insert temp address");
+
+    let mut servers = Vec::new();
+    let mut bound = Vec::new();
+    for helo_policy in [smtp::HeloPolicy::Reject, smtp::HeloPolicy::Tag] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+        bound.push(listener.local_addr().expect("local addr"));
+        let config = smtp::SmtpConfig {
+            banner_domain: "mx.smtp.test".into(),
+            helo_policy,
+            ..Default::default()
+        };
+        let server_pool = pool.clone();
+        servers.push(tokio::spawn(async move {
+            smtp::serve(listener, server_pool, config)
+                .await
+                .expect("smtp serve");
+        }));
+    }
+
+    let stream = TcpStream::connect(bound[0]).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    for (helo, expected) in [
+        ("EHLO 192.0.2.1", "550"),
+        ("EHLO mx.smtp.test", "550"),
+        ("EHLO", "501"),
+        ("HELO bad_name!", "501"),
+        ("EHLO [127.0.0.1]", "250"),
+    ] {
+        write_line(&mut w, helo).await;
+        let reply = read_reply(&mut reader).await;
+        assert!(reply[0].starts_with(expected), "{helo}: {reply:?}");
+    }
+
+    let stream = TcpStream::connect(bound[1]).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    let _ = read_line(&mut reader).await;
+    for command in [
+        "EHLO mx.smtp.test",
+        "MAIL FROM:<a@sender.example>",
+        "RCPT TO:<helo@smtp.test>",
+        "DATA",
+    ] {
+        write_line(&mut w, command).await;
+        let _ = read_reply(&mut reader).await;
+    }
+    write_line(&mut w, "Subject: forged\r\n\r\nbody\r\n.").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    let rows = db::list_received_emails(&pool, temp.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].tags, ["helo-forged"]);
+
+    for server in servers {
+        server.abort();
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_limits_connections_and_messages_per_ip() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        max_connections_per_ip: 2,
+        max_messages_per_ip_per_minute: 1,
+        ..Default::default()
+    };
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let connect = || async {
+        let stream = TcpStream::connect(bound).await.expect("connect smtp");
+        let (r, w) = stream.into_split();
+        let mut reader = BufReader::new(r);
+        let greeting = read_line(&mut reader).await;
+        (reader, w, greeting)
+    };
+    let (mut reader, mut w, greeting) = connect().await;
+    assert!(greeting.starts_with("220"));
+    let second = connect().await;
+    assert!(second.2.starts_with("220"));
+    let (_, _, refused) = connect().await;
+    assert!(refused.starts_with("421"), "{refused}");
+
+    // A closed connection frees its place.
+    drop(second);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let (_, _, greeting) = connect().await;
+    assert!(greeting.starts_with("220"), "{greeting}");
+
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RSET").await;
+    let _ = read_line(&mut reader).await;
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("450 4.7.1"), "{reply}");
+
+    server.abort();
+}
+
+#[tokio::test]
+#[serial]
+async fn smtp_lets_expected_senders_past_the_message_limit() {
+    let (_container, url) = start_postgres().await;
+    let pool = connect_retry(&url).await;
+    db::run_migrations(&pool).await.expect("migrations");
+
+    let waiting = db::insert_temporary_email(&pool, "waiting@smtp.test")
+        .await
+        .expect("insert temp address");
+    db::insert_temporary_email(&pool, "other@smtp.test")
+        .await
+        .expect("insert temp address");
+    let ttl = std::time::Duration::from_secs(600);
+    db::upsert_sender_expectation(&pool, waiting.id, "OTP.example", ttl)
+        .await
+        .expect("expect sender");
+    db::insert_webhook_subscription(&pool, waiting.id, "https://hooks.test/in", "secret")
+        .await
+        .expect("subscribe");
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind smtp");
+    let bound = listener.local_addr().expect("local addr");
+    let config = smtp::SmtpConfig {
+        max_messages_per_ip_per_minute: 1,
+        ..Default::default()
+    };
+    let server_pool = pool.clone();
+    let server = tokio::spawn(async move {
+        smtp::serve(listener, server_pool, config)
+            .await
+            .expect("smtp serve");
+    });
+
+    let stream = TcpStream::connect(bound).await.expect("connect smtp");
+    let (r, mut w) = stream.into_split();
+    let mut reader = BufReader::new(r);
+    assert!(read_line(&mut reader).await.starts_with("220"));
+
+    // Uses up the allowance.
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "RSET").await;
+    let _ = read_line(&mut reader).await;
+
+    write_line(&mut w, "MAIL FROM:<codes@mail.otp.example>").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("250"), "{reply}");
+    write_line(&mut w, "RCPT TO:<other@smtp.test>").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("450 4.7.1"), "{reply}");
+    write_line(&mut w, "RCPT TO:<waiting@smtp.test>").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+    write_line(&mut w, "DATA").await;
+    assert!(read_line(&mut reader).await.starts_with("354"));
+    write_line(&mut w, "Subject: your code is 123456").await;
+    write_line(&mut w, "").await;
+    write_line(&mut w, "123456").await;
+    write_line(&mut w, ".").await;
+    assert!(read_line(&mut reader).await.starts_with("250"));
+
+    // Other senders are still held back.
+    write_line(&mut w, "MAIL FROM:<a@sender.example>").await;
+    let reply = read_line(&mut reader).await;
+    assert!(reply.starts_with("450 4.7.1"), "{reply}");
+
+    let rows = db::list_received_emails(&pool, waiting.id, None, None, None)
+        .await
+        .expect("list received");
+    assert_eq!(rows.len(), 1);
+    let priority: Vec<bool> = sqlx::query_scalar("SELECT priority FROM webhook_delivery")
+        .fetch_all(&pool)
+        .await
+        .expect("deliveries");
+    assert_eq!(priority, [true]);
+
+    server.abort();
+}
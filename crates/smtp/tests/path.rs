@@ -0,0 +1,233 @@
+use smtp::path::{
+    decode_xtext, parse_mail_from, parse_rcpt_to, split_plus_tag, EsmtpParam, ForwardPath, Mailbox,
+    PathError, ReversePath,
+};
+
+fn mailbox(local_part: &str, domain: &str) -> Mailbox {
+    Mailbox {
+        local_part: local_part.into(),
+        domain: domain.into(),
+    }
+}
+
+#[test]
+fn parses_plain_paths() {
+    let from = parse_mail_from("MAIL FROM:<Alice@Example.COM>").expect("mail from");
+    assert_eq!(
+        from.path,
+        ReversePath::Mailbox(mailbox("Alice", "Example.COM"))
+    );
+    assert!(from.params.is_empty());
+    assert_eq!(
+        from.path.mailbox().map(Mailbox::address).as_deref(),
+        Some("alice@example.com")
+    );
+
+    let rcpt = parse_rcpt_to("rcpt to:<bob.smith+tag@mail.example.org>").expect("rcpt to");
+    assert_eq!(
+        rcpt.path,
+        ForwardPath::Mailbox(mailbox("bob.smith+tag", "mail.example.org"))
+    );
+}
+
+#[test]
+fn accepts_null_sender_and_postmaster() {
+    assert_eq!(
+        parse_mail_from("MAIL FROM:<>").expect("null sender").path,
+        ReversePath::Null
+    );
+    assert_eq!(
+        parse_mail_from("MAIL FROM:<> SIZE=0")
+            .expect("null sender with params")
+            .path,
+        ReversePath::Null
+    );
+    assert_eq!(
+        parse_rcpt_to("RCPT TO:<Postmaster>")
+            .expect("postmaster")
+            .path,
+        ForwardPath::Postmaster
+    );
+    assert_eq!(
+        parse_rcpt_to("RCPT TO:<>"),
+        Err(PathError::BadLocalPart),
+        "null forward path"
+    );
+}
+
+#[test]
+fn parses_esmtp_parameters() {
+    let from = parse_mail_from("MAIL FROM:<a@b.test> SIZE=12345 body=8BITMIME SMTPUTF8")
+        .expect("mail from");
+    assert_eq!(from.size(), Some(12345));
+    assert_eq!(
+        from.params,
+        vec![
+            EsmtpParam {
+                keyword: "SIZE".into(),
+                value: Some("12345".into()),
+            },
+            EsmtpParam {
+                keyword: "BODY".into(),
+                value: Some("8BITMIME".into()),
+            },
+            EsmtpParam {
+                keyword: "SMTPUTF8".into(),
+                value: None,
+            },
+        ]
+    );
+
+    let rcpt = parse_rcpt_to("RCPT TO:<a@b.test> NOTIFY=SUCCESS,FAILURE").expect("rcpt to");
+    assert_eq!(rcpt.params[0].value.as_deref(), Some("SUCCESS,FAILURE"));
+
+    assert_eq!(
+        parse_mail_from("MAIL FROM:<a@b.test>SIZE=1"),
+        Err(PathError::BadParameter)
+    );
+    assert_eq!(
+        parse_mail_from("MAIL FROM:<a@b.test> SIZE="),
+        Err(PathError::BadParameter)
+    );
+    assert_eq!(
+        parse_mail_from("MAIL FROM:<a@b.test> -SIZE=1"),
+        Err(PathError::BadParameter)
+    );
+}
+
+#[test]
+fn decodes_dsn_parameters() {
+    assert_eq!(decode_xtext("a+2Bb+3Dc").as_deref(), Some("a+b=c"));
+    assert_eq!(decode_xtext("caf+C3+A9").as_deref(), Some("café"));
+    assert_eq!(decode_xtext("a+2"), None);
+    assert_eq!(decode_xtext("a+GG"), None);
+    assert_eq!(decode_xtext("a=b"), None);
+    assert_eq!(decode_xtext("+FF"), None);
+
+    let from = parse_mail_from("MAIL FROM:<a@b.test> RET=HDRS ENVID=QQ+2B1").expect("mail from");
+    assert_eq!(from.envelope_id(), Ok(Some("QQ+1".into())));
+    let from = parse_mail_from("MAIL FROM:<a@b.test>").expect("mail from");
+    assert_eq!(from.envelope_id(), Ok(None));
+
+    let rcpt =
+        parse_rcpt_to("RCPT TO:<a@b.test> ORCPT=rfc822;bob+2Btag@old.test").expect("rcpt to");
+    assert_eq!(
+        rcpt.original_recipient(),
+        Ok(Some("bob+tag@old.test".into()))
+    );
+    for bad in ["rfc822", ";bob@old.test", "rfc822;", "rfc_822;bob@old.test"] {
+        let rcpt = parse_rcpt_to(&format!("RCPT TO:<a@b.test> ORCPT={bad}")).expect("rcpt to");
+        assert_eq!(
+            rcpt.original_recipient(),
+            Err(PathError::BadParameter),
+            "{bad}"
+        );
+    }
+}
+
+#[test]
+fn handles_quoted_local_parts() {
+    let from = parse_mail_from(r#"MAIL FROM:<"john doe>\"x"@example.com> SIZE=10"#)
+        .expect("quoted local part");
+    let sender = from.path.mailbox().expect("mailbox").clone();
+    assert_eq!(sender, mailbox(r#"john doe>"x"#, "example.com"));
+    assert_eq!(sender.to_string(), r#""john doe>\"x"@example.com"#);
+    assert_eq!(from.size(), Some(10));
+
+    let plain = parse_rcpt_to(r#"RCPT TO:<"simple"@example.com>"#).expect("needless quotes");
+    let ForwardPath::Mailbox(plain) = plain.path else {
+        panic!("expected a mailbox");
+    };
+    assert_eq!(plain.to_string(), "simple@example.com");
+
+    assert_eq!(
+        parse_mail_from(r#"MAIL FROM:<"unterminated@example.com>"#),
+        Err(PathError::MissingBrackets)
+    );
+    assert_eq!(
+        parse_mail_from(r#"MAIL FROM:<"a"b@example.com>"#),
+        Err(PathError::BadLocalPart)
+    );
+}
+
+#[test]
+fn tolerates_space_after_colon_and_source_routes() {
+    let from = parse_mail_from("MAIL FROM: <a@b.test>").expect("space after colon");
+    assert_eq!(from.path, ReversePath::Mailbox(mailbox("a", "b.test")));
+
+    let rcpt = parse_rcpt_to("RCPT TO:<@relay1.test,@relay2.test:c@d.test>").expect("route");
+    assert_eq!(rcpt.path, ForwardPath::Mailbox(mailbox("c", "d.test")));
+}
+
+#[test]
+fn accepts_address_literals() {
+    let rcpt = parse_rcpt_to("RCPT TO:<root@[192.0.2.1]>").expect("ipv4 literal");
+    assert_eq!(
+        rcpt.path,
+        ForwardPath::Mailbox(mailbox("root", "[192.0.2.1]"))
+    );
+    assert!(parse_rcpt_to("RCPT TO:<root@[IPv6:2001:db8::1]>").is_ok());
+    assert_eq!(
+        parse_rcpt_to("RCPT TO:<root@[]>"),
+        Err(PathError::BadDomain)
+    );
+}
+
+#[test]
+fn rejects_malformed_paths() {
+    let cases = [
+        ("MAIL FROM:a@b.test", PathError::MissingBrackets),
+        ("MAIL FROM:<a@b.test", PathError::MissingBrackets),
+        ("MAIL FROM:<ab.test>", PathError::BadLocalPart),
+        ("MAIL FROM:<.a@b.test>", PathError::BadLocalPart),
+        ("MAIL FROM:<a..b@b.test>", PathError::BadLocalPart),
+        ("MAIL FROM:<a b@b.test>", PathError::BadLocalPart),
+        ("MAIL FROM:<a@>", PathError::BadDomain),
+        ("MAIL FROM:<a@-b.test>", PathError::BadDomain),
+        ("MAIL FROM:<a@b..test>", PathError::BadDomain),
+        ("MAIL FROM:<a@b_c.test>", PathError::BadDomain),
+        ("RCPT TO:<a@b.test>", PathError::WrongCommand),
+    ];
+    for (cmd, want) in cases {
+        assert_eq!(parse_mail_from(cmd).map(|_| ()), Err(want), "{cmd}");
+    }
+
+    let long_local = format!("MAIL FROM:<{}@b.test>", "a".repeat(65));
+    assert_eq!(
+        parse_mail_from(&long_local),
+        Err(PathError::LocalPartTooLong)
+    );
+    let long_domain = format!("MAIL FROM:<a@{}.test>", "b.".repeat(130));
+    assert_eq!(parse_mail_from(&long_domain), Err(PathError::DomainTooLong));
+}
+
+#[test]
+fn accepts_internationalized_addresses() {
+    let from = parse_mail_from("MAIL FROM:<用户@例子.广告> SMTPUTF8").expect("utf-8 path");
+    let sender = from.path.mailbox().expect("mailbox");
+    assert_eq!(sender, &mailbox("用户", "例子.广告"));
+    assert!(!sender.is_ascii());
+    assert!(mailbox("alice", "example.com").is_ascii());
+
+    let rcpt = parse_rcpt_to("RCPT TO:<\"jörg smith\"@bücher.test>").expect("quoted utf-8");
+    assert_eq!(
+        rcpt.path,
+        ForwardPath::Mailbox(mailbox("jörg smith", "bücher.test"))
+    );
+}
+
+#[test]
+fn splits_plus_tags_off_the_local_part() {
+    let cases = [
+        ("abc123+shop@smtp.test", "abc123@smtp.test", Some("shop")),
+        ("abc123+a+b@smtp.test", "abc123@smtp.test", Some("a+b")),
+        ("abc123+@smtp.test", "abc123@smtp.test", None),
+        ("abc123@smtp.test", "abc123@smtp.test", None),
+        ("+shop@smtp.test", "+shop@smtp.test", None),
+        ("\"a+b\"@smtp.test", "\"a+b\"@smtp.test", None),
+        ("postmaster", "postmaster", None),
+    ];
+    for (addr, mailbox, tag) in cases {
+        assert_eq!(split_plus_tag(addr), (mailbox.to_owned(), tag), "{addr}");
+    }
+}
@@ -0,0 +1,174 @@
+use db::SpfResult;
+use smtp::dns::{Dns, DnsError};
+use smtp::spf::check;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Answers from fixed records; names in `broken` fail like a DNS outage.
+#[derive(Default)]
+struct FakeDns {
+    txt: HashMap<&'static str, Vec<&'static str>>,
+    addrs: HashMap<&'static str, Vec<IpAddr>>,
+    mx: HashMap<&'static str, Vec<&'static str>>,
+    broken: Vec<&'static str>,
+}
+
+impl FakeDns {
+    fn with_txt(mut self, name: &'static str, record: &'static str) -> Self {
+        self.txt.entry(name).or_default().push(record);
+        self
+    }
+
+    fn with_addr(mut self, name: &'static str, addr: &str) -> Self {
+        let addr = addr.parse().expect("ip");
+        self.addrs.entry(name).or_default().push(addr);
+        self
+    }
+
+    fn with_mx(mut self, name: &'static str, exchange: &'static str) -> Self {
+        self.mx.entry(name).or_default().push(exchange);
+        self
+    }
+
+    fn failing(&self, name: &str) -> Result<(), DnsError> {
+        if self.broken.contains(&name) {
+            return Err(DnsError);
+        }
+        Ok(())
+    }
+}
+
+impl Dns for FakeDns {
+    async fn txt(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        self.failing(name)?;
+        Ok(self
+            .txt
+            .get(name)
+            .map(|records| records.iter().map(|r| r.to_string()).collect())
+            .unwrap_or_default())
+    }
+
+    async fn addrs(&self, name: &str, ipv6: bool) -> Result<Vec<IpAddr>, DnsError> {
+        self.failing(name)?;
+        Ok(self
+            .addrs
+            .get(name)
+            .map(|addrs| {
+                addrs
+                    .iter()
+                    .copied()
+                    .filter(|a| a.is_ipv6() == ipv6)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn mx(&self, name: &str) -> Result<Vec<String>, DnsError> {
+        self.failing(name)?;
+        Ok(self
+            .mx
+            .get(name)
+            .map(|hosts| hosts.iter().map(|h| h.to_string()).collect())
+            .unwrap_or_default())
+    }
+}
+
+async fn spf(dns: &FakeDns, ip: &str, mail_from: Option<&str>) -> SpfResult {
+    check(dns, ip.parse().expect("ip"), mail_from, "mta.example.net").await
+}
+
+#[tokio::test]
+async fn matches_addresses_and_qualifiers() {
+    let dns = FakeDns::default()
+        .with_txt(
+            "example.com",
+            "v=spf1 ip4:192.0.2.0/24 ip6:2001:db8::/32 ~all",
+        )
+        .with_txt("strict.example", "v=spf1 ip4:192.0.2.10 -all")
+        .with_txt("open.example", "v=spf1 ?all")
+        .with_txt("example.com", "some-verification=abc");
+    let from = Some("alice@example.com");
+
+    assert_eq!(spf(&dns, "192.0.2.77", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "2001:db8::25", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "::ffff:192.0.2.77", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "198.51.100.1", from).await, SpfResult::SoftFail);
+    assert_eq!(
+        spf(&dns, "192.0.2.11", Some("bob@strict.example")).await,
+        SpfResult::Fail
+    );
+    assert_eq!(
+        spf(&dns, "192.0.2.11", Some("bob@open.example")).await,
+        SpfResult::Neutral
+    );
+    assert_eq!(
+        spf(&dns, "192.0.2.11", Some("bob@nospf.example")).await,
+        SpfResult::None
+    );
+}
+
+#[tokio::test]
+async fn follows_a_mx_include_and_redirect() {
+    let dns = FakeDns::default()
+        .with_txt(
+            "example.com",
+            "v=spf1 a mx/24 include:_spf.provider.test -all",
+        )
+        .with_addr("example.com", "203.0.113.5")
+        .with_mx("example.com", "mx.example.com")
+        .with_addr("mx.example.com", "198.51.100.20")
+        .with_txt("_spf.provider.test", "v=spf1 ip4:192.0.2.0/28 -all")
+        .with_txt("child.example", "v=spf1 redirect=example.com");
+    let from = Some("alice@example.com");
+
+    assert_eq!(spf(&dns, "203.0.113.5", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "198.51.100.200", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "192.0.2.3", from).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "192.0.2.30", from).await, SpfResult::Fail);
+    assert_eq!(
+        spf(&dns, "192.0.2.3", Some("bob@child.example")).await,
+        SpfResult::Pass
+    );
+}
+
+#[tokio::test]
+async fn expands_macros_and_checks_the_helo_name_for_bounces() {
+    let dns = FakeDns::default()
+        .with_txt("example.com", "v=spf1 exists:%{ir}.%{l1r+-}._spf.%{d} -all")
+        .with_addr("7.2.0.192.alice._spf.example.com", "127.0.0.2")
+        .with_txt("mta.example.net", "v=spf1 ip4:192.0.2.7 -all");
+
+    assert_eq!(
+        spf(&dns, "192.0.2.7", Some("alice@example.com")).await,
+        SpfResult::Pass
+    );
+    assert_eq!(
+        spf(&dns, "192.0.2.7", Some("mallory@example.com")).await,
+        SpfResult::Fail
+    );
+    assert_eq!(spf(&dns, "192.0.2.7", None).await, SpfResult::Pass);
+    assert_eq!(spf(&dns, "192.0.2.8", None).await, SpfResult::Fail);
+}
+
+#[tokio::test]
+async fn reports_broken_records_and_dns_failures() {
+    let mut dns = FakeDns::default()
+        .with_txt("twice.example", "v=spf1 -all")
+        .with_txt("twice.example", "v=spf1 +all")
+        .with_txt("typo.example", "v=spf1 ip4:192.0.2.300 -all")
+        .with_txt("loop.example", "v=spf1 include:loop.example -all")
+        .with_txt("missing.example", "v=spf1 include:nowhere.example -all")
+        .with_txt("flaky.example", "v=spf1 include:down.example -all");
+    dns.broken.push("down.example");
+
+    for (from, expected) in [
+        ("a@twice.example", SpfResult::PermError),
+        ("a@typo.example", SpfResult::PermError),
+        ("a@loop.example", SpfResult::PermError),
+        ("a@missing.example", SpfResult::PermError),
+        ("a@flaky.example", SpfResult::TempError),
+        ("a@down.example", SpfResult::TempError),
+    ] {
+        assert_eq!(spf(&dns, "192.0.2.1", Some(from)).await, expected, "{from}");
+    }
+}
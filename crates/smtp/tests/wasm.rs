@@ -0,0 +1,146 @@
+#![cfg(feature = "wasm-plugins")]
+
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+
+use smtp::wasm::{ApiReadInput, WasmStage};
+use smtp::{IncomingMessage, IngestStage, Verdict};
+use uuid::Uuid;
+
+/// Writes `wat` to a file named after `name`, which becomes the stage name.
+fn plugin(name: &str, wat: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("smtp-wasm-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create plugin dir");
+    let path = dir.join(format!("{name}.wat"));
+    std::fs::write(&path, wat).expect("write plugin");
+    path
+}
+
+fn message(recipients: &[String]) -> IncomingMessage<'_> {
+    IncomingMessage {
+        peer: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        mail_from: Some("a@sender.example"),
+        recipients,
+        raw: b"Subject: hi\r\n\r\nbody\r\n",
+        subject: Some("hi"),
+        headers: &[],
+        tags: &[],
+        spf: None,
+        dmarc: None,
+    }
+}
+
+#[tokio::test]
+async fn plugins_answer_both_hooks() {
+    let path = plugin(
+        "strict",
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 1024)
+            (func (export "on_rcpt") (param i32 i32) (result i32) i32.const 1)
+            (func (export "on_message") (param i32 i32) (result i32) i32.const 2))"#,
+    );
+    let stage = WasmStage::load(&path).expect("load plugin");
+    assert_eq!(stage.name(), "strict");
+
+    let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    assert!(!stage.accepts_recipient(peer, None, "a@smtp.test").await);
+    let recipients = ["a@smtp.test".to_owned()];
+    assert_eq!(
+        stage.process(&message(&recipients)).await,
+        Verdict::Reject("550 5.7.1 rejected by strict".into())
+    );
+}
+
+#[tokio::test]
+async fn missing_or_runaway_hooks_let_mail_through() {
+    let path = plugin(
+        "spinner",
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "on_message") (param i32 i32) (result i32)
+                (loop $forever (br $forever))
+                i32.const 2))"#,
+    );
+    let stage = WasmStage::load(&path)
+        .expect("load plugin")
+        .with_fuel(10_000);
+
+    let peer = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    assert!(stage.accepts_recipient(peer, None, "a@smtp.test").await);
+    let recipients = ["a@smtp.test".to_owned()];
+    assert_eq!(
+        stage.process(&message(&recipients)).await,
+        Verdict::Continue
+    );
+}
+
+#[test]
+fn plugins_may_not_import_anything() {
+    let path = plugin(
+        "clock",
+        r#"(module
+            (import "env" "now" (func (result i64)))
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0))"#,
+    );
+    let err = WasmStage::load(&path).err().expect("refused");
+    assert!(err.contains("env::now"), "{err}");
+
+    let path = plugin("forgetful", r#"(module (memory (export "memory") 1))"#);
+    let err = WasmStage::load(&path).err().expect("refused");
+    assert!(err.contains("does not export alloc"), "{err}");
+}
+
+#[tokio::test]
+async fn instances_are_held_to_the_memory_cap() {
+    let path = plugin(
+        "hungry",
+        r#"(module
+            (memory (export "memory") 4)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "on_message") (param i32 i32) (result i32) i32.const 2))"#,
+    );
+    let recipients = ["a@smtp.test".to_owned()];
+    let stage = WasmStage::load(&path).expect("load plugin");
+    assert_eq!(
+        stage.process(&message(&recipients)).await,
+        Verdict::Reject("550 5.7.1 rejected by hungry".into())
+    );
+    // Four 64 KiB pages do not fit in two.
+    let capped = stage.with_max_memory(2 << 16);
+    assert_eq!(
+        capped.process(&message(&recipients)).await,
+        Verdict::Continue
+    );
+}
+
+#[tokio::test]
+async fn api_reads_are_refused_only_by_plugins_hooking_them() {
+    let input = ApiReadInput {
+        address: Some("a@smtp.test"),
+        email_id: Uuid::nil(),
+        from: Some("a@sender.example"),
+        subject: Some("hi"),
+        tags: &[],
+    };
+    let path = plugin(
+        "censor",
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "on_api_read") (param i32 i32) (result i32) i32.const 1))"#,
+    );
+    let censor = WasmStage::load(&path).expect("load plugin");
+    assert!(!censor.allows_api_read(&input).await);
+
+    let path = plugin(
+        "silent",
+        r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0))"#,
+    );
+    let silent = WasmStage::load(&path).expect("load plugin");
+    assert!(silent.allows_api_read(&input).await);
+}
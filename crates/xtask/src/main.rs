@@ -0,0 +1,170 @@
+//! Developer commands for the workspace: `cargo xtask <command>`.
+
+mod seed;
+
+use sqlx::postgres::PgPool;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+
+const USAGE: &str = "\
+usage: cargo xtask <command>
+
+commands:
+  migrate          apply pending migrations to DATABASE_URL
+  prepare-sqlx     migrate, then refresh the offline query data in .sqlx/
+  gen-openapi      write the HTTP API description to openapi.json [--out <path>]
+  seed-demo-data   create two addresses with a mix of sample mail to click through
+  run-all          migrate, then run the backend and the UI dev server";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    dotenvy::from_path(workspace_root().join(".env")).ok();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("migrate") => migrate().await,
+        Some("prepare-sqlx") => prepare_sqlx().await,
+        Some("gen-openapi") => gen_openapi(&args[1..]),
+        Some("seed-demo-data") => seed_demo_data().await,
+        Some("run-all") => run_all().await,
+        Some("-h" | "--help" | "help") => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Some(other) => Err(format!("unknown command `{other}`\n\n{USAGE}")),
+        None => Err(USAGE.to_owned()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+type Result<T = ()> = std::result::Result<T, String>;
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .ancestors()
+        .nth(2)
+        .expect("xtask lives in crates/xtask")
+        .to_path_buf()
+}
+
+async fn connect() -> Result<PgPool> {
+    db::connect_pool()
+        .await
+        .map_err(|e| format!("database connection failed: {e}"))
+}
+
+async fn migrate() -> Result {
+    let pool = connect().await?;
+    let pending = db::pending_migrations(&pool)
+        .await
+        .map_err(|e| format!("listing migrations failed: {e}"))?;
+    db::run_migrations(&pool)
+        .await
+        .map_err(|e| format!("migrations failed: {e}"))?;
+    if pending.is_empty() {
+        println!("database is up to date");
+    }
+    for (version, description) in pending {
+        println!("applied {version} {description}");
+    }
+    Ok(())
+}
+
+/// Needs `cargo install sqlx-cli`. The data is only used by `query!`-style
+/// macros, so it stays empty as long as the crates build their SQL at runtime.
+async fn prepare_sqlx() -> Result {
+    migrate().await?;
+    run(cargo().args(["sqlx", "prepare", "--workspace", "--", "--all-targets"]))
+}
+
+fn gen_openapi(args: &[String]) -> Result {
+    let out = match args {
+        [] => workspace_root().join("openapi.json"),
+        [flag, path] if flag == "--out" => PathBuf::from(path),
+        _ => return Err("usage: cargo xtask gen-openapi [--out <path>]".into()),
+    };
+    let doc = serde_json::to_string_pretty(&http_server::openapi::document())
+        .map_err(|e| format!("serializing openapi: {e}"))?;
+    std::fs::write(&out, doc + "\n").map_err(|e| format!("writing {}: {e}", out.display()))?;
+    println!("wrote {}", out.display());
+    Ok(())
+}
+
+async fn seed_demo_data() -> Result {
+    let pool = connect().await?;
+    db::run_migrations(&pool)
+        .await
+        .map_err(|e| format!("migrations failed: {e}"))?;
+    let domain = std::env::var("MAIL_DOMAIN")
+        .or_else(|_| std::env::var("DOMAIN"))
+        .map_err(|_| "MAIL_DOMAIN or DOMAIN must be set".to_owned())?;
+    let compression = std::env::var("BODY_COMPRESSION")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+    for address in seed::run(&pool, &domain, compression)
+        .await
+        .map_err(|e| format!("seeding failed: {e}"))?
+    {
+        println!("{address}");
+    }
+    Ok(())
+}
+
+/// Runs `cargo run -p http-server` and `npm run dev` side by side until
+/// either exits, then stops the other.
+async fn run_all() -> Result {
+    migrate().await?;
+    let ui = workspace_root().join("ui");
+    if !ui.join("node_modules").exists() {
+        run(Command::new("npm").arg("install").current_dir(&ui))?;
+    }
+
+    let mut backend = tokio::process::Command::from(cargo());
+    backend
+        .args(["run", "-p", "http-server"])
+        .kill_on_drop(true);
+    let mut frontend = tokio::process::Command::new("npm");
+    frontend
+        .args(["run", "dev"])
+        .current_dir(&ui)
+        .kill_on_drop(true);
+
+    let mut backend = backend
+        .spawn()
+        .map_err(|e| format!("starting backend: {e}"))?;
+    let mut frontend = frontend.spawn().map_err(|e| format!("starting ui: {e}"))?;
+    let (name, status) = tokio::select! {
+        s = backend.wait() => ("backend", s),
+        s = frontend.wait() => ("ui", s),
+        _ = tokio::signal::ctrl_c() => return Ok(()),
+    };
+    match status {
+        Ok(s) if s.success() => Ok(()),
+        Ok(s) => Err(format!("{name} exited with {s}")),
+        Err(e) => Err(format!("waiting for {name}: {e}")),
+    }
+}
+
+fn cargo() -> Command {
+    let mut cmd = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()));
+    cmd.current_dir(workspace_root());
+    cmd
+}
+
+fn run(cmd: &mut Command) -> Result {
+    let status = cmd
+        .status()
+        .map_err(|e| format!("running {:?}: {e}", cmd.get_program()))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("{:?} exited with {status}", cmd.get_program()))
+    }
+}
@@ -0,0 +1,241 @@
+//! Demo mailboxes for local development. Messages go through an in-process
+//! SMTP server, so they are parsed and stored (attachments included) exactly
+//! like real mail.
+
+use db::BodyCompression;
+use sqlx::postgres::PgPool;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A demo message; `{to}` in `raw` is replaced with the recipient.
+struct DemoMessage {
+    /// Envelope sender; empty for a bounce.
+    from: &'static str,
+    raw: &'static str,
+}
+
+const CONFIRMATION: DemoMessage = DemoMessage {
+    from: "welcome@shop.example",
+    raw: "From: Shop <welcome@shop.example>\r\n\
+To: {to}\r\n\
+Subject: Confirm your account\r\n\
+Message-ID: <confirm-1@shop.example>\r\n\
+\r\n\
+Your confirmation code is 482913.\r\n",
+};
+
+const NEWSLETTER: DemoMessage = DemoMessage {
+    from: "news@blog.example",
+    raw: "From: The Blog <news@blog.example>\r\n\
+To: {to}\r\n\
+Subject: This week on the blog\r\n\
+Message-ID: <issue-42@blog.example>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/alternative; boundary=\"alt\"\r\n\
+\r\n\
+--alt\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+Three posts you might have missed:\r\n\
+- Shipping on Fridays\r\n\
+- Postgres LISTEN/NOTIFY in practice\r\n\
+- Why we deleted our cron jobs\r\n\
+--alt\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+\r\n\
+<h1>This week on the blog</h1>\r\n\
+<ul><li><a href=\"https://blog.example/fridays\">Shipping on Fridays</a></li>\r\n\
+<li><a href=\"https://blog.example/notify\">Postgres LISTEN/NOTIFY in practice</a></li>\r\n\
+<li><a href=\"https://blog.example/cron\">Why we deleted our cron jobs</a></li></ul>\r\n\
+--alt--\r\n",
+};
+
+const INVOICE: DemoMessage = DemoMessage {
+    from: "billing@saas.example",
+    raw: "From: SaaS Billing <billing@saas.example>\r\n\
+To: {to}\r\n\
+Subject: Your invoice for October\r\n\
+Message-ID: <invoice-2026-10@saas.example>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"mixed\"\r\n\
+\r\n\
+--mixed\r\n\
+Content-Type: text/plain; charset=utf-8\r\n\
+\r\n\
+Thanks for your payment. The invoice and our logo are attached.\r\n\
+--mixed\r\n\
+Content-Type: text/csv; name=\"invoice.csv\"\r\n\
+Content-Disposition: attachment; filename=\"invoice.csv\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+ZGF0ZSxkZXNjcmlwdGlvbixhbW91bnQNCjIwMjYtMTAtMDEsUHJvIHBsYW4gKG1vbnRobHkpLDEy\r\n\
+LjAwDQoyMDI2LTEwLTAxLFZBVCwyLjUyDQo=\r\n\
+--mixed\r\n\
+Content-Type: image/png; name=\"logo.png\"\r\n\
+Content-Disposition: attachment; filename=\"logo.png\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR42mNkYPhfDwAChwGA60e6kgAAAABJRU5ErkJggg==\r\n\
+--mixed--\r\n",
+};
+
+const SPAM: DemoMessage = DemoMessage {
+    from: "prizes@lucky-winner.example",
+    raw: "From: \"Prize Department\" <prizes@lucky-winner.example>\r\n\
+To: {to}\r\n\
+Subject: =?utf-8?B?8J+OiSBZT1UgSEFWRSBXT04gJDEsMDAwLDAwMCE=?=\r\n\
+Message-ID: <winner-0001@lucky-winner.example>\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: text/html; charset=utf-8\r\n\
+\r\n\
+<p style=\"color:red;font-size:32px\">CONGRATULATIONS!!!</p>\r\n\
+<p>Click <a href=\"http://lucky-winner.example/claim\">here</a> within 24 hours to claim your prize.</p>\r\n",
+};
+
+const THREAD: [DemoMessage; 3] = [
+    DemoMessage {
+        from: "alice@team.example",
+        raw: "From: Alice <alice@team.example>\r\n\
+To: {to}\r\n\
+Subject: Lunch on Friday?\r\n\
+Message-ID: <lunch-1@team.example>\r\n\
+\r\n\
+Anyone up for the new ramen place on Friday?\r\n",
+    },
+    DemoMessage {
+        from: "bob@team.example",
+        raw: "From: Bob <bob@team.example>\r\n\
+To: {to}\r\n\
+Subject: Re: Lunch on Friday?\r\n\
+Message-ID: <lunch-2@team.example>\r\n\
+In-Reply-To: <lunch-1@team.example>\r\n\
+References: <lunch-1@team.example>\r\n\
+\r\n\
+I'm in. 12:30?\r\n\
+\r\n\
+> Anyone up for the new ramen place on Friday?\r\n",
+    },
+    DemoMessage {
+        from: "alice@team.example",
+        raw: "From: Alice <alice@team.example>\r\n\
+To: {to}\r\n\
+Subject: Re: Lunch on Friday?\r\n\
+Message-ID: <lunch-3@team.example>\r\n\
+In-Reply-To: <lunch-2@team.example>\r\n\
+References: <lunch-1@team.example> <lunch-2@team.example>\r\n\
+\r\n\
+12:30 works, I'll book a table.\r\n\
+\r\n\
+> I'm in. 12:30?\r\n",
+    },
+];
+
+const BOUNCE: DemoMessage = DemoMessage {
+    from: "",
+    raw: "From: Mail Delivery System <mailer-daemon@mx.example>\r\n\
+To: {to}\r\n\
+Subject: Undelivered Mail Returned to Sender\r\n\
+Message-ID: <bounce-1@mx.example>\r\n\
+\r\n\
+Your message to nobody@mx.example could not be delivered:\r\n\
+550 5.1.1 User unknown\r\n",
+};
+
+/// Creates a private and a public address holding the demo messages and
+/// returns them. Addresses get a per-run suffix, so seeding twice is fine.
+pub async fn run(
+    pool: &PgPool,
+    domain: &str,
+    compression: BodyCompression,
+) -> Result<Vec<String>, String> {
+    let suffix = std::process::id();
+    let private = db::insert_temporary_email(pool, &format!("demo{suffix}@{domain}"))
+        .await
+        .map_err(|e| e.to_string())?;
+    let public = db::insert_public_temporary_email(pool, &format!("demo-public{suffix}@{domain}"))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| e.to_string())?;
+    let bound = listener.local_addr().map_err(|e| e.to_string())?;
+    let config = smtp::SmtpConfig {
+        body_compression: compression,
+        ..Default::default()
+    };
+    let server = tokio::spawn(smtp::serve(listener, pool.clone(), config));
+
+    let stream = TcpStream::connect(bound).await.map_err(|e| e.to_string())?;
+    let mut client = Client::new(stream);
+    client.expect("", "220").await?;
+    client.expect("EHLO seed.localhost", "250").await?;
+
+    let mut inbox = vec![&CONFIRMATION, &NEWSLETTER, &INVOICE, &SPAM, &BOUNCE];
+    inbox.extend(&THREAD);
+    for message in inbox {
+        client.deliver(message, &private.temp_email_addr).await?;
+    }
+    for message in [&NEWSLETTER, &SPAM] {
+        client.deliver(message, &public.temp_email_addr).await?;
+    }
+    client.expect("QUIT", "221").await?;
+    server.abort();
+
+    Ok(vec![private.temp_email_addr, public.temp_email_addr])
+}
+
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> Self {
+        let (reader, writer) = stream.into_split();
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    /// Sends `command` (nothing if empty) and checks the reply code.
+    async fn expect(&mut self, command: &str, code: &str) -> Result<(), String> {
+        if !command.is_empty() {
+            self.writer
+                .write_all(format!("{command}\r\n").as_bytes())
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let mut line = String::new();
+        loop {
+            line.clear();
+            self.reader
+                .read_line(&mut line)
+                .await
+                .map_err(|e| e.to_string())?;
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        if line.starts_with(code) {
+            Ok(())
+        } else {
+            Err(format!("`{command}` got {:?}", line.trim_end()))
+        }
+    }
+
+    async fn deliver(&mut self, message: &DemoMessage, to: &str) -> Result<(), String> {
+        self.expect(&format!("MAIL FROM:<{}>", message.from), "250")
+            .await?;
+        self.expect(&format!("RCPT TO:<{to}>"), "250").await?;
+        self.expect("DATA", "354").await?;
+        let body = message.raw.replace("{to}", to);
+        self.writer
+            .write_all(body.as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+        self.expect(".", "250").await
+    }
+}
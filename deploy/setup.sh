@@ -61,6 +61,7 @@ HTTP_PORT=3001
 SMTP_HOST=0.0.0.0
 SMTP_PORT=25
 CORS_ALLOWED_ORIGINS=${CORS_ORIGINS}
+TRUSTED_PROXIES=127.0.0.1
 PURGE_HOUR_UTC=3
 EOF
 sudo chmod 600 /etc/fake-email/env
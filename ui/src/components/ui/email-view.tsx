@@ -3,6 +3,7 @@
 import React from "react";
 import { motion } from "motion/react";
 import { X } from "lucide-react";
+import type { DmarcResult, SpfResult } from "@/lib/backend";
 
 export interface EmailPayload {
   id: string;
@@ -10,6 +11,9 @@ export interface EmailPayload {
   subject: string;
   body_text: string | null;
   received_at: string;
+  peer_ip: string | null;
+  spf_result: SpfResult | null;
+  dmarc_result: DmarcResult | null;
 }
 
 interface EmailViewProps {
@@ -72,6 +76,35 @@ export const EmailView: React.FC<EmailViewProps> = ({ email, onClose }) => {
                     {email.from_addr || "unknown"}
                   </span>
                 </p>
+                {email.spf_result && (
+                  <p
+                    className={`text-xs font-bold uppercase tracking-widest ${
+                      email.spf_result === "pass"
+                        ? "text-ink"
+                        : email.spf_result === "fail"
+                          ? "text-vermillion"
+                          : "text-smoke"
+                    }`}
+                    title={
+                      email.peer_ip ? `Sent from ${email.peer_ip}` : undefined
+                    }
+                  >
+                    SPF {email.spf_result}
+                  </p>
+                )}
+                {email.dmarc_result && (
+                  <p
+                    className={`text-xs font-bold uppercase tracking-widest ${
+                      email.dmarc_result === "pass"
+                        ? "text-ink"
+                        : email.dmarc_result === "fail"
+                          ? "text-vermillion"
+                          : "text-smoke"
+                    }`}
+                  >
+                    DMARC {email.dmarc_result}
+                  </p>
+                )}
                 <time className="text-ash text-xs">{formattedDate}</time>
               </div>
             </div>
@@ -32,18 +32,37 @@ export interface ReceivedEmail {
   subject: string;
   body_text: string | null;
   received_at: string;
+  peer_ip: string | null;
+  spf_result: SpfResult | null;
+  dmarc_result: DmarcResult | null;
+  tags: string[];
+  country: string | null;
+  plus_tag: string | null;
+  is_read: boolean;
 }
 
+export type SpfResult =
+  | "pass"
+  | "fail"
+  | "softfail"
+  | "neutral"
+  | "none"
+  | "temperror"
+  | "permerror";
+
+export type DmarcResult = "pass" | "fail" | "none" | "temperror" | "permerror";
+
 export interface InboxPollResponse {
   temp_email_addr: string;
   new_mail_count: number;
+  unread_count: number;
   next_since: string | null;
   messages: ReceivedEmail[];
 }
 
 export async function generateMailbox(
   body: unknown,
-): Promise<{ temp_email_addr?: string }> {
+): Promise<{ temp_email_addr?: string; access_token?: string }> {
   const validationResult = GenerateEmailRequestSchema.safeParse(body);
   if (!validationResult.success) {
     const fieldErrors = validationResult.error.flatten().fieldErrors.username;
@@ -60,10 +79,11 @@ export async function generateMailbox(
   });
 
   const responseText = await backendResponse.text();
-  let data: { temp_email_addr?: string; error?: string };
+  let data: { temp_email_addr?: string; access_token?: string; error?: string };
   try {
     data = JSON.parse(responseText) as {
       temp_email_addr?: string;
+      access_token?: string;
       error?: string;
     };
   } catch {
@@ -91,6 +111,7 @@ function backendErrorMessage(responseText: string, fallback: string): string {
 export async function pollInbox(
   address: string,
   since: string | null,
+  token: string | null,
 ): Promise<InboxPollResponse> {
   const baseUrl = getBackendBaseUrl();
   const params = new URLSearchParams({ address });
@@ -100,9 +121,13 @@ export async function pollInbox(
   const backendUrl = baseUrl
     ? `${baseUrl}/api/inbox/poll?${params}`
     : `/api/inbox/poll?${params}`;
+  const headers: Record<string, string> = { "Content-Type": "application/json" };
+  if (token) {
+    headers.Authorization = `Bearer ${token}`;
+  }
   const backendResponse = await fetch(backendUrl, {
     method: "GET",
-    headers: { "Content-Type": "application/json" },
+    headers,
     cache: "no-store",
   });
   const responseText = await backendResponse.text();
@@ -44,7 +44,8 @@ export function EmailsPage() {
   const fetchEmails = useCallback(
     async (address: string, since: string | null) => {
       try {
-        const data = await pollInbox(address, since);
+        const token = sessionStorage.getItem("temp_token");
+        const data = await pollInbox(address, since, token);
         setEmails((prev) => {
           if (since == null) return data.messages;
           const seen = new Set(prev.map((m) => m.id));
@@ -249,9 +250,16 @@ export function EmailsPage() {
                         <p className="font-mono text-xs text-smoke truncate">
                           {email.from_addr || "unknown sender"}
                         </p>
-                        <time className="text-[11px] text-ash shrink-0 tabular-nums">
-                          {formatTime(email.received_at)}
-                        </time>
+                        <div className="flex items-baseline gap-3 shrink-0">
+                          {email.dmarc_result === "fail" && (
+                            <span className="text-[11px] font-bold uppercase tracking-widest text-vermillion">
+                              DMARC fail
+                            </span>
+                          )}
+                          <time className="text-[11px] text-ash tabular-nums">
+                            {formatTime(email.received_at)}
+                          </time>
+                        </div>
                       </div>
                       <p className="font-display font-semibold text-ink truncate text-[15px] leading-snug">
                         {email.subject || "(no subject)"}
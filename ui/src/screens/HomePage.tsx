@@ -25,6 +25,11 @@ export function HomePage() {
       const addr = data.temp_email_addr;
       if (addr) {
         sessionStorage.setItem("temp_address", addr);
+        if (data.access_token) {
+          sessionStorage.setItem("temp_token", data.access_token);
+        } else {
+          sessionStorage.removeItem("temp_token");
+        }
         router.push("/emails");
       } else {
         throw new Error("Backend did not return a new email address.");